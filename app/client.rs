@@ -1,74 +1,17 @@
 use serde_json::error;
-use KEEP_RUNNING::raft::{proto, rpc};
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use KEEP_RUNNING::raft::{bench, client, doctor, migration, placement, proto, replay, rpc, state_machine};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex as TokioMutex;
 use tracing::{error, info, warn};
 
 const CLUSTER_ADDRS: [&str; 5] = [
-    "[::1].9001",
+    "[::1]:9001",
     "[::1]:9002", 
     "[::1]:9003",
     "[::1]:9004",
     "[::1]:9005",
 ];
 
-// 维护一个全局的 LeaderCache， 避免每个任务都去查找Leader
-struct LeaderCache {
-    leader_info : TokioMutex<Option<proto::ServerInfo>>,
-    rpc_client: rpc::Client,
-}
-
-impl LeaderCache {
-    fn new() -> Self {
-        Self {
-            leader_info: TokioMutex::new(None),
-            rpc_client: rpc::Client {},
-        }
-    }
-    async fn get_leader(&self) -> Option<proto::ServerInfo> {
-        let mut leader_info_guard = self.leader_info.lock().await;
-
-        if let Some(leader) = &*leader_info_guard {
-            return Some(leader.clone());
-        }
-
-        // 如果没有缓存的 Leader 信息，则查询
-        info!("No cached leader info, querying cluster...");
-        for addr in CLUSTER_ADDRS.iter() {
-            let request = proto::GetLeaderRequest {};
-            if let Ok(resp) = self.rpc_client.get_leader(request, addr.to_string()).await {
-                if let Some(leader) = resp.leader {
-                    info!("Found leader: ID={}, Addr={}", leader.server_id, leader.server_addr);
-                    *leader_info_guard = Some(leader.clone());
-                    return Some(leader);
-                }
-            }
-            warn!("Failed to get leader from {}. Trying next node.", addr);
-        }
-        None 
-    }
-
-    async fn update(&self, new_leader: Option<proto::ServerInfo>) {
-        let mut leader_info_guard = self.leader_info.lock().await;
-        *leader_info_guard = new_leader;
-    }
-}
-
-
-async fn find_leader(rpc_client: &rpc::Client) -> Option<proto::ServerInfo> {
-    for addr in CLUSTER_ADDRS.iter() {
-        info!("Querying get-leader from {}", addr);
-        let request = proto::GetLeaderRequest {};
-        match rpc_client.get_leader(request, addr.to_string()).await {
-            Ok(resp) => if let Some(leader) = resp.leader { return Some(leader) },
-            Err(e) => warn!("Failed to get leader from {}: {}. Trying next node.", addr, e),
-        }
-    }
-    None
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _ = tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).init();
@@ -77,15 +20,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if args.len() < 2 {
         println!("Usage:\n");
         println!("  client get-leader");
-        println!("  client get-config");
-        println!("  client propose <DATA>");
-        println!("  client bench <CONSURRENT_TASKS> <TOTAL_REQUESTS>");
+        println!("  client watch-leader [NODE_ADDR]");
+        println!("  client subscribe <NODE_ADDR> [START_INDEX]");
+        println!("  client get-config [--consistent]");
+        println!("  client config-history [NODE_ADDR]");
+        println!("  client set-config [--wait] [--single-server|--joint] <id:addr[@zone]> [id:addr[@zone]] ...");
+        println!("  client config-status <CONFIG_INDEX> [NODE_ADDR]");
+        println!("  client propose <DATA> [--wait]");
+        println!("  client bench <CONCURRENT_TASKS> <TOTAL_REQUESTS> [--read-ratio R] [--payload-size BYTES] [--warmup SECS]");
+        println!("  client read [QUERY] [--stale] [--token <READ_TOKEN>]");
+        println!("  client replay <SNAPSHOT_DIR> <METADATA_DIR> [UP_TO_INDEX]");
+        println!("  client migrate <SNAPSHOT_DIR> <METADATA_DIR> [--dry-run]");
+        println!("  client snapshot <trigger|status> <NODE_ADDR>");
+        println!("  client snapshot export <NODE_ADDR> <FILE>");
+        println!("  client latency <NODE_ADDR>");
+        println!("  client suggest-leader [--transfer]");
+        println!("  client decommission <SERVER_ID> [--wipe]");
+        println!("  client set-partition <NODE_ADDR> [SERVER_ID ...]");
+        println!("  client version <NODE_ADDR>");
+        println!("  client doctor [NODE_ADDR ...]");
         return Ok(());
     }
 
     let command = &args[1];
-    let mut rpc_client = rpc::Client {};
-    let leader_cache = Arc::new(LeaderCache::new());
+    let mut rpc_client = rpc::Client::default();
+    let leader_cache = Arc::new(client::LeaderCache::new(
+        CLUSTER_ADDRS.iter().map(|addr| addr.to_string()).collect(),
+    ));
 
     match command.as_str() {
         "get-leader" => {
@@ -95,14 +56,85 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 error!("Could not find the leader in the cluster.");
             }
         }
+        "watch-leader" => {
+            let addr = args.get(2).cloned().unwrap_or_else(|| CLUSTER_ADDRS[0].to_string());
+            let mut stream = match rpc_client.watch_leader(proto::WatchLeaderRequest {}, addr.clone()).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("Failed to watch leader via {}: {}", addr, e);
+                    return Ok(());
+                }
+            };
+            println!("Watching leader changes via {} (Ctrl-C to stop)", addr);
+            while let Some(resp) = stream.message().await? {
+                match resp.leader {
+                    Some(leader) => println!("Leader: ID={}, Addr={} (term {}, commit index {})", leader.server_id, leader.server_addr, resp.term, resp.commit_index),
+                    None => println!("Leader: unknown (term {})", resp.term),
+                }
+            }
+            println!("Node {} stopped, watch ended.", addr);
+        }
+        "subscribe" => {
+            if args.len() < 3 || args.len() > 4 {
+                error!("Usage: client subscribe <NODE_ADDR> [START_INDEX]");
+                return Ok(());
+            }
+            let addr = args[2].clone();
+            let start_index = match args.get(3).map(|arg| arg.parse::<u64>()).transpose() {
+                Ok(start_index) => start_index,
+                Err(e) => {
+                    error!("Invalid START_INDEX: {}", e);
+                    return Ok(());
+                }
+            };
+            let mut stream = match rpc_client.subscribe_committed(proto::SubscribeCommittedRequest { start_index }, addr.clone()).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("Failed to subscribe to committed entries via {}: {}", addr, e);
+                    return Ok(());
+                }
+            };
+            println!("Following committed entries via {} (Ctrl-C to stop)", addr);
+            while let Some(resp) = stream.message().await? {
+                if resp.snapshot_required {
+                    println!("Entries before the log start were compacted, fetch a snapshot and resubscribe from {}.", resp.snapshot_last_included_index + 1);
+                    return Ok(());
+                }
+                for entry in &resp.entries {
+                    println!("{} (term {}, {:?}): {}", entry.index, entry.term, entry.entry_type(), String::from_utf8_lossy(&entry.data));
+                }
+            }
+            println!("Node {} stopped, subscription ended.", addr);
+        }
         "get-config" => {
+            if args.iter().any(|arg| arg == "--consistent") {
+                match leader_cache.get_configuration_consistent().await {
+                    Some(resp) => {
+                        println!("Current Cluster Configuration (confirmed by leader):");
+                        for server in resp.servers {
+                            println!("  - ID: {}, Addr: {}{}", server.server_id, server.server_addr, server.zone.map_or(String::new(), |zone| format!(", Zone: {}", zone)));
+                        }
+                    }
+                    None => error!("Could not get a leader-confirmed configuration from the cluster."),
+                }
+                return Ok(());
+            }
             for addr in CLUSTER_ADDRS.iter() {
-                let request = proto::GetConfigurationRequest {};
+                let request = proto::GetConfigurationRequest { consistent: false };
                 match rpc_client.get_configuration(request, addr.to_string()).await {
                     Ok(resp) => {
                         println!("Current Cluster Configuration:");
                         for server in resp.servers {
-                            println!("  - ID: {}, Addr: {}", server.server_id, server.server_addr);
+                            println!("  - ID: {}, Addr: {}{}", server.server_id, server.server_addr, server.zone.map_or(String::new(), |zone| format!(", Zone: {}", zone)));
+                        }
+                        if resp.health == proto::NodeHealth::ConfigMismatch as i32 {
+                            warn!("Node {} reports that its address in the configuration does not match the address it listens on.", addr);
+                        }
+                        if resp.health == proto::NodeHealth::LogGap as i32 {
+                            warn!("Node {} is missing a committed log entry and has stopped applying.", addr);
+                        }
+                        if resp.health == proto::NodeHealth::IncompatibleSnapshot as i32 {
+                            warn!("Node {} refused to restore a snapshot written by an incompatible state machine version.", addr);
                         }
                         return Ok(());
                     }
@@ -111,58 +143,129 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
             error!("Could not get configuration from any node in the cluster.");
         }
+        "config-history" => {
+            let targets: Vec<String> = match args.get(2) {
+                Some(addr) => vec![addr.clone()],
+                None => CLUSTER_ADDRS.iter().map(|addr| addr.to_string()).collect(),
+            };
+            for addr in targets {
+                match rpc_client.get_configuration_history(proto::GetConfigurationHistoryRequest {}, addr.clone()).await {
+                    Ok(resp) => {
+                        println!("Configuration history from {}:", addr);
+                        for change in resp.changes {
+                            let ids = |servers: &[proto::ServerInfo]| servers.iter().map(|s| s.server_id).collect::<Vec<_>>();
+                            println!("  - index={} term={} initiator={} committed_at_ms={} old={:?} new={:?}",
+                                change.index, change.term, change.initiator_id, change.committed_at_ms,
+                                ids(&change.old_servers), ids(&change.new_servers));
+                        }
+                        return Ok(());
+                    }
+                    Err(e) => warn!("Failed to get config history from {}: {}. Trying next node.", addr, e),
+                }
+            }
+            error!("Could not get configuration history from any node in the cluster.");
+        }
         "set-config" => {
-            if args.len() < 3 {
-                error!("Usage: client set-config <id:addr> [id:addr] ...");
+            let wait_for_completion = args.iter().any(|arg| arg == "--wait");
+            let algorithm = if args.iter().any(|arg| arg == "--single-server") {
+                Some(proto::MembershipAlgorithm::SingleServer as i32)
+            } else if args.iter().any(|arg| arg == "--joint") {
+                Some(proto::MembershipAlgorithm::JointConsensus as i32)
+            } else {
+                None
+            };
+            let server_args: Vec<&String> = args[2..].iter().filter(|arg| !arg.starts_with("--")).collect();
+            if server_args.is_empty() {
+                error!("Usage: client set-config [--wait] [--single-server|--joint] <id:addr[@zone]> [id:addr[@zone]] ...");
                 return Ok(());
             }
 
             let mut new_servers = vec![];
-            for arg in &args[2..] {
-                let parts: Vec<&str> = arg.split(':').collect();
+            for arg in server_args {
+                // 可用区标注在地址之后，用 @ 分隔
+                let (server, zone) = match arg.rsplit_once('@') {
+                    Some((server, zone)) => (server, Some(zone.to_string())),
+                    None => (arg.as_str(), None),
+                };
+                let parts: Vec<&str> = server.split(':').collect();
                 if parts.len() < 2 {
-                    error!("Invalid server format: {}. Expected 'id:address'", arg);
+                    error!("Invalid server format: {}. Expected 'id:address' or 'id:address@zone'", arg);
                     return Ok(());
                 }
                 let server_id = parts[0].parse::<u64>()?;
                 let server_addr = parts[1..].join(":");
-                new_servers.push(proto::ServerInfo { server_id, server_addr });
+                new_servers.push(proto::ServerInfo { server_id, server_addr, zone });
             }
 
-            if let Some(leader) = find_leader(&rpc_client).await {
+            if let Some(leader) = leader_cache.get_leader().await {
                 info!("Found leader {}: {}. Sending SetConfiguration request.", leader.server_id, leader.server_addr);
-                let request = proto::SetConfigurationRequest { new_servers };
+                let request = proto::SetConfigurationRequest { new_servers, wait_for_completion, algorithm };
                 match rpc_client.set_configuration(request, leader.server_addr).await {
-                    Ok(resp) if resp.success => println!("Successfully proposed new configuration!"),
-                    _ => error!("Leader rejected or failed to process the configuration change."),
+                    Ok(resp) if resp.success => {
+                        let state = proto::ConfigChangeState::try_from(resp.state).unwrap_or(proto::ConfigChangeState::Unknown);
+                        println!("Successfully proposed new configuration at index {} (state: {:?})", resp.config_index, state);
+                        if wait_for_completion && state != proto::ConfigChangeState::Completed {
+                            error!("Configuration change did not complete in time, check it with `client config-status {}`.", resp.config_index);
+                        }
+                    }
+                    Ok(resp) => error!("Leader rejected the configuration change: {}", resp.error.unwrap_or_default()),
+                    Err(e) => error!("Leader failed to process the configuration change: {}", e),
                 }
             } else {
                 error!("Could not find the leader to send the configuration change.");
             }
         }
+        "config-status" => {
+            let Some(config_index) = args.get(2).and_then(|arg| arg.parse::<u64>().ok()) else {
+                error!("Usage: client config-status <CONFIG_INDEX> [NODE_ADDR]");
+                return Ok(());
+            };
+            let addr = match args.get(3) {
+                Some(addr) => addr.clone(),
+                None => match leader_cache.get_leader().await {
+                    Some(leader) => leader.server_addr,
+                    None => {
+                        error!("Could not find the leader, specify a node address.");
+                        return Ok(());
+                    }
+                },
+            };
+            let request = proto::GetConfigChangeStatusRequest { config_index };
+            match rpc_client.get_config_change_status(request, addr.clone()).await {
+                Ok(resp) => {
+                    let state = proto::ConfigChangeState::try_from(resp.state).unwrap_or(proto::ConfigChangeState::Unknown);
+                    println!("Configuration change at index {} (as seen by {}): {:?}", config_index, addr, state);
+                }
+                Err(e) => error!("Failed to get config change status from {}: {}", addr, e),
+            }
+        }
         "propose" => {
             if args.len() < 3 {
-                error!("Usage client propose <DATA>");
+                error!("Usage client propose <DATA> [--wait]");
                 return Ok(());
             }
             let data_to_propose = args[2].clone().into_bytes();
+            let wait_for_commit = args.iter().skip(3).any(|arg| arg == "--wait");
 
             // 循环直到成功
             // 循环直到成功
             for _ in 0..5 { // 最多重试5次
                 if let Some(leader) = leader_cache.get_leader().await {
-                    let req = proto::ProposeRequest { data: data_to_propose.clone() };
+                    let req = proto::ProposeRequest { data: data_to_propose.clone(), wait_for_commit };
                     match leader_cache.rpc_client.propose(req, leader.server_addr).await {
                         Ok(resp) if resp.success => {
-                            println!("Successfully proposed data!");
+                            println!("Successfully proposed data! Read token: {}", resp.read_token.unwrap_or(0));
+                            match &resp.result {
+                                Some(result) => println!("Applied, result: {}", String::from_utf8_lossy(result)),
+                                None if wait_for_commit => println!("Outcome unknown: the entry was not applied before the wait timed out."),
+                                None => {}
+                            }
                             return Ok(());
                         }
-                        Ok(resp) => { // Propose 失败，但收到了 Leader 提示
-                            warn!("Propose failed, updating leader hint...");
-                            leader_cache.update(resp.leader_addr.map(|addr| proto::ServerInfo {
-                                server_id: resp.index.unwrap_or(0),
-                                server_addr: addr,
-                            })).await;
+                        Ok(resp) => { // Propose 失败，根据拒绝原因决定如何重试
+                            if !leader_cache.handle_propose_rejection(&resp).await {
+                                return Ok(());
+                            }
                         }
                         Err(e) => { // RPC 级别的错误
                             warn!("RPC to leader failed: {}. Invalidating leader cache.", e);
@@ -176,83 +279,285 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         "bench" => {
-            if args.len() != 4 {
-                error!("Usage: client bench <CONCURRENT_TASKS> <TOTAL_REQUESTS>");
+            if args.len() < 4 {
+                error!("Usage: client bench <CONCURRENT_TASKS> <TOTAL_REQUESTS> [--read-ratio R] [--payload-size BYTES] [--warmup SECS]");
+                return Ok(());
+            }
+            let mut options = bench::BenchOptions {
+                concurrency: args[2].parse()?,
+                total_requests: args[3].parse()?,
+                ..Default::default()
+            };
+            let mut rest = args[4..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--read-ratio" => options.read_ratio = rest.next().ok_or("--read-ratio requires a value")?.parse()?,
+                    "--payload-size" => options.payload_size = rest.next().ok_or("--payload-size requires a value")?.parse()?,
+                    "--warmup" => options.warmup = Duration::from_secs_f64(rest.next().ok_or("--warmup requires a value")?.parse()?),
+                    other => {
+                        error!("Unknown bench option: {}", other);
+                        return Ok(());
+                    }
+                }
+            }
+            if !(0.0..=1.0).contains(&options.read_ratio) {
+                error!("--read-ratio must be between 0 and 1");
                 return Ok(());
             }
-            let concurrent_tasks: usize = args[2].parse()?;
-            let total_requests: usize = args[3].parse()?;
-            
-            info!("Starting benchmark with {} concurrent tasks, {} total requests.", concurrent_tasks, total_requests);
 
-            let successful_requests = Arc::new(AtomicUsize::new(0));
-            let total_latency = Arc::new(AtomicU64::new(0));
-            let start_time = Instant::now();
+            info!("Starting benchmark with {} concurrent tasks, {} total requests.", options.concurrency, options.total_requests);
+            let report = bench::run(Arc::clone(&leader_cache), options).await;
+            println!("\n{}", report.render());
+        }
+        "read" => {
+            let mut allow_degraded = false;
+            let mut read_token = None;
+            let mut query = Vec::new();
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--stale" => allow_degraded = true,
+                    "--token" => read_token = Some(rest.next().ok_or("--token requires a value")?.parse::<u64>()?),
+                    _ => query = arg.clone().into_bytes(),
+                }
+            }
+
+            // 优先读 Leader，找不到 Leader 且允许降级读时，读任意一个可达节点；
+            // 带 read_token 时任何节点都可以在应用到该位置后提供会话一致性读
+            let mut targets: Vec<String> = Vec::new();
+            if let Some(leader) = leader_cache.get_leader().await {
+                targets.push(leader.server_addr);
+            }
+            if allow_degraded || read_token.is_some() {
+                targets.extend(CLUSTER_ADDRS.iter().map(|addr| addr.to_string()));
+            }
 
-            let mut handles = vec![];
+            for addr in targets {
+                let req = proto::ReadRequest { query: query.clone(), allow_degraded, read_token };
+                match rpc_client.read(req, addr.clone()).await {
+                    Ok(resp) if resp.success => {
+                        if resp.degraded {
+                            println!("WARNING: degraded read from {} (cluster status: {:?}), result may be stale.",
+                                addr, proto::ClusterStatus::try_from(resp.cluster_status).unwrap_or(proto::ClusterStatus::ClusterUnavailable));
+                        }
+                        println!("{}", String::from_utf8_lossy(&resp.data));
+                        info!("Read served by {} at applied index {}.", addr, resp.applied_index);
+                        return Ok(());
+                    }
+                    Ok(resp) => warn!("Read refused by {} (cluster status: {:?}).", addr, resp.cluster_status),
+                    Err(e) => warn!("Read from {} failed: {}", addr, e),
+                }
+            }
+            error!("Could not read from the cluster. Use --stale to allow degraded reads.");
+        }
+        "replay" => {
+            if args.len() < 4 {
+                error!("Usage: client replay <SNAPSHOT_DIR> <METADATA_DIR> [UP_TO_INDEX]");
+                return Ok(());
+            }
+            let up_to_index = match args.get(4) {
+                Some(idx) => Some(idx.parse::<u64>()?),
+                None => None,
+            };
 
-            for i in 0..concurrent_tasks {
-                let leader_cache_clone = Arc::clone(&leader_cache);
-                let successful_requests_clone = Arc::clone(&successful_requests);
-                let total_latency_clone = Arc::clone(&total_latency);
-                let requests_per_task = total_requests / concurrent_tasks;
+            // 离线回放不需要连接集群
+            let mut state_machine = state_machine::SimpleStateMachine::new();
+            let report = replay::replay(&args[2], &args[3], &mut state_machine, up_to_index);
 
-                let handle = tokio::spawn(async move {
-                    for j in 0..requests_per_task {
-                        let data = format!("task-{}-req-{}", i, j).into_bytes();
-                        let req_start_time = Instant::now();
-                        
-                        // 循环直到成功
-                        loop {
-                            if let Some(leader) = leader_cache_clone.get_leader().await {
-                                let req = proto::ProposeRequest { data: data.clone() };
-                                match leader_cache_clone.rpc_client.propose(req, leader.server_addr).await {
-                                    Ok(resp) if resp.success => {
-                                        let latency = req_start_time.elapsed().as_micros() as u64;
-                                        successful_requests_clone.fetch_add(1, Ordering::SeqCst);
-                                        total_latency_clone.fetch_add(latency, Ordering::SeqCst);
-                                        break; // 成功，跳出循环
-                                    }
-                                    Ok(resp) => {
-                                        warn!("Task {}: Propose failed, updating leader hint...", i);
-                                        leader_cache_clone.update(resp.leader_addr.map(|addr| proto::ServerInfo {
-                                            server_id: resp.index.unwrap_or(0),
-                                            server_addr: addr,
-                                        })).await;
-                                    }
-                                    Err(e) => {
-                                        warn!("Task {}: RPC to leader failed: {}. Invalidating leader cache.", i, e);
-                                        leader_cache_clone.update(None).await;
-                                    }
-                                }
+            println!("\n--- Replay Results ---");
+            match &report.snapshot_filepath {
+                Some(path) => println!("Snapshot: {} (LII={}, LIT={})", path, report.snapshot_last_included_index, report.snapshot_last_included_term),
+                None => println!("Snapshot: none"),
+            }
+            println!("Log range: [{}, {}]", report.log_start_index, report.log_last_index);
+            for entry in report.entries.iter() {
+                let entry_type = entry.entry_type.map_or("Unknown".to_string(), |t| format!("{:?}", t));
+                println!("  applied index={} term={} type={} size={}", entry.index, entry.term, entry_type, entry.data_len);
+            }
+            println!("Final last_applied: {} (term {})", report.last_applied, report.last_applied_term);
+            if let Some(config) = &report.last_configuration {
+                println!("Last configuration: old={:?}, new={:?}", config.old_servers, config.new_servers);
+            }
+            println!("State machine entries: {:?}", state_machine.get_entries());
+        }
+        "migrate" => {
+            if args.len() < 4 {
+                error!("Usage: client migrate <SNAPSHOT_DIR> <METADATA_DIR> [--dry-run]");
+                return Ok(());
+            }
+            let dry_run = args.iter().any(|arg| arg == "--dry-run");
+            // 离线升级数据目录，节点必须处于停止状态
+            let report = migration::migrate(&args[2], &args[3], dry_run)?;
+            println!("Storage version: {} -> {}{}", report.from_version, report.to_version, if dry_run { " (dry run)" } else { "" });
+            for action in report.actions.iter() {
+                println!("  - {}", action);
+            }
+        }
+        "snapshot" => {
+            if args.len() != 4 && !(args.len() == 5 && args[2] == "export") {
+                error!("Usage: client snapshot <trigger|status> <NODE_ADDR> | client snapshot export <NODE_ADDR> <FILE>");
+                return Ok(());
+            }
+            let addr = args[3].clone();
+            match args[2].as_str() {
+                "trigger" => {
+                    let start_time = Instant::now();
+                    match rpc_client.trigger_snapshot(proto::TriggerSnapshotRequest {}, addr.clone()).await {
+                        Ok(resp) => {
+                            if resp.success {
+                                println!("Snapshot taken on {} in {:?}.", addr, start_time.elapsed());
                             } else {
-                                error!("Task {}: Could not find leader. Retrying...", i);
-                                tokio::time::sleep(Duration::from_millis(500)).await;
+                                println!("Snapshot not taken on {}: {}", addr, resp.error.unwrap_or_default());
                             }
+                            if let Some(status) = &resp.status {
+                                println!("{}", client::format_snapshot_status(status));
+                            }
+                        }
+                        Err(e) => error!("Failed to trigger snapshot on {}: {}", addr, e),
+                    }
+                }
+                "status" => {
+                    match rpc_client.get_snapshot_status(proto::GetSnapshotStatusRequest {}, addr.clone()).await {
+                        Ok(status) => println!("{}", client::format_snapshot_status(&status)),
+                        Err(e) => error!("Failed to get snapshot status from {}: {}", addr, e),
+                    }
+                }
+                "export" => {
+                    // 先写到临时文件，完整收到之后再改名，中断的导出不会留下看起来完整的备份
+                    let start_time = Instant::now();
+                    let tmp_path = format!("{}.tmp", args[4]);
+                    let export = async {
+                        let mut stream = rpc_client.export_snapshot(proto::ExportSnapshotRequest {}, addr.clone()).await?;
+                        let mut file = tokio::fs::File::create(&tmp_path).await?;
+                        let mut bytes = 0;
+                        while let Some(chunk) = stream.message().await? {
+                            bytes += chunk.data.len();
+                            tokio::io::AsyncWriteExt::write_all(&mut file, &chunk.data).await?;
+                        }
+                        file.sync_all().await?;
+                        tokio::fs::rename(&tmp_path, &args[4]).await?;
+                        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(bytes)
+                    };
+                    match export.await {
+                        Ok(bytes) => println!("Exported snapshot of {} to {} ({} bytes) in {:?}.", addr, args[4], bytes, start_time.elapsed()),
+                        Err(e) => {
+                            let _ = std::fs::remove_file(&tmp_path);
+                            error!("Failed to export snapshot from {}: {}", addr, e);
                         }
                     }
-                });
-                handles.push(handle);
+                }
+                other => error!("Unknown snapshot command: {}. Expected trigger, status or export.", other),
             }
-            
-            // 等待所有压测任务完成
-            for handle in handles {
-                handle.await?;
+        }
+        "latency" => {
+            if args.len() != 3 {
+                error!("Usage: client latency <NODE_ADDR>");
+                return Ok(());
             }
-
-            let total_duration = start_time.elapsed();
-            let successful_count = successful_requests.load(Ordering::Relaxed);
-            let avg_latency_us = if successful_count > 0 {
-                total_latency.load(Ordering::Relaxed) / successful_count as u64
-            } else { 0 };
-
-            println!("\n--- Benchmark Results ---");
-            println!("Total time: {:?}", total_duration);
-            println!("Concurrent tasks: {}", concurrent_tasks);
-            println!("Total requests: {}", total_requests);
-            println!("Successful requests: {}", successful_count);
-            println!("Requests per second (RPS): {:.2}", successful_count as f64 / total_duration.as_secs_f64());
-            println!("Average latency: {} \u{00B5}s (microseconds)", avg_latency_us);
+            match rpc_client.get_latency_stats(proto::GetLatencyStatsRequest {}, args[2].clone()).await {
+                Ok(resp) => {
+                    println!("Latency stats from {} (measured on the leader):", args[2]);
+                    for h in resp.histograms.iter().filter(|h| h.count > 0) {
+                        // 更新版本的节点可能返回本客户端不认识的类型，跳过而不是当成别的类型显示
+                        let (Ok(entry_type), Ok(stage)) = (proto::EntryType::try_from(h.entry_type), proto::LatencyStage::try_from(h.stage)) else {
+                            continue;
+                        };
+                        println!("  {:?}/{:?}: count={} avg={}us buckets(<=us)={:?} overflow={}",
+                            entry_type, stage, h.count, h.sum_us / h.count,
+                            h.bucket_upper_bounds_us.iter().zip(h.bucket_counts.iter()).collect::<Vec<_>>(),
+                            h.bucket_counts.last().copied().unwrap_or(0));
+                    }
+                }
+                Err(e) => error!("Failed to get latency stats from {}: {}", args[2], e),
+            }
+        }
+        "suggest-leader" => {
+            let transfer = args.iter().any(|arg| arg == "--transfer");
+            let Some(leader) = leader_cache.get_leader().await else {
+                error!("Could not find the leader in the cluster.");
+                return Ok(());
+            };
+            match rpc_client.suggest_leader(proto::SuggestLeaderRequest { transfer }, leader.server_addr.clone()).await {
+                Ok(resp) if resp.success => {
+                    println!("Leader candidates by estimated quorum latency (current leader: {}):", leader.server_id);
+                    for candidate in &resp.candidates {
+                        let server = candidate.server.clone().unwrap_or_default();
+                        println!("  - ID: {}, Addr: {}, quorum latency: {}{}", server.server_id, server.server_addr,
+                            placement::format_latency_us(candidate.quorum_latency_us),
+                            if candidate.complete { "" } else { " (partial samples)" });
+                    }
+                    if let Some(suggested) = resp.suggested {
+                        println!("Suggested leader: ID={}, Addr={}", suggested.server_id, suggested.server_addr);
+                    }
+                    if resp.transferred {
+                        println!("Leadership transfer started.");
+                        leader_cache.update(None).await;
+                    } else if let Some(e) = resp.error {
+                        error!("Leadership transfer failed: {}", e);
+                    }
+                }
+                Ok(resp) => error!("SuggestLeader rejected by {}: {}", leader.server_addr, resp.error.unwrap_or_default()),
+                Err(e) => error!("SuggestLeader to {} failed: {}", leader.server_addr, e),
+            }
+        }
+        "decommission" => {
+            let Some(server_id) = args.get(2).and_then(|arg| arg.parse::<u64>().ok()) else {
+                error!("Usage: client decommission <SERVER_ID> [--wipe]");
+                return Ok(());
+            };
+            let wipe_data = args.iter().skip(3).any(|arg| arg == "--wipe");
+            let Some(leader) = leader_cache.get_leader().await else {
+                error!("Could not find the leader in the cluster.");
+                return Ok(());
+            };
+            match rpc_client.decommission(proto::DecommissionRequest { server_id, wipe_data }, leader.server_addr.clone()).await {
+                Ok(resp) if resp.success => {
+                    println!("Server {} removed from the cluster at config index {}.", server_id, resp.config_index);
+                    if resp.shutdown_signaled {
+                        println!("Server {} was told to shut down{}.", server_id, if wipe_data { " and wipe its data" } else { "" });
+                    } else {
+                        println!("Server {} could not be reached, shut it down manually.", server_id);
+                    }
+                }
+                Ok(resp) => error!("Decommission of server {} failed: {}", server_id, resp.error.unwrap_or_default()),
+                Err(e) => error!("Decommission to {} failed: {}", leader.server_addr, e),
+            }
+        }
+        "version" => {
+            let Some(node_addr) = args.get(2) else {
+                error!("Usage: client version <NODE_ADDR>");
+                return Ok(());
+            };
+            match rpc_client.get_version(proto::GetVersionRequest {}, node_addr.clone()).await {
+                Ok(resp) => println!("{}: version {} ({}), protocol version {}, storage version {}",
+                    node_addr, resp.crate_version, resp.git_hash, resp.protocol_version, resp.storage_version),
+                Err(e) => error!("GetVersion to {} failed: {}", node_addr, e),
+            }
+        }
+        "set-partition" => {
+            let Some(node_addr) = args.get(2) else {
+                error!("Usage: client set-partition <NODE_ADDR> [SERVER_ID ...]");
+                return Ok(());
+            };
+            let Ok(blocked_server_ids) = args[3..].iter().map(|arg| arg.parse::<u64>()).collect::<Result<Vec<_>, _>>() else {
+                error!("Usage: client set-partition <NODE_ADDR> [SERVER_ID ...]");
+                return Ok(());
+            };
+            match rpc_client.set_partition(proto::SetPartitionRequest { blocked_server_ids }, node_addr.clone()).await {
+                Ok(resp) if resp.blocked_server_ids.is_empty() => println!("{} can reach all servers again.", node_addr),
+                Ok(resp) => println!("{} is partitioned away from servers {:?}.", node_addr, resp.blocked_server_ids),
+                Err(e) => error!("SetPartition to {} failed: {}", node_addr, e),
+            }
+        }
+        "doctor" => {
+            let addrs: Vec<String> = if args.len() > 2 {
+                args[2..].to_vec()
+            } else {
+                CLUSTER_ADDRS.iter().map(|addr| addr.to_string()).collect()
+            };
+            let reports = doctor::collect(&addrs).await;
+            let findings = doctor::diagnose(&reports);
+            println!("{}", doctor::render(&reports, &findings));
         }
         _ => error!("Unknown command: {}", command),
     }
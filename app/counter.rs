@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+use KEEP_RUNNING::raft::{self, config, proto, state_machine};
+
+// 复制计数器：在 RaftNode 之上实现一个小的 gRPC 服务，演示把真实的业务接到 Raft 上需要的几步：
+//   - 命令编码成日志条目的数据（这里用 JSON），通过 propose_and_wait 等待提交，状态机的返回值就是执行结果
+//   - 读请求通过 RaftNode::read 交给状态机，Leader 确认领导权之后才读取，保证线性一致
+//   - 不是 Leader 的节点返回 FailedPrecondition，消息中带上 Leader 的地址，客户端据此重定向
+//
+// 运行三个节点（Raft 端口 9101-9103，计数器服务端口 10101-10103）：
+//   cargo run --example counter
+// 另开一个终端访问：
+//   cargo run --example counter -- incr [::1]:10101 visits 5
+//   cargo run --example counter -- get [::1]:10101 visits
+
+pub mod counter_proto {
+    tonic::include_proto!("counter");
+}
+
+use counter_proto::counter_client::CounterClient;
+use counter_proto::counter_server::{Counter, CounterServer};
+
+// 写入日志的命令，状态机按同样的格式解码
+#[derive(Debug, Serialize, Deserialize)]
+enum Command {
+    Increment { name: String, delta: i64 },
+}
+
+#[derive(Debug, Default, Clone)]
+struct CounterStateMachine {
+    counters: Arc<Mutex<HashMap<String, i64>>>,
+}
+
+impl state_machine::StateMachine for CounterStateMachine {
+    fn apply(&mut self, data: &Vec<u8>) {
+        self.apply_with_result(data);
+    }
+
+    // 返回应用之后的值（8 字节小端），无法解码的条目不修改状态，返回空
+    fn apply_with_result(&mut self, data: &Vec<u8>) -> Vec<u8> {
+        match serde_json::from_slice::<Command>(data) {
+            Ok(Command::Increment { name, delta }) => {
+                let mut counters = self.counters.lock().unwrap();
+                let value = counters.entry(name).or_insert(0);
+                *value = value.wrapping_add(delta);
+                value.to_le_bytes().to_vec()
+            }
+            Err(e) => {
+                error!("Skipping undecodable counter command: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    // 查询是计数器的名字
+    fn read(&self, query: &[u8]) -> Option<Vec<u8>> {
+        let name = String::from_utf8_lossy(query);
+        let value = self.counters.lock().unwrap().get(name.as_ref()).copied().unwrap_or(0);
+        Some(value.to_le_bytes().to_vec())
+    }
+
+    fn take_snapshot(&mut self, snapshot_filepath: &str) {
+        let json = serde_json::to_vec(&*self.counters.lock().unwrap()).expect("failed to serialize counters");
+        if let Err(e) = std::fs::write(snapshot_filepath, json) {
+            panic!("failed to write snapshot file {}, error: {}", snapshot_filepath, e);
+        }
+    }
+
+    fn restore_snapshot(&mut self, snapshot_filepath: &str) {
+        let json = std::fs::read(snapshot_filepath).expect("failed to read snapshot file");
+        *self.counters.lock().unwrap() = serde_json::from_slice(&json).expect("failed to parse snapshot file");
+    }
+}
+
+fn decode_value(data: &[u8]) -> Option<i64> {
+    data.try_into().ok().map(i64::from_le_bytes)
+}
+
+fn unexpected_value(data: &[u8]) -> tonic::Status {
+    tonic::Status::internal(format!("unexpected counter value of {} bytes", data.len()))
+}
+
+fn not_leader(leader_addr: Option<String>) -> tonic::Status {
+    match leader_addr {
+        Some(addr) => tonic::Status::failed_precondition(format!("not the leader, retry on the node with raft address {}", addr)),
+        None => tonic::Status::unavailable("no leader is known, retry later"),
+    }
+}
+
+struct CounterService {
+    node: raft::lib::RaftNode,
+}
+
+#[tonic::async_trait]
+impl Counter for CounterService {
+    async fn increment(
+        &self,
+        request: tonic::Request<counter_proto::IncrementRequest>,
+    ) -> Result<tonic::Response<counter_proto::IncrementResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let command = serde_json::to_vec(&Command::Increment { name: request.name, delta: request.delta })
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+        let response = self.node.propose_and_wait(command).await;
+        if !response.success {
+            return Err(not_leader(response.leader_addr));
+        }
+        // 等待超时或 Leader 变化时，增量是否生效未知，客户端不能简单地重试
+        let Some(result) = response.result else {
+            return Err(tonic::Status::unavailable("outcome unknown, read the counter before retrying"));
+        };
+        Ok(tonic::Response::new(counter_proto::IncrementResponse {
+            value: decode_value(&result).ok_or_else(|| unexpected_value(&result))?,
+            index: response.index.unwrap_or_default(),
+        }))
+    }
+
+    async fn get(
+        &self,
+        request: tonic::Request<counter_proto::GetRequest>,
+    ) -> Result<tonic::Response<counter_proto::GetResponse>, tonic::Status> {
+        let response = self.node.read(request.into_inner().name.into_bytes(), false).await;
+        if !response.success {
+            return Err(not_leader(response.leader_addr));
+        }
+        let value = decode_value(&response.data).ok_or_else(|| unexpected_value(&response.data))?;
+        Ok(tonic::Response::new(counter_proto::GetResponse { value }))
+    }
+}
+
+// 启动一个 Raft 节点，并在 counter_addr 上提供计数器服务
+async fn start_node(
+    server_id: u64,
+    raft_port: u32,
+    peers: Vec<proto::ServerInfo>,
+    storage: raft::storage::StoragePaths,
+    counter_addr: std::net::SocketAddr,
+) -> Result<raft::lib::RaftNode, Box<dyn std::error::Error + Send + Sync>> {
+    let node = raft::lib::start(
+        server_id, raft_port, peers, Box::new(CounterStateMachine::default()),
+        storage, config::RaftOptions::default(),
+    ).await?;
+    node.ready().await?;
+    let listener = tokio::net::TcpListener::bind(counter_addr).await?;
+    let service = CounterServer::new(CounterService { node: node.clone() });
+    tokio::spawn(async move {
+        let incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
+        if let Err(e) = tonic::transport::Server::builder().add_service(service).serve_with_incoming(incoming).await {
+            error!("Counter service of node {} failed: {}", server_id, e);
+        }
+    });
+    info!("Node {} serves counters on {}", server_id, counter_addr);
+    Ok(node)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let _ = tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).try_init();
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("incr") if args.len() >= 4 => {
+            let delta = args.get(4).map_or(Ok(1), |d| d.parse())?;
+            let mut client = CounterClient::connect(format!("http://{}", args[2])).await?;
+            let response = client.increment(counter_proto::IncrementRequest { name: args[3].clone(), delta }).await?;
+            println!("{} = {} (log index {})", args[3], response.get_ref().value, response.get_ref().index);
+        }
+        Some("get") if args.len() >= 4 => {
+            let mut client = CounterClient::connect(format!("http://{}", args[2])).await?;
+            let response = client.get(counter_proto::GetRequest { name: args[3].clone() }).await?;
+            println!("{} = {}", args[3], response.get_ref().value);
+        }
+        Some(_) => {
+            println!("Usage:");
+            println!("  counter                              run a three node cluster");
+            println!("  counter incr <COUNTER_ADDR> <NAME> [DELTA]");
+            println!("  counter get <COUNTER_ADDR> <NAME>");
+        }
+        None => {
+            let storage = raft::storage::StoragePaths::new(std::env::current_dir()?.join("counter-data"));
+            let peers: Vec<proto::ServerInfo> = (1..=3)
+                .map(|id| proto::ServerInfo { server_id: id, server_addr: format!("[::1]:{}", 9100 + id), zone: None })
+                .collect();
+            let mut nodes = Vec::new();
+            for id in 1..=3u64 {
+                let counter_addr = format!("[::1]:{}", 10100 + id).parse()?;
+                nodes.push(start_node(id, 9100 + id as u32, peers.clone(), storage.clone(), counter_addr).await?);
+            }
+            info!("Counter cluster is running, try `cargo run --example counter -- incr [::1]:10101 visits`");
+            tokio::signal::ctrl_c().await?;
+            for node in nodes {
+                node.stop().await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("[::1]:0").unwrap().local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_counter_service_on_single_node_cluster() {
+        let storage = raft::storage::StoragePaths::temp().unwrap();
+        let raft_port = free_port() as u32;
+        let counter_addr: std::net::SocketAddr = format!("[::1]:{}", free_port()).parse().unwrap();
+        let peers = vec![proto::ServerInfo { server_id: 1, server_addr: format!("[::1]:{}", raft_port), zone: None }];
+        let node = start_node(1, raft_port, peers, storage, counter_addr).await.unwrap();
+
+        // 单节点集群在第一次选举超时后成为 Leader
+        let deadline = tokio::time::Instant::now() + config::CLUSTER_UNAVAILABLE_TIMEOUT;
+        while node.get_leader().await.leader.is_none() {
+            assert!(tokio::time::Instant::now() < deadline, "no leader elected");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let mut client = CounterClient::connect(format!("http://{}", counter_addr)).await.unwrap();
+        for (delta, expected) in [(1, 1), (5, 6), (-2, 4)] {
+            let response = client.increment(counter_proto::IncrementRequest { name: "visits".to_string(), delta }).await.unwrap();
+            assert_eq!(response.get_ref().value, expected);
+        }
+        let get = |name: &str| counter_proto::GetRequest { name: name.to_string() };
+        assert_eq!(client.get(get("visits")).await.unwrap().get_ref().value, 4);
+        assert_eq!(client.get(get("missing")).await.unwrap().get_ref().value, 0);
+        node.stop().await.unwrap();
+    }
+}
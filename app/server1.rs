@@ -3,10 +3,9 @@ use std::path::Path;
 use core::panic;
 use std::io::{Read, Write};
 use std::sync::Arc;
-use tokio::sync::Mutex as TokioMutex;
 use tracing::{error, info};
 use KEEP_RUNNING::raft::{self, config, snapshot};
-use KEEP_RUNNING::raft::{consensus, proto, rpc, state_machine};
+use KEEP_RUNNING::raft::{proto, rpc, state_machine, util};
 use tracing_subscriber::fmt::writer::MakeWriterExt;
 use std::collections::HashMap;
 use tokio::task::JoinHandle;
@@ -52,6 +51,12 @@ impl state_machine::StateMachine for MystateMachine {
         info!("State machine snapshot taken to {}", snapshot_filepath);
     }
 
+    fn read(&self, _query: &[u8]) -> Option<Vec<u8>> {
+        let datas_guard = self.datas.lock().unwrap();
+        let entries: Vec<String> = datas_guard.iter().map(|d| String::from_utf8_lossy(d).into_owned()).collect();
+        serde_json::to_vec(&entries).ok()
+    }
+
     fn restore_snapshot(&mut self, snapshot_filepath: &str) {
         if std::path::Path::new(snapshot_filepath).exists() {
             let snapshot_json = std::fs::read_to_string(snapshot_filepath)
@@ -68,8 +73,10 @@ impl state_machine::StateMachine for MystateMachine {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
+    let storage = raft::storage::StoragePaths::new(std::env::current_dir()?);
+
     // 日志初始化
-    let file_appender = tracing_appender::rolling::hourly("./logs", "server.log");
+    let file_appender = tracing_appender::rolling::hourly(storage.log_dir(), "server.log");
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
     let writer = non_blocking.and(std::io::stdout);
     let _ = tracing_subscriber::fmt()
@@ -78,8 +85,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .try_init();
     info!("Global logger initialized.");
     
-    let project_root = std::env::current_dir()?;
-    info!("Project root directory: {}", project_root.display());
+    info!("Data root directory: {}", storage.root().display());
 
 
     // 定义集群配置
@@ -98,16 +104,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .map(|(id, port)| proto::ServerInfo {
                 server_id: *id,
                 server_addr: format!("[::1]:{}", port),
+                zone: None,
             })
             .collect::<Vec<_>>()
     );
 
     // 使用 HashMap 来管理节点的 JoinHandle，方便我们杀掉和重启
-    let mut node_handles: HashMap<u64, JoinHandle<Option<Arc<TokioMutex<consensus::Consensus>>>>> = HashMap::new();
-    let project_root = std::env::current_dir()?;
+    let mut node_handles: HashMap<u64, JoinHandle<Option<raft::lib::RaftNode>>> = HashMap::new();
 
     for (server_id, port) in &cluster_info {
-        let handle = spawn_node(*server_id, *port, Arc::clone(&all_peers_info), project_root.clone()).await;
+        let handle = spawn_node(*server_id, *port, Arc::clone(&all_peers_info), storage.clone()).await;
         node_handles.insert(*server_id, handle);
     }
 
@@ -115,18 +121,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 使用一个命令行参数来决定是否开启 chaos 模式
     let args: Vec<String> = std::env::args().collect();
     if args.contains(&"--chaos".to_string()) {
-        info!("Chaos mode enabled! Nodes will be randomly killed and restarted.");
+        info!("Chaos mode enabled! Nodes will be randomly killed and restarted. Random seed: {}", util::Rng::global().seed());
         
         let chaos_all_peers = Arc::clone(&all_peers_info);
-        let chaos_project_root = project_root.clone();
+        let chaos_storage = storage.clone();
 
         tokio::spawn(async move {
             loop {
                 // 每隔 15-30 秒搞一次事情
-                let sleep_duration = Duration::from_secs(rand::random_range(15..30));
+                let sleep_duration = Duration::from_secs(util::Rng::global().random_range(15..30));
                 tokio::time::sleep(sleep_duration).await;
                 
-                let target_id = rand::random_range(1..=cluster_info.len() as u64);
+                let target_id = util::Rng::global().random_range(1..=cluster_info.len() as u64);
 
                 info!("[CHAOS] Targeting node {} for termination.", target_id);
                 if let Some(handle) = node_handles.get(&target_id) {
@@ -139,7 +145,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 info!("[CHAOS] Restarting node {}.", target_id);
                 let port = cluster_info.iter().find(|(id, _)| *id == target_id).unwrap().1;
-                let new_handle = spawn_node(target_id, port, Arc::clone(&chaos_all_peers), chaos_project_root.clone()).await;
+                let new_handle = spawn_node(target_id, port, Arc::clone(&chaos_all_peers), chaos_storage.clone()).await;
                 node_handles.insert(target_id, new_handle);
                 info!("[CHAOS] Node {} restarted.", target_id);
             }
@@ -159,24 +165,31 @@ async fn spawn_node(
     server_id: u64,
     port: u32,
     all_peers_info: Arc<Vec<proto::ServerInfo>>,
-    project_root: std::path::PathBuf,
-) -> JoinHandle<Option<Arc<TokioMutex<consensus::Consensus>>>> {
+    storage: raft::storage::StoragePaths,
+) -> JoinHandle<Option<raft::lib::RaftNode>> {
     tokio::spawn(async move {
         info!("Preparing to start Raft node {} on port {}", server_id, port);
-        // ... (构建路径、创建状态机等逻辑和之前一样) ...
-        let snapshot_dir = project_root.join(format!(".snapshot/server_{}", server_id));
-        let metadata_dir = project_root.join(format!(".metadata/server_{}", server_id));
-        let _ = tokio::fs::create_dir_all(&snapshot_dir).await;
-        let _ = tokio::fs::create_dir_all(&metadata_dir).await;
         let state_machine = Box::new(MystateMachine::new());
         let peers_vec: Vec<proto::ServerInfo> = (*all_peers_info).clone();
         
         match raft::lib::start(
             server_id, port, peers_vec, state_machine,
-            snapshot_dir.to_str().unwrap().to_string(),
-            metadata_dir.to_str().unwrap().to_string()
+            storage,
+            config::RaftOptions::default(),
         ).await {
-            Ok(arc) => Some(arc),
+            Ok(node) => {
+                if let Err(e) = node.ready().await {
+                    error!("Raft node {} RPC server failed: {}", server_id, e);
+                    return None;
+                }
+                let report = node.startup_report();
+                info!(
+                    "Raft node {} ready on {}: snapshot=({}, {}), log=[{}, {}]",
+                    server_id, report.bound_addr, report.snapshot_last_included_index,
+                    report.snapshot_last_included_term, report.log_start_index, report.log_last_index
+                );
+                Some(node)
+            }
             Err(e) => {
                 error!("Raft node {} failed to start: {}", server_id, e);
                 None
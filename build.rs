@@ -1,12 +1,30 @@
-use std::{env, path::PathBuf};
+use std::{env, path::{Path, PathBuf}, process::Command};
 fn main() {
 
+    // 构建时的 git 提交，GetVersion RPC 会返回它；不在 git 仓库中构建时为 unknown
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=RAFT_GIT_HASH={}", git_hash);
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
     // 要给哪些东西派生？这其实是一个需要思考的问题
     
     tonic_build::configure()
         // 给proto生成的rust类型加上派生宏
         .type_attribute("LogEntry","#[derive(serde::Deserialize, serde::Serialize)]")
         .type_attribute("ServerInfo", "#[derive(serde::Deserialize, serde::Serialize)]")
+        .type_attribute("ConfigChange", "#[derive(serde::Deserialize, serde::Serialize)]")
+        .type_attribute("CommandAuditRecord", "#[derive(serde::Deserialize, serde::Serialize)]")
         .compile_protos(&["proto/raft.proto"], &["proto"])
         .unwrap();
 
@@ -17,4 +35,9 @@ fn main() {
         .file_descriptor_set_path(out_dir.join("helloworld_descriptor.bin"))
         .compile_protos(&["proto/helloworld.proto"], &["proto"])
         .unwrap();
+
+    // app/counter.rs 示例的对外服务
+    tonic_build::configure()
+        .compile_protos(&["proto/counter.proto"], &["proto"])
+        .unwrap();
 }
\ No newline at end of file
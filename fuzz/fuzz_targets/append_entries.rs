@@ -0,0 +1,40 @@
+#![no_main]
+
+use KEEP_RUNNING::raft::{clock, consensus::Consensus, proto, sanity, state_machine};
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+use std::sync::Arc;
+
+// 把任意字节解码为 AppendEntriesRequest，交给一个新启动的 Follower 处理，
+// 与经由 RPC 收到这个请求的路径相同；畸形的请求只能被拒绝，不能让节点 panic
+fuzz_target!(|data: &[u8]| {
+    let Ok(request) = proto::AppendEntriesRequest::decode(data) else {
+        return;
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_dir = dir.path().join("snapshot");
+        let metadata_dir = dir.path().join("metadata");
+        std::fs::create_dir_all(&snapshot_dir).unwrap();
+        std::fs::create_dir_all(&metadata_dir).unwrap();
+        let consensus = Consensus::new(
+            1,
+            "[::1]:1".to_string(),
+            Vec::new(),
+            Box::new(state_machine::SimpleStateMachine::new()),
+            snapshot_dir.to_str().unwrap().to_string(),
+            metadata_dir.to_str().unwrap().to_string(),
+            Arc::new(clock::SystemClock),
+        ).await.unwrap();
+        let mut guard = consensus.lock().await;
+        // 与 RPC 服务一样先经过 check_request 检查，通过后才交给共识模块；同一个请求重复送达也要能处理
+        for _ in 0..2 {
+            let checked = guard.check_request("AppendEntries", request.leader_id, |term, last| sanity::check_append_entries(&request, term, last)).await;
+            if checked.is_ok() {
+                guard.handle_append_entries_rpc(&request).await;
+            }
+        }
+        guard.shutdown().await;
+    });
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use KEEP_RUNNING::raft::config::Config;
+use libfuzzer_sys::fuzz_target;
+
+// 配置条目的数据来自网络和磁盘，任意字节都只能得到错误，不能 panic；
+// 能解析的配置重新序列化后必须得到相同的配置
+fuzz_target!(|data: &[u8]| {
+    if let Ok(config) = Config::try_from_data(data) {
+        let round_trip = Config::try_from_data(&config.to_data()).expect("re-encoded configuration must parse");
+        assert_eq!(round_trip.to_data(), config.to_data());
+    }
+});
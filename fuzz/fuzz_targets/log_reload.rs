@@ -0,0 +1,23 @@
+#![no_main]
+
+use KEEP_RUNNING::raft::log::Log;
+use libfuzzer_sys::fuzz_target;
+
+// 把任意字节当作 raft.log 重新加载：损坏的文件加载失败，加载成功的日志上常用的查询都不能 panic
+fuzz_target!(|data: &[u8]| {
+    let dir = tempfile::tempdir().unwrap();
+    let metadata_dir = dir.path().to_str().unwrap().to_string();
+    std::fs::write(Log::gen_log_filepath(&metadata_dir), data).unwrap();
+
+    let verified = Log::verify_reader(data);
+    let mut log = Log::new(1, metadata_dir);
+    assert_eq!(log.reload().is_ok(), verified.is_ok());
+    let last_index = log.last_index(0);
+    let _ = log.last_term(0);
+    let _ = log.entry(log.start_index());
+    let _ = log.entry(last_index);
+    let _ = log.last_configuration_entry();
+    if let Ok(count) = verified {
+        assert_eq!(log.entries().len(), count);
+    }
+});
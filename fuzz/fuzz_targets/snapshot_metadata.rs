@@ -0,0 +1,14 @@
+#![no_main]
+
+use KEEP_RUNNING::raft::snapshot::SnapshotMeta;
+use libfuzzer_sys::fuzz_target;
+
+// 快照元数据来自本地文件或 FetchSnapshot 拉取的远端数据，解析失败只能返回错误
+fuzz_target!(|data: &[u8]| {
+    let Ok(json) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(meta) = SnapshotMeta::parse(json) {
+        assert!(meta.last_included_index.checked_add(1).is_some());
+    }
+});
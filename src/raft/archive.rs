@@ -0,0 +1,297 @@
+use crate::raft::{config, log, metadata, proto, snapshot};
+use super::logging::*;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+// 快照和日志归档：节点生成快照后，把快照文件和被快照压缩掉的日志条目（封存的日志段）上传到对象存储，
+// 可以据此恢复到某个历史时刻的状态，也可以用归档初始化新节点，而不必都从 Leader 拉取快照。
+// 对象的布局（prefix 默认为 group-{group_id}/server-{server_id}）：
+//   {prefix}/snapshots/raft-{index}-{term}.snapshot(.metadata)
+//   {prefix}/log/{start_index}-{end_index}.segment   条目的 JSON 数组，索引补零到 20 位便于排序
+// 快照元数据在快照数据之后上传，作为该快照归档完成的标志
+
+// 对象存储的最小抽象，调用发生在阻塞线程池中
+pub trait BlobStore: Debug + Send + Sync + 'static {
+    // 写入对象，已存在时覆盖；写入必须是原子的，读者看不到写了一半的对象
+    fn put(&self, key: &str, data: &[u8]) -> std::io::Result<()>;
+
+    fn get(&self, key: &str) -> std::io::Result<Vec<u8>>;
+
+    // 列出 prefix 下一层的所有对象键，prefix 不存在时返回空列表
+    fn list(&self, prefix: &str) -> std::io::Result<Vec<String>>;
+}
+
+// 以本地（或挂载的）目录作为对象存储，对象键中的 '/' 对应子目录
+#[derive(Debug, Clone)]
+pub struct FsBlobStore {
+    root: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FsBlobStore { root: root.into() }
+    }
+}
+
+impl BlobStore for FsBlobStore {
+    fn put(&self, key: &str, data: &[u8]) -> std::io::Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("uploading");
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, &path)
+    }
+
+    fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        std::fs::read(self.root.join(key))
+    }
+
+    fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        let dir = match std::fs::read_dir(self.root.join(prefix)) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut keys = Vec::new();
+        for entry in dir {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_file() && !name.ends_with(".uploading") {
+                keys.push(format!("{}/{}", prefix.trim_end_matches('/'), name));
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+pub fn default_prefix(group_id: u64, server_id: u64) -> String {
+    format!("group-{}/server-{}", group_id, server_id)
+}
+
+fn segment_key(prefix: &str, start: u64, end: u64) -> String {
+    format!("{}/log/{:020}-{:020}.segment", prefix, start, end)
+}
+
+fn parse_segment_key(key: &str) -> Option<(u64, u64)> {
+    let name = key.rsplit('/').next()?.strip_suffix(".segment")?;
+    let (start, end) = name.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+
+fn parse_snapshot_metadata_key(key: &str) -> Option<(u64, u64)> {
+    let name = key.rsplit('/').next()?.strip_prefix("raft-")?.strip_suffix(".snapshot.metadata")?;
+    let (index, term) = name.split_once('-')?;
+    Some((index.parse().ok()?, term.parse().ok()?))
+}
+
+#[derive(Debug)]
+enum ArchiveJob {
+    Segment { entries: Vec<proto::LogEntry> },
+    Snapshot { snapshot_filepath: String, metadata_filepath: String },
+}
+
+// 后台上传任务的句柄，按提交顺序依次上传；上传失败只记录日志，不影响节点运行
+#[derive(Debug)]
+pub struct Archiver {
+    tx: mpsc::UnboundedSender<ArchiveJob>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Archiver {
+    pub fn spawn(store: Arc<dyn BlobStore>, prefix: String) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<ArchiveJob>();
+        let task = tokio::spawn(async move {
+            while let Some(job) = rx.recv().await {
+                let store = Arc::clone(&store);
+                let prefix = prefix.clone();
+                let result = tokio::task::spawn_blocking(move || upload(store.as_ref(), &prefix, job)).await;
+                match result {
+                    Ok(Ok(key)) => info!("Archived {}", key),
+                    Ok(Err(e)) => error!("Archive upload failed: {}", e),
+                    Err(e) => error!("Archive upload task panicked: {}", e),
+                }
+            }
+        });
+        Archiver { tx, task }
+    }
+
+    // 归档即将被快照压缩掉的日志条目
+    pub fn archive_segment(&self, entries: Vec<proto::LogEntry>) {
+        if entries.is_empty() {
+            return;
+        }
+        let _ = self.tx.send(ArchiveJob::Segment { entries });
+    }
+
+    // 归档刚生成的快照；文件在上传前被更新的快照替换时跳过，新的快照也会被归档
+    pub fn archive_snapshot(&self, snapshot_filepath: String, metadata_filepath: String) {
+        let _ = self.tx.send(ArchiveJob::Snapshot { snapshot_filepath, metadata_filepath });
+    }
+
+    // 等待已提交的上传全部完成
+    pub async fn close(self) {
+        drop(self.tx);
+        let _ = self.task.await;
+    }
+}
+
+fn upload(store: &dyn BlobStore, prefix: &str, job: ArchiveJob) -> anyhow::Result<String> {
+    match job {
+        ArchiveJob::Segment { entries } => {
+            let (start, end) = (entries[0].index, entries[entries.len() - 1].index);
+            let key = segment_key(prefix, start, end);
+            store.put(&key, &serde_json::to_vec(&entries)?)?;
+            Ok(key)
+        }
+        ArchiveJob::Snapshot { snapshot_filepath, metadata_filepath } => {
+            let file_name = |path: &str| path.rsplit('/').next().unwrap_or(path).to_string();
+            let data = std::fs::read(&snapshot_filepath)
+                .map_err(|e| anyhow::anyhow!("snapshot {} is gone before upload: {}", snapshot_filepath, e))?;
+            let meta = std::fs::read(&metadata_filepath)
+                .map_err(|e| anyhow::anyhow!("snapshot metadata {} is gone before upload: {}", metadata_filepath, e))?;
+            store.put(&format!("{}/snapshots/{}", prefix, file_name(&snapshot_filepath)), &data)?;
+            let key = format!("{}/snapshots/{}", prefix, file_name(&metadata_filepath));
+            store.put(&key, &meta)?;
+            Ok(key)
+        }
+    }
+}
+
+// 用归档初始化一个全新的节点：选择索引不超过 target_index（为 None 时不限）的最新快照，
+// 再把之后连续的归档日志条目（同样不超过 target_index）写入日志。
+// 恢复出的日志条目需要等新的 Leader 在自己的任期内提交一条日志后才会被视为已提交。
+// 返回恢复到的最后索引，节点已有数据或没有可用的快照时返回 None
+pub fn restore_from_archive(
+    store: &dyn BlobStore,
+    prefix: &str,
+    snapshot_dir: &str,
+    metadata_dir: &str,
+    target_index: Option<u64>,
+) -> anyhow::Result<Option<u64>> {
+    let target_index = target_index.unwrap_or(u64::MAX);
+    let latest = store.list(&format!("{}/snapshots", prefix))?
+        .iter()
+        .filter_map(|key| parse_snapshot_metadata_key(key))
+        .filter(|(index, _)| *index <= target_index)
+        .max();
+    let Some((index, term)) = latest else {
+        warn!("No archived snapshot at or before index {} under {}", target_index, prefix);
+        return Ok(None);
+    };
+
+    let meta: snapshot::SnapshotMeta = serde_json::from_slice(&store.get(&format!("{}/snapshots/raft-{}-{}.snapshot.metadata", prefix, index, term))?)?;
+    let data = store.get(&format!("{}/snapshots/raft-{}-{}.snapshot", prefix, index, term))?;
+    if meta.checksum != 0 && snapshot::checksum_reader(&data[..])? != meta.checksum {
+        return Err(anyhow::anyhow!("archived snapshot raft-{}-{} does not match its checksum", index, term));
+    }
+    let download_filepath = format!("{}/raft-{}-{}.archive-download", snapshot_dir, index, term);
+    std::fs::write(&download_filepath, &data)?;
+    let initial = config::InitialSnapshot { filepath: download_filepath.clone(), last_included_index: index, last_included_term: term };
+    let imported = snapshot::import_initial_snapshot(snapshot_dir, metadata_dir, &initial, meta.configuration.unwrap_or_else(config::Config::new));
+    std::fs::remove_file(&download_filepath)?;
+    if !imported? {
+        return Ok(None);
+    }
+
+    // 快照之后连续的日志条目，遇到缺口就停止
+    let mut segments: Vec<(u64, u64, String)> = store.list(&format!("{}/log", prefix))?
+        .into_iter()
+        .filter_map(|key| parse_segment_key(&key).map(|(start, end)| (start, end, key)))
+        .filter(|(_, end, _)| *end > index)
+        .collect();
+    segments.sort();
+    let mut entries: Vec<proto::LogEntry> = Vec::new();
+    'segments: for (start, _, key) in segments {
+        let next_index = index + entries.len() as u64 + 1;
+        if start > next_index || next_index > target_index {
+            break;
+        }
+        let segment: Vec<proto::LogEntry> = serde_json::from_slice(&store.get(&key)?)?;
+        for entry in segment.into_iter().filter(|e| e.index >= next_index) {
+            if entry.index != index + entries.len() as u64 + 1 || entry.index > target_index {
+                break 'segments;
+            }
+            entries.push(entry);
+        }
+    }
+
+    let last_index = index + entries.len() as u64;
+    if let Some(last_term) = entries.last().map(|e| e.term) {
+        let mut log = log::Log::new(index + 1, metadata_dir.to_string());
+        log.append_entries(entries);
+        log.dump();
+        if last_term > term {
+            let restored = metadata::Metadata::load(metadata_dir)?;
+            metadata::Metadata { current_term: last_term, ..restored }.store()?;
+        }
+    }
+    info!("Restored node data in {} from archive {} up to index {} (snapshot at {})", metadata_dir, prefix, last_index, index);
+    Ok(Some(last_index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(index: u64, term: u64) -> proto::LogEntry {
+        proto::LogEntry { index, term, entry_type: proto::EntryType::Data as i32, data: format!("entry-{}", index).into_bytes(), timestamp_ms: None }
+    }
+
+    #[tokio::test]
+    async fn test_archive_and_restore_to_index() {
+        let archive_dir = tempdir().unwrap();
+        let source_dir = tempdir().unwrap();
+        let source_str = source_dir.path().to_str().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FsBlobStore::new(archive_dir.path()));
+
+        // 源节点在索引 3 和 6 各生成一次快照，两次之间的日志段为 [4, 6]
+        let mut manager = snapshot::SnapshotManager::new(source_str.to_string());
+        let configuration = config::Config::new_stable(vec![proto::ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string(), zone: None }]);
+        let archiver = Archiver::spawn(Arc::clone(&store), default_prefix(0, 1));
+        archiver.archive_segment((1..=3).map(|i| entry(i, 1)).collect());
+        for (index, term) in [(3, 1), (6, 2)] {
+            std::fs::write(manager.gen_snapshot_filepath(index, term), format!("state@{}", index)).unwrap();
+            manager.take_snapshot_metadata(index, term, Some(configuration.clone()), index, None).unwrap();
+            archiver.archive_snapshot(manager.gen_snapshot_filepath(index, term), manager.gen_snapshot_metadata_filepath(index, term));
+            if index == 3 {
+                archiver.archive_segment((4..=6).map(|i| entry(i, 2)).collect());
+            }
+        }
+        archiver.close().await;
+        assert_eq!(store.list("group-0/server-1/log").unwrap().len(), 2);
+
+        // 恢复到索引 5：使用索引 3 的快照，再重放 4、5 两条日志
+        let snapshot_dir = tempdir().unwrap();
+        let metadata_dir = tempdir().unwrap();
+        let (snapshot_str, metadata_str) = (snapshot_dir.path().to_str().unwrap(), metadata_dir.path().to_str().unwrap());
+        let restored = restore_from_archive(store.as_ref(), &default_prefix(0, 1), snapshot_str, metadata_str, Some(5)).unwrap();
+        assert_eq!(restored, Some(5));
+
+        let mut restored_manager = snapshot::SnapshotManager::new(snapshot_str.to_string());
+        restored_manager.reload_metadata();
+        assert_eq!((restored_manager.last_included_index(), restored_manager.last_included_term()), (3, 1));
+        assert_eq!(std::fs::read_to_string(restored_manager.latest_snapshot_filepath().unwrap()).unwrap(), "state@3");
+        let mut log = log::Log::new(1, metadata_str.to_string());
+        log.reload().unwrap();
+        assert_eq!(log.entries().iter().map(|e| e.index).collect::<Vec<_>>(), vec![4, 5]);
+        assert_eq!(metadata::Metadata::load(metadata_str).unwrap().current_term, 2);
+
+        // 节点已经有数据时不再恢复
+        assert_eq!(restore_from_archive(store.as_ref(), &default_prefix(0, 1), snapshot_str, metadata_str, None).unwrap(), None);
+
+        // 不指定索引时使用最新的快照
+        let snapshot_dir = tempdir().unwrap();
+        let metadata_dir = tempdir().unwrap();
+        let restored = restore_from_archive(
+            store.as_ref(), &default_prefix(0, 1),
+            snapshot_dir.path().to_str().unwrap(), metadata_dir.path().to_str().unwrap(), None,
+        ).unwrap();
+        assert_eq!(restored, Some(6));
+    }
+}
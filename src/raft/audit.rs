@@ -0,0 +1,127 @@
+use crate::raft::consensus::State;
+use super::logging::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 任期、投票和角色变化的审计日志，每条记录一行 JSON，只追加不修改，
+// 用于事后分析集群的可用性抖动（为什么发生选举、谁投给了谁、何时失去 Leader）
+
+// 变化的原因
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AuditReason {
+    ElectionTimeout, // 选举超时，发起新一轮选举
+    HigherTermSeen,  // 在 RPC 请求或响应中看到了更高的任期
+    LeaderSeen,      // 同一任期内收到了另一个 Leader 的请求
+    VoteRequested,   // 收到候选人的投票请求
+    ElectionWon,     // 获得多数派选票
+    Shutdown,        // 节点关闭
+    RemovedFromConfig, // 被提交的配置变更移出集群
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuditEvent {
+    TermChanged { from: u64, to: u64 },
+    VoteCast { candidate_id: u64 },
+    StateChanged { from: State, to: State },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp_ms: u64, // 墙上时间（Unix 毫秒），便于和其他节点的日志对齐
+    pub server_id: u64,
+    pub term: u64,         // 记录时节点所处的任期
+    pub reason: AuditReason,
+    pub event: AuditEvent,
+}
+
+pub struct AuditLog {
+    server_id: u64,
+    filepath: String,
+    file: Option<File>,
+}
+
+impl AuditLog {
+    pub fn new(server_id: u64, metadata_dir: &str) -> Self {
+        let filepath = Self::gen_filepath(metadata_dir);
+        // 审计日志打不开时不影响节点运行，只是不再记录
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&filepath)
+            .map_err(|e| warn!("Failed to open audit log {}: {}. Audit records will be dropped.", filepath, e))
+            .ok();
+        AuditLog { server_id, filepath, file }
+    }
+
+    pub fn gen_filepath(metadata_dir: &str) -> String {
+        format!("{}/raft.audit", metadata_dir)
+    }
+
+    pub fn filepath(&self) -> &str {
+        &self.filepath
+    }
+
+    pub fn record(&mut self, term: u64, reason: AuditReason, event: AuditEvent) {
+        let Some(file) = self.file.as_mut() else { return };
+        let record = AuditRecord {
+            timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+            server_id: self.server_id,
+            term,
+            reason,
+            event,
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize audit record {:?}: {}", record, e);
+                return;
+            }
+        };
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            error!("Failed to write audit record to {}: {}", self.filepath, e);
+        }
+    }
+
+    // 读取审计日志中的所有记录，跳过无法解析的行（例如崩溃时写了一半的最后一行）
+    pub fn read_records(filepath: &str) -> std::io::Result<Vec<AuditRecord>> {
+        let reader = BufReader::new(File::open(filepath)?);
+        let mut records = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("Skipping malformed audit record in {}: {}", filepath, e),
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_audit_log_appends_across_restarts() {
+        let dir = tempdir().unwrap();
+        let dir_str = dir.path().to_str().unwrap();
+
+        let mut audit = AuditLog::new(1, dir_str);
+        audit.record(1, AuditReason::ElectionTimeout, AuditEvent::TermChanged { from: 0, to: 1 });
+        audit.record(1, AuditReason::ElectionTimeout, AuditEvent::VoteCast { candidate_id: 1 });
+        drop(audit);
+
+        // 重启后继续追加，之前的记录不丢失
+        let mut audit = AuditLog::new(1, dir_str);
+        audit.record(1, AuditReason::ElectionWon, AuditEvent::StateChanged { from: State::Candidate, to: State::Leader });
+
+        let records = AuditLog::read_records(audit.filepath()).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].event, AuditEvent::TermChanged { from: 0, to: 1 });
+        assert_eq!(records[2].reason, AuditReason::ElectionWon);
+        assert!(records.iter().all(|r| r.server_id == 1 && r.term == 1));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::raft::{client, config, proto};
+use super::logging::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// 客户端压测：多个并发任务向 Leader 发送读写混合的请求，记录每个请求的延迟并输出分位数。
+// 预热期间发出的请求不计入统计；写请求失败时按拒绝原因重试，读请求被拒绝时换 Leader 重试
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub concurrency: usize,
+    pub total_requests: usize, // 计入统计的请求总数，平均分给各个任务
+    pub read_ratio: f64,       // 读请求所占的比例，0 表示只写，1 表示只读
+    pub payload_size: usize,   // 每个写请求的数据字节数
+    pub warmup: Duration,      // 开始统计之前的预热时间
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            concurrency: 1,
+            total_requests: 1000,
+            read_ratio: 0.0,
+            payload_size: 32,
+            warmup: Duration::ZERO,
+        }
+    }
+}
+
+// 一类请求的统计
+#[derive(Debug, Clone, Default)]
+pub struct OpStats {
+    pub succeeded: usize,
+    pub failed: usize,
+    latencies_us: Vec<u64>, // 成功请求的延迟，汇总后按升序排列
+}
+
+impl OpStats {
+    fn record(&mut self, latency: Option<Duration>) {
+        match latency {
+            Some(latency) => {
+                self.succeeded += 1;
+                self.latencies_us.push(latency.as_micros() as u64);
+            }
+            None => self.failed += 1,
+        }
+    }
+
+    fn merge(&mut self, other: OpStats) {
+        self.succeeded += other.succeeded;
+        self.failed += other.failed;
+        self.latencies_us.extend(other.latencies_us);
+        self.latencies_us.sort_unstable();
+    }
+
+    // 最近秩法计算的分位数（微秒），p 取 0~100
+    pub fn percentile_us(&self, p: f64) -> Option<u64> {
+        if self.latencies_us.is_empty() {
+            return None;
+        }
+        let rank = ((p / 100.0) * self.latencies_us.len() as f64).ceil() as usize;
+        Some(self.latencies_us[rank.clamp(1, self.latencies_us.len()) - 1])
+    }
+
+    pub fn mean_us(&self) -> Option<u64> {
+        if self.latencies_us.is_empty() {
+            return None;
+        }
+        Some(self.latencies_us.iter().sum::<u64>() / self.latencies_us.len() as u64)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub options: BenchOptions,
+    pub duration: Duration, // 预热结束到最后一个请求完成的时间
+    pub warmup_requests: usize,
+    pub writes: OpStats,
+    pub reads: OpStats,
+}
+
+impl BenchReport {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("--- Benchmark Results ---\n");
+        out.push_str(&format!("Concurrent tasks: {}\n", self.options.concurrency));
+        out.push_str(&format!("Read ratio: {:.2}, payload: {} bytes, warmup: {:?} ({} requests)\n",
+            self.options.read_ratio, self.options.payload_size, self.options.warmup, self.warmup_requests));
+        out.push_str(&format!("Total time: {:?}\n", self.duration));
+        let succeeded = self.writes.succeeded + self.reads.succeeded;
+        out.push_str(&format!("Requests per second: {:.2}\n", succeeded as f64 / self.duration.as_secs_f64().max(f64::EPSILON)));
+        let us = |v: Option<u64>| v.map_or("-".to_string(), |v| format!("{}us", v));
+        for (name, stats) in [("write", &self.writes), ("read", &self.reads)] {
+            if stats.succeeded + stats.failed == 0 {
+                continue;
+            }
+            out.push_str(&format!(
+                "{:<5} ok={} failed={} mean={} p50={} p95={} p99={} max={}\n",
+                name,
+                stats.succeeded,
+                stats.failed,
+                us(stats.mean_us()),
+                us(stats.percentile_us(50.0)),
+                us(stats.percentile_us(95.0)),
+                us(stats.percentile_us(99.0)),
+                us(stats.percentile_us(100.0)),
+            ));
+        }
+        out
+    }
+}
+
+// 第 seq 个请求是否为读请求；按比例均匀穿插，而不是随机，便于复现
+fn is_read(seq: usize, read_ratio: f64) -> bool {
+    let ratio = read_ratio.clamp(0.0, 1.0);
+    ((seq + 1) as f64 * ratio).floor() > (seq as f64 * ratio).floor()
+}
+
+fn payload(task: usize, seq: usize, size: usize) -> Vec<u8> {
+    let mut data = format!("task-{}-req-{}", task, seq).into_bytes();
+    data.resize(size, b'x');
+    data
+}
+
+pub async fn run(leader_cache: Arc<client::LeaderCache>, options: BenchOptions) -> BenchReport {
+    let concurrency = options.concurrency.max(1);
+    let requests_per_task = options.total_requests / concurrency;
+    let started_at = Instant::now();
+    let measure_from = started_at + options.warmup;
+
+    let mut handles = Vec::with_capacity(concurrency);
+    for task in 0..concurrency {
+        let leader_cache = Arc::clone(&leader_cache);
+        let options = options.clone();
+        handles.push(tokio::spawn(async move {
+            let (mut writes, mut reads, mut warmup_requests) = (OpStats::default(), OpStats::default(), 0);
+            let mut seq = 0;
+            while Instant::now() < measure_from {
+                send(&leader_cache, task, seq, &options).await;
+                warmup_requests += 1;
+                seq += 1;
+            }
+            for _ in 0..requests_per_task {
+                let latency = send(&leader_cache, task, seq, &options).await;
+                if is_read(seq, options.read_ratio) {
+                    reads.record(latency);
+                } else {
+                    writes.record(latency);
+                }
+                seq += 1;
+            }
+            (writes, reads, warmup_requests)
+        }));
+    }
+
+    let mut report = BenchReport {
+        options,
+        duration: Duration::ZERO,
+        warmup_requests: 0,
+        writes: OpStats::default(),
+        reads: OpStats::default(),
+    };
+    for handle in handles {
+        match handle.await {
+            Ok((writes, reads, warmup_requests)) => {
+                report.writes.merge(writes);
+                report.reads.merge(reads);
+                report.warmup_requests += warmup_requests;
+            }
+            Err(e) => error!("Benchmark task failed: {}", e),
+        }
+    }
+    report.duration = Instant::now().saturating_duration_since(measure_from.max(started_at));
+    report
+}
+
+// 发送一个请求直到成功，返回延迟；放弃时返回 None
+async fn send(leader_cache: &client::LeaderCache, task: usize, seq: usize, options: &BenchOptions) -> Option<Duration> {
+    let read = is_read(seq, options.read_ratio);
+    let started_at = Instant::now();
+    for _ in 0..config::BENCH_REQUEST_ATTEMPTS {
+        let Some(leader) = leader_cache.get_leader().await else {
+            warn!("Task {}: could not find leader, retrying.", task);
+            tokio::time::sleep(config::CLIENT_PROPOSE_BACKOFF).await;
+            continue;
+        };
+        if read {
+            let req = proto::ReadRequest { query: Vec::new(), allow_degraded: false, read_token: None };
+            match leader_cache.rpc_client.read(req, leader.server_addr.clone()).await {
+                Ok(resp) if resp.success => return Some(started_at.elapsed()),
+                Ok(_) => {
+                    debug!("Task {}: read refused by {}, looking for the leader again.", task, leader.server_addr);
+                    leader_cache.update(None).await;
+                }
+                Err(e) => {
+                    warn!("Task {}: read from {} failed: {}", task, leader.server_addr, e);
+                    leader_cache.update(None).await;
+                }
+            }
+        } else {
+            let req = proto::ProposeRequest { data: payload(task, seq, options.payload_size), wait_for_commit: false };
+            match leader_cache.rpc_client.propose(req, leader.server_addr.clone()).await {
+                Ok(resp) if resp.success => return Some(started_at.elapsed()),
+                Ok(resp) => {
+                    if !leader_cache.handle_propose_rejection(&resp).await {
+                        return None;
+                    }
+                }
+                // 被服务端限流时等待后重试，Leader 没有变化
+                Err(e) if e.downcast_ref::<tonic::Status>().is_some_and(|s| s.code() == tonic::Code::ResourceExhausted) => {
+                    debug!("Task {}: rate limited by {}, backing off.", task, leader.server_addr);
+                    tokio::time::sleep(config::CLIENT_PROPOSE_BACKOFF).await;
+                }
+                Err(e) => {
+                    warn!("Task {}: propose to {} failed: {}", task, leader.server_addr, e);
+                    leader_cache.update(None).await;
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_mix_and_percentiles() {
+        let reads = (0..100).filter(|seq| is_read(*seq, 0.25)).count();
+        assert_eq!(reads, 25);
+        assert!((0..10).all(|seq| !is_read(seq, 0.0)));
+        assert!((0..10).all(|seq| is_read(seq, 1.0)));
+        assert_eq!(payload(1, 2, 32).len(), 32);
+
+        let mut stats = OpStats::default();
+        assert_eq!(stats.percentile_us(50.0), None);
+        for us in (1..=100).rev() {
+            stats.record(Some(Duration::from_micros(us)));
+        }
+        stats.record(None);
+        stats.merge(OpStats::default());
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.percentile_us(50.0), Some(50));
+        assert_eq!(stats.percentile_us(95.0), Some(95));
+        assert_eq!(stats.percentile_us(99.0), Some(99));
+        assert_eq!(stats.percentile_us(100.0), Some(100));
+        assert_eq!(stats.percentile_us(0.0), Some(1));
+        assert_eq!(stats.mean_us(), Some(50));
+    }
+}
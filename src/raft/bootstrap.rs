@@ -0,0 +1,146 @@
+use crate::raft::{config, log, proto, rpc, snapshot};
+use super::logging::*;
+use std::io::Write;
+
+// 新节点的快照引导：全新节点启动时直接从一个已经追上的节点（通常是 Follower）拉取最新的快照，
+// 按导入初始快照的方式写入本地，不必等 Leader 通过 InstallSnapshot 发送，
+// 向大集群添加节点时的带宽开销可以分摊到各个 Follower 上。快照之后的日志仍由 Leader 通过 AppendEntries 补齐
+
+// 从 source_addr 拉取快照并导入到本节点的数据目录，返回是否执行了导入；
+// 节点已有快照或日志、或者对方还没有快照时不做任何修改。快照中没有配置时使用 fallback_config
+pub async fn bootstrap_from_peer(
+    rpc_client: &rpc::Client,
+    source_addr: &str,
+    snapshot_dir: &str,
+    metadata_dir: &str,
+    fallback_config: config::Config,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let manager = snapshot::SnapshotManager::new(snapshot_dir.to_string());
+    let mut log = log::Log::new(1, metadata_dir.to_string());
+    log.reload()?;
+    if manager.latest_metadata_filepath().is_some() || !log.entries().is_empty() || log.start_index() != 1 {
+        info!("Node data in {} already initialized, not bootstrapping from {}", metadata_dir, source_addr);
+        return Ok(false);
+    }
+
+    // 先读元数据，确定要拉取的快照，之后的请求都固定为这个快照
+    let mut metadata_json = Vec::new();
+    let Some((index, term)) = fetch_file(rpc_client, source_addr, proto::SnapshotDataType::Metadata, (0, 0), &mut metadata_json).await? else {
+        info!("Server {} has no snapshot yet, starting {} empty", source_addr, metadata_dir);
+        return Ok(false);
+    };
+    let meta = snapshot::SnapshotMeta::parse(std::str::from_utf8(&metadata_json)?)?;
+    if (meta.last_included_index, meta.last_included_term) != (index, term) {
+        return Err(format!("snapshot metadata from {} describes ({}, {}), expected ({}, {})",
+            source_addr, meta.last_included_index, meta.last_included_term, index, term).into());
+    }
+
+    info!("Bootstrapping from snapshot ({}, {}) on {}", index, term, source_addr);
+    // 下载到单独的临时文件，中途退出时由启动时的清理删除
+    let download_filepath = format!("{}/raft-{}-{}.snapshot.fetch.tmp", snapshot_dir, index, term);
+    let mut file = std::fs::File::create(&download_filepath)?;
+    fetch_file(rpc_client, source_addr, proto::SnapshotDataType::Snapshot, (index, term), &mut file).await?;
+    file.sync_all()?;
+    drop(file);
+
+    let checksum = snapshot::checksum_file(&download_filepath)?;
+    if meta.checksum != 0 && checksum != meta.checksum {
+        std::fs::remove_file(&download_filepath)?;
+        return Err(format!("snapshot ({}, {}) from {} has checksum {:x}, expected {:x}", index, term, source_addr, checksum, meta.checksum).into());
+    }
+    let initial = config::InitialSnapshot { filepath: download_filepath.clone(), last_included_index: index, last_included_term: term };
+    let imported = snapshot::import_initial_snapshot(snapshot_dir, metadata_dir, &initial, meta.configuration.unwrap_or(fallback_config));
+    std::fs::remove_file(&download_filepath)?;
+    Ok(imported?)
+}
+
+// 按分块读取对方的一个快照文件写入 out，返回快照的 (索引, 任期)；对方没有快照时返回 None
+async fn fetch_file(
+    rpc_client: &rpc::Client,
+    source_addr: &str,
+    data_type: proto::SnapshotDataType,
+    pinned: (u64, u64),
+    out: &mut impl Write,
+) -> Result<Option<(u64, u64)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut offset = 0;
+    let mut pinned = pinned;
+    loop {
+        let req = proto::FetchSnapshotRequest {
+            snapshot_data_type: data_type as i32,
+            offset,
+            max_bytes: 0,
+            last_included_index: pinned.0,
+            last_included_term: pinned.1,
+        };
+        let resp = rpc_client.fetch_snapshot(req, source_addr.to_string()).await?;
+        if !resp.success {
+            if pinned.0 == 0 {
+                return Ok(None);
+            }
+            return Err(resp.error.unwrap_or_default().into());
+        }
+        pinned = (resp.last_included_index, resp.last_included_term);
+        out.write_all(&resp.data)?;
+        offset += resp.data.len() as u64;
+        if resp.done {
+            return Ok(Some(pinned));
+        }
+        if resp.data.is_empty() {
+            return Err(format!("server {} returned an empty chunk at offset {} of {}", source_addr, offset, resp.total_size).into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft::{consensus, state_machine, storage};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_bootstrap_from_peer_snapshot() {
+        let source_storage = storage::StoragePaths::temp().unwrap();
+        let source = consensus::tests::test_consensus(&source_storage, Box::new(state_machine::SimpleStateMachine::new())).await;
+        let bound = rpc::bind_server("[::1]:0", None, true, Arc::clone(&source), None, rpc::ServerHooks::new(), &config::TransportOptions::default()).await.unwrap();
+        let source_addr = bound.addr().to_string();
+        let stop = source.lock().await.stop_signal();
+        let serving = tokio::spawn(bound.serve(stop));
+
+        let storage = storage::StoragePaths::temp().unwrap();
+        let (snapshot_dir, metadata_dir) = storage.create_node_dirs(2).unwrap();
+        let fallback = config::Config::new_stable(vec![proto::ServerInfo { server_id: 2, server_addr: "[::1]:2".to_string(), zone: None }]);
+
+        // 对方还没有快照
+        assert!(!bootstrap_from_peer(&rpc::Client::default(), &source_addr, &snapshot_dir, &metadata_dir, fallback.clone()).await.unwrap());
+
+        {
+            let mut guard = source.lock().await;
+            guard.handle_election_timeout().await;
+            // 只由下面的手动触发生成快照
+            guard.snapshot_policy = config::SnapshotPolicy { log_length_threshold: 1000, ..Default::default() };
+            for i in 0..20 {
+                let data = format!("entry-{}", i).into_bytes();
+                assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data, wait_for_commit: false }).await.success);
+            }
+        }
+        assert!(consensus::Consensus::trigger_snapshot(&source, &proto::TriggerSnapshotRequest {}).await.success);
+        let (index, term, checksum) = {
+            let guard = source.lock().await;
+            (guard.snapshot.last_included_index(), guard.snapshot.last_included_term(), guard.snapshot.meta().checksum)
+        };
+
+        assert!(bootstrap_from_peer(&rpc::Client::default(), &source_addr, &snapshot_dir, &metadata_dir, fallback.clone()).await.unwrap());
+        let mut manager = snapshot::SnapshotManager::new(snapshot_dir.clone());
+        manager.reload_metadata();
+        assert_eq!((manager.last_included_index(), manager.last_included_term()), (index, term));
+        assert_eq!(snapshot::checksum_file(&manager.latest_snapshot_filepath().unwrap()).unwrap(), checksum);
+        let mut log = log::Log::new(1, metadata_dir.clone());
+        log.reload().unwrap();
+        assert_eq!(log.start_index(), index + 1);
+        assert!(!std::path::Path::new(&format!("{}/raft-{}-{}.snapshot.fetch.tmp", snapshot_dir, index, term)).exists());
+
+        // 已经有数据的节点不再引导
+        assert!(!bootstrap_from_peer(&rpc::Client::default(), &source_addr, &snapshot_dir, &metadata_dir, fallback).await.unwrap());
+        serving.abort();
+    }
+}
@@ -0,0 +1,134 @@
+use crate::raft::config;
+use super::logging::*;
+use std::collections::HashMap;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, Instant};
+
+// 按对端地址的熔断器：连续连接失败 BREAKER_FAILURE_THRESHOLD 次后熔断，
+// 熔断期间直接拒绝连接，不再每次心跳都等待连接超时；
+// 熔断到期后放行一次探测（半开），探测失败则退避时间翻倍，成功则恢复
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant, backoff: Duration },
+    HalfOpen { since: Instant, backoff: Duration },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreaker {
+    state: BreakerState,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker { state: BreakerState::Closed { failures: 0 } }
+    }
+}
+
+impl CircuitBreaker {
+    // 是否允许发起一次连接
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until, backoff } if now >= until => {
+                self.state = BreakerState::HalfOpen { since: now, backoff };
+                true
+            }
+            BreakerState::Open { .. } => false,
+            // 探测的请求可能被取消而没有回报结果，超过一个退避周期后允许再次探测
+            BreakerState::HalfOpen { since, backoff } if now >= since + backoff => {
+                self.state = BreakerState::HalfOpen { since: now, backoff };
+                true
+            }
+            BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    pub fn on_success(&mut self) {
+        self.state = BreakerState::Closed { failures: 0 };
+    }
+
+    // 记录一次失败，返回是否因此进入熔断
+    pub fn on_failure(&mut self, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Closed { failures } if failures + 1 >= config::BREAKER_FAILURE_THRESHOLD => {
+                self.state = BreakerState::Open { until: now + config::BREAKER_INITIAL_BACKOFF, backoff: config::BREAKER_INITIAL_BACKOFF };
+                true
+            }
+            BreakerState::Closed { failures } => {
+                self.state = BreakerState::Closed { failures: failures + 1 };
+                false
+            }
+            BreakerState::HalfOpen { backoff, .. } | BreakerState::Open { backoff, .. } => {
+                let backoff = std::cmp::min(backoff * 2, config::BREAKER_MAX_BACKOFF);
+                self.state = BreakerState::Open { until: now + backoff, backoff };
+                true
+            }
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(self.state, BreakerState::Closed { .. })
+    }
+}
+
+// 进程内所有 RPC 客户端共享的熔断器，按地址区分
+#[derive(Debug, Default)]
+pub struct BreakerRegistry {
+    breakers: StdMutex<HashMap<String, CircuitBreaker>>,
+}
+
+impl BreakerRegistry {
+    pub fn allow(&self, addr: &str) -> bool {
+        self.breakers.lock().unwrap().entry(addr.to_string()).or_default().allow(Instant::now())
+    }
+
+    pub fn on_success(&self, addr: &str) {
+        let mut breakers = self.breakers.lock().unwrap();
+        if let Some(breaker) = breakers.get_mut(addr) {
+            if breaker.is_open() {
+                info!("Circuit breaker for {} closed after a successful probe.", addr);
+            }
+            breaker.on_success();
+        }
+    }
+
+    pub fn on_failure(&self, addr: &str) {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = breakers.entry(addr.to_string()).or_default();
+        if breaker.on_failure(Instant::now()) {
+            warn!("Circuit breaker for {} is open, connections are rejected until the next probe.", addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker_transitions() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..config::BREAKER_FAILURE_THRESHOLD - 1 {
+            assert!(breaker.allow(start));
+            assert!(!breaker.on_failure(start));
+        }
+        assert!(breaker.on_failure(start));
+        assert!(!breaker.allow(start));
+
+        // 到期后只放行一次探测
+        let probe_at = start + config::BREAKER_INITIAL_BACKOFF;
+        assert!(breaker.allow(probe_at));
+        assert!(!breaker.allow(probe_at));
+
+        // 探测失败，退避时间翻倍
+        breaker.on_failure(probe_at);
+        assert!(!breaker.allow(probe_at + config::BREAKER_INITIAL_BACKOFF));
+        assert!(breaker.allow(probe_at + config::BREAKER_INITIAL_BACKOFF * 2));
+
+        breaker.on_success();
+        assert!(!breaker.is_open());
+        assert!(breaker.allow(probe_at));
+    }
+}
@@ -0,0 +1,262 @@
+use crate::raft::{config, proto, rpc};
+use super::logging::*;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
+use tokio::sync::Mutex as TokioMutex;
+
+// 客户端维护的 Leader 缓存，避免每个请求都去查找 Leader
+// 查找时并发询问所有节点，第一个给出 Leader 的回答胜出，其余请求被取消；
+// 探测失败的节点会在 CLIENT_DEAD_NODE_TTL 内被跳过（负缓存）
+pub struct LeaderCache {
+    cluster_addrs: Vec<String>,
+    leader_info: TokioMutex<Option<proto::ServerInfo>>,
+    dead_nodes: StdMutex<HashMap<String, Instant>>,
+    pub rpc_client: rpc::Client,
+}
+
+impl LeaderCache {
+    pub fn new(cluster_addrs: Vec<String>) -> Self {
+        Self {
+            cluster_addrs,
+            leader_info: TokioMutex::new(None),
+            dead_nodes: StdMutex::new(HashMap::new()),
+            rpc_client: rpc::Client::default(),
+        }
+    }
+
+    pub fn cluster_addrs(&self) -> &Vec<String> {
+        &self.cluster_addrs
+    }
+
+    pub async fn get_leader(&self) -> Option<proto::ServerInfo> {
+        let mut leader_info_guard = self.leader_info.lock().await;
+
+        if let Some(leader) = &*leader_info_guard {
+            return Some(leader.clone());
+        }
+
+        // 如果没有缓存的 Leader 信息，则并发查询
+        info!("No cached leader info, querying cluster...");
+        let leader = self.find_leader().await;
+        if let Some(leader) = &leader {
+            info!("Found leader: ID={}, Addr={}", leader.server_id, leader.server_addr);
+            *leader_info_guard = Some(leader.clone());
+        }
+        leader
+    }
+
+    pub async fn update(&self, new_leader: Option<proto::ServerInfo>) {
+        let mut leader_info_guard = self.leader_info.lock().await;
+        *leader_info_guard = new_leader;
+    }
+
+    // 在后台订阅 Leader 变化并随时更新缓存，get_leader 不必在请求失败后再去轮询；
+    // 订阅断开时换下一个节点重新订阅，返回的任务句柄 abort 后停止订阅
+    pub fn watch(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let cache = Arc::clone(self);
+        tokio::spawn(async move {
+            for addr in cache.cluster_addrs.iter().cycle() {
+                match cache.rpc_client.watch_leader(proto::WatchLeaderRequest {}, addr.clone()).await {
+                    Ok(mut stream) => {
+                        info!("Watching leader changes via {}", addr);
+                        while let Some(Ok(resp)) = stream.next().await {
+                            debug!("Leader change pushed by {}: {:?}", addr, resp.leader);
+                            cache.update(resp.leader).await;
+                        }
+                        info!("Leader watch on {} ended, resubscribing.", addr);
+                    }
+                    Err(e) => {
+                        warn!("Failed to watch leader via {}: {}", addr, e);
+                        cache.mark_dead(addr);
+                    }
+                }
+                tokio::time::sleep(config::CLIENT_WATCH_RETRY_INTERVAL).await;
+            }
+        })
+    }
+
+    // 根据 Propose 的拒绝原因更新 Leader 缓存并等待，返回 false 表示重试也不会成功
+    pub async fn handle_propose_rejection(&self, resp: &proto::ProposeResponse) -> bool {
+        let reason = proto::ProposeRejectReason::try_from(resp.reject_reason).unwrap_or(proto::ProposeRejectReason::None);
+        match reason {
+            proto::ProposeRejectReason::NotLeader | proto::ProposeRejectReason::None => {
+                info!("Propose rejected by non-leader, updating leader hint to {:?}", resp.leader_addr);
+                self.update(resp.leader_addr.clone().map(|addr| proto::ServerInfo {
+                    server_id: resp.index.unwrap_or(0),
+                    server_addr: addr,
+                    zone: None,
+                })).await;
+                true
+            }
+            proto::ProposeRejectReason::Backpressure | proto::ProposeRejectReason::Busy => {
+                warn!("Propose rejected ({:?}), backing off.", reason);
+                tokio::time::sleep(config::CLIENT_PROPOSE_BACKOFF).await;
+                true
+            }
+            proto::ProposeRejectReason::Shutdown | proto::ProposeRejectReason::ConfChangeInProgress => {
+                warn!("Propose rejected ({:?}), waiting for another leader.", reason);
+                self.update(None).await;
+                tokio::time::sleep(config::CLIENT_PROPOSE_BACKOFF).await;
+                true
+            }
+            proto::ProposeRejectReason::PayloadTooLarge => {
+                error!("Propose rejected: payload too large, giving up.");
+                false
+            }
+        }
+    }
+
+    // 强一致地读取集群配置：请求发往 Leader，由其确认领导权后返回；
+    // 被拒绝时根据响应中的 Leader 提示更新缓存并重试
+    pub async fn get_configuration_consistent(&self) -> Option<proto::GetConfigurationResponse> {
+        for _ in 0..config::CLIENT_CONSISTENT_READ_RETRIES {
+            let leader = self.get_leader().await?;
+            let req = proto::GetConfigurationRequest { consistent: true };
+            match self.rpc_client.get_configuration(req, leader.server_addr.clone()).await {
+                Ok(resp) if resp.success => return Some(resp),
+                Ok(resp) => {
+                    info!("Consistent GetConfiguration rejected by {}, leader hint: {:?}", leader.server_addr, resp.leader);
+                    self.update(resp.leader.filter(|hint| hint.server_id != leader.server_id)).await;
+                }
+                Err(e) => {
+                    warn!("Consistent GetConfiguration to {} failed: {}", leader.server_addr, e);
+                    self.update(None).await;
+                }
+            }
+        }
+        None
+    }
+
+    // 并发向所有存活节点发送 GetLeader，返回第一个有效的 Leader
+    async fn find_leader(&self) -> Option<proto::ServerInfo> {
+        let mut targets: Vec<String> = self.cluster_addrs.iter()
+            .filter(|addr| !self.is_dead(addr))
+            .cloned()
+            .collect();
+        // 所有节点都在负缓存中时，仍然全部探测一遍
+        if targets.is_empty() {
+            targets = self.cluster_addrs.clone();
+        }
+
+        let mut probes = FuturesUnordered::new();
+        for addr in targets {
+            let rpc_client = self.rpc_client.clone();
+            probes.push(async move {
+                let result = tokio::time::timeout(
+                    config::CLIENT_PROBE_TIMEOUT,
+                    rpc_client.get_leader(proto::GetLeaderRequest {}, addr.clone()),
+                ).await;
+                (addr, result)
+            });
+        }
+
+        while let Some((addr, result)) = probes.next().await {
+            match result {
+                Ok(Ok(resp)) => {
+                    self.mark_alive(&addr);
+                    if let Some(leader) = resp.leader {
+                        // 提前返回时 probes 被 drop，剩余的请求随之取消
+                        return Some(leader);
+                    }
+                    debug!("Node {} does not know the leader.", addr);
+                }
+                Ok(Err(e)) => {
+                    warn!("Failed to get leader from {}: {}", addr, e);
+                    self.mark_dead(&addr);
+                }
+                Err(_) => {
+                    warn!("Get leader from {} timed out", addr);
+                    self.mark_dead(&addr);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn is_dead(&self, addr: &str) -> bool {
+        let mut dead_nodes = self.dead_nodes.lock().unwrap();
+        match dead_nodes.get(addr) {
+            Some(marked_at) if marked_at.elapsed() < config::CLIENT_DEAD_NODE_TTL => true,
+            Some(_) => {
+                dead_nodes.remove(addr);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn mark_dead(&self, addr: &str) {
+        self.dead_nodes.lock().unwrap().insert(addr.to_string(), Instant::now());
+    }
+
+    fn mark_alive(&self, addr: &str) {
+        self.dead_nodes.lock().unwrap().remove(addr);
+    }
+}
+
+// 快照状态的可读输出，供命令行工具使用
+pub fn format_snapshot_status(status: &proto::GetSnapshotStatusResponse) -> String {
+    let restore = match &status.restore {
+        Some(r) if r.in_progress => {
+            return format!(
+                "Restoring snapshot: {}/{} bytes, {} entries loaded, {}ms elapsed",
+                r.bytes_restored, r.total_bytes, r.entries_loaded, r.elapsed_ms
+            );
+        }
+        Some(r) => format!("\nLast restore: {} bytes, {} entries in {}ms", r.bytes_restored, r.entries_loaded, r.elapsed_ms),
+        None => String::new(),
+    };
+    let gap = match &status.last_applied_gap {
+        Some(gap) => format!("\nLast applied gap skipped by snapshot install: [{}, {}]", gap.from, gap.to),
+        None => String::new(),
+    };
+    let min_applied = match status.min_applied_index {
+        Some(index) => format!("\nCluster min applied: {}", index),
+        None => String::new(),
+    };
+    let quota = status.disk_quota_bytes.map_or(String::new(), |quota| format!(", quota: {} bytes", quota));
+    let mut disk = format!("\nDisk usage: log {} bytes, snapshots {} bytes{}", status.log_bytes, status.snapshot_dir_bytes, quota);
+    if let Some(reason) = &status.snapshot_deferred {
+        disk.push_str(&format!("\nNext snapshot deferred: {} (use `snapshot trigger` to force)", reason));
+    }
+    if status.last_included_index == 0 {
+        return format!(
+            "Snapshot: none\nLog range: [{}, {}], last_applied: {}{}{}{}{}",
+            status.log_start_index, status.log_last_index, status.last_applied, min_applied, disk, gap, restore
+        );
+    }
+    let duration = if status.duration_ms > 0 {
+        format!("{}ms", status.duration_ms)
+    } else {
+        "unknown (not taken since restart)".to_string()
+    };
+    format!(
+        "Snapshot: index={} term={} size={} bytes duration={}\nLog range: [{}, {}], last_applied: {}{}{}{}{}",
+        status.last_included_index, status.last_included_term, status.size_bytes, duration,
+        status.log_start_index, status.log_last_index, status.last_applied, min_applied, disk, gap, restore
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_leader_cache_negative_caching() {
+        // 这些端口上没有服务，连接会被立即拒绝
+        let cache = LeaderCache::new(vec!["[::1]:1".to_string(), "[::1]:2".to_string()]);
+        assert!(cache.get_leader().await.is_none());
+        assert!(cache.is_dead("[::1]:1"));
+        assert!(cache.is_dead("[::1]:2"));
+
+        // 缓存的 Leader 直接返回，不会发起查询
+        let leader = proto::ServerInfo { server_id: 1, server_addr: "[::1]:1".to_string(), zone: None };
+        cache.update(Some(leader.clone())).await;
+        assert_eq!(cache.get_leader().await, Some(leader));
+
+        cache.mark_alive("[::1]:1");
+        assert!(!cache.is_dead("[::1]:1"));
+    }
+}
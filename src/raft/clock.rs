@@ -0,0 +1,94 @@
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+// 时间抽象：计时器和租约判断都通过 Clock 获取时间，测试中可以替换为手动推进的 MockClock
+#[async_trait::async_trait]
+pub trait Clock: Send + Sync + Debug {
+    fn now(&self) -> Instant;
+    async fn sleep_until(&self, deadline: Instant);
+}
+
+// 使用系统时间的默认实现
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+#[async_trait::async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await;
+    }
+}
+
+// 测试用时钟，只有调用 advance 时时间才会前进，并唤醒所有到期的 sleep_until
+#[derive(Debug)]
+pub struct MockClock {
+    now_tx: watch::Sender<Instant>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        let (now_tx, _) = watch::channel(Instant::now());
+        MockClock { now_tx }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now_tx.send_modify(|now| *now += duration);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now_tx.borrow()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        let mut now_rx = self.now_tx.subscribe();
+        loop {
+            if *now_rx.borrow_and_update() >= deadline {
+                return;
+            }
+            if now_rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_mock_clock_sleep_until() {
+        let clock = Arc::new(MockClock::new());
+        let start = clock.now();
+        let deadline = start + Duration::from_millis(100);
+
+        let clock_clone = clock.clone();
+        let handle = tokio::spawn(async move {
+            clock_clone.sleep_until(deadline).await;
+        });
+
+        // 时间没有推进到 deadline 前不会返回
+        clock.advance(Duration::from_millis(50));
+        tokio::task::yield_now().await;
+        assert!(!handle.is_finished());
+
+        clock.advance(Duration::from_millis(50));
+        handle.await.unwrap();
+        assert_eq!(clock.now(), deadline);
+    }
+}
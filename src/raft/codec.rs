@@ -0,0 +1,174 @@
+use crate::raft::proto;
+use super::logging::*;
+
+// 大条目的分块编码：超过 max_entry_size 的提议数据被拆成多个连续的 DataChunk 条目，
+// 每个条目的数据前带一个固定长度的头部（块序号、总块数，均为大端 u32），
+// 应用时由 ChunkAssembler 按顺序重组，收齐最后一块后把完整数据交给状态机。
+// 一个提议的所有分块由 Leader 一次性追加，在日志中总是连续的；
+// 只有 Leader 变更时未提交的尾部被截断，才会出现不完整的分块序列，重组时直接丢弃
+
+pub const CHUNK_HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkHeader {
+    pub seq: u32,   // 块序号，从0开始
+    pub total: u32, // 总块数
+}
+
+// 严格解码条目类型：未知的类型值（例如更新版本写入的新类型）返回错误，
+// 不能像 unwrap_or(Data) 那样当成数据条目交给状态机
+pub fn entry_type(entry: &proto::LogEntry) -> Result<proto::EntryType, String> {
+    proto::EntryType::try_from(entry.entry_type)
+        .map_err(|_| format!("entry {} has unknown type {}", entry.index, entry.entry_type))
+}
+
+// 单个条目能容纳的数据字节数
+fn chunk_payload_size(max_entry_size: usize) -> usize {
+    max_entry_size.saturating_sub(CHUNK_HEADER_LEN).max(1)
+}
+
+// 长度为 payload_len 的数据拆分后的条目数
+pub fn chunk_count(payload_len: usize, max_entry_size: usize) -> usize {
+    payload_len.div_ceil(chunk_payload_size(max_entry_size)).max(1)
+}
+
+// 把数据拆成带头部的分块，每块（含头部）不超过 max_entry_size
+pub fn split(payload: &[u8], max_entry_size: usize) -> Vec<Vec<u8>> {
+    let total = chunk_count(payload.len(), max_entry_size) as u32;
+    let mut chunks: Vec<&[u8]> = payload.chunks(chunk_payload_size(max_entry_size)).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    chunks.into_iter().enumerate().map(|(seq, data)| {
+        let mut chunk = Vec::with_capacity(CHUNK_HEADER_LEN + data.len());
+        chunk.extend_from_slice(&(seq as u32).to_be_bytes());
+        chunk.extend_from_slice(&total.to_be_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }).collect()
+}
+
+pub fn decode_chunk(data: &[u8]) -> Result<(ChunkHeader, &[u8]), String> {
+    if data.len() < CHUNK_HEADER_LEN {
+        return Err(format!("chunk of {} bytes is shorter than its header", data.len()));
+    }
+    let seq = u32::from_be_bytes(data[0..4].try_into().unwrap());
+    let total = u32::from_be_bytes(data[4..8].try_into().unwrap());
+    if total == 0 || seq >= total {
+        return Err(format!("invalid chunk header: seq {} of {}", seq, total));
+    }
+    Ok((ChunkHeader { seq, total }, &data[CHUNK_HEADER_LEN..]))
+}
+
+// 按日志顺序接收分块条目并重组
+#[derive(Debug, Default)]
+pub struct ChunkAssembler {
+    buf: Vec<u8>,
+    next: Option<(u64, ChunkHeader)>, // 正在重组时：(第一块的日志索引, 期望的下一块)
+}
+
+impl ChunkAssembler {
+    // 接收索引为 index 的分块，收齐最后一块时返回完整的数据
+    pub fn push(&mut self, index: u64, data: &[u8]) -> Option<Vec<u8>> {
+        let (header, payload) = match decode_chunk(data) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                error!("Dropping malformed chunk at index {}: {}", index, e);
+                self.discard(index);
+                return None;
+            }
+        };
+        match self.next {
+            Some((_, expected)) if expected == header => {}
+            Some(_) if header.seq == 0 => self.discard(index),
+            None if header.seq == 0 => {}
+            _ => {
+                warn!("Dropping out-of-sequence chunk {}/{} at index {}.", header.seq, header.total, index);
+                self.discard(index);
+                return None;
+            }
+        }
+        if header.seq == 0 {
+            self.next = Some((index, header));
+        }
+        self.buf.extend_from_slice(payload);
+        if header.seq + 1 == header.total {
+            self.next = None;
+            return Some(std::mem::take(&mut self.buf));
+        }
+        if let Some((_, expected)) = self.next.as_mut() {
+            expected.seq += 1;
+        }
+        None
+    }
+
+    // 正在重组的数据第一块的日志索引，没有未完成的重组时为 None
+    pub fn pending(&self) -> Option<u64> {
+        self.next.map(|(first_index, _)| first_index)
+    }
+
+    // 丢弃未完成的重组；index 是打断它的条目，只用于日志
+    pub fn discard(&mut self, index: u64) {
+        if let Some((first_index, expected)) = self.next.take() {
+            warn!("Discarding incomplete chunked payload starting at index {} (got {}/{} chunks) at index {}.",
+                first_index, expected.seq, expected.total, index);
+        }
+        self.buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let payload: Vec<u8> = (0..100u8).collect();
+        let chunks = split(&payload, 40);
+        assert_eq!(chunks.len(), chunk_count(payload.len(), 40));
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|c| c.len() <= 40));
+
+        let mut assembler = ChunkAssembler::default();
+        for (i, chunk) in chunks[..3].iter().enumerate() {
+            assert_eq!(assembler.push(10 + i as u64, chunk), None);
+        }
+        assert_eq!(assembler.pending(), Some(10));
+        assert_eq!(assembler.push(13, &chunks[3]), Some(payload));
+        assert_eq!(assembler.pending(), None);
+
+        // 空数据也占一个分块
+        let chunks = split(&[], 40);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(assembler.push(14, &chunks[0]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_incomplete_sequence_is_discarded() {
+        let first = split(b"aaaaaaaaaaaaaaaaaaaa", 12);
+        let second = split(b"bbbbbbbb", 12);
+        let mut assembler = ChunkAssembler::default();
+
+        // 第一个序列只收到一部分就被新的序列打断
+        assert_eq!(assembler.push(1, &first[0]), None);
+        assert_eq!(assembler.push(2, &second[0]), None);
+        assert_eq!(assembler.push(3, &second[1]), Some(b"bbbbbbbb".to_vec()));
+
+        // 不从第0块开始的序列直接丢弃
+        assert_eq!(assembler.push(4, &first[1]), None);
+        assert_eq!(assembler.pending(), None);
+
+        assert_eq!(assembler.push(5, &first[0]), None);
+        assembler.discard(6);
+        assert_eq!(assembler.pending(), None);
+        assert!(decode_chunk(b"short").is_err());
+    }
+
+    #[test]
+    fn test_unknown_entry_type_is_an_error() {
+        let mut entry = proto::LogEntry { term: 1, index: 7, entry_type: proto::EntryType::DataChunk as i32, data: Vec::new(), timestamp_ms: None };
+        assert_eq!(entry_type(&entry), Ok(proto::EntryType::DataChunk));
+        entry.entry_type = 42;
+        assert_eq!(entry_type(&entry), Err("entry 7 has unknown type 42".to_string()));
+    }
+}
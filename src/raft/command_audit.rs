@@ -0,0 +1,176 @@
+use crate::raft::proto;
+use super::logging::*;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 管理操作（SetConfiguration、Leader 转移、TriggerSnapshot、Decommission）的审计日志，只记录本节点收到的调用。
+// 每条记录一行 JSON，当前文件超过 max_file_bytes 后轮转为 .1、.2……，最多保留 max_rotated 个旧文件；
+// 记录带有单调递增的序号，GetCommandAudit 按序号分页读取
+pub struct CommandAuditLog {
+    filepath: String,
+    max_file_bytes: u64,
+    max_rotated: usize,
+    next_seq: u64,
+    file: Option<File>,
+    file_bytes: u64,
+}
+
+impl CommandAuditLog {
+    pub fn new(metadata_dir: &str, max_file_bytes: u64, max_rotated: usize) -> Self {
+        let filepath = Self::gen_filepath(metadata_dir);
+        let mut audit = CommandAuditLog { filepath, max_file_bytes, max_rotated, next_seq: 1, file: None, file_bytes: 0 };
+        // 重启后接着已有记录的序号继续
+        audit.next_seq = audit.reader().read_all().last().map_or(1, |r| r.seq + 1);
+        audit.open();
+        audit
+    }
+
+    pub fn gen_filepath(metadata_dir: &str) -> String {
+        format!("{}/raft.commands", metadata_dir)
+    }
+
+    pub fn filepath(&self) -> &str {
+        &self.filepath
+    }
+
+    // 审计日志打不开时不影响节点运行，只是不再记录
+    fn open(&mut self) {
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.filepath)
+            .map_err(|e| warn!("Failed to open command audit log {}: {}. Command records will be dropped.", self.filepath, e))
+            .ok();
+        self.file_bytes = self.file.as_ref().and_then(|f| f.metadata().ok()).map_or(0, |m| m.len());
+    }
+
+    fn rotated_path(&self, n: usize) -> String {
+        rotated_path(&self.filepath, n)
+    }
+
+    // 读取保留的记录要解析所有文件，调用方拿到 reader 后释放共识模块的锁再读
+    pub fn reader(&self) -> CommandAuditReader {
+        CommandAuditReader { filepath: self.filepath.clone(), max_rotated: self.max_rotated }
+    }
+
+    // 当前文件依次后移为 .1，最旧的文件被删除
+    fn rotate(&mut self) {
+        self.file = None;
+        if self.max_rotated == 0 {
+            let _ = std::fs::remove_file(&self.filepath);
+        } else {
+            let _ = std::fs::remove_file(self.rotated_path(self.max_rotated));
+            for n in (1..self.max_rotated).rev() {
+                let _ = std::fs::rename(self.rotated_path(n), self.rotated_path(n + 1));
+            }
+            if let Err(e) = std::fs::rename(&self.filepath, self.rotated_path(1)) {
+                warn!("Failed to rotate command audit log {}: {}", self.filepath, e);
+            }
+        }
+        self.open();
+    }
+
+    pub fn record(&mut self, caller: &str, command: proto::ManagementCommand, parameters: String, success: bool, error: Option<String>) {
+        let record = proto::CommandAuditRecord {
+            seq: self.next_seq,
+            timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+            caller: caller.to_string(),
+            command: command as i32,
+            parameters,
+            success,
+            error,
+        };
+        self.next_seq += 1;
+        let Some(file) = self.file.as_mut() else { return };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize command audit record {:?}: {}", record, e);
+                return;
+            }
+        };
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            error!("Failed to write command audit record to {}: {}", self.filepath, e);
+            return;
+        }
+        self.file_bytes += line.len() as u64 + 1;
+        if self.file_bytes >= self.max_file_bytes {
+            self.rotate();
+        }
+    }
+}
+
+fn rotated_path(filepath: &str, n: usize) -> String {
+    format!("{}.{}", filepath, n)
+}
+
+// 审计日志文件的只读视图，不持有文件句柄，读取期间的轮转最多让这一页漏掉或重复几条记录
+pub struct CommandAuditReader {
+    filepath: String,
+    max_rotated: usize,
+}
+
+impl CommandAuditReader {
+    // 按序号从旧到新读取所有保留的记录，跳过无法解析的行（例如崩溃时写了一半的最后一行）
+    fn read_all(&self) -> Vec<proto::CommandAuditRecord> {
+        let paths = (1..=self.max_rotated).rev().map(|n| rotated_path(&self.filepath, n)).chain(std::iter::once(self.filepath.clone()));
+        let mut records = Vec::new();
+        for path in paths {
+            let Ok(file) = File::open(&path) else { continue };
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                match serde_json::from_str(&line) {
+                    Ok(record) => records.push(record),
+                    Err(e) => warn!("Skipping malformed command audit record in {}: {}", path, e),
+                }
+            }
+        }
+        records
+    }
+
+    // 返回序号不小于 start_seq 的最多 max_records 条记录；还有更多记录时 next_seq 是下一页的起点
+    pub fn page(&self, start_seq: u64, max_records: usize) -> proto::GetCommandAuditResponse {
+        let mut records: Vec<_> = self.read_all().into_iter().filter(|r| r.seq >= start_seq).collect();
+        let next_seq = (records.len() > max_records).then(|| records[max_records].seq);
+        records.truncate(max_records);
+        proto::GetCommandAuditResponse { records, next_seq }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_command_audit_rotates_and_pages() {
+        let dir = tempdir().unwrap();
+        let dir_str = dir.path().to_str().unwrap();
+
+        // 每个文件只放得下一两条记录，8 条记录会轮转多次，只有最近的文件被保留
+        let mut audit = CommandAuditLog::new(dir_str, 200, 2);
+        for i in 0..8 {
+            audit.record("127.0.0.1:5000", proto::ManagementCommand::TriggerSnapshot, format!("attempt {}", i), i % 2 == 0, None);
+        }
+        assert!(std::path::Path::new(&format!("{}.2", audit.filepath())).exists());
+        assert!(!std::path::Path::new(&format!("{}.3", audit.filepath())).exists());
+        let kept = audit.reader().page(0, usize::MAX).records;
+        assert!(!kept.is_empty() && kept.len() < 8);
+        assert_eq!(kept.last().unwrap().seq, 8);
+        assert!(kept.windows(2).all(|w| w[1].seq == w[0].seq + 1));
+
+        // 分页读取，重启后序号继续递增
+        let first = kept[0].seq;
+        let page = audit.reader().page(first, 1);
+        assert_eq!(page.records.len(), 1);
+        assert_eq!(page.next_seq, Some(first + 1));
+        drop(audit);
+        let mut audit = CommandAuditLog::new(dir_str, 200, 2);
+        audit.record("local", proto::ManagementCommand::Decommission, "server 3".to_string(), false, Some("not leader".to_string()));
+        let page = audit.reader().page(9, 10);
+        assert_eq!(page.next_seq, None);
+        assert_eq!(page.records.len(), 1);
+        assert_eq!(page.records[0].command(), proto::ManagementCommand::Decommission);
+        assert_eq!(page.records[0].error.as_deref(), Some("not leader"));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::raft::config;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, Read, Write};
+
+// 存储层的透明压缩：日志文件和快照数据文件可以用 zstd 压缩后落盘。
+// 压缩的文件以 5 字节的头开始：4 字节魔数 + 1 字节压缩算法，之后是压缩后的数据；
+// 没有这个头的文件按原样读取，所以读取总是按文件头识别，与当前的压缩设置无关，
+// 打开或关闭压缩都不需要迁移已有的数据目录，新旧两种文件也可以在集群中混用（快照按原始字节传输）
+
+const MAGIC: [u8; 4] = *b"RFTZ";
+const CODEC_ZSTD: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+// 写入压缩头和 data 压缩后的内容
+pub fn write_compressed<W: Write>(mut writer: W, data: &[u8], level: i32) -> std::io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&[CODEC_ZSTD])?;
+    zstd::stream::copy_encode(data, &mut writer, level)?;
+    writer.flush()
+}
+
+// 读取开头可能存在的压缩头：压缩的内容返回解压的 reader，否则原样返回（包括已经读出的头部字节）
+pub fn reader<'a, R: Read + 'a>(mut inner: R) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    (&mut inner).take(HEADER_LEN as u64).read_to_end(&mut header)?;
+    if header.len() < HEADER_LEN || header[..MAGIC.len()] != MAGIC {
+        return Ok(Box::new(Cursor::new(header).chain(inner)));
+    }
+    match header[MAGIC.len()] {
+        CODEC_ZSTD => Ok(Box::new(zstd::stream::Decoder::new(inner)?)),
+        codec => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("unknown compression codec {}", codec))),
+    }
+}
+
+pub fn is_compressed(filepath: &str) -> std::io::Result<bool> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    File::open(filepath)?.take(HEADER_LEN as u64).read_to_end(&mut header)?;
+    Ok(header.len() == HEADER_LEN && header[..MAGIC.len()] == MAGIC)
+}
+
+// 原地压缩文件：先写到 .tmp 文件再替换，中途失败时原文件不受影响；已经压缩过的文件不做处理
+pub fn compress_file(filepath: &str, level: i32) -> std::io::Result<()> {
+    if is_compressed(filepath)? {
+        return Ok(());
+    }
+    let tmp_filepath = format!("{}.tmp", filepath);
+    let result = (|| {
+        let mut writer = BufWriter::new(File::create(&tmp_filepath)?);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[CODEC_ZSTD])?;
+        zstd::stream::copy_encode(BufReader::new(File::open(filepath)?), &mut writer, level)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp_filepath);
+        return Err(e);
+    }
+    std::fs::rename(&tmp_filepath, filepath)
+}
+
+// 状态机只认识原始的快照格式：压缩的快照解压到同目录下的 .plain.tmp 文件并返回它的路径，
+// 调用方恢复完成后删除；未压缩的快照返回 None，直接使用原文件
+pub fn unpack_for_restore(snapshot_filepath: &str) -> std::io::Result<Option<String>> {
+    if !is_compressed(snapshot_filepath)? {
+        return Ok(None);
+    }
+    let plain_filepath = format!("{}.plain.tmp", snapshot_filepath);
+    let result = (|| {
+        let mut decoded = reader(BufReader::new(File::open(snapshot_filepath)?))?;
+        let mut writer = BufWriter::new(File::create(&plain_filepath)?);
+        std::io::copy(&mut decoded, &mut writer)?;
+        writer.flush()
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&plain_filepath);
+        return Err(e);
+    }
+    Ok(Some(plain_filepath))
+}
+
+// 按压缩设置返回压缩级别，不压缩时为 None
+pub fn zstd_level(compression: config::StorageCompression) -> Option<i32> {
+    match compression {
+        config::StorageCompression::None => None,
+        config::StorageCompression::Zstd { level } => Some(level),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft::{log, proto};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compressed_files_are_detected_by_header() {
+        let dir = tempdir().unwrap();
+        let data = "state machine snapshot ".repeat(1000).into_bytes();
+
+        // 未压缩以及短于压缩头的内容原样读出
+        for plain in [&data[..], b"RF"] {
+            let mut out = Vec::new();
+            reader(plain).unwrap().read_to_end(&mut out).unwrap();
+            assert_eq!(out, plain);
+        }
+
+        let filepath = dir.path().join("raft-10-2.snapshot").to_str().unwrap().to_string();
+        std::fs::write(&filepath, &data).unwrap();
+        assert_eq!(unpack_for_restore(&filepath).unwrap(), None);
+        compress_file(&filepath, 3).unwrap();
+        assert!(is_compressed(&filepath).unwrap());
+        assert!(std::fs::metadata(&filepath).unwrap().len() < data.len() as u64 / 10);
+        // 重复压缩不做处理
+        compress_file(&filepath, 3).unwrap();
+        let plain_filepath = unpack_for_restore(&filepath).unwrap().unwrap();
+        assert_eq!(std::fs::read(&plain_filepath).unwrap(), data);
+
+        // 魔数之后是不认识的算法时报错，而不是当作原始数据
+        assert!(reader(&b"RFTZ\x09rest"[..]).is_err());
+    }
+
+    #[test]
+    fn test_log_reloads_across_compression_settings() {
+        let dir = tempdir().unwrap();
+        let metadata_dir = dir.path().to_str().unwrap().to_string();
+        let mut log = log::Log::new(1, metadata_dir.clone());
+        log.set_compression(config::StorageCompression::Zstd { level: 3 });
+        log.append_data(1, (0..100).map(|i| (proto::EntryType::Data, format!("value-{}", i).into_bytes())).collect());
+        log.dump();
+        assert!(is_compressed(&log.filepath()).unwrap());
+        let file = File::open(log.filepath()).unwrap();
+        assert_eq!(log::Log::verify_reader(file).unwrap(), 100);
+
+        // 关闭压缩后仍能读取压缩的日志，下次写入时恢复为未压缩的格式
+        let mut reloaded = log::Log::new(1, metadata_dir.clone());
+        reloaded.reload().unwrap();
+        assert_eq!(reloaded.last_index(0), 100);
+        assert_eq!(reloaded.read_entry_from_disk(42).unwrap().data, b"value-41");
+        reloaded.dump();
+        assert!(!is_compressed(&reloaded.filepath()).unwrap());
+    }
+}
@@ -1,29 +1,395 @@
 use serde::{Deserialize, Serialize};
 use tonic::server;
-use core::panic;
+use std::sync::Arc;
 use std::time::Duration;
-use crate::raft::{peer, proto};
+use crate::raft::{archive, clock, group, peer, proto, rpc, sink};
 use std::io::Error;
 
 // 选举超时间隔范围
 pub const ELECTION_TIMEOUT_MAX_MILLIS: u64 = 15000;
 pub const ELECTION_TIMEOUT_MIN_MILLIS: u64 = 10000;
 pub const ELECTION_TIMEOUT_MIN: Duration = Duration::from_millis(ELECTION_TIMEOUT_MIN_MILLIS);
+// 重启后在持久化的任期内暂不投票的时间：一个完整的选举超时，
+// 重启前的投票可能还没有落盘，这段时间内该任期的选举要么已经结束，要么候选人已经进入下一个任期
+pub const RESTART_VOTE_HOLD: Duration = Duration::from_millis(ELECTION_TIMEOUT_MAX_MILLIS);
+
+// Leader超过该时间联系不上多数派，或Follower超过该时间没有收到Leader消息，则认为集群不可用
+pub const CLUSTER_UNAVAILABLE_TIMEOUT: Duration = Duration::from_millis(2 * ELECTION_TIMEOUT_MAX_MILLIS);
 
 // 心跳间隔时间
 pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(3000);
 
-// 快照间隔时间
-pub const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(30000);
+// 快照定时器的间隔。快照主要在应用新条目之后检查，定时器是兜底，同时刷新磁盘占用指标；
+// 上一次触发之后没有应用新条目时定时器不获取锁
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+// 应用路径上两次快照检查的最小间隔，避免写入高峰时频繁生成快照
+pub const SNAPSHOT_CHECK_MIN_INTERVAL: Duration = Duration::from_secs(30);
 
 // 快照阈值（日志条目长度）
 pub const SNAPSHOT_LOG_LENGTH_THRESHOLD: usize = 5;
 
 pub const NONE_SERVER_ID: u64 = 0;
+// 单个共识组部署时使用的组ID
+pub const DEFAULT_GROUP_ID: u64 = 0;
 pub const NONE_DATA: &'static str = "None";
 
-// 发送snapshot时分块大小
-pub const SNAPSHOT_TRUNK_SIZE: usize = 30;
+// 发送snapshot时分块大小，需要小于 gRPC 默认的 4 MiB 消息上限
+pub const SNAPSHOT_TRUNK_SIZE: usize = 1024 * 1024;
+
+// 发送快照时预先读好的分块数，读取磁盘和等待网络同时进行
+pub const SNAPSHOT_SEND_PIPELINE_DEPTH: usize = 8;
+
+// FetchSnapshot 单次返回的最大字节数
+pub const FETCH_SNAPSHOT_CHUNK_SIZE: usize = 1024 * 1024;
+
+// 快照传输超过该时间仍未完成时，允许 Leader 重新发起传输
+pub const SNAPSHOT_TRANSFER_TIMEOUT: Duration = Duration::from_millis(60000);
+
+// 客户端探测单个节点的超时时间
+pub const CLIENT_PROBE_TIMEOUT: Duration = Duration::from_millis(2000);
+// 客户端把探测失败的节点视为不可用的时间（负缓存）
+pub const CLIENT_DEAD_NODE_TTL: Duration = Duration::from_millis(5000);
+// 强一致读取配置时，客户端跟随 Leader 重定向的最大次数
+pub const CLIENT_CONSISTENT_READ_RETRIES: usize = 3;
+// 订阅 Leader 变化的连接断开后，客户端换一个节点重新订阅之前的等待时间
+pub const CLIENT_WATCH_RETRY_INTERVAL: Duration = Duration::from_millis(1000);
+
+// Unix domain socket 地址前缀，ServerInfo.server_addr 以此开头时通过 UDS 通信
+pub const UDS_ADDR_PREFIX: &str = "unix:";
+
+// 连续连接失败该次数后熔断对端地址
+pub const BREAKER_FAILURE_THRESHOLD: u32 = 3;
+// 熔断后第一次半开探测前的等待时间，探测失败时翻倍
+pub const BREAKER_INITIAL_BACKOFF: Duration = Duration::from_millis(1000);
+pub const BREAKER_MAX_BACKOFF: Duration = Duration::from_millis(30000);
+
+// 慢节点检测：落后量的采样间隔
+pub const SLOW_PEER_SAMPLE_INTERVAL: Duration = Duration::from_millis(1000);
+// 落后量低于该值时不视为慢节点
+pub const SLOW_PEER_MIN_LAG: u64 = 64;
+// 落后量的增长速度超过该值（条目/秒）时记为一次增长采样
+pub const SLOW_PEER_LAG_GROWTH_RATE: f64 = 16.0;
+// 连续增长采样达到该次数后视为慢节点
+pub const SLOW_PEER_SAMPLES: u32 = 3;
+// 慢节点的批量发送间隔和单次最多发送的条目数
+pub const SLOW_PEER_BATCH_INTERVAL: Duration = Duration::from_millis(500);
+pub const SLOW_PEER_MAX_ENTRIES: usize = 32;
+
+// 每个 Peer 保留的最近 RPC 往返时间样本数，用于计算中位数
+pub const PEER_RTT_SAMPLES: usize = 16;
+// 每个 Peer 保留的最近 next_index 取值个数，用于在状态中排查卡住的 Follower
+pub const NEXT_INDEX_HISTORY_LEN: usize = 16;
+// 每个 Peer 保留的最近时钟偏差采样数，取其中往返时间最短的一次作为估计
+pub const CLOCK_SKEW_SAMPLES: usize = 16;
+// 时钟偏差采样的有效期，联系不上的 Peer 的旧采样过期后不再影响读租约
+pub const CLOCK_SKEW_SAMPLE_MAX_AGE: Duration = Duration::from_millis(3 * ELECTION_TIMEOUT_MAX_MILLIS);
+// 延迟探测时向每个 Peer 发送的 Ping 次数
+pub const LATENCY_PROBE_COUNT: usize = 3;
+// Leader 转移前等待目标节点追上日志的最长时间
+pub const LEADER_TRANSFER_TIMEOUT: Duration = Duration::from_millis(2000);
+// 等待目标节点追上日志时两次检查之间的间隔
+pub const LEADER_TRANSFER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// 下线节点时等待其日志追上 Leader 的最长时间
+pub const DECOMMISSION_CATCH_UP_TIMEOUT: Duration = Duration::from_millis(10000);
+
+// 单次 Propose 数据的最大字节数
+pub const MAX_PROPOSE_PAYLOAD_SIZE: usize = 4 * 1024 * 1024;
+// 单个日志条目数据的默认上限，RaftOptions.max_entry_size 可以修改；
+// 超过上限的 Propose 被拒绝，开启 chunk_large_entries 时拆分成多个条目
+pub const DEFAULT_MAX_ENTRY_SIZE: usize = 1024 * 1024;
+// Leader 上已追加但未提交的日志条目超过该数量时拒绝新的 Propose
+pub const MAX_UNCOMMITTED_ENTRIES: u64 = 1024;
+// Leader 上已追加但未提交的日志数据超过该字节数时拒绝新的 Propose
+pub const MAX_UNCOMMITTED_BYTES: u64 = 64 * 1024 * 1024;
+// wait_for_commit 的 Propose 等待条目应用的最长时间，超时后返回不带结果的响应
+pub const PROPOSE_WAIT_TIMEOUT: Duration = Duration::from_millis(5000);
+// 按客户端 grpc-timeout 计算截止时间时预留的余量，保证在 tonic 因超时取消请求之前返回 DeadlineExceeded
+pub const CLIENT_DEADLINE_MARGIN: Duration = Duration::from_millis(20);
+// 每次持有锁时最多应用的已提交条目数和最长时间，剩下的条目释放锁后继续分批应用
+pub const APPLY_BATCH_MAX_ENTRIES: usize = 64;
+pub const APPLY_BATCH_MAX_DURATION: Duration = Duration::from_millis(10);
+// 客户端收到 Backpressure 等可重试拒绝后的等待时间
+pub const CLIENT_PROPOSE_BACKOFF: Duration = Duration::from_millis(200);
+// 启动时清理遗留临时文件和孤立快照文件的最小文件年龄，更新的文件可能仍在被写入
+pub const STALE_ARTIFACT_MIN_AGE: Duration = Duration::from_secs(600);
+// 状态机提交的后续命令：链条的最大层数、应用一个条目时最多提交的条数、Leader 上等待 Propose 的队列容量
+pub const FOLLOW_UP_MAX_DEPTH: u32 = 8;
+pub const FOLLOW_UP_MAX_PER_ENTRY: usize = 16;
+pub const FOLLOW_UP_QUEUE_CAPACITY: usize = 1024;
+// 压测时单个请求放弃前的最大尝试次数
+pub const BENCH_REQUEST_ATTEMPTS: usize = 5;
+// 指定全局随机数种子的环境变量，RaftOptions.rng_seed 优先
+pub const RNG_SEED_ENV: &str = "RAFT_RNG_SEED";
+// 来自网络的任期最多比本地任期大多少、索引最多比本地最后一条日志大多少，超过时视为损坏或恶意的请求
+pub const SANITY_MAX_TERM_JUMP: u64 = 100_000_000;
+pub const SANITY_MAX_INDEX_JUMP: u64 = 1 << 32;
+// SetConfiguration 等待配置变更完成的最长时间，超时后返回当前进度
+pub const CONFIG_CHANGE_WAIT_TIMEOUT: Duration = Duration::from_millis(10000);
+// 当前的数据目录格式版本，启动时低于该版本的目录会被原地升级
+pub const STORAGE_VERSION: u32 = 1;
+// 节点之间 RPC 的协议版本，proto 发生不兼容的变化时提升
+pub const PROTOCOL_VERSION: u32 = 1;
+// 持久化保留的已提交配置变更记录数量
+pub const CONFIG_HISTORY_CAPACITY: usize = 64;
+// 管理操作审计日志单个文件的大小上限，超过后轮转；保留的旧文件数量；GetCommandAudit 每页默认和最多返回的记录数
+pub const COMMAND_AUDIT_MAX_FILE_BYTES: u64 = 1024 * 1024;
+pub const COMMAND_AUDIT_MAX_ROTATED_FILES: usize = 4;
+pub const COMMAND_AUDIT_PAGE_SIZE: usize = 100;
+pub const COMMAND_AUDIT_MAX_PAGE_SIZE: usize = 1000;
+// 崩溃转储中保留的最近日志操作数量；日志操作时刷新复制进度的最短间隔
+pub const CRASH_DUMP_LOG_OPS: usize = 100;
+pub const CRASH_DUMP_PEERS_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+// 带 read_token 的读请求等待本地状态机追上的最长时间
+pub const READ_TOKEN_WAIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// 已提交条目订阅中每条消息最多携带的条目数和数据字节数。单个条目最大 1 MiB，字节上限要给 tonic 默认 4 MiB 的解码上限留出余量；
+// 第一个条目总会被携带，即使它本身超过字节上限
+pub const SUBSCRIBE_COMMITTED_BATCH: usize = 256;
+pub const SUBSCRIBE_COMMITTED_BATCH_BYTES: usize = 2 * 1024 * 1024;
+// 事件总线的缓冲区大小，订阅者落后超过该数量时会丢失最旧的事件
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+// 提交/应用延迟直方图各桶的上界（微秒），超过最后一个上界的计入溢出桶
+pub const LATENCY_BUCKET_BOUNDS_US: [u64; 14] = [
+    500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000,
+    100_000, 250_000, 500_000, 1_000_000, 2_500_000, 5_000_000, 10_000_000,
+];
+// 后台校验读取磁盘的限速（字节/秒）
+pub const VERIFY_BYTES_PER_SEC: u64 = 8 * 1024 * 1024;
+// 状态页展示的最近审计记录条数
+pub const STATUS_PAGE_RECENT_EVENTS: usize = 20;
+// 状态页读取 HTTP 请求头的最大字节数和超时时间
+pub const STATUS_PAGE_MAX_REQUEST_BYTES: usize = 8 * 1024;
+pub const STATUS_PAGE_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// 就绪检查允许的应用落后量：last_applied 落后 commit_index 超过这么多条目时节点报告未就绪
+pub const DEFAULT_READY_MAX_APPLY_LAG: u64 = 1000;
+
+// 减载时计算提交延迟指数移动平均的权重，越大越快反映最近的延迟
+pub const LOAD_SHED_LATENCY_WEIGHT: f64 = 0.2;
+// 受 ClientRateLimit 限制的 gRPC 方法
+pub const RATE_LIMITED_METHODS: &[&str] = &["/raft.ManagementRpc/Propose", "/raft.ManagementRpc/SetConfiguration"];
+// 限流器最多跟踪的客户端地址数，超过时丢弃已经回满（空闲）的令牌桶
+pub const RATE_LIMIT_MAX_CLIENTS: usize = 4096;
+
+// CommitSink 投递失败后重试之前的等待时间
+pub const COMMIT_SINK_RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+// CommitSink 投递队列的容量。队列满时暂停应用（反压），等待 COMMIT_SINK_BACKPRESSURE_DELAY 后再继续，
+// 下游长时间不可用时已提交的条目留在日志中，而不是堆积在内存里
+pub const COMMIT_SINK_QUEUE_CAPACITY: usize = 1024;
+pub const COMMIT_SINK_BACKPRESSURE_DELAY: Duration = Duration::from_millis(50);
+
+// client doctor：查询单个节点状态的超时，节点恢复快照时会一直占用共识模块的锁
+pub const DOCTOR_RPC_TIMEOUT: Duration = Duration::from_secs(3);
+// client doctor：已应用的位置落后 Leader 提交位置超过这么多条目时认为 Follower 卡住了
+pub const DOCTOR_STUCK_FOLLOWER_LAG: u64 = 1000;
+// client doctor：没有被快照覆盖的已应用条目超过这么多时提示检查快照
+pub const DOCTOR_UNCOMPACTED_ENTRIES: u64 = 100_000;
+
+// 节点启动时的运行时选项，通过 lib::start 传入
+#[derive(Debug, Clone, Default)]
+pub struct RaftOptions {
+    // 节点自身的监听地址，为 None 时使用 "[::1]:port"
+    // 以 "unix:" 开头时在对应路径上监听 Unix domain socket，例如 "unix:/tmp/raft-1.sock"
+    pub listen_addr: Option<String>,
+    // 管理服务(ManagementRpc)的独立监听地址，例如 "[::1]:19001"
+    // 为 None 时管理服务与共识服务共用同一个端口
+    pub management_addr: Option<String>,
+    // 为 true 时不注册 ManagementRpc 服务，只对外提供共识服务，管理功能通过进程内的 RaftNode 调用
+    // 编译时关闭 management-rpc feature 的效果与此相同
+    pub disable_management_rpc: bool,
+    // 计时器和租约判断使用的时钟，为 None 时使用系统时钟；测试中可以传入 MockClock
+    pub clock: Option<Arc<dyn clock::Clock>>,
+    // 后台校验日志文件和快照校验和的周期，为 None 时不启动校验任务
+    pub verify_interval: Option<Duration>,
+    // 所属共识组ID，同一进程内运行多个组时必须互不相同
+    pub group_id: u64,
+    // 同一进程内多个组共享的注册表，设置后心跳由注册表按 Peer 所在的节点合并发送，
+    // 收到的合并心跳也通过注册表分发给对应的组
+    pub group_registry: Option<Arc<group::GroupRegistry>>,
+    // 全局随机数种子，为 None 时使用环境变量 RAFT_RNG_SEED 或随机种子；实际种子在启动时打印
+    pub rng_seed: Option<u64>,
+    // 嵌入方附加到 RPC 服务上的拦截器和 tower 中间件（认证、限流、请求日志等）
+    pub server_hooks: rpc::ServerHooks,
+    // 用已有数据初始化全新节点时导入的快照，节点已经有数据时忽略
+    pub initial_snapshot: Option<InitialSnapshot>,
+    // 只读 HTTP 状态页的监听地址，例如 "[::1]:18001"，用浏览器或 curl 查看节点状态；为 None 时不启动
+    pub status_addr: Option<String>,
+    // 快照和被压缩日志的归档目标，设置后每次生成快照都会在后台上传，见 archive 模块
+    pub archive_store: Option<Arc<dyn archive::BlobStore>>,
+    // 单个日志条目数据的上限，为 None 时使用 DEFAULT_MAX_ENTRY_SIZE
+    pub max_entry_size: Option<usize>,
+    // 为 true 时超过 max_entry_size 的 Propose 被拆分成多个条目复制，应用时重组；否则直接拒绝
+    pub chunk_large_entries: bool,
+    // 日志文件和快照目录合计的磁盘配额（字节），为 None 时不限制；
+    // 超过配额时立即生成快照并删除旧快照，压缩后仍然超过时拒绝新的 Propose
+    pub disk_quota_bytes: Option<u64>,
+    // gRPC 连接的 keepalive 和 HTTP/2 参数，同时用于 RPC 服务和发往其他节点的连接
+    pub transport: TransportOptions,
+    // 定时快照的调度策略：只在允许的时间窗口内、写入速率不高时生成快照
+    pub snapshot_policy: SnapshotPolicy,
+    // 全新节点启动时从该地址的节点拉取快照作为初始数据，通常指定一个已经追上的 Follower，
+    // 把添加节点的带宽开销从 Leader 分摊出去；节点已有数据或设置了 initial_snapshot 时忽略
+    pub bootstrap_from: Option<String>,
+    // 面向客户端的 RPC（Propose、SetConfiguration）按客户端地址限流，为 None 时不限制
+    pub client_rate_limit: Option<ClientRateLimit>,
+    // 已应用结果的外部投递（CDC），按索引顺序、至少一次地投递每个数据条目，见 sink 模块
+    pub commit_sink: Option<Arc<dyn sink::CommitSink>>,
+    // 就绪检查允许的应用落后量，为 None 时使用 DEFAULT_READY_MAX_APPLY_LAG，见 Consensus::not_ready_reason
+    pub ready_max_apply_lag: Option<u64>,
+    // 日志文件和快照数据文件的压缩方式，默认不压缩，见 compress 模块
+    pub storage_compression: StorageCompression,
+    // Follower 应用已提交条目的限速，为 None 时不限制；运行中可以通过 RaftNode::set_apply_rate_limit 调整
+    pub apply_rate_limit: Option<ApplyRateLimit>,
+    // 提交延迟持续超过 SLO 时 Leader 按比例拒绝新的客户端提议，为 None 时不减载
+    pub load_shedding: Option<LoadShedPolicy>,
+    // 为 true 时安装 panic hook：共识任务（定时器、应用、RPC 处理等）中的 panic 会先把节点的状态摘要（任期、角色、
+    // 各索引、Peer 复制进度、最近的日志操作）写入元数据目录下的 raft.crash，然后中止进程；嵌入方自己的 panic 不受影响，见 crash 模块
+    pub crash_dump: bool,
+    // Leader 与最慢投票节点之间的日志差距告警，为 None 时不检查
+    pub divergence_alarm: Option<DivergenceAlarm>,
+    // 时钟偏差上限。Leader 在联系得上多数派期间直接读取本地状态（读租约），这依赖各节点的时钟走速大致相同；
+    // 任何节点的估计偏差上界超过它时租约失效，线性一致读被拒绝（允许降级时以降级方式读取），偏差恢复后自动恢复。
+    // 为 None 时不检查
+    pub max_clock_skew: Option<Duration>,
+}
+
+// 落盘时的压缩方式。读取时按文件头识别，与这里的设置无关，切换设置不需要迁移数据目录；
+// 压缩以 CPU 换磁盘空间，状态机数据冗长（例如 JSON）时数据目录通常能缩小数倍
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageCompression {
+    #[default]
+    None,
+    Zstd { level: i32 }, // zstd 的压缩级别，1 最快，3 是 zstd 的默认值，最高 22
+}
+
+// 每个客户端地址一个令牌桶：桶里最多 burst 个令牌，每秒补充 requests_per_sec 个，每个请求消耗一个，
+// 桶空时以 ResourceExhausted 拒绝，不进入共识模块，避免个别客户端（例如压测工具）挤占节点间 RPC 的处理
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientRateLimit {
+    pub requests_per_sec: f64,
+    pub burst: u32,
+}
+
+// Leader 减载策略：最近数据条目的提交延迟（指数移动平均）持续 sustain 超过 commit_latency_slo 后，
+// 以 Busy 拒绝 shed_percent% 的新客户端提议，保护已接受请求的尾延迟。
+// 配置变更、NOOP 和状态机提交的后续命令不会被拒绝
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadShedPolicy {
+    pub commit_latency_slo: Duration,
+    pub sustain: Duration,
+    pub shed_percent: u32, // 0-100
+}
+
+// 日志分歧告警：Leader 最后一条日志与最慢投票节点的 match_index 之差连续 heartbeats 个心跳周期超过 max_gap 时告警。
+// 差距过大时 Leader 一旦故障，新 Leader 只能从落后的节点中产生或者要等它们追上，可用性受到威胁；
+// block_membership_changes 为 true 时告警期间拒绝成员变更，避免在这种状态下进一步改变多数派的构成
+#[derive(Debug, Clone, PartialEq)]
+pub struct DivergenceAlarm {
+    pub max_gap: u64,
+    pub heartbeats: u32,
+    pub block_membership_changes: bool,
+}
+
+// Follower 应用条目的限速：分区恢复后一次追上成千上万条目时，集中应用会产生 I/O 突发，挤占同机业务的磁盘。
+// 条目数和数据字节数各一个令牌桶，速率为 None（或不大于 0）的维度不限制；令牌不足时暂停应用，到时间后由后台任务继续。
+// Leader 应用的条目关系到客户端的响应延迟，不受限制
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplyRateLimit {
+    pub entries_per_sec: Option<f64>,
+    pub bytes_per_sec: Option<f64>,
+    pub burst_entries: u64, // 空闲之后可以连续应用的条目数
+    pub burst_bytes: u64,   // 空闲之后可以连续应用的字节数，单个条目超过它时在桶满后放行
+}
+
+// 定时快照的调度策略。日志长度超过阈值后，还要满足时间窗口和写入速率的限制才生成快照，
+// 避免快照 I/O 与业务高峰重叠；TriggerSnapshot 和磁盘配额触发的压缩不受这些限制
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotPolicy {
+    pub log_length_threshold: usize,     // 已提交日志超过多少条时考虑生成快照
+    pub windows: Vec<SnapshotWindow>,    // 允许生成快照的时间段，为空时不限制
+    pub utc_offset_minutes: i32,         // 解释 windows 时使用的本地时区相对 UTC 的偏移，例如东八区为 480
+    pub max_entry_rate: Option<f64>,     // 最近一个快照周期内每秒追加的条目数超过该值时推迟
+    pub force_log_length: Option<usize>, // 已提交日志超过该长度时忽略窗口和速率限制，防止日志无限增长
+}
+
+// 一天中的时间段，单位为从本地零点起的分钟数，例如 02:00-04:00 为 [120, 240)；
+// start_minute 大于 end_minute 时表示跨过零点，例如 [1380, 60) 为 23:00-01:00
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotWindow {
+    pub start_minute: u32,
+    pub end_minute: u32,
+}
+
+impl SnapshotWindow {
+    pub fn contains(&self, minute_of_day: u32) -> bool {
+        if self.start_minute <= self.end_minute {
+            (self.start_minute..self.end_minute).contains(&minute_of_day)
+        } else {
+            minute_of_day >= self.start_minute || minute_of_day < self.end_minute
+        }
+    }
+}
+
+impl Default for SnapshotPolicy {
+    fn default() -> Self {
+        SnapshotPolicy {
+            log_length_threshold: SNAPSHOT_LOG_LENGTH_THRESHOLD,
+            windows: Vec::new(),
+            utc_offset_minutes: 0,
+            max_entry_rate: None,
+            force_log_length: None,
+        }
+    }
+}
+
+impl SnapshotPolicy {
+    // 判断现在能否生成定时快照：不需要时返回 Ok(false)，需要但被推迟时返回推迟的原因。
+    // unix_secs 是当前的 UNIX 时间，entry_rate 是最近每秒追加的条目数
+    pub fn check(&self, committed_len: usize, unix_secs: u64, entry_rate: f64) -> Result<bool, String> {
+        if committed_len <= self.log_length_threshold {
+            return Ok(false);
+        }
+        if self.force_log_length.is_some_and(|force| committed_len > force) {
+            return Ok(true);
+        }
+        if !self.windows.is_empty() {
+            let local_minutes = (unix_secs / 60) as i64 + self.utc_offset_minutes as i64;
+            let minute_of_day = local_minutes.rem_euclid(24 * 60) as u32;
+            if !self.windows.iter().any(|w| w.contains(minute_of_day)) {
+                return Err(format!("outside the snapshot windows (local time {:02}:{:02})", minute_of_day / 60, minute_of_day % 60));
+            }
+        }
+        if let Some(max_rate) = self.max_entry_rate {
+            if entry_rate > max_rate {
+                return Err(format!("entry rate {:.1}/s is above {:.1}/s", entry_rate, max_rate));
+            }
+        }
+        Ok(true)
+    }
+}
+
+// gRPC 连接参数，为 None 或 false 的项使用 tonic 的默认值。
+// 经过 NAT 或负载均衡器时，长时间空闲的连接会被静默断开，需要打开 keepalive；
+// 高延迟链路上打开 adaptive_window 可以让 HTTP/2 流控窗口随带宽时延积增长
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransportOptions {
+    pub keepalive_interval: Option<Duration>, // HTTP/2 PING 的发送间隔
+    pub keepalive_timeout: Option<Duration>,  // 等待 PING 响应的超时，超时后断开连接
+    pub keepalive_while_idle: bool,           // 客户端在没有进行中的请求时也发送 PING
+    pub tcp_keepalive: Option<Duration>,      // TCP 层的 keepalive 间隔，对 Unix domain socket 无效
+    pub adaptive_window: bool,                // 根据 BDP 估计自动调整 HTTP/2 流控窗口
+    pub concurrency_limit: Option<usize>,     // 每个连接上同时处理（服务端）或发出（客户端）的请求数上限
+    pub connect_timeout: Option<Duration>,    // 客户端建立连接的超时
+}
+
+// 把已有的数据集导入新集群：快照文件由状态机的 take_snapshot 格式生成，
+// 所有节点应使用同样的快照、索引和任期，日志从 last_included_index + 1 开始
+#[derive(Debug, Clone)]
+pub struct InitialSnapshot {
+    pub filepath: String,
+    pub last_included_index: u64,
+    pub last_included_term: u64,
+}
 
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct ConfigState {
@@ -38,6 +404,60 @@ impl ConfigState {
     }
 }
 
+// 配置校验和成员变更的错误
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    EmptyServers,                                                      // 节点列表为空
+    DuplicateServerId(u64),                                            // 同一个 ID 出现了多次
+    DuplicateServerAddr { addr: String, first_id: u64, second_id: u64 }, // 同一个地址对应了两个 ID
+    AlreadyJoint,                                                      // 已经处于 C(old,new)，不能开始新的变更
+    NotJoint,                                                          // 不处于 C(old,new)，没有可以完成的变更
+    TooManyChanges(usize),                                             // 单节点变更一次只能增加、移除或修改一个节点
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::EmptyServers => write!(f, "server list is empty"),
+            ConfigError::DuplicateServerId(id) => write!(f, "server id {} appears more than once", id),
+            ConfigError::DuplicateServerAddr { addr, first_id, second_id } => {
+                write!(f, "address {} is used by both server {} and server {}", addr, first_id, second_id)
+            }
+            ConfigError::AlreadyJoint => write!(f, "configuration is already C(old,new)"),
+            ConfigError::NotJoint => write!(f, "configuration is not C(old,new)"),
+            ConfigError::TooManyChanges(n) => write!(f, "single-server change touches {} servers, at most 1 is allowed", n),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// 成员变更算法，记录在每个配置条目中，集群始终按最新的配置条目决定下一次变更怎么做
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum MembershipAlgorithm {
+    #[default]
+    JointConsensus, // 先提交 C(old,new) 再提交 C(new)，一次可以变更任意多个节点
+    SingleServer,   // 每次只增加、移除或修改一个节点，直接追加新的稳定配置，适合小集群
+}
+
+impl From<proto::MembershipAlgorithm> for MembershipAlgorithm {
+    fn from(algorithm: proto::MembershipAlgorithm) -> Self {
+        match algorithm {
+            proto::MembershipAlgorithm::JointConsensus => MembershipAlgorithm::JointConsensus,
+            proto::MembershipAlgorithm::SingleServer => MembershipAlgorithm::SingleServer,
+        }
+    }
+}
+
+impl From<MembershipAlgorithm> for proto::MembershipAlgorithm {
+    fn from(algorithm: MembershipAlgorithm) -> Self {
+        match algorithm {
+            MembershipAlgorithm::JointConsensus => proto::MembershipAlgorithm::JointConsensus,
+            MembershipAlgorithm::SingleServer => proto::MembershipAlgorithm::SingleServer,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 pub struct Config {
     // C(old, new)联合共识期间，属于C_old配置的节点列表
@@ -45,6 +465,9 @@ pub struct Config {
     pub old_servers: Vec<proto::ServerInfo>,
     // 属于C_new配置的节点列表，在稳定配置中，这是活跃节点的列表，在C(old, new)联合共识期间，这是目标新配置的节点列表
     pub new_servers: Vec<proto::ServerInfo>,
+    // 之后的成员变更使用的算法，引入该字段之前的条目按联合共识处理
+    #[serde(default)]
+    pub algorithm: MembershipAlgorithm,
 }
 
 impl Config {
@@ -52,6 +475,7 @@ impl Config {
         Config { 
             old_servers: Vec::new(), 
             new_servers: Vec::new(), 
+            algorithm: MembershipAlgorithm::default(),
         }
     }
     // 在稳定配置中，old为空，只有new
@@ -59,12 +483,54 @@ impl Config {
         Config {
             old_servers: Vec::new(),
             new_servers: initial_servers,
+            algorithm: MembershipAlgorithm::default(),
         }
     }
+    // 带校验的 new_stable，节点列表为空或有重复的 ID、地址时返回错误
+    pub fn try_new_stable(initial_servers: Vec<proto::ServerInfo>) -> Result<Config, ConfigError> {
+        Self::validate_servers(&initial_servers)?;
+        Ok(Self::new_stable(initial_servers))
+    }
+
+    // 检查节点列表：不能为空，ID 不能重复，同一个地址不能对应两个 ID，否则 PeerManager 会把它们当成不同的节点
+    pub fn validate_servers(servers: &[proto::ServerInfo]) -> Result<(), ConfigError> {
+        if servers.is_empty() {
+            return Err(ConfigError::EmptyServers);
+        }
+        let mut ids = std::collections::HashSet::new();
+        for server in servers {
+            if !ids.insert(server.server_id) {
+                return Err(ConfigError::DuplicateServerId(server.server_id));
+            }
+        }
+        Self::check_addr_conflicts(servers.iter())
+    }
+
+    fn check_addr_conflicts<'a>(servers: impl Iterator<Item = &'a proto::ServerInfo>) -> Result<(), ConfigError> {
+        let mut addrs: std::collections::HashMap<&str, u64> = std::collections::HashMap::new();
+        for server in servers {
+            match addrs.insert(&server.server_addr, server.server_id) {
+                Some(first_id) if first_id != server.server_id => {
+                    return Err(ConfigError::DuplicateServerAddr {
+                        addr: server.server_addr.clone(),
+                        first_id,
+                        second_id: server.server_id,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     // 从字节切片反序列化
     pub fn from_data(data: &[u8]) -> Config {
         serde_json::from_slice(data).expect("Failed to convert vec<u8> to config")
     }
+    // 解析来自网络或磁盘、可能已损坏的配置数据
+    pub fn try_from_data(data: &[u8]) -> Result<Config, String> {
+        serde_json::from_slice(data).map_err(|e| format!("malformed configuration: {}", e))
+    }
     // 将Config序列化为字节向量
     pub fn to_data(&self) -> Vec<u8> {
         serde_json::to_vec(self).expect("Failed to convert config to vec<u8>")
@@ -89,6 +555,7 @@ impl Config {
                 self.old_servers.push(proto::ServerInfo {
                     server_id: peer.id,
                     server_addr: peer.addr.clone(),
+                    zone: None,
                 });
             }
         }
@@ -101,32 +568,59 @@ impl Config {
         self.old_servers = servers;
     }
 
-    // 将当前配置从C(old, new)状态转换到C(new)状态，完成成员变更。如果当前状态不是C(old, new)，则会panic
-    pub fn finalize_transition(&self) -> Config {
-        if self.old_servers.is_empty() {
-            panic!("Cannot transition to C(new): current configuration is not C(old, new) because of old_servers is empty.");
-        }
-        if self.new_servers.is_empty() {
-            panic!("Cannot transition to C(new): new_servers list is empty in C(old, new) state. ");
+    // 将当前配置从C(old, new)状态转换到C(new)状态，完成成员变更。如果当前状态不是C(old, new)，返回 NotJoint
+    pub fn finalize_transition(&self) -> Result<Config, ConfigError> {
+        if !self.is_joint() {
+            return Err(ConfigError::NotJoint);
         }
-        Config {
+        Ok(Config {
             old_servers: Vec::new(),
             new_servers: self.new_servers.clone(),
-        }
+            algorithm: self.algorithm,
+        })
     }
 
-    // 从C_new创建联合配置C(old, new)，，开始成员变更
-    pub fn start_transition(&self, target_new_servers: Vec<proto::ServerInfo>) -> Self {
+    // 从C_new创建联合配置C(old, new)，开始成员变更。
+    // 目标列表需要通过 validate_servers，且不能把 C_old 中某个节点的地址分给另一个 ID
+    pub fn start_transition(&self, target_new_servers: Vec<proto::ServerInfo>) -> Result<Config, ConfigError> {
         if !self.old_servers.is_empty() {
-            panic!("Cannot create C(old,new): current configuration is already a joint state (old_servers is not empty).");
+            return Err(ConfigError::AlreadyJoint);
         }
         if self.new_servers.is_empty() {
-            panic!("Cannot create C(old,new): current new_servers is empty (no C_old to transition from).");
+            return Err(ConfigError::EmptyServers);
         }
-        Config {
+        Self::validate_servers(&target_new_servers)?;
+        Self::check_addr_conflicts(self.new_servers.iter().chain(target_new_servers.iter()))?;
+        Ok(Config {
             old_servers: self.new_servers.clone(), // 当前new_server变成old
             new_servers: target_new_servers,
+            algorithm: MembershipAlgorithm::JointConsensus,
+        })
+    }
+
+    // 单节点变更：从稳定配置直接得到新的稳定配置。新旧配置最多相差一个节点（增加、移除或修改地址），
+    // 两者的任意多数派必然相交，不需要经过 C(old,new)
+    pub fn single_server_change(&self, target_new_servers: Vec<proto::ServerInfo>) -> Result<Config, ConfigError> {
+        if !self.old_servers.is_empty() {
+            return Err(ConfigError::AlreadyJoint);
         }
+        if self.new_servers.is_empty() {
+            return Err(ConfigError::EmptyServers);
+        }
+        Self::validate_servers(&target_new_servers)?;
+        // 只在一边出现的 (ID, 地址) 所属的节点就是被变更的节点
+        let touched: std::collections::HashSet<u64> = self.new_servers.iter().filter(|s| !target_new_servers.contains(s))
+            .chain(target_new_servers.iter().filter(|s| !self.new_servers.contains(s)))
+            .map(|s| s.server_id)
+            .collect();
+        if touched.len() > 1 {
+            return Err(ConfigError::TooManyChanges(touched.len()));
+        }
+        Ok(Config {
+            old_servers: Vec::new(),
+            new_servers: target_new_servers,
+            algorithm: MembershipAlgorithm::SingleServer,
+        })
     }
 
     // 根据Config对象的内容，确定node_id的ConfigState
@@ -165,6 +659,13 @@ impl Config {
         server_map.values().cloned().collect()
     }
 
+    // 节点在配置中标注的可用区，同时存在于新旧配置中时以 new 为准；未标注或不在配置中时为 None
+    pub fn zone_of(&self, server_id: u64) -> Option<&str> {
+        self.new_servers.iter().chain(self.old_servers.iter())
+            .find(|s| s.server_id == server_id)
+            .and_then(|s| s.zone.as_deref())
+    }
+
     // 返回此Config中存在的所有唯一节点 ID 的列表
     pub fn all_ids_in_config(&self) -> Vec<u64> {
         let mut ids = std::collections::HashSet::new();
@@ -181,10 +682,32 @@ impl Config {
 
 #[cfg(test)]
 mod tests {
-    use crate::raft::config::{Config, ConfigState};
+    use crate::raft::config::{Config, ConfigState, SnapshotPolicy, SnapshotWindow};
     use crate::raft::proto::ServerInfo;
     use crate::raft::peer::Peer;
 
+    #[test]
+    fn test_snapshot_policy() {
+        let policy = SnapshotPolicy {
+            log_length_threshold: 10,
+            windows: vec![SnapshotWindow { start_minute: 120, end_minute: 240 }, SnapshotWindow { start_minute: 1380, end_minute: 60 }],
+            utc_offset_minutes: 480,
+            max_entry_rate: Some(50.0),
+            force_log_length: Some(1000),
+        };
+        // UTC 19:00 为东八区 03:00，在窗口内
+        let three_am = 19 * 3600;
+        assert_eq!(policy.check(10, three_am, 0.0), Ok(false));
+        assert_eq!(policy.check(11, three_am, 0.0), Ok(true));
+        // 东八区 12:00，不在窗口内；23:30 在跨零点的窗口内
+        assert!(policy.check(11, 4 * 3600, 0.0).unwrap_err().contains("local time 12:00"));
+        assert_eq!(policy.check(11, 15 * 3600 + 1800, 0.0), Ok(true));
+        assert!(policy.check(11, three_am, 80.0).unwrap_err().contains("entry rate"));
+        // 日志过长时不再推迟
+        assert_eq!(policy.check(1001, 4 * 3600, 80.0), Ok(true));
+        assert_eq!(SnapshotPolicy::default().check(6, 4 * 3600, 1e6), Ok(true));
+    }
+
     #[test]
     fn test_configuration() {
         // Test initial empty config
@@ -197,8 +720,8 @@ mod tests {
 
         // Test new_stable
         let initial_servers = vec![
-            ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string() },
-            ServerInfo { server_id: 2, server_addr: "[::1]:9002".to_string() },
+            ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string(), zone: None },
+            ServerInfo { server_id: 2, server_addr: "[::1]:9002".to_string(), zone: None },
         ];
         let stable_config = Config::new_stable(initial_servers.clone());
         assert!(!stable_config.is_empty());
@@ -212,12 +735,12 @@ mod tests {
         // Test append_new_servers
         let mut config_append = Config::new();
         config_append.append_new_servers(&vec![
-            ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string() },
+            ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string(), zone: None },
         ]);
         assert_eq!(config_append.new_servers.len(), 1);
         config_append.append_new_servers(&vec![
-            ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string() }, // Duplicate
-            ServerInfo { server_id: 3, server_addr: "[::1]:9003".to_string() },
+            ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string(), zone: None }, // Duplicate
+            ServerInfo { server_id: 3, server_addr: "[::1]:9003".to_string(), zone: None },
         ]);
         assert_eq!(config_append.new_servers.len(), 2);
         assert!(config_append.new_servers.iter().any(|s| s.server_id == 1));
@@ -231,20 +754,20 @@ mod tests {
 
         // Test start_transition and finalize_transition
         let mut current_config = Config::new_stable(vec![
-            ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string() },
-            ServerInfo { server_id: 2, server_addr: "[::1]:9002".to_string() },
+            ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string(), zone: None },
+            ServerInfo { server_id: 2, server_addr: "[::1]:9002".to_string(), zone: None },
         ]);
         let target_new_servers = vec![
-            ServerInfo { server_id: 2, server_addr: "[::1]:9002".to_string() },
-            ServerInfo { server_id: 3, server_addr: "[::1]:9003".to_string() },
+            ServerInfo { server_id: 2, server_addr: "[::1]:9002".to_string(), zone: None },
+            ServerInfo { server_id: 3, server_addr: "[::1]:9003".to_string(), zone: None },
         ];
-        let joint_config = current_config.start_transition(target_new_servers.clone());
+        let joint_config = current_config.start_transition(target_new_servers.clone()).unwrap();
         assert!(joint_config.is_joint());
         assert!(!joint_config.is_stable());
         assert_eq!(joint_config.old_servers, current_config.new_servers);
         assert_eq!(joint_config.new_servers, target_new_servers);
 
-        let final_config = joint_config.finalize_transition();
+        let final_config = joint_config.finalize_transition().unwrap();
         assert!(!final_config.is_joint());
         assert!(final_config.is_stable());
         assert_eq!(final_config.old_servers.len(), 0);
@@ -265,13 +788,21 @@ mod tests {
                 addr: "[::1]:9001".to_string(), 
                 next_index: 0, // 根据 Peer 定义添加默认值或实际值
                 match_index: 0, // 根据 Peer 定义添加默认值或实际值
+                last_applied: 0,
                 vote_granted: false, // 根据 Peer 定义添加默认值或实际值
-                config_state: ConfigState::new() // 根据 Peer 定义添加默认值或实际值
+                config_state: ConfigState::new(), // 根据 Peer 定义添加默认值或实际值
+                last_contact: None,
+                progress: crate::raft::peer::ProgressState::Probe,
+                lag: crate::raft::peer::LagTracker::default(),
+                rtt: crate::raft::peer::RttWindow::default(),
+                snapshot_required: false,
+                next_index_history: Default::default(),
+                clock_skew: Default::default(),
             },
         ]);
         test_config.append_new_servers(&vec![
-            ServerInfo { server_id: 2, server_addr: "[::1]:9002".to_string() },
-            ServerInfo { server_id: 3, server_addr: "[::1]:9003".to_string() },
+            ServerInfo { server_id: 2, server_addr: "[::1]:9002".to_string(), zone: None },
+            ServerInfo { server_id: 3, server_addr: "[::1]:9003".to_string(), zone: None },
         ]);
 
         assert_eq!(test_config.get_node_state(1), ConfigState { newing: false, olding: true });
@@ -308,4 +839,57 @@ mod tests {
         assert!(all_ids_stable.contains(&1));
         assert!(all_ids_stable.contains(&2));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_reject_malformed_configs() {
+        use crate::raft::config::ConfigError;
+        let server = |id: u64, port: u64| ServerInfo { server_id: id, server_addr: format!("[::1]:{}", port), zone: None };
+
+        assert_eq!(Config::try_new_stable(Vec::new()), Err(ConfigError::EmptyServers));
+        assert_eq!(Config::try_new_stable(vec![server(1, 9001), server(1, 9002)]), Err(ConfigError::DuplicateServerId(1)));
+        assert_eq!(
+            Config::try_new_stable(vec![server(1, 9001), server(2, 9001)]),
+            Err(ConfigError::DuplicateServerAddr { addr: "[::1]:9001".to_string(), first_id: 1, second_id: 2 }),
+        );
+        let stable = Config::try_new_stable(vec![server(1, 9001), server(2, 9002)]).unwrap();
+
+        // 目标列表本身有重复，或把 C_old 中节点 2 的地址分给了新的节点 3
+        assert_eq!(stable.start_transition(vec![server(3, 9003), server(3, 9004)]), Err(ConfigError::DuplicateServerId(3)));
+        assert!(matches!(
+            stable.start_transition(vec![server(1, 9001), server(3, 9002)]),
+            Err(ConfigError::DuplicateServerAddr { second_id: 3, .. }),
+        ));
+        assert_eq!(stable.start_transition(Vec::new()), Err(ConfigError::EmptyServers));
+        assert_eq!(stable.finalize_transition(), Err(ConfigError::NotJoint));
+
+        let joint = stable.start_transition(vec![server(2, 9002), server(3, 9003)]).unwrap();
+        assert_eq!(joint.start_transition(vec![server(1, 9001)]), Err(ConfigError::AlreadyJoint));
+        assert_eq!(joint.finalize_transition().unwrap().new_servers, vec![server(2, 9002), server(3, 9003)]);
+    }
+
+    #[test]
+    fn test_single_server_change() {
+        use crate::raft::config::{ConfigError, MembershipAlgorithm};
+        let server = |id: u64, port: u64| ServerInfo { server_id: id, server_addr: format!("[::1]:{}", port), zone: None };
+        let stable = Config::new_stable(vec![server(1, 9001), server(2, 9002)]);
+
+        // 增加、移除或修改一个节点都可以直接得到新的稳定配置
+        let added = stable.single_server_change(vec![server(1, 9001), server(2, 9002), server(3, 9003)]).unwrap();
+        assert!(added.is_stable());
+        assert_eq!(added.algorithm, MembershipAlgorithm::SingleServer);
+        assert!(stable.single_server_change(vec![server(1, 9001)]).is_ok());
+        assert!(stable.single_server_change(vec![server(1, 9001), server(2, 9012)]).is_ok());
+
+        // 一次变更两个节点，或者从联合配置出发，都会被拒绝
+        assert_eq!(stable.single_server_change(vec![server(1, 9001), server(3, 9003), server(4, 9004)]), Err(ConfigError::TooManyChanges(3)));
+        assert_eq!(stable.single_server_change(vec![server(3, 9003)]), Err(ConfigError::TooManyChanges(3)));
+        let joint = added.start_transition(vec![server(1, 9001)]).unwrap();
+        assert_eq!(joint.algorithm, MembershipAlgorithm::JointConsensus);
+        assert_eq!(joint.single_server_change(vec![server(1, 9001)]), Err(ConfigError::AlreadyJoint));
+
+        // 引入算法字段之前的配置条目按联合共识解析
+        let legacy = br#"{"old_servers":[],"new_servers":[{"server_id":1,"server_addr":"[::1]:9001"}]}"#;
+        assert_eq!(Config::from_data(legacy).algorithm, MembershipAlgorithm::JointConsensus);
+        assert_eq!(Config::from_data(&added.to_data()), added);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::raft::{config, proto};
+use super::logging::*;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 已提交配置变更的历史，只保留最近 capacity 条，每次变化后整体写回磁盘。
+// 日志被快照压缩之后，成员变化的过程仍然可以通过它追溯
+#[derive(Debug)]
+pub struct ConfigHistory {
+    filepath: String,
+    capacity: usize,
+    changes: VecDeque<proto::ConfigChange>,
+}
+
+impl ConfigHistory {
+    pub fn new(metadata_dir: &str, capacity: usize) -> Self {
+        ConfigHistory {
+            filepath: Self::gen_filepath(metadata_dir),
+            capacity: capacity.max(1),
+            changes: VecDeque::new(),
+        }
+    }
+
+    pub fn gen_filepath(metadata_dir: &str) -> String {
+        format!("{}/raft.config_history", metadata_dir)
+    }
+
+    // 从磁盘加载历史，文件不存在或损坏时从空历史开始
+    pub fn reload(&mut self) {
+        let content = match std::fs::read_to_string(&self.filepath) {
+            Ok(content) => content,
+            Err(_) => return,
+        };
+        match serde_json::from_str::<Vec<proto::ConfigChange>>(&content) {
+            Ok(changes) => {
+                self.changes = changes.into_iter().collect();
+                while self.changes.len() > self.capacity {
+                    self.changes.pop_front();
+                }
+            }
+            Err(e) => warn!("Failed to parse config history {}: {}. Starting with empty history.", self.filepath, e),
+        }
+    }
+
+    // 记录一次已提交的配置；重启后重新应用的旧条目按索引去重
+    pub fn record(&mut self, index: u64, term: u64, committed_config: &config::Config, initiator_id: u64) {
+        if self.changes.back().is_some_and(|last| last.index >= index) {
+            return;
+        }
+        self.changes.push_back(proto::ConfigChange {
+            index,
+            term,
+            old_servers: committed_config.old_servers.clone(),
+            new_servers: committed_config.new_servers.clone(),
+            initiator_id,
+            committed_at_ms: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+        });
+        if self.changes.len() > self.capacity {
+            self.changes.pop_front();
+        }
+        self.persist();
+    }
+
+    pub fn changes(&self) -> Vec<proto::ConfigChange> {
+        self.changes.iter().cloned().collect()
+    }
+
+    fn persist(&self) {
+        // 先写临时文件再重命名，避免崩溃时留下写了一半的文件
+        let tmp_filepath = format!("{}.tmp", self.filepath);
+        let result = serde_json::to_string(&self.changes)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(&tmp_filepath, json).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&tmp_filepath, &self.filepath).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("Failed to persist config history to {}: {}", self.filepath, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn server(id: u64) -> proto::ServerInfo {
+        proto::ServerInfo { server_id: id, server_addr: format!("[::1]:{}", 9000 + id), zone: None }
+    }
+
+    #[test]
+    fn test_config_history_ring_and_reload() {
+        let dir = tempdir().unwrap();
+        let dir_str = dir.path().to_str().unwrap();
+
+        let mut history = ConfigHistory::new(dir_str, 2);
+        history.record(1, 1, &config::Config::new_stable(vec![server(1)]), 1);
+        let joint = config::Config { old_servers: vec![server(1)], new_servers: vec![server(1), server(2)], ..config::Config::new() };
+        history.record(5, 2, &joint, 1);
+        // 重复应用同一个索引不会产生新记录
+        history.record(5, 2, &joint, 1);
+        history.record(6, 2, &config::Config::new_stable(vec![server(1), server(2)]), 1);
+
+        let changes = history.changes();
+        assert_eq!(changes.iter().map(|c| c.index).collect::<Vec<_>>(), vec![5, 6]);
+        assert_eq!(changes[0].old_servers, vec![server(1)]);
+
+        let mut reloaded = ConfigHistory::new(dir_str, 2);
+        reloaded.reload();
+        assert_eq!(reloaded.changes(), changes);
+    }
+}
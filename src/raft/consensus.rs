@@ -1,100 +1,297 @@
-use crate::raft::{config, log, metadata, peer, proto, rpc, snapshot, state_machine, timer, util};
+use crate::raft::{archive, audit, clock, codec, command_audit, compress, config, config_history, crash, events, fairness, invariants, log, metadata, metrics, partition, peer, proto, rate_limit, rpc, sanity, shedding, sink, snapshot, state_machine, timer, util, zone};
 use super::logging::*; 
+use std::collections::{BTreeMap, VecDeque};
 use std::io::{Read, Seek, Write};
-use std::sync::{Arc, Mutex as StdMutex};
+use std::sync::{Arc, Mutex as StdMutex, Weak};
 use std::time::{Duration, Instant as StdInstant};
-use tokio::sync::Mutex as TokioMutex;
-use futures::future;
+use tokio::sync::{oneshot, watch, Mutex as TokioMutex};
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum State {
     Follower,
     Candidate,
     Leader,
 }
 
+// 对外发布的节点状态视图，GetLeader/GetConfiguration 直接读取它，不需要获取共识模块的锁
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateView {
+    pub state: State,
+    pub leader: Option<proto::ServerInfo>,  // 当前认定的 Leader，未知时为 None
+    pub servers: Vec<proto::ServerInfo>,    // 当前配置中的所有节点
+    pub health: proto::NodeHealth,          // 节点自检的结果
+    pub term: u64,                          // 当前任期
+    pub commit_index: u64,                  // 本节点的提交索引
+    pub algorithm: config::MembershipAlgorithm, // 当前配置记录的成员变更算法
+}
+
+impl StateView {
+    pub fn leader_response(&self) -> proto::GetLeaderResponse {
+        proto::GetLeaderResponse { leader: self.leader.clone(), redirect_to: None, term: self.term, commit_index: self.commit_index }
+    }
+
+    pub fn configuration_response(&self) -> proto::GetConfigurationResponse {
+        proto::GetConfigurationResponse {
+            servers: self.servers.clone(),
+            success: true,
+            leader: self.leader.clone(),
+            health: self.health as i32,
+            algorithm: proto::MembershipAlgorithm::from(self.algorithm) as i32,
+        }
+    }
+}
+
 pub struct Consensus {
     // 身份配置
     pub server_id: u64,                                 // 当前服务器唯一ID
     pub server_addr: String,                            // IP地址，用于RPC通信
     pub metadata: Arc<metadata::MetadataManager>,       // 持久化元数据管理器
     pub state: State,                                   // 当前节点状态(Follower, Candidate, Leader)
+    pub shutting_down: bool,                            // 是否已经开始关闭，关闭后拒绝新的 Propose
+    pub non_candidate: bool,                            // 正在被下线，不再发起选举，也不接受 TimeoutNow
+    pub group_id: u64,                                  // 所属共识组，同一进程内的多个组通过它区分
+    pub coalesce_heartbeats: bool,                      // 心跳是否交给 GroupRegistry 合并发送
+    pub partition: partition::Partition,                // 测试用的网络分区，被屏蔽节点之间不再通信
     pub current_config: config::Config,                 // 当前集群活跃配置
+    pub config_index: u64,                              // current_config 来自的配置条目的索引，初始配置为0
+    pub config_mismatch: Option<String>,                // current_config 中本节点的地址与 server_addr 不一致时为配置中的地址
+    pub incompatible_snapshot: Option<String>,          // 状态机拒绝恢复的快照及原因，成功从快照恢复或应用越过该快照后清除
+    incompatible_snapshot_index: u64,                   // 被拒绝的快照的 last_included_index
+    pub log_gap: Option<u64>,                           // 找不到而无法应用的已提交条目，应用越过它之后清除
+    pub config_history: config_history::ConfigHistory,  // 已提交配置变更的历史
     pub node_config_state: config::ConfigState,         // 当前节点在集群中的角色(newing, olding)
     
     // 日志与状态机相关
     pub log: log::Log,                                  // 日志模块
     pub commit_index: u64,                              // 已知的被提交的最高日志条目索引
     pub last_applied: u64,                              // 已应用到状态机的最高日志条目索引
+    applied_tx: watch::Sender<u64>,                     // last_applied 的变化通知，用于等待 read_token
+    append_times: VecDeque<(u64, proto::EntryType, StdInstant, u64)>, // Leader 追加但尚未提交的条目、追加时间和字节数，用于统计延迟
+    applying: VecDeque<(u64, proto::EntryType, StdInstant)>, // Leader 已提交但尚未应用的条目和追加时间，用于统计应用延迟
+    apply_scheduled: bool,                              // 是否已有后台任务在分批应用已提交的条目
+    apply_generation: u64,                              // 每次取消等待中的后台应用任务时递增，醒来时代数不同的任务直接退出
+    apply_pacer: Option<rate_limit::ApplyPacer>,        // Follower 应用条目的限速，None 表示不限制
+    pub load_shedder: Option<shedding::LoadShedder>,    // Leader 按提交延迟拒绝部分客户端提议，None 表示不减载
+    pub divergence_alarm: Option<config::DivergenceAlarm>, // Leader 与最慢投票节点的日志差距告警，None 表示不检查
+    divergence: (u32, bool),                            // 差距连续超过阈值的心跳周期数，以及当前是否处于告警状态
+    pub max_clock_skew: Option<Duration>,               // 时钟偏差上限，超过时 Leader 的读租约失效，None 表示不检查
+    clock_skew_exceeded: bool,                          // 最近一次心跳检查时是否有 Peer 的偏差超过上限
+    uncommitted_bytes: u64,                             // append_times 中条目数据的总字节数，用于 Propose 限流
+    apply_waiters: BTreeMap<u64, (u64, oneshot::Sender<Vec<u8>>)>, // 等待提交的 Propose：日志索引 -> (任期, 结果通道)
+    follow_up_queue: VecDeque<(u32, Vec<u8>)>,          // 状态机提交、等待 Leader Propose 的后续命令及其层数
+    follow_up_depths: BTreeMap<u64, u32>,               // 本 Leader Propose 的后续命令：最后一个条目的索引 -> 层数
+    follow_up_scheduled: bool,                          // 是否已有后台任务在 Propose 后续命令
+    pub max_entry_size: usize,                          // 单个条目数据的上限
+    pub chunk_large_entries: bool,                      // 超过上限的 Propose 是否拆分成多个 DataChunk 条目
+    pub disk_quota_bytes: Option<u64>,                  // 日志和快照合计的磁盘配额，None 表示不限制
+    over_disk_quota: bool,                              // 最近一次定时检查压缩后是否仍然超过配额，Propose 据此拒绝写入
+    pub storage_compression: config::StorageCompression, // 新生成的快照数据文件的压缩方式，日志的压缩方式设置在 log 上
+    pub ready_max_apply_lag: u64,                       // 就绪检查允许的应用落后量
+    chunk_assembler: codec::ChunkAssembler,             // 应用 DataChunk 条目时重组数据
     pub state_machine: Box<dyn state_machine::StateMachine>,// 用户定义的状态机
+    pub restore_progress: Arc<state_machine::RestoreProgress>, // 从快照恢复状态机的进度，查询时不需要加锁
+    pub proposal_scheduler: Arc<fairness::ProposalScheduler>, // Propose 在拿锁之前按客户端轮转排队，见 fairness 模块
 
     // Leader的选举与维护
-    pub leader_id: u64,                                 // 当前认定的Leader ID
+    pub leader_id: u64,                                 // 当前认定的Leader ID，通过 set_leader_id 修改
+    view_tx: watch::Sender<StateView>,                  // 状态视图，角色、Leader 或配置变化时更新
+    pub last_leader_contact: StdInstant,                // 最近一次收到Leader消息(或自己成为Leader)的时间
     pub election_timer: Arc<TokioMutex<timer::Timer>>,  // 选举超时计时器
-    pub heartbeat_timer: Arc<TokioMutex<timer::Timer>>, // 心跳超时计时器(Leader计时器)
+    pub heartbeat_timer: Arc<TokioMutex<timer::Timer>>, // 心跳超时计时器(Leader计时器)，只在成为 Leader 后运行
+    self_ref: Weak<TokioMutex<Consensus>>,              // 指向自身的弱引用，成为 Leader 时用它启动心跳计时器
     
     // 集群管理
     pub peer_manager: peer::PeerManager,            // 管理集群中的其他节点
 
     // 快照相关 
-    pub snapshot: snapshot::Snapshot,                   // 快照模块实例
+    pub snapshot: snapshot::SnapshotManager,                   // 快照模块实例
     pub snapshot_timer: Arc<TokioMutex<timer::Timer>>,  // 快照生成定时器
+    snapshot_transfer: Option<snapshot::SnapshotTransfer>, // Follower正在接收的快照传输进度
+    last_snapshot_duration: Option<Duration>,           // 本节点上一次生成快照的耗时
+    pub snapshot_policy: config::SnapshotPolicy,        // 定时快照的调度策略
+    snapshot_tick: (StdInstant, u64),                   // 上一次检查快照策略的时间和当时的最后日志索引，用于计算写入速率
+    last_snapshot_check: Option<StdInstant>,            // 应用路径上最近一次检查快照策略的时间
+    snapshot_running: bool,                             // 是否有后台任务正在生成快照，期间不再生成新的快照
+    snapshot_check_due: Arc<std::sync::atomic::AtomicBool>,          // 上一次定时检查之后是否应用过新条目，快照定时器据此决定要不要加锁检查
+    snapshot_deferred: Option<String>,                  // 定时快照被调度策略推迟的原因，生成快照后清空
+    last_applied_gap: Option<proto::AppliedGap>,        // 最近一次安装快照时跳过应用的日志范围
     
     // RPC通信
     rpc_client: rpc::Client,                            // 用于向其他节点发送RPC的客户端
+
+    clock: Arc<dyn clock::Clock>,                       // 计时器和租约判断使用的时间来源
+
+    // 可观测性
+    pub events: events::EventBus,                       // 对外发布的事件
+    pub metrics: Arc<metrics::Metrics>,                 // 运行指标
+    pub verify_task: Option<tokio::task::JoinHandle<()>>, // 后台校验任务，关闭时终止
+    pub status_task: Option<tokio::task::JoinHandle<()>>, // HTTP 状态页服务，关闭时终止
+    pub archiver: Option<archive::Archiver>,            // 快照和日志段的归档上传，关闭时不再接收新的任务
+    pub commit_sink: Option<sink::SinkDispatcher>,      // 已应用结果的外部投递，关闭时不再接收新的记录
+    stop_tx: watch::Sender<bool>,                       // 节点完全停止的信号，RPC 服务收到后退出
+    audit: audit::AuditLog,                             // 任期、投票和角色变化的审计日志
+    pub command_audit: command_audit::CommandAuditLog,  // 本节点收到的管理操作的审计日志
+    pub crash_recorder: Option<Arc<crash::CrashRecorder>>, // panic 时转储的现场，None 表示没有开启崩溃转储
+    vote_hold: Option<(u64, StdInstant)>,               // 重启后在该任期内暂不投票，直到这个时间；防止重启前未落盘的投票被重复投出
+}
+
+// Leader 向某个 Peer 复制时的下一步动作
+enum AppendEntriesPlan {
+    Skip,
+    Snapshot,
+    Send(String, proto::AppendEntriesRequest),
+}
+
+// 应用时取出的已提交条目，见 committed_entry
+enum CommittedEntry {
+    Found(proto::LogEntry),
+    RestoredFromSnapshot,
+    Missing,
+}
+
+// 一次快照传输用到的信息，发起时在持有锁的情况下确定，见 install_snapshot_to_peer
+struct SnapshotTransfer {
+    peer_id: u64,
+    peer_addr: String,
+    term: u64,
+    leader_id: u64,
+    last_included_index: u64,
+    last_included_term: u64,
+    checksum: u64,
+    metadata_filepath: String,
+    snapshot_filepath: String,
+    started_at: std::time::Instant,
+}
+
+// 状态机已经写出数据、还没有写入元数据的快照，见 prepare_snapshot 和 finish_snapshot
+struct PreparedSnapshot {
+    last_included_index: u64,
+    last_included_term: u64,
+    config_index: u64,
+    configuration: config::Config,
+    snapshot_filepath: String,
+    compression_level: Option<i32>,
+    started_at: std::time::Instant,
+}
+
+// 按配置压缩快照文件并计算校验和，元数据中的校验和与传输、归档的都是落盘的字节。只读写文件，可以在阻塞线程中调用
+fn seal_snapshot_file(snapshot_filepath: &str, compression_level: Option<i32>) -> Result<u64, String> {
+    if let Some(level) = compression_level {
+        compress::compress_file(snapshot_filepath, level)
+            .map_err(|e| format!("failed to compress snapshot file {}: {}", snapshot_filepath, e))?;
+    }
+    snapshot::checksum_file(snapshot_filepath)
+        .map_err(|e| format!("failed to compute checksum of snapshot file {}: {}", snapshot_filepath, e))
+}
+
+type RestoreResult = std::thread::Result<Box<dyn state_machine::StateMachine>>;
+
+// 正在阻塞线程中恢复的状态机。等待恢复的调用被取消时（例如 RPC 连接断开，持有的锁随之释放），
+// 由后台任务等恢复结束后把状态机放回共识模块；放回之前 restore_progress 一直处于恢复中，应用和读取都会暂停。
+// 恢复会整体替换状态机的内容，last_applied 仍在快照之前时应用流程会再次从快照恢复
+struct PendingRestore {
+    rx: Option<oneshot::Receiver<RestoreResult>>,
+    consensus: Weak<TokioMutex<Consensus>>,
+}
+
+impl Drop for PendingRestore {
+    fn drop(&mut self) {
+        let Some(rx) = self.rx.take() else {
+            return;
+        };
+        let consensus = self.consensus.clone();
+        tokio::spawn(crash::scope(async move {
+            let Ok(result) = rx.await else {
+                return;
+            };
+            let state_machine = result.unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            let Some(consensus) = consensus.upgrade() else {
+                return;
+            };
+            let mut guard = consensus.lock().await;
+            warn!("Snapshot restore finished after its caller was cancelled, putting the state machine back.");
+            guard.state_machine = state_machine;
+            guard.restore_progress.finish();
+            guard.schedule_apply(Duration::ZERO);
+        }));
+    }
 }
 
 impl Consensus {
     pub async fn new(
         server_id: u64,
-        port: u32,
+        server_addr: String,
         initial_peers_info: Vec<proto::ServerInfo>,
         state_machine: Box<dyn state_machine::StateMachine>,
         snapshot_dir: String,
         metadata_dir: String,
-    ) -> Arc<TokioMutex<Consensus>> {
-
-
-        // 初始化元数据管理器 (MetadataManager::new 内部会 tokio::spawn)
-        let initial_metadata_result = metadata::Metadata::load(&metadata_dir);
-        let initial_metadata = initial_metadata_result.unwrap_or_else(|e| {
-            warn!("Consensus::new: Failed to load metadata from {}: {}. Creating new.", metadata_dir, e);
-            metadata::Metadata::new(metadata_dir.clone())
+        clock: Arc<dyn clock::Clock>,
+    ) -> Result<Arc<TokioMutex<Consensus>>, String> {
+        // 加载日志，日志损坏时拒绝启动
+        let mut log_instance = log::Log::new(1, metadata_dir.clone());
+        log_instance.reload()?;
+
+        // 初始化元数据管理器 (MetadataManager::new 内部会 tokio::spawn)。
+        // 文件不存在时从空的元数据开始；文件存在但读不出来时拒绝启动，覆盖它会丢掉任期和投票记录
+        let mut initial_metadata = metadata::Metadata::load(&metadata_dir)
+            .map_err(|e| format!("failed to load metadata from {}: {}", metadata_dir, e))?;
+
+        let persisted_commit_index = initial_metadata.commit_index;
+        // 启动计数在任何投票之前同步落盘，之后的启动都能知道自己是重启
+        initial_metadata.boot_count += 1;
+        initial_metadata.store()
+            .map_err(|e| format!("failed to persist boot count {}: {}", initial_metadata.boot_count, e))?;
+        let vote_hold = (initial_metadata.boot_count > 1 && initial_metadata.current_term > 0).then(|| {
+            info!("Consensus::new: Restart #{}, not granting votes in term {} for {:?}.",
+                initial_metadata.boot_count - 1, initial_metadata.current_term, config::RESTART_VOTE_HOLD);
+            (initial_metadata.current_term, clock.now() + config::RESTART_VOTE_HOLD)
         });
-
         // Metadata内部会tokio::spawn一个后台任务来处理异步持久化
         let metadata_manager = metadata::MetadataManager::new(initial_metadata, Duration::from_millis(100));
 
-        let server_addr = format!("[::1]:{}", port);
 
+        let audit_log = audit::AuditLog::new(server_id, &metadata_dir);
+        let command_audit = command_audit::CommandAuditLog::new(&metadata_dir, config::COMMAND_AUDIT_MAX_FILE_BYTES, config::COMMAND_AUDIT_MAX_ROTATED_FILES);
+        let mut config_history = config_history::ConfigHistory::new(&metadata_dir, config::CONFIG_HISTORY_CAPACITY);
+        config_history.reload();
 
-        // 加载日志
-        let mut log_instance = log::Log::new(1, metadata_dir.clone());
-        log_instance.reload();
         // 加载快照
-        let mut snapshot_instance = snapshot::Snapshot::new(snapshot_dir);
+        let mut snapshot_instance = snapshot::SnapshotManager::new(snapshot_dir);
         snapshot_instance.reload_metadata();
 
 
         // 确定初始配置
         /*
-            优先从快照中获取配置，
-            如果快照没有，则尝试从日志的最后一个配置条目获取配置条目，
+            取快照中的配置和日志中最后一个配置条目里较新的一个：
+            快照可能是在 C(old,new) 期间生成的，之后追加的 C(new) 只在日志中，
             如果二者都没有，则基于传入的initial_peers_info创建一个新的稳定的配置
          */
-        let initial_config = snapshot_instance.configuration.clone().unwrap_or_else(|| {
-            log_instance.last_configuration().unwrap_or_else(|| {
+        let snapshot_config = snapshot_instance.configuration().cloned().map(|config| (snapshot_instance.meta().config_index(), config));
+        let log_config = log_instance.last_configuration_entry()
+            .filter(|(index, _)| snapshot_config.as_ref().is_none_or(|(snapshot_config_index, _)| index > snapshot_config_index));
+        // 传入的节点列表有重复的 ID 或地址时拒绝启动
+        let (config_index, initial_config) = match log_config.or(snapshot_config) {
+            Some(found) => found,
+            None => {
                 info!("Consensus::new: No configuration found in snapshot or log. Creating initial stable configuration.");
                 let mut initial_cluster_servers = initial_peers_info.clone();
                 if !initial_cluster_servers.iter().any(|s| s.server_id == server_id) {
                     initial_cluster_servers.push(proto::ServerInfo {
                         server_id,
                         server_addr: server_addr.clone(),
+                        zone: None,
                     });
                 }
-                config::Config::new_stable(initial_cluster_servers)
-            })
-        });
+                let initial_config = config::Config::try_new_stable(initial_cluster_servers)
+                    .map_err(|e| format!("invalid initial cluster configuration: {}", e))?;
+                (0, initial_config)
+            }
+        };
+        if initial_config.is_joint() {
+            info!("Consensus::new: Recovered in the middle of a configuration change (C(old,new) at index {}), the leader will append C(new).", config_index);
+        }
         // 根据初始配置计算当前节点的node_config_state
         let node_config_state = initial_config.get_node_state(server_id);
 
@@ -105,33 +302,103 @@ impl Consensus {
             server_addr,
             metadata: metadata_manager,
             state: State::Follower,
-            election_timer: Arc::new(TokioMutex::new(timer::Timer::new("election_timer"))),
-            heartbeat_timer: Arc::new(TokioMutex::new(timer::Timer::new("heartbeat_timer"))),
-            snapshot_timer: Arc::new(TokioMutex::new(timer::Timer::new("snapshot_timer"))),
+            shutting_down: false,
+            non_candidate: false,
+            group_id: config::DEFAULT_GROUP_ID,
+            coalesce_heartbeats: false,
+            election_timer: Arc::new(TokioMutex::new(timer::Timer::with_clock("election_timer", clock.clone()))),
+            heartbeat_timer: Arc::new(TokioMutex::new(timer::Timer::with_clock("heartbeat_timer", clock.clone()))),
+            self_ref: Weak::new(),
+            snapshot_timer: Arc::new(TokioMutex::new(timer::Timer::with_clock("snapshot_timer", clock.clone()))),
             commit_index: 0,
             last_applied: 0,
+            applied_tx: watch::channel(0).0,
+            append_times: VecDeque::new(),
+            applying: VecDeque::new(),
+            apply_scheduled: false,
+            apply_generation: 0,
+            apply_pacer: None,
+            load_shedder: None,
+            divergence_alarm: None,
+            divergence: (0, false),
+            max_clock_skew: None,
+            clock_skew_exceeded: false,
+            uncommitted_bytes: 0,
+            apply_waiters: BTreeMap::new(),
+            follow_up_queue: VecDeque::new(),
+            follow_up_depths: BTreeMap::new(),
+            follow_up_scheduled: false,
+            max_entry_size: config::DEFAULT_MAX_ENTRY_SIZE,
+            chunk_large_entries: false,
+            disk_quota_bytes: None,
+            over_disk_quota: false,
+            storage_compression: config::StorageCompression::None,
+            ready_max_apply_lag: config::DEFAULT_READY_MAX_APPLY_LAG,
+            chunk_assembler: codec::ChunkAssembler::default(),
             leader_id: config::NONE_SERVER_ID,
+            view_tx: watch::channel(StateView { state: State::Follower, leader: None, servers: Vec::new(), health: proto::NodeHealth::Ok, term: 0, commit_index: 0, algorithm: config::MembershipAlgorithm::default() }).0,
+            last_leader_contact: clock.now(),
             peer_manager: peer::PeerManager::new(),
             log: log_instance,
             snapshot: snapshot_instance,
+            snapshot_transfer: None,
+            last_snapshot_duration: None,
+            snapshot_policy: config::SnapshotPolicy::default(),
+            snapshot_tick: (StdInstant::now(), 0),
+            last_snapshot_check: None,
+            snapshot_running: false,
+            snapshot_check_due: Arc::new(std::sync::atomic::AtomicBool::new(true)),
+            snapshot_deferred: None,
+            last_applied_gap: None,
             current_config: initial_config,
+            config_index,
+            config_mismatch: None,
+            incompatible_snapshot: None,
+            incompatible_snapshot_index: 0,
+            log_gap: None,
+            partition: partition::Partition::default(),
+            config_history,
             node_config_state,
-            rpc_client: rpc::Client {},
+            rpc_client: rpc::Client::default(),
             state_machine,
+            restore_progress: Arc::new(state_machine::RestoreProgress::default()),
+            proposal_scheduler: Arc::new(fairness::ProposalScheduler::default()),
+            clock,
+            events: events::EventBus::new(),
+            metrics: Arc::new(metrics::Metrics::new()),
+            verify_task: None,
+            status_task: None,
+            archiver: None,
+            commit_sink: None,
+            stop_tx: watch::channel(false).0,
+            audit: audit_log,
+            command_audit,
+            crash_recorder: None,
+            vote_hold,
         };
 
 
-        // 应用快照
-        if consensus_struct.snapshot.last_included_index > 0 {  // 说明有快照
+        // 应用快照。状态机拒绝快照的版本时不推进 last_applied，应用停在快照之前
+        let state_machine_applied = consensus_struct.state_machine.applied_index();
+        if consensus_struct.snapshot.last_included_index() > 0 {  // 说明有快照
             // 调用接口将快照数据恢复到状态机
             if let Some(snapshot_filepath) = consensus_struct.snapshot.latest_snapshot_filepath() { // Removed &mut from latest_snapshot_filepath if it doesn't need it. Assuming it's &self.
-                info!("Consensus::new: Restoring state machine from snapshot: {}", snapshot_filepath);
-                consensus_struct.state_machine.restore_snapshot(&snapshot_filepath);
+                let restored = if state_machine_applied.is_some_and(|applied| applied >= consensus_struct.snapshot.last_included_index()) {
+                    // 持久化的状态机已经包含快照的内容，不需要恢复
+                    info!("Consensus::new: State machine already applied up to {:?}, skipping snapshot restore.", state_machine_applied);
+                    true
+                } else {
+                    info!("Consensus::new: Restoring state machine from snapshot: {}", snapshot_filepath);
+                    consensus_struct.restore_state_machine(&snapshot_filepath).await
+                };
                 // 更新commit_index和last_applied为快照的last_included_index
-                consensus_struct.commit_index = consensus_struct.snapshot.last_included_index;
-                consensus_struct.last_applied = consensus_struct.snapshot.last_included_index;
+                consensus_struct.commit_index = consensus_struct.snapshot.last_included_index();
+                if restored {
+                    consensus_struct.set_last_applied(consensus_struct.snapshot.last_included_index());
+                }
                 // 丢弃快照已经覆盖的日志条目
-                consensus_struct.log.truncate_prefix(consensus_struct.snapshot.last_included_index);
+                consensus_struct.log.truncate_prefix(consensus_struct.snapshot.last_included_index());
+                consensus_struct.log.persist();
             } else {    // 没有快照
                 warn!("Consensus::new: Snapshot metadata indicates last_included_index > 0 but no snapshot file found.");
             }
@@ -147,24 +414,26 @@ impl Consensus {
         // 将这些peer添加到管理器，并且设置其初始next_index
         consensus_struct.peer_manager.add(
             peers_for_manager,
-            consensus_struct.log.last_index(consensus_struct.snapshot.last_included_index),
+            consensus_struct.log.last_index(consensus_struct.snapshot.last_included_index()),
         );
         // 更新Peer配置状态
         consensus_struct.update_peer_config_states();
+        // 开始服务之前补齐已提交但状态机还没有应用的条目
+        consensus_struct.recover_committed_entries(persisted_commit_index, state_machine_applied).await;
+        consensus_struct.check_advertised_address();
 
 
         // 方便在多任务间共享和同步访问
         let consensus_arc = Arc::new(TokioMutex::new(consensus_struct));
 
-        // 启动定时器
+        // 启动定时器，心跳计时器在成为 Leader 时才启动
         let election_timer_arc_clone;
-        let heartbeat_timer_arc_clone;
         let snapshot_timer_arc_clone;
         {
-            let tmp_consensus_guard = consensus_arc.lock().await;
+            let mut tmp_consensus_guard = consensus_arc.lock().await;
+            tmp_consensus_guard.self_ref = Arc::downgrade(&consensus_arc);
 
             election_timer_arc_clone = Arc::clone(&tmp_consensus_guard.election_timer);
-            heartbeat_timer_arc_clone = Arc::clone(&tmp_consensus_guard.heartbeat_timer);
             snapshot_timer_arc_clone = Arc::clone(&tmp_consensus_guard.snapshot_timer);
 
             drop(tmp_consensus_guard);  // 释放锁
@@ -176,10 +445,10 @@ impl Consensus {
             util::rand_election_timeout(),
             move || {
                 if let Some(sc_arc_strong) = election_consensus_weak.upgrade() {
-                    tokio::spawn(async move {
+                    tokio::spawn(crash::scope(async move {
                         let mut consensus_guard = sc_arc_strong.lock().await;
                         consensus_guard.handle_election_timeout().await;
-                    });
+                    }));
                 } else {
                     warn!("Election timer fired but Consensus Arc was dropped.");
                 }
@@ -188,34 +457,21 @@ impl Consensus {
         drop(election_timer_guard); // 显式释放 guard
         
         
-        // 仅Leader使用，向Leader周期性发送心跳，通常是空的AppendEntries RPC
-        let heartbeat_consensus_weak = Arc::downgrade(&consensus_arc);
-        let mut heartbeat_timer_guard = heartbeat_timer_arc_clone.lock().await; // <--- 使用 .await
-        heartbeat_timer_guard.schedule(
-            config::HEARTBEAT_INTERVAL,
-            move || {
-                if let Some(sc_arc_strong) = heartbeat_consensus_weak.upgrade() {
-                    tokio::spawn(async move {
-                        let mut consensus_guard = sc_arc_strong.lock().await;
-                        consensus_guard.handle_heartbeat_timeout().await;
-                    });
-                } else {
-                     warn!("Heartbeat timer fired but Consensus Arc was dropped.");
-                }
-            },
-        );
-        drop(heartbeat_timer_guard); // 显式释放 guard
-
         let snapshot_consensus_weak = Arc::downgrade(&consensus_arc);
+        let snapshot_check_due = Arc::clone(&consensus_arc.lock().await.snapshot_check_due);
         let mut snapshot_timer_guard = snapshot_timer_arc_clone.lock().await; // <--- 使用 .await
         snapshot_timer_guard.schedule(
             config::SNAPSHOT_INTERVAL,
             move || {
+                // 上一次检查之后没有应用新条目时不必加锁
+                if !snapshot_check_due.swap(false, std::sync::atomic::Ordering::AcqRel) {
+                    return;
+                }
                  if let Some(sc_arc_strong) = snapshot_consensus_weak.upgrade() {
-                    tokio::spawn(async move {
+                    tokio::spawn(crash::scope(async move {
                         let mut consensus_guard = sc_arc_strong.lock().await;
                         consensus_guard.handle_snapshot_timeout().await;
-                    });
+                    }));
                 } else {
                     warn!("Snapshot timer fired but Consensus Arc was dropped.");
                 }
@@ -223,7 +479,47 @@ impl Consensus {
         );
         drop(snapshot_timer_guard); // 显式释放 guard
 
-        consensus_arc
+        Ok(consensus_arc)
+    }
+
+    // 启动恢复：状态机报告的 applied_index（或快照）之前的条目已经应用过，之后直到持久化的
+    // 提交索引为止的条目已提交但可能没有应用，在这里逐条补齐。
+    // 配置条目在确定初始配置时已经生效，这里只应用数据
+    async fn recover_committed_entries(&mut self, persisted_commit_index: u64, state_machine_applied: Option<u64>) {
+        let last_log_index = self.log.last_index(self.snapshot.last_included_index());
+        if let Some(applied) = state_machine_applied {
+            if applied > last_log_index {
+                error!("State machine reports applied index {} beyond the last log index {}, the log may have been lost.", applied, last_log_index);
+            } else if applied > self.last_applied {
+                self.set_last_applied(applied);
+            }
+        }
+
+        // 状态机拒绝了快照时，快照覆盖的条目无法补齐，应用停在快照之前
+        let recover_to = if self.incompatible_snapshot.is_some() { self.last_applied } else { persisted_commit_index.min(last_log_index) };
+        if recover_to > self.last_applied {
+            info!("Startup recovery: applying committed entries [{}, {}] to the state machine.", self.last_applied + 1, recover_to);
+        }
+        for index in (self.last_applied + 1)..=recover_to {
+            let Some(entry) = self.log.entry(index).cloned() else {
+                error!("Startup recovery: entry {} not found in log, stopping at {}.", index, self.last_applied);
+                self.mark_log_gap(index);
+                break;
+            };
+            match codec::entry_type(&entry) {
+                Ok(proto::EntryType::Data) => {
+                    self.chunk_assembler.discard(index);
+                    self.apply_data(&entry, &entry.data);
+                }
+                Ok(proto::EntryType::DataChunk) => self.apply_chunk(&entry),
+                Ok(proto::EntryType::Configuration | proto::EntryType::Noop) => self.chunk_assembler.discard(index),
+                Err(e) => self.skip_unknown_entry(index, entry.entry_type, &e),
+            }
+            self.set_last_applied(index);
+        }
+        self.commit_index = self.commit_index.max(self.last_applied);
+        self.metadata.update_commit_index(self.commit_index).await;
+        self.publish_view();
     }
 
     fn update_peer_config_states(&mut self) {
@@ -231,6 +527,234 @@ impl Consensus {
         for peer_in_manager in self.peer_manager.peers_mut().iter_mut() {
             peer_in_manager.config_state = self.current_config.get_node_state(peer_in_manager.id);
         }
+        self.publish_view();
+    }
+
+    fn set_leader_id(&mut self, leader_id: u64) {
+        if self.leader_id != leader_id {
+            self.leader_id = leader_id;
+            self.publish_view();
+        }
+    }
+
+    pub fn subscribe_view(&self) -> watch::Receiver<StateView> {
+        self.view_tx.subscribe()
+    }
+
+    // 刷新崩溃现场中的状态摘要，并记下一次日志操作；没有开启崩溃转储时不会格式化 op。
+    // 复制进度按 CRASH_DUMP_PEERS_REFRESH_INTERVAL 限频刷新，其余字段每次都更新
+    fn record_log_op(&self, op: impl FnOnce() -> String) {
+        let Some(recorder) = &self.crash_recorder else { return };
+        self.update_crash_report(recorder.peers_due());
+        recorder.record_log_op(op());
+    }
+
+    pub fn refresh_crash_report(&self) {
+        self.update_crash_report(true);
+    }
+
+    fn update_crash_report(&self, with_peers: bool) {
+        let Some(recorder) = &self.crash_recorder else { return };
+        let last_included_index = self.snapshot.last_included_index();
+        let peers = with_peers.then(|| self.peer_manager.peers().iter()
+            .map(|p| crash::PeerProgress { server_id: p.id, next_index: p.next_index, match_index: p.match_index })
+            .collect());
+        recorder.update(|report| {
+            report.term = self.metadata.current_term();
+            report.role = Some(self.state);
+            report.commit_index = self.commit_index;
+            report.last_applied = self.last_applied;
+            report.log_start_index = self.log.start_index();
+            report.log_last_index = self.log.last_index(last_included_index);
+            report.snapshot_last_included_index = last_included_index;
+            if let Some(peers) = peers {
+                report.peers = peers;
+            }
+        });
+    }
+
+    // 只在视图确实变化时通知订阅者
+    fn publish_view(&self) {
+        let view = StateView {
+            state: self.state,
+            leader: self.leader_info(),
+            servers: self.current_config.all_servers_in_config(),
+            health: self.health(),
+            term: self.metadata.current_term(),
+            commit_index: self.commit_index,
+            algorithm: self.current_config.algorithm,
+        };
+        self.view_tx.send_if_modified(|current| {
+            if *current == view {
+                return false;
+            }
+            *current = view;
+            true
+        });
+    }
+
+    pub fn health(&self) -> proto::NodeHealth {
+        if self.log_gap.is_some() {
+            proto::NodeHealth::LogGap
+        } else if self.incompatible_snapshot.is_some() {
+            proto::NodeHealth::IncompatibleSnapshot
+        } else if self.config_mismatch.is_some() {
+            proto::NodeHealth::ConfigMismatch
+        } else {
+            proto::NodeHealth::Ok
+        }
+    }
+
+    // 检查已提交的配置中本节点的地址是否就是 server_addr。地址不一致（例如改了端口重启）时其他节点会一直连接错误的地址：
+    // 把节点标记为 ConfigMismatch；本节点是 Leader 时再发起一次配置变更，把配置中的地址改成实际的地址。
+    // 地址中有主机名时在后台解析，不在持有锁时做 DNS 查询
+    fn check_advertised_address(&mut self) {
+        let recorded = self.current_config.all_servers_in_config().into_iter()
+            .find(|s| s.server_id == self.server_id)
+            .map(|s| s.server_addr);
+        let Some(recorded) = recorded else {
+            self.record_address_check(None, util::AddressMatch::Same);
+            return;
+        };
+        if let Some(result) = util::compare_literal_addresses(&recorded, &self.server_addr) {
+            self.record_address_check(Some(recorded), result);
+            return;
+        }
+        let Some(consensus) = self.self_ref.upgrade() else {
+            return;
+        };
+        let actual = self.server_addr.clone();
+        tokio::spawn(crash::scope(async move {
+            let result = util::compare_addresses(&recorded, &actual).await;
+            let mut guard = consensus.lock().await;
+            // 解析期间配置或地址变了时，以后一次检查为准
+            let current = guard.current_config.all_servers_in_config().into_iter()
+                .find(|s| s.server_id == guard.server_id)
+                .map(|s| s.server_addr);
+            if current.as_deref() == Some(recorded.as_str()) && guard.server_addr == actual {
+                guard.record_address_check(Some(recorded), result);
+            }
+        }));
+    }
+
+    // 无法解析时只报告，不改变健康状态，也不修改配置
+    fn record_address_check(&mut self, recorded: Option<String>, result: util::AddressMatch) {
+        let mismatch = match result {
+            util::AddressMatch::Same => None,
+            util::AddressMatch::Different => recorded,
+            util::AddressMatch::Unresolved(e) => {
+                warn!("Cannot check the configured address of server {} against {}: {}", self.server_id, self.server_addr, e);
+                return;
+            }
+        };
+        if mismatch != self.config_mismatch {
+            match &mismatch {
+                Some(addr) => warn!("Configuration advertises server {} at {}, but this node is reachable at {}. Peers will dial the wrong address until the configuration is fixed.",
+                    self.server_id, addr, self.server_addr),
+                None => info!("Configuration address of server {} matches {} again.", self.server_id, self.server_addr),
+            }
+            self.config_mismatch = mismatch;
+            self.publish_view();
+        }
+        if self.config_mismatch.is_some() && self.state == State::Leader {
+            if util::is_wildcard_address(&self.server_addr) {
+                warn!("Server {} listens on the wildcard address {}, not writing it into the configuration; fix the address with SetConfiguration.",
+                    self.server_id, self.server_addr);
+                return;
+            }
+            self.schedule_address_fix();
+        }
+    }
+
+    // 在后台发起修正本节点地址的配置变更；有配置变更正在进行时放弃，变更完成后应用配置时会再次检查
+    fn schedule_address_fix(&self) {
+        let Some(consensus) = self.self_ref.upgrade() else {
+            return;
+        };
+        tokio::spawn(crash::scope(async move {
+            let mut guard = consensus.lock().await;
+            let pending_change = guard.log.last_configuration().is_some_and(|c| c != guard.current_config);
+            if guard.state != State::Leader || guard.config_mismatch.is_none() || !guard.current_config.is_stable() || pending_change {
+                return;
+            }
+            let (server_id, server_addr) = (guard.server_id, guard.server_addr.clone());
+            let new_servers = guard.current_config.new_servers.iter()
+                .map(|s| if s.server_id == server_id { proto::ServerInfo { server_addr: server_addr.clone(), ..s.clone() } } else { s.clone() })
+                .collect();
+            info!("Changing the configured address of server {} to {}.", server_id, server_addr);
+            let request = proto::SetConfigurationRequest { new_servers, wait_for_completion: false, algorithm: None };
+            if !guard.handle_set_configuration_rpc(&request).await.success {
+                warn!("Failed to start the configuration change fixing the address of server {}.", server_id);
+            }
+        }));
+    }
+
+    pub fn audit_filepath(&self) -> &str {
+        self.audit.filepath()
+    }
+
+    // 就绪检查：应用进度落后提交位置不超过 ready_max_apply_lag，并且没有正在接收或恢复的快照。
+    // 未就绪时返回原因，负载均衡器据此不把读请求路由到严重落后的副本
+    pub fn not_ready_reason(&self) -> Option<String> {
+        if let Some(reason) = &self.incompatible_snapshot {
+            return Some(format!("refused to restore snapshot: {}", reason));
+        }
+        if self.restore_progress.in_progress() {
+            return Some("restoring the state machine from a snapshot".to_string());
+        }
+        if let Some(transfer) = &self.snapshot_transfer {
+            return Some(format!("receiving snapshot ({}, {}) from leader {}",
+                transfer.last_included_index, transfer.last_included_term, transfer.leader_id));
+        }
+        let apply_lag = self.commit_index.saturating_sub(self.last_applied);
+        if apply_lag > self.ready_max_apply_lag {
+            return Some(format!("applied up to {}, {} entries behind commit index {} (allowed {})",
+                self.last_applied, apply_lag, self.commit_index, self.ready_max_apply_lag));
+        }
+        None
+    }
+
+    fn leader_info(&self) -> Option<proto::ServerInfo> {
+        if self.leader_id == config::NONE_SERVER_ID {
+            return None;
+        }
+        if self.leader_id == self.server_id {
+            return Some(proto::ServerInfo {
+                server_id: self.server_id,
+                server_addr: self.server_addr.clone(),
+                zone: self.current_config.zone_of(self.server_id).map(str::to_string),
+            });
+        }
+        self.peer_manager.peers().iter()
+            .find(|p| p.id == self.leader_id)
+            .map(|p| proto::ServerInfo { server_id: p.id, server_addr: p.addr.clone(), zone: self.current_config.zone_of(p.id).map(str::to_string) })
+    }
+
+
+    fn update_peer_addrs(&mut self, config_to_apply: &config::Config) {
+        for server_info in config_to_apply.all_servers_in_config() {
+            if server_info.server_id == self.server_id {
+                continue;
+            }
+            let old_addr = self.peer_manager.peers().iter()
+                .find(|p| p.id == server_info.server_id)
+                .map(|p| p.addr.clone());
+            if self.peer_manager.update_addr(server_info.server_id, &server_info.server_addr) {
+                info!("Peer {} address changed from {:?} to {}", server_info.server_id, old_addr, server_info.server_addr);
+            }
+        }
+    }
+
+    // 把 new_servers 中还不是 Peer 的节点加入复制，从当前最后一条日志开始
+    fn add_new_peers(&mut self, config_to_apply: &config::Config, phase: &str) {
+        let new_peers_to_add: Vec<_> = config_to_apply.new_servers.iter()
+            .filter(|s| s.server_id != self.server_id && !self.peer_manager.contains(s.server_id))
+            .map(|s| peer::Peer::new(s.server_id, s.server_addr.clone()))
+            .collect();
+        if !new_peers_to_add.is_empty() {
+            info!("Adding new peers for {}: {:?}", phase, new_peers_to_add.iter().map(|p| p.id).collect::<Vec<_>>());
+            self.peer_manager.add(new_peers_to_add, self.log.last_index(self.snapshot.last_included_index()));
+        }
     }
 
 
@@ -252,99 +776,152 @@ impl Consensus {
             self.leader_advance_commit_index().await;
             return;
         }
-        // Consider using futures::future::join_all for concurrent appends
+        // 先向正常节点复制并推进提交，慢节点放在最后，避免拖慢多数派的提交
+        let isolate_slow = self.track_peer_lag();
+        let now = self.clock.now();
+        let mut slow_peers = Vec::new();
         for peer_id in peer_server_ids {
-             self.append_one_entry_to_peer(peer_id, heartbeat).await;
+            let slow = isolate_slow && self.peer_manager.peer(peer_id).is_some_and(|p| p.lag.slow);
+            if slow {
+                slow_peers.push(peer_id);
+            } else {
+                self.append_one_entry_to_peer(peer_id, heartbeat, None).await;
+            }
         }
         self.leader_advance_commit_index().await;
-    }
-
-    async fn append_one_entry_to_peer(&mut self, peer_id: u64, heartbeat: bool) {
-        // Use a temporary variable to hold peer_addr to avoid borrowing issues
-        let peer_addr_opt = self.peer_manager.peer(peer_id).map(|p| p.addr.clone());
-
-        if peer_addr_opt.is_none() {
-            warn!("Peer {} not found in peer_manager when appending entries", peer_id);
+        if slow_peers.is_empty() {
             return;
         }
-        let peer_addr = peer_addr_opt.unwrap();
 
+        // 慢节点按更长的间隔批量发送、限制单批条目数；未到间隔时只在心跳轮次发送心跳
+        for peer_id in slow_peers {
+            let batch_due = self.peer_manager.peer(peer_id).is_some_and(|p| p.lag.batch_due(now));
+            if batch_due {
+                if let Some(peer) = self.peer_manager.peer(peer_id) {
+                    peer.lag.last_batch_at = Some(now);
+                }
+                self.append_one_entry_to_peer(peer_id, false, Some(config::SLOW_PEER_MAX_ENTRIES)).await;
+            } else if heartbeat {
+                self.append_one_entry_to_peer(peer_id, true, None).await;
+            }
+        }
+        self.leader_advance_commit_index().await;
+    }
+
+    // 偏差上界最大的 Peer 及其上界，没有任何采样时为 None
+    fn max_peer_clock_skew(&self) -> Option<(u64, u64)> {
+        self.peer_manager.peers().iter()
+            .filter_map(|p| p.clock_skew.bound_us().map(|bound| (p.id, bound)))
+            .max_by_key(|(_, bound)| *bound)
+    }
 
-        // MODIFIED: Added .await
-        let current_term = self.metadata.get().await.current_term;
-        let leader_commit_idx = self.commit_index;
-        let server_id = self.server_id;
+    // 读租约：没有配置偏差上限，或者所有 Peer 的偏差上界都在上限以内。
+    // 还没有采样的 Peer 不影响租约，Leader 联系得上多数派时多数派一定已经有了采样
+    fn clock_skew_within_bound(&self) -> bool {
+        match self.max_clock_skew {
+            Some(max) => self.max_peer_clock_skew().is_none_or(|(_, bound)| bound <= max.as_micros() as u64),
+            None => true,
+        }
+    }
 
+    // 每个心跳周期更新一次时钟偏差指标，偏差越过上限或恢复时记录日志并发布事件。
+    // 先丢弃过期的采样，长时间联系不上的 Peer 不再影响读租约
+    fn check_clock_skew(&mut self) {
+        let now = self.clock.now();
+        for peer in self.peer_manager.peers_mut().iter_mut() {
+            peer.clock_skew.expire(now, config::CLOCK_SKEW_SAMPLE_MAX_AGE);
+        }
+        let (peer_id, bound) = self.max_peer_clock_skew().unwrap_or((self.server_id, 0));
+        self.metrics.max_clock_skew_us.store(bound, std::sync::atomic::Ordering::Relaxed);
+        let Some(max) = self.max_clock_skew else { return };
+        let exceeded = !self.clock_skew_within_bound();
+        if exceeded == self.clock_skew_exceeded {
+            return;
+        }
+        self.clock_skew_exceeded = exceeded;
+        if exceeded {
+            warn!("Clock skew of peer {} may be up to {}us, beyond the bound {:?}. Read lease suspended, linearizable reads are refused.",
+                peer_id, bound, max);
+            self.metrics.lease_invalidations.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.events.publish(events::RaftEvent::ClockSkewExceeded { peer_id, skew_bound_us: bound });
+        } else {
+            info!("Clock skew is back within {:?} (largest bound {}us), read lease restored.", max, bound);
+            self.events.publish(events::RaftEvent::ClockSkewCleared { skew_bound_us: bound });
+        }
+    }
 
-        let (req_prev_log_index, req_prev_log_term, entries_to_send, needs_snapshot) = {
-            // Scoped borrow for peer_manager
-            let peer_opt = self.peer_manager.peer(peer_id);
-            if peer_opt.is_none() {
-                warn!("Peer {} disappeared before preparing AppendEntries", peer_id);
-                return;
+    // 每个心跳周期检查一次 Leader 最后一条日志与最慢投票节点之间的差距，持续超过阈值时告警
+    fn check_divergence(&mut self) {
+        let last_log_idx = self.log.last_index(self.snapshot.last_included_index());
+        let slowest = self.peer_manager.peers().iter()
+            .filter(|p| p.config_state.newing || p.config_state.olding)
+            .min_by_key(|p| p.match_index)
+            .map(|p| (p.id, last_log_idx.saturating_sub(p.match_index)));
+        let (peer_id, gap) = slowest.unwrap_or((self.server_id, 0));
+        self.metrics.voter_gap.store(gap, std::sync::atomic::Ordering::Relaxed);
+        let Some(alarm) = &self.divergence_alarm else { return };
+        let (over, raised) = &mut self.divergence;
+        if gap <= alarm.max_gap {
+            *over = 0;
+            if std::mem::take(raised) {
+                info!("Divergence alarm cleared: slowest voter is {} entries behind.", gap);
+                self.events.publish(events::RaftEvent::DivergenceCleared { gap });
             }
-            let peer_ref = peer_opt.unwrap();
-
-            let needs_snapshot_decision = !heartbeat && peer_ref.next_index < self.log.start_index();
+            return;
+        }
+        *over += 1;
+        if *over > alarm.heartbeats && !*raised {
+            *raised = true;
+            warn!("Divergence alarm: voter {} has been more than {} entries behind for {} heartbeats (gap {}).",
+                peer_id, alarm.max_gap, alarm.heartbeats, gap);
+            self.metrics.divergence_alarms.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.events.publish(events::RaftEvent::DivergenceAlarm { peer_id, gap });
+        }
+    }
 
-            if needs_snapshot_decision {
-                (0,0, Vec::new(), true)
+    // 采样每个 Peer 的落后量，返回是否可以降低慢节点的复制优先级
+    fn track_peer_lag(&mut self) -> bool {
+        let now = self.clock.now();
+        let last_log_idx = self.log.last_index(self.snapshot.last_included_index());
+        for peer in self.peer_manager.peers_mut() {
+            let lag = last_log_idx.saturating_sub(peer.match_index);
+            if !peer.lag.observe(lag, now) {
+                continue;
+            }
+            if peer.lag.slow {
+                warn!("Peer {} is falling behind persistently (lag {}), deprioritizing its replication.", peer.id, lag);
+                self.events.publish(events::RaftEvent::PeerSlow { peer_id: peer.id, lag });
             } else {
-                let entries = if heartbeat {
-                    Vec::new()
-                } else {
-                    self.log.pack_entries(peer_ref.next_index)
-                };
-
-                let prev_idx = peer_ref.next_index - 1;
-                let prev_term = self.log.prev_log_term(
-                    prev_idx,
-                    self.snapshot.last_included_index,
-                    self.snapshot.last_included_term,
-                );
-                (prev_idx, prev_term, entries, false)
+                info!("Peer {} is catching up (lag {}), restoring normal replication.", peer.id, lag);
+                self.events.publish(events::RaftEvent::PeerRecovered { peer_id: peer.id, lag });
             }
-        };
-
-
-        if needs_snapshot {
-            let next_idx_for_log = self.peer_manager.peer(peer_id).map_or(0, |p| p.next_index);
-            info!("Peer {} requires snapshot, next_index: {}, log_start_index: {}", peer_id, next_idx_for_log, self.log.start_index());
-            Box::pin(self.install_snapshot_to_peer(peer_id)).await;
-            return;
         }
+        // 去掉慢节点后不足多数派时，所有节点都必须走正常路径
+        self.peer_manager.quorum_without_slow(&self.node_config_state)
+    }
 
-        let req = proto::AppendEntriesRequest {
-            term: current_term,
-            leader_id: server_id,
-            prev_log_index: req_prev_log_index,
-            prev_log_term: req_prev_log_term,
-            entries: entries_to_send.clone(), // Clone here if entries_to_send is used later
-            leader_commit: leader_commit_idx,
+    async fn append_one_entry_to_peer(&mut self, peer_id: u64, heartbeat: bool, max_entries: Option<usize>) {
+        let (peer_addr, mut req) = match self.prepare_append_entries(peer_id, heartbeat).await {
+            AppendEntriesPlan::Skip => return,
+            AppendEntriesPlan::Snapshot => {
+                let next_idx_for_log = self.peer_manager.peer(peer_id).map_or(0, |p| p.next_index);
+                info!("Peer {} requires snapshot, next_index: {}, log_start_index: {}", peer_id, next_idx_for_log, self.log.start_index());
+                Box::pin(self.install_snapshot_to_peer(peer_id)).await;
+                return;
+            }
+            AppendEntriesPlan::Send(peer_addr, req) => (peer_addr, req),
         };
 
-        // `self.rpc_client` methods are `async`, so they need `.await`
-        // `rpc_client` should ideally not take `&mut self` if it's just making calls.
-        // Assuming `self.rpc_client.append_entries` takes `&self` or `&mut self.rpc_client` implicitly.
-        match Box::pin(self.rpc_client.append_entries(req.clone(), peer_addr.clone())).await { // req.clone() if needed by logging/error
+        if let Some(max_entries) = max_entries {
+            req.entries.truncate(max_entries);
+        }
+        // 只保留摘要用于处理响应，请求本身直接交给 RPC，不复制整批条目
+        let summary = rpc::AppendEntriesSummary::of(&req);
+        let sent_at = self.clock.now();
+        match Box::pin(self.rpc_client.append_entries(req, peer_addr.clone())).await {
             Ok(resp) => {
-                // MODIFIED: Added .await (though current_term is already fetched, ensure consistency if it could change)
-                if resp.term > self.metadata.get().await.current_term {
-                    Box::pin(self.step_down(resp.term)).await;
-                    return;
-                }
-                if let Some(peer_to_update) = self.peer_manager.peer(peer_id) {
-                    if resp.success {
-                        peer_to_update.match_index = req.prev_log_index + entries_to_send.len() as u64;
-                        peer_to_update.next_index = peer_to_update.match_index + 1;
-                    } else {
-                        if peer_to_update.next_index > 1 {
-                            peer_to_update.next_index -= 1;
-                        }
-                    }
-                } else {
-                    warn!("Peer {} disappeared before processing AppendEntries response", peer_id);
-                }
+                self.record_peer_rtt(peer_id, self.clock.now().saturating_duration_since(sent_at));
+                self.handle_append_entries_response(peer_id, &summary, &resp).await;
             }
             Err(e) => {
                 error!("AppendEntries RPC to peer {} ({}) failed: {}", peer_id, peer_addr, e);
@@ -352,124 +929,363 @@ impl Consensus {
         }
     }
 
+    // 构造发给 peer_id 的 AppendEntries 请求，或者判断该节点需要快照
+    async fn prepare_append_entries(&mut self, peer_id: u64, heartbeat: bool) -> AppendEntriesPlan {
+        // MODIFIED: Added .await
+        let current_term = self.metadata.get().await.current_term;
+        let leader_commit_idx = self.commit_index;
+        let server_id = self.server_id;
+        if self.partition.blocks(peer_id) {
+            return AppendEntriesPlan::Skip;
+        }
 
-    async fn install_snapshot_to_peer(&mut self, peer_id: u64) {
-        let peer_addr = match self.peer_manager.peer(peer_id) {
-            Some(p) => p.addr.clone(),
+        // Scoped borrow for peer_manager
+        let peer_ref = match self.peer_manager.peer(peer_id) {
+            Some(p) => p,
             None => {
-                warn!("Peer {} not found for install_snapshot", peer_id);
-                return;
+                warn!("Peer {} not found in peer_manager when appending entries", peer_id);
+                return AppendEntriesPlan::Skip;
             }
         };
 
-        let current_term = self.metadata.get().await.current_term;
-        let leader_id = self.server_id;
-        let snap_last_idx = self.snapshot.last_included_index;
-        let snap_last_term = self.snapshot.last_included_term;
+        if !heartbeat && (peer_ref.snapshot_required || peer_ref.next_index < self.log.start_index()) {
+            return AppendEntriesPlan::Snapshot;
+        }
 
-        let metadata_filepath_opt = self.snapshot.latest_metadata_filepath();
-        let snapshot_filepath_opt = self.snapshot.latest_snapshot_filepath();
+        let entries = if heartbeat {
+            Vec::new()
+        } else {
+            self.log.pack_entries(peer_ref.next_index)
+        };
 
+        let prev_idx = peer_ref.next_index.saturating_sub(1);
+        let prev_term = self.log.prev_log_term(
+            prev_idx,
+            self.snapshot.last_included_index(),
+            self.snapshot.last_included_term(),
+        );
 
-        if metadata_filepath_opt.is_none() || snapshot_filepath_opt.is_none() {
-            error!("Cannot install snapshot: snapshot files (metadata or data) not found.");
+        AppendEntriesPlan::Send(peer_ref.addr.clone(), proto::AppendEntriesRequest {
+            term: current_term,
+            leader_id: server_id,
+            prev_log_index: prev_idx,
+            prev_log_term: prev_term,
+            entries,
+            leader_commit: leader_commit_idx,
+            leader_time_us: Some(util::unix_micros()),
+        })
+    }
+
+    // 处理 peer_id 对 req 的 AppendEntries 响应，更新复制进度
+    async fn handle_append_entries_response(
+        &mut self,
+        peer_id: u64,
+        req: &rpc::AppendEntriesSummary,
+        resp: &proto::AppendEntriesResponse,
+    ) {
+        // MODIFIED: Added .await (though current_term is already fetched, ensure consistency if it could change)
+        let current_term = self.metadata.get().await.current_term;
+        if let Err(e) = sanity::check_term(current_term, resp.term) {
+            warn!("Ignoring AppendEntries response from peer {}: {}", peer_id, e);
             return;
         }
-        let metadata_filepath = metadata_filepath_opt.unwrap();
-        let snapshot_filepath = snapshot_filepath_opt.unwrap();
-
-        info!("Installing snapshot to peer {}: metadata {} (size {}), snapshot {} (size {})",
-            peer_id, metadata_filepath, std::fs::metadata(&metadata_filepath).map(|m| m.len()).unwrap_or(0),
-            snapshot_filepath, std::fs::metadata(&snapshot_filepath).map(|m| m.len()).unwrap_or(0));
-
-        let mut current_global_offset = 0;
-        // NOTE: File operations here are synchronous. For large files, consider spawn_blocking or tokio::fs.
-        if let Ok(mut meta_file) = std::fs::File::open(&metadata_filepath) {
-            let meta_size = meta_file.metadata().unwrap().len();
-            let mut local_offset = 0;
-            while local_offset < meta_size {
-                let chunk_len = std::cmp::min(config::SNAPSHOT_TRUNK_SIZE as u64, meta_size - local_offset) as usize;
-                let mut data = vec![0; chunk_len];
-                meta_file.seek(std::io::SeekFrom::Start(local_offset)).unwrap();
-                meta_file.read_exact(&mut data).unwrap();
-
-                let req_install_snap = proto::InstallSnapshotRequest { // Renamed
-                    term: current_term, leader_id,
-                    last_included_index: snap_last_idx, last_included_term: snap_last_term,
-                    offset: current_global_offset,
-                    data,
-                    snapshot_data_type: proto::SnapshotDataType::Metadata as i32,
-                    done: false,
-                };
-                match Box::pin(self.rpc_client.install_snapshot(req_install_snap, peer_addr.clone())).await {
-                    Ok(resp) => if resp.term > self.metadata.get().await.current_term { 
-                        Box::pin(self.step_down(resp.term)).await; 
-                        return; 
-                    }, // MODIFIED .await
-                    Err(e) => { error!("Error sending snapshot metadata to {}: {}", peer_id, e); return; }
+        if resp.term > current_term {
+            Box::pin(self.step_down(resp.term, audit::AuditReason::HigherTermSeen)).await;
+            return;
+        }
+        let last_log_index = self.log.last_index(self.snapshot.last_included_index());
+        let log_start_index = self.log.start_index();
+        if let Some(peer_to_update) = self.peer_manager.peer(peer_id) {
+            peer_to_update.last_contact = Some(self.clock.now());
+            if let (Some(sent_us), Some(remote_us)) = (resp.leader_time_us, resp.follower_time_us) {
+                peer_to_update.clock_skew.observe(sent_us, remote_us, util::unix_micros(), self.clock.now());
+            }
+            // Follower 应用的条目都已提交，一定在 Leader 的日志里
+            if resp.last_applied <= last_log_index {
+                peer_to_update.last_applied = resp.last_applied;
+            } else {
+                warn!("Peer {} reported last_applied {} beyond the leader's last log index {}, ignoring it.", peer_id, resp.last_applied, last_log_index);
+            }
+            if resp.success {
+                if !peer_to_update.advance_match_index(req.last_index()) {
+                    debug!("Ignoring stale AppendEntries success from peer {} at index {}, match_index stays at {}.",
+                        peer_id, req.last_index(), peer_to_update.match_index);
                 }
-                current_global_offset += chunk_len as u64;
-                local_offset += chunk_len as u64;
-            }
-        } else { error!("Could not open metadata file {}", metadata_filepath); return; }
-
-        // Send Snapshot Data Chunks
-        if let Ok(mut snap_file) = std::fs::File::open(&snapshot_filepath) {
-            let snap_size = snap_file.metadata().unwrap().len();
-            let mut local_offset = 0;
-            while local_offset < snap_size {
-                let chunk_len = std::cmp::min(config::SNAPSHOT_TRUNK_SIZE as u64, snap_size - local_offset) as usize;
-                let mut data = vec![0; chunk_len];
-                snap_file.seek(std::io::SeekFrom::Start(local_offset)).unwrap();
-                snap_file.read_exact(&mut data).unwrap();
-
-                let is_last_chunk_of_snapshot = (local_offset + chunk_len as u64) >= snap_size;
-                let req_install_snap_data = proto::InstallSnapshotRequest { // Renamed
-                    term: current_term, leader_id,
-                    last_included_index: snap_last_idx, last_included_term: snap_last_term,
-                    offset: current_global_offset,
-                    data,
-                    snapshot_data_type: proto::SnapshotDataType::Snapshot as i32,
-                    done: is_last_chunk_of_snapshot,
-                };
-
-                match self.rpc_client.install_snapshot(req_install_snap_data, peer_addr.clone()).await {
-                    Ok(resp) => {
-                        // MODIFIED: Added .await
-                        if resp.term > self.metadata.get().await.current_term { 
-                            Box::pin(self.step_down(resp.term)).await; 
-                            return; 
-                        }
-                        if is_last_chunk_of_snapshot {
-                            if let Some(p) = self.peer_manager.peer(peer_id) {
-                                p.next_index = snap_last_idx + 1;
-                                p.match_index = snap_last_idx;
-                                info!("Snapshot successfully installed on peer {}. next_index set to {}", peer_id, p.next_index);
-                            }
-                        }
-                    },
-                    Err(e) => { error!("Error sending snapshot data to {}: {}", peer_id, e); return; }
+                // 节点通过日志追上了，正在进行的快照传输不再需要
+                if let peer::ProgressState::Snapshot { last_included_index, .. } = peer_to_update.progress {
+                    info!("Peer {} caught up via log, cancelling snapshot (LII {}) transfer.", peer_id, last_included_index);
+                }
+                peer_to_update.progress = peer::ProgressState::Replicate;
+            } else {
+                if peer_to_update.probe_back(log_start_index) {
+                    debug!("Peer {} rejected the entry at the log start {}, falling back to a snapshot.", peer_id, log_start_index);
+                }
+                if !matches!(peer_to_update.progress, peer::ProgressState::Snapshot { .. }) {
+                    peer_to_update.progress = peer::ProgressState::Probe;
                 }
-                current_global_offset += chunk_len as u64;
-                local_offset += chunk_len as u64;
             }
-        } else { error!("Could not open snapshot data file {}", snapshot_filepath); return; }
+        } else {
+            warn!("Peer {} disappeared before processing AppendEntries response", peer_id);
+        }
+        self.check_invariants("AppendEntries response").await;
     }
 
-
-
-    async fn leader_advance_commit_index(&mut self) {
+    // 合并心跳：为所有 Peer 构造心跳请求，由 GroupRegistry 统一发送
+    pub async fn collect_heartbeats(&mut self) -> Vec<(u64, String, proto::AppendEntriesRequest)> {
         if self.state != State::Leader {
-            return;
+            return Vec::new();
         }
-        let new_commit_index = self.peer_manager.quoram_match_index(
-            &self.node_config_state,
-            self.log.last_index(self.snapshot.last_included_index),
-        );
-
-        if new_commit_index > self.commit_index {
-            // MODIFIED: Added .await
-            let current_term_val = self.metadata.get().await.current_term;
+        let peer_server_ids: Vec<u64> = self.peer_manager.peers().iter().map(|p| p.id).collect();
+        let mut heartbeats = Vec::with_capacity(peer_server_ids.len());
+        for peer_id in peer_server_ids {
+            if let AppendEntriesPlan::Send(peer_addr, req) = self.prepare_append_entries(peer_id, true).await {
+                heartbeats.push((peer_id, peer_addr, req));
+            }
+        }
+        heartbeats
+    }
+
+    // 合并心跳：处理 GroupRegistry 转交回来的心跳响应
+    pub async fn handle_heartbeat_response(
+        &mut self,
+        peer_id: u64,
+        req: &rpc::AppendEntriesSummary,
+        resp: &proto::AppendEntriesResponse,
+    ) {
+        if self.state != State::Leader || req.term != self.metadata.get().await.current_term {
+            // 发送心跳之后已经不是该任期的Leader，忽略过期的响应
+            return;
+        }
+        self.handle_append_entries_response(peer_id, req, resp).await;
+        self.leader_advance_commit_index().await;
+    }
+
+
+    // 发起快照传输，传输期间节点处于 ProgressState::Snapshot，避免重复发送同一个快照。
+    // 传输在后台任务中进行，只在发送每个分块前后短暂持有锁，传输期间节点照常处理心跳和其他请求
+    async fn install_snapshot_to_peer(&mut self, peer_id: u64) {
+        if self.partition.blocks(peer_id) {
+            return;
+        }
+        let snap_last_idx = self.snapshot.last_included_index();
+        let now = self.clock.now();
+        match self.peer_manager.peer(peer_id) {
+            Some(p) => {
+                if p.snapshot_in_flight(snap_last_idx, now, config::SNAPSHOT_TRANSFER_TIMEOUT) {
+                    info!("Snapshot (LII {}) transfer to peer {} is already in flight, skipping.", snap_last_idx, peer_id);
+                    return;
+                }
+                p.progress = peer::ProgressState::Snapshot { last_included_index: snap_last_idx, started_at: now };
+            }
+            None => {
+                warn!("Peer {} not found for install_snapshot", peer_id);
+                return;
+            }
+        }
+
+        let Some(transfer) = self.prepare_snapshot_transfer(peer_id).await else {
+            self.finish_snapshot_transfer_to(peer_id, snap_last_idx);
+            return;
+        };
+        let consensus = self.self_ref.clone();
+        let rpc_client = self.rpc_client.clone();
+        tokio::spawn(crash::scope(Self::transfer_snapshot_to_peer(consensus, rpc_client, transfer)));
+    }
+
+    // 传输失败或中途终止时回到 Probe，下次复制时重新判断是否需要快照
+    fn finish_snapshot_transfer_to(&mut self, peer_id: u64, snap_last_idx: u64) {
+        if let Some(p) = self.peer_manager.peer(peer_id) {
+            if matches!(p.progress, peer::ProgressState::Snapshot { last_included_index, .. } if last_included_index == snap_last_idx) {
+                p.progress = peer::ProgressState::Probe;
+            }
+        }
+    }
+
+    // 节点已经不再等待这个快照(通过日志追上或者有了更新的快照)，或者本节点已经不是发起传输时那个任期的 Leader 时，
+    // 终止正在进行的传输
+    fn snapshot_transfer_cancelled(&mut self, transfer: &SnapshotTransfer) -> bool {
+        if self.state != State::Leader || self.metadata.current_term() != transfer.term {
+            return true;
+        }
+        match self.peer_manager.peer(transfer.peer_id) {
+            Some(p) => !matches!(p.progress, peer::ProgressState::Snapshot { last_included_index, .. } if last_included_index == transfer.last_included_index),
+            None => true,
+        }
+    }
+
+    async fn prepare_snapshot_transfer(&mut self, peer_id: u64) -> Option<SnapshotTransfer> {
+        let peer_addr = match self.peer_manager.peer(peer_id) {
+            Some(p) => p.addr.clone(),
+            None => {
+                warn!("Peer {} not found for install_snapshot", peer_id);
+                return None;
+            }
+        };
+
+        let metadata_filepath_opt = self.snapshot.latest_metadata_filepath();
+        let snapshot_filepath_opt = self.snapshot.latest_snapshot_filepath();
+
+
+        if metadata_filepath_opt.is_none() || snapshot_filepath_opt.is_none() {
+            error!("Cannot install snapshot: snapshot files (metadata or data) not found.");
+            return None;
+        }
+        let metadata_filepath = metadata_filepath_opt.unwrap();
+        let snapshot_filepath = snapshot_filepath_opt.unwrap();
+
+        info!("Installing snapshot to peer {}: metadata {} (size {}), snapshot {} (size {})",
+            peer_id, metadata_filepath, std::fs::metadata(&metadata_filepath).map(|m| m.len()).unwrap_or(0),
+            snapshot_filepath, std::fs::metadata(&snapshot_filepath).map(|m| m.len()).unwrap_or(0));
+
+        Some(SnapshotTransfer {
+            peer_id,
+            peer_addr,
+            term: self.metadata.get().await.current_term,
+            leader_id: self.server_id,
+            last_included_index: self.snapshot.last_included_index(),
+            last_included_term: self.snapshot.last_included_term(),
+            checksum: self.snapshot.meta().checksum,
+            metadata_filepath,
+            snapshot_filepath,
+            started_at: self.clock.now(),
+        })
+    }
+
+    // 先发送元数据文件，再发送快照数据文件，两个文件各自从偏移 0 开始
+    async fn transfer_snapshot_to_peer(consensus: Weak<TokioMutex<Consensus>>, mut rpc_client: rpc::Client, transfer: SnapshotTransfer) {
+        let sent = async {
+            let metadata_bytes = Self::send_snapshot_file(&consensus, &mut rpc_client, &transfer, &transfer.metadata_filepath, proto::SnapshotDataType::Metadata).await?;
+            let snapshot_bytes = Self::send_snapshot_file(&consensus, &mut rpc_client, &transfer, &transfer.snapshot_filepath, proto::SnapshotDataType::Snapshot).await?;
+            Some(metadata_bytes + snapshot_bytes)
+        }.await;
+        let Some(consensus) = consensus.upgrade() else { return };
+        let mut guard = consensus.lock().await;
+        let (peer_id, snap_last_idx) = (transfer.peer_id, transfer.last_included_index);
+        match sent {
+            Some(sent_bytes) if !guard.snapshot_transfer_cancelled(&transfer) => {
+                let elapsed = guard.clock.now().saturating_duration_since(transfer.started_at);
+                guard.metrics.record_snapshot_sent(sent_bytes, elapsed);
+                info!("Sent snapshot (LII {}) to peer {}: {} bytes in {:?}.", snap_last_idx, peer_id, sent_bytes, elapsed);
+                if let Some(p) = guard.peer_manager.peer(peer_id) {
+                    p.advance_match_index(snap_last_idx);
+                    p.progress = peer::ProgressState::Replicate;
+                    info!("Snapshot successfully installed on peer {}. next_index set to {}", peer_id, p.next_index);
+                }
+            }
+            _ => guard.finish_snapshot_transfer_to(peer_id, snap_last_idx),
+        }
+    }
+
+    // 按顺序分块发送一个快照文件，offset 为文件内偏移；返回发送的字节数，None 表示传输中止。
+    // 分块由后台任务预先读取，等待 Follower 响应的同时读取后面的分块；等待响应时不持有锁
+    async fn send_snapshot_file(
+        consensus: &Weak<TokioMutex<Consensus>>,
+        rpc_client: &mut rpc::Client,
+        transfer: &SnapshotTransfer,
+        filepath: &str,
+        data_type: proto::SnapshotDataType,
+    ) -> Option<u64> {
+        let peer_id = transfer.peer_id;
+        let expected_checksum = match data_type {
+            proto::SnapshotDataType::Snapshot => transfer.checksum,
+            _ => 0,
+        };
+
+        // 空文件也会读出一个分块，让 Follower 知道该文件已经传输完成
+        let mut chunks = snapshot::spawn_chunk_reader(filepath.to_string(), config::SNAPSHOT_TRUNK_SIZE, config::SNAPSHOT_SEND_PIPELINE_DEPTH);
+        while let Some(chunk) = chunks.recv().await {
+            let consensus = consensus.upgrade()?;
+            if consensus.lock().await.snapshot_transfer_cancelled(transfer) {
+                info!("Snapshot transfer to peer {} cancelled.", peer_id);
+                return None;
+            }
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    error!("Failed to read snapshot file {}: {}", filepath, e);
+                    return None;
+                }
+            };
+            let is_last_chunk = chunk.is_last();
+            // 读完整个文件时校验和已经确定，与元数据不一致时不发送最后一个分块，Follower 不会安装损坏的快照
+            if is_last_chunk && expected_checksum != 0 && chunk.checksum != expected_checksum {
+                error!("Snapshot file {} does not match the checksum in its metadata ({:x} != {:x}), aborting transfer to peer {}.",
+                    filepath, chunk.checksum, expected_checksum, peer_id);
+                return None;
+            }
+
+            let (offset, chunk_len, total_size) = (chunk.offset, chunk.data.len() as u64, chunk.total_size);
+            let req = proto::InstallSnapshotRequest {
+                term: transfer.term,
+                leader_id: transfer.leader_id,
+                last_included_index: transfer.last_included_index,
+                last_included_term: transfer.last_included_term,
+                offset,
+                data: chunk.data,
+                snapshot_data_type: data_type as i32,
+                done: is_last_chunk && data_type == proto::SnapshotDataType::Snapshot,
+                total_size,
+            };
+
+            let result = Box::pin(rpc_client.install_snapshot(req, transfer.peer_addr.clone())).await;
+            let mut guard = consensus.lock().await;
+            match result {
+                Ok(resp) => {
+                    let current_term = guard.metadata.get().await.current_term;
+                    if let Err(e) = sanity::check_term(current_term, resp.term) {
+                        warn!("Ignoring InstallSnapshot response from peer {}: {}", peer_id, e);
+                        return None;
+                    }
+                    if resp.term > current_term {
+                        Box::pin(guard.step_down(resp.term, audit::AuditReason::HigherTermSeen)).await;
+                        return None;
+                    }
+                    let now = guard.clock.now();
+                    if let Some(p) = guard.peer_manager.peer(peer_id) {
+                        p.last_contact = Some(now);
+                    }
+                    if !resp.success {
+                        // Follower 拒绝了乱序或不匹配的分块，放弃本次传输，下次复制时从头重传
+                        warn!("Peer {} rejected {:?} chunk at offset {} (expected {}), restarting snapshot transfer later.",
+                            peer_id, data_type, offset, resp.next_offset);
+                        return None;
+                    }
+                }
+                Err(e) => {
+                    error!("Error sending snapshot {:?} chunk to {}: {}", data_type, peer_id, e);
+                    return None;
+                }
+            }
+            guard.metrics.snapshot_bytes_sent.fetch_add(chunk_len, std::sync::atomic::Ordering::Relaxed);
+
+            if is_last_chunk {
+                return Some(total_size);
+            }
+        }
+        error!("Snapshot file {} ended before its last chunk was read.", filepath);
+        None
+    }
+
+
+
+    async fn leader_advance_commit_index(&mut self) {
+        if self.state != State::Leader {
+            return;
+        }
+        let last_log_index = self.log.last_index(self.snapshot.last_included_index());
+        let Some(new_commit_index) = self.peer_manager.quoram_match_index(&self.node_config_state, last_log_index) else {
+            warn!("Leader cannot advance commit_index: neither the old nor the new configuration has any members ({:?}).", self.node_config_state);
+            return;
+        };
+        // 多数派的 match_index 不可能超过 Leader 自己的日志，出现时说明状态已经不一致，拒绝推进
+        if new_commit_index > last_log_index {
+            error!("Refusing to advance commit_index to {}: beyond the leader's last log index {}.", new_commit_index, last_log_index);
+            return;
+        }
+
+        if new_commit_index > self.commit_index {
+            // MODIFIED: Added .await
+            let current_term_val = self.metadata.get().await.current_term;
             if let Some(entry_to_check) = self.log.entry(new_commit_index) {
                 if entry_to_check.term != current_term_val {
                     debug!("Leader cannot advance commit_index to {} because its term {} is not current term {}",
@@ -477,7 +1293,7 @@ impl Consensus {
                     return;
                 }
             } else {
-                if new_commit_index <= self.snapshot.last_included_index {
+                if new_commit_index <= self.snapshot.last_included_index() {
                     // fine
                 } else {
                     warn!("Leader wants to advance commit_index to {} but entry not found in log.", new_commit_index);
@@ -490,47 +1306,47 @@ impl Consensus {
                 self.commit_index, new_commit_index
             );
 
-            for index_to_apply in (self.commit_index + 1)..=new_commit_index {
-                if index_to_apply <= self.last_applied {
-                    continue;
-                }
-                if let Some(entry) = self.log.entry(index_to_apply) {
-                    let entry_data = entry.data.clone();
-                    let entry_type_val = proto::EntryType::from_i32(entry.entry_type).unwrap_or(proto::EntryType::Data);
-
-                    match entry_type_val {
-                        proto::EntryType::Data => {
-                            debug!("Leader applying data entry to state machine: index {}", entry.index);
-                            self.state_machine.apply(&entry_data);
-                        }
-                        proto::EntryType::Configuration => {
-                            info!("Leader applying configuration entry to state machine (committing): index {}", entry.index);
-                            let committed_config = config::Config::from_data(&entry_data);
-                            self.apply_configuration_to_internal_state(committed_config.clone(), true).await;
-
-                            if committed_config.is_joint() {
-                                info!("Committed C(old,new) config. Leader replicating C(new). Config: {:?}", committed_config);
-                                self.append_and_replicate_final_config().await;
-                            }
-                        }
-                        proto::EntryType::Noop => {
-                            debug!("Leader applying NOOP entry: index {}", entry.index);
-                        }
+            // 统计本次提交的条目从追加到提交的延迟，应用完成后再统计到应用的延迟
+            let committed_at = self.clock.now();
+            while self.append_times.front().is_some_and(|(index, _, _, _)| *index <= new_commit_index) {
+                let (index, entry_type, appended_at, bytes) = self.append_times.pop_front().unwrap();
+                self.uncommitted_bytes = self.uncommitted_bytes.saturating_sub(bytes);
+                let latency = committed_at.saturating_duration_since(appended_at);
+                self.metrics.record_commit_latency(entry_type, latency);
+                if matches!(entry_type, proto::EntryType::Data | proto::EntryType::DataChunk) {
+                    if let Some(shedder) = &mut self.load_shedder {
+                        shedder.observe(latency, committed_at);
                     }
-                    self.last_applied = index_to_apply;
-                } else {
-                    error!("Entry {} not found in log for leader application, though commit_index advanced.", index_to_apply);
-                    break;
                 }
+                self.applying.push_back((index, entry_type, appended_at));
             }
-            self.commit_index = new_commit_index;
+
+            let previous_commit_index = std::mem::replace(&mut self.commit_index, new_commit_index);
+            self.record_log_op(|| format!("leader commit {} -> {}", previous_commit_index, new_commit_index));
+            self.metadata.update_commit_index(self.commit_index).await;
+            self.publish_view();
+            self.record_peer_match_hints().await;
+            self.apply_committed_entries().await;
+            self.check_invariants("leader commit").await;
         }
     }
 
+    // 记录当前配置中各节点的 match_index；本任期还没有确认过的节点沿用之前的提示
+    async fn record_peer_match_hints(&mut self) {
+        let previous = self.metadata.get().await.peer_match_hints;
+        let hints = self.peer_manager.peers().iter()
+            .filter_map(|p| {
+                let hint = if p.match_index > 0 { Some(p.match_index) } else { previous.get(&p.id).copied() };
+                hint.map(|hint| (p.id, hint))
+            })
+            .collect();
+        self.metadata.update_peer_match_hints(hints).await;
+    }
+
     async fn follower_advance_commit_index(&mut self, leader_commit_index: u64) {
         let new_commit_index = std::cmp::min(
             leader_commit_index,
-            self.log.last_index(self.snapshot.last_included_index)
+            self.log.last_index(self.snapshot.last_included_index())
         );
 
         if new_commit_index > self.commit_index {
@@ -539,56 +1355,260 @@ impl Consensus {
                 self.commit_index, new_commit_index, leader_commit_index
             );
 
-            for index_to_apply in (self.commit_index + 1)..=new_commit_index {
-                if index_to_apply <= self.last_applied {
-                    continue;
+            let previous_commit_index = std::mem::replace(&mut self.commit_index, new_commit_index);
+            self.record_log_op(|| format!("follower commit {} -> {}", previous_commit_index, new_commit_index));
+            self.metadata.update_commit_index(self.commit_index).await;
+            self.publish_view();
+            self.apply_committed_entries().await;
+            self.check_invariants("follower commit").await;
+        }
+    }
+
+    // 应用一批已提交的条目，应用了新条目时检查是否需要生成快照
+    async fn apply_committed_entries(&mut self) {
+        let applied_before = self.last_applied;
+        self.apply_committed_batch().await;
+        if self.last_applied > applied_before {
+            self.maybe_snapshot_after_apply();
+        }
+    }
+
+    // 把 (last_applied, commit_index] 范围内的条目应用到状态机。每次最多应用 APPLY_BATCH_MAX_ENTRIES 条
+    // 或持续 APPLY_BATCH_MAX_DURATION，剩下的交给后台任务分批应用，批次之间释放锁，
+    // 这样大量条目同时提交时（例如分区恢复后）节点仍能及时处理心跳和其他请求
+    async fn apply_committed_batch(&mut self) {
+        // 状态机还在阻塞线程中恢复（等待恢复的调用被取消了），放回之后再应用
+        if self.restore_progress.in_progress() {
+            return;
+        }
+        let role = if self.state == State::Leader { "Leader" } else { "Follower" };
+        let started_at = StdInstant::now();
+        let first_index = self.last_applied + 1;
+        let mut applied = 0;
+        while self.last_applied < self.commit_index {
+            if applied >= config::APPLY_BATCH_MAX_ENTRIES || started_at.elapsed() >= config::APPLY_BATCH_MAX_DURATION {
+                debug!("{} applied entries [{}, {}] in {:?}, {} more to apply in the background.",
+                    role, first_index, self.last_applied, started_at.elapsed(), self.commit_index - self.last_applied);
+                self.schedule_apply(Duration::ZERO);
+                return;
+            }
+            let index_to_apply = self.last_applied + 1;
+            let entry = match self.committed_entry(index_to_apply).await {
+                CommittedEntry::Found(entry) => entry,
+                CommittedEntry::RestoredFromSnapshot => continue,
+                CommittedEntry::Missing => return,
+            };
+            if let Some(wait) = self.apply_pacing_delay(entry.data.len() as u64) {
+                debug!("{} pausing apply at index {} for {:?} due to the apply rate limit, {} entries to apply.",
+                    role, index_to_apply, wait, self.commit_index - self.last_applied);
+                self.metrics.apply_throttled.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                self.schedule_apply(wait);
+                return;
+            }
+            if self.commit_sink.as_ref().is_some_and(|dispatcher| !dispatcher.has_capacity()) {
+                debug!("{} pausing apply at index {}: commit sink queue is full.", role, index_to_apply);
+                self.schedule_apply(config::COMMIT_SINK_BACKPRESSURE_DELAY);
+                return;
+            }
+            let (term, entry_data, raw_type) = (entry.term, entry.data.clone(), entry.entry_type);
+            let entry_type_val = codec::entry_type(&entry);
+
+            if entry_type_val != Ok(proto::EntryType::DataChunk) {
+                self.chunk_assembler.discard(index_to_apply);
+            }
+            match entry_type_val {
+                Ok(proto::EntryType::Data) => {
+                    debug!("{} applying data entry to state machine: index {}", role, index_to_apply);
+                    let result = self.apply_data(&entry, &entry.data);
+                    self.notify_apply_waiter(index_to_apply, term, result);
+                    self.set_last_applied(index_to_apply);
                 }
-                if let Some(entry) = self.log.entry(index_to_apply) {
-                    let entry_data = entry.data.clone();
-                    let entry_type_val = proto::EntryType::from_i32(entry.entry_type).unwrap_or(proto::EntryType::Data);
-
-                    match entry_type_val {
-                        proto::EntryType::Data => {
-                            debug!("Follower applying data entry to state machine: index {}", entry.index);
-                            self.state_machine.apply(&entry_data);
-                        }
-                        proto::EntryType::Configuration => {
-                             info!("Follower applying configuration entry to state machine (committing): index {}", entry.index);
-                            let committed_config = config::Config::from_data(&entry_data);
-                            self.apply_configuration_to_internal_state(committed_config, true).await;
-                        }
-                        proto::EntryType::Noop => {
-                             debug!("Follower applying NOOP entry: index {}", entry.index);
+                Ok(proto::EntryType::DataChunk) => {
+                    debug!("{} applying data chunk: index {}", role, index_to_apply);
+                    self.apply_chunk(&entry);
+                    self.set_last_applied(index_to_apply);
+                }
+                Ok(proto::EntryType::Configuration) => {
+                    info!("{} applying configuration entry to state machine (committing): index {}", role, index_to_apply);
+                    // 应用 C(old,new) 时 Leader 会追加并复制 C(new)，期间可能再次进入这里，先标记为已应用
+                    self.set_last_applied(index_to_apply);
+                    // 追加时已经校验过，这里只会遇到磁盘上损坏的条目
+                    let committed_config = match config::Config::try_from_data(&entry_data) {
+                        Ok(committed_config) => committed_config,
+                        Err(e) => {
+                            self.skip_unknown_entry(index_to_apply, raw_type, &e);
+                            applied += 1;
+                            continue;
                         }
+                    };
+                    let source = if self.state == State::Leader { self.server_id } else { self.leader_id };
+                    self.config_history.record(index_to_apply, term, &committed_config, source);
+                    self.apply_configuration_to_internal_state(index_to_apply, committed_config.clone(), true).await;
+
+                    if committed_config.is_joint() && self.state == State::Leader {
+                        info!("Committed C(old,new) config. Leader replicating C(new). Config: {:?}", committed_config);
+                        self.append_and_replicate_final_config().await;
                     }
-                    self.last_applied = index_to_apply;
-                } else {
-                    error!("Entry {} not found in log for follower application. Breaking. Leader commit: {}", index_to_apply, leader_commit_index);
+                }
+                Ok(proto::EntryType::Noop) => {
+                    debug!("{} applying NOOP entry: index {}", role, index_to_apply);
+                    self.set_last_applied(index_to_apply);
+                }
+                Err(e) => {
+                    self.skip_unknown_entry(index_to_apply, raw_type, &e);
+                    self.set_last_applied(index_to_apply);
+                }
+            }
+            while let Some((index, entry_type, appended_at)) = self.applying.front().copied() {
+                if index > index_to_apply {
                     break;
                 }
+                self.applying.pop_front();
+                if index == index_to_apply {
+                    self.metrics.record_apply_latency(entry_type, self.clock.now().saturating_duration_since(appended_at));
+                }
+            }
+            applied += 1;
+        }
+    }
+
+    // 取出要应用的已提交条目。内存中找不到时按以下顺序恢复，而不是跳过或停在原地：
+    // 1. 条目已被快照覆盖（日志压缩越过了 last_applied）：从快照恢复状态机，last_applied 跳到快照的位置
+    // 2. 磁盘上的日志文件中还有该条目：使用磁盘上的条目
+    // 3. 都没有：标记节点为 LogGap 并停止应用，已提交的条目不能跳过，否则状态机会和其他节点分叉
+    async fn committed_entry(&mut self, index: u64) -> CommittedEntry {
+        let last_included_index = self.snapshot.last_included_index();
+        if index <= last_included_index {
+            let Some(snapshot_filepath) = self.snapshot.latest_snapshot_filepath() else {
+                error!("Entry {} is covered by snapshot (LII {}) but the snapshot file is missing.", index, last_included_index);
+                self.mark_log_gap(index);
+                return CommittedEntry::Missing;
+            };
+            warn!("Entry {} was compacted into snapshot (LII {}) before being applied, restoring the state machine from {}.",
+                index, last_included_index, snapshot_filepath);
+            if !self.restore_state_machine(&snapshot_filepath).await {
+                return CommittedEntry::Missing;
+            }
+            self.set_last_applied(last_included_index);
+            return CommittedEntry::RestoredFromSnapshot;
+        }
+        if let Some(entry) = self.log.entry(index) {
+            return CommittedEntry::Found(entry.clone());
+        }
+        match self.log.read_entry_from_disk(index) {
+            Some(entry) => {
+                warn!("Entry {} is missing from the in-memory log, using the copy from the log file.", index);
+                CommittedEntry::Found(entry)
+            }
+            None => {
+                error!("Committed entry {} is missing from both the in-memory log and the log file (commit_index {}), stopping apply.", index, self.commit_index);
+                self.mark_log_gap(index);
+                CommittedEntry::Missing
             }
-            self.commit_index = self.last_applied;
         }
     }
 
-    async fn apply_configuration_to_internal_state(&mut self, config_to_apply: config::Config, committed: bool) { // Renamed `config` to avoid conflict
+    fn mark_log_gap(&mut self, index: u64) {
+        if self.log_gap != Some(index) {
+            self.log_gap = Some(index);
+            self.publish_view();
+        }
+    }
+
+    // Follower 按 apply_pacer 限速，令牌不足时返回需要等待的时间
+    fn apply_pacing_delay(&mut self, bytes: u64) -> Option<Duration> {
+        if self.state == State::Leader {
+            return None;
+        }
+        let now = self.clock.now();
+        self.apply_pacer.as_mut()?.try_take(bytes, now)
+    }
+
+    // 运行中调整应用限速，None 表示不再限制。新的限速从满的令牌桶开始，
+    // 正在等待令牌的条目按新的限速立即重试，不必等到旧的暂停结束
+    pub async fn set_apply_rate_limit(&mut self, limit: Option<config::ApplyRateLimit>) {
+        if self.apply_pacer.as_ref().map(|pacer| pacer.limit()) == limit.as_ref() {
+            return;
+        }
+        info!("Node {} apply rate limit changed to {:?}", self.server_id, limit);
+        self.apply_pacer = limit.map(|limit| rate_limit::ApplyPacer::new(limit, self.clock.now()));
+        if self.apply_scheduled {
+            // 取消按旧限速等待的任务，否则重新调度会因为已有任务而被忽略
+            self.apply_generation += 1;
+            self.apply_scheduled = false;
+            self.apply_committed_entries().await;
+        }
+    }
+
+    // 启动后台任务在 delay 之后继续应用剩下的条目，同一时间最多一个
+    fn schedule_apply(&mut self, delay: Duration) {
+        if self.apply_scheduled {
+            return;
+        }
+        let Some(consensus) = self.self_ref.upgrade() else {
+            return;
+        };
+        self.apply_scheduled = true;
+        let generation = self.apply_generation;
+        let (clock, deadline) = (Arc::clone(&self.clock), self.clock.now() + delay);
+        tokio::spawn(crash::scope(async move {
+            if delay.is_zero() {
+                tokio::task::yield_now().await;
+            } else {
+                clock.sleep_until(deadline).await;
+            }
+            let mut guard = consensus.lock().await;
+            if guard.apply_generation != generation {
+                return;
+            }
+            guard.apply_scheduled = false;
+            Box::pin(guard.apply_committed_entries()).await;
+            guard.check_invariants("background apply").await;
+        }));
+    }
+
+    // 重启后重放日志时可能再次应用已经生效的配置条目（current_config 启动时取自快照或日志中最后一个配置），
+    // 这种情况下不能有任何副作用：不能退回更早的配置，也不能再次添加/移除节点或者触发关闭
+    async fn apply_configuration_to_internal_state(&mut self, index: u64, config_to_apply: config::Config, committed: bool) { // Renamed `config` to avoid conflict
+        let already_in_effect = if committed {
+            index < self.config_index || (index == self.config_index && config_to_apply == self.current_config)
+        } else {
+            config_to_apply == self.current_config
+        };
+        if already_in_effect {
+            info!("Configuration entry {} (committed: {}) is already in effect (current config from index {}), skipping.",
+                index, committed, self.config_index);
+            return;
+        }
         info!(
             "Applying configuration (committed: {}): Old servers: {:?}, New servers: {:?}",
             committed, config_to_apply.old_servers, config_to_apply.new_servers
         );
 
+        // 同一个id但地址发生变化的节点，需要更新其地址，后续RPC会连接到新地址
+        self.update_peer_addrs(&config_to_apply);
+
         if committed {
             self.current_config = config_to_apply.clone();
+            self.config_index = index;
             self.update_peer_config_states();
+            self.check_advertised_address();
+            if self.current_config.is_stable() {
+                let peers_to_remove_ids: Vec<u64> = self.peer_manager.peers().iter()
+                    .filter(|p| !self.current_config.new_servers.iter().any(|s| s.server_id == p.id))
+                    .map(|p| p.id)
+                    .collect();
+                if !peers_to_remove_ids.is_empty() {
+                    info!("Removing peers not present in committed C(new): {:?}", peers_to_remove_ids);
+                    self.peer_manager.remove(peers_to_remove_ids);
+                }
+            }
 
             info!("Committed new configuration. Node state: {:?}. All peer states updated.", self.node_config_state);
 
             if self.state == State::Leader && self.current_config.is_stable() && !self.node_config_state.newing {
-                info!("Leader is not in the newly committed stable configuration. Stepping down.");
-                // MODIFIED: Added .await to inner get() call
-                // self.step_down(self.metadata.get().await.current_term).await;
-                // OR prefer shutdown for a leader being removed.
-                self.shutdown().await;
+                info!("Leader is not in the newly committed stable configuration. Handing over leadership and stopping.");
+                self.leave_cluster();
             }
 
         } else { // Appended but not committed
@@ -596,28 +1616,13 @@ impl Consensus {
 
             if config_to_apply.is_joint() {
                 info!("Pending C(old,new) configuration appended. Node state in this pending config: {:?}", pending_node_state);
-                let mut new_peers_to_add = Vec::new();
-                for server_info in config_to_apply.new_servers.iter() {
-                    if server_info.server_id != self.server_id && !self.peer_manager.contains(server_info.server_id) {
-                        new_peers_to_add.push(peer::Peer::new(server_info.server_id, server_info.server_addr.clone()));
-                    }
-                }
-                if !new_peers_to_add.is_empty() {
-                    info!("Adding new peers for C(old,new): {:?}", new_peers_to_add.iter().map(|p|p.id).collect::<Vec<_>>());
-                    self.peer_manager.add(new_peers_to_add, self.log.last_index(self.snapshot.last_included_index));
-                }
+                self.add_new_peers(&config_to_apply, "C(old,new)");
             } else if config_to_apply.is_stable() {
                 info!("Pending C(new) configuration appended. Node state in this pending config: {:?}", pending_node_state);
-                let mut peers_to_remove_ids = Vec::new();
-                for existing_peer in self.peer_manager.peers() {
-                    if !config_to_apply.new_servers.iter().any(|s| s.server_id == existing_peer.id) {
-                        peers_to_remove_ids.push(existing_peer.id);
-                    }
-                }
-                if !peers_to_remove_ids.is_empty() {
-                    info!("Removing peers for C(new) not present in new_servers: {:?}", peers_to_remove_ids);
-                    self.peer_manager.remove(peers_to_remove_ids);
-                }
+                // 单节点变更直接追加新的稳定配置，新节点从这里开始接收复制
+                self.add_new_peers(&config_to_apply, "C(new)");
+                // 被移出的节点在 C(new) 提交之前仍然接收复制，这样它能看到移除自己的配置（见 handle_retire_rpc）；
+                // 它不在新配置中，不计入多数派
 
                 if self.state != State::Leader && !pending_node_state.newing {
                     info!("Node (Follower/Candidate) is not in pending C(new) config. Shutting down.");
@@ -630,45 +1635,45 @@ impl Consensus {
                 p_mut.config_state = config_to_apply.get_node_state(p_mut.id);
             }
         }
+        // 地址变化或新增的 Peer 可能影响 Leader 的地址
+        self.publish_view();
     }
 
-    async fn append_and_replicate_config_change(&mut self, target_new_servers_opt: Option<Vec<proto::ServerInfo>>) -> bool {
+    // 追加并复制配置变更条目，不能变更时返回原因
+    async fn append_and_replicate_config_change(&mut self, target_new_servers_opt: Option<(Vec<proto::ServerInfo>, config::MembershipAlgorithm)>) -> Result<(), String> {
         if self.state != State::Leader {
-            error!("Only leader can append configuration changes.");
-            return false;
+            return Err("only leader can append configuration changes".to_string());
         }
 
         let config_to_replicate = match target_new_servers_opt {
-            Some(target_new_servers) => {
+            Some((target_new_servers, algorithm)) => {
                 if target_new_servers.is_empty() {
-                    error!("Cannot start configuration change with empty target server list.");
-                    return false;
+                    return Err("cannot start configuration change with empty target server list".to_string());
                 }
                 if !self.current_config.is_stable() {
-                    error!("Cannot start a new configuration change: current configuration is not stable (is {:?})", self.current_config);
-                    return false;
+                    return Err(format!("cannot start a new configuration change: current configuration is not stable (is {:?})", self.current_config));
                 }
-                info!("Starting transition from stable config {:?} to new servers: {:?}", self.current_config.new_servers, target_new_servers);
-                self.current_config.start_transition(target_new_servers)
+                info!("Starting {:?} transition from stable config {:?} to new servers: {:?}", algorithm, self.current_config.new_servers, target_new_servers);
+                let next_config = match algorithm {
+                    config::MembershipAlgorithm::JointConsensus => self.current_config.start_transition(target_new_servers),
+                    config::MembershipAlgorithm::SingleServer => self.current_config.single_server_change(target_new_servers),
+                };
+                next_config.map_err(|e| format!("cannot start configuration change: {}", e))?
             }
             None => {
                 if !self.current_config.is_joint() {
-                    error!("Cannot finalize to C(new): current configuration {:?} is not C(old,new).", self.current_config);
-                    return false;
+                    return Err(format!("cannot finalize to C(new): current configuration {:?} is not C(old,new)", self.current_config));
                 }
                 info!("Finalizing transition from C(old,new) config: {:?}", self.current_config);
                 self.current_config.finalize_transition()
+                    .map_err(|e| format!("cannot finalize configuration change: {}", e))?
             }
         };
 
         info!("Replicating new configuration: Old:{:?}, New:{:?}", config_to_replicate.old_servers, config_to_replicate.new_servers);
-        match Box::pin(self.replicate(proto::EntryType::Configuration, config_to_replicate.to_data())).await {
-            std::result::Result::Ok(_) => true,
-            Err(e) => {
-                error!("Failed to replicate configuration change: {}", e);
-                false
-            }
-        }
+        Box::pin(self.replicate(proto::EntryType::Configuration, config_to_replicate.to_data())).await
+            .map(|_| ())
+            .map_err(|e| format!("failed to replicate configuration change: {}", e))
     }
 
     async fn append_and_replicate_final_config(&mut self) {
@@ -678,18 +1683,36 @@ impl Consensus {
             return;
         }
         info!("Leader automatically appending C(new) as C(old,new) is committed.");
-        self.append_and_replicate_config_change(None).await;
+        if let Err(e) = self.append_and_replicate_config_change(None).await {
+            error!("Failed to append C(new): {}", e);
+        }
     }
 
     pub async fn shutdown(&mut self) {
         info!("Shutting down this node (server_id: {})", self.server_id);
-        self.state = State::Follower;
-        self.leader_id = config::NONE_SERVER_ID;
+        self.shutting_down = true;
+        let current_term = self.metadata.get().await.current_term;
+        self.set_state(State::Follower, current_term, audit::AuditReason::Shutdown);
+        self.set_leader_id(config::NONE_SERVER_ID);
 
         // MODIFIED: Added .await for timer stop
         self.heartbeat_timer.lock().await.stop().await;
         self.election_timer.lock().await.stop().await;
         self.snapshot_timer.lock().await.stop().await;
+        if let Some(verify_task) = self.verify_task.take() {
+            verify_task.abort();
+        }
+        if let Some(status_task) = self.status_task.take() {
+            status_task.abort();
+        }
+        self.apply_waiters.clear();
+        // 已提交的上传在后台继续完成
+        self.archiver = None;
+        self.commit_sink = None;
+        // 等待元数据的后台任务写完剩余的更新后退出，正常关闭不会丢失任期和投票
+        if let Err(e) = self.metadata.shutdown().await {
+            error!("Node {} failed to flush metadata on shutdown: {}", self.server_id, e);
+        }
 
         info!("Node {} timers stopped.", self.server_id);
         info!("Node {} shutdown sequence in Consensus complete. External server shutdown needed.", self.server_id);
@@ -697,83 +1720,517 @@ impl Consensus {
 
     
 
-    pub async fn handle_snapshot_timeout(&mut self) {
-        if self.log.committed_entries_len(self.commit_index) > config::SNAPSHOT_LOG_LENGTH_THRESHOLD {
-            info!("Snapshot timeout: Log length exceeds threshold. Starting snapshot.");
-
-            let last_included_idx = self.last_applied;
-            if last_included_idx == 0 {
-                info!("Skipping snapshot: last_applied is 0.");
-                 // MODIFIED: Explicitly reset timer
-                self.snapshot_timer.lock().await.reset(config::SNAPSHOT_INTERVAL);
-                return;
-            }
-            let last_included_term = self.log.entry(last_included_idx).map_or_else(
-                || {
-                    if last_included_idx == self.snapshot.last_included_index {
-                        self.snapshot.last_included_term
-                    } else {
-                        error!("Cannot determine term for last_applied index {} for snapshot.", last_included_idx);
-                        0
-                    }
-                },
-                |entry| entry.term
-            );
+    // 完全停止节点：关闭共识模块，并通知 RPC 服务停止接受请求
+    pub async fn stop(&mut self) {
+        self.shutdown().await;
+        self.stop_tx.send_replace(true);
+        info!("Node {} stopped.", self.server_id);
+    }
+
+    pub fn stop_signal(&self) -> watch::Receiver<bool> {
+        self.stop_tx.subscribe()
+    }
 
-            if last_included_term == 0 && last_included_idx > 0 {
-                 error!("Failed to get term for snapshot at index {}. Aborting snapshot.", last_included_idx);
-                  // MODIFIED: Explicitly reset timer
-                 self.snapshot_timer.lock().await.reset(config::SNAPSHOT_INTERVAL);
-                 return;
+    // 被配置变更移出集群的 Leader：C(new) 已经提交，把领导权交给日志最新的节点，转移失败时直接退位，
+    // 然后发布事件并完全停止。转移领导权需要在锁外等待，放到单独的任务中执行
+    fn leave_cluster(&mut self) {
+        self.non_candidate = true;
+        let Some(consensus) = self.self_ref.upgrade() else {
+            return;
+        };
+        // 优先交给同一可用区的节点，客户端到新 Leader 的延迟变化最小
+        let own_zone = self.current_config.zone_of(self.server_id);
+        let successor = self.peer_manager.peers().iter()
+            .filter(|p| p.config_state.newing)
+            .max_by_key(|p| (own_zone.is_some() && self.current_config.zone_of(p.id) == own_zone, p.match_index))
+            .map(|p| p.id);
+        tokio::spawn(crash::scope(async move {
+            let mut handed_over = None;
+            if let Some(successor) = successor {
+                match Consensus::transfer_leadership(&consensus, successor).await {
+                    Ok(()) => handed_over = Some(successor),
+                    Err(e) => warn!("Removed leader failed to transfer leadership to server {}: {}", successor, e),
+                }
+            }
+            let mut guard = consensus.lock().await;
+            if guard.state == State::Leader {
+                let term = guard.metadata.get().await.current_term;
+                guard.step_down(term, audit::AuditReason::RemovedFromConfig).await;
             }
+            info!("Node {} left the cluster (successor: {:?}).", guard.server_id, handed_over);
+            guard.events.publish(events::RaftEvent::RemovedFromCluster { successor: handed_over });
+            guard.stop().await;
+        }));
+    }
 
-            let config_for_snapshot = self.current_config.clone();
-            // Snapshot::gen_snapshot_filepath likely takes &self
-            let snapshot_filepath = self.snapshot.gen_snapshot_filepath(last_included_idx, last_included_term);
+    // 兜底的快照检查：按已提交的日志长度判断，不区分角色，也覆盖 Leader 因为 Follower 落后而推迟的压缩
+    pub async fn handle_snapshot_timeout(&mut self) {
+        let committed_len = self.log.committed_entries_len(self.commit_index);
+        if self.check_snapshot_policy(committed_len, "Snapshot timeout") {
+            self.spawn_snapshot("Snapshot timeout");
+        }
+        // 顺便刷新磁盘占用指标；超过配额时不等日志长度达到阈值就压缩
+        self.over_disk_quota = self.enforce_disk_quota();
+        // 被推迟或者还没有完成的压缩在下一次定时器触发时重新检查
+        if self.snapshot_deferred.is_some() || self.snapshot_running || self.over_disk_quota {
+            self.snapshot_check_due.store(true, std::sync::atomic::Ordering::Release);
+        }
+        self.check_invariants("snapshot timeout").await;
+        // MODIFIED: Explicitly reset timer
+        self.snapshot_timer.lock().await.reset(config::SNAPSHOT_INTERVAL);
+    }
 
-            info!("Taking snapshot for index {}, term {}. File: {}", last_included_idx, last_included_term, snapshot_filepath);
+    // 按快照策略判断 log_len 条日志是否需要压缩，被推迟时记录原因
+    fn check_snapshot_policy(&mut self, log_len: usize, trigger: &str) -> bool {
+        let unix_secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let entry_rate = self.entry_rate();
+        match self.snapshot_policy.check(log_len, unix_secs, entry_rate) {
+            Ok(true) => {
+                info!("{}: log length {} exceeds threshold. Starting snapshot.", trigger, log_len);
+                self.snapshot_deferred = None;
+                true
+            }
+            Ok(false) => {
+                self.snapshot_deferred = None;
+                false
+            }
+            Err(reason) => {
+                info!("{}: deferring snapshot of {} entries, {}.", trigger, log_len, reason);
+                self.snapshot_deferred = Some(reason);
+                false
+            }
+        }
+    }
 
-            // If state_machine.take_snapshot is very slow, use spawn_blocking
-            // For now, assuming it's acceptable.
-            // tokio::task::spawn_blocking({
-            //    let state_machine_clone = self.state_machine.clone(); // If state_machine is Arc<Mutex<dyn ...>> or similar
-            //    let snapshot_filepath_clone = snapshot_filepath.clone();
-            //    move || state_machine_clone.take_snapshot(&snapshot_filepath_clone) // Pass as &str
-            // }).await.unwrap();
-            // Or if it's Box<dyn ...> and the trait method takes `&mut self`, you can't easily clone it.
-            // Direct call if it's not too blocking:
-            self.state_machine.take_snapshot(&snapshot_filepath); // Pass as &str. Typo `take_snapshow` fixed.
+    // 在后台任务中生成快照，调用方不必等待，同一时间最多一个（见 run_snapshot）
+    fn spawn_snapshot(&mut self, trigger: &'static str) {
+        if self.snapshot_running {
+            return;
+        }
+        let Some(consensus) = self.self_ref.upgrade() else {
+            return;
+        };
+        self.snapshot_running = true;
+        tokio::spawn(crash::scope(Consensus::snapshot_in_background(consensus, trigger)));
+    }
 
+    async fn snapshot_in_background(consensus: Arc<TokioMutex<Consensus>>, trigger: &'static str) {
+        if let Err(e) = Consensus::run_snapshot(&consensus).await {
+            warn!("{}: {}", trigger, e);
+        }
+    }
 
-            if !std::path::Path::new(&snapshot_filepath).exists() {
-                error!("State machine failed to create snapshot file: {}", snapshot_filepath);
-                 // MODIFIED: Explicitly reset timer
-                self.snapshot_timer.lock().await.reset(config::SNAPSHOT_INTERVAL);
-                return;
+    // 立即生成一次快照并等待完成，另一个快照正在生成时返回错误
+    pub async fn snapshot_now(consensus: &TokioMutex<Consensus>) -> Result<(), String> {
+        {
+            let mut guard = consensus.lock().await;
+            if guard.snapshot_running {
+                return Err("skipping snapshot, another snapshot is being taken".to_string());
             }
-            info!("Successfully took snapshot data to {}", snapshot_filepath);
-
-            self.snapshot.take_snapshot_metadata(
-                last_included_idx,
-                last_included_term,
-                Some(config_for_snapshot),
-            );
-
-            self.log.truncate_prefix(last_included_idx);
-            info!("Log truncated up to index {}. New log start_index: {}", last_included_idx, self.log.start_index());
+            guard.snapshot_running = true;
         }
-        // MODIFIED: Explicitly reset timer
-        self.snapshot_timer.lock().await.reset(config::SNAPSHOT_INTERVAL);
+        Consensus::run_snapshot(consensus).await
     }
 
+    // 状态机在锁内写出快照数据，压缩和计算校验和在阻塞线程中进行，之后重新加锁写入元数据并压缩日志。
+    // 期间节点照常处理请求和应用新条目，快照只包含准备时已经应用的条目。
+    // 调用方负责设置 snapshot_running，结束时清除
+    async fn run_snapshot(consensus: &TokioMutex<Consensus>) -> Result<(), String> {
+        let prepared = {
+            let mut guard = consensus.lock().await;
+            match guard.prepare_snapshot() {
+                Ok(prepared) => prepared,
+                Err(e) => {
+                    guard.snapshot_running = false;
+                    guard.recheck_disk_quota();
+                    return Err(e);
+                }
+            }
+        };
+        let (filepath, level) = (prepared.snapshot_filepath.clone(), prepared.compression_level);
+        let sealed = tokio::task::spawn_blocking(move || crash::scope_blocking(|| seal_snapshot_file(&filepath, level))).await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+        let mut guard = consensus.lock().await;
+        guard.snapshot_running = false;
+        let result = sealed.and_then(|checksum| guard.finish_snapshot(prepared, checksum));
+        guard.recheck_disk_quota();
+        result
+    }
 
-    pub async fn handle_propose_rpc(
-        &mut self, 
-        request: & proto::ProposeRequest,
-    ) -> proto::ProposeResponse {
-        if self.state != State::Leader {
-            // 如果当前节点不是 Leader，返回失败并告知客户端 Leader 的信息
-            let leader_info = if self.leader_id != config::NONE_SERVER_ID {
+    // 应用新条目之后检查是否需要生成快照，不必等快照定时器，也不会在没有新条目时获取锁。
+    // Leader 只把所有 Peer 都已经复制的条目计入日志长度，落后的 Follower 仍然可以从日志追赶而不必接收快照，
+    // 这种情况下的压缩留给定时器兜底；两次检查至少间隔 SNAPSHOT_CHECK_MIN_INTERVAL
+    fn maybe_snapshot_after_apply(&mut self) {
+        if self.shutting_down || self.snapshot_running {
+            return;
+        }
+        let now = self.clock.now();
+        if self.last_snapshot_check.is_some_and(|at| now.saturating_duration_since(at) < config::SNAPSHOT_CHECK_MIN_INTERVAL) {
+            return;
+        }
+        let mut compactable = self.last_applied;
+        if self.state == State::Leader {
+            // 联系不上的 Peer 不参与计算，否则一个宕机的节点会让日志一直无法压缩，它恢复之后通过快照追赶
+            let reachable = |p: &&peer::Peer| p.last_contact.is_some_and(|t| now.saturating_duration_since(t) < config::CLUSTER_UNAVAILABLE_TIMEOUT);
+            if let Some(min_match) = self.peer_manager.peers().iter().filter(reachable).map(|p| p.match_index).min() {
+                compactable = compactable.min(min_match);
+            }
+        }
+        let log_len = compactable.saturating_sub(self.snapshot.last_included_index()) as usize;
+        if log_len <= self.snapshot_policy.log_length_threshold {
+            return;
+        }
+        self.last_snapshot_check = Some(now);
+        if self.check_snapshot_policy(log_len, "Snapshot after apply") {
+            self.spawn_snapshot("Snapshot after apply");
+        }
+    }
+
+    // 距上一次检查快照策略以来每秒追加的条目数，同时开始下一个统计周期
+    fn entry_rate(&mut self) -> f64 {
+        let now = StdInstant::now();
+        let last_index = self.log.last_index(self.snapshot.last_included_index());
+        let (since, since_index) = std::mem::replace(&mut self.snapshot_tick, (now, last_index));
+        let elapsed = now.saturating_duration_since(since).as_secs_f64();
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+        last_index.saturating_sub(since_index) as f64 / elapsed
+    }
+
+    // 日志文件和快照目录占用的字节数，同时更新到指标中
+    pub fn disk_usage(&self) -> (u64, u64) {
+        let log_bytes = std::fs::metadata(self.log.filepath()).map_or(0, |m| m.len());
+        let snapshot_bytes = self.snapshot.disk_usage();
+        self.metrics.log_bytes.store(log_bytes, std::sync::atomic::Ordering::Relaxed);
+        self.metrics.snapshot_bytes.store(snapshot_bytes, std::sync::atomic::Ordering::Relaxed);
+        (log_bytes, snapshot_bytes)
+    }
+
+    // 磁盘占用超过配额时在后台生成快照，不等日志长度达到阈值，返回当前是否超过配额。
+    // 快照任务结束后删除旧快照并重新检查（见 recheck_disk_quota）
+    fn enforce_disk_quota(&mut self) -> bool {
+        let (log_bytes, snapshot_bytes) = self.disk_usage();
+        let Some(quota) = self.disk_quota_bytes else {
+            return false;
+        };
+        if log_bytes + snapshot_bytes <= quota {
+            return false;
+        }
+        warn!("Disk usage {} bytes (log {}, snapshots {}) exceeds the quota {}, compacting.",
+            log_bytes + snapshot_bytes, log_bytes, snapshot_bytes, quota);
+        self.metrics.disk_quota_compactions.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.spawn_snapshot("Disk quota compaction");
+        true
+    }
+
+    // 超过配额时快照任务结束后调用：删除旧快照，重新统计磁盘占用，Propose 据此恢复或继续拒绝写入
+    fn recheck_disk_quota(&mut self) {
+        if !self.over_disk_quota {
+            return;
+        }
+        self.snapshot.remove_stale_snapshots();
+        let (log_bytes, snapshot_bytes) = self.disk_usage();
+        self.over_disk_quota = self.disk_quota_bytes.is_some_and(|quota| log_bytes + snapshot_bytes > quota);
+    }
+
+    // 检查能否生成快照，由状态机把已应用的数据写入快照文件
+    fn prepare_snapshot(&mut self) -> Result<PreparedSnapshot, String> {
+        let started_at = self.clock.now();
+        let last_included_idx = self.last_applied;
+        if last_included_idx == 0 {
+            return Err("skipping snapshot, last_applied is 0".to_string());
+        }
+        if self.restore_progress.in_progress() {
+            return Err("skipping snapshot, state machine is being restored".to_string());
+        }
+        if last_included_idx <= self.snapshot.last_included_index() {
+            return Err(format!("no newly applied entries since snapshot at index {}", self.snapshot.last_included_index()));
+        }
+        // 重组到一半的数据还没有进入状态机，此时的快照会丢掉已经应用的分块
+        if let Some(first_index) = self.chunk_assembler.pending() {
+            return Err(format!("skipping snapshot, chunked payload starting at index {} is partially applied", first_index));
+        }
+        // 还没有投递给 CommitSink 的条目要留在日志中，重启后才能重新投递
+        if let Some(dispatcher) = &self.commit_sink {
+            let watermark = dispatcher.watermark();
+            if watermark < last_included_idx {
+                return Err(format!("skipping snapshot, commit sink has only delivered up to index {}", watermark));
+            }
+        }
+        let last_included_term = match self.log.entry(last_included_idx) {
+            Some(entry) => entry.term,
+            None => return Err(format!("failed to get term for snapshot at index {}", last_included_idx)),
+        };
+
+        // 快照中的配置必须是 last_included_index 处生效的配置，之后追加但还没有提交的配置条目仍然留在日志中。
+        // 日志和快照中都没有配置条目时生效的是启动时的初始配置，它已经被之后的配置条目取代时推迟生成快照
+        let (config_index, config_for_snapshot) = match self.log.configuration_entry_at(last_included_idx)
+            .or_else(|| self.snapshot.configuration().map(|c| (self.snapshot.meta().config_index(), c.clone())))
+        {
+            Some(config_in_effect) => config_in_effect,
+            None if self.config_index <= last_included_idx => (self.config_index, self.current_config.clone()),
+            None => return Err(format!("skipping snapshot, configuration in effect at index {} is no longer known", last_included_idx)),
+        };
+        let snapshot_filepath = self.snapshot.gen_snapshot_filepath(last_included_idx, last_included_term);
+
+        info!("Taking snapshot for index {}, term {}. File: {}", last_included_idx, last_included_term, snapshot_filepath);
+
+        // If state_machine.take_snapshot is very slow, use spawn_blocking
+        // For now, assuming it's acceptable.
+        self.state_machine.take_snapshot(&snapshot_filepath);
+
+        if !std::path::Path::new(&snapshot_filepath).exists() {
+            return Err(format!("state machine failed to create snapshot file: {}", snapshot_filepath));
+        }
+        Ok(PreparedSnapshot {
+            last_included_index: last_included_idx,
+            last_included_term,
+            config_index,
+            configuration: config_for_snapshot,
+            snapshot_filepath,
+            compression_level: compress::zstd_level(self.storage_compression),
+            started_at,
+        })
+    }
+
+    // 写入快照元数据并压缩日志。准备之后本节点安装了更新的快照时放弃这次快照，元数据写入失败时不压缩日志
+    fn finish_snapshot(&mut self, prepared: PreparedSnapshot, checksum: u64) -> Result<(), String> {
+        let PreparedSnapshot { last_included_index: last_included_idx, last_included_term, config_index, configuration, snapshot_filepath, started_at, .. } = prepared;
+        if last_included_idx <= self.snapshot.last_included_index() {
+            return Err(format!("discarding snapshot at index {}, snapshot at index {} is already in place", last_included_idx, self.snapshot.last_included_index()));
+        }
+        info!("Successfully took snapshot data to {}", snapshot_filepath);
+
+        self.snapshot.store_metadata(snapshot::SnapshotMeta {
+            last_included_index: last_included_idx,
+            last_included_term,
+            configuration: Some(configuration),
+            checksum,
+            config_index,
+            state_machine_version: self.state_machine.snapshot_version(),
+        })?;
+
+        if let Some(archiver) = &self.archiver {
+            let sealed = self.log.entries().iter().filter(|e| e.index <= last_included_idx).cloned().collect();
+            archiver.archive_segment(sealed);
+            archiver.archive_snapshot(
+                snapshot_filepath.clone(),
+                self.snapshot.gen_snapshot_metadata_filepath(last_included_idx, last_included_term),
+            );
+        }
+
+        self.log.truncate_prefix(last_included_idx);
+        self.log.persist();
+        self.record_log_op(|| format!("snapshot compacted log up to {} (term {})", last_included_idx, last_included_term));
+        self.last_snapshot_duration = Some(self.clock.now().saturating_duration_since(started_at));
+        info!("Log truncated up to index {}. New log start_index: {}", last_included_idx, self.log.start_index());
+        Ok(())
+    }
+
+    // 导出用的快照：有新应用的条目时先生成一个，生成失败（例如分块数据只应用了一部分）时退回到已有的快照。
+    // 生成快照期间不持有锁
+    pub async fn snapshot_for_export(consensus: &TokioMutex<Consensus>) -> Result<(u64, u64), String> {
+        let stale = {
+            let guard = consensus.lock().await;
+            guard.last_applied > guard.snapshot.last_included_index()
+        };
+        if stale {
+            match Consensus::snapshot_now(consensus).await {
+                Ok(()) => consensus.lock().await.snapshot_deferred = None,
+                Err(e) => warn!("Export could not take a fresh snapshot, using the existing one: {}", e),
+            }
+        }
+        let guard = consensus.lock().await;
+        match guard.snapshot.last_included_index() {
+            0 => Err(format!("server {} has no snapshot to export", guard.server_id)),
+            index => Ok((index, guard.snapshot.last_included_term())),
+        }
+    }
+
+    // 不检查日志长度阈值和调度策略，立即生成一次快照并等待完成，生成期间不持有锁
+    pub async fn trigger_snapshot(
+        consensus: &TokioMutex<Consensus>,
+        _request: &proto::TriggerSnapshotRequest,
+    ) -> proto::TriggerSnapshotResponse {
+        info!("Snapshot triggered manually.");
+        let result = Consensus::snapshot_now(consensus).await;
+        let mut guard = consensus.lock().await;
+        if result.is_ok() {
+            guard.snapshot_deferred = None;
+        }
+        if let Err(e) = &result {
+            warn!("Manual snapshot failed: {}", e);
+        }
+        proto::TriggerSnapshotResponse {
+            success: result.is_ok(),
+            error: result.err(),
+            status: Some(guard.handle_get_snapshot_status_rpc(&proto::GetSnapshotStatusRequest {})),
+        }
+    }
+
+    // 从本地的快照文件中读取一个分块，供新节点引导时拉取；不要求本节点是 Leader。
+    // 持有锁时只确定文件路径，读取在阻塞线程中进行，期间快照被清理时返回错误，由拉取方重试
+    pub async fn fetch_snapshot(consensus: &TokioMutex<Consensus>, request: &proto::FetchSnapshotRequest) -> proto::FetchSnapshotResponse {
+        let refuse = |error: String| proto::FetchSnapshotResponse { success: false, error: Some(error), ..Default::default() };
+        let (server_id, index, term, filepath) = {
+            let guard = consensus.lock().await;
+            let (index, term) = match (request.last_included_index, guard.snapshot.last_included_index()) {
+                (0, 0) => return refuse(format!("server {} has no snapshot", guard.server_id)),
+                (0, current) => (current, guard.snapshot.last_included_term()),
+                (index, _) => (index, request.last_included_term),
+            };
+            let filepath = match proto::SnapshotDataType::try_from(request.snapshot_data_type) {
+                Ok(proto::SnapshotDataType::Metadata) => guard.snapshot.gen_snapshot_metadata_filepath(index, term),
+                Ok(proto::SnapshotDataType::Snapshot) => guard.snapshot.gen_snapshot_filepath(index, term),
+                Err(_) => return refuse(format!("unknown snapshot data type {}", request.snapshot_data_type)),
+            };
+            (guard.server_id, index, term, filepath)
+        };
+        let max_bytes = match request.max_bytes {
+            0 => config::FETCH_SNAPSHOT_CHUNK_SIZE,
+            n => (n as usize).min(config::FETCH_SNAPSHOT_CHUNK_SIZE),
+        };
+        let offset = request.offset;
+        let read = move || -> std::io::Result<(u64, Vec<u8>)> {
+            let mut file = std::fs::File::open(&filepath)?;
+            let total_size = file.metadata()?.len();
+            let len = total_size.saturating_sub(offset).min(max_bytes as u64) as usize;
+            let mut data = vec![0; len];
+            file.seek(std::io::SeekFrom::Start(offset))?;
+            file.read_exact(&mut data)?;
+            Ok((total_size, data))
+        };
+        let read = tokio::task::spawn_blocking(read).await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+        match read {
+            Ok((total_size, data)) => proto::FetchSnapshotResponse {
+                success: true,
+                error: None,
+                last_included_index: index,
+                last_included_term: term,
+                total_size,
+                done: request.offset + data.len() as u64 >= total_size,
+                data,
+            },
+            Err(e) => refuse(format!("snapshot ({}, {}) is not available on server {}: {}", index, term, server_id, e)),
+        }
+    }
+
+    pub fn handle_get_latency_stats_rpc(
+        &self,
+        _request: &proto::GetLatencyStatsRequest,
+    ) -> proto::GetLatencyStatsResponse {
+        proto::GetLatencyStatsResponse { histograms: self.metrics.latency_stats() }
+    }
+
+    pub async fn handle_get_status_rpc(&mut self, _request: &proto::GetStatusRequest) -> proto::GetStatusResponse {
+        let role = match self.state {
+            State::Follower => proto::NodeRole::Follower,
+            State::Candidate => proto::NodeRole::Candidate,
+            State::Leader => proto::NodeRole::Leader,
+        };
+        let peers = if self.state == State::Leader {
+            self.peer_manager.peers().iter()
+                .map(|p| proto::PeerReplication {
+                    server_id: p.id,
+                    match_index: p.match_index,
+                    next_index: p.next_index,
+                    next_index_history: p.next_index_history.iter().copied().collect(),
+                    snapshot_required: p.snapshot_required,
+                    median_rtt_us: p.rtt.median().map_or(0, |rtt| rtt.as_micros() as u64),
+                    clock_offset_us: p.clock_skew.estimate().map(|(offset_us, _)| offset_us),
+                    clock_skew_bound_us: p.clock_skew.bound_us(),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let zones = if self.state == State::Leader {
+            let now = self.clock.now();
+            zone::zone_health(&self.current_config, |id| {
+                id == self.server_id || self.peer_manager.peers().iter().any(|p| p.id == id
+                    && p.last_contact.is_some_and(|t| now.saturating_duration_since(t) < config::CLUSTER_UNAVAILABLE_TIMEOUT))
+            })
+        } else {
+            Vec::new()
+        };
+        proto::GetStatusResponse {
+            server_id: self.server_id,
+            server_addr: self.server_addr.clone(),
+            role: role as i32,
+            current_term: self.metadata.get().await.current_term,
+            leader: self.leader_info(),
+            commit_index: self.commit_index,
+            last_applied: self.last_applied,
+            log_start_index: self.log.start_index(),
+            log_last_index: self.log.last_index(self.snapshot.last_included_index()),
+            snapshot_last_included_index: self.snapshot.last_included_index(),
+            old_servers: self.current_config.old_servers.clone(),
+            new_servers: self.current_config.new_servers.clone(),
+            health: self.health() as i32,
+            peers,
+            not_ready_reason: self.not_ready_reason(),
+            zones,
+        }
+    }
+
+    pub fn handle_get_snapshot_status_rpc(
+        &self,
+        _request: &proto::GetSnapshotStatusRequest,
+    ) -> proto::GetSnapshotStatusResponse {
+        let size_bytes = self.snapshot.latest_snapshot_filepath()
+            .and_then(|filepath| std::fs::metadata(filepath).ok())
+            .map_or(0, |m| m.len());
+        let (log_bytes, snapshot_dir_bytes) = self.disk_usage();
+        proto::GetSnapshotStatusResponse {
+            last_included_index: self.snapshot.last_included_index(),
+            last_included_term: self.snapshot.last_included_term(),
+            size_bytes,
+            duration_ms: self.last_snapshot_duration.map_or(0, |d| d.as_millis() as u64),
+            log_start_index: self.log.start_index(),
+            log_last_index: self.log.last_index(self.snapshot.last_included_index()),
+            last_applied: self.last_applied,
+            last_applied_gap: self.last_applied_gap,
+            restore: self.restore_progress.to_proto(),
+            min_applied_index: self.min_applied_index(),
+            log_bytes,
+            snapshot_dir_bytes,
+            disk_quota_bytes: self.disk_quota_bytes,
+            snapshot_deferred: self.snapshot_deferred.clone(),
+        }
+    }
+
+    // 集群中所有节点（含自身）最小的 last_applied，只有 Leader 能给出；
+    // 还没有响应过的节点按0计算，结果只会偏保守，可用于判断哪些日志已被所有节点应用
+    pub fn min_applied_index(&self) -> Option<u64> {
+        if self.state != State::Leader {
+            return None;
+        }
+        let peers_min = self.peer_manager.peers().iter().map(|p| p.last_applied).min();
+        Some(peers_min.map_or(self.last_applied, |m| m.min(self.last_applied)))
+    }
+
+
+    pub async fn handle_propose_rpc(
+        &mut self, 
+        request: & proto::ProposeRequest,
+    ) -> proto::ProposeResponse {
+        if self.shutting_down {
+            warn!("Rejecting Propose: node is shutting down.");
+            return proto::ProposeResponse {
+                success: false,
+                index: None,
+                leader_addr: None,
+                reject_reason: proto::ProposeRejectReason::Shutdown as i32,
+                read_token: None,
+                result: None,
+            };
+        }
+
+        if self.state != State::Leader {
+            // 如果当前节点不是 Leader，返回失败并告知客户端 Leader 的信息
+            let leader_info = if self.leader_id != config::NONE_SERVER_ID {
                 self.peer_manager.peers().iter()
                     .find(|p| p.id == self.leader_id)
                     .map(|p| (p.id, p.addr.clone()))
@@ -784,43 +2241,166 @@ impl Consensus {
                     })
             } else { None };
     
-            if let Some((id, addr)) = leader_info {
-                return proto::ProposeResponse {
-                    success: false,
-                    index: Some(id),
-                    leader_addr: Some(addr),
-                };
-            } else {
-                 // 还不知道 Leader 是谁
-                return proto::ProposeResponse {
-                    success: false,
-                    index: None,
-                    leader_addr: None,
-                };
-            }
+            // 还不知道 Leader 是谁时 index 和 leader_addr 为 None
+            let (index, leader_addr) = match leader_info {
+                Some((id, addr)) => (Some(id), Some(addr)),
+                None => (None, None),
+            };
+            return proto::ProposeResponse {
+                success: false,
+                index,
+                leader_addr,
+                reject_reason: proto::ProposeRejectReason::NotLeader as i32,
+                read_token: None,
+                result: None,
+            };
         }
-    
+
+        let (server_id, server_addr) = (self.server_id, self.server_addr.clone());
+        let reject = move |reason: proto::ProposeRejectReason| proto::ProposeResponse {
+            success: false,
+            index: Some(server_id),
+            leader_addr: Some(server_addr.clone()),
+            reject_reason: reason as i32,
+            read_token: None,
+            result: None,
+        };
+
+        if request.data.len() > config::MAX_PROPOSE_PAYLOAD_SIZE {
+            warn!("Rejecting Propose: payload size {} exceeds limit {}.", request.data.len(), config::MAX_PROPOSE_PAYLOAD_SIZE);
+            return reject(proto::ProposeRejectReason::PayloadTooLarge);
+        }
+        if request.data.len() > self.max_entry_size && !self.chunk_large_entries {
+            warn!("Rejecting Propose: payload size {} exceeds max entry size {} and chunking is disabled.", request.data.len(), self.max_entry_size);
+            return reject(proto::ProposeRejectReason::PayloadTooLarge);
+        }
+
+        // Leader 不在新配置中，C(new) 提交后就会退位，此时接受的数据需要等待新 Leader
+        if self.current_config.is_joint() && !self.node_config_state.newing {
+            warn!("Rejecting Propose: leader is being removed by an in-progress configuration change.");
+            return reject(proto::ProposeRejectReason::ConfChangeInProgress);
+        }
+
+        if self.cluster_status() == proto::ClusterStatus::ClusterUnavailable {
+            // 联系不上多数派时快速失败，而不是让客户端一直等待
+            warn!("Rejecting Propose: leader cannot reach a quorum.");
+            return reject(proto::ProposeRejectReason::Backpressure);
+        }
+
+        let entry_count = self.proposal_entry_count(request.data.len());
+        let uncommitted = self.log.last_index(self.snapshot.last_included_index()).saturating_sub(self.commit_index);
+        if uncommitted + entry_count as u64 > config::MAX_UNCOMMITTED_ENTRIES {
+            warn!("Rejecting Propose: {} entries are waiting to be committed.", uncommitted);
+            return reject(proto::ProposeRejectReason::Backpressure);
+        }
+        // 多数派不可用时条目无法提交，按字节数限制未提交的日志，避免大条目把内存耗尽
+        if self.uncommitted_bytes + request.data.len() as u64 > config::MAX_UNCOMMITTED_BYTES {
+            warn!("Rejecting Propose: {} bytes are waiting to be committed, adding {} would exceed the limit {}.",
+                self.uncommitted_bytes, request.data.len(), config::MAX_UNCOMMITTED_BYTES);
+            return reject(proto::ProposeRejectReason::Backpressure);
+        }
+        // 定时检查时压缩后仍然超过磁盘配额就拒绝写入，避免日志把磁盘写满。
+        // 这里只看缓存的结果，不在每次 Propose 时统计目录
+        if self.over_disk_quota && self.disk_quota_bytes.is_some() {
+            warn!("Rejecting Propose: disk usage exceeds the quota of {} bytes.", self.disk_quota_bytes.unwrap_or_default());
+            self.metrics.disk_quota_rejections.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            return reject(proto::ProposeRejectReason::Backpressure);
+        }
+
         info!("Leader handling Propose request, data size: {}", request.data.len());
-        
-        // 调用已有的 replicate 方法
-        match self.replicate(proto::EntryType::Data, request.data.clone()).await {
+
+        let result = if entry_count > 1 {
+            info!("Splitting payload of {} bytes into {} chunks.", request.data.len(), entry_count);
+            let chunks = codec::split(&request.data, self.max_entry_size);
+            self.replicate_entries(proto::EntryType::DataChunk, chunks).await
+        } else {
+            self.replicate(proto::EntryType::Data, request.data.clone()).await
+        };
+        match result {
             Ok(_) => proto::ProposeResponse {
                 success: true,
                 index: Some(self.server_id),
                 leader_addr: Some(self.server_addr.clone()),
+                reject_reason: proto::ProposeRejectReason::None as i32,
+                // 新条目的索引，任何节点应用到该索引后都能读到本次写入
+                read_token: Some(self.log.last_index(self.snapshot.last_included_index())),
+                result: None,
             },
             Err(e) => {
                 error!("Failed to replicate data from client: {}", e);
-                proto::ProposeResponse { success: false, index: Some(self.server_id), leader_addr: Some(self.server_addr.clone()) }
+                // replicate 只会在当前节点已经不是 Leader 时失败
+                proto::ProposeResponse {
+                    success: false,
+                    index: None,
+                    leader_addr: None,
+                    reject_reason: proto::ProposeRejectReason::NotLeader as i32,
+                    read_token: None,
+                    result: None,
+                }
             }
         }
 
     }
 
 
+    // 状态转换后检查内部不变量，只在 debug 构建或开启 invariants feature 时生效，见 invariants 模块
+    async fn check_invariants(&mut self, context: &str) {
+        if !invariants::ENABLED {
+            return;
+        }
+        let last_included_index = self.snapshot.last_included_index();
+        let checkpoint = invariants::Checkpoint {
+            state: self.state,
+            server_id: self.server_id,
+            leader_id: self.leader_id,
+            current_term: self.metadata.get().await.current_term,
+            commit_index: self.commit_index,
+            last_applied: self.last_applied,
+            log_start_index: self.log.start_index(),
+            last_log_index: self.log.last_index(last_included_index),
+            last_log_term: self.log.last_term(self.snapshot.last_included_term()),
+            last_included_index,
+            peers: self.peer_manager.peers().iter()
+                .map(|p| invariants::PeerProgress { id: p.id, next_index: p.next_index, match_index: p.match_index })
+                .collect(),
+        };
+        invariants::enforce(context, &checkpoint);
+    }
+
+    // 拒绝来自网络的荒谬任期和索引（见 sanity 模块），check 的参数是本地的当前任期和最后一条日志的索引
+    pub async fn check_request(
+        &mut self,
+        kind: &str,
+        from: u64,
+        check: impl FnOnce(u64, u64) -> Result<(), String>,
+    ) -> Result<(), String> {
+        if self.partition.blocks(from) {
+            debug!("Dropping {} from {}: server is partitioned away.", kind, from);
+            return Err(format!("server {} is partitioned away from server {}", from, self.server_id));
+        }
+        let current_term = self.metadata.get().await.current_term;
+        let last_index = self.log.last_index(self.snapshot.last_included_index());
+        check(current_term, last_index).inspect_err(|e| warn!("Rejecting {} from {}: {}", kind, from, e))
+    }
+
     pub async fn handle_append_entries_rpc(
         &mut self,
         request: &proto::AppendEntriesRequest,
+    ) -> proto::AppendEntriesResponse {
+        let received_us = util::unix_micros();
+        let mut resp = self.append_entries(request).await;
+        // 回传 Leader 的发送时间和本地收到请求的时间，Leader 据此估计两边的时钟偏差
+        if request.leader_time_us.is_some() {
+            resp.leader_time_us = request.leader_time_us;
+            resp.follower_time_us = Some(received_us);
+        }
+        self.check_invariants("AppendEntries").await;
+        resp
+    }
+
+    async fn append_entries(
+        &mut self,
+        request: &proto::AppendEntriesRequest,
     ) -> proto::AppendEntriesResponse {
         let meta = self.metadata.get().await;
         let current_term = meta.current_term;
@@ -828,6 +2408,9 @@ impl Consensus {
         let mut refuse_resp = proto::AppendEntriesResponse {
             term: current_term,
             success: false,
+            last_applied: self.last_applied,
+            leader_time_us: None,
+            follower_time_us: None,
         };
 
         if request.term < current_term {
@@ -835,283 +2418,1094 @@ impl Consensus {
             return refuse_resp;
         }
 
-        if request.term > current_term {
-            // 只有在收到更高任期时才会step_down
-            Box::pin(self.step_down(request.term)).await;
-            // 更新refuse_resp的term
-            refuse_resp.term = self.metadata.get().await.current_term;
-        } else if self.state == State::Leader && request.leader_id != self.server_id {
-            // 自己是Leader，但收到同任期的另一个Leader的心跳，这是一种分区情况，需要退位
-            info!("Leader received AR from another leader {} in same term {}. Stepping down.", request.leader_id, request.term);
-            Box::pin(self.step_down(request.term)).await;
+        if request.term > current_term {
+            // 只有在收到更高任期时才会step_down
+            Box::pin(self.step_down(request.term, audit::AuditReason::HigherTermSeen)).await;
+            // 更新refuse_resp的term
+            refuse_resp.term = self.metadata.get().await.current_term;
+            // 回复确认新任期之前，必须保证新任期已经落盘
+            if let Err(e) = self.metadata.sync_durable().await {
+                error!("AE: failed to durably persist new term {}: {}. Refusing request.", request.term, e);
+                return refuse_resp;
+            }
+        } else if self.state == State::Leader && request.leader_id != self.server_id {
+            // 自己是Leader，但收到同任期的另一个Leader的心跳，这是一种分区情况，需要退位
+            info!("Leader received AR from another leader {} in same term {}. Stepping down.", request.leader_id, request.term);
+            Box::pin(self.step_down(request.term, audit::AuditReason::LeaderSeen)).await;
+        }
+
+        self.election_timer.lock().await.reset(util::rand_election_timeout());
+        self.set_leader_id(request.leader_id);
+        self.last_leader_contact = self.clock.now();
+
+        if request.prev_log_index > 0 {
+            if request.prev_log_index < self.log.start_index() {
+                if request.prev_log_index == self.snapshot.last_included_index() {
+                    if request.prev_log_term != self.snapshot.last_included_term() {
+                        warn!("AE Refused: prev_log_index {} is snapshot's last, but term mismatch (req_term: {}, snap_term: {})",
+                              request.prev_log_index, request.prev_log_term, self.snapshot.last_included_term());
+                        return refuse_resp;
+                    }
+                } else {
+                     debug!("AE: prev_log_index {} is within current snapshot (ends at {}). Assuming term match for consistency check up to snapshot.",
+                           request.prev_log_index, self.snapshot.last_included_index());
+                }
+            } else {
+                match self.log.entry(request.prev_log_index) {
+                    Some(local_prev_entry) => {
+                        if local_prev_entry.term != request.prev_log_term {
+                            warn!("AE Refused: Log mismatch at index {}. Local term: {}, Request's prev_log_term: {}",
+                                  request.prev_log_index, local_prev_entry.term, request.prev_log_term);
+                            warn!("Local log state: start_index={}, last_index={}", self.log.start_index(), self.log.last_index(self.snapshot.last_included_index()));
+                            return refuse_resp;
+                        }
+                    }
+                    None => {
+                        warn!("AE Refused: Log doesn't contain prev_log_index {}. Local last_index: {}",
+                              request.prev_log_index, self.log.last_index(self.snapshot.last_included_index()));
+                        return refuse_resp;
+                    }
+                }
+            }
+        }
+
+        if !request.entries.is_empty() {
+            let appended = match self.log.reconcile(&request.entries, self.commit_index) {
+                Ok(appended) => appended,
+                Err(e) => {
+                    error!("AE Refused: {}", e);
+                    return refuse_resp;
+                }
+            };
+            if !appended.is_empty() {
+                info!("Appended {} new entries from leader. New last_index: {}", appended.len(), self.log.last_index(self.snapshot.last_included_index()));
+                self.record_log_op(|| format!("follower append [{}, {}] from leader {} term {}",
+                    appended[0].index, appended[appended.len() - 1].index, request.leader_id, request.term));
+
+                for entry_being_applied in appended.iter() {
+                    if codec::entry_type(entry_being_applied) == Ok(proto::EntryType::Configuration) {
+                        let pending_config = config::Config::from_data(&entry_being_applied.data);
+                        self.apply_configuration_to_internal_state(entry_being_applied.index, pending_config, false).await;
+                    }
+                }
+            }
+        }
+
+        // 截断和追加的结果在回复Leader之前一次性落盘
+        self.log.persist();
+
+        // 只能提交到本次请求确认过的最后一条日志：过期的请求不会截断本地更长的日志，
+        // 之后的条目还没有和 Leader 核对过
+        let last_new_index = request.prev_log_index + request.entries.len() as u64;
+        let leader_commit = std::cmp::min(request.leader_commit, last_new_index);
+        if leader_commit > self.commit_index {
+            self.follower_advance_commit_index(leader_commit).await;
+        }
+
+        proto::AppendEntriesResponse {
+            // MODIFIED: Added .await
+            term: self.metadata.get().await.current_term,
+            success: true,
+            last_applied: self.last_applied,
+            leader_time_us: None,
+            follower_time_us: None,
+        }
+    }
+
+
+    pub async fn handle_install_snapshot_rpc(
+        &mut self,
+        request: &proto::InstallSnapshotRequest,
+    ) -> proto::InstallSnapshotResponse {
+        let resp = self.install_snapshot(request).await;
+        self.check_invariants("InstallSnapshot").await;
+        resp
+    }
+
+    async fn install_snapshot(
+        &mut self,
+        request: &proto::InstallSnapshotRequest,
+    ) -> proto::InstallSnapshotResponse {
+        let current_term_val = self.metadata.get().await.current_term;
+        if request.term < current_term_val {
+            info!("IS Refused: request term {} < current term {}", request.term, current_term_val);
+            return self.install_snapshot_rejected().await;
+        }
+
+        if request.term > current_term_val {
+            Box::pin(self.step_down(request.term, audit::AuditReason::HigherTermSeen)).await;
+            // 回复确认新任期之前，必须保证新任期已经落盘
+            if let Err(e) = self.metadata.sync_durable().await {
+                error!("IS: failed to durably persist new term {}: {}. Refusing request.", request.term, e);
+                return self.install_snapshot_rejected().await;
+            }
+        } else if self.state == State::Leader && request.leader_id != self.server_id {
+            info!("Leader received IS from another leader {} in same term {}. Stepping down. ", request.leader_id, request.term);
+            Box::pin(self.step_down(request.term, audit::AuditReason::LeaderSeen)).await;
+        }
+        self.election_timer.lock().await.reset(util::rand_election_timeout());
+        self.set_leader_id(request.leader_id);
+        self.last_leader_contact = self.clock.now();
+
+        // 校验分块是否是当前传输期望的下一个分块，不匹配时丢弃传输状态，让 Leader 从头重传
+        let next_transfer = match snapshot::SnapshotTransfer::accept_chunk(self.snapshot_transfer.as_ref(), request) {
+            Ok(transfer) => transfer,
+            Err(reason) => {
+                warn!("IS: rejecting chunk (LII {}, type {}, offset {}): {}", request.last_included_index, request.snapshot_data_type, request.offset, reason);
+                self.snapshot_transfer = None;
+                return self.install_snapshot_rejected().await;
+            }
+        };
+
+        // File I/O is sync; consider spawn_blocking for very large chunks/files.
+        let tmp_filepath_str = match next_transfer.data_type {
+            proto::SnapshotDataType::Metadata => self.snapshot.gen_tmp_snapshot_metadata_filepath(
+                request.last_included_index, request.last_included_term
+            ),
+            proto::SnapshotDataType::Snapshot => self.snapshot.gen_tmp_snapshot_filepath(
+                request.last_included_index, request.last_included_term
+            ),
+        };
+        // 在写入文件前，确保父目录存在
+        if let Some(parent_dir) = std::path::Path::new(&tmp_filepath_str).parent() {
+            if !parent_dir.exists() {
+                if let Err(e) = std::fs::create_dir_all(parent_dir) {
+                    error!("Failed to create parent directory for snapshot file {}: {}", parent_dir.display(), e);
+                    self.snapshot_transfer = None;
+                    return self.install_snapshot_rejected().await;
+                }
+            }
+        }
+        // 每个文件的第一个分块重新创建文件，之后的分块按顺序追加
+        let open_result = if request.offset == 0 {
+            std::fs::File::create(&tmp_filepath_str)
+        } else {
+            std::fs::OpenOptions::new().append(true).open(&tmp_filepath_str)
+        };
+        let write_result = open_result.and_then(|mut file| file.write_all(&request.data));
+        if let Err(e) = write_result {
+            error!("Failed to write tmp snapshot file {}: {}", tmp_filepath_str, e);
+            self.snapshot_transfer = None;
+            return self.install_snapshot_rejected().await;
+        }
+        let next_offset = next_transfer.next_offset;
+        let (transfer_leader, transfer_term) = (next_transfer.leader_id, next_transfer.leader_term);
+        self.snapshot_transfer = Some(next_transfer);
+
+
+        if request.done {
+            info!("InstallSnapshot: received final chunk for LII {}, LIT {}.", request.last_included_index, request.last_included_term);
+            // 覆盖本地快照之前再确认一次，发起传输的 Leader 仍然是当前任期的 Leader
+            let current_term = self.metadata.get().await.current_term;
+            if transfer_term != current_term || self.leader_id != transfer_leader {
+                warn!("IS: not finalizing snapshot from leader {} in term {}, current leader {} in term {}.",
+                    transfer_leader, transfer_term, self.leader_id, current_term);
+                self.abandon_snapshot_transfer("leadership changed before finalization");
+                return self.install_snapshot_rejected().await;
+            }
+            let final_meta_path_str = self.snapshot.gen_snapshot_metadata_filepath(request.last_included_index, request.last_included_term); // Renamed
+            let final_snap_path_str = self.snapshot.gen_snapshot_filepath(request.last_included_index, request.last_included_term); // Renamed
+            let tmp_meta_path_str = self.snapshot.gen_tmp_snapshot_metadata_filepath(request.last_included_index, request.last_included_term); // Renamed
+            let tmp_snap_path_str = self.snapshot.gen_tmp_snapshot_filepath(request.last_included_index, request.last_included_term); // Renamed
+
+            // 覆盖本地快照之前确认状态机能够解释收到的快照，不兼容时保留原有的快照并拒绝安装；
+            // Leader 会继续重试，直到本节点升级或回滚到兼容的版本
+            // 无法解析的元数据同样拒绝，无法确认快照的版本
+            let received_meta = std::fs::read_to_string(&tmp_meta_path_str).map_err(|e| e.to_string())
+                .and_then(|json| snapshot::SnapshotMeta::parse(&json))
+                .map_err(|e| format!("cannot read metadata of snapshot ({}, {}): {}", request.last_included_index, request.last_included_term, e));
+            let received_meta = match received_meta.and_then(|meta| self.check_snapshot_version(&meta).map(|_| meta)) {
+                Ok(meta) => meta,
+                Err(reason) => {
+                    self.mark_incompatible_snapshot(request.last_included_index, reason);
+                    self.abandon_snapshot_transfer("state machine is not compatible with the snapshot version");
+                    return self.install_snapshot_rejected().await;
+                }
+            };
+            // 传输中损坏的数据不能覆盖本地快照，丢弃后由 Leader 重新发送
+            if !received_meta.verify_checksum(&tmp_snap_path_str) {
+                warn!("IS: received snapshot ({}, {}) does not match the checksum recorded in its metadata.",
+                    request.last_included_index, request.last_included_term);
+                self.abandon_snapshot_transfer("checksum mismatch");
+                return self.install_snapshot_rejected().await;
+            }
+
+            // These renames should be atomic if on the same filesystem.
+            if let Err(e) = std::fs::rename(&tmp_meta_path_str, &final_meta_path_str) {
+                error!("Failed to rename temp metadata snapshot {} to {}: {}", tmp_meta_path_str, final_meta_path_str, e);
+            }
+            if let Err(e) = std::fs::rename(&tmp_snap_path_str, &final_snap_path_str) {
+                error!("Failed to rename temp data snapshot {} to {}: {}", tmp_snap_path_str, final_snap_path_str, e);
+            }
+
+            self.snapshot.reload_metadata(); // Assumes this reads the new final files
+
+            let restored = match self.snapshot.latest_snapshot_filepath() {
+                Some(snap_file_to_restore) => {
+                    info!("Restoring state machine from received snapshot: {}", snap_file_to_restore);
+                    self.restore_state_machine(&snap_file_to_restore).await
+                }
+                None => {
+                    error!("Installed snapshot ({}, {}) has no snapshot file.", request.last_included_index, request.last_included_term);
+                    false
+                }
+            };
+
+            // 和启动时一样：状态机没有从快照恢复时不推进 last_applied，应用停在快照之前
+            self.commit_index = self.snapshot.last_included_index();
+            if restored {
+                if self.snapshot.last_included_index() > self.last_applied {
+                    let gap = proto::AppliedGap { from: self.last_applied + 1, to: self.snapshot.last_included_index() };
+                    warn!("Installed snapshot skipped applying entries [{}, {}] individually.", gap.from, gap.to);
+                    self.events.publish(events::RaftEvent::AppliedGapSkipped { from: gap.from, to: gap.to });
+                    self.last_applied_gap = Some(gap);
+                }
+                self.set_last_applied(self.snapshot.last_included_index());
+            }
+
+            if let Some(conf) = self.snapshot.configuration() {
+                self.current_config = conf.clone();
+                self.config_index = self.snapshot.meta().config_index();
+                self.update_peer_config_states();
+                self.check_advertised_address();
+            }
+
+            self.log.truncate_prefix(self.snapshot.last_included_index());
+            self.log.persist();
+            self.record_log_op(|| format!("installed snapshot up to {}", self.snapshot.last_included_index()));
+            self.publish_view();
+            info!("Successfully processed installed snapshot. commit_idx={}, applied_idx={}", self.commit_index, self.last_applied);
+            self.snapshot_transfer = None;
+        }
+        // MODIFIED: Added .await
+        proto::InstallSnapshotResponse { term: self.metadata.get().await.current_term, success: true, next_offset }
+    }
+
+    // 快照元数据中的版本不被状态机接受时返回原因
+    fn check_snapshot_version(&self, meta: &snapshot::SnapshotMeta) -> Result<(), String> {
+        let version = meta.state_machine_version.as_deref();
+        if self.state_machine.compatible_with(version) {
+            return Ok(());
+        }
+        Err(format!("state machine is not compatible with snapshot ({}, {}) of version {}",
+            meta.last_included_index, meta.last_included_term, version.unwrap_or("<none>")))
+    }
+
+    fn mark_incompatible_snapshot(&mut self, last_included_index: u64, reason: String) {
+        self.incompatible_snapshot_index = last_included_index;
+        if self.incompatible_snapshot.as_ref() != Some(&reason) {
+            error!("Refusing to restore snapshot: {}.", reason);
+            self.incompatible_snapshot = Some(reason);
+            self.publish_view();
+        }
+    }
+
+    // 在阻塞线程池中从快照恢复状态机，大快照不会阻塞异步运行时；进度写入 restore_progress。
+    // 状态机拒绝快照的版本、快照文件与校验和不一致或者无法解压时不恢复，把节点标记为 IncompatibleSnapshot 并返回 false
+    async fn restore_state_machine(&mut self, snapshot_filepath: &str) -> bool {
+        if let Err(reason) = self.check_snapshot_version(self.snapshot.meta()) {
+            self.mark_incompatible_snapshot(self.snapshot.last_included_index(), reason);
+            return false;
+        }
+        if !self.snapshot.verify_checksum(snapshot_filepath) {
+            let reason = format!("snapshot file '{}' does not match the checksum recorded in its metadata", snapshot_filepath);
+            self.mark_incompatible_snapshot(self.snapshot.last_included_index(), reason);
+            return false;
+        }
+        self.chunk_assembler.discard(self.snapshot.last_included_index());
+        // 压缩的快照先解压到临时文件，状态机和恢复进度都只看到解压后的数据
+        let unpack_filepath = snapshot_filepath.to_string();
+        let plain_filepath = match tokio::task::spawn_blocking(move || crash::scope_blocking(|| compress::unpack_for_restore(&unpack_filepath))).await {
+            Ok(Ok(plain_filepath)) => plain_filepath,
+            Ok(Err(e)) => {
+                let reason = format!("failed to decompress snapshot file '{}': {}", snapshot_filepath, e);
+                self.mark_incompatible_snapshot(self.snapshot.last_included_index(), reason);
+                return false;
+            }
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        };
+        let restore_filepath = plain_filepath.clone().unwrap_or_else(|| snapshot_filepath.to_string());
+        let total_bytes = std::fs::metadata(&restore_filepath).map_or(0, |m| m.len());
+        self.restore_progress.start(total_bytes);
+        // 恢复期间状态机交给阻塞线程，这里先放一个空的占位，恢复结束后通过 oneshot 交回（见 PendingRestore）
+        let mut state_machine: Box<dyn state_machine::StateMachine> =
+            std::mem::replace(&mut self.state_machine, Box::new(state_machine::SimpleStateMachine::new()));
+        let progress = Arc::clone(&self.restore_progress);
+        let (tx, rx) = oneshot::channel();
+        tokio::task::spawn_blocking(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| crash::scope_blocking(|| {
+                state_machine.restore_snapshot_with_progress(&restore_filepath, &progress);
+                state_machine
+            })));
+            if let Some(plain_filepath) = plain_filepath {
+                let _ = std::fs::remove_file(plain_filepath);
+            }
+            let _ = tx.send(result);
+        });
+        let mut pending = PendingRestore { rx: Some(rx), consensus: self.self_ref.clone() };
+        let result = pending.rx.as_mut().expect("restore receiver is set above").await;
+        pending.rx = None;
+        self.restore_progress.finish();
+        match result {
+            Ok(Ok(state_machine)) => self.state_machine = state_machine,
+            // 状态机恢复失败时原本会直接 panic，这里保持同样的行为
+            Ok(Err(panic)) => std::panic::resume_unwind(panic),
+            Err(_) => panic!("snapshot restore task was dropped before finishing"),
+        }
+        if let Some(progress) = self.restore_progress.to_proto() {
+            info!("Restored state machine from {}: {}/{} bytes, {} entries in {}ms",
+                snapshot_filepath, progress.bytes_restored, progress.total_bytes, progress.entries_loaded, progress.elapsed_ms);
+        }
+        if self.incompatible_snapshot.take().is_some() {
+            self.publish_view();
+        }
+        true
+    }
+
+    // 放弃正在接收的快照传输并删除已经写入的临时文件
+    fn abandon_snapshot_transfer(&mut self, reason: &str) {
+        let Some(transfer) = self.snapshot_transfer.take() else {
+            return;
+        };
+        info!("IS: abandoning snapshot transfer ({}, {}) from leader {} in term {}: {}",
+            transfer.last_included_index, transfer.last_included_term, transfer.leader_id, transfer.leader_term, reason);
+        for tmp_filepath in [
+            self.snapshot.gen_tmp_snapshot_metadata_filepath(transfer.last_included_index, transfer.last_included_term),
+            self.snapshot.gen_tmp_snapshot_filepath(transfer.last_included_index, transfer.last_included_term),
+        ] {
+            if let Err(e) = std::fs::remove_file(&tmp_filepath) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("IS: failed to remove tmp snapshot file {}: {}", tmp_filepath, e);
+                }
+            }
+        }
+    }
+
+    async fn install_snapshot_rejected(&mut self) -> proto::InstallSnapshotResponse {
+        proto::InstallSnapshotResponse { term: self.metadata.get().await.current_term, success: false, next_offset: 0 }
+    }
+
+    // Leader检查是否能联系到多数派，Follower/Candidate检查最近是否收到过Leader消息
+    pub fn cluster_status(&self) -> proto::ClusterStatus {
+        let available = match self.state {
+            State::Leader => self.peer_manager.quorum_active(&self.node_config_state, self.clock.now(), config::CLUSTER_UNAVAILABLE_TIMEOUT),
+            State::Follower | State::Candidate => self.clock.now().saturating_duration_since(self.last_leader_contact) < config::CLUSTER_UNAVAILABLE_TIMEOUT,
+        };
+        if available {
+            proto::ClusterStatus::ClusterAvailable
+        } else {
+            proto::ClusterStatus::ClusterUnavailable
+        }
+    }
+
+    // 等待状态机应用到 read_token，超时返回 false；等待期间不持有共识模块的锁
+    pub async fn wait_for_applied(consensus: &Arc<TokioMutex<Consensus>>, read_token: u64, timeout: Duration) -> bool {
+        Consensus::wait_for_applied_until(consensus, read_token, tokio::time::Instant::now() + timeout).await
+    }
+
+    pub async fn wait_for_applied_until(consensus: &Arc<TokioMutex<Consensus>>, read_token: u64, deadline: tokio::time::Instant) -> bool {
+        let mut applied_rx = consensus.lock().await.applied_tx.subscribe();
+        let result = tokio::time::timeout_at(deadline, applied_rx.wait_for(|applied| *applied >= read_token)).await;
+        matches!(result, Ok(Ok(_)))
+    }
+
+    pub fn subscribe_applied(&self) -> watch::Receiver<u64> {
+        self.applied_tx.subscribe()
+    }
+
+    // 已提交条目订阅的一批数据：从 from 开始最多 max_entries 个、数据合计不超过 max_bytes 的已应用条目（至少一个），
+    // 还没有应用到 from 时为空；from 之前的条目已被快照压缩时返回 snapshot_required
+    pub fn committed_entries_from(&self, from: u64, max_entries: usize, max_bytes: usize) -> proto::SubscribeCommittedResponse {
+        let mut response = proto::SubscribeCommittedResponse {
+            last_applied: self.last_applied,
+            snapshot_last_included_index: self.snapshot.last_included_index(),
+            ..Default::default()
+        };
+        if from < self.log.start_index() {
+            response.snapshot_required = true;
+            return response;
+        }
+        let end = self.last_applied.min(from.saturating_add(max_entries as u64 - 1));
+        let mut bytes = 0;
+        for entry in (from..=end).map_while(|index| self.log.entry(index)) {
+            bytes += entry.data.len();
+            if bytes > max_bytes && !response.entries.is_empty() {
+                break;
+            }
+            response.entries.push(entry.clone());
+        }
+        response
+    }
+
+    fn set_last_applied(&mut self, index: u64) {
+        self.last_applied = index;
+        self.snapshot_check_due.store(true, std::sync::atomic::Ordering::Release);
+        if let Some(recorder) = &self.crash_recorder {
+            recorder.update(|report| report.last_applied = index);
+        }
+        if self.log_gap.is_some_and(|gap| gap <= index) {
+            info!("Applied past the missing entry {}, node is healthy again.", self.log_gap.unwrap_or_default());
+            self.log_gap = None;
+            self.publish_view();
+        }
+        // 逐条应用追上了被拒绝的快照（例如其他节点重新复制了日志），状态机已经包含快照的内容
+        if self.incompatible_snapshot.is_some() && index >= self.incompatible_snapshot_index {
+            info!("Applied past the refused snapshot at index {}, node is healthy again.", self.incompatible_snapshot_index);
+            self.incompatible_snapshot = None;
+            self.publish_view();
+        }
+        self.applied_tx.send_replace(index);
+        if let Some(dispatcher) = &self.commit_sink {
+            dispatcher.applied(index);
+        }
+        if self.chunk_assembler.pending().is_none() {
+            self.state_machine.set_applied_index(index);
+        }
+        // 已应用范围内仍在等待的 Propose 不会再有结果（条目被覆盖或被快照跳过），
+        // 丢弃通道让调用方立即返回
+        self.apply_waiters = self.apply_waiters.split_off(&(index + 1));
+    }
+
+    // 把数据交给状态机应用，收集状态机提交的后续命令；只有 Leader 上的后续命令会进入队列。
+    // entry 是实际找到的条目（可能来自日志文件，不在内存中），分块数据时是最后一块
+    fn apply_data(&mut self, entry: &proto::LogEntry, data: &Vec<u8>) -> Vec<u8> {
+        let index = entry.index;
+        let depth = self.follow_up_depths.remove(&index).unwrap_or(0);
+        let mut ctx = state_machine::ApplyContext::new(index, self.state == State::Leader, entry.timestamp_ms, depth);
+        let result = self.state_machine.apply_with_context(data, &mut ctx);
+        if let Some(dispatcher) = &self.commit_sink {
+            dispatcher.deliver(sink::CommitRecord { index, term: entry.term, data: data.clone(), result: Some(result.clone()) });
+        }
+        let follow_ups = ctx.into_follow_ups();
+        if follow_ups.is_empty() {
+            return result;
+        }
+        for data in follow_ups {
+            if self.follow_up_queue.len() >= config::FOLLOW_UP_QUEUE_CAPACITY {
+                warn!("Dropping follow-up command of entry {}: queue is full ({} pending).", index, self.follow_up_queue.len());
+                self.metrics.follow_ups_dropped.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                continue;
+            }
+            self.follow_up_queue.push_back((depth + 1, data));
+        }
+        self.schedule_follow_ups();
+        result
+    }
+
+    // 启动后台任务 Propose 队列中的后续命令，同一时间最多一个。
+    // 不在应用过程中直接 Propose：单节点集群在 Propose 内部就会应用新条目，会形成递归
+    fn schedule_follow_ups(&mut self) {
+        if self.follow_up_scheduled {
+            return;
+        }
+        let Some(consensus) = self.self_ref.upgrade() else {
+            return;
+        };
+        self.follow_up_scheduled = true;
+        tokio::spawn(crash::scope(async move {
+            tokio::task::yield_now().await;
+            loop {
+                let mut guard = consensus.lock().await;
+                if !guard.propose_follow_ups().await {
+                    guard.follow_up_scheduled = false;
+                    return;
+                }
+                // 被限流时释放锁，等待之后重试
+                drop(guard);
+                tokio::time::sleep(config::CLIENT_PROPOSE_BACKOFF).await;
+            }
+        }));
+    }
+
+    // Propose 队列中的后续命令，被限流需要稍后重试时返回 true
+    async fn propose_follow_ups(&mut self) -> bool {
+        while let Some((depth, data)) = self.follow_up_queue.pop_front() {
+            if self.state != State::Leader {
+                let dropped = self.follow_up_queue.len() as u64 + 1;
+                warn!("Dropping {} follow-up commands: no longer the leader.", dropped);
+                self.metrics.follow_ups_dropped.fetch_add(dropped, std::sync::atomic::Ordering::Relaxed);
+                self.follow_up_queue.clear();
+                return false;
+            }
+            // 单节点集群在 Propose 内部就会应用新条目，层数要在 Propose 之前登记
+            let index = self.log.last_index(self.snapshot.last_included_index()) + self.proposal_entry_count(data.len()) as u64;
+            self.follow_up_depths.insert(index, depth);
+            let request = proto::ProposeRequest { data, wait_for_commit: false };
+            let resp = self.handle_propose_rpc(&request).await;
+            if resp.success {
+                self.metrics.follow_ups_proposed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                continue;
+            }
+            self.follow_up_depths.remove(&index);
+            if resp.reject_reason == proto::ProposeRejectReason::Backpressure as i32 {
+                debug!("Follow-up command throttled, {} pending.", self.follow_up_queue.len() + 1);
+                self.follow_up_queue.push_front((depth, request.data));
+                return true;
+            }
+            warn!("Dropping follow-up command: propose rejected with reason {}.", resp.reject_reason);
+            self.metrics.follow_ups_dropped.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        false
+    }
+
+    // 已提交的条目类型无法识别：不能猜测它的含义，跳过它并发出事件，由运维决定是否升级本节点
+    fn skip_unknown_entry(&mut self, index: u64, entry_type: i32, reason: &str) {
+        error!("Skipping committed entry {}: {}. This node may be older than the one that wrote it.", index, reason);
+        self.chunk_assembler.discard(index);
+        self.events.publish(events::RaftEvent::UnknownEntrySkipped { index, entry_type });
+    }
+
+    // 启动投递任务，把水位线之后已经应用过的数据条目从日志中重新投递一遍
+    pub fn attach_commit_sink(&mut self, commit_sink: Arc<dyn sink::CommitSink>, metadata_dir: String) {
+        let watermark = sink::load_watermark(&metadata_dir);
+        let first_index = watermark + 1;
+        if first_index < self.log.start_index() && first_index <= self.last_applied {
+            warn!("Commit sink watermark {} is behind the log start {}, entries in between will not be delivered.", watermark, self.log.start_index());
+        }
+        let mut assembler = codec::ChunkAssembler::default();
+        let mut backlog = Vec::new();
+        for index in first_index.max(self.log.start_index())..=self.last_applied {
+            let Some(entry) = self.log.entry(index) else {
+                continue;
+            };
+            let data = match codec::entry_type(entry) {
+                Ok(proto::EntryType::Data) => Some(entry.data.clone()),
+                Ok(proto::EntryType::DataChunk) => assembler.push(index, &entry.data),
+                _ => None,
+            };
+            if let Some(data) = data {
+                backlog.push(sink::CommitRecord { index, term: entry.term, data, result: None });
+            }
+        }
+        let dispatcher = sink::SinkDispatcher::spawn(commit_sink, metadata_dir, backlog);
+        if self.last_applied > watermark {
+            info!("Commit sink resuming after watermark {}, redelivering up to index {}.", watermark, self.last_applied);
+        }
+        dispatcher.applied(self.last_applied);
+        self.commit_sink = Some(dispatcher);
+    }
+
+    // 分块交给 ChunkAssembler，收齐最后一块时把完整的数据应用到状态机，结果交给等待最后一块的 Propose
+    fn apply_chunk(&mut self, entry: &proto::LogEntry) {
+        if let Some(payload) = self.chunk_assembler.push(entry.index, &entry.data) {
+            let result = self.apply_data(entry, &payload);
+            self.notify_apply_waiter(entry.index, entry.term, result);
         }
+    }
 
-        self.election_timer.lock().await.reset(util::rand_election_timeout());
-        self.leader_id = request.leader_id;
+    // 一次 Propose 会追加的条目数，超过条目上限且开启分块时大于1
+    pub fn proposal_entry_count(&self, payload_len: usize) -> usize {
+        if self.chunk_large_entries && payload_len > self.max_entry_size {
+            codec::chunk_count(payload_len, self.max_entry_size)
+        } else {
+            1
+        }
+    }
 
-        if request.prev_log_index > 0 {
-            if request.prev_log_index < self.log.start_index() {
-                if request.prev_log_index == self.snapshot.last_included_index {
-                    if request.prev_log_term != self.snapshot.last_included_term {
-                        warn!("AE Refused: prev_log_index {} is snapshot's last, but term mismatch (req_term: {}, snap_term: {})",
-                              request.prev_log_index, request.prev_log_term, self.snapshot.last_included_term);
-                        return refuse_resp;
-                    }
-                } else {
-                     debug!("AE: prev_log_index {} is within current snapshot (ends at {}). Assuming term match for consistency check up to snapshot.",
-                           request.prev_log_index, self.snapshot.last_included_index);
-                }
+    // 把状态机的执行结果交给等待该条目的 Propose；任期不同说明原来的条目已被新 Leader 覆盖
+    fn notify_apply_waiter(&mut self, index: u64, term: u64, result: Vec<u8>) {
+        if let Some((waiter_term, tx)) = self.apply_waiters.remove(&index) {
+            if waiter_term == term {
+                let _ = tx.send(result);
             } else {
-                match self.log.entry(request.prev_log_index) {
-                    Some(local_prev_entry) => {
-                        if local_prev_entry.term != request.prev_log_term {
-                            warn!("AE Refused: Log mismatch at index {}. Local term: {}, Request's prev_log_term: {}",
-                                  request.prev_log_index, local_prev_entry.term, request.prev_log_term);
-                            warn!("Local log state: start_index={}, last_index={}", self.log.start_index(), self.log.last_index(self.snapshot.last_included_index));
-                            return refuse_resp;
-                        }
-                    }
-                    None => {
-                        warn!("AE Refused: Log doesn't contain prev_log_index {}. Local last_index: {}",
-                              request.prev_log_index, self.log.last_index(self.snapshot.last_included_index));
-                        return refuse_resp;
-                    }
-                }
+                debug!("Entry {} was overwritten (proposed in term {}, committed in term {}), dropping its waiter.", index, waiter_term, term);
             }
         }
+    }
 
-        if !request.entries.is_empty() {
-            // Conflict check needs to compare against the first entry in the request.
-            // If request.entries[0].index exists in log and terms differ, truncate.
-            let first_new_entry_index_in_request = request.entries[0].index;
-            if let Some(existing_entry_at_conflict) = self.log.entry(first_new_entry_index_in_request) {
-                if existing_entry_at_conflict.term != request.entries[0].term {
-                    info!("Conflict detected at index {}. Deleting suffix from log index {}.",
-                          first_new_entry_index_in_request, first_new_entry_index_in_request -1); // Truncate *before* this index
-                    self.log.truncate_suffix(first_new_entry_index_in_request - 1);
-                }
-            }
+    pub fn handle_read_rpc(
+        &self,
+        request: &proto::ReadRequest,
+    ) -> proto::ReadResponse {
+        let cluster_status = self.cluster_status();
+        let leader_addr = if self.state == State::Leader {
+            Some(self.server_addr.clone())
+        } else {
+            self.peer_manager.peers().iter()
+                .find(|p| p.id == self.leader_id)
+                .map(|p| p.addr.clone())
+        };
+        let mut resp = proto::ReadResponse {
+            success: false,
+            data: Vec::new(),
+            cluster_status: cluster_status as i32,
+            degraded: false,
+            leader_addr,
+            applied_index: self.last_applied,
+        };
+        if self.restore_progress.in_progress() {
+            return resp;
         }
 
-
-        if !request.entries.is_empty() {
-            let mut entries_to_add_to_log = Vec::new();
-            for entry_from_req in request.entries.iter() {
-                if entry_from_req.index > self.log.last_index(self.snapshot.last_included_index) ||
-                   self.log.entry(entry_from_req.index).map_or(true, |e| e.term != entry_from_req.term) {
-                    entries_to_add_to_log.push(entry_from_req.clone());
-                }
+        // 已提交的条目还在分批应用时，本地状态落后于提交位置，不能当作线性一致读；
+        // 时钟偏差超过上限时不能依赖联系得上多数派这一租约
+        let serve_normally = self.state == State::Leader
+            && cluster_status == proto::ClusterStatus::ClusterAvailable
+            && self.last_applied >= self.commit_index
+            && self.clock_skew_within_bound();
+        // 会话一致性读：本地已经应用到客户端上次写入（或读到）的位置，任何节点都可以直接读取
+        let token_satisfied = request.read_token.is_some_and(|token| self.last_applied >= token);
+        if !serve_normally && !token_satisfied {
+            if !request.allow_degraded {
+                debug!("Read refused: state {:?}, cluster status {:?}, degraded reads not allowed.", self.state, cluster_status);
+                return resp;
             }
-            if !entries_to_add_to_log.is_empty() {
-                self.log.append_entries(entries_to_add_to_log.clone());
-                info!("Appended {} new entries from leader. New last_index: {}", entries_to_add_to_log.len(), self.log.last_index(self.snapshot.last_included_index));
+            // 显式的降级只读模式：读取本地状态，不保证线性一致
+            warn!("Serving degraded (non-linearizable) read: state {:?}, cluster status {:?}", self.state, cluster_status);
+            resp.degraded = true;
+        }
 
-                for entry_being_applied in entries_to_add_to_log.iter() {
-                    if proto::EntryType::from_i32(entry_being_applied.entry_type) == Some(proto::EntryType::Configuration) {
-                        let pending_config = config::Config::from_data(&entry_being_applied.data);
-                        self.apply_configuration_to_internal_state(pending_config, false).await;
-                    }
-                }
+        match self.state_machine.read(&request.query) {
+            Some(data) => {
+                resp.success = true;
+                resp.data = data;
             }
+            None => warn!("Read failed: state machine does not support reads."),
         }
+        resp
+    }
 
-        if request.leader_commit > self.commit_index {
-            self.follower_advance_commit_index(request.leader_commit).await;
+    pub fn handle_get_leader_rpc(
+        &self,
+        _request: &proto::GetLeaderRequest,
+    ) -> proto::GetLeaderResponse {
+        proto::GetLeaderResponse {
+            leader: self.leader_info(),
+            redirect_to: None,
+            term: self.metadata.current_term(),
+            commit_index: self.commit_index,
         }
+    }
 
-        proto::AppendEntriesResponse {
-            // MODIFIED: Added .await
-            term: self.metadata.get().await.current_term,
-            success: true,
+    pub async fn handle_get_configuration_rpc(
+        &mut self,
+        request: &proto::GetConfigurationRequest,
+    ) -> proto::GetConfigurationResponse {
+        let leader = self.leader_info();
+        let algorithm = proto::MembershipAlgorithm::from(self.current_config.algorithm) as i32;
+        if request.consistent && !self.confirm_leadership().await {
+            debug!("Consistent GetConfiguration refused: state {:?}, leader {:?}", self.state, leader);
+            return proto::GetConfigurationResponse { servers: Vec::new(), success: false, leader, health: self.health() as i32, algorithm };
         }
+        proto::GetConfigurationResponse { servers: self.current_config.all_servers_in_config(), success: true, leader, health: self.health() as i32, algorithm }
     }
 
+    // ReadIndex 式的领导权确认：发送一轮心跳，新旧配置的多数派在此之后都有回应，
+    // 说明没有更高任期的 Leader 出现，此时本地的状态是最新的
+    pub async fn confirm_leadership(&mut self) -> bool {
+        if self.state != State::Leader {
+            return false;
+        }
+        let since = self.clock.now();
+        self.append_entries_to_peers(true).await;
+        self.state == State::Leader && self.peer_manager.quorum_contacted_since(&self.node_config_state, since)
+    }
 
-    pub async fn handle_install_snapshot_rpc(
+    // 把一次管理操作连同请求参数和结果记录到本节点的命令审计日志，caller 为调用方地址，进程内调用时为 local
+    pub fn audit_command<R: std::fmt::Debug>(&mut self, caller: &str, command: proto::ManagementCommand, request: &R, success: bool, error: Option<String>) {
+        self.command_audit.record(caller, command, format!("{:?}", request), success, error);
+    }
+
+    // 审计文件在释放锁之后读取，解析几 MB 的记录不会阻塞共识模块
+    pub async fn get_command_audit(consensus: &TokioMutex<Consensus>, request: &proto::GetCommandAuditRequest) -> proto::GetCommandAuditResponse {
+        let max_records = match request.max_records as usize {
+            0 => config::COMMAND_AUDIT_PAGE_SIZE,
+            n => n.min(config::COMMAND_AUDIT_MAX_PAGE_SIZE),
+        };
+        let reader = consensus.lock().await.command_audit.reader();
+        let start_seq = request.start_seq;
+        tokio::task::spawn_blocking(move || reader.page(start_seq, max_records)).await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    pub fn handle_get_configuration_history_rpc(
+        &self,
+        _request: &proto::GetConfigurationHistoryRequest,
+    ) -> proto::GetConfigurationHistoryResponse {
+        proto::GetConfigurationHistoryResponse { changes: self.config_history.changes() }
+    }
+
+    pub async fn handle_set_configuration_rpc(
         &mut self,
-        request: &proto::InstallSnapshotRequest,
-    ) -> proto::InstallSnapshotResponse {
-        let current_term_val = self.metadata.get().await.current_term;
-        if request.term < current_term_val {
-            info!("IS Refused: request term {} < current term {}", request.term, current_term_val);
-            return proto::InstallSnapshotResponse { term: self.metadata.get().await.current_term };
+        request: &proto::SetConfigurationRequest,
+    ) -> proto::SetConfigurationResponse {
+        let rejected = |error: String| {
+            error!("SetConfiguration failed: {}", error);
+            proto::SetConfigurationResponse {
+                success: false,
+                config_index: 0,
+                state: proto::ConfigChangeState::Unknown as i32,
+                error: Some(error),
+            }
+        };
+        if self.state != State::Leader {
+            return rejected("SetConfiguration can only be handled by the leader".to_string());
         }
 
-        if request.term > current_term_val {
-            Box::pin(self.step_down(request.term)).await;
-        } else if self.state == State::Leader && request.leader_id != self.server_id {
-            info!("Leader received IS from another leader {} in same term {}. Stepping down. ", request.leader_id, request.term);
-            Box::pin(self.step_down(request.term)).await;
+        if let Err(e) = config::Config::validate_servers(&request.new_servers) {
+            return rejected(format!("invalid new_servers {:?}: {}", request.new_servers, e));
+        }
+        if self.divergence.1 && self.divergence_alarm.as_ref().is_some_and(|alarm| alarm.block_membership_changes) {
+            return rejected(format!("the divergence alarm is raised, the slowest voter is {} entries behind",
+                self.metrics.voter_gap.load(std::sync::atomic::Ordering::Relaxed)));
         }
-        self.election_timer.lock().await.reset(util::rand_election_timeout());
-        self.leader_id = request.leader_id;
 
-        let data_type = proto::SnapshotDataType::from_i32(request.snapshot_data_type).unwrap_or(proto::SnapshotDataType::Snapshot);
+        if self.current_config.is_joint() {
+            return rejected("a joint consensus C(old,new) is already active and must be finalized first".to_string());
+        }
+        if let Some(last_log_cfg) = self.log.last_configuration() {
+            if last_log_cfg.is_joint() {
+                 return rejected("last configuration entry in log is C(old,new) and not yet committed/finalized".to_string());
+            }
+        }
 
-        // Snapshot file handling is complex and stateful across chunks.
-        // This is a simplified version. Robust impl needs careful state management for chunks.
-        // File I/O is sync; consider spawn_blocking for very large chunks/files.
-        let tmp_filepath_str = match data_type { // Renamed
-            proto::SnapshotDataType::Metadata => self.snapshot.gen_tmp_snapshot_metadata_filepath(
-                request.last_included_index, request.last_included_term
-            ),
-            proto::SnapshotDataType::Snapshot => self.snapshot.gen_tmp_snapshot_filepath(
-                request.last_included_index, request.last_included_term
-            ),
+        // 上一次变更的配置条目还没有提交时，不能切换算法，也不能发起单节点变更，两种算法的变更不会交错
+        let algorithm = match request.algorithm.map(proto::MembershipAlgorithm::try_from) {
+            Some(Ok(algorithm)) => config::MembershipAlgorithm::from(algorithm),
+            Some(Err(_)) => {
+                return rejected(format!("unknown membership algorithm {:?}", request.algorithm));
+            }
+            None => self.current_config.algorithm,
         };
-        // 在写入文件前，确保父目录存在
-        if let Some(parent_dir) = std::path::Path::new(&tmp_filepath_str).parent() {
-            if !parent_dir.exists() {
-                if let Err(e) = std::fs::create_dir_all(parent_dir) {
-                    error!("Failed to create parent directory for snapshot file {}: {}", parent_dir.display(), e);
-                    // 返回一个错误响应，而不是 panic
-                    return proto::InstallSnapshotResponse { term: self.metadata.get().await.current_term };
-                }
+        if let Some((pending_index, pending_config)) = self.log.last_configuration_entry().filter(|(index, _)| *index > self.commit_index) {
+            if algorithm == config::MembershipAlgorithm::SingleServer || algorithm != pending_config.algorithm {
+                return rejected(format!("configuration entry {} ({:?}) is not committed yet, cannot start a {:?} change",
+                    pending_index, pending_config.algorithm, algorithm));
             }
         }
-        let mut file_handle = match std::fs::OpenOptions::new() // 使用 match 替代 .unwrap()
-            .create(request.offset == 0)
-            .write(true)
-            .append(request.offset > 0) // 使用 append 模式更安全
-            .open(&tmp_filepath_str)
-        {
-            std::result::Result::Ok(file) => file,
-            Err(e) => {
-                error!("Failed to open/create tmp snapshot file {}: {}", tmp_filepath_str, e);
-                // 返回错误响应
-                return proto::InstallSnapshotResponse { term: self.metadata.get().await.current_term };
+        if algorithm == config::MembershipAlgorithm::SingleServer {
+            // 新 Leader 在本任期提交过条目之后才能做单节点变更，否则可能和上一任 Leader 未提交的变更冲突
+            let current_term = self.metadata.current_term();
+            let committed_term = self.log.entry(self.commit_index).map_or(self.snapshot.last_included_term(), |e| e.term);
+            if committed_term != current_term {
+                return rejected(format!("no entry of term {} is committed yet, cannot start a single-server change", current_term));
             }
-        };
-
-        if request.offset > 0 && data_type == proto::SnapshotDataType::Snapshot {
-        } else if request.offset > 0 {
-             file_handle.seek(std::io::SeekFrom::Start(request.offset)).unwrap();
         }
 
+        if let Some(zone) = zone::dominant_zone(&config::Config::new_stable(request.new_servers.clone())) {
+            warn!("SetConfiguration: zone {:?} holds a quorum by itself in the new configuration, losing it makes the cluster unavailable.", zone);
+        }
+        info!("Leader handling SetConfiguration request. New target servers: {:?} ({:?})", request.new_servers, algorithm);
+        // 复制过程中 C(old,new) 可能立即提交并追加 C(new)，所以在追加之前记下联合配置的索引
+        let config_index = self.log.last_index(self.snapshot.last_included_index()) + 1;
+        if let Err(e) = self.append_and_replicate_config_change(Some((request.new_servers.clone(), algorithm))).await {
+            return rejected(e);
+        }
 
-        file_handle.write_all(&request.data).unwrap();
+        proto::SetConfigurationResponse {
+            success: true,
+            config_index,
+            state: self.config_change_state(config_index) as i32,
+            error: None,
+        }
+    }
 
+    pub fn handle_get_config_change_status_rpc(
+        &self,
+        request: &proto::GetConfigChangeStatusRequest,
+    ) -> proto::GetConfigChangeStatusResponse {
+        proto::GetConfigChangeStatusResponse { state: self.config_change_state(request.config_index) as i32 }
+    }
 
-        if request.done {
-            info!("InstallSnapshot: received final chunk for LII {}, LIT {}.", request.last_included_index, request.last_included_term);
-            let final_meta_path_str = self.snapshot.gen_snapshot_metadata_filepath(request.last_included_index, request.last_included_term); // Renamed
-            let final_snap_path_str = self.snapshot.gen_snapshot_filepath(request.last_included_index, request.last_included_term); // Renamed
-            let tmp_meta_path_str = self.snapshot.gen_tmp_snapshot_metadata_filepath(request.last_included_index, request.last_included_term); // Renamed
-            let tmp_snap_path_str = self.snapshot.gen_tmp_snapshot_filepath(request.last_included_index, request.last_included_term); // Renamed
+    // 根据已提交的配置历史判断以 config_index 处的配置条目开始的配置变更进行到了哪一步
+    fn config_change_state(&self, config_index: u64) -> proto::ConfigChangeState {
+        let changes = self.config_history.changes();
+        if let Some(change) = changes.iter().find(|c| c.index == config_index) {
+            // 单节点变更的条目本身就是稳定配置，提交即完成；
+            // 联合配置之后提交的第一个稳定配置就是这次变更的 C(new)
+            if change.old_servers.is_empty() || changes.iter().any(|c| c.index > config_index && c.old_servers.is_empty()) {
+                return proto::ConfigChangeState::Completed;
+            }
+            return proto::ConfigChangeState::InProgress;
+        }
+        if config_index > self.commit_index {
+            return match self.log.entry(config_index) {
+                Some(entry) if entry.entry_type == proto::EntryType::Configuration as i32 => proto::ConfigChangeState::InProgress,
+                _ => proto::ConfigChangeState::Unknown,
+            };
+        }
+        // 已提交的范围内该索引不是配置条目，说明联合配置没有被提交就被覆盖了；
+        // 索引早于历史记录的起点时无法判断
+        if changes.first().is_some_and(|c| c.index < config_index) {
+            return proto::ConfigChangeState::Aborted;
+        }
+        proto::ConfigChangeState::Unknown
+    }
 
-            // These renames should be atomic if on the same filesystem.
-            if let Err(e) = std::fs::rename(&tmp_meta_path_str, &final_meta_path_str) {
-                error!("Failed to rename temp metadata snapshot {} to {}: {}", tmp_meta_path_str, final_meta_path_str, e);
+    // 等待配置变更结束（完成或被覆盖），超时返回当时的进度；等待期间不持有共识模块的锁
+    pub async fn wait_for_config_change(consensus: &Arc<TokioMutex<Consensus>>, config_index: u64, timeout: Duration) -> proto::ConfigChangeState {
+        let mut applied_rx = consensus.lock().await.applied_tx.subscribe();
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let state = consensus.lock().await.config_change_state(config_index);
+            if matches!(state, proto::ConfigChangeState::Completed | proto::ConfigChangeState::Aborted) {
+                return state;
             }
-            if let Err(e) = std::fs::rename(&tmp_snap_path_str, &final_snap_path_str) {
-                error!("Failed to rename temp data snapshot {} to {}: {}", tmp_snap_path_str, final_snap_path_str, e);
+            // 配置条目随日志一起应用，每次 last_applied 变化后重新检查
+            let changed = tokio::time::timeout_at(deadline, applied_rx.changed()).await;
+            if !matches!(changed, Ok(Ok(()))) {
+                return state;
             }
+        }
+    }
 
-            self.snapshot.reload_metadata(); // Assumes this reads the new final files
 
-            if let Some(snap_file_to_restore) = self.snapshot.latest_snapshot_filepath() { // Assumes &self
-                info!("Restoring state machine from received snapshot: {}", snap_file_to_restore);
-                self.state_machine.restore_snapshot(&snap_file_to_restore); // Pass as &str
-            }
 
-            self.commit_index = self.snapshot.last_included_index;
-            self.last_applied = self.snapshot.last_included_index;
 
-            if let Some(conf) = &self.snapshot.configuration {
-                self.current_config = conf.clone();
-                self.update_peer_config_states();
-            }
+    // 记录一次发往 peer_id 的 RPC 往返时间
+    pub fn record_peer_rtt(&mut self, peer_id: u64, rtt: Duration) {
+        if let Some(peer) = self.peer_manager.peer(peer_id) {
+            peer.rtt.record(rtt);
+        }
+    }
 
-            self.log.truncate_prefix(self.snapshot.last_included_index);
-            info!("Successfully processed installed snapshot. commit_idx={}, applied_idx={}", self.commit_index, self.last_applied);
+    pub fn handle_get_peer_latency_rpc(&self) -> proto::GetPeerLatencyResponse {
+        let peers = self.peer_manager.peers().iter().map(|p| proto::PeerLatency {
+            server_id: p.id,
+            median_rtt_us: p.rtt.median().map_or(0, |rtt| rtt.as_micros() as u64),
+            samples: p.rtt.samples() as u32,
+        }).collect();
+        proto::GetPeerLatencyResponse { server_id: self.server_id, peers }
+    }
+
+    // 替换被屏蔽的节点集合，返回生效后的集合
+    pub fn handle_set_partition_rpc(&mut self, request: &proto::SetPartitionRequest) -> proto::SetPartitionResponse {
+        self.partition.set(request.blocked_server_ids.iter().copied().filter(|id| *id != self.server_id));
+        let blocked_server_ids = self.partition.blocked();
+        if blocked_server_ids.is_empty() {
+            info!("Server {} partition healed.", self.server_id);
+        } else {
+            warn!("Server {} partitioned away from servers {:?}.", self.server_id, blocked_server_ids);
         }
-        // MODIFIED: Added .await
-        proto::InstallSnapshotResponse { term: self.metadata.get().await.current_term }
+        proto::SetPartitionResponse { blocked_server_ids }
     }
 
-    // These are synchronous handlers, as they don't await anything internally.
-    pub fn handle_get_leader_rpc(
-        &mut self, // &mut self is okay if PeerManager methods need it, but &self might be enough
-        _request: &proto::GetLeaderRequest,
-    ) -> proto::GetLeaderResponse {
-        if self.state == State::Leader {
-            return proto::GetLeaderResponse {
-                leader: Some(proto::ServerInfo {
-                    server_id: self.server_id,
-                    server_addr: self.server_addr.clone(),
-                }),
-                redirect_to: None,
-            };
+    // 收到 Leader 的 TimeoutNow，返回 true 时由调用方立即发起选举
+    pub async fn handle_timeout_now_rpc(&mut self, request: &proto::TimeoutNowRequest) -> proto::TimeoutNowResponse {
+        let current_term = self.metadata.get().await.current_term;
+        let rejected = proto::TimeoutNowResponse { term: current_term, success: false };
+        if request.term < current_term {
+            info!("TimeoutNow from {} refused: request term {} < current term {}", request.leader_id, request.term, current_term);
+            return rejected;
         }
-        if self.leader_id != config::NONE_SERVER_ID {
-            // Borrow immutably if possible
-            if let Some(peer) = self.peer_manager.peers().iter().find(|p| p.id == self.leader_id) {
-                 return proto::GetLeaderResponse {
-                    leader: Some(proto::ServerInfo {
-                        server_id: peer.id,
-                        server_addr: peer.addr.clone(),
-                    }),
-                    redirect_to: None,
-                };
-            } else if self.leader_id == self.server_id {
-                 return proto::GetLeaderResponse {
-                    leader: Some(proto::ServerInfo {
-                        server_id: self.server_id,
-                        server_addr: self.server_addr.clone(),
-                    }),
-                    redirect_to: None,
-                };
+        if self.shutting_down || self.non_candidate || !self.current_config.all_ids_in_config().contains(&self.server_id) {
+            info!("TimeoutNow from {} refused: shutting down, being decommissioned or not in the current configuration.", request.leader_id);
+            return rejected;
+        }
+        if request.term > current_term {
+            Box::pin(self.step_down(request.term, audit::AuditReason::HigherTermSeen)).await;
+        } else if self.state == State::Leader {
+            return rejected;
+        }
+        info!("TimeoutNow from leader {} in term {}, starting an election immediately.", request.leader_id, request.term);
+        proto::TimeoutNowResponse { term: request.term, success: true }
+    }
+
+    // 下线流程中 Leader 发来的通知，shutdown 为 true 时由调用方随后执行 retire
+    pub async fn handle_retire_rpc(&mut self, request: &proto::RetireRequest) -> proto::RetireResponse {
+        let current_term = self.metadata.get().await.current_term;
+        if request.term < current_term {
+            info!("Retire from {} refused: request term {} < current term {}", request.leader_id, request.term, current_term);
+            return proto::RetireResponse { term: current_term, success: false };
+        }
+        if request.shutdown {
+            // 关闭并清空数据之前确认本节点已被移出最新的配置，并且通知来自本节点认定的 Leader，
+            // 过期或发错的通知不能让仍在配置中的成员下线。收到不包含自己的 C(new) 时节点已经自行关闭，不再记得 Leader
+            let latest_config = self.log.last_configuration_entry().map_or_else(|| self.current_config.clone(), |(_, config)| config);
+            if latest_config.get_node_state(self.server_id).newing {
+                warn!("Retire from {} refused: node {} is still in its latest configuration.", request.leader_id, self.server_id);
+                return proto::RetireResponse { term: current_term, success: false };
+            }
+            if request.leader_id != self.leader_id && !self.shutting_down {
+                warn!("Retire from {} refused: the known leader is {}.", request.leader_id, self.leader_id);
+                return proto::RetireResponse { term: current_term, success: false };
             }
         }
-        proto::GetLeaderResponse { leader: None , redirect_to: None }
+        if request.cancel {
+            info!("Decommission of node {} by leader {} was abandoned, standing for election again.", self.server_id, request.leader_id);
+            self.non_candidate = false;
+        } else if !request.shutdown {
+            info!("Node {} is being decommissioned by leader {}, no longer standing for election.", self.server_id, request.leader_id);
+            self.non_candidate = true;
+        }
+        proto::RetireResponse { term: current_term, success: true }
     }
 
-    pub fn handle_get_configuration_rpc(
-        &mut self, // &self should be enough here
-        _request: &proto::GetConfigurationRequest,
-    ) -> proto::GetConfigurationResponse {
-        let servers = self.current_config.all_servers_in_config();
-        proto::GetConfigurationResponse { servers }
+    // 已被移出配置：完全停止节点（包括 RPC 服务），wipe_data 为 true 时再清空快照和元数据目录
+    pub async fn retire(&mut self, wipe_data: bool) {
+        info!("Node {} retiring after decommission (wipe data: {}).", self.server_id, wipe_data);
+        self.non_candidate = true;
+        self.stop().await;
+        let mut wiped = false;
+        if wipe_data {
+            let metadata_dir = self.metadata.get().await.metadata_dir;
+            wiped = true;
+            for dir in [self.snapshot.snapshot_dir().to_string(), metadata_dir] {
+                if let Err(e) = std::fs::remove_dir_all(&dir) {
+                    error!("Failed to wipe data dir {} of decommissioned node {}: {}", dir, self.server_id, e);
+                    wiped = false;
+                }
+            }
+        }
+        self.events.publish(events::RaftEvent::Decommissioned { wiped });
     }
 
-    pub async fn handle_set_configuration_rpc(
-        &mut self,
-        request: &proto::SetConfigurationRequest,
-    ) -> proto::SetConfigurationResponse {
-        if self.state != State::Leader {
-            error!("SetConfiguration can only be handled by the leader.");
-            return proto::SetConfigurationResponse { success: false };
+    // Propose 并在需要时等待条目应用到本节点的状态机，返回状态机的执行结果。
+    // 等待者在追加条目之前登记，单节点集群在 replicate 内部就会提交并应用；等待时不持有锁
+    pub async fn propose_and_wait(consensus: &Arc<TokioMutex<Consensus>>, request: &proto::ProposeRequest) -> proto::ProposeResponse {
+        let scheduler = Arc::clone(&consensus.lock().await.proposal_scheduler);
+        let turn = scheduler.acquire(None).await;
+        Consensus::propose_and_wait_until(consensus, request, None, turn).await
+    }
+
+    // 同 propose_and_wait，但等待不超过客户端的截止时间 deadline；turn 是调用方从 proposal_scheduler 拿到的机会。
+    // 追加条目的部分放在单独的任务里执行：客户端断开或超时时 tonic 会丢弃处理请求的 future，
+    // 不能让追加和落盘做到一半被取消；之后的等待随时可以取消，等待者的通道随之关闭
+    pub async fn propose_and_wait_until(
+        consensus: &Arc<TokioMutex<Consensus>>,
+        request: &proto::ProposeRequest,
+        deadline: Option<tokio::time::Instant>,
+        turn: fairness::Turn,
+    ) -> proto::ProposeResponse {
+        let proposing = tokio::spawn({
+            let consensus = Arc::clone(consensus);
+            let request = request.clone();
+            crash::scope(async move {
+                let proposed = Consensus::propose_with_waiter(&consensus, &request).await;
+                drop(turn);
+                proposed
+            })
+        });
+        let (mut resp, rx) = match proposing.await {
+            Ok((resp, Some(rx))) => (resp, rx),
+            Ok((resp, None)) => return resp,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        };
+
+        let wait_until = tokio::time::Instant::now() + config::PROPOSE_WAIT_TIMEOUT;
+        match tokio::time::timeout_at(deadline.map_or(wait_until, |d| d.min(wait_until)), rx).await {
+            Ok(Ok(result)) => resp.result = Some(result),
+            Ok(Err(_)) => warn!("Proposed entry {:?} was not applied by this leader, outcome unknown.", resp.read_token),
+            Err(_) => warn!("Timed out waiting for proposed entry {:?} to be applied.", resp.read_token),
         }
+        resp
+    }
 
-        if request.new_servers.is_empty() {
-            error!("SetConfiguration failed: new_servers list is empty.");
-            return proto::SetConfigurationResponse { success: false };
+    // 客户端提议的减载：提交延迟持续超过 SLO 时按比例以 Busy 拒绝。
+    // 只在客户端的提议路径上检查，状态机的后续命令直接调用 handle_propose_rpc，不会被拒绝
+    fn shed_proposal(&mut self) -> Option<proto::ProposeResponse> {
+        if self.state != State::Leader || self.shutting_down {
+            return None;
         }
+        let now = self.clock.now();
+        if !self.load_shedder.as_mut()?.should_shed(now) {
+            return None;
+        }
+        debug!("Shedding Propose: commit latency exceeds the SLO.");
+        self.metrics.proposals_shed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Some(proto::ProposeResponse {
+            success: false,
+            index: Some(self.server_id),
+            leader_addr: Some(self.server_addr.clone()),
+            reject_reason: proto::ProposeRejectReason::Busy as i32,
+            read_token: None,
+            result: None,
+        })
+    }
 
-        if self.current_config.is_joint() {
-            error!("SetConfiguration failed: a joint consensus C(old,new) is already active and must be finalized first.");
-            return proto::SetConfigurationResponse { success: false };
+    // 追加提议的条目；需要等待结果时先登记等待者，返回它的接收端
+    async fn propose_with_waiter(
+        consensus: &Arc<TokioMutex<Consensus>>,
+        request: &proto::ProposeRequest,
+    ) -> (proto::ProposeResponse, Option<oneshot::Receiver<Vec<u8>>>) {
+        let mut guard = consensus.lock().await;
+        if let Some(resp) = guard.shed_proposal() {
+            return (resp, None);
         }
-        if let Some(last_log_cfg) = self.log.last_configuration() {
-            if last_log_cfg.is_joint() {
-                 error!("SetConfiguration failed: last configuration entry in log is C(old,new) and not yet committed/finalized.");
-                 return proto::SetConfigurationResponse { success: false };
-            }
+        if !request.wait_for_commit || guard.state != State::Leader {
+            return (guard.handle_propose_rpc(request).await, None);
+        }
+        // 分块的提议在最后一块应用时才有结果
+        let entry_count = guard.proposal_entry_count(request.data.len()) as u64;
+        let index = guard.log.last_index(guard.snapshot.last_included_index()) + entry_count;
+        let term = guard.metadata.get().await.current_term;
+        // 客户端已经放弃等待的等待者不再保留到条目应用
+        guard.apply_waiters.retain(|_, (_, tx)| !tx.is_closed());
+        let (tx, rx) = oneshot::channel();
+        guard.apply_waiters.insert(index, (term, tx));
+        let resp = guard.handle_propose_rpc(request).await;
+        if !resp.success {
+            guard.apply_waiters.remove(&index);
+            return (resp, None);
         }
+        (resp, Some(rx))
+    }
 
-        info!("Leader handling SetConfiguration request. New target servers: {:?}", request.new_servers);
-        let success_flag = self.append_and_replicate_config_change(Some(request.new_servers.clone())).await; // Renamed
+    // Leader 转移：等目标节点的日志追上后发送 TimeoutNow，由目标节点立即发起选举。
+    // 发送 TimeoutNow 时不持有锁，目标节点随后发来的 RequestVote 需要本节点处理
+    pub async fn transfer_leadership(consensus: &Arc<TokioMutex<Consensus>>, target_id: u64) -> Result<(), String> {
+        let deadline = tokio::time::Instant::now() + config::LEADER_TRANSFER_TIMEOUT;
+        let (term, leader_id, target_addr) = loop {
+            {
+                let mut guard = consensus.lock().await;
+                if guard.state != State::Leader {
+                    return Err("no longer the leader".to_string());
+                }
+                if guard.partition.blocks(target_id) {
+                    return Err(format!("server {} is partitioned away", target_id));
+                }
+                let last_log_idx = guard.log.last_index(guard.snapshot.last_included_index());
+                let (match_index, target_addr) = match guard.peer_manager.peer(target_id) {
+                    Some(peer) => (peer.match_index, peer.addr.clone()),
+                    None => return Err(format!("server {} is not a peer of this leader", target_id)),
+                };
+                if match_index >= last_log_idx {
+                    break (guard.metadata.get().await.current_term, guard.server_id, target_addr);
+                }
+                debug!("Leader transfer: waiting for server {} to catch up ({} < {})", target_id, match_index, last_log_idx);
+                guard.append_entries_to_peers(false).await;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(format!("server {} did not catch up within {:?}", target_id, config::LEADER_TRANSFER_TIMEOUT));
+            }
+            tokio::time::sleep(config::LEADER_TRANSFER_POLL_INTERVAL).await;
+        };
 
-        proto::SetConfigurationResponse { success: success_flag }
+        info!("Transferring leadership to server {} ({}) in term {}", target_id, target_addr, term);
+        let req = proto::TimeoutNowRequest { term, leader_id };
+        let rpc_client = consensus.lock().await.rpc_client();
+        match rpc_client.timeout_now(req, target_addr).await {
+            Ok(resp) if resp.success => Ok(()),
+            Ok(resp) => Err(format!("server {} refused TimeoutNow (term {})", target_id, resp.term)),
+            Err(e) => Err(format!("TimeoutNow to server {} failed: {}", target_id, e)),
+        }
     }
 
+    // 按本节点的 RaftOptions.transport 建立连接的 RPC 客户端，供不持有锁发送请求的流程使用
+    pub fn rpc_client(&self) -> rpc::Client {
+        self.rpc_client.clone()
+    }
 
-
+    pub fn set_transport(&mut self, transport: config::TransportOptions) {
+        self.rpc_client = rpc::Client::with_transport(transport);
+    }
 
     pub async fn handle_heartbeat_timeout(&mut self) {
         if self.state == State::Leader {
-            debug!("Heartbeat timeout: Leader sending heartbeats/empty AppendEntries.");
-            self.append_entries_to_peers(true).await;
+            // 开启心跳合并时由 GroupRegistry 统一发送心跳
+            if !self.coalesce_heartbeats {
+                debug!("Heartbeat timeout: Leader sending heartbeats/empty AppendEntries.");
+                self.append_entries_to_peers(true).await;
+            }
+            if self.cluster_status() == proto::ClusterStatus::ClusterUnavailable {
+                warn!("Check-quorum: leader {} has not heard from a quorum within {:?}. Cluster is unavailable, only degraded reads are served.",
+                    self.server_id, config::CLUSTER_UNAVAILABLE_TIMEOUT);
+            }
+            self.check_divergence();
+            self.check_clock_skew();
+        } else {
+            self.divergence = (0, false);
+            self.clock_skew_exceeded = false;
         }
         // MODIFIED: Explicitly reset timer after handling, as original timer might not auto-reschedule on simple tick
         self.heartbeat_timer.lock().await.reset(config::HEARTBEAT_INTERVAL);
@@ -1153,29 +3547,40 @@ impl Consensus {
             State::Leader => {
                 warn!("Leader received election timeout. This should ideally not happen.");
             }
+            State::Candidate | State::Follower if self.non_candidate => {
+                info!("Election timeout ignored: node {} is being decommissioned.", self.server_id);
+            }
             // 如果是Follower或者Candidate
             State::Candidate | State::Follower => {
                 info!("Election timeout: Starting new election (or re-election).");
+                // 增加当前任期
+                let current_term = self.metadata.get().await.current_term;
+                let new_term = current_term + 1;
+
                 // 状态转换为Candidate
-                self.state = State::Candidate;
+                self.set_state(State::Candidate, new_term, audit::AuditReason::ElectionTimeout);
 
-                // 增加当前任期
-                let new_term = self.metadata.get().await.current_term + 1;
-                
                 // 更新元数据
-                self.metadata.update_current_term(new_term).await;
-                self.metadata.update_voted_for(self.server_id).await;
-                self.metadata.sync().await;
+                self.metadata.update_term_and_vote(new_term, self.server_id).await;
+                self.publish_view();
+                self.audit.record(new_term, audit::AuditReason::ElectionTimeout, audit::AuditEvent::TermChanged { from: current_term, to: new_term });
+                self.audit.record(new_term, audit::AuditReason::ElectionTimeout, audit::AuditEvent::VoteCast { candidate_id: self.server_id });
                 // 重置LeaderID
-                self.leader_id = config::NONE_SERVER_ID;
-                
-                // 发送投票请求
-                self.request_vote_rpc().await;
+                self.set_leader_id(config::NONE_SERVER_ID);
+
+                // 给自己的投票落盘之后才能发送投票请求，否则重启后可能在同一任期再投给别人
+                if let Err(e) = self.metadata.sync_durable().await {
+                    error!("Election timeout: failed to durably persist vote in term {}: {}. Not requesting votes.", new_term, e);
+                } else {
+                    // 发送投票请求
+                    self.request_vote_rpc().await;
+                }
             }
         }
 
         // 重置选举计时器
         self.election_timer.lock().await.reset(util::rand_election_timeout());
+        self.check_invariants("election timeout").await;
     }
 
     // 发起投票请求
@@ -1188,30 +3593,17 @@ impl Consensus {
         // 获取当前的term、id、log_last_idx和log_last_term
         let candidate_term = self.metadata.get().await.current_term;
         let candidate_id = self.server_id;
-        let log_last_idx = self.log.last_index(self.snapshot.last_included_index);
-        let log_last_term = self.log.last_term(self.snapshot.last_included_term);
-
-
-        // 遍历所有peer，为每个peer构建一个proto::RequestVoteRequest
-        let peer_infos: Vec<(u64, String)> = self.peer_manager.peers().iter()
-            .map(|p| (p.id, p.addr.clone()))
-            .collect();
-
-        let mut vote_futs = Vec::new();
-
-        for (peer_id, peer_addr) in peer_infos {
-            let req_vote = proto::RequestVoteRequest {
-                term: candidate_term,
-                candidate_id: candidate_id,
-                last_log_index: log_last_idx,
-                last_log_term: log_last_term,
-            };
-            // 并发发送RPC，为每个请求调用self.rpc_client.request_vote，使用join_all来并发等待所有投票结果
-            let fut = self.rpc_client.request_vote(req_vote, peer_addr.clone());
-            vote_futs.push(async move { (peer_id, peer_addr, fut.await) });
-        }
+        let log_last_idx = self.log.last_index(self.snapshot.last_included_index());
+        let log_last_term = self.log.last_term(self.snapshot.last_included_term());
 
 
+        // 所有 peer 收到的请求完全相同，只构建一次
+        let req_vote = proto::RequestVoteRequest {
+            term: candidate_term,
+            candidate_id,
+            last_log_index: log_last_idx,
+            last_log_term: log_last_term,
+        };
 
         // -- 统计投票结果 --
         let mut granted_votes_for_new = 0;
@@ -1228,22 +3620,54 @@ impl Consensus {
             granted_votes_for_old +=1;
             total_nodes_in_old +=1;
         }
-        // 如果new_quorum和old_quorum都满足，并且当前状态是Candidate，则成为Leader
-        let results = future::join_all(vote_futs).await;
+        for peer in self.peer_manager.peers() {
+            if peer.config_state.newing {
+                total_nodes_in_new +=1;
+            }
+            if peer.config_state.olding {
+                total_nodes_in_old +=1;
+            }
+        }
+        // new_quorum 和 old_quorum 都满足时才能成为 Leader
+        let has_quorum = |granted_new: usize, granted_old: usize| {
+            (total_nodes_in_new == 0 || granted_new * 2 > total_nodes_in_new)
+                && (total_nodes_in_old == 0 || granted_old * 2 > total_nodes_in_old)
+        };
+
+        // 并发发送RPC，按到达顺序处理投票结果；凑够多数派后立即结束，
+        // vote_futs 被 drop 时尚未返回的请求随之取消
+        let mut vote_futs = FuturesUnordered::new();
+        let reachable = self.peer_manager.peers().iter().filter(|p| !self.partition.blocks(p.id));
+        for (peer_id, peer_addr) in reachable.map(|p| (p.id, p.addr.clone())) {
+            let rpc_client = self.rpc_client.clone();
+            vote_futs.push(async move {
+                let result = rpc_client.request_vote(req_vote, peer_addr.clone()).await;
+                (peer_id, peer_addr, result)
+            });
+        }
 
-        for result_item in results {
-            // result_item is (peer_id, peer_addr, Result<Response, Error>)
-            let (peer_id, peer_addr, rpc_result) = result_item;
+        while !has_quorum(granted_votes_for_new, granted_votes_for_old) {
+            let Some((peer_id, peer_addr, rpc_result)) = vote_futs.next().await else {
+                break;
+            };
             match rpc_result {
                 Ok(resp) => {
                     info!("RequestVote response from {}({}): {:?}", peer_id, peer_addr, resp);
 
+                    let current_term = self.metadata.get().await.current_term;
+                    if let Err(e) = sanity::check_term(current_term, resp.term) {
+                        warn!("Ignoring RequestVote response from peer {}: {}", peer_id, e);
+                        continue;
+                    }
                     // 如果收到的响应中自己的任期落后，则选举失败
-                    if resp.term > self.metadata.get().await.current_term {
+                    if resp.term > current_term {
                         info!("Received higher term {} from peer {} during election. Stepping down.", resp.term, peer_id);
-                        Box::pin(self.step_down(resp.term)).await;
+                        Box::pin(self.step_down(resp.term, audit::AuditReason::HigherTermSeen)).await;
                         return;
                     }
+                    if let Some(peer) = self.peer_manager.peer(peer_id) {
+                        peer.last_contact = Some(self.clock.now());
+                    }
                     if resp.vote_granted {
                         if let Some(peer) = self.peer_manager.peer(peer_id) {
                             peer.vote_granted = true;
@@ -1260,32 +3684,21 @@ impl Consensus {
                     error!("RequestVote RPC to {}({}) failed: {}", peer_id, peer_addr, e);
                 }
             }
-            if self.state != State::Candidate {
-                return;
-            }
-        }
-
-        for peer in self.peer_manager.peers() {
-            if peer.config_state.newing {
-                total_nodes_in_new +=1;
-            }
-            if peer.config_state.olding {
-                total_nodes_in_old +=1;
-            }
+            if self.state != State::Candidate {
+                return;
+            }
         }
+        let pending = vote_futs.len();
+        drop(vote_futs);
 
-        let new_config_has_quorum = total_nodes_in_new == 0 || granted_votes_for_new * 2 > total_nodes_in_new;
-        let old_config_has_quorum = total_nodes_in_old == 0 || granted_votes_for_old * 2 > total_nodes_in_old;
-
-
-        if new_config_has_quorum && old_config_has_quorum {
+        if has_quorum(granted_votes_for_new, granted_votes_for_old) {
              if self.state == State::Candidate {
-                info!("Election won. Becoming Leader.");
+                info!("Election won. Becoming Leader ({} vote requests still pending were cancelled).", pending);
                 self.become_leader().await;
             }
         } else {
-            info!("Election lost or not enough votes. Granted New: {}/{}, Granted Old: {}/{}. New Quorum: {}, Old Quorum: {}",
-                granted_votes_for_new, total_nodes_in_new, granted_votes_for_old, total_nodes_in_old, new_config_has_quorum, old_config_has_quorum);
+            info!("Election lost or not enough votes. Granted New: {}/{}, Granted Old: {}/{}.",
+                granted_votes_for_new, total_nodes_in_new, granted_votes_for_old, total_nodes_in_old);
         }
     }
 
@@ -1296,6 +3709,15 @@ impl Consensus {
     pub async fn handle_request_vote_rpc(
         &mut self,
         request: &proto::RequestVoteRequest,
+    ) -> proto::RequestVoteResponse {
+        let resp = self.request_vote(request).await;
+        self.check_invariants("RequestVote").await;
+        resp
+    }
+
+    async fn request_vote(
+        &mut self,
+        request: &proto::RequestVoteRequest,
     ) -> proto::RequestVoteResponse {
         let meta_initial = self.metadata.get().await;
         let initial_current_term = meta_initial.current_term; // Store for clarity, though meta gets updated
@@ -1308,7 +3730,7 @@ impl Consensus {
             // 如果请求的任期大于或等于当前任期，则更新当前任期并可能回退状态
             if request.term > initial_current_term {
                 info!("RV: request term {} > current term {}. Stepping down.", request.term, initial_current_term);
-                Box::pin(self.step_down(request.term)).await;
+                Box::pin(self.step_down(request.term, audit::AuditReason::HigherTermSeen)).await;
             }
 
             // 投票条件，在任期检查通过或者更新之后
@@ -1318,9 +3740,9 @@ impl Consensus {
 
 
 
-            let log_ok = request.last_log_term > self.log.last_term(self.snapshot.last_included_term) ||
-                         (request.last_log_term == self.log.last_term(self.snapshot.last_included_term) &&
-                          request.last_log_index >= self.log.last_index(self.snapshot.last_included_index));
+            let log_ok = request.last_log_term > self.log.last_term(self.snapshot.last_included_term()) ||
+                         (request.last_log_term == self.log.last_term(self.snapshot.last_included_term()) &&
+                          request.last_log_index >= self.log.last_index(self.snapshot.last_included_index()));
 
 
             // 检查日志是否符合条件
@@ -1328,24 +3750,33 @@ impl Consensus {
                 info!("RV Refused for {}: Candidate's log is not up-to-date. Candidate: (idx={}, term={}), Self: (idx={}, term={})",
                     request.candidate_id,
                     request.last_log_index, request.last_log_term,
-                    self.log.last_index(self.snapshot.last_included_index),
-                    self.log.last_term(self.snapshot.last_included_term)
+                    self.log.last_index(self.snapshot.last_included_index()),
+                    self.log.last_term(self.snapshot.last_included_term())
                 );
             }
+            let vote_held = self.vote_hold.is_some_and(|(term, until)| term == updated_current_term_val && self.clock.now() < until);
             // 检查是否可以投票给候选人
-            if log_ok && (voted_for_val == config::NONE_SERVER_ID || voted_for_val == request.candidate_id) {
+            if vote_held {
+                info!("RV Refused for {}: restarted less than {:?} ago in term {}, the vote cast before the restart may not be durable.",
+                    request.candidate_id, config::RESTART_VOTE_HOLD, updated_current_term_val);
+            } else if log_ok && (voted_for_val == config::NONE_SERVER_ID || voted_for_val == request.candidate_id) {
                  let candidate_in_current_config = self.current_config.all_ids_in_config().contains(&request.candidate_id);
                  if !candidate_in_current_config && !self.current_config.is_empty() {
                      info!("RV Refused for {}: Candidate not in current configuration.", request.candidate_id);
                  } else {
                     // 
-                    info!("RV Granted for server {} in term {}", request.candidate_id, updated_current_term_val);
                     self.metadata.update_voted_for(request.candidate_id).await;
-                    self.metadata.sync().await;
-                    grant_vote = true;
-                    self.state = State::Follower;
-                    self.leader_id = config::NONE_SERVER_ID;
-                    self.election_timer.lock().await.reset(util::rand_election_timeout());
+                    // 投票落盘之后才能回复，否则重启后回到旧的记录，同一任期可能再投给另一个候选人
+                    if let Err(e) = self.metadata.sync_durable().await {
+                        error!("RV Refused for {}: failed to durably persist vote in term {}: {}", request.candidate_id, updated_current_term_val, e);
+                    } else {
+                        info!("RV Granted for server {} in term {}", request.candidate_id, updated_current_term_val);
+                        self.audit.record(updated_current_term_val, audit::AuditReason::VoteRequested, audit::AuditEvent::VoteCast { candidate_id: request.candidate_id });
+                        grant_vote = true;
+                        self.set_state(State::Follower, updated_current_term_val, audit::AuditReason::VoteRequested);
+                        self.set_leader_id(config::NONE_SERVER_ID);
+                        self.election_timer.lock().await.reset(util::rand_election_timeout());
+                    }
                  }
             } else {
                  info!("RV Refused for {}: log_ok={}, voted_for={}, candidate_id={}",
@@ -1369,14 +3800,28 @@ impl Consensus {
             return;
         }
         
-        self.state = State::Leader;
-        self.leader_id = self.server_id;
+        let current_term = self.metadata.get().await.current_term;
+        self.set_state(State::Leader, current_term, audit::AuditReason::ElectionWon);
+        self.set_leader_id(self.server_id);
+        // 之前任期留下的追加时间已经没有意义
+        self.append_times.clear();
+        self.uncommitted_bytes = 0;
+        self.last_leader_contact = self.clock.now();
         info!("Became Leader for term {}", self.metadata.get().await.current_term);
 
-        let last_log_idx = self.log.last_index(self.snapshot.last_included_index);
+        // 有上次担任 Leader 时记录的 match_index 时，从该位置开始复制，不必从日志末尾逐条回退。
+        // 不超过 commit_index 的部分一定已经在对方日志中且与本地一致，所以提示按 commit_index 截断；
+        // 提示只影响 next_index，match_index 仍然要等对方确认
+        let last_log_idx = self.log.last_index(self.snapshot.last_included_index());
+        let hints = self.metadata.get().await.peer_match_hints;
+        let (commit_index, log_start) = (self.commit_index, self.log.start_index());
         for peer in self.peer_manager.peers_mut() {
-            peer.next_index = last_log_idx + 1;
+            peer.set_next_index(match hints.get(&peer.id) {
+                Some(hint) => (hint.min(&commit_index) + 1).max(log_start).min(last_log_idx + 1),
+                None => last_log_idx + 1,
+            });
             peer.match_index = 0;
+            peer.snapshot_required = false;
         }
 
         // 提交一个NOOP条目以确保领导者状态下的日志一致性
@@ -1386,12 +3831,45 @@ impl Consensus {
         ).await {
             error!("Failed to replicate NOOP entry after becoming leader: {:?}", e);
         }
-        // 重置心跳计时器
-        self.heartbeat_timer.lock().await.reset(config::HEARTBEAT_INTERVAL);
+        self.resume_config_change().await;
+        self.start_heartbeat_timer().await;
+        self.check_advertised_address();
+        self.check_invariants("becoming leader").await;
+    }
+
+    // 已提交的 C(old,new) 还没有后续的 C(new) 时由新 Leader 补上：之前的 Leader 在追加 C(new) 之前失去了领导权，
+    // 或者本节点从联合共识期间生成的快照恢复，应用 C(old,new) 的时候不是 Leader
+    async fn resume_config_change(&mut self) {
+        if !self.current_config.is_joint() || self.config_index > self.commit_index {
+            return;
+        }
+        if self.log.last_configuration_entry().is_some_and(|(index, _)| index > self.config_index) {
+            return;
+        }
+        info!("Leader resuming configuration change: C(old,new) at index {} is committed, appending C(new).", self.config_index);
+        self.append_and_replicate_final_config().await;
+    }
+
+    // 仅Leader使用，周期性地向Follower发送心跳，通常是空的AppendEntries RPC
+    async fn start_heartbeat_timer(&mut self) {
+        let heartbeat_consensus_weak = self.self_ref.clone();
+        self.heartbeat_timer.lock().await.schedule(
+            config::HEARTBEAT_INTERVAL,
+            move || {
+                if let Some(sc_arc_strong) = heartbeat_consensus_weak.upgrade() {
+                    tokio::spawn(crash::scope(async move {
+                        let mut consensus_guard = sc_arc_strong.lock().await;
+                        consensus_guard.handle_heartbeat_timeout().await;
+                    }));
+                } else {
+                     warn!("Heartbeat timer fired but Consensus Arc was dropped.");
+                }
+            },
+        );
     }
 
     // 状态回退
-    async fn step_down(&mut self, new_term: u64) {
+    async fn step_down(&mut self, new_term: u64, reason: audit::AuditReason) {
         let meta = self.metadata.get().await;
         let current_term = meta.current_term;
 
@@ -1409,15 +3887,24 @@ impl Consensus {
         }
 
         let old_state = self.state;
-        self.state = State::Follower;
+        self.set_state(State::Follower, new_term, reason);
+        // 本节点作为 Leader Propose 的后续命令的层数不再有意义，队列留给后台任务丢弃并计数
+        self.follow_up_depths.clear();
+        if old_state == State::Leader {
+            // Follower 不发送心跳，停掉计时器避免无意义的唤醒和锁竞争
+            self.heartbeat_timer.lock().await.stop().await;
+        }
 
         if new_term > current_term {
-            self.metadata.update_current_term(new_term).await;
-            self.metadata.update_voted_for(config::NONE_SERVER_ID).await;
-            self.leader_id = config::NONE_SERVER_ID;
+            // 旧任期的 Leader 发起的快照传输不会再完成
+            self.abandon_snapshot_transfer("term changed");
+            self.metadata.update_term_and_vote(new_term, config::NONE_SERVER_ID).await;
+            self.publish_view();
+            self.audit.record(new_term, reason, audit::AuditEvent::TermChanged { from: current_term, to: new_term });
+            self.set_leader_id(config::NONE_SERVER_ID);
         } else {
             if old_state == State::Leader || old_state == State::Candidate {
-                 self.leader_id = config::NONE_SERVER_ID;
+                 self.set_leader_id(config::NONE_SERVER_ID);
             }
         }
 
@@ -1429,6 +3916,19 @@ impl Consensus {
             .reset(util::rand_election_timeout());
         // MODIFIED: Added .await
         info!("Stepped down. New state: {:?}, New term: {}, Leader ID: {}", self.state, self.metadata.get().await.current_term, self.leader_id);
+        self.check_invariants("stepping down").await;
+    }
+
+    // 切换角色，角色发生变化时写入审计日志
+    fn set_state(&mut self, new_state: State, term: u64, reason: audit::AuditReason) {
+        if self.state != new_state {
+            self.audit.record(term, reason, audit::AuditEvent::StateChanged { from: self.state, to: new_state });
+        }
+        let previous_state = std::mem::replace(&mut self.state, new_state);
+        if previous_state != new_state {
+            self.record_log_op(|| format!("role {:?} -> {:?} term {}", previous_state, new_state, term));
+        }
+        self.publish_view();
     }
 
 
@@ -1449,6 +3949,15 @@ impl Consensus {
         &mut self,
         entry_type: proto::EntryType,
         data: Vec<u8>,
+    ) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        self.replicate_entries(entry_type, vec![data]).await
+    }
+
+    // 一次追加多个同类型的条目，落盘和发送只做一次；用于分块的大提议，保证各块在日志中连续
+    pub async fn replicate_entries(
+        &mut self,
+        entry_type: proto::EntryType,
+        entries: Vec<Vec<u8>>,
     ) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
         if self.state != State::Leader {
             error!("replicate should be processed by leader");
@@ -1457,15 +3966,29 @@ impl Consensus {
                 "not leader",
             )));
         }
-        info!("replicate data type: {:?}, size: {}", entry_type, data.len());
+        let sizes: Vec<u64> = entries.iter().map(|data| data.len() as u64).collect();
+        info!("replicate data type: {:?}, entries: {}, size: {}", entry_type, entries.len(), sizes.iter().sum::<u64>());
 
         // MODIFIED: Added .await
         let current_term = self.metadata.get().await.current_term;
-        self.log.append_data(current_term, vec![(entry_type, data.clone())]);
+        // 先解析配置，数据随后直接移交给日志，不再复制
+        let pending_config = entries.last()
+            .filter(|_| entry_type == proto::EntryType::Configuration)
+            .map(|data| config::Config::from_data(data));
+        self.log.append_data(current_term, entries.into_iter().map(|data| (entry_type, data)).collect());
+        let first_index = self.log.last_index(self.snapshot.last_included_index()) + 1 - sizes.len() as u64;
+        let appended_at = self.clock.now();
+        for (offset, bytes) in sizes.into_iter().enumerate() {
+            self.append_times.push_back((first_index + offset as u64, entry_type, appended_at, bytes));
+            self.uncommitted_bytes += bytes;
+        }
+        // 复制给其他节点之前先在本地落盘
+        self.log.persist();
+        self.record_log_op(|| format!("leader append [{}, {}] {:?} term {}", first_index, self.log.last_index(self.snapshot.last_included_index()), entry_type, current_term));
 
-        if entry_type == proto::EntryType::Configuration {
-            let pending_config = config::Config::from_data(&data);
-            self.apply_configuration_to_internal_state(pending_config, false).await;
+        if let Some(pending_config) = pending_config {
+            let config_index = self.log.last_index(self.snapshot.last_included_index());
+            self.apply_configuration_to_internal_state(config_index, pending_config, false).await;
         }
 
         self.append_entries_to_peers(false).await;
@@ -1479,3 +4002,1393 @@ impl Consensus {
 
 }
 
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // 测试用的单节点共识模块，数据放在 storage 中节点 1 的目录下；用同一个 storage 再次调用相当于重启
+    pub(crate) async fn test_consensus(
+        storage: &crate::raft::storage::StoragePaths,
+        state_machine: Box<dyn state_machine::StateMachine>,
+    ) -> Arc<TokioMutex<Consensus>> {
+        test_consensus_with(storage, 1, Vec::new(), state_machine, Arc::new(clock::SystemClock)).await
+    }
+
+    // 指定节点编号、初始成员和时钟，节点 server_id 的地址为 "[::1]:<server_id>"
+    pub(crate) async fn test_consensus_with(
+        storage: &crate::raft::storage::StoragePaths,
+        server_id: u64,
+        initial_peers: Vec<proto::ServerInfo>,
+        state_machine: Box<dyn state_machine::StateMachine>,
+        clock: Arc<dyn clock::Clock>,
+    ) -> Arc<TokioMutex<Consensus>> {
+        let (snapshot_dir, metadata_dir) = storage.create_node_dirs(server_id).unwrap();
+        Consensus::new(server_id, format!("[::1]:{}", server_id), initial_peers, state_machine, snapshot_dir, metadata_dir, clock).await.unwrap()
+    }
+
+    // 把每条数据追加到内存中，apply 返回追加之前的条目数
+    #[derive(Debug, Default)]
+    struct CountingStateMachine {
+        entries: Vec<Vec<u8>>,
+        write_snapshots: bool, // 为 true 时 take_snapshot 把条目数写入快照文件，否则不生成文件
+    }
+
+    impl state_machine::StateMachine for CountingStateMachine {
+        fn apply(&mut self, data: &Vec<u8>) {
+            self.entries.push(data.clone());
+        }
+
+        fn apply_with_result(&mut self, data: &Vec<u8>) -> Vec<u8> {
+            let previous = self.entries.len().to_string().into_bytes();
+            self.apply(data);
+            previous
+        }
+
+        fn take_snapshot(&mut self, snapshot_filepath: &str) {
+            if self.write_snapshots {
+                std::fs::write(snapshot_filepath, self.entries.len().to_string()).unwrap();
+            }
+        }
+
+        fn restore_snapshot(&mut self, _snapshot_filepath: &str) {}
+    }
+
+    // 快照带有数据格式版本的状态机，只接受同一个版本的快照
+    #[derive(Debug)]
+    struct VersionedStateMachine {
+        version: &'static str,
+        entries: u64,
+    }
+
+    impl state_machine::StateMachine for VersionedStateMachine {
+        fn apply(&mut self, _data: &Vec<u8>) {
+            self.entries += 1;
+        }
+
+        fn take_snapshot(&mut self, snapshot_filepath: &str) {
+            std::fs::write(snapshot_filepath, self.entries.to_string()).unwrap();
+        }
+
+        fn restore_snapshot(&mut self, snapshot_filepath: &str) {
+            self.entries = std::fs::read_to_string(snapshot_filepath).unwrap().parse().unwrap();
+        }
+
+        fn snapshot_version(&self) -> Option<String> {
+            Some(self.version.to_string())
+        }
+
+        fn compatible_with(&self, version: Option<&str>) -> bool {
+            version == Some(self.version)
+        }
+    }
+
+    // 模拟自身持久化数据的状态机：数据和 applied_index 保存在共享的存储里，"重启"后仍然存在
+    #[derive(Debug, Default, Clone)]
+    struct DurableStateMachine {
+        applied: Arc<StdMutex<Option<u64>>>,
+        entries: Arc<StdMutex<Vec<Vec<u8>>>>,
+    }
+
+    impl state_machine::StateMachine for DurableStateMachine {
+        fn apply(&mut self, data: &Vec<u8>) {
+            self.entries.lock().unwrap().push(data.clone());
+        }
+
+        // 数据和应用到的索引一起"落盘"
+        fn apply_with_context(&mut self, data: &Vec<u8>, ctx: &mut state_machine::ApplyContext) -> Vec<u8> {
+            self.apply(data);
+            *self.applied.lock().unwrap() = Some(ctx.index);
+            Vec::new()
+        }
+
+        fn applied_index(&self) -> Option<u64> {
+            *self.applied.lock().unwrap()
+        }
+
+        fn set_applied_index(&mut self, index: u64) {
+            *self.applied.lock().unwrap() = Some(index);
+        }
+
+        fn take_snapshot(&mut self, _snapshot_filepath: &str) {}
+
+        fn restore_snapshot(&mut self, _snapshot_filepath: &str) {}
+    }
+
+    // 每应用一条数据就提交一条同样的后续命令，用来检验后续命令链条的层数上限
+    #[derive(Debug, Default, Clone)]
+    struct EchoStateMachine {
+        entries: Arc<StdMutex<Vec<Vec<u8>>>>,
+    }
+
+    impl state_machine::StateMachine for EchoStateMachine {
+        fn apply(&mut self, data: &Vec<u8>) {
+            self.entries.lock().unwrap().push(data.clone());
+        }
+
+        fn apply_with_context(&mut self, data: &Vec<u8>, ctx: &mut state_machine::ApplyContext) -> Vec<u8> {
+            self.apply(data);
+            let _ = ctx.propose(data.clone());
+            Vec::new()
+        }
+
+        fn take_snapshot(&mut self, _snapshot_filepath: &str) {}
+
+        fn restore_snapshot(&mut self, _snapshot_filepath: &str) {}
+    }
+
+    #[tokio::test]
+    async fn test_follow_up_chain_is_bounded() {
+        let mut follower_ctx = state_machine::ApplyContext::new(1, false, None, 0);
+        assert!(follower_ctx.propose(b"x".to_vec()).is_err());
+        assert!(follower_ctx.into_follow_ups().is_empty());
+
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let state_machine = EchoStateMachine::default();
+        let consensus = test_consensus(&storage, Box::new(state_machine.clone())).await;
+        {
+            let mut guard = consensus.lock().await;
+            guard.handle_election_timeout().await;
+            assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: b"tick".to_vec(), wait_for_commit: false }).await.success);
+        }
+
+        // 客户端的条目加上 FOLLOW_UP_MAX_DEPTH 层后续命令，之后不再增长
+        let expected = config::FOLLOW_UP_MAX_DEPTH as usize + 1;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while state_machine.entries.lock().unwrap().len() < expected {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(state_machine.entries.lock().unwrap().len(), expected);
+        let guard = consensus.lock().await;
+        assert_eq!(guard.metrics.snapshot().follow_ups_proposed, config::FOLLOW_UP_MAX_DEPTH as u64);
+        assert!(guard.follow_up_depths.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_propose_and_wait_returns_apply_result() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        // 单节点集群直接选自己为 Leader，条目在 replicate 内部就会提交并应用
+        consensus.lock().await.handle_election_timeout().await;
+        assert_eq!(consensus.lock().await.state, State::Leader);
+
+        let propose = |data: &str, wait_for_commit: bool| proto::ProposeRequest { data: data.as_bytes().to_vec(), wait_for_commit };
+        let resp = Consensus::propose_and_wait(&consensus, &propose("a", true)).await;
+        assert!(resp.success);
+        assert_eq!(resp.result, Some(b"0".to_vec()));
+
+        // 不等待时不带结果
+        let resp = Consensus::propose_and_wait(&consensus, &propose("b", false)).await;
+        assert!(resp.success);
+        assert_eq!(resp.result, None);
+
+        let resp = Consensus::propose_and_wait(&consensus, &propose("c", true)).await;
+        assert_eq!(resp.result, Some(b"2".to_vec()));
+        assert!(consensus.lock().await.apply_waiters.is_empty());
+
+        consensus.lock().await.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_retire_stops_elections_and_wipes_data() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let (snapshot_dir, metadata_dir) = storage.create_node_dirs(2).unwrap();
+        let consensus = test_consensus_with(
+            &storage,
+            2,
+            vec![proto::ServerInfo { server_id: 1, server_addr: "[::1]:1".to_string(), zone: None }],
+            Box::new(CountingStateMachine::default()),
+            Arc::new(clock::SystemClock),
+        ).await;
+        let mut guard = consensus.lock().await;
+        let mut events = guard.events.subscribe();
+
+        // 过期任期的通知被拒绝
+        guard.metadata.update_current_term(3).await;
+        let retire = |term: u64, shutdown: bool| proto::RetireRequest { term, leader_id: 1, shutdown, wipe_data: true, cancel: false };
+        assert!(!guard.handle_retire_rpc(&retire(2, false)).await.success);
+        assert!(!guard.non_candidate);
+
+        // 不再参与竞选，也不接受 TimeoutNow
+        assert!(guard.handle_retire_rpc(&retire(3, false)).await.success);
+        guard.handle_election_timeout().await;
+        assert_eq!(guard.state, State::Follower);
+        assert_eq!(guard.metadata.get().await.current_term, 3);
+        assert!(!guard.handle_timeout_now_rpc(&proto::TimeoutNowRequest { term: 3, leader_id: 1 }).await.success);
+
+        // 下线流程失败时 Leader 撤销通知，节点重新参与竞选
+        assert!(guard.handle_retire_rpc(&proto::RetireRequest { cancel: true, ..retire(3, false) }).await.success);
+        assert!(!guard.non_candidate);
+        assert!(guard.handle_retire_rpc(&retire(3, false)).await.success);
+
+        // 仍在配置中的成员不接受关闭和清空数据
+        guard.set_leader_id(1);
+        assert!(!guard.handle_retire_rpc(&retire(3, true)).await.success);
+        assert!(!guard.shutting_down);
+
+        // 最新的配置已经不包含本节点之后，只接受本节点认定的 Leader 发来的关闭通知
+        let removed = config::Config::new_stable(vec![proto::ServerInfo { server_id: 1, server_addr: "[::1]:1".to_string(), zone: None }]);
+        guard.log.append_data(3, vec![(proto::EntryType::Configuration, removed.to_data())]);
+        assert!(!guard.handle_retire_rpc(&proto::RetireRequest { leader_id: 3, ..retire(3, true) }).await.success);
+        assert!(guard.handle_retire_rpc(&retire(3, true)).await.success);
+        let stopped = guard.stop_signal();
+        guard.retire(true).await;
+        assert!(guard.shutting_down);
+        assert!(*stopped.borrow());
+        assert!(!std::path::Path::new(&snapshot_dir).exists());
+        assert!(!std::path::Path::new(&metadata_dir).exists());
+        assert_eq!(events.try_recv().unwrap(), events::RaftEvent::Decommissioned { wiped: true });
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_timer_only_runs_on_leader() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        assert!(!guard.heartbeat_timer.lock().await.is_running());
+
+        guard.handle_election_timeout().await;
+        assert_eq!(guard.state, State::Leader);
+        assert!(guard.heartbeat_timer.lock().await.is_running());
+
+        let term = guard.metadata.get().await.current_term;
+        guard.step_down(term + 1, audit::AuditReason::HigherTermSeen).await;
+        assert!(!guard.heartbeat_timer.lock().await.is_running());
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_min_applied_index_tracks_follower_reports() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        assert_eq!(guard.min_applied_index(), None);
+
+        guard.handle_election_timeout().await;
+        let resp = guard.handle_propose_rpc(&proto::ProposeRequest { data: b"a".to_vec(), wait_for_commit: false }).await;
+        assert!(resp.success);
+        let last_applied = guard.last_applied;
+        assert!(last_applied > 0);
+        assert_eq!(guard.min_applied_index(), Some(last_applied));
+
+        // 新加入的节点还没有响应过，按0计算
+        let last_log_index = guard.log.last_index(guard.snapshot.last_included_index());
+        guard.peer_manager.add(vec![peer::Peer::new(2, "[::1]:2".to_string())], last_log_index);
+        assert_eq!(guard.min_applied_index(), Some(0));
+
+        // 失败的响应同样带回 Follower 的应用进度
+        let term = guard.metadata.get().await.current_term;
+        let summary = rpc::AppendEntriesSummary {
+            term,
+            leader_id: 1,
+            prev_log_index: last_log_index,
+            prev_log_term: term,
+            entries: 0,
+            bytes: 0,
+            leader_commit: guard.commit_index,
+        };
+        let resp = proto::AppendEntriesResponse { term, success: false, last_applied: last_applied - 1, ..Default::default() };
+        guard.handle_append_entries_response(2, &summary, &resp).await;
+        assert_eq!(guard.min_applied_index(), Some(last_applied - 1));
+
+        let resp = proto::AppendEntriesResponse { term, success: true, last_applied, ..Default::default() };
+        guard.handle_append_entries_response(2, &summary, &resp).await;
+        assert_eq!(guard.min_applied_index(), Some(last_applied));
+
+        // 超出 Leader 日志的应用进度不可能出现，直接忽略
+        let resp = proto::AppendEntriesResponse { term, success: true, last_applied: u64::MAX, ..Default::default() };
+        guard.handle_append_entries_response(2, &summary, &resp).await;
+        assert_eq!(guard.peer_manager.peer(2).unwrap().last_applied, last_applied);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_large_proposals_are_chunked_or_rejected() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        consensus.lock().await.handle_election_timeout().await;
+        consensus.lock().await.max_entry_size = 40;
+        let payload: Vec<u8> = (0..100u8).collect();
+        let propose = proto::ProposeRequest { data: payload.clone(), wait_for_commit: true };
+
+        // 不分块时超过上限直接拒绝
+        let resp = Consensus::propose_and_wait(&consensus, &propose).await;
+        assert!(!resp.success);
+        assert_eq!(resp.reject_reason, proto::ProposeRejectReason::PayloadTooLarge as i32);
+
+        consensus.lock().await.chunk_large_entries = true;
+        let last_applied = consensus.lock().await.last_applied;
+        let resp = Consensus::propose_and_wait(&consensus, &propose).await;
+        assert!(resp.success);
+        // 拆成4个条目，状态机只应用一次完整的数据
+        assert_eq!(resp.read_token, Some(last_applied + 4));
+        assert_eq!(resp.result, Some(b"0".to_vec()));
+
+        let mut guard = consensus.lock().await;
+        assert_eq!(guard.last_applied, last_applied + 4);
+        let entry = guard.log.entry(last_applied + 1).unwrap();
+        assert_eq!(entry.entry_type, proto::EntryType::DataChunk as i32);
+        assert!(entry.data.len() <= 40);
+        assert_eq!(guard.chunk_assembler.pending(), None);
+        assert!(guard.apply_waiters.is_empty());
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_startup_recovery_applies_missing_committed_entries() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let state_machine = DurableStateMachine::default();
+        let consensus = test_consensus(&storage, Box::new(state_machine.clone())).await;
+        let (first_data_index, last_index) = {
+            let mut guard = consensus.lock().await;
+            guard.handle_election_timeout().await;
+            let first_data_index = guard.log.last_index(0) + 1;
+            for data in [b"a", b"b", b"c"] {
+                assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: data.to_vec(), wait_for_commit: false }).await.success);
+            }
+            guard.metadata.sync_durable().await.unwrap();
+            guard.shutdown().await;
+            (first_data_index, guard.last_applied)
+        };
+        assert_eq!(*state_machine.applied.lock().unwrap(), Some(last_index));
+
+        // 状态机只持久化到了第一条数据，后两条在崩溃时丢失
+        *state_machine.applied.lock().unwrap() = Some(first_data_index);
+        *state_machine.entries.lock().unwrap() = vec![b"a".to_vec()];
+
+        let restarted = test_consensus(&storage, Box::new(state_machine.clone())).await;
+        {
+            let mut guard = restarted.lock().await;
+            assert_eq!(guard.last_applied, last_index);
+            assert_eq!(guard.commit_index, last_index);
+            assert_eq!(*state_machine.entries.lock().unwrap(), vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+            assert_eq!(*state_machine.applied.lock().unwrap(), Some(last_index));
+            guard.metadata.sync_durable().await.unwrap();
+            guard.shutdown().await;
+        }
+
+        // 应用 b 之后、set_applied_index 之前崩溃：索引已经随 b 一起记录，重启后不会再次应用 b
+        *state_machine.applied.lock().unwrap() = Some(first_data_index + 1);
+        *state_machine.entries.lock().unwrap() = vec![b"a".to_vec(), b"b".to_vec()];
+        let restarted = test_consensus(&storage, Box::new(state_machine.clone())).await;
+        let mut guard = restarted.lock().await;
+        assert_eq!(*state_machine.entries.lock().unwrap(), vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_replayed_configuration_entries_are_idempotent() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let server = |id: u64| proto::ServerInfo { server_id: id, server_addr: format!("[::1]:{}", id), zone: None };
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let (joint, stable, last_index, term) = {
+            let mut guard = consensus.lock().await;
+            guard.handle_election_timeout().await;
+            assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: b"a".to_vec(), wait_for_commit: false }).await.success);
+            // 加入节点2的配置变更写进了日志，但崩溃前提交索引还没有覆盖它们
+            let joint = guard.current_config.start_transition(vec![server(1), server(2)]).unwrap();
+            let stable = joint.finalize_transition().unwrap();
+            let term = guard.metadata.get().await.current_term;
+            guard.log.append_data(term, vec![
+                (proto::EntryType::Configuration, joint.to_data()),
+                (proto::EntryType::Configuration, stable.to_data()),
+            ]);
+            guard.log.persist();
+            guard.metadata.sync_durable().await.unwrap();
+            guard.shutdown().await;
+            (joint, stable, guard.log.last_index(0), term)
+        };
+
+        // 重启后 current_config 取自日志中最后一个配置条目
+        let restarted = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = restarted.lock().await;
+        assert!(guard.commit_index < last_index - 1);
+        assert_eq!(guard.current_config, stable);
+        assert_eq!(guard.config_index, last_index);
+        assert!(guard.peer_manager.contains(2));
+
+        // 新 Leader 逐步提交这两个条目时重放它们：重放 C(old,new) 不能让配置退回去
+        let mut request = proto::AppendEntriesRequest {
+            term: term + 1,
+            leader_id: 2,
+            prev_log_index: last_index,
+            prev_log_term: term,
+            entries: Vec::new(),
+            leader_commit: last_index - 1,
+            leader_time_us: None,
+        };
+        assert!(guard.handle_append_entries_rpc(&request).await.success);
+        assert_eq!(guard.last_applied, last_index - 1);
+        assert_eq!(guard.current_config, stable);
+
+        request.leader_commit = last_index;
+        assert!(guard.handle_append_entries_rpc(&request).await.success);
+        assert_eq!(guard.last_applied, last_index);
+        assert_eq!(guard.current_config, stable);
+        assert_eq!(guard.peer_manager.peers().len(), 1);
+
+        // 未提交的相同配置再次追加时同样没有副作用
+        guard.apply_configuration_to_internal_state(last_index, stable.clone(), false).await;
+        assert_eq!(guard.current_config, stable);
+        assert_ne!(guard.current_config, joint);
+        assert!(!guard.shutting_down);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_follower_replicates_and_skips_unknown_entry_types() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus_with(
+            &storage,
+            2,
+            vec![proto::ServerInfo { server_id: 1, server_addr: "[::1]:1".to_string(), zone: None }],
+            Box::new(CountingStateMachine::default()),
+            Arc::new(clock::SystemClock),
+        ).await;
+        let mut guard = consensus.lock().await;
+        let mut events = guard.events.subscribe();
+
+        // 新版本 Leader 写入的类型：通过检查并追加，应用时跳过，之后的条目照常应用
+        let unknown = proto::LogEntry { term: 1, index: 1, entry_type: 42, data: b"new".to_vec(), timestamp_ms: None };
+        let data = proto::LogEntry { term: 1, index: 2, entry_type: proto::EntryType::Data as i32, data: b"a".to_vec(), timestamp_ms: None };
+        let request = proto::AppendEntriesRequest {
+            term: 1,
+            leader_id: 1,
+            prev_log_index: 0,
+            prev_log_term: 0,
+            entries: vec![unknown, data],
+            leader_commit: 2,
+            leader_time_us: None,
+        };
+        assert!(sanity::check_append_entries(&request, 0, 0).is_ok());
+        assert!(guard.handle_append_entries_rpc(&request).await.success);
+        assert_eq!(guard.log.last_index(0), 2);
+        assert_eq!(guard.last_applied, 2);
+        assert_eq!(events.try_recv().unwrap(), events::RaftEvent::UnknownEntrySkipped { index: 1, entry_type: 42 });
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_removed_leader_steps_down_and_stops() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let (mut events, mut stop_signal) = {
+            let mut guard = consensus.lock().await;
+            guard.handle_election_timeout().await;
+            assert_eq!(guard.state, State::Leader);
+            let subscriptions = (guard.events.subscribe(), guard.stop_signal());
+            // 没有其他节点可以接手，直接退位
+            guard.leave_cluster();
+            subscriptions
+        };
+
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+        assert_eq!(event, events::RaftEvent::RemovedFromCluster { successor: None });
+        tokio::time::timeout(Duration::from_secs(5), stop_signal.wait_for(|stopped| *stopped)).await.unwrap().unwrap();
+
+        let guard = consensus.lock().await;
+        assert_eq!(guard.state, State::Follower);
+        assert!(guard.non_candidate);
+        assert!(guard.shutting_down);
+    }
+
+    #[tokio::test]
+    async fn test_large_commit_is_applied_in_batches() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let total = config::APPLY_BATCH_MAX_ENTRIES as u64 * 3;
+        let commit_index = {
+            let mut guard = consensus.lock().await;
+            guard.handle_election_timeout().await;
+            let term = guard.metadata.get().await.current_term;
+            let applied_before = guard.last_applied;
+
+            // 一次提交大量条目，持有锁期间只应用第一批
+            let entries = (0..total).map(|i| (proto::EntryType::Data, i.to_string().into_bytes())).collect();
+            guard.log.append_data(term, entries);
+            guard.leader_advance_commit_index().await;
+            assert_eq!(guard.commit_index, applied_before + total);
+            assert!(guard.last_applied > applied_before);
+            assert!(guard.last_applied <= applied_before + config::APPLY_BATCH_MAX_ENTRIES as u64);
+            assert!(guard.apply_scheduled);
+            guard.commit_index
+        };
+
+        // 剩下的条目由后台任务分批应用完
+        assert!(Consensus::wait_for_applied(&consensus, commit_index, Duration::from_secs(5)).await);
+        let guard = consensus.lock().await;
+        assert_eq!(guard.last_applied, commit_index);
+        assert!(!guard.apply_scheduled);
+    }
+
+    #[tokio::test]
+    async fn test_new_leader_starts_from_peer_match_hints() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        for data in [b"a", b"b", b"c"] {
+            assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: data.to_vec(), wait_for_commit: false }).await.success);
+        }
+        let commit_index = guard.commit_index;
+        let last_log_index = guard.log.last_index(guard.snapshot.last_included_index());
+
+        // Leader 推进提交时记录已确认的 match_index
+        guard.peer_manager.add(vec![peer::Peer::new(2, "[::1]:2".to_string()), peer::Peer::new(3, "[::1]:3".to_string())], last_log_index);
+        guard.peer_manager.peer(2).unwrap().match_index = 2;
+        guard.record_peer_match_hints().await;
+        assert_eq!(guard.metadata.get().await.peer_match_hints, BTreeMap::from([(2, 2)]));
+
+        // 再次当选：有提示的节点从提示处开始（超过 commit_index 的部分截断），没有提示的从日志末尾开始
+        guard.metadata.update_peer_match_hints(BTreeMap::from([(2, 2), (3, commit_index + 10)])).await;
+        guard.state = State::Candidate;
+        guard.become_leader().await;
+        assert_eq!(guard.peer_manager.peer(2).unwrap().next_index, 3);
+        assert_eq!(guard.peer_manager.peer(3).unwrap().next_index, commit_index + 1);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_divergence_alarm_blocks_membership_changes() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        guard.divergence_alarm = Some(config::DivergenceAlarm { max_gap: 3, heartbeats: 2, block_membership_changes: true });
+        let mut events = guard.events.subscribe();
+        for i in 0..5u8 {
+            assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: vec![i], wait_for_commit: false }).await.success);
+        }
+        let last_log_index = guard.log.last_index(guard.snapshot.last_included_index());
+        guard.peer_manager.add(vec![peer::Peer::new(2, "[::1]:2".to_string()), peer::Peer::new(3, "[::1]:3".to_string())], last_log_index);
+        guard.peer_manager.peer(2).unwrap().match_index = last_log_index;
+        guard.peer_manager.peer(3).unwrap().match_index = 1;
+
+        // 差距超过阈值的心跳周期数达到 heartbeats 之后才告警，告警期间拒绝成员变更
+        for _ in 0..2 {
+            guard.check_divergence();
+        }
+        assert!(events.try_recv().is_err());
+        guard.check_divergence();
+        assert_eq!(events.try_recv().unwrap(), events::RaftEvent::DivergenceAlarm { peer_id: 3, gap: last_log_index - 1 });
+        let metrics = guard.metrics.snapshot();
+        assert_eq!((metrics.voter_gap, metrics.divergence_alarms), (last_log_index - 1, 1));
+        let servers = guard.current_config.new_servers.clone();
+        let request = proto::SetConfigurationRequest { new_servers: servers, wait_for_completion: false, algorithm: None };
+        assert!(!guard.handle_set_configuration_rpc(&request).await.success);
+
+        // 节点追上之后解除告警
+        guard.peer_manager.peer(3).unwrap().match_index = last_log_index - 1;
+        guard.check_divergence();
+        assert_eq!(events.try_recv().unwrap(), events::RaftEvent::DivergenceCleared { gap: 1 });
+        assert!(!guard.divergence.1);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_clock_skew_suspends_read_lease() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(state_machine::SimpleStateMachine::new())).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        guard.max_clock_skew = Some(Duration::from_millis(20));
+        let mut events = guard.events.subscribe();
+        let term = guard.metadata.get().await.current_term;
+        let last_log_index = guard.log.last_index(guard.snapshot.last_included_index());
+        guard.peer_manager.add(vec![peer::Peer::new(2, "[::1]:2".to_string()), peer::Peer::new(3, "[::1]:3".to_string())], last_log_index);
+
+        // 心跳响应带回对方收到请求时的时间：节点 2 的时钟与 Leader 一致，节点 3 快了 100ms
+        let summary = rpc::AppendEntriesSummary::of(&proto::AppendEntriesRequest { term, leader_id: 1, prev_log_index: last_log_index, ..Default::default() });
+        let respond = |rtt_us: u64, offset_us: u64| {
+            let sent_us = util::unix_micros() - rtt_us;
+            proto::AppendEntriesResponse {
+                term,
+                success: true,
+                last_applied: 0,
+                leader_time_us: Some(sent_us),
+                follower_time_us: Some(sent_us + rtt_us / 2 + offset_us),
+            }
+        };
+        guard.handle_heartbeat_response(2, &summary, &respond(1_000, 0)).await;
+        guard.handle_heartbeat_response(3, &summary, &respond(1_000, 100_000)).await;
+        let status = guard.handle_get_status_rpc(&proto::GetStatusRequest {}).await;
+        let peer3 = status.peers.iter().find(|p| p.server_id == 3).unwrap();
+        assert!(peer3.clock_offset_us.unwrap().abs_diff(100_000) < 10_000);
+
+        // 偏差超过上限：租约失效，线性一致读被拒绝，降级读仍然可以
+        let read = |allow_degraded: bool| proto::ReadRequest { query: Vec::new(), allow_degraded, read_token: None };
+        assert_eq!(guard.cluster_status(), proto::ClusterStatus::ClusterAvailable);
+        assert!(!guard.handle_read_rpc(&read(false)).success);
+        assert!(guard.handle_read_rpc(&read(true)).degraded);
+        guard.check_clock_skew();
+        assert!(matches!(events.try_recv().unwrap(), events::RaftEvent::ClockSkewExceeded { peer_id: 3, .. }));
+        assert_eq!(guard.metrics.snapshot().lease_invalidations, 1);
+
+        // 往返时间更短的采样更准确，取代之前的估计后租约恢复
+        guard.handle_heartbeat_response(3, &summary, &respond(200, 0)).await;
+        assert!(guard.handle_read_rpc(&read(false)).success);
+        guard.check_clock_skew();
+        assert!(matches!(events.try_recv().unwrap(), events::RaftEvent::ClockSkewCleared { .. }));
+        assert!(guard.metrics.snapshot().max_clock_skew_us < 20_000);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_stale_heartbeat_success_does_not_regress_match_index() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        let term = guard.metadata.get().await.current_term;
+        for data in [b"a", b"b", b"c"] {
+            assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: data.to_vec(), wait_for_commit: false }).await.success);
+        }
+        let last_log_index = guard.log.last_index(guard.snapshot.last_included_index());
+        guard.peer_manager.add(vec![peer::Peer::new(2, "[::1]:2".to_string()), peer::Peer::new(3, "[::1]:3".to_string())], last_log_index);
+
+        let summary = |prev_log_index: u64, entries: usize| rpc::AppendEntriesSummary::of(&proto::AppendEntriesRequest {
+            term,
+            leader_id: 1,
+            prev_log_index,
+            prev_log_term: term,
+            entries: vec![proto::LogEntry::default(); entries],
+            leader_commit: 0,
+            leader_time_us: None,
+        });
+        let ok = proto::AppendEntriesResponse { term, success: true, last_applied: 0, ..Default::default() };
+        let refused = proto::AppendEntriesResponse { success: false, ..ok };
+
+        // 节点 2 确认了全部日志
+        guard.handle_heartbeat_response(2, &summary(0, last_log_index as usize), &ok).await;
+        assert_eq!(guard.peer_manager.peer(2).unwrap().match_index, last_log_index);
+        let commit_index = guard.commit_index;
+
+        // 之前发出的、prev_log_index 更低的心跳晚到：match_index 和提交都不能回退
+        guard.handle_heartbeat_response(2, &summary(1, 0), &ok).await;
+        let p = guard.peer_manager.peer(2).unwrap();
+        assert_eq!((p.match_index, p.next_index), (last_log_index, last_log_index + 1));
+        guard.handle_heartbeat_response(2, &summary(1, 0), &refused).await;
+        assert_eq!(guard.peer_manager.peer(2).unwrap().next_index, last_log_index + 1);
+        assert_eq!(guard.commit_index, commit_index);
+
+        // 节点 3 在回退探测中截断了冲突的尾部：心跳只确认到 1，更早的心跳确认到 0 也不能让它回退
+        guard.peer_manager.peer(3).unwrap().next_index = 2;
+        guard.handle_heartbeat_response(3, &summary(1, 0), &ok).await;
+        guard.handle_heartbeat_response(3, &summary(0, 0), &ok).await;
+        let p = guard.peer_manager.peer(3).unwrap();
+        assert_eq!((p.match_index, p.next_index), (1, 2));
+        guard.handle_heartbeat_response(3, &summary(1, last_log_index as usize - 1), &ok).await;
+        assert_eq!(guard.peer_manager.peer(3).unwrap().match_index, last_log_index);
+        assert_eq!(guard.commit_index, last_log_index);
+        guard.shutdown().await;
+    }
+
+    // 记录每次应用时看到的 (索引, 时间戳)
+    #[derive(Debug, Default)]
+    struct TimestampStateMachine {
+        seen: Arc<StdMutex<Vec<(u64, Option<u64>)>>>,
+    }
+
+    impl state_machine::StateMachine for TimestampStateMachine {
+        fn apply(&mut self, _data: &Vec<u8>) {}
+
+        fn apply_with_context(&mut self, _data: &Vec<u8>, ctx: &mut state_machine::ApplyContext) -> Vec<u8> {
+            self.seen.lock().unwrap().push((ctx.index, ctx.timestamp_ms));
+            Vec::new()
+        }
+
+        fn take_snapshot(&mut self, _snapshot_filepath: &str) {}
+
+        fn restore_snapshot(&mut self, _snapshot_filepath: &str) {}
+    }
+
+    #[tokio::test]
+    async fn test_entry_read_from_disk_keeps_its_timestamp() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let state_machine = TimestampStateMachine::default();
+        let seen = Arc::clone(&state_machine.seen);
+        let consensus = test_consensus(&storage, Box::new(state_machine)).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: b"a".to_vec(), wait_for_commit: false }).await.success);
+        let last = guard.last_applied;
+        let timestamp_ms = guard.log.entry(last).unwrap().timestamp_ms;
+        assert!(timestamp_ms.is_some());
+
+        // 内存中缺失、从日志文件读到的条目，应用时带上文件中条目的时间戳
+        guard.log.truncate_suffix(last - 1);
+        guard.last_applied = last - 1;
+        guard.apply_committed_entries().await;
+        assert_eq!(guard.last_applied, last);
+        assert_eq!(seen.lock().unwrap().last(), Some(&(last, timestamp_ms)));
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_missing_committed_entry_is_recovered_or_reported() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        for data in [b"a", b"b"] {
+            assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: data.to_vec(), wait_for_commit: false }).await.success);
+        }
+        let last = guard.commit_index;
+        assert_eq!(guard.last_applied, last);
+        let lost = guard.log.entry(last).unwrap().clone();
+
+        // 内存中的条目丢了，但日志文件里还有：从磁盘读取后继续应用
+        guard.log.truncate_suffix(last - 1);
+        guard.last_applied = last - 1;
+        guard.apply_committed_entries().await;
+        assert_eq!(guard.last_applied, last);
+        assert_eq!(guard.health(), proto::NodeHealth::Ok);
+
+        // 磁盘上也没有：停在缺失的条目之前并标记为 LogGap，而不是跳过
+        guard.log.persist();
+        guard.last_applied = last - 1;
+        guard.apply_committed_entries().await;
+        assert_eq!((guard.last_applied, guard.commit_index), (last - 1, last));
+        assert_eq!(guard.health(), proto::NodeHealth::LogGap);
+        assert_eq!(guard.subscribe_view().borrow().health, proto::NodeHealth::LogGap);
+
+        // 条目重新出现（例如 Leader 重新复制）后应用越过缺口，恢复健康
+        guard.log.append_entries(vec![lost]);
+        guard.apply_committed_entries().await;
+        assert_eq!(guard.last_applied, last);
+        assert_eq!(guard.health(), proto::NodeHealth::Ok);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_readiness_requires_small_apply_lag_and_no_snapshot_install() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        guard.ready_max_apply_lag = 10;
+        assert_eq!(guard.not_ready_reason(), None);
+
+        guard.commit_index = 11;
+        assert!(guard.not_ready_reason().unwrap().contains("11 entries behind"));
+        guard.last_applied = 1;
+        assert_eq!(guard.not_ready_reason(), None);
+        (guard.commit_index, guard.last_applied) = (0, 0);
+
+        // 元数据分块到达后即处于接收快照的状态
+        let chunk = proto::InstallSnapshotRequest {
+            term: 1,
+            leader_id: 2,
+            last_included_index: 20,
+            last_included_term: 1,
+            offset: 0,
+            data: vec![1; 4],
+            snapshot_data_type: proto::SnapshotDataType::Metadata as i32,
+            done: false,
+            total_size: 8,
+        };
+        assert!(guard.handle_install_snapshot_rpc(&chunk).await.success);
+        assert!(guard.not_ready_reason().unwrap().contains("receiving snapshot"));
+        assert!(guard.handle_get_status_rpc(&proto::GetStatusRequest {}).await.not_ready_reason.is_some());
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_snapshot_is_not_installed_or_restored() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let start = || test_consensus(&storage, Box::new(VersionedStateMachine { version: "v1", entries: 0 }));
+        let consensus = start().await;
+        let (index, term) = (10, 2);
+        let snapshot_filepath = {
+            let mut guard = consensus.lock().await;
+            let meta = snapshot::SnapshotMeta {
+                last_included_index: index,
+                last_included_term: term,
+                checksum: snapshot::checksum_reader(&b"13"[..]).unwrap(),
+                state_machine_version: Some("v1".to_string()),
+                ..Default::default()
+            };
+            let metadata = serde_json::to_vec(&meta).unwrap();
+            let chunk = |data_type: proto::SnapshotDataType, data: Vec<u8>, done: bool| proto::InstallSnapshotRequest {
+                term,
+                leader_id: 2,
+                last_included_index: index,
+                last_included_term: term,
+                offset: 0,
+                total_size: data.len() as u64,
+                data,
+                snapshot_data_type: data_type as i32,
+                done,
+            };
+
+            // 传输中损坏的数据与元数据中的校验和不一致：拒绝安装，不覆盖本地快照，也不压缩日志
+            assert!(guard.handle_install_snapshot_rpc(&chunk(proto::SnapshotDataType::Metadata, metadata.clone(), false)).await.success);
+            assert!(!guard.handle_install_snapshot_rpc(&chunk(proto::SnapshotDataType::Snapshot, b"14".to_vec(), true)).await.success);
+            assert_eq!(guard.snapshot.last_included_index(), 0);
+            assert!(!std::path::Path::new(&guard.snapshot.gen_tmp_snapshot_filepath(index, term)).exists());
+            assert_eq!(guard.health(), proto::NodeHealth::Ok);
+
+            // Leader 重新发送完整的数据后正常安装
+            assert!(guard.handle_install_snapshot_rpc(&chunk(proto::SnapshotDataType::Metadata, metadata, false)).await.success);
+            assert!(guard.handle_install_snapshot_rpc(&chunk(proto::SnapshotDataType::Snapshot, b"13".to_vec(), true)).await.success);
+            assert_eq!((guard.snapshot.last_included_index(), guard.last_applied), (index, index));
+            guard.shutdown().await;
+            guard.snapshot.latest_snapshot_filepath().unwrap()
+        };
+        drop(consensus);
+
+        // 磁盘上的快照文件损坏后重启：不从它恢复，应用停在快照之前，节点标记为不健康
+        std::fs::write(&snapshot_filepath, b"99").unwrap();
+        let consensus = start().await;
+        let mut guard = consensus.lock().await;
+        assert_eq!(guard.last_applied, 0);
+        assert_eq!(guard.health(), proto::NodeHealth::IncompatibleSnapshot);
+        assert!(guard.not_ready_reason().unwrap().contains("checksum"));
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_snapshot_is_refused() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let start = |version: &'static str| test_consensus(&storage, Box::new(VersionedStateMachine { version, entries: 0 }));
+
+        // 快照元数据记录生成它的状态机版本
+        let consensus = start("v1").await;
+        let snapshot_index = {
+            let mut guard = consensus.lock().await;
+            guard.handle_election_timeout().await;
+            for i in 0..3 {
+                assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: vec![i], wait_for_commit: false }).await.success);
+            }
+            drop(guard);
+            let (index, _) = Consensus::snapshot_for_export(&consensus).await.unwrap();
+            let mut guard = consensus.lock().await;
+            assert_eq!(guard.snapshot.meta().state_machine_version.as_deref(), Some("v1"));
+            guard.shutdown().await;
+            index
+        };
+        drop(consensus);
+
+        // 升级后的状态机不接受旧版本的快照：不恢复，应用停在快照之前，节点标记为 IncompatibleSnapshot
+        let consensus = start("v2").await;
+        {
+            let mut guard = consensus.lock().await;
+            assert_eq!(guard.last_applied, 0);
+            assert_eq!(guard.health(), proto::NodeHealth::IncompatibleSnapshot);
+            assert_eq!(guard.subscribe_view().borrow().health, proto::NodeHealth::IncompatibleSnapshot);
+            assert!(guard.not_ready_reason().unwrap().contains("version v1"));
+
+            // Leader 发来的 v1 快照同样被拒绝，原有的快照保持不变
+            let meta = snapshot::SnapshotMeta {
+                last_included_index: snapshot_index + 10,
+                last_included_term: 2,
+                state_machine_version: Some("v1".to_string()),
+                ..Default::default()
+            };
+            let metadata = serde_json::to_vec(&meta).unwrap();
+            let chunk = |data_type: proto::SnapshotDataType, data: Vec<u8>, done: bool| proto::InstallSnapshotRequest {
+                term: 2,
+                leader_id: 2,
+                last_included_index: snapshot_index + 10,
+                last_included_term: 2,
+                offset: 0,
+                total_size: data.len() as u64,
+                data,
+                snapshot_data_type: data_type as i32,
+                done,
+            };
+            assert!(guard.handle_install_snapshot_rpc(&chunk(proto::SnapshotDataType::Metadata, metadata, false)).await.success);
+            assert!(!guard.handle_install_snapshot_rpc(&chunk(proto::SnapshotDataType::Snapshot, b"13".to_vec(), true)).await.success);
+            assert_eq!(guard.snapshot.last_included_index(), snapshot_index);
+            assert!(!std::path::Path::new(&guard.snapshot.gen_tmp_snapshot_filepath(snapshot_index + 10, 2)).exists());
+            assert_eq!(guard.last_applied, 0);
+
+            // 无法解析的元数据不能当作兼容
+            assert!(guard.handle_install_snapshot_rpc(&chunk(proto::SnapshotDataType::Metadata, b"{".to_vec(), false)).await.success);
+            assert!(!guard.handle_install_snapshot_rpc(&chunk(proto::SnapshotDataType::Snapshot, b"13".to_vec(), true)).await.success);
+            assert_eq!(guard.snapshot.last_included_index(), snapshot_index);
+            assert!(guard.not_ready_reason().unwrap().contains("cannot read metadata"));
+            guard.shutdown().await;
+        }
+        drop(consensus);
+
+        // 回滚到兼容的版本后正常恢复
+        let consensus = start("v1").await;
+        let mut guard = consensus.lock().await;
+        assert_eq!(guard.health(), proto::NodeHealth::Ok);
+        assert_eq!(guard.last_applied, snapshot_index);
+
+        // 逐条应用越过被拒绝的快照之后恢复健康
+        guard.mark_incompatible_snapshot(snapshot_index + 2, "refused".to_string());
+        guard.set_last_applied(snapshot_index + 1);
+        assert_eq!(guard.health(), proto::NodeHealth::IncompatibleSnapshot);
+        guard.set_last_applied(snapshot_index + 2);
+        assert_eq!(guard.health(), proto::NodeHealth::Ok);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_transfer_from_old_term_is_not_finalized() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        let chunk = |term: u64, data_type: proto::SnapshotDataType, done: bool| proto::InstallSnapshotRequest {
+            term,
+            leader_id: 2,
+            last_included_index: 10,
+            last_included_term: 1,
+            offset: 0,
+            data: vec![1; 4],
+            snapshot_data_type: data_type as i32,
+            done,
+            total_size: 4,
+        };
+
+        // 任期 1 的 Leader 发完元数据后，节点进入任期 2，同一个节点在任期 2 接着发送快照数据
+        assert!(guard.handle_install_snapshot_rpc(&chunk(1, proto::SnapshotDataType::Metadata, false)).await.success);
+        let tmp_metadata = guard.snapshot.gen_tmp_snapshot_metadata_filepath(10, 1);
+        assert!(std::path::Path::new(&tmp_metadata).exists());
+        assert!(!guard.handle_install_snapshot_rpc(&chunk(2, proto::SnapshotDataType::Snapshot, true)).await.success);
+        assert!(!std::path::Path::new(&tmp_metadata).exists());
+        assert!(!std::path::Path::new(&guard.snapshot.gen_tmp_snapshot_filepath(10, 1)).exists());
+        assert_eq!((guard.snapshot.last_included_index(), guard.commit_index), (0, 0));
+
+        // 旧任期剩下的分块同样被拒绝
+        assert!(!guard.handle_install_snapshot_rpc(&chunk(1, proto::SnapshotDataType::Snapshot, true)).await.success);
+        assert_eq!(guard.snapshot.last_included_index(), 0);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_records_config_in_effect_at_last_included_index() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(state_machine::SimpleStateMachine::new())).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        let term = guard.metadata.current_term();
+        let server = |id: u64| proto::ServerInfo { server_id: id, server_addr: format!("[::1]:{}", id), zone: None };
+
+        // 已提交并应用的配置条目
+        let committed = config::Config::new_stable(vec![server(1)]);
+        guard.log.append_data(term, vec![(proto::EntryType::Configuration, committed.to_data())]);
+        guard.commit_index = guard.log.last_index(0);
+        guard.apply_committed_entries().await;
+        let (committed_index, last_applied) = (guard.commit_index, guard.last_applied);
+        assert_eq!(last_applied, committed_index);
+
+        // 之后追加、还没有提交的配置条目已经生效，但不属于快照
+        let pending = committed.start_transition(vec![server(1), server(2)]).unwrap();
+        guard.log.append_data(term, vec![(proto::EntryType::Configuration, pending.to_data())]);
+        (guard.current_config, guard.config_index) = (pending, last_applied + 1);
+        drop(guard);
+        Consensus::snapshot_now(&consensus).await.unwrap();
+        let mut guard = consensus.lock().await;
+        assert_eq!(guard.snapshot.last_included_index(), last_applied);
+        assert_eq!(guard.snapshot.configuration(), Some(&committed));
+        assert_eq!(guard.snapshot.meta().config_index, committed_index);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_leader_finalizes_config_change_recovered_from_joint_snapshot() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let (snapshot_dir, metadata_dir) = storage.create_node_dirs(1).unwrap();
+        // 节点在 C(old,new)（索引 3，把自己的地址改为 [::1]:1）提交之后、C(new) 追加之前生成了快照
+        let stable = config::Config::new_stable(vec![proto::ServerInfo { server_id: 1, server_addr: "[::1]:7".to_string(), zone: None }]);
+        let joint = stable.start_transition(vec![proto::ServerInfo { server_id: 1, server_addr: "[::1]:1".to_string(), zone: None }]).unwrap();
+        let mut manager = snapshot::SnapshotManager::new(snapshot_dir.clone());
+        std::fs::write(manager.gen_snapshot_filepath(5, 2), b"5").unwrap();
+        log::Log::new(6, metadata_dir.clone()).dump();
+        metadata::Metadata { current_term: 2, ..metadata::Metadata::new(metadata_dir.clone()) }.store().unwrap();
+        manager.take_snapshot_metadata(5, 2, Some(joint.clone()), 3, None).unwrap();
+
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        assert_eq!((guard.config_index, &guard.current_config), (3, &joint));
+
+        // 成为 Leader 后补上 C(new)，单节点集群立即提交
+        guard.handle_election_timeout().await;
+        assert_eq!(guard.state, State::Leader);
+        assert_eq!(guard.current_config, joint.finalize_transition().unwrap());
+        assert_eq!(guard.config_index, 7);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_single_server_membership_changes() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        let server = |id: u64| proto::ServerInfo { server_id: id, server_addr: format!("[::1]:{}", id), zone: None };
+        let set_config = |ids: &[u64], algorithm: Option<proto::MembershipAlgorithm>| proto::SetConfigurationRequest {
+            new_servers: ids.iter().map(|id| server(*id)).collect(),
+            wait_for_completion: false,
+            algorithm: algorithm.map(|a| a as i32),
+        };
+        let single = Some(proto::MembershipAlgorithm::SingleServer);
+
+        // 单节点变更一次只能变更一个节点；切换算法本身记录在新的配置条目中，单节点集群立即提交
+        // 拒绝的原因返回给调用方
+        let resp = guard.handle_set_configuration_rpc(&set_config(&[1, 2, 3], single)).await;
+        assert!(!resp.success);
+        assert!(resp.error.unwrap().contains("at most 1 is allowed"));
+        let resp = guard.handle_set_configuration_rpc(&set_config(&[1], single)).await;
+        assert_eq!(resp.state, proto::ConfigChangeState::Completed as i32);
+        assert_eq!(guard.current_config.algorithm, config::MembershipAlgorithm::SingleServer);
+        assert_eq!(guard.subscribe_view().borrow().configuration_response().algorithm, proto::MembershipAlgorithm::SingleServer as i32);
+
+        // 沿用当前算法增加节点 2，不经过 C(old,new)；节点 2 联系不上，变更提交之前不能发起新的变更，也不能切换算法
+        let resp = guard.handle_set_configuration_rpc(&set_config(&[1, 2], None)).await;
+        assert!(resp.success);
+        let (_, pending) = guard.log.last_configuration_entry().unwrap();
+        assert!(pending.is_stable());
+        assert!(guard.peer_manager.contains(2));
+        assert!(resp.config_index > guard.commit_index);
+        assert!(!guard.handle_set_configuration_rpc(&set_config(&[1], None)).await.success);
+        assert!(!guard.handle_set_configuration_rpc(&set_config(&[1, 2, 3], Some(proto::MembershipAlgorithm::JointConsensus))).await.success);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_restarted_node_holds_vote_in_persisted_term() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let (_, metadata_dir) = storage.create_node_dirs(1).unwrap();
+        // 上一次启动停在任期 3，投票可能没有落盘
+        metadata::Metadata { current_term: 3, boot_count: 1, ..metadata::Metadata::new(metadata_dir.clone()) }.store().unwrap();
+        let clock = Arc::new(clock::MockClock::new());
+        let consensus = test_consensus_with(
+            &storage,
+            1,
+            vec![proto::ServerInfo { server_id: 2, server_addr: "[::1]:2".to_string(), zone: None }, proto::ServerInfo { server_id: 3, server_addr: "[::1]:3".to_string(), zone: None }],
+            Box::new(CountingStateMachine::default()),
+            clock.clone(),
+        ).await;
+        let mut guard = consensus.lock().await;
+        assert_eq!(metadata::Metadata::load(&metadata_dir).unwrap().boot_count, 2);
+        let vote = |term, candidate_id| proto::RequestVoteRequest { term, candidate_id, last_log_term: 0, last_log_index: 0 };
+
+        // 持久化的任期内暂不投票，更高的任期照常投票
+        assert!(!guard.handle_request_vote_rpc(&vote(3, 2)).await.vote_granted);
+        clock.advance(config::RESTART_VOTE_HOLD);
+        assert!(guard.handle_request_vote_rpc(&vote(3, 2)).await.vote_granted);
+        assert!(!guard.handle_request_vote_rpc(&vote(3, 3)).await.vote_granted);
+        assert!(guard.handle_request_vote_rpc(&vote(4, 3)).await.vote_granted);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_vote_in_new_term_is_durable_before_reply() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let (_, metadata_dir) = storage.create_node_dirs(1).unwrap();
+        // 磁盘上的任期是 3，之后的任期 4 还没有写入过
+        metadata::Metadata { current_term: 3, boot_count: 1, ..metadata::Metadata::new(metadata_dir.clone()) }.store().unwrap();
+        let peers = vec![proto::ServerInfo { server_id: 2, server_addr: "[::1]:2".to_string(), zone: None }, proto::ServerInfo { server_id: 3, server_addr: "[::1]:3".to_string(), zone: None }];
+        let vote = |term, candidate_id| proto::RequestVoteRequest { term, candidate_id, last_log_term: 0, last_log_index: 0 };
+        let clock = Arc::new(clock::MockClock::new());
+        let consensus = test_consensus_with(&storage, 1, peers.clone(), Box::new(CountingStateMachine::default()), clock.clone()).await;
+        {
+            let mut guard = consensus.lock().await;
+            assert!(guard.handle_request_vote_rpc(&vote(4, 2)).await.vote_granted);
+            // 回复之前投票已经落盘，不依赖之后的后台写入
+            let persisted = metadata::Metadata::load(&metadata_dir).unwrap();
+            assert_eq!((persisted.current_term, persisted.voted_for), (4, 2));
+            guard.shutdown().await;
+        }
+
+        // 重启之后（等过暂不投票的时间）同一任期不会再投给另一个候选人
+        let clock = Arc::new(clock::MockClock::new());
+        let consensus = test_consensus_with(&storage, 1, peers, Box::new(CountingStateMachine::default()), clock.clone()).await;
+        let mut guard = consensus.lock().await;
+        clock.advance(config::RESTART_VOTE_HOLD);
+        assert!(!guard.handle_request_vote_rpc(&vote(4, 3)).await.vote_granted);
+        assert!(guard.handle_request_vote_rpc(&vote(4, 2)).await.vote_granted);
+
+        // 发起选举时给自己的投票同样先落盘
+        guard.handle_election_timeout().await;
+        let persisted = metadata::Metadata::load(&metadata_dir).unwrap();
+        assert_eq!((persisted.current_term, persisted.voted_for), (5, 1));
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_disk_quota_compacts_then_applies_backpressure() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let (snapshot_dir, _) = storage.create_node_dirs(1).unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine { write_snapshots: true, ..Default::default() })).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        // 只让磁盘配额触发压缩，应用路径上的快照检查不介入
+        guard.snapshot_policy = config::SnapshotPolicy { log_length_threshold: 1000, ..Default::default() };
+        let term = guard.metadata.get().await.current_term;
+        let entries = (0..50).map(|_| (proto::EntryType::Data, vec![b'x'; 1024])).collect();
+        guard.log.append_data(term, entries);
+        guard.log.persist();
+        guard.leader_advance_commit_index().await;
+        while guard.last_applied < guard.commit_index {
+            guard.apply_committed_entries().await;
+        }
+        let stale = format!("{}/raft-1-1.snapshot", snapshot_dir);
+        std::fs::write(&stale, vec![0u8; 16 * 1024]).unwrap();
+
+        // 快照在后台任务中生成，等它完成后重新加锁
+        let snapshot_finished = || async {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while consensus.lock().await.snapshot_running {
+                    tokio::task::yield_now().await;
+                }
+            }).await.unwrap();
+            consensus.lock().await
+        };
+
+        // 日志和旧快照合计超过配额：压缩完成之前拒绝写入，压缩后降到配额以下，Propose 照常接受
+        let (log_bytes, snapshot_bytes) = guard.disk_usage();
+        assert!(log_bytes > 50 * 1024);
+        assert!(snapshot_bytes >= 16 * 1024);
+        guard.disk_quota_bytes = Some((log_bytes + snapshot_bytes) / 4);
+        guard.handle_snapshot_timeout().await;
+        let request = proto::ProposeRequest { data: b"after quota".to_vec(), wait_for_commit: false };
+        assert!(!guard.handle_propose_rpc(&request).await.success);
+        drop(guard);
+        let mut guard = snapshot_finished().await;
+        assert!(guard.handle_propose_rpc(&request).await.success);
+        assert!(guard.snapshot.last_included_index() > 0);
+        assert!(!std::path::Path::new(&stale).exists());
+        let status = guard.handle_get_snapshot_status_rpc(&proto::GetSnapshotStatusRequest {});
+        assert!(status.log_bytes + status.snapshot_dir_bytes <= guard.disk_quota_bytes.unwrap());
+        assert_eq!(status.disk_quota_bytes, guard.disk_quota_bytes);
+
+        // 压缩之后仍然超过配额时拒绝写入，直到下一次定时检查
+        guard.disk_quota_bytes = Some(1);
+        assert!(guard.handle_propose_rpc(&request).await.success);
+        guard.handle_snapshot_timeout().await;
+        drop(guard);
+        let mut guard = snapshot_finished().await;
+        let resp = guard.handle_propose_rpc(&request).await;
+        assert!(!resp.success);
+        assert_eq!(resp.reject_reason, proto::ProposeRejectReason::Backpressure as i32);
+        let metrics = guard.metrics.snapshot();
+        assert_eq!(metrics.disk_quota_compactions, 2);
+        assert_eq!(metrics.disk_quota_rejections, 2);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_policy_defers_until_forced() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine { write_snapshots: true, ..Default::default() })).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        // 写入速率不会低于 0，定时快照总是被推迟
+        guard.snapshot_policy = config::SnapshotPolicy { max_entry_rate: Some(0.0), ..Default::default() };
+        for i in 0..10 {
+            assert!(guard.handle_propose_rpc(&proto::ProposeRequest { data: vec![i], wait_for_commit: false }).await.success);
+        }
+        guard.handle_snapshot_timeout().await;
+        assert_eq!(guard.snapshot.last_included_index(), 0);
+        let status = guard.handle_get_snapshot_status_rpc(&proto::GetSnapshotStatusRequest {});
+        assert!(status.snapshot_deferred.unwrap().contains("entry rate"));
+
+        // TriggerSnapshot 不受调度策略限制
+        drop(guard);
+        let resp = Consensus::trigger_snapshot(&consensus, &proto::TriggerSnapshotRequest {}).await;
+        let mut guard = consensus.lock().await;
+        assert!(resp.success);
+        assert_eq!(resp.status.unwrap().snapshot_deferred, None);
+        assert_eq!(guard.snapshot.last_included_index(), guard.last_applied);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_taken_after_apply_without_timer() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let clock = Arc::new(clock::MockClock::new());
+        let consensus = test_consensus_with(&storage, 1, Vec::new(), Box::new(CountingStateMachine { write_snapshots: true, ..Default::default() }), clock.clone()).await;
+        {
+            let mut guard = consensus.lock().await;
+            guard.handle_election_timeout().await;
+            guard.snapshot_policy = config::SnapshotPolicy { log_length_threshold: 3, ..Default::default() };
+        }
+        let propose = |i: u8| proto::ProposeRequest { data: vec![i], wait_for_commit: false };
+        // 快照在后台任务中生成，等它完成
+        let snapshot_finished = || async {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while consensus.lock().await.snapshot_running {
+                    tokio::task::yield_now().await;
+                }
+            }).await.unwrap();
+            let guard = consensus.lock().await;
+            (guard.snapshot.last_included_index(), guard.last_applied)
+        };
+
+        // 应用的条目超过阈值后生成快照
+        for i in 0..3 {
+            assert!(consensus.lock().await.handle_propose_rpc(&propose(i)).await.success);
+        }
+        let (first, last_applied) = snapshot_finished().await;
+        assert_eq!(first, last_applied);
+
+        // 两次检查之间至少间隔 SNAPSHOT_CHECK_MIN_INTERVAL
+        for i in 3..7 {
+            assert!(consensus.lock().await.handle_propose_rpc(&propose(i)).await.success);
+        }
+        assert_eq!(snapshot_finished().await.0, first);
+        clock.advance(config::SNAPSHOT_CHECK_MIN_INTERVAL);
+        assert!(consensus.lock().await.handle_propose_rpc(&propose(7)).await.success);
+        let (second, last_applied) = snapshot_finished().await;
+        assert_eq!(second, last_applied);
+        consensus.lock().await.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_partitioned_peer_is_isolated() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let mut guard = consensus.lock().await;
+        guard.handle_election_timeout().await;
+        let last_log_index = guard.log.last_index(guard.snapshot.last_included_index());
+        guard.peer_manager.add(vec![peer::Peer::new(2, "[::1]:2".to_string()), peer::Peer::new(3, "[::1]:3".to_string())], last_log_index);
+
+        // 本节点自己的 ID 被忽略
+        let resp = guard.handle_set_partition_rpc(&proto::SetPartitionRequest { blocked_server_ids: vec![2, 1] });
+        assert_eq!(resp.blocked_server_ids, vec![2]);
+        let heartbeats: Vec<u64> = guard.collect_heartbeats().await.into_iter().map(|(id, _, _)| id).collect();
+        assert_eq!(heartbeats, vec![3]);
+        assert!(guard.check_request("RequestVote", 2, |_, _| Ok(())).await.is_err());
+        assert!(guard.check_request("RequestVote", 3, |_, _| Ok(())).await.is_ok());
+
+        assert!(guard.handle_set_partition_rpc(&proto::SetPartitionRequest { blocked_server_ids: Vec::new() }).blocked_server_ids.is_empty());
+        assert_eq!(guard.collect_heartbeats().await.len(), 2);
+        assert!(guard.check_request("RequestVote", 2, |_, _| Ok(())).await.is_ok());
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_address_is_flagged_and_fixed() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(CountingStateMachine::default())).await;
+        let view = {
+            let mut guard = consensus.lock().await;
+            assert_eq!(guard.health(), proto::NodeHealth::Ok);
+            guard.handle_election_timeout().await;
+
+            // 换了端口重启：配置里仍然是旧地址
+            guard.server_addr = "[::1]:2".to_string();
+            guard.check_advertised_address();
+            assert_eq!(guard.config_mismatch.as_deref(), Some("[::1]:1"));
+            assert_eq!(guard.subscribe_view().borrow().health, proto::NodeHealth::ConfigMismatch);
+            guard.subscribe_view()
+        };
+
+        // Leader 在后台发起配置变更把地址改正，C(new) 提交后恢复正常
+        let fixed = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                {
+                    let guard = consensus.lock().await;
+                    if guard.config_mismatch.is_none() && guard.current_config.is_stable() {
+                        return guard.current_config.new_servers.clone();
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+        assert_eq!(fixed, vec![proto::ServerInfo { server_id: 1, server_addr: "[::1]:2".to_string(), zone: None }]);
+        assert_eq!(view.borrow().health, proto::NodeHealth::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_follower_apply_is_paced_by_rate_limit() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let clock = Arc::new(clock::MockClock::new());
+        let consensus = test_consensus_with(&storage, 1, Vec::new(), Box::new(state_machine::SimpleStateMachine::new()), clock.clone()).await;
+        let limit = config::ApplyRateLimit { entries_per_sec: Some(1.0), bytes_per_sec: None, burst_entries: 2, burst_bytes: 0 };
+        consensus.lock().await.set_apply_rate_limit(Some(limit)).await;
+
+        // 一次追上 6 个已提交的条目：突发额度内应用 2 个，剩下的等待令牌
+        let entries = (1..=6)
+            .map(|index| proto::LogEntry { term: 1, index, entry_type: proto::EntryType::Data as i32, data: vec![b'x'], timestamp_ms: None })
+            .collect();
+        let request = proto::AppendEntriesRequest { term: 1, leader_id: 2, prev_log_index: 0, prev_log_term: 0, entries, leader_commit: 6, ..Default::default() };
+        {
+            let mut guard = consensus.lock().await;
+            assert!(guard.handle_append_entries_rpc(&request).await.success);
+            assert_eq!(guard.last_applied, 2);
+            assert_eq!(guard.metrics.snapshot().apply_throttled, 1);
+        }
+
+        // 过 1 秒补充一个令牌，后台任务应用一个条目后再次暂停
+        clock.advance(Duration::from_secs(1));
+        while consensus.lock().await.last_applied < 3 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(consensus.lock().await.last_applied, 3);
+
+        // 运行中调高限速：新的令牌桶立即应用一个条目，下一个按新的限速等待，不必等到旧的暂停结束
+        let faster = config::ApplyRateLimit { entries_per_sec: Some(100.0), bytes_per_sec: None, burst_entries: 1, burst_bytes: 0 };
+        consensus.lock().await.set_apply_rate_limit(Some(faster)).await;
+        assert_eq!(consensus.lock().await.last_applied, 4);
+        clock.advance(Duration::from_millis(10));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while consensus.lock().await.last_applied < 5 {
+                tokio::task::yield_now().await;
+            }
+        }).await.unwrap();
+
+        // 运行中取消限速，剩下的条目立即应用
+        let mut guard = consensus.lock().await;
+        guard.set_apply_rate_limit(None).await;
+        assert_eq!(guard.last_applied, 6);
+        guard.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_rejected_set_configuration_is_audited_with_reason() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = test_consensus(&storage, Box::new(state_machine::SimpleStateMachine::new())).await;
+        let request = proto::SetConfigurationRequest { new_servers: Vec::new(), wait_for_completion: true, algorithm: None };
+        {
+            let mut guard = consensus.lock().await;
+            let response = guard.handle_set_configuration_rpc(&request).await;
+            assert!(!response.success);
+            guard.audit_command("local", proto::ManagementCommand::SetConfiguration, &request, response.success, response.error);
+        }
+
+        let page = Consensus::get_command_audit(&consensus, &proto::GetCommandAuditRequest { start_seq: 0, max_records: 0 }).await;
+        assert_eq!(page.records.len(), 1);
+        assert_eq!(page.records[0].error.as_deref(), Some("SetConfiguration can only be handled by the leader"));
+        consensus.lock().await.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_log_refuses_to_start() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let (snapshot_dir, metadata_dir) = storage.create_node_dirs(1).unwrap();
+        std::fs::write(log::Log::gen_log_filepath(&metadata_dir), b"{\"start_index\": 1, \"entries\": [").unwrap();
+        let started = Consensus::new(1, "[::1]:1".to_string(), Vec::new(), Box::new(state_machine::SimpleStateMachine::new()),
+            snapshot_dir, metadata_dir, Arc::new(clock::SystemClock)).await;
+        assert!(started.is_err_and(|e| e.contains("corrupted")));
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_metadata_refuses_to_start() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let (snapshot_dir, metadata_dir) = storage.create_node_dirs(1).unwrap();
+        let metadata_filepath = metadata::Metadata::gen_metadata_filepath(&metadata_dir);
+        std::fs::write(&metadata_filepath, b"{\"current_term\": 7, ").unwrap();
+        let started = Consensus::new(1, "[::1]:1".to_string(), Vec::new(), Box::new(state_machine::SimpleStateMachine::new()),
+            snapshot_dir, metadata_dir, Arc::new(clock::SystemClock)).await;
+        assert!(started.is_err_and(|e| e.contains("failed to load metadata")));
+        // 读不出来的文件保持原样，留给人工处理
+        assert_eq!(std::fs::read(&metadata_filepath).unwrap(), b"{\"current_term\": 7, ");
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_initial_servers_refuse_to_start() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let (snapshot_dir, metadata_dir) = storage.create_node_dirs(1).unwrap();
+        // 另一个 ID 使用了本节点的地址
+        let peers = vec![proto::ServerInfo { server_id: 2, server_addr: "[::1]:1".to_string(), zone: None }];
+        let started = Consensus::new(1, "[::1]:1".to_string(), peers, Box::new(state_machine::SimpleStateMachine::new()),
+            snapshot_dir, metadata_dir, Arc::new(clock::SystemClock)).await;
+        assert!(started.is_err_and(|e| e.contains("invalid initial cluster configuration")));
+    }
+}
@@ -0,0 +1,214 @@
+use crate::raft::config;
+use crate::raft::consensus::State;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::{Arc, Mutex as StdMutex, Once, TryLockError, Weak};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// 崩溃现场：共识模块在日志操作和角色变化时把自身状态的摘要和最近的日志操作记到这里，
+// 共识任务 panic 时 panic hook 把所有存活节点的现场写入各自的崩溃文件后中止进程。
+// panic 可能发生在持有共识模块锁的时候，hook 只用 try_lock 读取这里的数据，不会因此卡住
+
+tokio::task_local! {
+    // 标记当前任务属于共识模块，只有这些任务的 panic 会转储现场并中止进程
+    static CONSENSUS_TASK: ();
+}
+
+// 把 future 标记为共识任务
+pub fn scope<F: Future>(future: F) -> tokio::task::futures::TaskLocalFuture<(), F> {
+    CONSENSUS_TASK.scope((), future)
+}
+
+// 在当前线程上以共识任务的身份执行 f，用于 spawn_blocking 中的同步代码
+pub fn scope_blocking<R>(f: impl FnOnce() -> R) -> R {
+    CONSENSUS_TASK.sync_scope((), f)
+}
+
+fn in_consensus_task() -> bool {
+    CONSENSUS_TASK.try_with(|_| ()).is_ok()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerProgress {
+    pub server_id: u64,
+    pub next_index: u64,
+    pub match_index: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CrashReport {
+    pub server_id: u64,
+    pub term: u64,
+    pub role: Option<State>,
+    pub commit_index: u64,
+    pub last_applied: u64,
+    pub log_start_index: u64,
+    pub log_last_index: u64,
+    pub snapshot_last_included_index: u64,
+    pub peers: Vec<PeerProgress>,             // 只有 Leader 的复制进度有意义
+    pub log_ops: VecDeque<(u64, String)>,     // 最近的日志操作及其时间（Unix 毫秒），最多 CRASH_DUMP_LOG_OPS 条
+}
+
+impl CrashReport {
+    fn render(&self, panic_message: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "panic: {}", panic_message);
+        let _ = writeln!(out, "time_ms: {}", unix_millis());
+        let _ = writeln!(out, "server_id: {} term: {} role: {:?}", self.server_id, self.term, self.role);
+        let _ = writeln!(out, "commit_index: {} last_applied: {}", self.commit_index, self.last_applied);
+        let _ = writeln!(out, "log: [{}, {}] snapshot_last_included_index: {}", self.log_start_index, self.log_last_index, self.snapshot_last_included_index);
+        for peer in &self.peers {
+            let _ = writeln!(out, "peer {}: next_index {} match_index {}", peer.server_id, peer.next_index, peer.match_index);
+        }
+        let _ = writeln!(out, "last {} log operations:", self.log_ops.len());
+        for (at_ms, op) in &self.log_ops {
+            let _ = writeln!(out, "  {} {}", at_ms, op);
+        }
+        out
+    }
+}
+
+pub struct CrashRecorder {
+    filepath: String,
+    report: StdMutex<CrashReport>,
+    peers_refreshed_at: StdMutex<Option<Instant>>, // 上次刷新复制进度的时间，见 peers_due
+}
+
+impl CrashRecorder {
+    pub fn new(server_id: u64, metadata_dir: &str) -> Self {
+        CrashRecorder {
+            filepath: Self::gen_filepath(metadata_dir),
+            report: StdMutex::new(CrashReport { server_id, ..Default::default() }),
+            peers_refreshed_at: StdMutex::new(None),
+        }
+    }
+
+    pub fn gen_filepath(metadata_dir: &str) -> String {
+        format!("{}/raft.crash", metadata_dir)
+    }
+
+    pub fn filepath(&self) -> &str {
+        &self.filepath
+    }
+
+    // 更新状态摘要，日志操作保持不变
+    pub fn update(&self, update: impl FnOnce(&mut CrashReport)) {
+        update(&mut self.report.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    // 复制进度需要遍历所有节点，日志操作时最多每 CRASH_DUMP_PEERS_REFRESH_INTERVAL 刷新一次
+    pub fn peers_due(&self) -> bool {
+        let mut refreshed_at = self.peers_refreshed_at.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if refreshed_at.is_some_and(|at| now.duration_since(at) < config::CRASH_DUMP_PEERS_REFRESH_INTERVAL) {
+            return false;
+        }
+        *refreshed_at = Some(now);
+        true
+    }
+
+    pub fn record_log_op(&self, op: String) {
+        let mut report = self.report.lock().unwrap_or_else(|e| e.into_inner());
+        if report.log_ops.len() >= config::CRASH_DUMP_LOG_OPS {
+            report.log_ops.pop_front();
+        }
+        report.log_ops.push_back((unix_millis(), op));
+    }
+
+    pub fn report(&self) -> CrashReport {
+        self.report.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // 把现场写入崩溃文件，覆盖上一次的内容
+    pub fn dump(&self, panic_message: &str) -> std::io::Result<()> {
+        let content = match self.report.try_lock() {
+            Ok(report) => report.render(panic_message),
+            Err(TryLockError::Poisoned(e)) => e.into_inner().render(panic_message),
+            Err(TryLockError::WouldBlock) => format!("panic: {}\ncrash report was being updated by the panicking thread\n", panic_message),
+        };
+        std::fs::write(&self.filepath, content)
+    }
+}
+
+static RECORDERS: StdMutex<Vec<Weak<CrashRecorder>>> = StdMutex::new(Vec::new());
+static INSTALL_HOOK: Once = Once::new();
+
+// 登记一个节点的现场，第一次调用时安装 panic hook。共识任务（见 scope）panic 时先转储所有存活节点的现场，
+// 再调用之前的 hook 打印 panic 信息，然后中止进程；其他 panic 直接交给之前的 hook，行为不变
+pub fn install(recorder: &Arc<CrashRecorder>) {
+    {
+        let mut recorders = RECORDERS.lock().unwrap_or_else(|e| e.into_inner());
+        recorders.retain(|r| r.strong_count() > 0);
+        recorders.push(Arc::downgrade(recorder));
+    }
+    INSTALL_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !in_consensus_task() {
+                previous(info);
+                return;
+            }
+            let message = info.to_string();
+            let recorders = match RECORDERS.try_lock() {
+                Ok(recorders) => recorders.clone(),
+                Err(TryLockError::Poisoned(e)) => e.into_inner().clone(),
+                Err(TryLockError::WouldBlock) => Vec::new(),
+            };
+            for recorder in recorders.iter().filter_map(Weak::upgrade) {
+                match recorder.dump(&message) {
+                    Ok(()) => eprintln!("Crash report written to {}", recorder.filepath()),
+                    Err(e) => eprintln!("Failed to write crash report to {}: {}", recorder.filepath(), e),
+                }
+            }
+            previous(info);
+            std::process::abort();
+        }));
+    });
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_crash_dump_keeps_recent_log_ops() {
+        let dir = tempdir().unwrap();
+        let recorder = CrashRecorder::new(2, dir.path().to_str().unwrap());
+        recorder.update(|report| {
+            report.term = 7;
+            report.role = Some(State::Leader);
+            report.commit_index = 120;
+            report.peers = vec![PeerProgress { server_id: 3, next_index: 118, match_index: 117 }];
+        });
+        for i in 0..config::CRASH_DUMP_LOG_OPS + 20 {
+            recorder.record_log_op(format!("append [{}, {}]", i, i));
+        }
+        assert_eq!(recorder.report().log_ops.len(), config::CRASH_DUMP_LOG_OPS);
+
+        recorder.dump("panicked at consensus.rs:1:1").unwrap();
+        let content = std::fs::read_to_string(recorder.filepath()).unwrap();
+        assert!(content.contains("server_id: 2 term: 7 role: Some(Leader)"));
+        assert!(content.contains("peer 3: next_index 118 match_index 117"));
+        assert!(content.contains(&format!("append [{}, {}]", config::CRASH_DUMP_LOG_OPS + 19, config::CRASH_DUMP_LOG_OPS + 19)));
+        assert!(!content.contains("append [19, 19]"));
+    }
+
+    #[test]
+    fn test_panics_outside_consensus_tasks_are_not_dumped() {
+        let dir = tempdir().unwrap();
+        let recorder = Arc::new(CrashRecorder::new(1, dir.path().to_str().unwrap()));
+        install(&recorder);
+        assert!(scope_blocking(in_consensus_task));
+        assert!(!in_consensus_task());
+
+        // 不属于共识任务的 panic 交给之前的 hook，进程继续运行，也不写崩溃文件
+        assert!(std::panic::catch_unwind(|| panic!("unrelated panic")).is_err());
+        assert!(!std::path::Path::new(recorder.filepath()).exists());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::raft::consensus::{Consensus, State};
+use crate::raft::{config, proto, rpc};
+use super::logging::*;
+use std::sync::Arc;
+use tokio::sync::Mutex as TokioMutex;
+
+// 节点下线流程，由 Leader 执行：
+//   1. 通知目标节点不再参与竞选；目标是 Leader 自己时先把领导权转移出去，再把请求转交给新 Leader
+//   2. 等目标节点的日志追上（目标不可达时跳过）
+//   3. 通过配置变更把目标移出集群，并等待 C(new) 提交
+//   4. 通知目标节点关闭，按需清空数据目录
+// 任何一步失败都会返回错误；目标节点还在配置中时撤销第 1 步，让它重新参与竞选，修复问题后可以重新发起
+
+pub async fn decommission(consensus: &Arc<TokioMutex<Consensus>>, request: &proto::DecommissionRequest) -> proto::DecommissionResponse {
+    let target_id = request.server_id;
+    let (leader, term, target_addr, successor, rpc_client) = {
+        let guard = consensus.lock().await;
+        let leader = proto::ServerInfo {
+            server_id: guard.server_id,
+            server_addr: guard.server_addr.clone(),
+            zone: guard.current_config.zone_of(guard.server_id).map(str::to_string),
+        };
+        if guard.state != State::Leader {
+            return failed(None, 0, "Decommission can only be handled by the leader".to_string());
+        }
+        if guard.current_config.is_joint() {
+            return failed(Some(leader), 0, "a configuration change is already in progress".to_string());
+        }
+        let servers = guard.current_config.all_servers_in_config();
+        let Some(target) = servers.iter().find(|s| s.server_id == target_id) else {
+            return failed(Some(leader), 0, format!("server {} is not in the current configuration", target_id));
+        };
+        if servers.len() == 1 {
+            return failed(Some(leader), 0, "cannot decommission the only server of the cluster".to_string());
+        }
+        // 领导权交给日志最新的其他节点，优先选择和 Leader 同一可用区的节点
+        let own_zone = leader.zone.as_deref();
+        let successor = guard.peer_manager.peers().iter()
+            .filter(|p| p.id != target_id && servers.iter().any(|s| s.server_id == p.id))
+            .max_by_key(|p| (own_zone.is_some() && guard.current_config.zone_of(p.id) == own_zone, p.match_index))
+            .map(|p| proto::ServerInfo { server_id: p.id, server_addr: p.addr.clone(), zone: guard.current_config.zone_of(p.id).map(str::to_string) });
+        (leader, guard.metadata.get().await.current_term, target.server_addr.clone(), successor, guard.rpc_client())
+    };
+    info!("Decommissioning server {} ({}), wipe data: {}", target_id, target_addr, request.wipe_data);
+
+    if target_id == leader.server_id {
+        return hand_over(consensus, &rpc_client, leader, successor, request).await;
+    }
+
+    // 1. 目标不再参与竞选
+    let retire = proto::RetireRequest { term, leader_id: leader.server_id, shutdown: false, wipe_data: false, cancel: false };
+    let reachable = match rpc_client.retire(retire, target_addr.clone()).await {
+        Ok(resp) if resp.success => true,
+        Ok(resp) => return failed(Some(leader), 0, format!("server {} refused to retire (term {})", target_id, resp.term)),
+        Err(e) => {
+            warn!("Server {} is unreachable ({}), removing it without waiting for it to catch up.", target_id, e);
+            false
+        }
+    };
+
+    // 2. 等目标追上，移除后它仍然可以把已有的日志交给其他节点
+    if reachable {
+        if let Err(e) = wait_for_catch_up(consensus, target_id).await {
+            cancel_retire(&rpc_client, retire, &target_addr).await;
+            return failed(Some(leader), 0, e);
+        }
+    }
+
+    // 3. 移出配置并等待完成
+    let new_servers = {
+        let guard = consensus.lock().await;
+        guard.current_config.all_servers_in_config().into_iter().filter(|s| s.server_id != target_id).collect()
+    };
+    let set_config = proto::SetConfigurationRequest { new_servers, wait_for_completion: true, algorithm: None };
+    let resp = consensus.lock().await.handle_set_configuration_rpc(&set_config).await;
+    if !resp.success {
+        if reachable {
+            cancel_retire(&rpc_client, retire, &target_addr).await;
+        }
+        return failed(Some(leader), 0, format!("configuration change to remove the server was rejected: {}", resp.error.unwrap_or_default()));
+    }
+    let config_index = resp.config_index;
+    let state = Consensus::wait_for_config_change(consensus, config_index, config::CONFIG_CHANGE_WAIT_TIMEOUT).await;
+    // 还没有完成的变更之后仍可能把目标移出配置，只有确定被放弃时才撤销
+    if state == proto::ConfigChangeState::Aborted && reachable {
+        cancel_retire(&rpc_client, retire, &target_addr).await;
+    }
+    if state != proto::ConfigChangeState::Completed {
+        return failed(Some(leader), config_index, format!("configuration change at index {} did not complete: {:?}", config_index, state));
+    }
+
+    // 4. 通知目标关闭；目标不可达时由运维自行处理
+    let retire = proto::RetireRequest { term, leader_id: leader.server_id, shutdown: true, wipe_data: request.wipe_data, cancel: false };
+    let shutdown_signaled = match rpc_client.retire(retire, target_addr.clone()).await {
+        Ok(resp) => resp.success,
+        Err(e) => {
+            warn!("Failed to signal decommissioned server {} to shut down: {}", target_id, e);
+            false
+        }
+    };
+    info!("Server {} decommissioned at config index {} (shutdown signaled: {})", target_id, config_index, shutdown_signaled);
+    proto::DecommissionResponse { success: true, error: None, leader: Some(leader), config_index, shutdown_signaled }
+}
+
+// 下线的是 Leader 自己：停止竞选，转移领导权，然后把请求交给新 Leader 执行
+async fn hand_over(
+    consensus: &Arc<TokioMutex<Consensus>>,
+    rpc_client: &rpc::Client,
+    leader: proto::ServerInfo,
+    successor: Option<proto::ServerInfo>,
+    request: &proto::DecommissionRequest,
+) -> proto::DecommissionResponse {
+    let Some(successor) = successor else {
+        return failed(Some(leader), 0, "no other server to transfer leadership to".to_string());
+    };
+    consensus.lock().await.non_candidate = true;
+    if let Err(e) = Consensus::transfer_leadership(consensus, successor.server_id).await {
+        consensus.lock().await.non_candidate = false;
+        return failed(Some(leader), 0, format!("leadership transfer to server {} failed: {}", successor.server_id, e));
+    }
+
+    // 新 Leader 当选需要一点时间，在此期间它会拒绝请求
+    let deadline = tokio::time::Instant::now() + config::LEADER_TRANSFER_TIMEOUT;
+    loop {
+        match rpc_client.decommission(*request, successor.server_addr.clone()).await {
+            Ok(resp) if resp.success || resp.leader.is_some() => return resp,
+            Ok(resp) => debug!("Server {} is not the leader yet: {:?}", successor.server_id, resp.error),
+            Err(e) => debug!("Forwarding Decommission to server {} failed: {}", successor.server_id, e),
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return failed(None, 0, format!("leadership moved to server {}, retry Decommission there", successor.server_id));
+        }
+        tokio::time::sleep(config::LEADER_TRANSFER_POLL_INTERVAL).await;
+    }
+}
+
+async fn wait_for_catch_up(consensus: &Arc<TokioMutex<Consensus>>, target_id: u64) -> Result<(), String> {
+    let deadline = tokio::time::Instant::now() + config::DECOMMISSION_CATCH_UP_TIMEOUT;
+    let target_index = {
+        let guard = consensus.lock().await;
+        guard.log.last_index(guard.snapshot.last_included_index())
+    };
+    loop {
+        {
+            let mut guard = consensus.lock().await;
+            if guard.state != State::Leader {
+                return Err("no longer the leader".to_string());
+            }
+            match guard.peer_manager.peer(target_id) {
+                Some(peer) if peer.match_index >= target_index => return Ok(()),
+                Some(_) => {}
+                None => return Err(format!("server {} is no longer a peer", target_id)),
+            }
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(format!("server {} did not catch up to index {} within {:?}", target_id, target_index, config::DECOMMISSION_CATCH_UP_TIMEOUT));
+        }
+        tokio::time::sleep(config::LEADER_TRANSFER_POLL_INTERVAL).await;
+    }
+}
+
+// 撤销第 1 步的通知；目标节点收不到时仍然不参与竞选，重新发起下线或者重启节点即可恢复
+async fn cancel_retire(rpc_client: &rpc::Client, retire: proto::RetireRequest, target_addr: &str) {
+    let cancel = proto::RetireRequest { cancel: true, ..retire };
+    if let Err(e) = rpc_client.retire(cancel, target_addr.to_string()).await {
+        warn!("Failed to tell {} that its decommission was abandoned: {}", target_addr, e);
+    }
+}
+
+fn failed(leader: Option<proto::ServerInfo>, config_index: u64, error: String) -> proto::DecommissionResponse {
+    warn!("Decommission failed: {}", error);
+    proto::DecommissionResponse { success: false, error: Some(error), leader, config_index, shutdown_signaled: false }
+}
@@ -0,0 +1,306 @@
+use crate::raft::{config, proto, rpc};
+use std::collections::BTreeMap;
+
+// 集群诊断：向每个节点查询 GetStatus，交叉比较任期、提交位置、配置和日志范围，
+// 给出问题和建议的处理方式。只读，不会改变集群的状态；
+// 各节点的视图不是同一时刻的快照，落后量只有超过阈值才报告
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub problem: String,
+    pub remediation: String,
+}
+
+// 一个节点的查询结果
+#[derive(Debug, Clone)]
+pub struct NodeReport {
+    pub addr: String,
+    pub status: Result<proto::GetStatusResponse, String>,
+}
+
+// 并发查询所有节点，每个节点最多等待 DOCTOR_RPC_TIMEOUT
+pub async fn collect(addrs: &[String]) -> Vec<NodeReport> {
+    let queries = addrs.iter().map(|addr| async move {
+        let client = rpc::Client::default();
+        let status = match tokio::time::timeout(config::DOCTOR_RPC_TIMEOUT, client.get_status(proto::GetStatusRequest {}, addr.clone())).await {
+            Ok(Ok(status)) => Ok(status),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("no answer within {:?}", config::DOCTOR_RPC_TIMEOUT)),
+        };
+        NodeReport { addr: addr.clone(), status }
+    });
+    futures::future::join_all(queries).await
+}
+
+fn node_name(status: &proto::GetStatusResponse) -> String {
+    format!("{} ({})", status.server_id, status.server_addr)
+}
+
+fn server_ids(servers: &[proto::ServerInfo]) -> Vec<u64> {
+    let mut ids: Vec<u64> = servers.iter().map(|s| s.server_id).collect();
+    ids.sort_unstable();
+    ids
+}
+
+fn format_config(status: &proto::GetStatusResponse) -> String {
+    if status.old_servers.is_empty() {
+        format!("{:?}", server_ids(&status.new_servers))
+    } else {
+        format!("old={:?} new={:?}", server_ids(&status.old_servers), server_ids(&status.new_servers))
+    }
+}
+
+fn finding(severity: Severity, problem: String, remediation: &str) -> Finding {
+    Finding { severity, problem, remediation: remediation.to_string() }
+}
+
+// 按严重程度从高到低返回发现的问题，没有问题时为空
+pub fn diagnose(reports: &[NodeReport]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut nodes = Vec::new();
+    for report in reports {
+        match &report.status {
+            Ok(status) => nodes.push(status),
+            Err(e) => findings.push(finding(Severity::Warning,
+                format!("Node {} did not answer GetStatus: {}", report.addr, e),
+                "Check that the process is running and that the address is reachable from here.")),
+        }
+    }
+    if nodes.is_empty() {
+        findings.push(finding(Severity::Critical, "No node answered, nothing to cross-check.".to_string(),
+            "Check the addresses passed to doctor and the network between this host and the cluster."));
+        return findings;
+    }
+
+    // 同一任期内只能有一个 Leader；不同任期的 Leader 中任期较小的已经过时
+    let mut leaders_by_term: BTreeMap<u64, Vec<&proto::GetStatusResponse>> = BTreeMap::new();
+    for node in nodes.iter().filter(|n| n.role == proto::NodeRole::Leader as i32) {
+        leaders_by_term.entry(node.current_term).or_default().push(node);
+    }
+    for (term, leaders) in leaders_by_term.iter().filter(|(_, leaders)| leaders.len() > 1) {
+        findings.push(finding(Severity::Critical,
+            format!("Split leader: nodes {} all claim leadership of term {}.",
+                leaders.iter().map(|n| node_name(n)).collect::<Vec<_>>().join(", "), term),
+            "Two leaders in one term violates election safety. Stop all but one of them, keep the node with the longest log, and rebuild the others from its snapshot."));
+    }
+    let leader = leaders_by_term.values().next_back().map(|leaders| leaders[0]);
+    for (term, leaders) in leaders_by_term.iter().rev().skip(1) {
+        for stale in leaders {
+            findings.push(finding(Severity::Warning,
+                format!("Stale leader: node {} still acts as leader of term {}, but term {} has a newer leader.",
+                    node_name(stale), term, leader.map_or(0, |l| l.current_term)),
+                "The node is cut off from the cluster and steps down once it hears the newer term. Check the network between it and the other nodes."));
+        }
+    }
+
+    let Some(leader) = leader else {
+        let max_term = nodes.iter().map(|n| n.current_term).max().unwrap_or(0);
+        findings.push(finding(Severity::Critical,
+            format!("No leader among the {} nodes that answered (highest term {}).", nodes.len(), max_term),
+            "Check that a majority of the configuration is running and can reach each other. A term that keeps growing points to flapping elections."));
+        add_node_findings(&nodes, &mut findings);
+        findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+        return findings;
+    };
+
+    let leader_config = (server_ids(&leader.old_servers), server_ids(&leader.new_servers));
+    let leader_match: BTreeMap<u64, u64> = leader.peers.iter().map(|p| (p.server_id, p.match_index)).collect();
+    for node in nodes.iter().filter(|n| n.server_id != leader.server_id) {
+        if node.current_term > leader.current_term {
+            findings.push(finding(Severity::Warning,
+                format!("Node {} is at term {}, ahead of leader {} at term {}.",
+                    node_name(node), node.current_term, node_name(leader), leader.current_term),
+                "The node keeps campaigning without winning. Check its connectivity to the leader and whether its log is behind."));
+        }
+        if (server_ids(&node.old_servers), server_ids(&node.new_servers)) != leader_config {
+            findings.push(finding(Severity::Warning,
+                format!("Config divergence: node {} has configuration {}, leader {} has {}.",
+                    node_name(node), format_config(node), node_name(leader), format_config(leader)),
+                "A node behind the leader's configuration entry is usually still catching up. If this persists, check the node's log range and health below."));
+        }
+        // Leader 已经把这个节点需要的条目压缩掉了，只能通过 InstallSnapshot 追上
+        if node.log_last_index + 1 < leader.log_start_index {
+            findings.push(finding(Severity::Warning,
+                format!("Snapshot lag: node {} has log up to {}, but leader {} has compacted its log up to {}.",
+                    node_name(node), node.log_last_index, node_name(leader), leader.log_start_index - 1),
+                "The leader sends a snapshot automatically. If this persists, look for snapshot transfer errors in the leader's log and run `client snapshot status` on both nodes."));
+        } else if leader.commit_index.saturating_sub(node.last_applied) > config::DOCTOR_STUCK_FOLLOWER_LAG {
+            let match_index = leader_match.get(&node.server_id).map_or("unknown".to_string(), |m| m.to_string());
+            findings.push(finding(Severity::Warning,
+                format!("Stuck follower: node {} has applied up to {}, {} entries behind the leader's commit index {} (match index on the leader: {}).",
+                    node_name(node), node.last_applied, leader.commit_index - node.last_applied, leader.commit_index, match_index),
+                "If the match index is also behind, check the network to this node (`client latency`). If only applying is behind, check its disk and state machine."));
+        }
+    }
+
+    // 配置中有但没有查询到的节点
+    let queried: Vec<u64> = nodes.iter().map(|n| n.server_id).collect();
+    for server in leader.old_servers.iter().chain(leader.new_servers.iter()) {
+        if !queried.contains(&server.server_id) && reports.iter().all(|r| r.addr != server.server_addr) {
+            findings.push(finding(Severity::Info,
+                format!("Server {} ({}) is in the configuration but was not queried.", server.server_id, server.server_addr),
+                "Pass its address to doctor to include it in the checks."));
+        }
+    }
+
+    // 可用区视图只有 Leader 填写
+    if leader.zones.len() > 1 {
+        for zone in leader.zones.iter().filter(|z| z.holds_quorum) {
+            findings.push(finding(Severity::Warning,
+                format!("Zone quorum: zone {:?} holds {} of the {} voters, a majority by itself.",
+                    zone.zone, zone.voters, leader.zones.iter().map(|z| z.voters).sum::<u32>()),
+                "Losing this zone makes the cluster unavailable. Spread the voters so that no zone holds a majority, e.g. with `client set-config`."));
+        }
+    }
+    for zone in leader.zones.iter().filter(|z| z.reachable < z.voters && !z.quorum_without_zone) {
+        findings.push(finding(Severity::Warning,
+            format!("Zone at risk: the leader reaches {} of {} voters in zone {:?}, and the other zones cannot form a quorum without it.",
+                zone.reachable, zone.voters, zone.zone),
+            "Bring the unreachable voters back before taking anything else in this zone down."));
+    }
+
+    add_node_findings(&nodes, &mut findings);
+    findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+    findings
+}
+
+// 只看单个节点就能发现的问题
+fn add_node_findings(nodes: &[&proto::GetStatusResponse], findings: &mut Vec<Finding>) {
+    for node in nodes {
+        match proto::NodeHealth::try_from(node.health) {
+            Ok(proto::NodeHealth::LogGap) => findings.push(finding(Severity::Critical,
+                format!("Node {} is missing a committed entry after index {} and has stopped applying.", node_name(node), node.last_applied),
+                "Rebuild the node from a snapshot: take one on the leader with `client snapshot trigger`, then restart the node with an empty data directory.")),
+            Ok(proto::NodeHealth::IncompatibleSnapshot) => findings.push(finding(Severity::Critical,
+                format!("Node {} refused to restore a snapshot its state machine cannot read and has stopped applying.", node_name(node)),
+                "Run the same state machine version on every node: upgrade or roll back this node, then restart it.")),
+            Ok(proto::NodeHealth::ConfigMismatch) => findings.push(finding(Severity::Warning,
+                format!("Node {} listens on an address different from the one in the configuration.", node_name(node)),
+                "Restart the node on the configured address, or update the configuration with `client set-config`.")),
+            _ => {}
+        }
+        if let Some(reason) = &node.not_ready_reason {
+            findings.push(finding(Severity::Info,
+                format!("Node {} reports not ready: {}.", node_name(node), reason),
+                "Load balancers using /ready keep reads away from it until it catches up. If this persists, check the stuck follower and snapshot findings."));
+        }
+        let uncompacted = node.last_applied.saturating_sub(node.snapshot_last_included_index);
+        if uncompacted > config::DOCTOR_UNCOMPACTED_ENTRIES {
+            findings.push(finding(Severity::Info,
+                format!("Node {} has {} applied entries not covered by a snapshot.", node_name(node), uncompacted),
+                "Check `client snapshot status` for a deferral reason, or take one with `client snapshot trigger`."));
+        }
+    }
+}
+
+// 命令行输出：先列出各节点的视图，再列出问题
+pub fn render(reports: &[NodeReport], findings: &[Finding]) -> String {
+    let answered = reports.iter().filter(|r| r.status.is_ok()).count();
+    let mut out = format!("Cluster doctor: {}/{} nodes answered\n", answered, reports.len());
+    for report in reports {
+        match &report.status {
+            Ok(s) => {
+                let role = proto::NodeRole::try_from(s.role).map_or("Unknown".to_string(), |r| format!("{:?}", r));
+                let health = proto::NodeHealth::try_from(s.health).map_or("Unknown".to_string(), |h| format!("{:?}", h));
+                out.push_str(&format!("  {} {} term={} commit={} applied={} log=[{}, {}] snapshot={} config={} health={}\n",
+                    node_name(s), role, s.current_term, s.commit_index, s.last_applied,
+                    s.log_start_index, s.log_last_index, s.snapshot_last_included_index, format_config(s), health));
+            }
+            Err(_) => out.push_str(&format!("  {} unreachable\n", report.addr)),
+        }
+    }
+    if findings.is_empty() {
+        out.push_str("No problems found.");
+        return out;
+    }
+    out.push_str("Findings:");
+    for f in findings {
+        out.push_str(&format!("\n  [{:?}] {}\n      -> {}", f.severity, f.problem, f.remediation));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(id: u64) -> proto::ServerInfo {
+        proto::ServerInfo { server_id: id, server_addr: format!("[::1]:900{}", id), zone: None }
+    }
+
+    fn status(id: u64, role: proto::NodeRole, term: u64, applied: u64) -> NodeReport {
+        let status = proto::GetStatusResponse {
+            server_id: id,
+            server_addr: server(id).server_addr,
+            role: role as i32,
+            current_term: term,
+            commit_index: applied,
+            last_applied: applied,
+            log_start_index: 1,
+            log_last_index: applied,
+            new_servers: (1..=3).map(server).collect(),
+            ..Default::default()
+        };
+        NodeReport { addr: status.server_addr.clone(), status: Ok(status) }
+    }
+
+    fn problems(findings: &[Finding]) -> Vec<(Severity, String)> {
+        findings.iter().map(|f| (f.severity, f.problem.split(": ").next().unwrap().to_string())).collect()
+    }
+
+    #[test]
+    fn test_diagnose_cross_node_problems() {
+        let healthy = vec![
+            status(1, proto::NodeRole::Leader, 3, 5000),
+            status(2, proto::NodeRole::Follower, 3, 5000),
+            status(3, proto::NodeRole::Follower, 3, 4990),
+        ];
+        assert!(diagnose(&healthy).is_empty());
+
+        let mut reports = healthy.clone();
+        // 节点 2 在同一任期也认为自己是 Leader，节点 3 卡住且配置落后
+        if let Ok(s) = &mut reports[1].status {
+            s.role = proto::NodeRole::Leader as i32;
+        }
+        if let Ok(s) = &mut reports[2].status {
+            s.last_applied = 100;
+            s.new_servers.pop();
+        }
+        reports.push(NodeReport { addr: "[::1]:9004".to_string(), status: Err("connection refused".to_string()) });
+        assert_eq!(problems(&diagnose(&reports)), vec![
+            (Severity::Critical, "Split leader".to_string()),
+            (Severity::Warning, "Node [::1]:9004 did not answer GetStatus".to_string()),
+            (Severity::Warning, "Config divergence".to_string()),
+            (Severity::Warning, "Stuck follower".to_string()),
+        ]);
+
+        // Leader 已经压缩掉了节点 3 需要的日志
+        let mut reports = healthy.clone();
+        if let Ok(s) = &mut reports[0].status {
+            s.log_start_index = 4800;
+        }
+        if let Ok(s) = &mut reports[2].status {
+            s.log_last_index = 200;
+            s.last_applied = 200;
+        }
+        assert_eq!(problems(&diagnose(&reports)), vec![(Severity::Warning, "Snapshot lag".to_string())]);
+
+        // 两个可用区，其中一个单独构成多数派
+        let mut reports = healthy.clone();
+        if let Ok(s) = &mut reports[0].status {
+            s.zones = vec![
+                proto::ZoneHealth { zone: "a".to_string(), voters: 2, reachable: 2, holds_quorum: true, quorum_without_zone: false },
+                proto::ZoneHealth { zone: "b".to_string(), voters: 1, reachable: 1, holds_quorum: false, quorum_without_zone: true },
+            ];
+        }
+        assert_eq!(problems(&diagnose(&reports)), vec![(Severity::Warning, "Zone quorum".to_string())]);
+    }
+}
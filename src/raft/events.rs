@@ -0,0 +1,55 @@
+use crate::raft::config;
+use tokio::sync::broadcast;
+
+// 节点运行过程中对外发布的事件，嵌入方通过 RaftNode::subscribe_events 订阅
+#[derive(Debug, Clone, PartialEq)]
+pub enum RaftEvent {
+    // 后台校验发现磁盘上的文件损坏
+    CorruptionDetected { filepath: String, reason: String },
+    // Follower 安装了领先于 last_applied 的快照，[from, to] 范围内的条目没有逐条经过 apply，
+    // 有副作用的状态机需要据此自行对账
+    AppliedGapSkipped { from: u64, to: u64 },
+    // Leader 发现某个 Follower 的落后量持续增长，降低其复制优先级；落后量开始下降后恢复
+    PeerSlow { peer_id: u64, lag: u64 },
+    PeerRecovered { peer_id: u64, lag: u64 },
+    // 本节点已被下线流程移出集群并关闭，wiped 表示数据目录已被清空；嵌入方可以据此退出进程
+    Decommissioned { wiped: bool },
+    // 本节点作为 Leader 被配置变更移出集群，C(new) 提交后已交出领导权并完全停止；
+    // successor 是接手领导权的节点，转移失败时为 None（直接退位，由其余节点重新选举）
+    RemovedFromCluster { successor: Option<u64> },
+    // Leader 最后一条日志与最慢投票节点的差距持续超过阈值（见 config::DivergenceAlarm），回到阈值以内后发布 DivergenceCleared
+    DivergenceAlarm { peer_id: u64, gap: u64 },
+    DivergenceCleared { gap: u64 },
+    // Peer 的时钟偏差上界超过 RaftOptions::max_clock_skew，Leader 的读租约失效；回到上限以内后发布 ClockSkewCleared
+    ClockSkewExceeded { peer_id: u64, skew_bound_us: u64 },
+    ClockSkewCleared { skew_bound_us: u64 },
+    // 已提交的条目类型无法识别（通常由更新版本的节点写入），本节点跳过了它而没有应用
+    UnknownEntrySkipped { index: u64, entry_type: i32 },
+}
+
+// 基于 broadcast channel 的事件总线，没有订阅者时事件直接丢弃，订阅者处理过慢时会丢失最旧的事件
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<RaftEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(config::EVENT_CHANNEL_CAPACITY);
+        EventBus { tx }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RaftEvent> {
+        self.tx.subscribe()
+    }
+
+    pub fn publish(&self, event: RaftEvent) {
+        let _ = self.tx.send(event);
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,170 @@
+use crate::raft::{config, consensus, snapshot};
+use super::logging::*;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex as TokioMutex;
+
+// 快照导出：把最新的快照（有新应用的条目时先生成一个）连同元数据写入调用方提供的 AsyncWrite，
+// 供外部备份工具使用，不经过 InstallSnapshot 的复制路径。导出的格式：
+//   "RFTX" | 版本（1 字节）| 元数据长度（u64 LE）| 元数据 JSON | 数据长度（u64 LE）| 快照数据 | 数据的校验和（u64 LE）
+// 数据是快照文件在磁盘上的原样字节（可能经过压缩），导入时用 read_export 写回文件，再作为 RaftOptions::initial_snapshot 使用
+
+const MAGIC: &[u8; 4] = b"RFTX";
+const VERSION: u8 = 1;
+
+// 一次导出的结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportedSnapshot {
+    pub last_included_index: u64,
+    pub last_included_term: u64,
+    pub bytes: u64, // 写入 writer 的总字节数
+}
+
+pub async fn export_snapshot<W: AsyncWrite + Unpin>(
+    consensus: &Arc<TokioMutex<consensus::Consensus>>,
+    writer: &mut W,
+) -> Result<ExportedSnapshot, String> {
+    consensus::Consensus::snapshot_for_export(consensus).await?;
+    // 持有锁时确定最新的快照并打开文件，之后即使快照被更新的快照替换删除，打开的文件仍然可以读完；
+    // 读取都在释放锁之后进行
+    let (index, term, expected_checksum, metadata_file, data_file) = {
+        let guard = consensus.lock().await;
+        let (index, term) = (guard.snapshot.last_included_index(), guard.snapshot.last_included_term());
+        let metadata_filepath = guard.snapshot.gen_snapshot_metadata_filepath(index, term);
+        let data_filepath = guard.snapshot.gen_snapshot_filepath(index, term);
+        let metadata_file = std::fs::File::open(&metadata_filepath)
+            .map_err(|e| format!("failed to open snapshot metadata {}: {}", metadata_filepath, e))?;
+        let data_file = std::fs::File::open(&data_filepath)
+            .map_err(|e| format!("failed to open snapshot file {}: {}", data_filepath, e))?;
+        (index, term, guard.snapshot.meta().checksum, metadata_file, data_file)
+    };
+    let mut metadata_json = Vec::new();
+    tokio::fs::File::from_std(metadata_file).read_to_end(&mut metadata_json).await
+        .map_err(|e| format!("failed to read snapshot metadata: {}", e))?;
+    let data_len = data_file.metadata().map_err(|e| format!("failed to stat snapshot file: {}", e))?.len();
+    info!("Exporting snapshot ({}, {}): {} bytes of metadata, {} bytes of data", index, term, metadata_json.len(), data_len);
+
+    let write_err = |e: std::io::Error| format!("failed to write snapshot export: {}", e);
+    writer.write_all(MAGIC).await.map_err(write_err)?;
+    writer.write_u8(VERSION).await.map_err(write_err)?;
+    writer.write_u64_le(metadata_json.len() as u64).await.map_err(write_err)?;
+    writer.write_all(&metadata_json).await.map_err(write_err)?;
+    writer.write_u64_le(data_len).await.map_err(write_err)?;
+
+    let mut data_file = tokio::fs::File::from_std(data_file);
+    let mut checksum = snapshot::Checksum::default();
+    let mut buf = vec![0; config::FETCH_SNAPSHOT_CHUNK_SIZE];
+    let mut remaining = data_len;
+    while remaining > 0 {
+        let n = data_file.read(&mut buf).await.map_err(|e| format!("failed to read snapshot file: {}", e))?;
+        if n == 0 {
+            return Err(format!("snapshot file ended {} bytes early", remaining));
+        }
+        let n = n.min(remaining as usize);
+        checksum.update(&buf[..n]);
+        writer.write_all(&buf[..n]).await.map_err(write_err)?;
+        remaining -= n as u64;
+    }
+    // 不写校验和，导入方会把不完整的导出当作错误
+    if expected_checksum != 0 && checksum.value() != expected_checksum {
+        return Err(format!("snapshot file ({}, {}) does not match the checksum in its metadata", index, term));
+    }
+    writer.write_u64_le(checksum.value()).await.map_err(write_err)?;
+    writer.flush().await.map_err(write_err)?;
+
+    Ok(ExportedSnapshot {
+        last_included_index: index,
+        last_included_term: term,
+        bytes: (MAGIC.len() + 1 + 8 + metadata_json.len() + 8 + 8) as u64 + data_len,
+    })
+}
+
+// 读取 export_snapshot 的输出，快照数据写入 data_filepath，返回其中的元数据。
+// 校验和不一致或者导出不完整时返回错误，此时 data_filepath 中的内容不可用
+pub async fn read_export<R: AsyncRead + Unpin>(reader: &mut R, data_filepath: &str) -> Result<snapshot::SnapshotMeta, String> {
+    let read_err = |e: std::io::Error| format!("failed to read snapshot export: {}", e);
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).await.map_err(read_err)?;
+    if &magic != MAGIC {
+        return Err("not a snapshot export".to_string());
+    }
+    let version = reader.read_u8().await.map_err(read_err)?;
+    if version != VERSION {
+        return Err(format!("unsupported snapshot export version {}", version));
+    }
+    let metadata_len = reader.read_u64_le().await.map_err(read_err)?;
+    let mut metadata_json = Vec::new();
+    reader.take(metadata_len).read_to_end(&mut metadata_json).await.map_err(read_err)?;
+    let meta = snapshot::SnapshotMeta::parse(&String::from_utf8_lossy(&metadata_json))?;
+
+    let data_len = reader.read_u64_le().await.map_err(read_err)?;
+    let mut data_file = tokio::fs::File::create(data_filepath).await
+        .map_err(|e| format!("failed to create {}: {}", data_filepath, e))?;
+    let mut checksum = snapshot::Checksum::default();
+    let mut buf = vec![0; config::FETCH_SNAPSHOT_CHUNK_SIZE];
+    let mut remaining = data_len;
+    while remaining > 0 {
+        let want = buf.len().min(remaining as usize);
+        let n = reader.read(&mut buf[..want]).await.map_err(read_err)?;
+        if n == 0 {
+            return Err(format!("snapshot export ended {} bytes early", remaining));
+        }
+        checksum.update(&buf[..n]);
+        data_file.write_all(&buf[..n]).await.map_err(|e| format!("failed to write {}: {}", data_filepath, e))?;
+        remaining -= n as u64;
+    }
+    data_file.sync_all().await.map_err(|e| format!("failed to write {}: {}", data_filepath, e))?;
+    let trailer = reader.read_u64_le().await.map_err(read_err)?;
+    if trailer != checksum.value() || (meta.checksum != 0 && meta.checksum != trailer) {
+        return Err(format!("snapshot export ({}, {}) is corrupted: checksum mismatch", meta.last_included_index, meta.last_included_term));
+    }
+    Ok(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft::{proto, state_machine};
+
+    #[tokio::test]
+    async fn test_export_snapshot_round_trip() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = consensus::tests::test_consensus(&storage, Box::new(state_machine::SimpleStateMachine::new())).await;
+        let propose = |data: &str| proto::ProposeRequest { data: data.as_bytes().to_vec(), wait_for_commit: true };
+
+        // 还没有应用任何条目时无法导出
+        let mut out = Vec::new();
+        assert!(export_snapshot(&consensus, &mut out).await.is_err());
+
+        consensus.lock().await.handle_election_timeout().await;
+        for data in ["a", "b"] {
+            assert!(consensus::Consensus::propose_and_wait(&consensus, &propose(data)).await.success);
+        }
+        let exported = export_snapshot(&consensus, &mut out).await.unwrap();
+        assert_eq!(exported.bytes, out.len() as u64);
+        let last_applied = consensus.lock().await.last_applied;
+        assert_eq!(exported.last_included_index, last_applied);
+
+        // 没有新条目时复用同一个快照
+        let mut again = Vec::new();
+        assert_eq!(export_snapshot(&consensus, &mut again).await.unwrap(), exported);
+        assert_eq!(again, out);
+
+        let dir = tempfile::tempdir().unwrap();
+        let data_filepath = dir.path().join("restored.snapshot").to_str().unwrap().to_string();
+        let meta = read_export(&mut &out[..], &data_filepath).await.unwrap();
+        assert_eq!((meta.last_included_index, meta.last_included_term), (exported.last_included_index, exported.last_included_term));
+        let mut restored = state_machine::SimpleStateMachine::new();
+        state_machine::StateMachine::restore_snapshot(&mut restored, &data_filepath);
+        assert_eq!(restored.get_entries(), vec!["a", "b"]);
+
+        // 截断或者被篡改的导出都会被拒绝
+        assert!(read_export(&mut &out[..out.len() - 4], &data_filepath).await.is_err());
+        let mut corrupted = out.clone();
+        let data_byte = corrupted.len() - 9;
+        corrupted[data_byte] ^= 0xff;
+        assert!(read_export(&mut &corrupted[..], &data_filepath).await.is_err());
+
+        consensus.lock().await.shutdown().await;
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+// Propose 的公平调度：所有提议最终都在共识模块的锁上串行执行，锁按到达顺序分配，
+// 一个并发发送大量提议的客户端（例如批量导入）会排满锁的等待队列，交互式客户端的每个提议都要排在它们后面。
+// 这里在拿锁之前按客户端连接排队，每轮依次给每个有等待提议的连接一次机会（轮转），
+// 同一时刻只有一个提议在追加；提议拿到的 Turn 在追加完成后释放，等待提交的过程不占用 Turn。
+// 客户端以连接的对端地址区分，拿不到地址的请求（Unix domain socket、进程内调用）共用一个队列
+
+pub type ClientKey = Option<SocketAddr>;
+
+#[derive(Debug, Default)]
+struct SchedulerState {
+    busy: bool,                                                    // 是否有提议持有 Turn
+    queues: HashMap<ClientKey, VecDeque<oneshot::Sender<Turn>>>, // 每个客户端等待中的提议，按到达顺序
+    rotation: VecDeque<ClientKey>,                                 // 有等待提议的客户端，队首下一个获得 Turn
+}
+
+#[derive(Debug, Default)]
+pub struct ProposalScheduler {
+    state: Mutex<SchedulerState>,
+}
+
+// 追加一个提议的许可，drop 时交给下一个客户端
+#[derive(Debug)]
+pub struct Turn {
+    scheduler: Option<Arc<ProposalScheduler>>,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.release();
+        }
+    }
+}
+
+impl ProposalScheduler {
+    // 等待 client 的下一次机会；调用方放弃等待（drop 这个 future）时，排队的位置随之作废
+    pub async fn acquire(self: &Arc<Self>, client: ClientKey) -> Turn {
+        let rx = {
+            let mut guard = self.state.lock().unwrap();
+            let state = &mut *guard;
+            if !state.busy {
+                state.busy = true;
+                return Turn { scheduler: Some(Arc::clone(self)) };
+            }
+            let (tx, rx) = oneshot::channel();
+            let queue = state.queues.entry(client).or_default();
+            if queue.is_empty() {
+                state.rotation.push_back(client);
+            }
+            queue.push_back(tx);
+            rx
+        };
+        // 发送端只会在交出 Turn 时使用，调度器存活期间不会被直接丢弃
+        rx.await.expect("proposal scheduler dropped a waiting proposal")
+    }
+
+    // 等待中的提议数量
+    pub fn waiting(&self) -> usize {
+        self.state.lock().unwrap().queues.values().map(VecDeque::len).sum()
+    }
+
+    // 把 Turn 交给轮转队首的客户端，它还有等待的提议时排回队尾；已经放弃等待的提议跳过
+    fn release(self: &Arc<Self>) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        while let Some(client) = state.rotation.pop_front() {
+            let queue = state.queues.get_mut(&client).expect("client in rotation has a queue");
+            let tx = queue.pop_front().expect("client in rotation has a waiting proposal");
+            if queue.is_empty() {
+                state.queues.remove(&client);
+            } else {
+                state.rotation.push_back(client);
+            }
+            match tx.send(Turn { scheduler: Some(Arc::clone(self)) }) {
+                Ok(()) => return,
+                // 交出的 Turn 退回来时不能再触发 release，这里已经持有锁
+                Err(mut turn) => turn.scheduler = None,
+            }
+        }
+        state.busy = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_turns_rotate_across_clients() {
+        let scheduler = Arc::new(ProposalScheduler::default());
+        let (bulk, interactive): (ClientKey, ClientKey) = (Some("10.0.0.1:5000".parse().unwrap()), Some("10.0.0.2:5000".parse().unwrap()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // 批量客户端先排了 4 个提议，交互式客户端随后排 2 个，Turn 应该交替分给两者
+        let first = scheduler.acquire(bulk).await;
+        let mut tasks = Vec::new();
+        for (client, name) in [(bulk, "bulk"); 4].into_iter().chain([(interactive, "interactive"); 2]) {
+            let (task_scheduler, order) = (Arc::clone(&scheduler), Arc::clone(&order));
+            tasks.push(tokio::spawn(async move {
+                let _turn = task_scheduler.acquire(client).await;
+                order.lock().unwrap().push(name);
+            }));
+            while scheduler.waiting() < tasks.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        // 放弃等待的提议不占用机会
+        let abandoned = tokio::time::timeout(Duration::from_millis(10), scheduler.acquire(interactive)).await;
+        assert!(abandoned.is_err());
+
+        drop(first);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec!["bulk", "interactive", "bulk", "interactive", "bulk", "bulk"]);
+        assert_eq!(scheduler.waiting(), 0);
+        drop(scheduler.acquire(None).await);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::raft::{config, consensus, crash, proto, rpc, sanity};
+use super::logging::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex, Weak};
+use std::time::Duration;
+use tokio::sync::Mutex as TokioMutex;
+
+// 同一进程内多个共识组的注册表（multi-raft 的基础）
+// 发送侧：每个心跳周期收集本进程内作为 Leader 的所有组的心跳，按目标节点合并成一个 RPC；
+// 接收侧：把收到的合并心跳拆开，分发给对应组处理
+pub struct GroupRegistry {
+    groups: StdMutex<HashMap<u64, Weak<TokioMutex<consensus::Consensus>>>>,
+    heartbeat_task: StdMutex<Option<tokio::task::JoinHandle<()>>>,
+    rpc_client: rpc::Client,
+}
+
+impl std::fmt::Debug for GroupRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GroupRegistry")
+            .field("groups", &self.group_ids())
+            .finish()
+    }
+}
+
+// 一次合并心跳中每个组的心跳及其来源，用于把响应交回对应的组
+struct PendingHeartbeat {
+    group_id: u64,
+    peer_id: u64,
+    peer_addr: String,
+    summary: rpc::AppendEntriesSummary,
+    heartbeat: proto::GroupHeartbeat,
+}
+
+impl GroupRegistry {
+    pub fn new() -> Arc<Self> {
+        Self::with_transport(config::TransportOptions::default())
+    }
+
+    // 合并心跳使用的连接参数，应和注册到本注册表的节点的 RaftOptions.transport 一致
+    pub fn with_transport(transport: config::TransportOptions) -> Arc<Self> {
+        Arc::new(GroupRegistry {
+            groups: StdMutex::new(HashMap::new()),
+            heartbeat_task: StdMutex::new(None),
+            rpc_client: rpc::Client::with_transport(transport),
+        })
+    }
+
+    // 注册一个组，第一个组注册时启动合并心跳任务
+    pub fn register(self: &Arc<Self>, group_id: u64, consensus: &Arc<TokioMutex<consensus::Consensus>>) {
+        if self.groups.lock().unwrap().insert(group_id, Arc::downgrade(consensus)).is_some() {
+            warn!("Group {} registered twice, replacing the previous instance", group_id);
+        }
+        let mut heartbeat_task = self.heartbeat_task.lock().unwrap();
+        if heartbeat_task.is_none() {
+            *heartbeat_task = Some(self.spawn_heartbeat_task(config::HEARTBEAT_INTERVAL));
+        }
+    }
+
+    pub fn deregister(&self, group_id: u64) {
+        self.groups.lock().unwrap().remove(&group_id);
+    }
+
+    pub fn group(&self, group_id: u64) -> Option<Arc<TokioMutex<consensus::Consensus>>> {
+        let mut groups = self.groups.lock().unwrap();
+        match groups.get(&group_id).map(Weak::upgrade) {
+            Some(Some(consensus)) => Some(consensus),
+            Some(None) => {
+                // 组已经被释放
+                groups.remove(&group_id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn group_ids(&self) -> Vec<u64> {
+        self.groups.lock().unwrap().keys().cloned().collect()
+    }
+
+    // 启动合并心跳任务，registry 被释放后任务自动退出
+    fn spawn_heartbeat_task(self: &Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let registry = Arc::downgrade(self);
+        tokio::spawn(crash::scope(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match registry.upgrade() {
+                    Some(registry) => registry.send_heartbeats().await,
+                    None => break,
+                }
+            }
+        }))
+    }
+
+    // 收集所有组的心跳，按目标节点合并发送，并把响应交回各组。
+    // 每个组的 Peer 地址是对方进程中该组的服务地址，同一进程的各组地址不同，但共享一个注册表，
+    // 发往其中任何一个地址的合并心跳都会分发给对方进程中的所有组。因此按 Peer 的节点ID合并，
+    // 依次发往该节点的各个地址，直到所有心跳都有响应：某个地址不可达，或者对方没有处理某些组
+    // （该进程中没有这个组，或者节点ID在不同组中指向不同进程）时，剩下的心跳发往下一个地址
+    pub async fn send_heartbeats(&self) {
+        let mut by_node: HashMap<u64, Vec<PendingHeartbeat>> = HashMap::new();
+        for group_id in self.group_ids() {
+            let Some(consensus) = self.group(group_id) else { continue };
+            let heartbeats = consensus.lock().await.collect_heartbeats().await;
+            for (peer_id, peer_addr, request) in heartbeats {
+                by_node.entry(peer_id).or_default().push(PendingHeartbeat {
+                    group_id,
+                    peer_id,
+                    peer_addr,
+                    summary: rpc::AppendEntriesSummary::of(&request),
+                    heartbeat: proto::GroupHeartbeat { group_id, request: Some(request), server_id: peer_id },
+                });
+            }
+        }
+
+        for (peer_id, mut pending) in by_node {
+            let mut addrs: Vec<String> = Vec::new();
+            for p in pending.iter() {
+                if !addrs.contains(&p.peer_addr) {
+                    addrs.push(p.peer_addr.clone());
+                }
+            }
+            for addr in addrs {
+                if pending.is_empty() {
+                    break;
+                }
+                pending = self.deliver_heartbeats(&addr, pending).await;
+            }
+            if !pending.is_empty() {
+                debug!("{} heartbeats to server {} got no response", pending.len(), peer_id);
+            }
+        }
+    }
+
+    // 把一批心跳合并发往 addr 并把响应交回各组，返回没有得到响应的心跳；请求失败时全部返回
+    async fn deliver_heartbeats(&self, addr: &str, pending: Vec<PendingHeartbeat>) -> Vec<PendingHeartbeat> {
+        let req = proto::CoalescedHeartbeatRequest { heartbeats: pending.iter().map(|p| p.heartbeat.clone()).collect() };
+        debug!("Sending coalesced heartbeat for {} groups to {}", pending.len(), addr);
+        let resp = match self.rpc_client.coalesced_heartbeat(req, addr.to_string()).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                error!("Coalesced heartbeat to {} failed: {}", addr, e);
+                return pending;
+            }
+        };
+
+        let mut unanswered = pending;
+        for group_resp in resp.responses {
+            let Some(response) = group_resp.response else { continue };
+            let Some(pos) = unanswered.iter().position(|p| p.group_id == group_resp.group_id) else {
+                warn!("Coalesced heartbeat response from {} contains unexpected group {}", addr, group_resp.group_id);
+                continue;
+            };
+            let p = unanswered.swap_remove(pos);
+            if let Some(consensus) = self.group(p.group_id) {
+                consensus.lock().await.handle_heartbeat_response(p.peer_id, &p.summary, &response).await;
+            }
+        }
+        unanswered
+    }
+
+    // 把收到的合并心跳分发给本进程内对应的组，不存在的组直接跳过
+    pub async fn handle_coalesced_heartbeat(
+        &self,
+        request: &proto::CoalescedHeartbeatRequest,
+    ) -> proto::CoalescedHeartbeatResponse {
+        let mut responses = Vec::with_capacity(request.heartbeats.len());
+        for heartbeat in request.heartbeats.iter() {
+            let Some(ae_request) = &heartbeat.request else { continue };
+            match self.group(heartbeat.group_id) {
+                Some(consensus) => {
+                    let mut guard = consensus.lock().await;
+                    // 节点ID在不同组中可能指向不同的进程，本进程中的该组不是心跳的目标时不处理
+                    if heartbeat.server_id != 0 && heartbeat.server_id != guard.server_id {
+                        debug!("Heartbeat for group {} targets server {}, not server {}", heartbeat.group_id, heartbeat.server_id, guard.server_id);
+                        continue;
+                    }
+                    let check = |term, last| sanity::check_append_entries(ae_request, term, last);
+                    if guard.check_request("heartbeat", ae_request.leader_id, check).await.is_err() {
+                        continue;
+                    }
+                    let response = guard.handle_append_entries_rpc(ae_request).await;
+                    responses.push(proto::GroupHeartbeatResponse {
+                        group_id: heartbeat.group_id,
+                        response: Some(response),
+                    });
+                }
+                None => warn!("Received heartbeat for unknown group {}", heartbeat.group_id),
+            }
+        }
+        proto::CoalescedHeartbeatResponse { responses }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_coalesced_heartbeat_skips_unknown_groups() {
+        let registry = GroupRegistry::new();
+        let req = proto::CoalescedHeartbeatRequest {
+            heartbeats: vec![proto::GroupHeartbeat {
+                group_id: 7,
+                request: Some(proto::AppendEntriesRequest::default()),
+                server_id: 0,
+            }],
+        };
+        let resp = registry.handle_coalesced_heartbeat(&req).await;
+        assert!(resp.responses.is_empty());
+        assert!(registry.group(7).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_coalesce_per_node_across_group_addresses() {
+        use crate::raft::{peer, state_machine, storage};
+        let server = |server_id: u64, addr: &str| proto::ServerInfo { server_id, server_addr: addr.to_string(), zone: None };
+        let storages: Vec<_> = (0..4).map(|_| storage::StoragePaths::temp().unwrap()).collect();
+
+        // 对方进程：节点 2 上的两个组共享一个注册表，只有组 1 的服务在监听
+        let remote = GroupRegistry::new();
+        let mut followers = Vec::new();
+        for (group_id, storage) in [(1, &storages[0]), (2, &storages[1])] {
+            let consensus = consensus::tests::test_consensus_with(storage, 2, vec![server(1, "[::1]:1")],
+                Box::new(state_machine::SimpleStateMachine::new()), Arc::new(crate::raft::clock::SystemClock)).await;
+            {
+                // 测试期间不发起选举，保持在 Leader 的任期
+                let mut guard = consensus.lock().await;
+                guard.group_id = group_id;
+                guard.non_candidate = true;
+            }
+            remote.register(group_id, &consensus);
+            followers.push(consensus);
+        }
+        let bound = rpc::bind_server("[::1]:0", None, false, Arc::clone(&followers[0]), Some(Arc::clone(&remote)),
+            rpc::ServerHooks::new(), &config::TransportOptions::default()).await.unwrap();
+        let remote_addr = bound.addr().to_string();
+        let stop = followers[0].lock().await.stop_signal();
+        let serving = tokio::spawn(bound.serve(stop));
+
+        // 本进程：节点 1 是两个组的 Leader，组 2 中节点 2 的地址不可达，心跳仍然随组 1 的心跳送达
+        let registry = GroupRegistry::new();
+        let mut leaders = Vec::new();
+        for (group_id, storage, peer_addr) in [(1, &storages[2], remote_addr.as_str()), (2, &storages[3], "[::1]:1")] {
+            let consensus = consensus::tests::test_consensus(storage, Box::new(state_machine::SimpleStateMachine::new())).await;
+            {
+                let mut guard = consensus.lock().await;
+                guard.group_id = group_id;
+                guard.handle_election_timeout().await;
+                let last_log_index = guard.log.last_index(guard.snapshot.last_included_index());
+                guard.peer_manager.add(vec![peer::Peer::new(2, peer_addr.to_string())], last_log_index);
+            }
+            registry.groups.lock().unwrap().insert(group_id, Arc::downgrade(&consensus));
+            leaders.push(consensus);
+        }
+        registry.send_heartbeats().await;
+
+        for (follower, leader) in followers.iter().zip(&leaders) {
+            assert_eq!(follower.lock().await.leader_id, 1);
+            assert!(leader.lock().await.peer_manager.peer(2).unwrap().last_contact.is_some());
+        }
+        serving.abort();
+    }
+}
@@ -0,0 +1,117 @@
+use crate::raft::consensus::State;
+
+// 状态转换后的内部不变量检查。只在 debug 构建或开启 invariants feature 时生效，
+// release 构建中 ENABLED 为 false，检查代码会被整体优化掉。
+// 不变量被破坏说明内部状态已经损坏，直接 panic，让测试在出错的地方立即失败，而不是在很久之后表现为数据不一致
+
+pub const ENABLED: bool = cfg!(any(debug_assertions, feature = "invariants"));
+
+// 参与检查的状态，由 Consensus 在检查点收集
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub state: State,
+    pub server_id: u64,
+    pub leader_id: u64,
+    pub current_term: u64,
+    pub commit_index: u64,
+    pub last_applied: u64,
+    pub log_start_index: u64,
+    pub last_log_index: u64,
+    pub last_log_term: u64,
+    pub last_included_index: u64,
+    pub peers: Vec<PeerProgress>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PeerProgress {
+    pub id: u64,
+    pub next_index: u64,
+    pub match_index: u64,
+}
+
+// 返回所有被破坏的不变量，为空表示状态正常
+pub fn violations(c: &Checkpoint) -> Vec<String> {
+    let mut violations = Vec::new();
+    let mut expect = |ok: bool, message: String| {
+        if !ok {
+            violations.push(message);
+        }
+    };
+    expect(c.commit_index <= c.last_log_index,
+        format!("commit_index {} is beyond the last log index {}", c.commit_index, c.last_log_index));
+    expect(c.last_applied <= c.commit_index,
+        format!("last_applied {} is beyond commit_index {}", c.last_applied, c.commit_index));
+    expect(c.last_included_index <= c.last_log_index,
+        format!("snapshot index {} is beyond the last log index {}", c.last_included_index, c.last_log_index));
+    expect(c.log_start_index == c.last_included_index + 1,
+        format!("log starts at {} but the snapshot ends at {}", c.log_start_index, c.last_included_index));
+    expect(c.last_log_term <= c.current_term,
+        format!("last log term {} is newer than the current term {}", c.last_log_term, c.current_term));
+
+    if c.state == State::Leader {
+        expect(c.leader_id == c.server_id,
+            format!("leader {} believes server {} is the leader", c.server_id, c.leader_id));
+        for peer in &c.peers {
+            expect(peer.next_index >= 1, format!("peer {} has next_index 0", peer.id));
+            expect(peer.match_index < peer.next_index,
+                format!("peer {} has match_index {} not below next_index {}", peer.id, peer.match_index, peer.next_index));
+            expect(peer.match_index <= c.last_log_index,
+                format!("peer {} has match_index {} beyond the last log index {}", peer.id, peer.match_index, c.last_log_index));
+        }
+    }
+    violations
+}
+
+// 有不变量被破坏时 panic，context 说明刚刚完成的状态转换
+pub fn enforce(context: &str, checkpoint: &Checkpoint) {
+    let violations = violations(checkpoint);
+    assert!(violations.is_empty(), "invariant violated after {}: {} ({:?})", context, violations.join("; "), checkpoint);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy() -> Checkpoint {
+        Checkpoint {
+            state: State::Leader,
+            server_id: 1,
+            leader_id: 1,
+            current_term: 3,
+            commit_index: 8,
+            last_applied: 7,
+            log_start_index: 5,
+            last_log_index: 10,
+            last_log_term: 3,
+            last_included_index: 4,
+            peers: vec![PeerProgress { id: 2, next_index: 11, match_index: 10 }],
+        }
+    }
+
+    #[test]
+    fn test_violations_are_reported() {
+        assert!(violations(&healthy()).is_empty());
+        enforce("test", &healthy());
+
+        let broken = Checkpoint {
+            commit_index: 11,
+            last_applied: 12,
+            last_log_term: 4,
+            leader_id: 2,
+            peers: vec![PeerProgress { id: 2, next_index: 0, match_index: 0 }],
+            ..healthy()
+        };
+        let found = violations(&broken);
+        assert_eq!(found.len(), 6, "{:?}", found);
+
+        // Follower 不检查 Peer 的复制进度
+        let follower = Checkpoint { state: State::Follower, leader_id: 2, peers: broken.peers.clone(), ..healthy() };
+        assert!(violations(&follower).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "invariant violated after apply")]
+    fn test_enforce_panics() {
+        enforce("apply", &Checkpoint { last_applied: 9, ..healthy() });
+    }
+}
@@ -6,42 +6,401 @@ use tracing_subscriber::fmt::writer::MakeWriterExt;
 use super::*;
 use tokio::sync::Mutex as TokioMutex;
 
+// 节点启动时的状态报告，start 返回前已经完成恢复并绑定了监听地址
+#[derive(Debug, Clone)]
+pub struct StartupReport {
+    pub server_id: u64,
+    pub bound_addr: String,                   // 共识服务实际监听的地址
+    pub management_addr: Option<String>,      // 管理服务单独监听时的实际地址
+    pub status_addr: Option<String>,          // HTTP 状态页的实际监听地址
+    pub snapshot_last_included_index: u64,    // 恢复时加载的快照，0 表示没有快照
+    pub snapshot_last_included_term: u64,
+    pub log_start_index: u64,
+    pub log_last_index: u64,
+    pub last_applied: u64,
+    pub configuration: config::Config,
+}
+
+// RPC 服务的运行状态
+#[derive(Debug, Clone, PartialEq)]
+pub enum Readiness {
+    Starting,
+    Ready,
+    Failed(String),
+}
+
+// 进程内的节点句柄，提供与 ManagementRpc 等价的管理接口，
+// 关闭管理服务时嵌入方通过它实现自己的控制面
+#[derive(Clone)]
+pub struct RaftNode {
+    consensus: Arc<TokioMutex<consensus::Consensus>>,
+    startup_report: Arc<StartupReport>,
+    readiness: tokio::sync::watch::Receiver<Readiness>,
+    storage: storage::StoragePaths,
+}
+
+impl RaftNode {
+    pub fn consensus(&self) -> Arc<TokioMutex<consensus::Consensus>> {
+        Arc::clone(&self.consensus)
+    }
+
+    pub fn startup_report(&self) -> &StartupReport {
+        &self.startup_report
+    }
+
+    // 节点使用的数据目录；临时目录会一直保留到最后一个句柄被 drop
+    pub fn storage(&self) -> &storage::StoragePaths {
+        &self.storage
+    }
+
+    // 等待 RPC 服务开始处理请求，服务启动失败时返回错误
+    pub async fn ready(&self) -> Result<(), String> {
+        let mut readiness = self.readiness.clone();
+        let state = readiness
+            .wait_for(|state| *state != Readiness::Starting)
+            .await
+            .map_err(|_| "RPC server task exited before becoming ready".to_string())?
+            .clone();
+        match state {
+            Readiness::Failed(e) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn get_leader(&self) -> proto::GetLeaderResponse {
+        self.consensus.lock().await.handle_get_leader_rpc(&proto::GetLeaderRequest {})
+    }
+
+    // consistent 为 true 时只有确认了领导权的 Leader 才返回成功
+    pub async fn get_configuration(&self, consistent: bool) -> proto::GetConfigurationResponse {
+        self.consensus.lock().await.handle_get_configuration_rpc(&proto::GetConfigurationRequest { consistent }).await
+    }
+
+    pub async fn get_configuration_history(&self) -> proto::GetConfigurationHistoryResponse {
+        self.consensus.lock().await.handle_get_configuration_history_rpc(&proto::GetConfigurationHistoryRequest {})
+    }
+
+    // wait_for_completion 为 true 时等到 C(new) 提交（或超时）后返回；沿用当前配置记录的成员变更算法
+    pub async fn set_configuration(&self, new_servers: Vec<proto::ServerInfo>, wait_for_completion: bool) -> proto::SetConfigurationResponse {
+        self.set_configuration_with_algorithm(new_servers, wait_for_completion, None).await
+    }
+
+    // algorithm 不为空时用它完成这次变更，并记录为集群之后的默认算法
+    pub async fn set_configuration_with_algorithm(
+        &self,
+        new_servers: Vec<proto::ServerInfo>,
+        wait_for_completion: bool,
+        algorithm: Option<config::MembershipAlgorithm>,
+    ) -> proto::SetConfigurationResponse {
+        let algorithm = algorithm.map(|a| proto::MembershipAlgorithm::from(a) as i32);
+        let request = proto::SetConfigurationRequest { new_servers, wait_for_completion, algorithm };
+        let mut response = {
+            let mut guard = self.consensus.lock().await;
+            let response = guard.handle_set_configuration_rpc(&request).await;
+            // 在等待变更完成之前记录，调用被取消时记录也不会丢失
+            guard.audit_command("local", proto::ManagementCommand::SetConfiguration, &request, response.success, response.error.clone());
+            response
+        };
+        if response.success && wait_for_completion {
+            let state = consensus::Consensus::wait_for_config_change(&self.consensus, response.config_index, config::CONFIG_CHANGE_WAIT_TIMEOUT).await;
+            response.state = state as i32;
+        }
+        response
+    }
+
+    pub async fn config_change_status(&self, config_index: u64) -> proto::GetConfigChangeStatusResponse {
+        self.consensus.lock().await.handle_get_config_change_status_rpc(&proto::GetConfigChangeStatusRequest { config_index })
+    }
+
+    // 根据节点间的延迟给出建议的 Leader，transfer 为 true 时把领导权转移过去
+    pub async fn suggest_leader(&self, transfer: bool) -> proto::SuggestLeaderResponse {
+        let response = placement::suggest_leader(&self.consensus, transfer).await;
+        if transfer {
+            let request = proto::SuggestLeaderRequest { transfer };
+            self.consensus.lock().await.audit_command("local", proto::ManagementCommand::TransferLeadership, &request, response.transferred, response.error.clone());
+        }
+        response
+    }
+
+    // 下线节点：移出配置后通知它关闭，wipe_data 为 true 时同时清空它的数据目录
+    pub async fn decommission(&self, server_id: u64, wipe_data: bool) -> proto::DecommissionResponse {
+        let request = proto::DecommissionRequest { server_id, wipe_data };
+        let response = decommission::decommission(&self.consensus, &request).await;
+        self.consensus.lock().await.audit_command("local", proto::ManagementCommand::Decommission, &request, response.success, response.error.clone());
+        response
+    }
+
+    pub async fn propose(&self, data: Vec<u8>) -> proto::ProposeResponse {
+        let request = proto::ProposeRequest { data, wait_for_commit: false };
+        consensus::Consensus::propose_and_wait(&self.consensus, &request).await
+    }
+
+    // 等条目应用到状态机后返回，响应中的 result 是状态机 apply_with_result 的返回值
+    pub async fn propose_and_wait(&self, data: Vec<u8>) -> proto::ProposeResponse {
+        let request = proto::ProposeRequest { data, wait_for_commit: true };
+        consensus::Consensus::propose_and_wait(&self.consensus, &request).await
+    }
+
+    pub async fn read(&self, query: Vec<u8>, allow_degraded: bool) -> proto::ReadResponse {
+        let request = proto::ReadRequest { query, allow_degraded, read_token: None };
+        self.consensus.lock().await.handle_read_rpc(&request)
+    }
+
+    // 会话一致性读：等待本地状态机应用到 read_token（Propose 成功时返回）之后再读取
+    pub async fn read_with_token(&self, query: Vec<u8>, read_token: u64) -> proto::ReadResponse {
+        consensus::Consensus::wait_for_applied(&self.consensus, read_token, config::READ_TOKEN_WAIT_TIMEOUT).await;
+        let request = proto::ReadRequest { query, allow_degraded: false, read_token: Some(read_token) };
+        self.consensus.lock().await.handle_read_rpc(&request)
+    }
+
+    // 调整 Follower 应用条目的限速，立即生效，None 表示不限制
+    pub async fn set_apply_rate_limit(&self, limit: Option<config::ApplyRateLimit>) {
+        self.consensus.lock().await.set_apply_rate_limit(limit).await;
+    }
+
+    pub async fn trigger_snapshot(&self) -> proto::TriggerSnapshotResponse {
+        let request = proto::TriggerSnapshotRequest {};
+        let response = consensus::Consensus::trigger_snapshot(&self.consensus, &request).await;
+        self.consensus.lock().await.audit_command("local", proto::ManagementCommand::TriggerSnapshot, &request, response.success, response.error.clone());
+        response
+    }
+
+    // 按序号分页读取本节点的管理操作审计记录，max_records 为 0 时使用默认的每页数量
+    pub async fn command_audit(&self, start_seq: u64, max_records: u32) -> proto::GetCommandAuditResponse {
+        consensus::Consensus::get_command_audit(&self.consensus, &proto::GetCommandAuditRequest { start_seq, max_records }).await
+    }
+
+    // 把最新的快照和元数据写入 writer，供外部备份；有新应用的条目时先生成快照，格式见 export 模块
+    pub async fn export_snapshot<W: tokio::io::AsyncWrite + Unpin>(&self, writer: &mut W) -> Result<export::ExportedSnapshot, String> {
+        export::export_snapshot(&self.consensus, writer).await
+    }
+
+    pub async fn snapshot_status(&self) -> proto::GetSnapshotStatusResponse {
+        self.consensus.lock().await.handle_get_snapshot_status_rpc(&proto::GetSnapshotStatusRequest {})
+    }
+
+    pub async fn status(&self) -> proto::GetStatusResponse {
+        self.consensus.lock().await.handle_get_status_rpc(&proto::GetStatusRequest {}).await
+    }
+
+    // 订阅已提交的日志条目，start_index 为 None 时只跟随之后应用的条目，见 subscription 模块
+    pub async fn subscribe_committed(&self, start_index: Option<u64>) -> impl futures::Stream<Item = proto::SubscribeCommittedResponse> + Send {
+        let stop = self.consensus.lock().await.stop_signal();
+        subscription::committed_entries(self.consensus(), start_index, stop)
+    }
+
+    pub async fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<events::RaftEvent> {
+        self.consensus.lock().await.events.subscribe()
+    }
+
+    pub async fn metrics(&self) -> metrics::MetricsSnapshot {
+        self.consensus.lock().await.metrics.snapshot()
+    }
+
+    pub async fn latency_stats(&self) -> Vec<proto::LatencyHistogram> {
+        self.consensus.lock().await.metrics.latency_stats()
+    }
+
+    pub async fn stop(&self) -> Result<(), String> {
+        stop(self.consensus()).await
+    }
+
+    // 等待节点完全停止：调用 stop，或者作为 Leader 被配置变更移出集群之后
+    pub async fn wait_stopped(&self) {
+        let mut stop_signal = self.consensus.lock().await.stop_signal();
+        let _ = stop_signal.wait_for(|stopped| *stopped).await;
+    }
+}
+
 pub async fn start (
     server_id: u64,
     port: u32,
     initial_peers_info: Vec<proto::ServerInfo>,
     state_machine: Box<dyn state_machine::StateMachine>,
-    snapshot_dir_str: String,
-    metadata_dir_str: String,
-) -> Result<Arc<TokioMutex<consensus::Consensus>>, Box<dyn std::error::Error + Send + Sync>> {
+    storage: storage::StoragePaths,
+    options: config::RaftOptions,
+) -> Result<RaftNode, Box<dyn std::error::Error + Send + Sync>> {
 
-    info!("Starting Raft node {} on port {}", server_id, port);
+    let addr = options.listen_addr.clone().unwrap_or_else(|| format!("[::1]:{}", port));
+    info!("Starting Raft node {} {} on {} (data root: {})", server_id, version::describe(), addr, storage.root().display());
+    let (snapshot_dir_str, metadata_dir_str) = storage.create_node_dirs(server_id)?;
+    // 旧版本的数据目录先原地升级到当前格式
+    let migration_report = migration::migrate(&snapshot_dir_str, &metadata_dir_str, false)?;
+    if migration_report.from_version != migration_report.to_version {
+        info!("Upgraded data dir of node {} from storage version {} to {} ({} actions)",
+            server_id, migration_report.from_version, migration_report.to_version, migration_report.actions.len());
+    }
+    // 清理上次运行遗留的临时文件和孤立的快照文件，必须在 Consensus 加载快照之前进行
+    for path in storage::remove_stale_tmp_files(std::path::Path::new(&metadata_dir_str), config::STALE_ARTIFACT_MIN_AGE) {
+        info!("Removed stale temp file {} of node {}", path.display(), server_id);
+    }
+    let mut snapshot_manager = snapshot::SnapshotManager::new(snapshot_dir_str.clone());
+    snapshot_manager.reload_metadata();
+    snapshot_manager.remove_stale_artifacts(config::STALE_ARTIFACT_MIN_AGE);
+    // 导入外部数据集：快照中的配置与各节点传入的初始集群一致
+    let initial_configuration = || {
+        let mut initial_servers = initial_peers_info.clone();
+        if !initial_servers.iter().any(|s| s.server_id == server_id) {
+            initial_servers.push(proto::ServerInfo { server_id, server_addr: addr.clone(), zone: None });
+        }
+        config::Config::try_new_stable(initial_servers)
+    };
+    if let Some(initial_snapshot) = &options.initial_snapshot {
+        if snapshot::import_initial_snapshot(&snapshot_dir_str, &metadata_dir_str, initial_snapshot, initial_configuration()?)? {
+            info!("Node {} initialized from snapshot {} at index {}, term {}", server_id,
+                initial_snapshot.filepath, initial_snapshot.last_included_index, initial_snapshot.last_included_term);
+        }
+    } else if let Some(source_addr) = &options.bootstrap_from {
+        // 新加入的节点从已经追上的节点拉取快照，而不是等 Leader 发送
+        if bootstrap::bootstrap_from_peer(&rpc::Client::with_transport(options.transport.clone()), source_addr, &snapshot_dir_str, &metadata_dir_str, initial_configuration()?).await? {
+            info!("Node {} bootstrapped from the snapshot of {}", server_id, source_addr);
+        }
+    }
+    // 打印随机数种子，失败的运行可以通过 RAFT_RNG_SEED 重放
+    let rng_seed = util::init_rng(options.rng_seed);
+    info!("Random seed: {} (replay with {}={})", rng_seed, config::RNG_SEED_ENV, rng_seed);
     // 初始化共识模块
     let consensus_arc = consensus::Consensus::new(
         server_id,
-        port,
+        addr.clone(),
         initial_peers_info, // 使用 ServerInfo 列表
         state_machine,
-        snapshot_dir_str,  // 直接传递 String
-        metadata_dir_str,  // 直接传递 String
-    ).await; // 调用 await
+        snapshot_dir_str.clone(),  // 直接传递 String
+        metadata_dir_str.clone(),  // 直接传递 String
+        options.clock.clone().unwrap_or_else(|| Arc::new(clock::SystemClock)),
+    ).await?;
 
-    // 启动 rpc server
-    let consensus_clone_for_rpc = Arc::clone(&consensus_arc);
-    let addr = format!("[::1]:{}", port);
+    // 加入同一进程内的共识组注册表，由注册表合并发送心跳
+    {
+        let mut consensus_guard = consensus_arc.lock().await;
+        consensus_guard.group_id = options.group_id;
+        consensus_guard.set_transport(options.transport.clone());
+        consensus_guard.max_entry_size = options.max_entry_size.unwrap_or(config::DEFAULT_MAX_ENTRY_SIZE);
+        consensus_guard.chunk_large_entries = options.chunk_large_entries;
+        consensus_guard.disk_quota_bytes = options.disk_quota_bytes;
+        consensus_guard.storage_compression = options.storage_compression;
+        consensus_guard.ready_max_apply_lag = options.ready_max_apply_lag.unwrap_or(config::DEFAULT_READY_MAX_APPLY_LAG);
+        consensus_guard.log.set_compression(options.storage_compression);
+        consensus_guard.snapshot_policy = options.snapshot_policy.clone();
+        consensus_guard.set_apply_rate_limit(options.apply_rate_limit.clone()).await;
+        consensus_guard.load_shedder = options.load_shedding.clone().map(shedding::LoadShedder::new);
+        consensus_guard.divergence_alarm = options.divergence_alarm.clone();
+        consensus_guard.max_clock_skew = options.max_clock_skew;
+        if options.crash_dump {
+            let recorder = Arc::new(crash::CrashRecorder::new(server_id, &metadata_dir_str));
+            crash::install(&recorder);
+            consensus_guard.crash_recorder = Some(recorder);
+            consensus_guard.refresh_crash_report();
+        }
+        if let Some(registry) = &options.group_registry {
+            consensus_guard.coalesce_heartbeats = true;
+            registry.register(options.group_id, &consensus_arc);
+        }
+        if let Some(store) = &options.archive_store {
+            consensus_guard.archiver = Some(archive::Archiver::spawn(Arc::clone(store), archive::default_prefix(options.group_id, server_id)));
+        }
+        if let Some(commit_sink) = &options.commit_sink {
+            consensus_guard.attach_commit_sink(Arc::clone(commit_sink), metadata_dir_str.clone());
+        }
+    }
+
+    // 启动 rpc server：先绑定地址，绑定失败时直接返回错误
+    info!("Attempting to start RPC server on {} (management: {:?}) for Raft node {}", addr, options.management_addr, server_id);
+    let hooks = match &options.client_rate_limit {
+        Some(limit) => options.server_hooks.clone().layer(rate_limit::RateLimitLayer::new(limit.clone())),
+        None => options.server_hooks.clone(),
+    };
+    let bound_server = match rpc::bind_server(
+        &addr,
+        options.management_addr.as_deref(),
+        !options.disable_management_rpc,
+        Arc::clone(&consensus_arc),
+        options.group_registry.clone(),
+        hooks,
+        &options.transport,
+    ).await {
+        Ok(bound_server) => bound_server,
+        Err(e) => {
+            error!("Tonic rpc server for node {} failed to bind {}: {}", server_id, addr, e);
+            consensus_arc.lock().await.shutdown().await;
+            return Err(e);
+        }
+    };
+
+    // 启动 HTTP 状态页，绑定失败时与 RPC 服务一样直接返回错误
+    let status_listener = match &options.status_addr {
+        Some(status_addr) => match status_page::bind(status_addr).await {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                error!("Status page for node {} failed to bind {}: {}", server_id, status_addr, e);
+                consensus_arc.lock().await.shutdown().await;
+                return Err(e.into());
+            }
+        },
+        None => None,
+    };
+    let status_addr = match &status_listener {
+        Some(listener) => Some(listener.local_addr()?.to_string()),
+        None => None,
+    };
+
+    let startup_report = {
+        let consensus_guard = consensus_arc.lock().await;
+        StartupReport {
+            server_id,
+            bound_addr: bound_server.addr().to_string(),
+            management_addr: bound_server.management_addr().map(str::to_string),
+            status_addr,
+            snapshot_last_included_index: consensus_guard.snapshot.last_included_index(),
+            snapshot_last_included_term: consensus_guard.snapshot.last_included_term(),
+            log_start_index: consensus_guard.log.start_index(),
+            log_last_index: consensus_guard.log.last_index(consensus_guard.snapshot.last_included_index()),
+            last_applied: consensus_guard.last_applied,
+            configuration: consensus_guard.current_config.clone(),
+        }
+    };
+    info!("Startup report for node {}: {:?}", server_id, startup_report);
+
+    let (readiness_tx, readiness_rx) = tokio::sync::watch::channel(Readiness::Starting);
+    let stop_signal = consensus_arc.lock().await.stop_signal();
     tokio::spawn(async move {
-        info!("Attempting to start RPC server on {} for Raft node {}", addr, server_id);
-        if let Err(e) = rpc::start_server(&addr, consensus_clone_for_rpc).await { // 调用 await
-            error!("Tonic rpc server for node {} failed to start or encountered an error: {}", server_id, e);
-            // 在实际应用中，这里可能需要更健壮的错误处理，例如通知主程序或尝试重启
+        // 监听地址已经绑定，恢复也已完成，连接会在服务开始后被依次处理
+        readiness_tx.send_replace(Readiness::Ready);
+        if let Err(e) = bound_server.serve(stop_signal).await {
+            error!("Tonic rpc server for node {} encountered an error: {}", server_id, e);
+            readiness_tx.send_replace(Readiness::Failed(e.to_string()));
         } else {
             info!("RPC server for Raft node {} has shut down.", server_id);
         }
     });
     info!("RPC server task for node {} spawned.", server_id);
 
+    if let Some(listener) = status_listener {
+        info!("Status page for node {} available at http://{}/status", server_id, startup_report.status_addr.as_deref().unwrap_or_default());
+        let handle = tokio::spawn(status_page::serve(listener, Arc::clone(&consensus_arc)));
+        consensus_arc.lock().await.status_task = Some(handle);
+    }
+
+    // 启动后台校验任务
+    if let Some(verify_interval) = options.verify_interval {
+        let mut consensus_guard = consensus_arc.lock().await;
+        let handle = verify::spawn(
+            snapshot_dir_str,
+            metadata_dir_str,
+            verify_interval,
+            consensus_guard.events.clone(),
+            Arc::clone(&consensus_guard.metrics),
+        );
+        consensus_guard.verify_task = Some(handle);
+    }
+
     info!("Raft node {} fully started and initialized.", server_id);
-    Ok(consensus_arc)
+    Ok(RaftNode {
+        consensus: consensus_arc,
+        startup_report: Arc::new(startup_report),
+        readiness: readiness_rx,
+        storage,
+    })
 }
 
 pub async fn stop(
@@ -52,15 +411,11 @@ pub async fn stop(
     let mut consensus_guard = consensus_arc.lock().await;
 
 
-    // 调用Consensus内部的 shutdown 方法
-    consensus_guard.shutdown().await;
-    info!("Consensus module shutdown initiated for node {}.", consensus_guard.server_id);
-
-    // TODO 这里的处理不够细腻，比较复杂，后续需要重新设计
-    
-    // 对于测试，简单地让spawned RPC在服务器任务在Consensus被drop后自然结束
+    // 关闭共识模块，RPC 服务处理完正在进行的请求后退出
+    consensus_guard.stop().await;
+    info!("Consensus module stopped for node {}.", consensus_guard.server_id);
     drop(consensus_guard);
-    info!("Raft node stop sequence complete. RPC server might need separate handling for graceful shutdown.");
+    info!("Raft node stop sequence complete.");
     Ok(())
 
 }
\ No newline at end of file
@@ -1,11 +1,11 @@
 use super::logging::*; 
-use crate::raft::config;
+use crate::raft::{compress, config};
 use crate::raft::proto; 
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use std::io::{BufReader, BufWriter, Read, Write};
-use std::sync::Mutex;
 use std::fs::{File, OpenOptions}; 
+use std::time::{SystemTime, UNIX_EPOCH};
 
 lazy_static! {
     // VIRTUAL_LOG_ENTRY 用于表示快照之前的日志条目，其索引为0，任期为0
@@ -17,23 +17,65 @@ lazy_static! {
         // 这里假设 proto::EntryType::Noop.into() 是正确的
         entry_type: proto::EntryType::Noop.into(),
         data: Vec::new(), // 空数据
+        timestamp_ms: None,
     };
 }
 
 /// LogEntryData 是一个元组，包含日志条目的类型和具体数据
 pub type LogEntryData = (proto::EntryType, Vec<u8>);
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Log {
+/// LogCore 是日志中需要持久化的部分，也是磁盘上日志文件的格式
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LogCore {
     entries: Vec<proto::LogEntry>, // 内存中的日志条目列表
     start_index: u64,              // entries 向量中第一条日志的索引（快照后的起始索引）
-    metadata_dir: String,          // 日志文件存储目录
+}
+
+impl LogCore {
+    /// 校验从磁盘读出的内容：start_index 至少为 1，索引从 start_index 开始连续、任期单调不减
+    fn validate(&self) -> Result<(), String> {
+        if self.start_index == 0 {
+            return Err("start_index is 0".to_string());
+        }
+        let mut prev_term = 0;
+        for (offset, entry) in self.entries.iter().enumerate() {
+            let expected_index = self.start_index.checked_add(offset as u64)
+                .ok_or_else(|| format!("index overflows after start_index {}", self.start_index))?;
+            if entry.index != expected_index {
+                return Err(format!("expected index {} but found {}", expected_index, entry.index));
+            }
+            if entry.term < prev_term {
+                return Err(format!("term decreases from {} to {} at index {}", prev_term, entry.term, entry.index));
+            }
+            prev_term = entry.term;
+        }
+        Ok(())
+    }
+}
+
+/// Log 只通过 &mut self 修改，不需要额外的锁。
+/// 修改方法只改内存状态并标记 dirty，调用方在合适的时机（例如回复RPC之前）调用 persist 落盘，
+/// 这样一次RPC中的多次修改只需要写一次磁盘。
+// 日志文件的版本：每次 dump 都会重写文件，长度和修改时间都相同时认为内容没有变化
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DiskVersion {
+    len: u64,
+    modified: Option<SystemTime>,
+}
 
-    // append_mutex 用于防止并发修改 entries 导致索引冲突
-    // 注意：Mutex<String> 的 payload "String" 在这里没有实际意义，Mutex<()> 更合适。
-    // 但为了保持与原代码一致，暂时保留 String。
-    #[serde(skip)] // 持久化时跳过这个字段
-    append_mutex: Mutex<String>,
+impl DiskVersion {
+    fn of(metadata: &std::fs::Metadata) -> Self {
+        DiskVersion { len: metadata.len(), modified: metadata.modified().ok() }
+    }
+}
+
+#[derive(Debug)]
+pub struct Log {
+    core: LogCore,
+    metadata_dir: String, // 日志文件存储目录
+    dirty: bool,          // 内存状态是否有尚未持久化的修改
+    compression: config::StorageCompression, // 写入日志文件时的压缩方式，读取时按文件头识别
+    disk_entries: Option<(DiskVersion, LogCore)>, // read_entry_from_disk 上次解析的日志文件及其版本
 }
 
 impl Log {
@@ -41,25 +83,29 @@ impl Log {
     /// start_index 通常是 1，或者在从快照恢复后是 last_included_index + 1
     pub fn new(start_index: u64, metadata_dir: String) -> Self {
         Log {
-            entries: Vec::new(),
-            start_index,
+            core: LogCore {
+                entries: Vec::new(),
+                start_index,
+            },
             metadata_dir,
-            append_mutex: Mutex::new(String::new()), // 初始化互斥锁
+            dirty: false,
+            compression: config::StorageCompression::None,
+            disk_entries: None,
         }
     }
 
+    /// 设置之后写入日志文件时使用的压缩方式，已经在磁盘上的文件在下次写入时转换
+    pub fn set_compression(&mut self, compression: config::StorageCompression) {
+        self.compression = compression;
+    }
+
     /// 追加新的日志数据
     /// term: 当前领导者的任期
     /// entry_data: 一个包含 (EntryType, data_bytes) 元组的向量
+    /// 条目带上追加时的 Unix 时间，随条目复制给 Follower，用于状态机的 TTL 逻辑和延迟排查
     pub fn append_data(&mut self, term: u64, entry_data_list: Vec<LogEntryData>) {
-        // 获取互斥锁以保证追加操作的原子性
-        // 如果你的 Raft 是单线程处理日志的，这个锁可能不是必需的
-        let _lock = self.append_mutex.lock().unwrap_or_else(|poisoned| {
-            error!("append_data: Mutex was poisoned, recovering.");
-            poisoned.into_inner() // 尝试恢复
-        });
-
         let mut current_last_index = self.last_index(0); // 获取当前日志的最后索引
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
         for (entry_type, data) in entry_data_list {
             current_last_index += 1;
             let log_entry = proto::LogEntry {
@@ -67,10 +113,11 @@ impl Log {
                 term,
                 entry_type: entry_type.into(), // 将 proto::EntryType 枚举转换为 i32
                 data,
+                timestamp_ms: Some(timestamp_ms),
             };
-            self.entries.push(log_entry);
+            self.core.entries.push(log_entry);
         }
-        self.dump(); // 追加后持久化日志
+        self.dirty = true;
     }
 
     /// 追加已经构造好的日志条目 (通常用于 Follower 接收 Leader 的日志)
@@ -78,11 +125,6 @@ impl Log {
         if entries_to_append.is_empty() {
             return;
         }
-        let _lock = self.append_mutex.lock().unwrap_or_else(|poisoned| {
-            error!("append_entries: Mutex was poisoned, recovering.");
-            poisoned.into_inner()
-        });
-
         // 校验待追加日志的连续性 (可选，但推荐)
         // let expected_next_index = self.last_index(0) + 1;
         // if let Some(first_entry) = entries_to_append.first() {
@@ -95,18 +137,18 @@ impl Log {
         //         return;
         //     }
         // }
-        self.entries.extend(entries_to_append);
-        self.dump(); // 追加后持久化日志
+        self.core.entries.extend(entries_to_append);
+        self.dirty = true;
     }
 
     /// 返回所有内存中的日志条目的不可变引用
     pub fn entries(&self) -> &Vec<proto::LogEntry> {
-        &self.entries
+        &self.core.entries
     }
 
     /// 返回日志的起始索引 (通常是快照的 last_included_index + 1)
     pub fn start_index(&self) -> u64 {
-        self.start_index
+        self.core.start_index
     }
 
     /// 根据索引获取日志条目
@@ -117,7 +159,7 @@ impl Log {
         if index == 0 { // 通常 raft 日志索引从 1 开始，0 可以作为特殊值
             return Some(&VIRTUAL_LOG_ENTRY);
         }
-        if index < self.start_index {
+        if index < self.core.start_index {
             // 这意味着请求的日志在快照中，并且这是一个有效的已提交日志
             // 返回 VIRTUAL_LOG_ENTRY 表示该条目存在但其内容未知（已快照）
             // 或者，如果知道快照的 last_included_term，可以构造一个更精确的虚拟条目
@@ -133,20 +175,20 @@ impl Log {
             return Some(&VIRTUAL_LOG_ENTRY);
         }
         // 计算在 `entries` Vec 中的实际索引
-        let vec_index = (index - self.start_index) as usize;
-        self.entries.get(vec_index)
+        let vec_index = (index - self.core.start_index) as usize;
+        self.core.entries.get(vec_index)
     }
 
     /// 打包从 next_index 开始的所有日志条目 (用于发送给 Follower)
     pub fn pack_entries(&self, next_index: u64) -> Vec<proto::LogEntry> {
-        if next_index < self.start_index {
+        if next_index < self.core.start_index {
             // 如果请求的 next_index 比内存日志的起始还早，
             // 这通常意味着 Follower 需要一个快照。
             // Leader 应该发送快照而不是尝试发送这些日志。
             // 返回空Vec表示没有可从内存打包的日志。
             warn!(
                 "pack_entries: next_index {} is less than start_index {}. Follower might need a snapshot.",
-                next_index, self.start_index
+                next_index, self.core.start_index
             );
             return Vec::new();
         }
@@ -155,32 +197,32 @@ impl Log {
             return Vec::new();
         }
 
-        let skip_count = (next_index - self.start_index) as usize;
-        self.entries.iter().skip(skip_count).cloned().collect()
+        let skip_count = (next_index - self.core.start_index) as usize;
+        self.core.entries.iter().skip(skip_count).cloned().collect()
     }
 
     /// 获取日志中的最后一个条目的索引
     /// last_included_index: 快照中的最后一个索引，如果日志为空且快照存在，则以此为准
     pub fn last_index(&self, last_included_index: u64) -> u64 {
-        if self.entries.is_empty() {
+        if self.core.entries.is_empty() {
             // 如果内存日志为空，则最后一个索引是 start_index - 1
             // 或者，如果提供了有效的 last_included_index (来自快照)，则使用它
-            if last_included_index > 0 && last_included_index >= self.start_index -1 { // 确保 last_included_index 合理
+            if last_included_index > 0 && last_included_index >= self.core.start_index.saturating_sub(1) { // 确保 last_included_index 合理
                 return last_included_index;
             } else {
-                return self.start_index.saturating_sub(1); // 防止 start_index 为 0 或 1 时下溢
+                return self.core.start_index.saturating_sub(1); // 防止 start_index 为 0 或 1 时下溢
             }
         }
         // 否则，返回内存中最后一条日志的索引
-        self.entries.last().map_or(self.start_index.saturating_sub(1), |entry| entry.index)
+        self.core.entries.last().map_or(self.core.start_index.saturating_sub(1), |entry| entry.index)
     }
 
     /// 获取日志中的最后一个条目的任期
     /// last_included_term: 快照中的最后一个任期，如果日志为空且快照存在，则以此为准
     pub fn last_term(&self, last_included_term: u64) -> u64 {
-        if self.entries.is_empty() {
+        if self.core.entries.is_empty() {
             // 如果内存日志为空
-            if last_included_term > 0 && self.start_index > 0 { // 假设快照存在
+            if last_included_term > 0 && self.core.start_index > 0 { // 假设快照存在
                 return last_included_term;
             } else {
                 // 如果没有快照信息或 start_index 为 0，则认为任期为 0
@@ -188,7 +230,7 @@ impl Log {
             }
         }
         // 否则，返回内存中最后一条日志的任期
-        self.entries.last().map_or(0, |entry| entry.term)
+        self.core.entries.last().map_or(0, |entry| entry.term)
     }
 
     /// 获取指定索引的前一个日志条目的任期
@@ -216,12 +258,12 @@ impl Log {
                 // 那么这里的 term (0) 可能不准确。
                 // 但如果 prev_log_index < start_index，并且不是 last_included_index，
                 // 这种情况通常不应该发生，或者意味着状态不一致。
-                if entry.index == prev_log_index || prev_log_index >= self.start_index {
+                if entry.index == prev_log_index || prev_log_index >= self.core.start_index {
                      entry.term
                 } else {
                     // prev_log_index < start_index 但不是 last_included_index, 也不是 VIRTUAL_LOG_ENTRY 的 index 0
                     // 这是一种不一致的状态，或者 VIRTUAL_LOG_ENTRY 的设计需要调整
-                    warn!("prev_log_term: Inconsistent state for prev_log_index {} which is before start_index {} but not last_included_index {}", prev_log_index, self.start_index, last_included_index);
+                    warn!("prev_log_term: Inconsistent state for prev_log_index {} which is before start_index {} but not last_included_index {}", prev_log_index, self.core.start_index, last_included_index);
                     0 // 或者 panic
                 }
 
@@ -235,59 +277,92 @@ impl Log {
 
     /// 截断从 last_index_kept 之后的日志条目 (用于处理日志冲突)
     pub fn truncate_suffix(&mut self, last_index_kept: u64) {
-        if self.entries.is_empty() || last_index_kept < self.start_index {
+        if self.core.entries.is_empty() || last_index_kept < self.core.start_index {
             // 如果要保留的索引在当前内存日志范围之前，或者日志为空，
             // 意味着所有内存日志都应该被清除。
             // 但 Raft 中，通常是 last_index_kept >= commit_index，且 commit_index >= start_index-1
-            if last_index_kept < self.start_index.saturating_sub(1) { // 小于等于快照前的日志
+            if last_index_kept < self.core.start_index.saturating_sub(1) { // 小于等于快照前的日志
                  warn!("truncate_suffix: last_index_kept {} is less than or equal to snapshot's last index. Clearing all in-memory entries.", last_index_kept);
-                 self.entries.clear();
-            } else if last_index_kept < self.start_index {
+                 self.core.entries.clear();
+            } else if last_index_kept < self.core.start_index {
                 // 如果 last_index_kept 恰好是快照的最后一条，则内存日志清空
-                self.entries.clear();
+                self.core.entries.clear();
             }
             // else (last_index_kept >= start_index), proceed to normal truncation below.
-            // No, the condition is `last_index_kept < self.start_index`. If true, all current entries are after `last_index_kept`.
+            // No, the condition is `last_index_kept < self.core.start_index`. If true, all current entries are after `last_index_kept`.
             // So, if `last_index_kept` is valid (e.g., `last_index_kept = prevLogIndex` from AppendEntries RPC),
-            // and `prevLogIndex` is less than `self.start_index`, it means the leader's `prevLogIndex`
-            // points to an entry in our snapshot. So, all our current `self.entries` are conflicting.
-            // Example: self.entries = [idx=5, idx=6], start_index=5. Leader says prevLogIndex=3.
+            // and `prevLogIndex` is less than `self.core.start_index`, it means the leader's `prevLogIndex`
+            // points to an entry in our snapshot. So, all our current `self.core.entries` are conflicting.
+            // Example: self.core.entries = [idx=5, idx=6], start_index=5. Leader says prevLogIndex=3.
             // last_index_kept = 3. 3 < 5. So clear [idx=5, idx=6].
-            else { // This case: last_index_kept < self.start_index.
-                   // All entries in `self.entries` have index >= self.start_index.
-                   // So, all entries in `self.entries` are after `last_index_kept`.
+            else { // This case: last_index_kept < self.core.start_index.
+                   // All entries in `self.core.entries` have index >= self.core.start_index.
+                   // So, all entries in `self.core.entries` are after `last_index_kept`.
                    // They all need to be removed.
-                self.entries.clear();
+                self.core.entries.clear();
             }
 
         } else {
             // 计算在 Vec 中的截断点
             // 我们要保留到 last_index_kept (包含它)
-            // 所以 Vec 的长度应该是 (last_index_kept - self.start_index + 1)
-            let new_len = (last_index_kept - self.start_index + 1) as usize;
-            if new_len < self.entries.len() { // 只有当新长度小于当前长度时才截断
-                self.entries.truncate(new_len);
-            } else if new_len > self.entries.len() {
+            // 所以 Vec 的长度应该是 (last_index_kept - self.core.start_index + 1)
+            let new_len = (last_index_kept - self.core.start_index + 1) as usize;
+            if new_len < self.core.entries.len() { // 只有当新长度小于当前长度时才截断
+                self.core.entries.truncate(new_len);
+            } else if new_len > self.core.entries.len() {
                 // 这表示 last_index_kept 指向了当前日志之外的未来条目
                 // 这不应该通过 truncate_suffix 来处理，可能是逻辑错误
                 error!(
                     "truncate_suffix: last_index_kept {} (new_len {}) is beyond current log entries (len {}). No truncation performed.",
-                    last_index_kept, new_len, self.entries.len()
+                    last_index_kept, new_len, self.core.entries.len()
                 );
                 return; // 不做任何事或 panic
             }
-            // 如果 new_len == self.entries.len()，则无需操作
+            // 如果 new_len == self.core.entries.len()，则无需操作
         }
-        self.dump(); // 截断后持久化
+        self.dirty = true;
+    }
+
+    /// 按 Raft 的规则把 Leader 发来的条目合并进日志：已存在且任期相同的条目保持不变，
+    /// 在第一个同索引不同任期的条目处截断本地日志，然后追加剩余的条目；返回新追加的条目。
+    /// 已经被快照覆盖的条目一定已提交，直接跳过；冲突位置不超过 commit_index 时说明
+    /// 请求与已提交的日志矛盾，返回错误且不修改日志
+    pub fn reconcile(&mut self, entries: &[proto::LogEntry], commit_index: u64) -> Result<Vec<proto::LogEntry>, String> {
+        let mut first_new = entries.len();
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.index < self.core.start_index {
+                continue;
+            }
+            match self.entry(entry.index) {
+                Some(existing) if existing.term == entry.term => continue,
+                Some(existing) => {
+                    if entry.index <= commit_index {
+                        return Err(format!(
+                            "entry {} (term {}) conflicts with committed entry of term {}, commit_index {}",
+                            entry.index, entry.term, existing.term, commit_index
+                        ));
+                    }
+                    info!("Conflict detected at index {} (local term {}, leader term {}). Truncating log after index {}.",
+                        entry.index, existing.term, entry.term, entry.index - 1);
+                    self.truncate_suffix(entry.index - 1);
+                }
+                None => {}
+            }
+            first_new = i;
+            break;
+        }
+        let new_entries = entries[first_new..].to_vec();
+        self.append_entries(new_entries.clone());
+        Ok(new_entries)
     }
 
     /// 截断由于快照而已过时的前缀日志条目
     pub fn truncate_prefix(&mut self, last_included_index_from_snapshot: u64) {
         // 如果快照的最后索引小于当前内存日志的起始索引，则无需操作
-        if last_included_index_from_snapshot < self.start_index {
+        if last_included_index_from_snapshot < self.core.start_index {
             info!(
                 "truncate_prefix: Snapshot index {} is older than current start_index {}. No prefix truncation needed.",
-                last_included_index_from_snapshot, self.start_index
+                last_included_index_from_snapshot, self.core.start_index
             );
             return;
         }
@@ -296,93 +371,145 @@ impl Log {
 
         if current_last_log_index <= last_included_index_from_snapshot {
             // 所有内存中的日志条目都已经被包含在快照中
-            self.entries.clear();
+            self.core.entries.clear();
         } else {
             // 计算需要从 entries Vec 中移除的元素数量
             // 我们要移除所有索引 <= last_included_index_from_snapshot 的条目
-            // (last_included_index_from_snapshot - self.start_index + 1) 是要移除的数量
-            let drain_count = (last_included_index_from_snapshot - self.start_index + 1) as usize;
-            if drain_count > 0 && drain_count <= self.entries.len() {
-                self.entries.drain(0..drain_count);
-            } else if drain_count > self.entries.len() {
+            // (last_included_index_from_snapshot - self.core.start_index + 1) 是要移除的数量
+            let drain_count = (last_included_index_from_snapshot - self.core.start_index + 1) as usize;
+            if drain_count > 0 && drain_count <= self.core.entries.len() {
+                self.core.entries.drain(0..drain_count);
+            } else if drain_count > self.core.entries.len() {
                 // 要移除的比现有的还多，说明全部移除
-                warn!("truncate_prefix: drain_count {} exceeds entries len {}. Clearing all entries.", drain_count, self.entries.len());
-                self.entries.clear();
+                warn!("truncate_prefix: drain_count {} exceeds entries len {}. Clearing all entries.", drain_count, self.core.entries.len());
+                self.core.entries.clear();
             }
             // 如果 drain_count == 0，则无需操作 (通常是因为 last_included_index < start_index)
         }
         // 更新 start_index
-        self.start_index = last_included_index_from_snapshot + 1;
-        self.dump(); // 截断后持久化
-        info!("truncate_prefix: Log truncated. New start_index: {}. Entries count: {}", self.start_index, self.entries.len());
+        self.core.start_index = last_included_index_from_snapshot + 1;
+        self.dirty = true;
+        // 快照已经包含的条目不会再从日志文件读取
+        self.disk_entries = None;
+        info!("truncate_prefix: Log truncated. New start_index: {}. Entries count: {}", self.core.start_index, self.core.entries.len());
     }
 
     /// 获取已提交日志条目的数量 (在内存中)
     pub fn committed_entries_len(&self, commit_index: u64) -> usize {
-        if commit_index < self.start_index {
+        if commit_index < self.core.start_index {
             return 0;
         }
-        // (commit_index - self.start_index + 1) 是相对于 start_index 的长度
+        // (commit_index - self.core.start_index + 1) 是相对于 start_index 的长度
         // 但要确保不超过实际内存中的日志数量
-        let len_in_mem = (commit_index - self.start_index + 1) as usize;
-        std::cmp::min(len_in_mem, self.entries.len())
+        let len_in_mem = (commit_index - self.core.start_index + 1) as usize;
+        std::cmp::min(len_in_mem, self.core.entries.len())
     }
 
     /// 从后向前查找日志中最新的配置条目
     pub fn last_configuration(&self) -> Option<config::Config> { // 返回新的 config::Config
-        for entry in self.entries.iter().rev() {
+        self.last_configuration_entry().map(|(_, config)| config)
+    }
+
+    /// 最后一个配置条目的索引和配置
+    pub fn last_configuration_entry(&self) -> Option<(u64, config::Config)> {
+        self.configuration_entry_at(u64::MAX)
+    }
+
+    /// 在 index 处生效的配置：索引不超过 index 的最后一个配置条目
+    pub fn configuration_entry_at(&self, index: u64) -> Option<(u64, config::Config)> {
+        for entry in self.core.entries.iter().rev().skip_while(|entry| entry.index > index) {
             // 假设你的 proto::EntryType::Configuration 的数值是固定的
             // 或者 entry.entry_type 直接就是 proto::EntryType 枚举类型 (取决于 prost 生成方式)
             // 这里我们用 as i32 来比较
             if entry.entry_type == proto::EntryType::Configuration as i32 {
-                // 使用新的 config::Config::from_data
-                return Some(config::Config::from_data(&entry.data));
+                // 损坏的配置条目跳过，继续使用更早的配置
+                match config::Config::try_from_data(&entry.data) {
+                    Ok(configuration) => return Some((entry.index, configuration)),
+                    Err(e) => error!("last_configuration_entry: skipping entry {}: {}", entry.index, e),
+                }
             }
         }
         None // 如果内存日志中没有配置条目，则返回 None
     }
 
+    /// 本实例的日志文件路径
+    pub fn filepath(&self) -> String {
+        Log::gen_log_filepath(&self.metadata_dir)
+    }
+
     /// 生成日志文件的完整路径
     pub fn gen_log_filepath(metadata_dir: &str) -> String { // &str 参数更通用
         format!("{}/raft.log", metadata_dir)
     }
 
-    /// 从磁盘重新加载日志
-    pub fn reload(&mut self) {
+    /// 校验日志文件内容：能够完整解析，并且索引从 start_index 开始连续、任期单调不减。
+    /// 成功时返回条目数量，供后台校验任务使用，不修改内存中的日志
+    pub fn verify_reader<R: Read>(reader: R) -> Result<usize, String> {
+        let reader = compress::reader(BufReader::new(reader))
+            .map_err(|e| format!("failed to read log file: {}", e))?;
+        let core: LogCore = serde_json::from_reader(reader)
+            .map_err(|e| format!("failed to parse log file: {}", e))?;
+        core.validate()?;
+        Ok(core.entries.len())
+    }
+
+    /// 从磁盘重新加载日志。文件无法读取或内容损坏时返回错误，日志保持不变：
+    /// 按空日志启动会丢掉已经确认过的条目，节点应当拒绝启动，由运维介入
+    pub fn reload(&mut self) -> Result<(), String> {
         let filepath = Log::gen_log_filepath(&self.metadata_dir);
-        if std::path::Path::new(&filepath).exists() {
-            info!("reloading raft log from {}", filepath);
-            match File::open(&filepath) {
-                Ok(file) => {
-                    let reader = BufReader::new(file); // 使用 BufReader 提高读取效率
-                    match serde_json::from_reader(reader) { // 从 reader 反序列化
-                        Ok(log_from_disk) => {
-                            let loaded_log: Log = log_from_disk;
-                            self.entries = loaded_log.entries;
-                            self.start_index = loaded_log.start_index;
-                            info!(
-                                "raft log reloaded successfully. Start_index: {}, Entries count: {}",
-                                self.start_index,
-                                self.entries.len()
-                            );
-                        }
-                        Err(e) => {
-                            error!("failed to deserialize raft log from {}: {}. Starting with an empty log.", filepath, e);
-                            // 如果反序列化失败，可能文件损坏，可以选择清空或报错退出
-                            self.entries.clear();
-                            self.start_index = 1; // 或者从一个已知的安全点开始
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("failed to open raft log file {} for reloading: {}. Starting with an empty log.", filepath, e);
-                    self.entries.clear();
-                    self.start_index = 1;
-                }
-            }
-        } else {
+        if !std::path::Path::new(&filepath).exists() {
             info!("no raft log file found at {}. Starting with an empty log.", filepath);
             // 文件不存在，通常是第一次启动，保持 new() 创建的空状态
+            return Ok(());
+        }
+        info!("reloading raft log from {}", filepath);
+        let file = File::open(&filepath)
+            .map_err(|e| format!("failed to open raft log file {}: {}", filepath, e))?;
+        // 使用 BufReader 提高读取效率；压缩的文件在这里透明解压
+        // 能解析但索引不连续的内容同样视为损坏，后续按索引计算偏移时会越界
+        let log_from_disk = compress::reader(BufReader::new(file))
+            .map_err(|e| e.to_string())
+            .and_then(|reader| serde_json::from_reader::<_, LogCore>(reader).map_err(|e| e.to_string()))
+            .and_then(|core| core.validate().map(|_| core))
+            .map_err(|e| format!("raft log {} is corrupted: {}", filepath, e))?;
+        self.core = log_from_disk;
+        self.dirty = false;
+        info!(
+            "raft log reloaded successfully. Start_index: {}, Entries count: {}",
+            self.core.start_index,
+            self.core.entries.len()
+        );
+        Ok(())
+    }
+
+    /// 直接从磁盘上的日志文件读取一个条目，不修改内存中的日志；用于内存中意外缺失条目时的恢复。
+    /// 解析后的文件内容保留下来，文件没有重写时之后的缺失条目直接在其中查找，不再重新解析
+    pub fn read_entry_from_disk(&mut self, index: u64) -> Option<proto::LogEntry> {
+        let file = File::open(Log::gen_log_filepath(&self.metadata_dir)).ok()?;
+        let version = DiskVersion::of(&file.metadata().ok()?);
+        let core = match self.disk_entries.take() {
+            Some((cached_version, core)) if cached_version == version => core,
+            _ => {
+                let core: LogCore = serde_json::from_reader(compress::reader(BufReader::new(file)).ok()?).ok()?;
+                core.validate().ok()?;
+                core
+            }
+        };
+        let entry = index.checked_sub(core.start_index).and_then(|offset| core.entries.get(offset as usize).cloned());
+        self.disk_entries = Some((version, core));
+        entry
+    }
+
+    /// 是否有尚未持久化的修改
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// 如果有尚未持久化的修改，则写入磁盘
+    pub fn persist(&mut self) {
+        if self.dirty {
+            self.dump();
+            self.dirty = false;
         }
     }
 
@@ -394,7 +521,14 @@ impl Log {
         match OpenOptions::new().write(true).create(true).truncate(true).open(&log_filepath) {
             Ok(file) => {
                 let writer = BufWriter::new(file); // 使用 BufWriter 提高写入效率
-                match serde_json::to_writer_pretty(writer, self) { // 使用 to_writer_pretty 格式化JSON，便于调试
+                // 不压缩时使用 to_writer_pretty 格式化JSON，便于调试；压缩时格式化没有意义
+                let result = match compress::zstd_level(self.compression) {
+                    None => serde_json::to_writer_pretty(writer, &self.core).map_err(|e| e.to_string()),
+                    Some(level) => serde_json::to_vec(&self.core)
+                        .map_err(|e| e.to_string())
+                        .and_then(|json| compress::write_compressed(writer, &json, level).map_err(|e| e.to_string())),
+                };
+                match result {
                     Ok(_) => {
                         // trace!("raft log dumped successfully to {}", log_filepath); // dump 通常很频繁，用 trace
                     }
@@ -484,8 +618,8 @@ mod tests {
         let mut log = Log::new(1, test_dir.to_string());
 
         let entries_to_add = vec![
-            proto::LogEntry { index: 1, term: 1, entry_type: proto::EntryType::Data.into(), data: b"entry1".to_vec() },
-            proto::LogEntry { index: 2, term: 1, entry_type: proto::EntryType::Data.into(), data: b"entry2".to_vec() },
+            proto::LogEntry { index: 1, term: 1, entry_type: proto::EntryType::Data.into(), data: b"entry1".to_vec(), timestamp_ms: None },
+            proto::LogEntry { index: 2, term: 1, entry_type: proto::EntryType::Data.into(), data: b"entry2".to_vec(), timestamp_ms: None },
         ];
         log.append_entries(entries_to_add);
         assert_eq!(log.entries().len(), 2);
@@ -493,7 +627,7 @@ mod tests {
         assert_eq!(log.entry(2).unwrap().data, b"entry2".to_vec());
 
         let more_entries = vec![
-            proto::LogEntry { index: 3, term: 2, entry_type: proto::EntryType::Data.into(), data: b"entry3".to_vec() },
+            proto::LogEntry { index: 3, term: 2, entry_type: proto::EntryType::Data.into(), data: b"entry3".to_vec(), timestamp_ms: None },
         ];
         log.append_entries(more_entries);
         assert_eq!(log.entries().len(), 3);
@@ -617,12 +751,14 @@ mod tests {
             let mut log = Log::new(1, test_dir.to_string());
             log.append_data(1, vec![(proto::EntryType::Data, b"persist1".to_vec())]);
             log.append_data(2, vec![(proto::EntryType::Data, b"persist2".to_vec())]);
-            // log.dump() is called internally by append_data
+            assert!(log.is_dirty());
+            log.persist();
+            assert!(!log.is_dirty());
         } // log 被 drop，其数据应该已写入文件
 
         let mut reloaded_log = Log::new(1, test_dir.to_string()); // 初始状态
         assert_eq!(reloaded_log.entries().len(), 0);
-        reloaded_log.reload(); // 从文件加载
+        reloaded_log.reload().unwrap(); // 从文件加载
 
         assert_eq!(reloaded_log.entries().len(), 2);
         assert_eq!(reloaded_log.start_index(), 1);
@@ -634,11 +770,11 @@ mod tests {
 
         // 测试截断后再加载
         reloaded_log.truncate_prefix(1); // 快照到 idx 1, start_index=2, entries=[idx 2]
-        // dump is called by truncate_prefix
+        reloaded_log.persist();
         drop(reloaded_log);
 
         let mut final_log = Log::new(1, test_dir.to_string());
-        final_log.reload();
+        final_log.reload().unwrap();
         assert_eq!(final_log.entries().len(), 1);
         assert_eq!(final_log.start_index(), 2);
         assert_eq!(final_log.entry(2).unwrap().data, b"persist2".to_vec());
@@ -655,13 +791,13 @@ mod tests {
         assert!(log.last_configuration().is_none()); // 空日志
 
         let cfg_data1 = config::Config::new_stable(vec![
-            proto::ServerInfo { server_id: 1, server_addr: "addr1".to_string() }
+            proto::ServerInfo { server_id: 1, server_addr: "addr1".to_string(), zone: None }
         ]).to_data();
         log.append_data(1, vec![(proto::EntryType::Configuration, cfg_data1.clone())]); // idx 1
 
         let cfg_data2 = config::Config::new_stable(vec![
-            proto::ServerInfo { server_id: 1, server_addr: "addr1".to_string() },
-            proto::ServerInfo { server_id: 2, server_addr: "addr2".to_string() }
+            proto::ServerInfo { server_id: 1, server_addr: "addr1".to_string(), zone: None },
+            proto::ServerInfo { server_id: 2, server_addr: "addr2".to_string(), zone: None }
         ]).to_data();
         log.append_data(1, vec![(proto::EntryType::Data, b"some data".to_vec())]); // idx 2
         log.append_data(2, vec![(proto::EntryType::Configuration, cfg_data2.clone())]); // idx 3
@@ -681,6 +817,11 @@ mod tests {
             panic!("Expected to find a configuration");
         }
 
+        // 更早的位置上生效的是更早的配置条目
+        assert_eq!(log.configuration_entry_at(2).map(|(index, c)| (index, c.new_servers.len())), Some((1, 1)));
+        assert_eq!(log.configuration_entry_at(3).map(|(index, _)| index), Some(3));
+        assert!(log.configuration_entry_at(0).is_none());
+
         // 测试截断对 last_configuration 的影响
         log.truncate_suffix(2); // 保留到 idx 2 (包含 cfg_data1 和 "some data")
                                 // 最新的配置应该是 cfg_data1
@@ -758,7 +899,7 @@ mod tests {
         let last_included_term_snap = 2;
         log.truncate_prefix(last_included_idx_snap); // start_index = 3, entries empty
 
-        assert_eq!(log.entries.len(), 0);
+        assert_eq!(log.entries().len(), 0);
         assert_eq!(log.start_index(), 3);
 
         // 此时内存日志为空，应使用快照信息
@@ -772,4 +913,140 @@ mod tests {
 
         fs::remove_dir_all(test_dir).ok();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_reconcile_partial_overlaps() {
+        let test_dir = "./test_log_reconcile";
+        cleanup_test_dir(test_dir);
+        let mut log = Log::new(1, test_dir.to_string());
+        let entry = |index: u64, term: u64| proto::LogEntry {
+            index,
+            term,
+            entry_type: proto::EntryType::Data.into(),
+            data: format!("{}-{}", index, term).into_bytes(),
+            timestamp_ms: None,
+        };
+        let terms = |log: &Log| log.entries().iter().map(|e| (e.index, e.term)).collect::<Vec<_>>();
+        log.append_entries(vec![entry(1, 1), entry(2, 1), entry(3, 2), entry(4, 2)]);
+
+        // 完全重复的条目不会改变日志
+        assert_eq!(log.reconcile(&[entry(2, 1), entry(3, 2)], 0).unwrap(), vec![]);
+        assert_eq!(terms(&log), vec![(1, 1), (2, 1), (3, 2), (4, 2)]);
+
+        // 过期的请求只覆盖了前缀，不能截断后面的日志
+        assert_eq!(log.reconcile(&[entry(1, 1)], 0).unwrap(), vec![]);
+        assert_eq!(log.entries().len(), 4);
+
+        // 部分重叠：前面的匹配，后面追加
+        assert_eq!(log.reconcile(&[entry(4, 2), entry(5, 2)], 0).unwrap(), vec![entry(5, 2)]);
+        assert_eq!(terms(&log), vec![(1, 1), (2, 1), (3, 2), (4, 2), (5, 2)]);
+
+        // 匹配和冲突混合：在第一个冲突处截断，再追加剩余部分
+        let appended = log.reconcile(&[entry(2, 1), entry(3, 2), entry(4, 3), entry(5, 3), entry(6, 3)], 3).unwrap();
+        assert_eq!(appended, vec![entry(4, 3), entry(5, 3), entry(6, 3)]);
+        assert_eq!(terms(&log), vec![(1, 1), (2, 1), (3, 2), (4, 3), (5, 3), (6, 3)]);
+
+        // 冲突的条目短于本地日志时，本地后面的条目一并截断
+        assert_eq!(log.reconcile(&[entry(5, 4)], 4).unwrap(), vec![entry(5, 4)]);
+        assert_eq!(terms(&log), vec![(1, 1), (2, 1), (3, 2), (4, 3), (5, 4)]);
+
+        // 与已提交的条目冲突时拒绝，日志不变
+        assert!(log.reconcile(&[entry(4, 5), entry(5, 5)], 4).is_err());
+        assert_eq!(terms(&log), vec![(1, 1), (2, 1), (3, 2), (4, 3), (5, 4)]);
+
+        // 快照覆盖的条目直接跳过
+        log.truncate_prefix(3);
+        assert_eq!(log.reconcile(&[entry(2, 1), entry(3, 2), entry(4, 3), entry(5, 4), entry(6, 4)], 3).unwrap(), vec![entry(6, 4)]);
+        assert_eq!(terms(&log), vec![(4, 3), (5, 4), (6, 4)]);
+
+        fs::remove_dir_all(test_dir).ok();
+    }
+
+    #[test]
+    fn test_reload_rejects_malformed_files() {
+        let test_dir = "./test_reload_rejects_malformed_files";
+        cleanup_test_dir(test_dir);
+        let filepath = Log::gen_log_filepath(test_dir);
+        let entry = |index: u64, entry_type: proto::EntryType, data: &str| serde_json::json!(
+            {"term": 1, "index": index, "entry_type": entry_type as i32, "data": data.as_bytes()});
+
+        // 能解析但 start_index 为 0 或索引不连续的文件与无法解析的文件一样视为损坏，加载失败，日志保持为空
+        for core in [
+            serde_json::json!({"start_index": 0, "entries": []}),
+            serde_json::json!({"start_index": u64::MAX, "entries": [entry(u64::MAX, proto::EntryType::Data, "a"), entry(0, proto::EntryType::Data, "b")]}),
+            serde_json::json!({"start_index": 1, "entries": [entry(2, proto::EntryType::Data, "a")]}),
+        ] {
+            let data = serde_json::to_vec(&core).unwrap();
+            assert!(Log::verify_reader(data.as_slice()).is_err());
+            fs::write(&filepath, data).unwrap();
+            let mut log = Log::new(1, test_dir.to_string());
+            assert!(log.reload().is_err());
+            assert_eq!((log.start_index(), log.entries().len(), log.last_index(0)), (1, 0, 0));
+        }
+
+        // 损坏的配置条目被跳过，使用更早的配置
+        let configuration = String::from_utf8(config::Config::new().to_data()).unwrap();
+        let core = serde_json::json!({"start_index": 1, "entries": [
+            entry(1, proto::EntryType::Configuration, &configuration),
+            entry(2, proto::EntryType::Configuration, "{"),
+        ]});
+        fs::write(&filepath, serde_json::to_vec(&core).unwrap()).unwrap();
+        let mut log = Log::new(1, test_dir.to_string());
+        log.reload().unwrap();
+        assert_eq!(log.entries().len(), 2);
+        assert_eq!(log.last_configuration_entry().map(|(index, _)| index), Some(1));
+
+        fs::remove_dir_all(test_dir).ok();
+    }
+
+    #[test]
+    fn test_append_data_records_timestamp() {
+        let test_dir = "./test_append_data_records_timestamp";
+        cleanup_test_dir(test_dir);
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        let mut log = Log::new(1, test_dir.to_string());
+        log.append_data(1, vec![(proto::EntryType::Data, b"a".to_vec())]);
+        let timestamp = log.entry(1).unwrap().timestamp_ms.unwrap();
+        assert!(timestamp >= before);
+
+        // 时间戳随日志落盘，Follower 追加的条目原样保留
+        log.persist();
+        let mut reloaded = Log::new(1, test_dir.to_string());
+        reloaded.reload().unwrap();
+        assert_eq!(reloaded.entry(1).unwrap().timestamp_ms, Some(timestamp));
+
+        // 旧版本写入的条目没有时间戳
+        let old: proto::LogEntry = serde_json::from_str(r#"{"term":1,"index":2,"entry_type":1,"data":[98]}"#).unwrap();
+        assert_eq!(old.timestamp_ms, None);
+        reloaded.append_entries(vec![old]);
+        assert_eq!(reloaded.entry(2).unwrap().timestamp_ms, None);
+
+        fs::remove_dir_all(test_dir).ok();
+    }
+
+    #[test]
+    fn test_read_entry_from_disk_reuses_parsed_file() {
+        let test_dir = "./test_read_entry_from_disk_reuses_parsed_file";
+        cleanup_test_dir(test_dir);
+        let mut log = Log::new(1, test_dir.to_string());
+        log.append_data(1, (1..=3).map(|i| (proto::EntryType::Data, vec![i])).collect());
+        log.persist();
+
+        // 第一次读取时解析文件，文件没有变化时之后的读取使用解析结果
+        assert_eq!(log.read_entry_from_disk(1).unwrap().data, vec![1]);
+        let version = log.disk_entries.as_ref().unwrap().0;
+        assert_eq!(log.read_entry_from_disk(3).unwrap().data, vec![3]);
+        assert_eq!(log.disk_entries.as_ref().unwrap().0, version);
+
+        // 文件重写之后重新解析
+        log.append_data(1, vec![(proto::EntryType::Data, vec![4])]);
+        assert!(log.read_entry_from_disk(4).is_none());
+        log.persist();
+        assert_eq!(log.read_entry_from_disk(4).unwrap().data, vec![4]);
+        log.truncate_suffix(2);
+        log.persist();
+        assert!(log.read_entry_from_disk(3).is_none());
+
+        fs::remove_dir_all(test_dir).ok();
+    }
+}
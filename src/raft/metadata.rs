@@ -2,13 +2,16 @@ use crate::raft::config;
 use super::logging::info;
 use serde::{Deserialize, Serialize};
 use std::clone;
+use std::collections::BTreeMap;
 use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::sync::{Mutex as TokioMutex, mpsc};
+use tokio::sync::{Mutex as TokioMutex, mpsc, oneshot};
+use tokio::io::AsyncWriteExt;
 
 use tokio::time::{sleep, Duration, interval};
-use anyhow::{Result};
+use anyhow::{anyhow, Result};
 
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -16,19 +19,39 @@ pub struct Metadata {
     pub current_term: u64,
     pub voted_for: u64,
     pub metadata_dir: String,
+    // 最近一次记录的提交索引，异步落盘，重启时只会比真实值小；用于启动时补齐已提交但未应用的条目
+    #[serde(default)]
+    pub commit_index: u64,
+    // 担任 Leader 时记录的各节点 match_index（节点 id -> 索引），尽力而为地异步落盘；
+    // 再次当选时用来初始化 next_index，减少逐条回退探测的次数
+    #[serde(default)]
+    pub peer_match_hints: BTreeMap<u64, u64>,
+    // 节点启动的次数，每次启动时加一并同步落盘；大于 1 表示这是一次重启
+    #[serde(default)]
+    pub boot_count: u64,
 }
 
 #[derive(Debug)]
 enum PersistCommand {
     UpdateTerm(u64),
     UpdateVotedFor(u64),
+    // 任期和投票一起更新，后台任务在同一条命令里应用，不会只持久化其中一个
+    UpdateTermAndVote(u64, u64),
+    UpdateCommitIndex(u64),
+    UpdatePeerMatchHints(BTreeMap<u64, u64>),
     Flush,
+    // 落盘后通过 oneshot 回执，调用方可以等待持久化真正完成
+    FlushDurable(oneshot::Sender<Result<()>>),
+    // 写入剩余的脏数据、回执后退出后台任务
+    Shutdown(oneshot::Sender<Result<()>>),
 }
 
 #[derive(Debug)]
 pub struct MetadataManager {
     metadata_cache: TokioMutex<Metadata>, // 这是内存中的缓存
     tx: mpsc::Sender<PersistCommand>,     // tx直接存储Sender
+    task: Mutex<Option<tokio::task::JoinHandle<()>>>, // 持久化任务，shutdown 时取出并等待其退出
+    current_term: AtomicU64,              // 缓存中任期的副本，供不能 await 的调用方读取
 }
 
 
@@ -38,7 +61,10 @@ impl Metadata {
         Metadata { 
             current_term: (0), 
             voted_for: (config::NONE_SERVER_ID), 
-            metadata_dir: (dir) 
+            metadata_dir: (dir),
+            commit_index: 0,
+            peer_match_hints: BTreeMap::new(),
+            boot_count: 0,
         }
     }
 
@@ -68,6 +94,84 @@ impl Metadata {
         let metadata: Metadata = serde_json::from_str(&content)?;
         Ok(metadata)
     }
+
+    // 数据目录的格式版本文件，没有该文件的目录视为版本 0（引入版本号之前的格式）
+    pub fn gen_storage_version_filepath(dir: &str) -> PathBuf {
+        let mut path = PathBuf::from(dir);
+        path.push("raft.storage_version");
+        path
+    }
+
+    pub fn load_storage_version(dir: &str) -> Result<u32> {
+        let filepath = Self::gen_storage_version_filepath(dir);
+        if !filepath.exists() {
+            return Ok(0);
+        }
+        let content = std::fs::read_to_string(&filepath)?;
+        content.trim().parse::<u32>()
+            .map_err(|e| anyhow!("invalid storage version file {}: {}", filepath.display(), e))
+    }
+
+    pub fn store_storage_version(dir: &str, version: u32) -> Result<()> {
+        let filepath = Self::gen_storage_version_filepath(dir);
+        let tmp_filepath = filepath.with_extension("storage_version.tmp");
+        std::fs::write(&tmp_filepath, version.to_string())?;
+        std::fs::rename(&tmp_filepath, &filepath)?;
+        Ok(())
+    }
+
+    // 最近一次打开数据目录的程序版本，只用于诊断，例如拒绝降级时说明目录是哪个版本写入的
+    pub fn gen_written_by_filepath(dir: &str) -> PathBuf {
+        let mut path = PathBuf::from(dir);
+        path.push("raft.written_by");
+        path
+    }
+
+    pub fn load_written_by(dir: &str) -> Option<String> {
+        std::fs::read_to_string(Self::gen_written_by_filepath(dir)).ok().map(|content| content.trim().to_string())
+    }
+
+    pub fn store_written_by(dir: &str, version: &str) -> Result<()> {
+        let filepath = Self::gen_written_by_filepath(dir);
+        let tmp_filepath = filepath.with_extension("written_by.tmp");
+        std::fs::write(&tmp_filepath, version)?;
+        std::fs::rename(&tmp_filepath, &filepath)?;
+        Ok(())
+    }
+
+    // 存储格式迁移（版本 0 -> 1）：旧版本把 metadata_dir 一起写进了 raft.metadata，
+    // 数据目录被移动后仍会写回原来的位置，这里把它改写为实际所在的目录
+    pub fn migrate_metadata_dir(dir: &str, dry_run: bool) -> Result<Vec<String>> {
+        let filepath = Self::gen_metadata_filepath(dir);
+        if !filepath.exists() {
+            return Ok(Vec::new());
+        }
+        let mut metadata = Self::load(dir)?;
+        if metadata.metadata_dir == dir {
+            return Ok(Vec::new());
+        }
+        let action = format!("rewrite metadata_dir in {} from {} to {}", filepath.display(), metadata.metadata_dir, dir);
+        if !dry_run {
+            metadata.metadata_dir = dir.to_string();
+            metadata.store()?;
+        }
+        Ok(vec![action])
+    }
+
+    // 同步写入 metadata_dir 下的 raft.metadata 并落盘，只在节点启动前使用，运行时通过 MetadataManager 持久化
+    pub fn store(&self) -> Result<()> {
+        let filepath = Self::gen_metadata_filepath(&self.metadata_dir);
+        let tmp_filepath = filepath.with_extension("metadata.tmp");
+        let mut file = std::fs::File::create(&tmp_filepath)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_filepath, &filepath)?;
+        // rename 记录在目录中，目录也要落盘，否则崩溃后看到的可能仍是旧文件
+        if let Some(dir) = filepath.parent() {
+            std::fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
 }
 
 
@@ -80,7 +184,7 @@ impl MetadataManager {
         // 这个任务需要访问 initial_metadata 的副本或者路径来写入
         let metadata_for_task = initial_metadata.clone(); // 克隆一份给异步任务使用和修改
 
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             let mut current_metadata_state = metadata_for_task; // 任务内部持有的状态
             let mut dirty = false;
             let mut periodic_flush_timer = interval(flush_interval);
@@ -101,6 +205,25 @@ impl MetadataManager {
                                     dirty = true;
                                 }
                             }
+                            PersistCommand::UpdateTermAndVote(term, id) => {
+                                if current_metadata_state.current_term != term || current_metadata_state.voted_for != id {
+                                    current_metadata_state.current_term = term;
+                                    current_metadata_state.voted_for = id;
+                                    dirty = true;
+                                }
+                            }
+                            PersistCommand::UpdateCommitIndex(index) => {
+                                if current_metadata_state.commit_index < index {
+                                    current_metadata_state.commit_index = index;
+                                    dirty = true;
+                                }
+                            }
+                            PersistCommand::UpdatePeerMatchHints(hints) => {
+                                if current_metadata_state.peer_match_hints != hints {
+                                    current_metadata_state.peer_match_hints = hints;
+                                    dirty = true;
+                                }
+                            }
                             PersistCommand::Flush => {
                                 if dirty { // 只有在脏的时候才写入
                                     if let Err(e) = Self::persist_to_disk(&current_metadata_state).await {
@@ -110,6 +233,35 @@ impl MetadataManager {
                                     }
                                 }
                             }
+                            PersistCommand::FlushDurable(ack) => {
+                                // 通道是有序的，之前发送的更新命令此时都已经应用到 current_metadata_state
+                                let result = if dirty {
+                                    let persist_result = Self::persist_to_disk(&current_metadata_state).await;
+                                    if persist_result.is_ok() {
+                                        dirty = false;
+                                    }
+                                    persist_result
+                                } else {
+                                    Ok(())
+                                };
+                                if let Err(e) = &result {
+                                    log::error!("MetadataManager task: Failed to persist metadata on FlushDurable command: {}", e);
+                                }
+                                let _ = ack.send(result);
+                            }
+                            PersistCommand::Shutdown(ack) => {
+                                let result = if dirty {
+                                    Self::persist_to_disk(&current_metadata_state).await
+                                } else {
+                                    Ok(())
+                                };
+                                if let Err(e) = &result {
+                                    log::error!("MetadataManager task: Failed to persist metadata on shutdown: {}", e);
+                                }
+                                log::info!("MetadataManager task: Shutting down persistence task.");
+                                let _ = ack.send(result);
+                                break;
+                            }
                         }
                     }
                     _ = periodic_flush_timer.tick() => {
@@ -139,9 +291,11 @@ impl MetadataManager {
         });
 
         let manager = Arc::new(MetadataManager {
+            current_term: AtomicU64::new(initial_metadata.current_term),
             // get() 方法现在需要异步获取锁
             metadata_cache: TokioMutex::new(initial_metadata), // 主线程持有的缓存，用于快速 get()
             tx: tx_cmd, // 存储 Sender
+            task: Mutex::new(Some(task)),
         });
         manager
     }
@@ -150,7 +304,12 @@ impl MetadataManager {
         let filepath = Metadata::gen_metadata_filepath(&metadata_to_persist.metadata_dir);
         log::trace!("MetadataManager: Persisting metadata to {}", filepath.display());
         let content = serde_json::to_string_pretty(metadata_to_persist)?; // 使用 pretty 方便调试
-        tokio::fs::write(&filepath, content.as_bytes()).await?; // 使用 tokio::fs
+        // 先写临时文件再 rename，崩溃时磁盘上要么是旧记录要么是新记录，不会出现写了一半的文件
+        let tmp_filepath = filepath.with_extension("metadata.tmp");
+        let mut file = tokio::fs::File::create(&tmp_filepath).await?; // 使用 tokio::fs
+        file.write_all(content.as_bytes()).await?;
+        file.sync_all().await?; // 确保数据真正写到磁盘上
+        tokio::fs::rename(&tmp_filepath, &filepath).await?;
         log::trace!("MetadataManager: Metadata persisted successfully to {}", filepath.display());
         Ok(())
     }
@@ -163,11 +322,10 @@ impl MetadataManager {
                 return;
             }
             guard.current_term = current_term;
+            self.current_term.store(current_term, Ordering::Relaxed);
         }
         // 2. 发送持久化命令
-        if let Err(e) = self.tx.send(PersistCommand::UpdateTerm(current_term)).await {
-            log::error!("MetadataManager: Failed to send UpdateTerm command: {}", e);
-        }
+        self.send(PersistCommand::UpdateTerm(current_term)).await;
     }
 
     pub async fn update_voted_for(&self, voted_for: u64) {
@@ -178,17 +336,94 @@ impl MetadataManager {
             }
             guard.voted_for = voted_for;
         }
-        if let Err(e) = self.tx.send(PersistCommand::UpdateVotedFor(voted_for)).await {
-             log::error!("MetadataManager: Failed to send UpdateVotedFor command: {}", e);
+        self.send(PersistCommand::UpdateVotedFor(voted_for)).await;
+    }
+
+    // 同时更新任期和投票对象。分两次调用时两条命令之间可能发生定期刷新，
+    // 崩溃后磁盘上会留下新任期和旧任期的投票，重启后可能在同一任期内再投一次票
+    pub async fn update_term_and_vote(&self, current_term: u64, voted_for: u64) {
+        {
+            let mut guard = self.metadata_cache.lock().await;
+            if guard.current_term == current_term && guard.voted_for == voted_for {
+                return;
+            }
+            guard.current_term = current_term;
+            guard.voted_for = voted_for;
+            self.current_term.store(current_term, Ordering::Relaxed);
+        }
+        self.send(PersistCommand::UpdateTermAndVote(current_term, voted_for)).await;
+    }
+
+    // 提交索引只增不减，随定期刷新落盘，不需要等待
+    pub async fn update_commit_index(&self, commit_index: u64) {
+        {
+            let mut guard = self.metadata_cache.lock().await;
+            if guard.commit_index >= commit_index {
+                return;
+            }
+            guard.commit_index = commit_index;
+        }
+        self.send(PersistCommand::UpdateCommitIndex(commit_index)).await;
+    }
+
+    // 整体替换各节点的 match_index 提示，随定期刷新落盘，丢失也不影响正确性
+    pub async fn update_peer_match_hints(&self, hints: BTreeMap<u64, u64>) {
+        {
+            let mut guard = self.metadata_cache.lock().await;
+            if guard.peer_match_hints == hints {
+                return;
+            }
+            guard.peer_match_hints = hints.clone();
         }
+        self.send(PersistCommand::UpdatePeerMatchHints(hints)).await;
     }
 
     // 强制将当前内存状态同步到磁盘（通过命令）
     pub async fn sync(&self) {
-        if let Err(e) = self.tx.send(PersistCommand::Flush).await {
-            log::error!("MetadataManager: Failed to send Flush command: {}", e);
+        self.send(PersistCommand::Flush).await;
+    }
+    // 与 sync 不同，等待后台任务把当前状态真正写入磁盘后才返回
+    // 用于回复确认新任期的 RPC 之前，防止崩溃后旧任期"复活"
+    pub async fn sync_durable(&self) -> Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        if self.tx.send(PersistCommand::FlushDurable(ack_tx)).await.is_err() {
+            return Self::persist_to_disk(&self.get().await).await;
+        }
+        ack_rx
+            .await
+            .map_err(|e| anyhow!("metadata persistence task dropped FlushDurable ack: {}", e))?
+    }
+
+    // 写入剩余的脏数据并等待后台任务退出，节点关闭时调用；重复调用直接返回。
+    // 之后的更新不再经过后台任务，而是在调用方直接写入磁盘
+    pub async fn shutdown(&self) -> Result<()> {
+        let Some(task) = self.task.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let result = match self.tx.send(PersistCommand::Shutdown(ack_tx)).await {
+            Ok(()) => ack_rx.await.unwrap_or_else(|e| Err(anyhow!("metadata persistence task dropped Shutdown ack: {}", e))),
+            Err(_) => Err(anyhow!("metadata persistence task exited before shutdown")),
+        };
+        task.await.map_err(|e| anyhow!("metadata persistence task failed: {}", e))?;
+        result
+    }
+
+    // 把命令交给后台任务；任务已经退出时直接写入当前的缓存，shutdown 之后的更新同样不会丢失
+    async fn send(&self, command: PersistCommand) {
+        if let Err(e) = self.tx.send(command).await {
+            let metadata = self.get().await;
+            if let Err(persist_err) = Self::persist_to_disk(&metadata).await {
+                log::error!("MetadataManager: Failed to persist {:?} after the persistence task exited: {}", e.0, persist_err);
+            }
         }
     }
+
+    // 当前任期，与 get().current_term 相同，但不需要等待锁
+    pub fn current_term(&self) -> u64 {
+        self.current_term.load(Ordering::Relaxed)
+    }
+
     // get 方法现在是 async，因为它需要 lock TokioMutex
     pub async fn get(&self) -> Metadata {
         self.metadata_cache.lock().await.clone()
@@ -282,6 +517,71 @@ mod tests {
     }
 
 
+    #[tokio::test]
+    async fn test_metadata_manager_sync_durable() {
+        let dir = tempdir().unwrap();
+        let metadata_dir_str = dir.path().to_str().unwrap().to_string();
+        // 刷新间隔设置得很长，确保数据只能通过 sync_durable 落盘
+        let manager = MetadataManager::new(Metadata::new(metadata_dir_str.clone()), Duration::from_secs(3600));
+
+        manager.update_current_term(7).await;
+        manager.update_voted_for(3).await;
+        manager.sync_durable().await.expect("sync_durable failed");
+
+        // 无需等待，返回时数据应已在磁盘上
+        let reloaded = Metadata::load(&metadata_dir_str).unwrap();
+        assert_eq!(reloaded.current_term, 7);
+        assert_eq!(reloaded.voted_for, 3);
+
+        // 没有脏数据时也应立即成功返回
+        manager.sync_durable().await.expect("sync_durable on clean metadata failed");
+    }
+
+    #[tokio::test]
+    async fn test_metadata_manager_shutdown_flushes_and_stops_task() {
+        let dir = tempdir().unwrap();
+        let metadata_dir_str = dir.path().to_str().unwrap().to_string();
+        let manager = MetadataManager::new(Metadata::new(metadata_dir_str.clone()), Duration::from_secs(3600));
+
+        // 刷新间隔很长，数据只能在 shutdown 时落盘
+        manager.update_term_and_vote(4, 2).await;
+        manager.update_commit_index(9).await;
+        manager.shutdown().await.expect("shutdown failed");
+        let reloaded = Metadata::load(&metadata_dir_str).unwrap();
+        assert_eq!((reloaded.current_term, reloaded.voted_for, reloaded.commit_index), (4, 2, 9));
+        assert!(manager.task.lock().unwrap().is_none());
+        manager.shutdown().await.expect("second shutdown failed");
+
+        // 关闭之后的更新直接写入磁盘
+        manager.update_current_term(5).await;
+        assert_eq!(Metadata::load(&metadata_dir_str).unwrap().current_term, 5);
+        manager.update_voted_for(3).await;
+        manager.sync_durable().await.expect("sync_durable after shutdown failed");
+        assert_eq!(Metadata::load(&metadata_dir_str).unwrap().voted_for, 3);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_manager_update_term_and_vote() {
+        let dir = tempdir().unwrap();
+        let metadata_dir_str = dir.path().to_str().unwrap().to_string();
+        let manager = MetadataManager::new(Metadata::new(metadata_dir_str.clone()), Duration::from_secs(3600));
+
+        manager.update_term_and_vote(5, 2).await;
+        let cached = manager.get().await;
+        assert_eq!((cached.current_term, cached.voted_for), (5, 2));
+        manager.sync_durable().await.expect("sync_durable failed");
+        let reloaded = Metadata::load(&metadata_dir_str).unwrap();
+        assert_eq!((reloaded.current_term, reloaded.voted_for), (5, 2));
+
+        // 进入新任期并清空投票
+        manager.update_term_and_vote(6, config::NONE_SERVER_ID).await;
+        manager.sync_durable().await.expect("sync_durable failed");
+        let reloaded = Metadata::load(&metadata_dir_str).unwrap();
+        assert_eq!((reloaded.current_term, reloaded.voted_for), (6, config::NONE_SERVER_ID));
+        // 临时文件已经被 rename 掉
+        assert!(!Metadata::gen_metadata_filepath(&metadata_dir_str).with_extension("metadata.tmp").exists());
+    }
+
     #[tokio::test]
     async fn test_metadata_manager_performance_refactored() {
         let dir = tempdir().unwrap();
@@ -0,0 +1,169 @@
+use crate::raft::{config, proto};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// 节点的运行指标，各模块共享同一个 Arc<Metrics> 并直接累加计数
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub verification_runs: AtomicU64,     // 后台校验执行的轮数
+    pub corruption_detected: AtomicU64,   // 后台校验发现的损坏文件数
+    pub log_bytes: AtomicU64,             // 最近一次统计时日志文件的大小
+    pub snapshot_bytes: AtomicU64,        // 最近一次统计时快照目录的总大小
+    pub disk_quota_compactions: AtomicU64, // 因超过磁盘配额触发的压缩次数
+    pub disk_quota_rejections: AtomicU64,  // 因超过磁盘配额被拒绝的 Propose 数
+    pub follow_ups_proposed: AtomicU64,    // 状态机提交、由 Leader Propose 成功的后续命令数
+    pub follow_ups_dropped: AtomicU64,     // 因队列已满或失去领导权被丢弃的后续命令数
+    pub snapshot_bytes_sent: AtomicU64,    // Leader 发送给 Follower 并被确认的快照字节数，包括中途失败的传输
+    pub snapshots_sent: AtomicU64,         // 完整发送的快照次数
+    pub snapshot_send_throughput: AtomicU64, // 最近一次完整发送快照的吞吐量，字节/秒
+    pub apply_throttled: AtomicU64,        // Follower 应用条目因限速暂停的次数
+    pub proposals_shed: AtomicU64,         // 提交延迟超过 SLO 时被 Leader 减载拒绝的提议数
+    pub voter_gap: AtomicU64,              // Leader 最近一次心跳时最后一条日志与最慢投票节点 match_index 的差距
+    pub divergence_alarms: AtomicU64,      // 日志分歧告警的次数
+    pub max_clock_skew_us: AtomicU64,      // Leader 最近一次心跳时各 Peer 时钟偏差上界的最大值
+    pub lease_invalidations: AtomicU64,    // 时钟偏差超过上限导致读租约失效的次数
+    // 按条目类型（下标为 EntryType 的值）统计的 Leader 收到条目到提交、到应用的延迟
+    commit_latency: [Histogram; 4],
+    apply_latency: [Histogram; 4],
+}
+
+// Metrics 在某一时刻的只读拷贝
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub verification_runs: u64,
+    pub corruption_detected: u64,
+    pub log_bytes: u64,
+    pub snapshot_bytes: u64,
+    pub disk_quota_compactions: u64,
+    pub disk_quota_rejections: u64,
+    pub follow_ups_proposed: u64,
+    pub follow_ups_dropped: u64,
+    pub snapshot_bytes_sent: u64,
+    pub snapshots_sent: u64,
+    pub snapshot_send_throughput: u64,
+    pub apply_throttled: u64,
+    pub proposals_shed: u64,
+    pub voter_gap: u64,
+    pub divergence_alarms: u64,
+    pub max_clock_skew_us: u64,
+    pub lease_invalidations: u64,
+}
+
+// 固定分桶的延迟直方图，桶的上界见 config::LATENCY_BUCKET_BOUNDS_US
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; config::LATENCY_BUCKET_BOUNDS_US.len() + 1],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    pub fn record(&self, latency: Duration) {
+        let us = latency.as_micros() as u64;
+        let bucket = config::LATENCY_BUCKET_BOUNDS_US.iter()
+            .position(|bound| us <= *bound)
+            .unwrap_or(config::LATENCY_BUCKET_BOUNDS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    fn to_proto(&self, entry_type: proto::EntryType, stage: proto::LatencyStage) -> proto::LatencyHistogram {
+        proto::LatencyHistogram {
+            entry_type: entry_type as i32,
+            stage: stage as i32,
+            bucket_upper_bounds_us: config::LATENCY_BUCKET_BOUNDS_US.to_vec(),
+            bucket_counts: self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum_us: self.sum_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+const ENTRY_TYPES: [proto::EntryType; 4] = [
+    proto::EntryType::Configuration, proto::EntryType::Data, proto::EntryType::Noop, proto::EntryType::DataChunk,
+];
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            verification_runs: self.verification_runs.load(Ordering::Relaxed),
+            corruption_detected: self.corruption_detected.load(Ordering::Relaxed),
+            log_bytes: self.log_bytes.load(Ordering::Relaxed),
+            snapshot_bytes: self.snapshot_bytes.load(Ordering::Relaxed),
+            disk_quota_compactions: self.disk_quota_compactions.load(Ordering::Relaxed),
+            disk_quota_rejections: self.disk_quota_rejections.load(Ordering::Relaxed),
+            follow_ups_proposed: self.follow_ups_proposed.load(Ordering::Relaxed),
+            follow_ups_dropped: self.follow_ups_dropped.load(Ordering::Relaxed),
+            snapshot_bytes_sent: self.snapshot_bytes_sent.load(Ordering::Relaxed),
+            snapshots_sent: self.snapshots_sent.load(Ordering::Relaxed),
+            snapshot_send_throughput: self.snapshot_send_throughput.load(Ordering::Relaxed),
+            apply_throttled: self.apply_throttled.load(Ordering::Relaxed),
+            proposals_shed: self.proposals_shed.load(Ordering::Relaxed),
+            voter_gap: self.voter_gap.load(Ordering::Relaxed),
+            divergence_alarms: self.divergence_alarms.load(Ordering::Relaxed),
+            max_clock_skew_us: self.max_clock_skew_us.load(Ordering::Relaxed),
+            lease_invalidations: self.lease_invalidations.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn record_commit_latency(&self, entry_type: proto::EntryType, latency: Duration) {
+        self.commit_latency[entry_type as usize].record(latency);
+    }
+
+    pub fn record_apply_latency(&self, entry_type: proto::EntryType, latency: Duration) {
+        self.apply_latency[entry_type as usize].record(latency);
+    }
+
+    // 一次完整的快照发送，耗时不足 1 毫秒时按 1 毫秒计算吞吐量
+    pub fn record_snapshot_sent(&self, bytes: u64, elapsed: Duration) {
+        let millis = elapsed.as_millis().max(1) as u64;
+        self.snapshots_sent.fetch_add(1, Ordering::Relaxed);
+        self.snapshot_send_throughput.store(bytes.saturating_mul(1000) / millis, Ordering::Relaxed);
+    }
+
+    // 所有条目类型和阶段的延迟直方图
+    pub fn latency_stats(&self) -> Vec<proto::LatencyHistogram> {
+        let mut histograms = Vec::with_capacity(ENTRY_TYPES.len() * 2);
+        for entry_type in ENTRY_TYPES {
+            histograms.push(self.commit_latency[entry_type as usize].to_proto(entry_type, proto::LatencyStage::Commit));
+            histograms.push(self.apply_latency[entry_type as usize].to_proto(entry_type, proto::LatencyStage::Apply));
+        }
+        histograms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_histogram_buckets() {
+        let metrics = Metrics::new();
+        metrics.record_commit_latency(proto::EntryType::Data, Duration::from_micros(300));
+        metrics.record_commit_latency(proto::EntryType::Data, Duration::from_micros(1_000));
+        metrics.record_commit_latency(proto::EntryType::Data, Duration::from_secs(60));
+        metrics.record_apply_latency(proto::EntryType::Noop, Duration::from_millis(3));
+
+        let stats = metrics.latency_stats();
+        assert_eq!(stats.len(), 8);
+        let data_commit = stats.iter()
+            .find(|h| h.entry_type == proto::EntryType::Data as i32 && h.stage == proto::LatencyStage::Commit as i32)
+            .unwrap();
+        assert_eq!(data_commit.count, 3);
+        assert_eq!(data_commit.bucket_counts.len(), data_commit.bucket_upper_bounds_us.len() + 1);
+        assert_eq!(data_commit.bucket_counts[0], 1);
+        assert_eq!(data_commit.bucket_counts[1], 1);
+        assert_eq!(*data_commit.bucket_counts.last().unwrap(), 1);
+
+        let noop_apply = stats.iter()
+            .find(|h| h.entry_type == proto::EntryType::Noop as i32 && h.stage == proto::LatencyStage::Apply as i32)
+            .unwrap();
+        assert_eq!(noop_apply.count, 1);
+        assert_eq!(noop_apply.sum_us, 3_000);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::raft::{config, metadata, snapshot, version};
+use super::logging::*;
+use anyhow::{anyhow, Result};
+
+// 数据目录的格式迁移：格式版本记录在元数据目录的 raft.storage_version 中，
+// 启动时按顺序执行从当前版本到 config::STORAGE_VERSION 之间的所有迁移步骤。
+// 每个版本的步骤全部成功后才写入新版本号，中途崩溃时下次启动会重新执行该版本的步骤，
+// 所以每个步骤都必须是幂等的
+
+pub struct MigrationContext<'a> {
+    pub snapshot_dir: &'a str,
+    pub metadata_dir: &'a str,
+    pub dry_run: bool, // 只列出将要执行的操作，不修改任何文件
+}
+
+pub struct Migration {
+    pub to_version: u32,             // 执行后数据目录达到的版本
+    pub description: &'static str,
+    pub run: fn(&MigrationContext) -> Result<Vec<String>>, // 返回执行（或 dry_run 时将要执行）的操作
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub dry_run: bool,
+    pub actions: Vec<String>,
+}
+
+// 按版本从低到高排列，新的格式变化在这里追加步骤并提升 config::STORAGE_VERSION
+pub fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            to_version: 1,
+            description: "rewrite legacy snapshot metadata without local paths and with checksums",
+            run: |ctx| snapshot::migrate_legacy_metadata(ctx.snapshot_dir, ctx.dry_run),
+        },
+        Migration {
+            to_version: 1,
+            description: "point raft.metadata at the directory it actually lives in",
+            run: |ctx| metadata::Metadata::migrate_metadata_dir(ctx.metadata_dir, ctx.dry_run),
+        },
+    ]
+}
+
+// 把数据目录升级到当前版本；目录版本比程序支持的版本更新时拒绝启动
+pub fn migrate(snapshot_dir: &str, metadata_dir: &str, dry_run: bool) -> Result<MigrationReport> {
+    let from_version = metadata::Metadata::load_storage_version(metadata_dir)?;
+    if from_version > config::STORAGE_VERSION {
+        let written_by = metadata::Metadata::load_written_by(metadata_dir).unwrap_or_else(|| "an unknown version".to_string());
+        return Err(anyhow!(
+            "data dir {} has storage version {} (written by {}), newer than the supported version {} of {}; refusing to downgrade",
+            metadata_dir, from_version, written_by, config::STORAGE_VERSION, version::describe()
+        ));
+    }
+
+    let ctx = MigrationContext { snapshot_dir, metadata_dir, dry_run };
+    let mut report = MigrationReport { from_version, to_version: from_version, dry_run, actions: Vec::new() };
+    for version in from_version + 1..=config::STORAGE_VERSION {
+        for migration in migrations().iter().filter(|m| m.to_version == version) {
+            let actions = (migration.run)(&ctx)
+                .map_err(|e| anyhow!("storage migration to version {} ({}) failed: {}", version, migration.description, e))?;
+            for action in &actions {
+                info!("Storage migration to version {}{}: {}", version, if dry_run { " (dry run)" } else { "" }, action);
+            }
+            report.actions.extend(actions);
+        }
+        if !dry_run {
+            metadata::Metadata::store_storage_version(metadata_dir, version)?;
+        }
+        report.to_version = version;
+    }
+    if !dry_run {
+        metadata::Metadata::store_written_by(metadata_dir, &version::describe())?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_migrate_legacy_data_dir() {
+        let snapshot_dir = tempdir().unwrap();
+        let metadata_dir = tempdir().unwrap();
+        let snapshot_dir_str = snapshot_dir.path().to_str().unwrap();
+        let metadata_dir_str = metadata_dir.path().to_str().unwrap();
+
+        // 旧格式：元数据中带有 snapshot_dir，没有 checksum；raft.metadata 指向另一个目录
+        std::fs::write(format!("{}/raft-10-2.snapshot", snapshot_dir_str), b"state").unwrap();
+        let legacy_meta = r#"{"last_included_index":10,"last_included_term":2,"configuration":null,"snapshot_dir":"/old/place"}"#;
+        let legacy_meta_filepath = format!("{}/raft-10-2.snapshot.metadata", snapshot_dir_str);
+        std::fs::write(&legacy_meta_filepath, legacy_meta).unwrap();
+        std::fs::write(
+            metadata::Metadata::gen_metadata_filepath(metadata_dir_str),
+            r#"{"current_term":3,"voted_for":1,"metadata_dir":"/old/place"}"#,
+        ).unwrap();
+
+        // dry run 只报告，不修改文件和版本号
+        let report = migrate(snapshot_dir_str, metadata_dir_str, true).unwrap();
+        assert_eq!((report.from_version, report.to_version), (0, config::STORAGE_VERSION));
+        assert_eq!(report.actions.len(), 2);
+        assert_eq!(std::fs::read_to_string(&legacy_meta_filepath).unwrap(), legacy_meta);
+        assert_eq!(metadata::Metadata::load_storage_version(metadata_dir_str).unwrap(), 0);
+
+        let report = migrate(snapshot_dir_str, metadata_dir_str, false).unwrap();
+        assert_eq!(report.actions.len(), 2);
+        assert_eq!(metadata::Metadata::load_storage_version(metadata_dir_str).unwrap(), config::STORAGE_VERSION);
+        let mut manager = snapshot::SnapshotManager::new(snapshot_dir_str.to_string());
+        manager.reload_metadata();
+        assert_eq!(manager.meta().checksum, snapshot::checksum_file(&format!("{}/raft-10-2.snapshot", snapshot_dir_str)).unwrap());
+        assert_eq!(metadata::Metadata::load(metadata_dir_str).unwrap().metadata_dir, metadata_dir_str);
+
+        // 已经是当前版本时不再执行任何步骤
+        assert!(migrate(snapshot_dir_str, metadata_dir_str, false).unwrap().actions.is_empty());
+
+        // 更新版本的目录拒绝处理
+        assert_eq!(metadata::Metadata::load_written_by(metadata_dir_str), Some(version::describe()));
+        metadata::Metadata::store_storage_version(metadata_dir_str, config::STORAGE_VERSION + 1).unwrap();
+        metadata::Metadata::store_written_by(metadata_dir_str, "9.9.9 (abcdef)").unwrap();
+        let err = migrate(snapshot_dir_str, metadata_dir_str, false).unwrap_err().to_string();
+        assert!(err.contains("written by 9.9.9 (abcdef)"), "{}", err);
+        assert_eq!(metadata::Metadata::load_written_by(metadata_dir_str).as_deref(), Some("9.9.9 (abcdef)"));
+    }
+}
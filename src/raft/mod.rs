@@ -10,6 +10,40 @@ pub mod snapshot;
 pub mod util;
 pub mod state_machine;
 pub mod rpc;
+pub mod replay;
+pub mod client;
+pub mod clock;
+pub mod events;
+pub mod metrics;
+pub mod verify;
+pub mod group;
+pub mod audit;
+pub mod command_audit;
+pub mod crash;
+pub mod config_history;
+pub mod breaker;
+pub mod storage;
+pub mod migration;
+pub mod placement;
+pub mod status_page;
+pub mod archive;
+pub mod decommission;
+pub mod codec;
+pub mod bench;
+pub mod sanity;
+pub mod invariants;
+pub mod partition;
+pub mod version;
+pub mod bootstrap;
+pub mod rate_limit;
+pub mod sink;
+pub mod doctor;
+pub mod compress;
+pub mod fairness;
+pub mod export;
+pub mod shedding;
+pub mod zone;
+pub mod subscription;
 pub extern crate log as logging;
 
 pub mod lib;
\ No newline at end of file
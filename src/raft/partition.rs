@@ -0,0 +1,47 @@
+use std::collections::BTreeSet;
+
+// 测试用的网络分区：被屏蔽的节点发来的请求直接拒绝，发往它们的请求不再发送，
+// 集成测试和混沌测试可以通过 SetPartition RPC 按脚本制造分区，而不需要修改系统防火墙。
+// 分区只影响 Raft 节点之间的通信，客户端和管理 RPC 不受影响
+
+#[derive(Debug, Clone, Default)]
+pub struct Partition {
+    blocked: BTreeSet<u64>,
+}
+
+impl Partition {
+    // 用新的集合替换当前被屏蔽的节点，空集合表示恢复全部通信
+    pub fn set(&mut self, blocked: impl IntoIterator<Item = u64>) {
+        self.blocked = blocked.into_iter().collect();
+    }
+
+    pub fn heal(&mut self) {
+        self.blocked.clear();
+    }
+
+    pub fn blocks(&self, server_id: u64) -> bool {
+        self.blocked.contains(&server_id)
+    }
+
+    pub fn blocked(&self) -> Vec<u64> {
+        self.blocked.iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_heal() {
+        let mut partition = Partition::default();
+        assert!(!partition.blocks(2));
+        partition.set([3, 2, 3]);
+        assert!(partition.blocks(2) && partition.blocks(3) && !partition.blocks(1));
+        assert_eq!(partition.blocked(), vec![2, 3]);
+        partition.set([4]);
+        assert_eq!(partition.blocked(), vec![4]);
+        partition.heal();
+        assert!(partition.blocked().is_empty());
+    }
+}
@@ -1,5 +1,7 @@
 use tonic::server;
 use crate::raft::config::{self, ConfigState};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
 
 #[derive(Debug, Default, Clone)]
@@ -12,11 +14,157 @@ pub struct Peer {
     pub next_index: u64,
     /// 该节点已经成功匹配日志的最高索引，用于跟踪日志同步的进度和状态，确保Leader能够了解各子节点的日志情况 
     pub match_index: u64,
+    /// 该节点在最近一次 AppendEntries 响应中报告的 last_applied，尚未收到响应时为0
+    pub last_applied: u64,
     /// 该节点是否已经授予当前Leader投票权，在选举过程中，Leader需要获得大多数节点的投票才能当选
     pub vote_granted: bool,
     /// 管理集群成员的动态变换等情况
     pub config_state: config::ConfigState,
+    /// 最近一次成功收到该节点RPC响应的时间，用于Leader检查是否还能联系到多数派
+    pub last_contact: Option<Instant>,
+    /// Leader向该节点复制数据的进度状态，用于避免对同一节点重复发起快照传输
+    pub progress: ProgressState,
+    /// 落后量的变化趋势，用于识别持续变慢的节点
+    pub lag: LagTracker,
+    /// 最近发往该节点的 RPC 往返时间，用于 Leader 位置建议
+    pub rtt: RttWindow,
+    /// 回退探测已经退到 Leader 日志起点仍被拒绝，只能通过快照补齐；对方确认匹配之前不再回退
+    pub snapshot_required: bool,
+    /// 最近 NEXT_INDEX_HISTORY_LEN 次 next_index 的取值，从旧到新
+    pub next_index_history: VecDeque<u64>,
+    /// 根据 AppendEntries 往返携带的时间戳估计的时钟偏差
+    pub clock_skew: SkewWindow,
+}
+
+/// 最近 PEER_RTT_SAMPLES 次 RPC 往返时间的滑动窗口
+#[derive(Debug, Default, Clone)]
+pub struct RttWindow {
+    samples: VecDeque<Duration>,
+}
+
+impl RttWindow {
+    pub fn record(&mut self, rtt: Duration) {
+        if self.samples.len() == config::PEER_RTT_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples.len()
+    }
 
+    pub fn median(&self) -> Option<Duration> {
+        let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+        sorted.sort();
+        sorted.get(sorted.len() / 2).copied()
+    }
+}
+
+/// 最近 CLOCK_SKEW_SAMPLES 次时钟偏差采样，每次采样为 (偏差, 往返时间, 采样时间)，单位微秒，偏差 = 对方时钟 - 本地时钟。
+/// 对方的时间戳假定落在往返的中点，单次采样的误差不超过往返时间的一半，所以取往返时间最短的采样作为估计
+#[derive(Debug, Default, Clone)]
+pub struct SkewWindow {
+    samples: VecDeque<(i64, u64, Instant)>,
+}
+
+impl SkewWindow {
+    /// 由请求发出时的本地时间、对方收到请求时的时间和收到响应时的本地时间记录一次采样，now 用于采样过期
+    pub fn observe(&mut self, sent_us: u64, remote_us: u64, received_us: u64, now: Instant) {
+        let rtt_us = received_us.saturating_sub(sent_us);
+        let offset_us = remote_us as i64 - (sent_us + rtt_us / 2) as i64;
+        if self.samples.len() == config::CLOCK_SKEW_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((offset_us, rtt_us, now));
+    }
+
+    /// 丢弃早于 max_age 的采样，节点联系不上时它的偏差估计随之消失
+    pub fn expire(&mut self, now: Instant, max_age: Duration) {
+        while self.samples.front().is_some_and(|(_, _, at)| now.saturating_duration_since(*at) > max_age) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// 往返时间最短的采样，没有采样时为 None
+    pub fn estimate(&self) -> Option<(i64, u64)> {
+        self.samples.iter().min_by_key(|(_, rtt_us, _)| *rtt_us).map(|(offset_us, rtt_us, _)| (*offset_us, *rtt_us))
+    }
+
+    /// 偏差绝对值的上界：估计值加上测量误差
+    pub fn bound_us(&self) -> Option<u64> {
+        self.estimate().map(|(offset_us, rtt_us)| offset_us.unsigned_abs() + rtt_us / 2)
+    }
+}
+
+/// 慢节点检测：按固定间隔采样该节点的落后量（Leader 最后索引 - match_index），
+/// 连续多次采样都在快速增长时认为节点持续变慢（例如磁盘写入停顿），落后量开始下降后恢复
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LagTracker {
+    /// 上一次采样的落后量和时间
+    last_sample: Option<(u64, Instant)>,
+    /// 落后量连续快速增长的采样次数
+    growing_samples: u32,
+    /// 当前是否被视为慢节点
+    pub slow: bool,
+    /// 最近一次向该节点发送日志条目的时间，慢节点按更长的间隔批量发送
+    pub last_batch_at: Option<Instant>,
+}
+
+impl LagTracker {
+    /// 记录一次落后量采样，返回慢节点状态是否因此发生变化
+    pub fn observe(&mut self, lag: u64, now: Instant) -> bool {
+        let (last_lag, sampled_at) = match self.last_sample {
+            Some((_, sampled_at)) if now.saturating_duration_since(sampled_at) < config::SLOW_PEER_SAMPLE_INTERVAL => return false,
+            Some(sample) => sample,
+            None => {
+                self.last_sample = Some((lag, now));
+                return false;
+            }
+        };
+        self.last_sample = Some((lag, now));
+
+        let elapsed = now.saturating_duration_since(sampled_at).as_secs_f64();
+        let growth_rate = (lag as f64 - last_lag as f64) / elapsed;
+        let recovering = lag < config::SLOW_PEER_MIN_LAG || growth_rate < 0.0;
+        if lag >= config::SLOW_PEER_MIN_LAG && growth_rate > config::SLOW_PEER_LAG_GROWTH_RATE {
+            self.growing_samples += 1;
+        } else if recovering {
+            self.growing_samples = 0;
+        }
+
+        if !self.slow && self.growing_samples >= config::SLOW_PEER_SAMPLES {
+            self.slow = true;
+            return true;
+        }
+        if self.slow && recovering {
+            self.slow = false;
+            return true;
+        }
+        false
+    }
+
+    /// 慢节点距离上一批日志是否已经超过批量发送间隔
+    pub fn batch_due(&self, now: Instant) -> bool {
+        self.last_batch_at.is_none_or(|t| now.saturating_duration_since(t) >= config::SLOW_PEER_BATCH_INTERVAL)
+    }
+}
+
+/// Leader视角下单个节点的复制进度
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressState {
+    /// 正在探测该节点的日志匹配位置
+    #[default]
+    Probe,
+    /// 日志已经匹配，正常复制
+    Replicate,
+    /// 正在向该节点传输快照
+    Snapshot {
+        /// 正在传输的快照的 last_included_index
+        last_included_index: u64,
+        /// 传输开始的时间，超过一定时间仍未完成视为传输已失效
+        started_at: Instant,
+    },
 }
 
 impl Peer {
@@ -26,10 +174,70 @@ impl Peer {
             addr: server_addr,
             next_index: 1,
             match_index: 0,
+            last_applied: 0,
             vote_granted: false,
             config_state: config::ConfigState::new(),
+            last_contact: None,
+            progress: ProgressState::Probe,
+            lag: LagTracker::default(),
+            rtt: RttWindow::default(),
+            snapshot_required: false,
+            next_index_history: VecDeque::new(),
+            clock_skew: SkewWindow::default(),
+        }
+    }
+
+    /// 修改 next_index 并记入历史，取值不变时不记录
+    pub fn set_next_index(&mut self, next_index: u64) {
+        if next_index == self.next_index && !self.next_index_history.is_empty() {
+            return;
+        }
+        self.next_index = next_index;
+        if self.next_index_history.len() == config::NEXT_INDEX_HISTORY_LEN {
+            self.next_index_history.pop_front();
+        }
+        self.next_index_history.push_back(next_index);
+    }
+
+    /// 记录对方确认已匹配到 index，match_index 只前进不后退，next_index 随之更新为 match_index + 1。
+    /// 心跳和乱序到达的旧响应确认的位置可能低于已知的 match_index，这时保持原值并返回 false
+    pub fn advance_match_index(&mut self, index: u64) -> bool {
+        let advanced = index >= self.match_index;
+        self.match_index = self.match_index.max(index);
+        self.set_next_index(self.match_index + 1);
+        if advanced {
+            self.snapshot_required = false;
+        }
+        advanced
+    }
+
+    /// 对方拒绝了 next_index - 1 处的匹配，回退一条继续探测，返回之后是否需要快照。
+    /// next_index 不低于 max(1, log_start_index)：已经在日志起点被拒绝时，对方缺少的条目只在快照里，
+    /// 这时标记 snapshot_required 而不是继续回退，避免在起点和快照边界之间来回抖动
+    pub fn probe_back(&mut self, log_start_index: u64) -> bool {
+        // 已确认匹配的位置不会再冲突，过期的失败响应不能把 next_index 回退到 match_index 之前
+        if self.next_index <= self.match_index + 1 {
+            return self.snapshot_required;
+        }
+        let floor = log_start_index.max(1);
+        if self.next_index > floor {
+            self.set_next_index(self.next_index - 1);
+        } else if log_start_index > 1 {
+            self.set_next_index(floor);
+            self.snapshot_required = true;
+        }
+        self.snapshot_required
+    }
+
+    /// 是否已有一个针对 last_included_index 的快照传输正在进行且尚未超时
+    pub fn snapshot_in_flight(&self, last_included_index: u64, now: Instant, timeout: Duration) -> bool {
+        match self.progress {
+            ProgressState::Snapshot { last_included_index: in_flight_index, started_at } => {
+                in_flight_index == last_included_index && now.saturating_duration_since(started_at) < timeout
+            }
+            _ => false,
         }
-    } 
+    }
 }
 
 
@@ -47,7 +255,7 @@ impl PeerManager {
         // 当新节点加入时，领导者会调用该方法将新节点添加到PeerManager，并且纳入集群管理范围
         // 设置初始的next_index为当前领导人最后的日志条目索引+1，以便新节点能够从何时的位置开始接受和同步信息
         for peer in new_peers.iter_mut() {
-            peer.next_index = last_log_index + 1;
+            peer.set_next_index(last_log_index + 1);
         }
         self.peers.extend(new_peers);
     }
@@ -80,6 +288,16 @@ impl PeerManager {
             .iter_mut()
             .find(|peer| peer.id == server_id)
     }
+    // 同一个id的节点更换了地址，返回是否发生了变化
+    pub fn update_addr(&mut self, server_id: u64, new_addr: &str) -> bool {
+        match self.peer(server_id) {
+            Some(peer) if peer.addr != new_addr => {
+                peer.addr = new_addr.to_string();
+                true
+            }
+            _ => false,
+        }
+    }
     pub fn contains(&self, server_id: u64) -> bool {
         self.peers
             .iter()
@@ -93,18 +311,20 @@ impl PeerManager {
             .for_each(|peer| peer.vote_granted = false);
     }
 
+    /// 新旧配置各自多数派都已复制到的最大索引，取两者中较小的一个；
+    /// 某个配置没有任何成员时不构成约束，两个配置都没有成员时返回 None
     pub fn quoram_match_index(
         &self,
         leader_config_state: &config::ConfigState,
         leader_last_index: u64,
-    ) -> u64 {
+    ) -> Option<u64> {
         // 无论是新旧集群节点，都可以进行联合共识
         fn get_quorum_match_index<F>(
             peers: &Vec<Peer>, 
             leader_last_index: u64,
             is_peer_in_config: F,
             is_leader_in_this_config: bool,
-        ) -> u64 
+        ) -> Option<u64>
         where F: Fn(&Peer) -> bool,
         {
             let mut match_indexes: Vec<u64> = Vec::new();
@@ -121,10 +341,10 @@ impl PeerManager {
             //     .for_each(|x|print!("{} ", *x));
             // println!("");
             if match_indexes.is_empty() {
-                return std::u64::MAX;
+                return None;
             }
             match_indexes.sort_unstable();
-            *match_indexes.get((match_indexes.len() - 1) / 2).unwrap()
+            match_indexes.get((match_indexes.len() - 1) / 2).copied()
         }
 
         let new_quorum_match_index = get_quorum_match_index(
@@ -142,7 +362,72 @@ impl PeerManager {
         // 测试用的
         // println!("新的中间值{}, 旧的中间值{}", new_quorum_match_index, old_quorum_match_index);
         
-        std::cmp::min(new_quorum_match_index, old_quorum_match_index)
+        match (new_quorum_match_index, old_quorum_match_index) {
+            (Some(new_index), Some(old_index)) => Some(std::cmp::min(new_index, old_index)),
+            (index, None) | (None, index) => index,
+        }
+    }
+
+    // check-quorum：Leader在within时间内是否与新旧配置的多数派都有过通信
+    pub fn quorum_active(
+        &self,
+        leader_config_state: &config::ConfigState,
+        now: Instant,
+        within: Duration,
+    ) -> bool {
+        self.quorum_matches(leader_config_state, |peer| peer.last_contact.is_some_and(|t| now.saturating_duration_since(t) < within))
+    }
+
+    // 不计慢节点时新旧配置是否仍然都有多数派，只有这时才能降低慢节点的复制优先级
+    pub fn quorum_without_slow(&self, leader_config_state: &config::ConfigState) -> bool {
+        self.quorum_matches(leader_config_state, |peer| !peer.lag.slow)
+    }
+
+    // ReadIndex：Leader在since之后是否收到了新旧配置多数派的回应
+    pub fn quorum_contacted_since(
+        &self,
+        leader_config_state: &config::ConfigState,
+        since: Instant,
+    ) -> bool {
+        self.quorum_matches(leader_config_state, |peer| peer.last_contact.is_some_and(|t| t >= since))
+    }
+
+    fn quorum_matches(
+        &self,
+        leader_config_state: &config::ConfigState,
+        is_active: impl Fn(&Peer) -> bool,
+    ) -> bool {
+        let mut total_new_servers = 0;
+        let mut active_new_servers = 0;
+        let mut total_old_servers = 0;
+        let mut active_old_servers = 0;
+
+        if leader_config_state.newing {
+            total_new_servers += 1;
+            active_new_servers += 1;
+        }
+        if leader_config_state.olding {
+            total_old_servers += 1;
+            active_old_servers += 1;
+        }
+        for peer in self.peers.iter() {
+            if peer.config_state.newing {
+                total_new_servers += 1;
+                if is_active(peer) {
+                    active_new_servers += 1;
+                }
+            }
+            if peer.config_state.olding {
+                total_old_servers += 1;
+                if is_active(peer) {
+                    active_old_servers += 1;
+                }
+            }
+        }
+
+        let new_servers_quorum = total_new_servers == 0 || active_new_servers * 2 > total_new_servers;
+        let old_servers_quorum = total_old_servers == 0 || active_old_servers * 2 > total_old_servers;
+        new_servers_quorum && old_servers_quorum
     }
 
     pub fn quorum_vote_granted(
@@ -204,8 +489,16 @@ mod tests {
             addr:format!("127.0.0.1:{}", 9000+id),
             next_index: 1,
             match_index,
+            last_applied: 0,
             vote_granted:false,
             config_state: ConfigState {newing, olding},
+            last_contact: None,
+            progress: ProgressState::Probe,
+            lag: LagTracker::default(),
+            rtt: RttWindow::default(),
+            snapshot_required: false,
+            next_index_history: VecDeque::new(),
+            clock_skew: SkewWindow::default(),
         }
     }
     
@@ -217,16 +510,32 @@ mod tests {
             addr: "127.0.0.1:9001".to_string(),
             next_index: 3, // Will be overwritten by add
             match_index: 2,
+            last_applied: 0,
             vote_granted: false,
             config_state: ConfigState::new(), // Uses the mock/local ConfigState::new
+            last_contact: None,
+            progress: ProgressState::Probe,
+            lag: LagTracker::default(),
+            rtt: RttWindow::default(),
+            snapshot_required: false,
+            next_index_history: VecDeque::new(),
+            clock_skew: SkewWindow::default(),
         };
         let peer2 = Peer {
             id: 2,
             addr: "127.0.0.1:9002".to_string(),
             next_index: 2, // Will be overwritten by add
             match_index: 2,
+            last_applied: 0,
             vote_granted: false,
             config_state: ConfigState::new(), // Uses the mock/local ConfigState::new
+            last_contact: None,
+            progress: ProgressState::Probe,
+            lag: LagTracker::default(),
+            rtt: RttWindow::default(),
+            snapshot_required: false,
+            next_index_history: VecDeque::new(),
+            clock_skew: SkewWindow::default(),
         };
         peer_manager.add(vec![peer1, peer2.clone()], 5); // last_log_index = 5
         // println!("{:?}", peer_manager); // For debugging
@@ -238,6 +547,192 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_snapshot_in_flight() {
+        let mut peer = make_test_peer(1, 0, true, false);
+        let now = Instant::now();
+        let timeout = Duration::from_secs(60);
+        assert!(!peer.snapshot_in_flight(10, now, timeout));
+
+        peer.progress = ProgressState::Snapshot { last_included_index: 10, started_at: now };
+        assert!(peer.snapshot_in_flight(10, now, timeout));
+        // 有了更新的快照，需要重新传输
+        assert!(!peer.snapshot_in_flight(20, now, timeout));
+        // 传输超时后允许重新发起
+        assert!(!peer.snapshot_in_flight(10, now + timeout, timeout));
+
+        peer.progress = ProgressState::Replicate;
+        assert!(!peer.snapshot_in_flight(10, now, timeout));
+    }
+
+    #[test]
+    fn test_probe_back_stops_at_log_start() {
+        // 没有快照时逐条回退，不低于 1
+        let mut peer = Peer::new(2, "[::1]:2".to_string());
+        peer.set_next_index(3);
+        assert!(!peer.probe_back(1));
+        assert!(!peer.probe_back(1));
+        assert!(!peer.probe_back(1));
+        assert_eq!(peer.next_index, 1);
+        assert!(!peer.snapshot_required);
+
+        // 日志从 5 开始：退到起点后再被拒绝就改用快照，之后的拒绝不再移动 next_index
+        peer.set_next_index(7);
+        assert!(!peer.probe_back(5));
+        assert!(!peer.probe_back(5));
+        assert_eq!(peer.next_index, 5);
+        assert!(peer.probe_back(5));
+        assert!(peer.probe_back(5));
+        assert_eq!(peer.next_index, 5);
+
+        // 快照安装到 8 后恢复正常复制，快照之前发出的请求迟到的拒绝不能让它回退
+        assert!(peer.advance_match_index(8));
+        assert!(!peer.snapshot_required);
+        assert!(!peer.probe_back(5));
+        assert_eq!(peer.next_index, 9);
+
+        // 等待快照期间日志起点被压缩越过了 next_index：直接拉到新的起点，仍然需要快照
+        let mut peer = Peer::new(3, "[::1]:3".to_string());
+        peer.set_next_index(4);
+        assert!(peer.probe_back(10));
+        assert_eq!(peer.next_index, 10);
+        assert!(peer.probe_back(12));
+        assert_eq!(peer.next_index, 12);
+        assert_eq!(peer.next_index_history.iter().copied().collect::<Vec<_>>(), vec![4, 10, 12]);
+
+        // 历史只保留最近的取值
+        for i in 0..config::NEXT_INDEX_HISTORY_LEN as u64 * 2 {
+            peer.set_next_index(100 + i);
+        }
+        assert_eq!(peer.next_index_history.len(), config::NEXT_INDEX_HISTORY_LEN);
+        assert_eq!(peer.next_index_history.back(), Some(&peer.next_index));
+    }
+
+    #[test]
+    fn test_skew_window_prefers_shortest_round_trip() {
+        let mut skew = SkewWindow::default();
+        let now = Instant::now();
+        assert_eq!(skew.bound_us(), None);
+        // 对方快 5ms，往返 2ms：对方的时间戳按往返中点计算
+        skew.observe(1_000_000, 1_006_000, 1_002_000, now);
+        assert_eq!(skew.estimate(), Some((5_000, 2_000)));
+        assert_eq!(skew.bound_us(), Some(6_000));
+        // 往返很长的采样误差大，不取代已有的估计；往返更短的采样取代它
+        skew.observe(2_000_000, 1_990_000, 2_040_000, now);
+        assert_eq!(skew.estimate(), Some((5_000, 2_000)));
+        skew.observe(3_000_000, 2_999_000, 3_000_400, now);
+        assert_eq!(skew.estimate(), Some((-1_200, 400)));
+        assert_eq!(skew.bound_us(), Some(1_400));
+        // 只保留最近的采样，旧的短往返采样最终会过期
+        for i in 0..config::CLOCK_SKEW_SAMPLES as u64 {
+            skew.observe(4_000_000 + i, 4_000_000 + i + 30_000, 4_000_000 + i + 10_000, now);
+        }
+        assert_eq!(skew.estimate(), Some((25_000, 10_000)));
+        // 超过有效期的采样被丢弃，之后的采样仍然保留
+        let later = now + Duration::from_secs(60);
+        skew.observe(5_000_000, 5_000_000, 5_000_200, later);
+        skew.expire(later + Duration::from_secs(1), Duration::from_secs(30));
+        assert_eq!(skew.estimate(), Some((-100, 200)));
+        skew.expire(later + Duration::from_secs(31), Duration::from_secs(30));
+        assert_eq!(skew.bound_us(), None);
+    }
+
+    #[test]
+    fn test_peers_update_addr() {
+        let mut peer_manager = PeerManager::new();
+        peer_manager.add(vec![Peer::new(1, "127.0.0.1:9001".to_string())], 0);
+
+        assert!(peer_manager.update_addr(1, "127.0.0.1:9101"));
+        assert_eq!(peer_manager.peers()[0].addr, "127.0.0.1:9101");
+        // 地址没有变化
+        assert!(!peer_manager.update_addr(1, "127.0.0.1:9101"));
+        // 不存在的节点
+        assert!(!peer_manager.update_addr(2, "127.0.0.1:9002"));
+    }
+
+    #[test]
+    fn test_quorum_active() {
+        let leader_cs = ConfigState { newing: true, olding: false };
+        let mut peer_manager = PeerManager {
+            peers: vec![
+                make_test_peer(1, 0, true, false),
+                make_test_peer(2, 0, true, false),
+                make_test_peer(3, 0, true, false),
+                make_test_peer(4, 0, true, false),
+            ],
+        };
+        let within = Duration::from_secs(10);
+        let now = Instant::now();
+        // 只有Leader自己，1/5
+        assert!(!peer_manager.quorum_active(&leader_cs, now, within));
+
+        peer_manager.peers[0].last_contact = Some(now);
+        // 2/5
+        assert!(!peer_manager.quorum_active(&leader_cs, now, within));
+
+        peer_manager.peers[1].last_contact = Some(now);
+        // 3/5
+        assert!(peer_manager.quorum_active(&leader_cs, now, within));
+
+        // 过期的通信不算数
+        assert!(!peer_manager.quorum_active(&leader_cs, now + within, within));
+    }
+
+    #[test]
+    fn test_lag_tracker_detects_persistent_slowness() {
+        let start = Instant::now();
+        let interval = config::SLOW_PEER_SAMPLE_INTERVAL;
+        let step = (config::SLOW_PEER_LAG_GROWTH_RATE * interval.as_secs_f64()) as u64 + 1;
+        let mut tracker = LagTracker::default();
+        let mut lag = config::SLOW_PEER_MIN_LAG;
+        assert!(!tracker.observe(lag, start));
+
+        // 采样间隔内的观测被忽略
+        assert!(!tracker.observe(lag + 1000, start + interval / 2));
+
+        for i in 1..config::SLOW_PEER_SAMPLES {
+            lag += step;
+            assert!(!tracker.observe(lag, start + interval * i));
+        }
+        lag += step;
+        assert!(tracker.observe(lag, start + interval * config::SLOW_PEER_SAMPLES));
+        assert!(tracker.slow);
+
+        // 落后量保持不变时仍然是慢节点，开始下降后恢复
+        assert!(!tracker.observe(lag, start + interval * (config::SLOW_PEER_SAMPLES + 1)));
+        assert!(tracker.observe(lag - 1, start + interval * (config::SLOW_PEER_SAMPLES + 2)));
+        assert!(!tracker.slow);
+    }
+
+    #[test]
+    fn test_quorum_without_slow() {
+        let leader_cs = ConfigState { newing: true, olding: false };
+        let mut peer_manager = PeerManager {
+            peers: vec![make_test_peer(1, 0, true, false), make_test_peer(2, 0, true, false)],
+        };
+        peer_manager.peers[0].lag.slow = true;
+        assert!(peer_manager.quorum_without_slow(&leader_cs));
+        // 两个 Follower 都慢时，Leader 自己无法构成多数派
+        peer_manager.peers[1].lag.slow = true;
+        assert!(!peer_manager.quorum_without_slow(&leader_cs));
+    }
+
+    #[test]
+    fn test_quorum_contacted_since() {
+        let leader_cs = ConfigState { newing: true, olding: false };
+        let before = Instant::now();
+        let since = before + Duration::from_millis(1);
+        let mut peer_manager = PeerManager {
+            peers: vec![make_test_peer(1, 0, true, false), make_test_peer(2, 0, true, false)],
+        };
+        // 确认开始之前的通信不算数
+        peer_manager.peers[0].last_contact = Some(before);
+        assert!(!peer_manager.quorum_contacted_since(&leader_cs, since));
+
+        peer_manager.peers[1].last_contact = Some(since);
+        assert!(peer_manager.quorum_contacted_since(&leader_cs, since));
+    }
+
     #[test]
     fn test_qmi_all_in_both_configs() {
         // Leader and 2 peers, all in new and old configs
@@ -253,7 +748,7 @@ mod tests {
         // New config: Leader (100), P1 (90), P2 (80). Sorted: [80, 90, 100]. Median (idx (3-1)/2=1): 90
         // Old config: Leader (100), P1 (90), P2 (80). Sorted: [80, 90, 100]. Median (idx (3-1)/2=1): 90
         // min(90, 90) = 90
-        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), 90);
+        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), Some(90));
     }
 
     #[test]
@@ -272,7 +767,7 @@ mod tests {
         // New config: Leader (100), P1 (90). Sorted: [90, 100]. Median (idx (2-1)/2=0): 90
         // Old config: P2 (80), P3 (70). Sorted: [70, 80]. Median (idx (2-1)/2=0): 70
         // min(90, 70) = 70
-        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), 70);
+        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), Some(70));
     }
 
     #[test]
@@ -290,7 +785,7 @@ mod tests {
         // New config: Leader (100), P1 (90), P2 (85). Sorted: [85, 90, 100]. Median: 90
         // Old config: No members. Returns u64::MAX
         // min(90, u64::MAX) = 90
-        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), 90);
+        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), Some(90));
     }
 
     #[test]
@@ -308,7 +803,7 @@ mod tests {
         // New config: No members. Returns u64::MAX
         // Old config: Leader (100), P1 (90), P2 (85). Sorted: [85, 90, 100]. Median: 90
         // min(u64::MAX, 90) = 90
-        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), 90);
+        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), Some(90));
     }
     #[test]
     fn test_qmi_no_quorum_in_either_config() {
@@ -321,10 +816,10 @@ mod tests {
             ],
         };
 
-        // New config: No members. Returns u64::MAX
-        // Old config: No members. Returns u64::MAX
-        // min(u64::MAX, u64::MAX) = u64::MAX
-        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), std::u64::MAX);
+        // New config: No members. Returns None
+        // Old config: No members. Returns None
+        // 两个配置都没有成员时没有多数派，不能推进 commit_index
+        assert_eq!(peer_manager.quoram_match_index(&leader_cs, leader_last_idx), None);
     }
 
     // ......未完全覆盖测试，使用gemini2.5pro写的测试用例，以上是都已经通过了的
@@ -0,0 +1,213 @@
+use crate::raft::consensus::{Consensus, State};
+use crate::raft::{config, proto};
+use super::logging::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex as TokioMutex;
+
+// Leader 位置建议：根据各节点测得的到其他节点的 RPC 往返时间，估算每个节点作为 Leader 时
+// 凑齐多数派确认所需的延迟（自己算一票，再等最快的 n/2 个节点回应），延迟最小的节点即为建议的 Leader。
+// 建议只是参考，只有请求方明确要求时才会发起 Leader 转移
+
+// 每个节点到其他节点的往返时间中位数（微秒），rtts[from][to]
+pub type LatencyMatrix = HashMap<u64, HashMap<u64, u64>>;
+
+// 按预估延迟从低到高给所有节点排序；某个方向没有样本时用反方向的数据代替，
+// 延迟相同时优先保留当前 Leader，避免无意义的转移，其次选择和当前 Leader 同一可用区的节点
+pub fn rank_candidates(servers: &[proto::ServerInfo], rtts: &LatencyMatrix, current_leader: u64) -> Vec<proto::LeaderCandidate> {
+    let rtt = |from: u64, to: u64| rtts.get(&from).and_then(|m| m.get(&to)).copied();
+    let leader_zone = servers.iter().find(|s| s.server_id == current_leader).and_then(|s| s.zone.clone());
+    let same_zone = |c: &proto::LeaderCandidate| leader_zone.is_some() && c.server.as_ref().is_some_and(|s| s.zone == leader_zone);
+    let acks_needed = servers.len() / 2;
+    let mut candidates: Vec<proto::LeaderCandidate> = servers.iter().map(|candidate| {
+        let mut known: Vec<u64> = servers.iter()
+            .filter(|other| other.server_id != candidate.server_id)
+            .filter_map(|other| rtt(candidate.server_id, other.server_id).or_else(|| rtt(other.server_id, candidate.server_id)))
+            .collect();
+        known.sort_unstable();
+        let complete = known.len() + 1 == servers.len();
+        let quorum_latency_us = match acks_needed {
+            0 => Some(0),
+            n => known.get(n - 1).copied(),
+        };
+        proto::LeaderCandidate { server: Some(candidate.clone()), quorum_latency_us, complete }
+    }).collect();
+    candidates.sort_by_key(|c| {
+        let id = c.server.as_ref().map_or(config::NONE_SERVER_ID, |s| s.server_id);
+        (c.quorum_latency_us.is_none(), c.quorum_latency_us, id != current_leader, !same_zone(c), id)
+    });
+    candidates
+}
+
+// 返回本节点到各个 Peer 的延迟；probe 为 true 时先 Ping 每个 Peer 若干次，
+// 保证空闲的 Follower 也有样本。Ping 期间不持有共识模块的锁
+pub async fn probe_peer_latency(consensus: &Arc<TokioMutex<Consensus>>, probe: bool) -> proto::GetPeerLatencyResponse {
+    if probe {
+        let (peers, rpc_client): (Vec<(u64, String)>, _) = {
+            let guard = consensus.lock().await;
+            (guard.peer_manager.peers().iter().map(|p| (p.id, p.addr.clone())).collect(), guard.rpc_client())
+        };
+        let rpc_client = &rpc_client;
+        let results = futures::future::join_all(peers.into_iter().map(|(peer_id, peer_addr)| async move {
+            let mut samples = Vec::new();
+            for _ in 0..config::LATENCY_PROBE_COUNT {
+                let sent_at = Instant::now();
+                match rpc_client.ping(proto::PingRequest {}, peer_addr.clone()).await {
+                    Ok(_) => samples.push(sent_at.elapsed()),
+                    Err(e) => {
+                        debug!("Ping to peer {} ({}) failed: {}", peer_id, peer_addr, e);
+                        break;
+                    }
+                }
+            }
+            (peer_id, samples)
+        })).await;
+
+        let mut guard = consensus.lock().await;
+        for (peer_id, samples) in results {
+            for rtt in samples {
+                guard.record_peer_rtt(peer_id, rtt);
+            }
+        }
+    }
+    consensus.lock().await.handle_get_peer_latency_rpc()
+}
+
+// 只能由 Leader 处理：收集所有节点的延迟数据，给出建议的 Leader，transfer 为 true 时发起转移
+pub async fn suggest_leader(consensus: &Arc<TokioMutex<Consensus>>, transfer: bool) -> proto::SuggestLeaderResponse {
+    let (leader, servers, rpc_client) = {
+        let guard = consensus.lock().await;
+        let leader = proto::ServerInfo {
+            server_id: guard.server_id,
+            server_addr: guard.server_addr.clone(),
+            zone: guard.current_config.zone_of(guard.server_id).map(str::to_string),
+        };
+        if guard.state != State::Leader {
+            return failed(None, "SuggestLeader can only be handled by the leader".to_string());
+        }
+        (leader, guard.current_config.all_servers_in_config(), guard.rpc_client())
+    };
+
+    let own = probe_peer_latency(consensus, true).await;
+    let remote = futures::future::join_all(servers.iter()
+        .filter(|s| s.server_id != leader.server_id)
+        .map(|s| {
+            let addr = s.server_addr.clone();
+            let rpc_client = &rpc_client;
+            async move {
+                let result = tokio::time::timeout(
+                    config::LATENCY_PROBE_COUNT as u32 * config::CLIENT_PROBE_TIMEOUT,
+                    rpc_client.get_peer_latency(proto::GetPeerLatencyRequest { probe: true }, addr.clone()),
+                ).await;
+                match result {
+                    Ok(Ok(resp)) => Some(resp),
+                    Ok(Err(e)) => {
+                        warn!("GetPeerLatency from {} failed: {}", addr, e);
+                        None
+                    }
+                    Err(_) => {
+                        warn!("GetPeerLatency from {} timed out", addr);
+                        None
+                    }
+                }
+            }
+        })).await;
+
+    let mut rtts = LatencyMatrix::new();
+    for resp in std::iter::once(own).chain(remote.into_iter().flatten()) {
+        let row = rtts.entry(resp.server_id).or_default();
+        for peer in resp.peers.iter().filter(|p| p.samples > 0) {
+            row.insert(peer.server_id, peer.median_rtt_us);
+        }
+    }
+
+    let candidates = rank_candidates(&servers, &rtts, leader.server_id);
+    let suggested = match candidates.first() {
+        Some(c) if c.quorum_latency_us.is_some() => c.server.clone(),
+        _ => return failed(Some(leader), "not enough latency samples to make a suggestion".to_string()),
+    };
+    info!("SuggestLeader: suggested {:?}, candidates: {:?}", suggested, candidates);
+
+    let mut resp = proto::SuggestLeaderResponse {
+        success: true,
+        current_leader: Some(leader.clone()),
+        suggested: suggested.clone(),
+        candidates,
+        transferred: false,
+        error: None,
+    };
+    let target_id = suggested.map_or(leader.server_id, |s| s.server_id);
+    if transfer && target_id != leader.server_id {
+        match Consensus::transfer_leadership(consensus, target_id).await {
+            Ok(()) => resp.transferred = true,
+            Err(e) => {
+                warn!("Leader transfer to server {} failed: {}", target_id, e);
+                resp.error = Some(e);
+            }
+        }
+    }
+    resp
+}
+
+fn failed(current_leader: Option<proto::ServerInfo>, error: String) -> proto::SuggestLeaderResponse {
+    proto::SuggestLeaderResponse {
+        success: false,
+        current_leader,
+        suggested: None,
+        candidates: Vec::new(),
+        transferred: false,
+        error: Some(error),
+    }
+}
+
+// 方便日志输出
+pub fn format_latency_us(latency_us: Option<u64>) -> String {
+    match latency_us {
+        Some(us) => format!("{:?}", Duration::from_micros(us)),
+        None => "unknown".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(id: u64) -> proto::ServerInfo {
+        proto::ServerInfo { server_id: id, server_addr: format!("[::1]:{}", 9000 + id), zone: None }
+    }
+
+    #[test]
+    fn test_rank_candidates() {
+        // 节点 2 离 1、3 都近，节点 3 离 1 很远；节点 3 没有到 2 的样本时用 2 到 3 的数据
+        let servers = vec![server(1), server(2), server(3)];
+        let mut rtts = LatencyMatrix::new();
+        rtts.insert(1, HashMap::from([(2, 1_000), (3, 50_000)]));
+        rtts.insert(2, HashMap::from([(1, 1_000), (3, 2_000)]));
+        rtts.insert(3, HashMap::from([(1, 50_000)]));
+
+        let candidates = rank_candidates(&servers, &rtts, 1);
+        let order: Vec<u64> = candidates.iter().map(|c| c.server.as_ref().unwrap().server_id).collect();
+        // 1 和 2 的多数派延迟都是 1ms，相同时保留当前 Leader
+        assert_eq!(order, vec![1, 2, 3]);
+        assert_eq!(candidates[0].quorum_latency_us, Some(1_000));
+        assert_eq!(candidates[2].quorum_latency_us, Some(2_000));
+        assert!(candidates.iter().all(|c| c.complete));
+
+        // 当前 Leader 是 3 时建议转移到 1 或 2
+        let candidates = rank_candidates(&servers, &rtts, 3);
+        assert_eq!(candidates[0].server.as_ref().unwrap().server_id, 1);
+        // 延迟相同时优先选择和当前 Leader 同一可用区的节点
+        let zoned = |id: u64, zone: &str| proto::ServerInfo { zone: Some(zone.to_string()), ..server(id) };
+        let candidates = rank_candidates(&[zoned(1, "a"), zoned(2, "b"), zoned(3, "b")], &rtts, 3);
+        assert_eq!(candidates[0].server.as_ref().unwrap().server_id, 2);
+
+        // 没有任何样本的节点排在最后
+        let servers = vec![server(1), server(2), server(3), server(4)];
+        let candidates = rank_candidates(&servers, &rtts, 1);
+        let last = candidates.last().unwrap();
+        assert_eq!(last.server.as_ref().unwrap().server_id, 4);
+        assert_eq!(last.quorum_latency_us, None);
+        assert!(!candidates[0].complete);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::raft::config;
+use super::logging::*;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tonic::codegen::http;
+
+// 面向客户端 RPC 的限流中间件，按客户端 IP 各用一个令牌桶（见 config::ClientRateLimit）。
+// 只限制 RATE_LIMITED_METHODS 中的方法，节点之间的共识 RPC 不受影响；
+// 通过 Unix domain socket 连接的客户端没有 IP，共用一个令牌桶
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: &config::ClientRateLimit, now: Instant) -> Self {
+        TokenBucket { tokens: limit.burst as f64, updated_at: now }
+    }
+
+    fn refill(&mut self, limit: &config::ClientRateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.requests_per_sec).min(limit.burst as f64);
+        self.updated_at = now;
+    }
+
+    fn try_take(&mut self, limit: &config::ClientRateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    limit: config::ClientRateLimit,
+    buckets: Arc<StdMutex<HashMap<Option<IpAddr>, TokenBucket>>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limit: config::ClientRateLimit) -> Self {
+        RateLimitLayer { limit, buckets: Arc::new(StdMutex::new(HashMap::new())) }
+    }
+
+    // client 在 now 时刻能否再发一个受限的请求
+    pub fn allow(&self, client: Option<IpAddr>, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(&client) && buckets.len() >= config::RATE_LIMIT_MAX_CLIENTS {
+            let limit = &self.limit;
+            buckets.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                bucket.tokens < limit.burst as f64
+            });
+        }
+        buckets.entry(client).or_insert_with(|| TokenBucket::full(&self.limit, now)).try_take(&self.limit, now)
+    }
+}
+
+impl<S> tower::Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService { inner, limiter: self.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: RateLimitLayer,
+}
+
+fn client_ip<B>(request: &http::Request<B>) -> Option<IpAddr> {
+    request.extensions()
+        .get::<tonic::transport::server::TcpConnectInfo>()
+        .and_then(|info| info.remote_addr())
+        .map(|addr| addr.ip())
+}
+
+impl<S> tower::Service<http::Request<tonic::body::Body>> for RateLimitService<S>
+where
+    S: tower::Service<http::Request<tonic::body::Body>, Response = http::Response<tonic::body::Body>, Error = BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = BoxError;
+    type Future = futures::future::Either<S::Future, futures::future::Ready<Result<Self::Response, BoxError>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<tonic::body::Body>) -> Self::Future {
+        if config::RATE_LIMITED_METHODS.contains(&request.uri().path()) {
+            let client = client_ip(&request);
+            if !self.limiter.allow(client, Instant::now()) {
+                debug!("Rate limiting {} from {:?}.", request.uri().path(), client);
+                let status = tonic::Status::resource_exhausted("client request rate limit exceeded, retry later");
+                return futures::future::Either::Right(futures::future::ready(Ok(status.into_http())));
+            }
+        }
+        futures::future::Either::Left(self.inner.call(request))
+    }
+}
+
+// Follower 应用条目的限速器（见 config::ApplyRateLimit），初始时两个令牌桶都是满的。
+// 单个条目的开销超过桶容量时，等到桶满后放行，令牌透支成负数，之后按速率补回
+#[derive(Debug, Clone)]
+pub struct ApplyPacer {
+    limit: config::ApplyRateLimit,
+    entries: f64,
+    bytes: f64,
+    updated_at: Instant,
+}
+
+impl ApplyPacer {
+    pub fn new(limit: config::ApplyRateLimit, now: Instant) -> Self {
+        ApplyPacer { entries: limit.burst_entries as f64, bytes: limit.burst_bytes as f64, limit, updated_at: now }
+    }
+
+    pub fn limit(&self) -> &config::ApplyRateLimit {
+        &self.limit
+    }
+
+    // 应用一个 bytes 字节的条目：令牌足够时扣除并返回 None，否则返回还需要等待的时间
+    pub fn try_take(&mut self, bytes: u64, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.updated_at = now;
+        let limit = &self.limit;
+        let mut buckets = [
+            (&mut self.entries, limit.entries_per_sec, limit.burst_entries, 1),
+            (&mut self.bytes, limit.bytes_per_sec, limit.burst_bytes, bytes),
+        ];
+        let mut wait = Duration::ZERO;
+        for (tokens, rate, burst, cost) in buckets.iter_mut() {
+            let Some(rate) = rate.filter(|rate| *rate > 0.0) else { continue };
+            **tokens = (**tokens + elapsed * rate).min(*burst as f64);
+            let needed = (*cost).min(*burst) as f64 - **tokens;
+            if needed > 0.0 {
+                wait = wait.max(Duration::from_secs_f64(needed / rate));
+            }
+        }
+        if !wait.is_zero() {
+            return Some(wait);
+        }
+        for (tokens, rate, _, cost) in buckets {
+            if rate.is_some_and(|rate| rate > 0.0) {
+                *tokens -= cost as f64;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket_per_client() {
+        let limiter = RateLimitLayer::new(config::ClientRateLimit { requests_per_sec: 2.0, burst: 3 });
+        let (a, b) = (Some("10.0.0.1".parse().unwrap()), Some("10.0.0.2".parse().unwrap()));
+        let now = Instant::now();
+
+        // 突发额度用完后拒绝，其他客户端不受影响
+        assert!((0..3).all(|_| limiter.allow(a, now)));
+        assert!(!limiter.allow(a, now));
+        assert!(limiter.allow(b, now));
+        assert!(limiter.allow(None, now));
+
+        // 按速率补充，但不超过容量
+        assert!(!limiter.allow(a, now + Duration::from_millis(400)));
+        assert!(limiter.allow(a, now + Duration::from_millis(500)));
+        let later = now + Duration::from_secs(60);
+        assert!((0..3).all(|_| limiter.allow(a, later)));
+        assert!(!limiter.allow(a, later));
+    }
+
+    #[test]
+    fn test_apply_pacer_limits_entries_and_bytes() {
+        let limit = config::ApplyRateLimit { entries_per_sec: Some(10.0), bytes_per_sec: Some(1000.0), burst_entries: 2, burst_bytes: 500 };
+        let now = Instant::now();
+        let mut pacer = ApplyPacer::new(limit, now);
+
+        // 突发额度内直接放行，之后按条目数的速率等待
+        assert_eq!(pacer.try_take(10, now), None);
+        assert_eq!(pacer.try_take(10, now), None);
+        assert_eq!(pacer.try_take(10, now), Some(Duration::from_millis(100)));
+        let now = now + Duration::from_millis(100);
+        assert_eq!(pacer.try_take(10, now), None);
+
+        // 字节数不足时按字节速率等待；超过桶容量的条目在桶满后放行并透支
+        let now = now + Duration::from_secs(1);
+        assert_eq!(pacer.try_take(400, now), None);
+        assert_eq!(pacer.try_take(2000, now), Some(Duration::from_millis(400)));
+        let now = now + Duration::from_millis(400);
+        assert_eq!(pacer.try_take(2000, now), None);
+        assert!(pacer.try_take(1, now + Duration::from_secs(1)).is_some());
+        assert_eq!(pacer.try_take(1, now + Duration::from_secs(2)), None);
+    }
+}
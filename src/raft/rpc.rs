@@ -1,44 +1,496 @@
-use tonic::transport::Channel;
+use tonic::transport::{Channel, Endpoint};
 
 use crate::raft::consensus::Consensus;
-use crate::raft::{consensus, proto, timer};
+use crate::raft::{breaker, config, consensus, crash, decommission, export, fairness, group, placement, proto, sanity, state_machine, subscription, timer, version};
 use super::logging::*;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::Mutex as TokioMutex;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex as TokioMutex};
+use tonic::codegen::http;
+use tower::ServiceExt;
+use futures::StreamExt;
 
 // RPC Server
 #[derive(Clone)]
 pub struct Server {
     pub consensus: Arc<TokioMutex<consensus::Consensus>>,
+    pub groups: Option<Arc<group::GroupRegistry>>,  // 同一进程内的其他共识组，用于分发合并心跳
+    pub view: watch::Receiver<consensus::StateView>, // 共识模块发布的状态视图，只读请求不需要加锁
+    pub restore_progress: Arc<state_machine::RestoreProgress>, // 快照恢复期间共识模块被占用，进度直接从这里读取
+    pub proposal_scheduler: Arc<fairness::ProposalScheduler>, // Propose 拿锁之前按客户端连接轮转排队
+    pub stop: watch::Receiver<bool>,                 // 节点停止信号，用于结束流式响应
 }
 
-// #[tokio::main]
-pub async fn start_server(
-    addr: &str,
-    consensus: Arc<TokioMutex<Consensus>>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let addr = addr.parse().unwrap();
+// 已经绑定好监听地址、尚未开始处理请求的 RPC 服务
+// 先绑定再服务，启动方可以在绑定失败时立即得到错误，并拿到实际监听的地址（例如端口为 0 时）
+pub struct BoundServer {
+    addr: String,
+    management_addr: Option<String>,
+    routers: Vec<(HookedRouter, Listener)>,
+}
+
+impl BoundServer {
+    // 共识服务实际监听的地址
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    // 管理服务单独监听时的实际地址
+    pub fn management_addr(&self) -> Option<&str> {
+        self.management_addr.as_deref()
+    }
+
+    // 开始处理请求，任意一个监听失败都视为整个 RPC 服务失败
+    // 处理请求直到 stop 变为 true（节点完全停止），正在处理的请求会先完成
+    pub async fn serve(self, stop: watch::Receiver<bool>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        futures::future::try_join_all(self.routers.into_iter().map(|(router, listener)| listener.serve(router, stop.clone()))).await?;
+        Ok(())
+    }
+}
 
-    info!("Raft server listening on {}", addr);
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+// 类型擦除后的 HTTP 服务，嵌入方的 tower 中间件都包在它外面
+pub type BoxHttpService = tower::util::BoxCloneService<http::Request<tonic::body::Body>, http::Response<tonic::body::Body>, BoxError>;
+type InterceptorFn = Arc<dyn Fn(tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> + Send + Sync>;
+type LayerFn = Arc<dyn Fn(BoxHttpService) -> BoxHttpService + Send + Sync>;
+type HookedRouter = tonic::transport::server::Router<tower::layer::util::Stack<ServerHooks, tower::layer::util::Identity>>;
+
+// 嵌入方添加到 RPC 服务上的钩子（认证、限流、请求日志等），同时作用于共识服务和管理服务，
+// 不需要修改本 crate。中间件按添加顺序由外到内包装，拦截器在最内层，按添加顺序依次执行
+#[derive(Clone, Default)]
+pub struct ServerHooks {
+    interceptors: Vec<InterceptorFn>,
+    layers: Vec<LayerFn>,
+}
+
+impl std::fmt::Debug for ServerHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerHooks")
+            .field("interceptors", &self.interceptors.len())
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+impl ServerHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 添加一个 tonic 拦截器，可以检查或修改请求的元数据；返回 Err 时直接以该状态拒绝请求
+    pub fn interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> + Send + Sync + 'static,
+    {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    // 添加一个 tower 中间件
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: tower::Layer<BoxHttpService> + Send + Sync + 'static,
+        L::Service: tower::Service<http::Request<tonic::body::Body>, Response = http::Response<tonic::body::Body>, Error = BoxError>
+            + Clone + Send + 'static,
+        <L::Service as tower::Service<http::Request<tonic::body::Body>>>::Future: Send + 'static,
+    {
+        self.layers.push(Arc::new(move |inner| tower::util::BoxCloneService::new(layer.layer(inner))));
+        self
+    }
+}
 
+impl<S> tower::Layer<S> for ServerHooks
+where
+    S: tower::Service<http::Request<tonic::body::Body>, Response = http::Response<tonic::body::Body>, Error = std::convert::Infallible>
+        + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Service = BoxHttpService;
+
+    // 拦截器的签名由 tonic 规定，错误类型只能是 tonic::Status
+    #[allow(clippy::result_large_err)]
+    fn layer(&self, inner: S) -> BoxHttpService {
+        // 请求处理标记为共识任务，处理中的 panic 会转储崩溃现场（见 crash::install）
+        let mut service = tower::util::BoxCloneService::new(inner.map_err(|e| match e {}).map_future(crash::scope));
+        if !self.interceptors.is_empty() {
+            let interceptors = self.interceptors.clone();
+            let intercepted = tonic::service::interceptor::InterceptedService::new(service, move |mut req: tonic::Request<()>| {
+                for interceptor in interceptors.iter() {
+                    req = interceptor(req)?;
+                }
+                Ok(req)
+            });
+            service = tower::util::BoxCloneService::new(intercepted.map_response(|resp| resp.map(tonic::body::Body::new)));
+        }
+        for layer in self.layers.iter().rev() {
+            service = layer(service);
+        }
+        service
+    }
+}
+
+// management_addr 为 None 时，两个服务共用 addr；否则管理服务单独监听 management_addr，
+// 共识服务仅保留在面向其他节点的 addr 上，方便用防火墙隔离内部流量和客户端流量
+// enable_management 为 false 或未开启 management-rpc feature 时，只注册共识服务
+pub async fn bind_server(
+    addr: &str,
+    management_addr: Option<&str>,
+    enable_management: bool,
+    consensus: Arc<TokioMutex<Consensus>>,
+    groups: Option<Arc<group::GroupRegistry>>,
+    hooks: ServerHooks,
+    transport: &config::TransportOptions,
+) -> Result<BoundServer, Box<dyn std::error::Error + Send + Sync>> {
+    let (view, restore_progress, proposal_scheduler, stop) = {
+        let consensus_guard = consensus.lock().await;
+        (consensus_guard.subscribe_view(), Arc::clone(&consensus_guard.restore_progress),
+            Arc::clone(&consensus_guard.proposal_scheduler), consensus_guard.stop_signal())
+    };
     let consensus_server = Server {
         consensus: consensus.clone(),
+        groups: groups.clone(),
+        view: view.clone(),
+        restore_progress: restore_progress.clone(),
+        proposal_scheduler: proposal_scheduler.clone(),
+        stop: stop.clone(),
     };
     let management_server = Server {
         consensus: consensus.clone(),
+        groups,
+        view,
+        restore_progress,
+        proposal_scheduler,
+        stop,
     };
-    tonic::transport::Server::builder()
-        .add_service(proto::consensus_rpc_server::ConsensusRpcServer::new(
-            consensus_server,
-        ))
-        .add_service(proto::management_rpc_server::ManagementRpcServer::new(
-            management_server,
-        ))
-        .serve(addr)
-        .await?;
 
-    Ok(())
+    if !(enable_management && cfg!(feature = "management-rpc")) {
+        if let Some(management_addr) = management_addr {
+            warn!("Management service is disabled, ignoring management address {}", management_addr);
+        }
+        let listener = Listener::bind(addr, transport).await?;
+        info!("Raft consensus service listening on {} (management service disabled)", listener.local_addr());
+        let router = server_builder(transport).layer(hooks.clone())
+            .add_service(proto::consensus_rpc_server::ConsensusRpcServer::new(
+                consensus_server,
+            ));
+        return Ok(BoundServer { addr: listener.local_addr(), management_addr: None, routers: vec![(router, listener)] });
+    }
+
+    match management_addr {
+        None => {
+            let listener = Listener::bind(addr, transport).await?;
+            info!("Raft server listening on {}", listener.local_addr());
+            let router = server_builder(transport).layer(hooks.clone())
+                .add_service(proto::consensus_rpc_server::ConsensusRpcServer::new(
+                    consensus_server,
+                ))
+                .add_service(proto::management_rpc_server::ManagementRpcServer::new(
+                    management_server,
+                ));
+            Ok(BoundServer { addr: listener.local_addr(), management_addr: None, routers: vec![(router, listener)] })
+        }
+        Some(management_addr) => {
+            let consensus_listener = Listener::bind(addr, transport).await?;
+            let management_listener = Listener::bind(management_addr, transport).await?;
+            info!("Raft consensus service listening on {}, management service listening on {}",
+                consensus_listener.local_addr(), management_listener.local_addr());
+
+            let consensus_router = server_builder(transport).layer(hooks.clone())
+                .add_service(proto::consensus_rpc_server::ConsensusRpcServer::new(
+                    consensus_server,
+                ));
+            let management_router = server_builder(transport).layer(hooks.clone())
+                .add_service(proto::management_rpc_server::ManagementRpcServer::new(
+                    management_server,
+                ));
+            Ok(BoundServer {
+                addr: consensus_listener.local_addr(),
+                management_addr: Some(management_listener.local_addr()),
+                routers: vec![(consensus_router, consensus_listener), (management_router, management_listener)],
+            })
+        }
+    }
+}
+
+pub async fn start_server(
+    addr: &str,
+    management_addr: Option<&str>,
+    enable_management: bool,
+    consensus: Arc<TokioMutex<Consensus>>,
+    groups: Option<Arc<group::GroupRegistry>>,
+    hooks: ServerHooks,
+    transport: &config::TransportOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let stop = consensus.lock().await.stop_signal();
+    bind_server(addr, management_addr, enable_management, consensus, groups, hooks, transport).await?.serve(stop).await
+}
+
+// 解析 grpc-timeout 头的值：最多 8 位数字加一个单位（H、M、S、m、u、n）
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let split = value.len().checked_sub(1)?;
+    let (digits, unit) = value.split_at(split);
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+// 客户端设置的截止时间。tonic 在 grpc-timeout 到期时直接丢弃请求并返回 Cancelled，
+// 这里提前 CLIENT_DEADLINE_MARGIN 结束等待，让客户端收到明确的 DeadlineExceeded
+fn client_deadline<T>(request: &tonic::Request<T>) -> Option<tokio::time::Instant> {
+    let timeout = parse_grpc_timeout(request.metadata().get("grpc-timeout")?.to_str().ok()?)?;
+    Some(tokio::time::Instant::now() + timeout.saturating_sub(config::CLIENT_DEADLINE_MARGIN))
+}
+
+fn deadline_passed(deadline: Option<tokio::time::Instant>) -> bool {
+    deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline)
+}
+
+// TCP 层的 keepalive 和 TCP_NODELAY 由 Listener 设置在接受的连接上，builder 上的设置对 serve_with_incoming 不生效
+fn server_builder(transport: &config::TransportOptions) -> tonic::transport::Server {
+    let mut builder = tonic::transport::Server::builder()
+        .http2_keepalive_interval(transport.keepalive_interval)
+        .http2_keepalive_timeout(transport.keepalive_timeout);
+    if transport.adaptive_window {
+        builder = builder.http2_adaptive_window(Some(true));
+    }
+    if let Some(limit) = transport.concurrency_limit {
+        builder = builder.concurrency_limit_per_connection(limit);
+    }
+    builder
+}
+
+// 把导出任务写入管道的字节切成消息；导出失败时先发完已经写出的数据，再以错误结束。
+// 客户端断开时管道的读端被 drop，导出任务写入失败后退出
+fn export_chunks(
+    reader: tokio::io::DuplexStream,
+    export: tokio::task::JoinHandle<Result<export::ExportedSnapshot, String>>,
+) -> impl futures::Stream<Item = Result<proto::ExportSnapshotChunk, tonic::Status>> + Send {
+    use tokio::io::AsyncReadExt;
+    futures::stream::unfold((reader, Some(export)), |(mut reader, export)| async move {
+        let export = export?;
+        let mut data = vec![0; config::FETCH_SNAPSHOT_CHUNK_SIZE];
+        match reader.read(&mut data).await {
+            Ok(0) => match export.await {
+                Ok(Ok(_)) => None,
+                Ok(Err(e)) => Some((Err(tonic::Status::failed_precondition(e)), (reader, None))),
+                Err(e) => Some((Err(tonic::Status::internal(format!("snapshot export task failed: {}", e))), (reader, None))),
+            },
+            Ok(n) => {
+                data.truncate(n);
+                Some((Ok(proto::ExportSnapshotChunk { data }), (reader, Some(export))))
+            }
+            Err(e) => Some((Err(tonic::Status::internal(format!("failed to read snapshot export: {}", e))), (reader, None))),
+        }
+    })
+}
+
+// Leader 变化流：先给出当前的 Leader，之后只在 Leader 变化时给出（配置变化等其他视图更新不推送）；
+// 节点停止或共识模块被释放时结束，否则优雅关闭会一直等待这些流
+pub fn leader_changes(
+    view: watch::Receiver<consensus::StateView>,
+    stop: watch::Receiver<bool>,
+) -> impl futures::Stream<Item = proto::GetLeaderResponse> + Send {
+    futures::stream::unfold((view, stop, None), |(mut view, mut stop, sent)| async move {
+        loop {
+            if *stop.borrow() {
+                return None;
+            }
+            let current = view.borrow_and_update().leader_response();
+            // Leader 或任期变化时推送，提交索引的变化不推送
+            let key = (current.leader.clone(), current.term);
+            if sent.as_ref() != Some(&key) {
+                return Some((current, (view, stop, Some(key))));
+            }
+            tokio::select! {
+                changed = view.changed() => changed.ok()?,
+                changed = stop.changed() => changed.ok()?,
+            }
+        }
+    })
+}
+
+// 地址以 UDS_ADDR_PREFIX 开头时返回 socket 文件路径
+pub fn uds_path(addr: &str) -> Option<&str> {
+    addr.strip_prefix(config::UDS_ADDR_PREFIX)
+}
+
+// 根据地址类型在 TCP 或 Unix domain socket 上监听
+enum Listener {
+    Tcp(tonic::transport::server::TcpIncoming),
+    Unix(tokio::net::UnixListener, String),
+}
+
+impl Listener {
+    async fn bind(addr: &str, transport: &config::TransportOptions) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        match uds_path(addr) {
+            Some(path) => {
+                // 清理上次运行残留的 socket 文件，否则 bind 会失败
+                if std::path::Path::new(path).exists() {
+                    std::fs::remove_file(path)?;
+                }
+                Ok(Listener::Unix(tokio::net::UnixListener::bind(path)?, addr.to_string()))
+            }
+            None => {
+                let socket_addr: std::net::SocketAddr = addr.parse()?;
+                let listener = tokio::net::TcpListener::bind(socket_addr).await?;
+                // 和 tonic 直接监听地址时的默认行为一致：关闭 Nagle 算法，按配置打开 TCP keepalive
+                Ok(Listener::Tcp(tonic::transport::server::TcpIncoming::from(listener)
+                    .with_nodelay(Some(true))
+                    .with_keepalive(transport.tcp_keepalive)))
+            }
+        }
+    }
+
+    fn local_addr(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map_or_else(|_| String::new(), |a| a.to_string()),
+            Listener::Unix(_, addr) => addr.clone(),
+        }
+    }
+
+    async fn serve(self, router: HookedRouter, mut stop: watch::Receiver<bool>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stopped = async move {
+            let _ = stop.wait_for(|stopped| *stopped).await;
+        };
+        match self {
+            Listener::Tcp(listener) => {
+                router
+                    .serve_with_incoming_shutdown(listener, stopped)
+                    .await?;
+            }
+            Listener::Unix(listener, _) => {
+                router
+                    .serve_with_incoming_shutdown(tokio_stream::wrappers::UnixListenerStream::new(listener), stopped)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref BREAKERS: breaker::BreakerRegistry = breaker::BreakerRegistry::default();
+}
+
+fn configure_endpoint(mut endpoint: Endpoint, transport: &config::TransportOptions) -> Endpoint {
+    endpoint = endpoint.tcp_keepalive(transport.tcp_keepalive).keep_alive_while_idle(transport.keepalive_while_idle);
+    if let Some(interval) = transport.keepalive_interval {
+        endpoint = endpoint.http2_keep_alive_interval(interval);
+    }
+    if let Some(timeout) = transport.keepalive_timeout {
+        endpoint = endpoint.keep_alive_timeout(timeout);
+    }
+    if transport.adaptive_window {
+        endpoint = endpoint.http2_adaptive_window(true);
+    }
+    if let Some(limit) = transport.concurrency_limit {
+        endpoint = endpoint.concurrency_limit(limit);
+    }
+    if let Some(timeout) = transport.connect_timeout {
+        endpoint = endpoint.connect_timeout(timeout);
+    }
+    endpoint
+}
+
+// 建立到 addr 的连接；对端处于熔断状态时直接返回错误，不再等待连接超时
+pub async fn connect(addr: &str, transport: &config::TransportOptions) -> Result<Channel, Box<dyn std::error::Error + Send + Sync>> {
+    if !BREAKERS.allow(addr) {
+        return Err(format!("circuit breaker open for {}", addr).into());
+    }
+    let result = connect_endpoint(addr, transport).await;
+    match &result {
+        Ok(_) => BREAKERS.on_success(addr),
+        Err(_) => BREAKERS.on_failure(addr),
+    }
+    result
+}
+
+// 建立到 addr 的连接，UDS 地址通过自定义 connector 连接，其余按 http://addr 处理
+async fn connect_endpoint(addr: &str, transport: &config::TransportOptions) -> Result<Channel, Box<dyn std::error::Error + Send + Sync>> {
+    match uds_path(addr) {
+        Some(path) => {
+            let path = path.to_string();
+            // UDS 连接不使用 uri 中的主机名，这里只需要一个合法的占位地址
+            let channel = configure_endpoint(Endpoint::try_from("http://[::]:50051")?, transport)
+                .connect_with_connector(tower::service_fn(move |_: tonic::transport::Uri| {
+                    let path = path.clone();
+                    async move {
+                        let stream = tokio::net::UnixStream::connect(path).await?;
+                        Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+                    }
+                }))
+                .await?;
+            Ok(channel)
+        }
+        None => {
+            let channel = configure_endpoint(Endpoint::from_shared(format!("http://{}", addr))?, transport).connect().await?;
+            Ok(channel)
+        }
+    }
+}
+
+// AppendEntries 请求的摘要：日志中只打印索引范围和字节数，处理响应时也只需要它，
+// 这样请求本身可以直接交给 RPC 发送，而不必为了日志或响应处理复制整批条目
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AppendEntriesSummary {
+    pub term: u64,
+    pub leader_id: u64,
+    pub prev_log_index: u64,
+    pub prev_log_term: u64,
+    pub entries: u64,
+    pub bytes: u64,
+    pub leader_commit: u64,
+}
+
+impl AppendEntriesSummary {
+    pub fn of(req: &proto::AppendEntriesRequest) -> Self {
+        AppendEntriesSummary {
+            term: req.term,
+            leader_id: req.leader_id,
+            prev_log_index: req.prev_log_index,
+            prev_log_term: req.prev_log_term,
+            entries: req.entries.len() as u64,
+            bytes: req.entries.iter().map(|e| e.data.len() as u64).sum(),
+            leader_commit: req.leader_commit,
+        }
+    }
+
+    // 请求中最后一条日志的索引，没有条目时为 prev_log_index
+    pub fn last_index(&self) -> u64 {
+        self.prev_log_index + self.entries
+    }
+}
+
+impl std::fmt::Display for AppendEntriesSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "term={} leader={} prev=({}, {}) commit={}", self.term, self.leader_id, self.prev_log_index, self.prev_log_term, self.leader_commit)?;
+        if self.entries == 0 {
+            write!(f, " heartbeat")
+        } else {
+            write!(f, " entries=[{}, {}] ({} bytes)", self.prev_log_index + 1, self.last_index(), self.bytes)
+        }
+    }
+}
+
+// InstallSnapshot 分块的摘要，不打印分块数据
+fn install_snapshot_summary(req: &proto::InstallSnapshotRequest) -> String {
+    format!(
+        "term={} leader={} lii={} lit={} type={} offset={} len={} total={} done={}",
+        req.term, req.leader_id, req.last_included_index, req.last_included_term,
+        proto::SnapshotDataType::try_from(req.snapshot_data_type)
+            .map_or_else(|_| format!("Unknown({})", req.snapshot_data_type), |t| format!("{:?}", t)),
+        req.offset, req.data.len(), req.total_size, req.done
+    )
 }
 
 #[tonic::async_trait]
@@ -49,12 +501,15 @@ impl proto::consensus_rpc_server::ConsensusRpc for Server {
     ) -> Result<tonic::Response<proto::AppendEntriesResponse>, tonic::Status> {
         let addr = request.remote_addr(); // Returns Option<SocketAddr>
         info!(
-            "Handle append entries from {:?}, request: {:?}",
-            &addr, &request
+            "Handle append entries from {:?}, request: {}",
+            &addr, AppendEntriesSummary::of(request.get_ref())
         );
         
         let mut consensus_guard = self.consensus.lock().await; // Lock TokioMutex
-        let response_data = consensus_guard.handle_append_entries_rpc(request.get_ref()).await; // Pass &proto::AppendEntriesRequest
+        let req = request.get_ref();
+        consensus_guard.check_request("AppendEntries", req.leader_id, |term, last| sanity::check_append_entries(req, term, last)).await
+            .map_err(tonic::Status::invalid_argument)?;
+        let response_data = consensus_guard.handle_append_entries_rpc(req).await; // Pass &proto::AppendEntriesRequest
         
         let response = tonic::Response::new(response_data);
         info!(
@@ -75,7 +530,10 @@ impl proto::consensus_rpc_server::ConsensusRpc for Server {
         );
 
         let mut consensus_guard = self.consensus.lock().await;
-        let response_data = consensus_guard.handle_request_vote_rpc(request.get_ref()).await;
+        let req = request.get_ref();
+        consensus_guard.check_request("RequestVote", req.candidate_id, |term, last| sanity::check_request_vote(req, term, last)).await
+            .map_err(tonic::Status::invalid_argument)?;
+        let response_data = consensus_guard.handle_request_vote_rpc(req).await;
         
         let response = tonic::Response::new(response_data);
         info!(
@@ -91,12 +549,15 @@ impl proto::consensus_rpc_server::ConsensusRpc for Server {
     ) -> Result<tonic::Response<proto::InstallSnapshotResponse>, tonic::Status> {
         let addr = request.remote_addr();
         info!(
-            "Handle install snapshot from {:?}, request: {:?}",
-            &addr, &request
+            "Handle install snapshot from {:?}, request: {}",
+            &addr, install_snapshot_summary(request.get_ref())
         );
         
         let mut consensus_guard = self.consensus.lock().await;
-        let response_data = consensus_guard.handle_install_snapshot_rpc(request.get_ref()).await;
+        let req = request.get_ref();
+        consensus_guard.check_request("InstallSnapshot", req.leader_id, |term, last| sanity::check_install_snapshot(req, term, last)).await
+            .map_err(tonic::Status::invalid_argument)?;
+        let response_data = consensus_guard.handle_install_snapshot_rpc(req).await;
 
         let response = tonic::Response::new(response_data);
         info!(
@@ -105,10 +566,128 @@ impl proto::consensus_rpc_server::ConsensusRpc for Server {
         );
         Ok(response)
     }
+
+    async fn coalesced_heartbeat(
+        &self,
+        request: tonic::Request<proto::CoalescedHeartbeatRequest>,
+    ) -> Result<tonic::Response<proto::CoalescedHeartbeatResponse>, tonic::Status> {
+        let addr = request.remote_addr();
+        debug!(
+            "Handle coalesced heartbeat from {:?}, groups: {}",
+            &addr, request.get_ref().heartbeats.len()
+        );
+
+        let response_data = match &self.groups {
+            Some(groups) => groups.handle_coalesced_heartbeat(request.get_ref()).await,
+            None => {
+                // 没有注册表时只有本节点所在的组
+                let mut responses = Vec::new();
+                for heartbeat in request.get_ref().heartbeats.iter() {
+                    let mut consensus_guard = self.consensus.lock().await;
+                    match &heartbeat.request {
+                        Some(ae_request) if heartbeat.group_id == consensus_guard.group_id
+                            && (heartbeat.server_id == 0 || heartbeat.server_id == consensus_guard.server_id) => {
+                            let check = |term, last| sanity::check_append_entries(ae_request, term, last);
+                            if consensus_guard.check_request("heartbeat", ae_request.leader_id, check).await.is_err() {
+                                continue;
+                            }
+                            let response = consensus_guard.handle_append_entries_rpc(ae_request).await;
+                            responses.push(proto::GroupHeartbeatResponse { group_id: heartbeat.group_id, response: Some(response) });
+                        }
+                        _ => warn!("Received heartbeat for unknown group {}", heartbeat.group_id),
+                    }
+                }
+                proto::CoalescedHeartbeatResponse { responses }
+            }
+        };
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn ping(
+        &self,
+        _request: tonic::Request<proto::PingRequest>,
+    ) -> Result<tonic::Response<proto::PingResponse>, tonic::Status> {
+        Ok(tonic::Response::new(proto::PingResponse {}))
+    }
+
+    async fn get_peer_latency(
+        &self,
+        request: tonic::Request<proto::GetPeerLatencyRequest>,
+    ) -> Result<tonic::Response<proto::GetPeerLatencyResponse>, tonic::Status> {
+        let response_data = placement::probe_peer_latency(&self.consensus, request.get_ref().probe).await;
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn timeout_now(
+        &self,
+        request: tonic::Request<proto::TimeoutNowRequest>,
+    ) -> Result<tonic::Response<proto::TimeoutNowResponse>, tonic::Status> {
+        let addr = request.remote_addr();
+        info!(
+            "Handle timeout now from {:?}, request: {:?}",
+            &addr, &request
+        );
+
+        let mut consensus_guard = self.consensus.lock().await;
+        let req = request.get_ref();
+        consensus_guard.check_request("TimeoutNow", req.leader_id, |term, _| sanity::check_term(term, req.term)).await
+            .map_err(tonic::Status::invalid_argument)?;
+        let response_data = consensus_guard.handle_timeout_now_rpc(req).await;
+        drop(consensus_guard);
+        // 选举需要等待其他节点的投票，放到后台执行，先回复 Leader
+        if response_data.success {
+            let consensus = self.consensus.clone();
+            tokio::spawn(async move {
+                consensus.lock().await.handle_election_timeout().await;
+            });
+        }
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn retire(
+        &self,
+        request: tonic::Request<proto::RetireRequest>,
+    ) -> Result<tonic::Response<proto::RetireResponse>, tonic::Status> {
+        let addr = request.remote_addr();
+        info!(
+            "Handle retire from {:?}, request: {:?}",
+            &addr, &request
+        );
+
+        let request = request.into_inner();
+        let mut consensus_guard = self.consensus.lock().await;
+        consensus_guard.check_request("Retire", request.leader_id, |term, _| sanity::check_term(term, request.term)).await
+            .map_err(tonic::Status::invalid_argument)?;
+        let response_data = consensus_guard.handle_retire_rpc(&request).await;
+        drop(consensus_guard);
+        // 先回复 Leader 再关闭节点
+        if response_data.success && request.shutdown {
+            let consensus = self.consensus.clone();
+            tokio::spawn(async move {
+                consensus.lock().await.retire(request.wipe_data).await;
+            });
+        }
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn fetch_snapshot(
+        &self,
+        request: tonic::Request<proto::FetchSnapshotRequest>,
+    ) -> Result<tonic::Response<proto::FetchSnapshotResponse>, tonic::Status> {
+        let req = request.get_ref();
+        debug!("Handle fetch snapshot from {:?}: type={} lii={} offset={}",
+            request.remote_addr(), req.snapshot_data_type, req.last_included_index, req.offset);
+        let response_data = consensus::Consensus::fetch_snapshot(&self.consensus, req).await;
+        Ok(tonic::Response::new(response_data))
+    }
 }
 
 #[tonic::async_trait]
 impl proto::management_rpc_server::ManagementRpc for Server {
+    type WatchLeaderStream = std::pin::Pin<Box<dyn futures::Stream<Item = Result<proto::GetLeaderResponse, tonic::Status>> + Send>>;
+    type ExportSnapshotStream = std::pin::Pin<Box<dyn futures::Stream<Item = Result<proto::ExportSnapshotChunk, tonic::Status>> + Send>>;
+    type SubscribeCommittedStream = std::pin::Pin<Box<dyn futures::Stream<Item = Result<proto::SubscribeCommittedResponse, tonic::Status>> + Send>>;
+
     async fn get_leader(
         &self,
         request: tonic::Request<proto::GetLeaderRequest>,
@@ -119,9 +698,8 @@ impl proto::management_rpc_server::ManagementRpc for Server {
             &addr, &request
         );
 
-        let mut consensus_guard = self.consensus.lock().await;
-        let response_data = consensus_guard.handle_get_leader_rpc(request.get_ref());
-        
+        let response_data = self.view.borrow().leader_response();
+
         let response = tonic::Response::new(response_data);
         info!(
             "Handle get leader from {:?}, response: {:?}",
@@ -140,8 +718,13 @@ impl proto::management_rpc_server::ManagementRpc for Server {
             &addr, &request
         );
 
-        let mut consensus_guard = self.consensus.lock().await;
-        let response_data = consensus_guard.handle_get_configuration_rpc(request.get_ref());
+        // 强一致请求需要 Leader 确认领导权，其余请求直接读取状态视图
+        let response_data = if request.get_ref().consistent {
+            let mut consensus_guard = self.consensus.lock().await;
+            consensus_guard.handle_get_configuration_rpc(request.get_ref()).await
+        } else {
+            self.view.borrow().configuration_response()
+        };
 
         let response = tonic::Response::new(response_data);
         info!(
@@ -151,6 +734,24 @@ impl proto::management_rpc_server::ManagementRpc for Server {
         Ok(response)
     }
 
+    async fn get_configuration_history(
+        &self,
+        request: tonic::Request<proto::GetConfigurationHistoryRequest>,
+    ) -> Result<tonic::Response<proto::GetConfigurationHistoryResponse>, tonic::Status> {
+        let consensus_guard = self.consensus.lock().await;
+        let response_data = consensus_guard.handle_get_configuration_history_rpc(request.get_ref());
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn get_config_change_status(
+        &self,
+        request: tonic::Request<proto::GetConfigChangeStatusRequest>,
+    ) -> Result<tonic::Response<proto::GetConfigChangeStatusResponse>, tonic::Status> {
+        let consensus_guard = self.consensus.lock().await;
+        let response_data = consensus_guard.handle_get_config_change_status_rpc(request.get_ref());
+        Ok(tonic::Response::new(response_data))
+    }
+
     async fn set_configuration(
         &self,
         request: tonic::Request<proto::SetConfigurationRequest>,
@@ -162,9 +763,18 @@ impl proto::management_rpc_server::ManagementRpc for Server {
             &addr, &request
         );
 
-        let mut consensus_guard = self.consensus.lock().await;
-        let response_data = consensus_guard.handle_set_configuration_rpc(request.get_ref()).await;
-        
+        let mut response_data = {
+            let mut consensus_guard = self.consensus.lock().await;
+            let response_data = consensus_guard.handle_set_configuration_rpc(request.get_ref()).await;
+            // 在等待变更完成之前记录，客户端断开时记录也不会丢失
+            consensus_guard.audit_command(&caller_addr(addr), proto::ManagementCommand::SetConfiguration, request.get_ref(), response_data.success, response_data.error.clone());
+            response_data
+        };
+        if response_data.success && request.get_ref().wait_for_completion {
+            let state = consensus::Consensus::wait_for_config_change(&self.consensus, response_data.config_index, config::CONFIG_CHANGE_WAIT_TIMEOUT).await;
+            response_data.state = state as i32;
+        }
+
         let response = tonic::Response::new(response_data);
         info!(
             "Handle set configuration from {:?}, response: {:?}",
@@ -180,12 +790,35 @@ impl proto::management_rpc_server::ManagementRpc for Server {
     ) -> Result<tonic::Response<proto::ProposeResponse>, tonic::Status> {
         let addr = request.remote_addr();
         info!(
-            "Handle propose from {:?}, request: {:?}",
-            &addr, &request
+            "Handle propose from {:?}, data size: {}",
+            &addr, request.get_ref().data.len()
         );
 
-        let mut consensus_guard = self.consensus.lock().await;
-        let response_data = consensus_guard.handle_propose_rpc(request.get_ref()).await;
+        // 截止时间已到的请求不再追加，客户端已经不会等待它的结果
+        let deadline = client_deadline(&request);
+        if deadline_passed(deadline) {
+            warn!("Propose from {:?} arrived after its deadline, not proposing.", &addr);
+            return Err(tonic::Status::deadline_exceeded("deadline exceeded before the entry was proposed"));
+        }
+        // 先按客户端连接轮转排队，排队同样受截止时间限制
+        let turn = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, self.proposal_scheduler.acquire(addr)).await {
+                Ok(turn) => turn,
+                Err(_) => {
+                    warn!("Propose from {:?}: deadline exceeded while queued behind other clients, not proposing.", &addr);
+                    return Err(tonic::Status::deadline_exceeded("deadline exceeded before the entry was proposed"));
+                }
+            },
+            None => self.proposal_scheduler.acquire(addr).await,
+        };
+        let response_data = Consensus::propose_and_wait_until(&self.consensus, request.get_ref(), deadline, turn).await;
+        if response_data.success && request.get_ref().wait_for_commit && response_data.result.is_none() && deadline_passed(deadline) {
+            warn!("Propose from {:?}: deadline exceeded waiting for entry {:?} to be applied.", &addr, response_data.read_token);
+            return Err(tonic::Status::deadline_exceeded(format!(
+                "deadline exceeded waiting for entry {} to be applied, outcome unknown",
+                response_data.read_token.unwrap_or_default(),
+            )));
+        }
 
         let response = tonic::Response::new(response_data);
         info!(
@@ -194,27 +827,227 @@ impl proto::management_rpc_server::ManagementRpc for Server {
         );
         Ok(response)
     }
+
+    async fn read(
+        &self,
+        request: tonic::Request<proto::ReadRequest>,
+    ) -> Result<tonic::Response<proto::ReadResponse>, tonic::Status> {
+        let addr = request.remote_addr();
+        info!(
+            "Handle read from {:?}, request: {:?}",
+            &addr, &request
+        );
+
+        let deadline = client_deadline(&request);
+        if let Some(read_token) = request.get_ref().read_token {
+            let wait_until = tokio::time::Instant::now() + config::READ_TOKEN_WAIT_TIMEOUT;
+            if !consensus::Consensus::wait_for_applied_until(&self.consensus, read_token, deadline.map_or(wait_until, |d| d.min(wait_until))).await {
+                warn!("Read from {:?}: timed out waiting for read token {}", &addr, read_token);
+            }
+        }
+        if deadline_passed(deadline) {
+            return Err(tonic::Status::deadline_exceeded("deadline exceeded before the read was served"));
+        }
+        let consensus_guard = self.consensus.lock().await;
+        let response_data = consensus_guard.handle_read_rpc(request.get_ref());
+
+        let response = tonic::Response::new(response_data);
+        info!(
+            "Handle read from {:?}, response: {:?}",
+            &addr, &response
+        );
+        Ok(response)
+    }
+
+    async fn trigger_snapshot(
+        &self,
+        request: tonic::Request<proto::TriggerSnapshotRequest>,
+    ) -> Result<tonic::Response<proto::TriggerSnapshotResponse>, tonic::Status> {
+        let addr = request.remote_addr();
+        info!("Handle trigger snapshot from {:?}", &addr);
+
+        let response_data = consensus::Consensus::trigger_snapshot(&self.consensus, request.get_ref()).await;
+        self.consensus.lock().await.audit_command(&caller_addr(addr), proto::ManagementCommand::TriggerSnapshot, request.get_ref(), response_data.success, response_data.error.clone());
+
+        let response = tonic::Response::new(response_data);
+        info!(
+            "Handle trigger snapshot from {:?}, response: {:?}",
+            &addr, &response
+        );
+        Ok(response)
+    }
+
+    async fn get_snapshot_status(
+        &self,
+        request: tonic::Request<proto::GetSnapshotStatusRequest>,
+    ) -> Result<tonic::Response<proto::GetSnapshotStatusResponse>, tonic::Status> {
+        // 恢复快照时共识模块的锁一直被占用，只返回恢复进度
+        if self.restore_progress.in_progress() {
+            let restore = self.restore_progress.to_proto();
+            return Ok(tonic::Response::new(proto::GetSnapshotStatusResponse { restore, ..Default::default() }));
+        }
+        let consensus_guard = self.consensus.lock().await;
+        let response_data = consensus_guard.handle_get_snapshot_status_rpc(request.get_ref());
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn get_latency_stats(
+        &self,
+        request: tonic::Request<proto::GetLatencyStatsRequest>,
+    ) -> Result<tonic::Response<proto::GetLatencyStatsResponse>, tonic::Status> {
+        let consensus_guard = self.consensus.lock().await;
+        let response_data = consensus_guard.handle_get_latency_stats_rpc(request.get_ref());
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn suggest_leader(
+        &self,
+        request: tonic::Request<proto::SuggestLeaderRequest>,
+    ) -> Result<tonic::Response<proto::SuggestLeaderResponse>, tonic::Status> {
+        let addr = request.remote_addr();
+        info!(
+            "Handle suggest leader from {:?}, request: {:?}",
+            &addr, &request
+        );
+
+        let response_data = placement::suggest_leader(&self.consensus, request.get_ref().transfer).await;
+        // 只是查询建议时不改变集群状态，不需要审计
+        if request.get_ref().transfer {
+            self.consensus.lock().await.audit_command(&caller_addr(addr), proto::ManagementCommand::TransferLeadership, request.get_ref(), response_data.transferred, response_data.error.clone());
+        }
+
+        let response = tonic::Response::new(response_data);
+        info!(
+            "Handle suggest leader from {:?}, response: {:?}",
+            &addr, &response
+        );
+        Ok(response)
+    }
+
+    async fn decommission(
+        &self,
+        request: tonic::Request<proto::DecommissionRequest>,
+    ) -> Result<tonic::Response<proto::DecommissionResponse>, tonic::Status> {
+        let addr = request.remote_addr();
+        info!(
+            "Handle decommission from {:?}, request: {:?}",
+            &addr, &request
+        );
+
+        let response_data = decommission::decommission(&self.consensus, request.get_ref()).await;
+        self.consensus.lock().await.audit_command(&caller_addr(addr), proto::ManagementCommand::Decommission, request.get_ref(), response_data.success, response_data.error.clone());
+
+        let response = tonic::Response::new(response_data);
+        info!(
+            "Handle decommission from {:?}, response: {:?}",
+            &addr, &response
+        );
+        Ok(response)
+    }
+
+    async fn get_command_audit(
+        &self,
+        request: tonic::Request<proto::GetCommandAuditRequest>,
+    ) -> Result<tonic::Response<proto::GetCommandAuditResponse>, tonic::Status> {
+        let response_data = consensus::Consensus::get_command_audit(&self.consensus, request.get_ref()).await;
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn get_version(
+        &self,
+        _request: tonic::Request<proto::GetVersionRequest>,
+    ) -> Result<tonic::Response<proto::GetVersionResponse>, tonic::Status> {
+        Ok(tonic::Response::new(version::build_info()))
+    }
+
+    async fn get_status(
+        &self,
+        request: tonic::Request<proto::GetStatusRequest>,
+    ) -> Result<tonic::Response<proto::GetStatusResponse>, tonic::Status> {
+        let mut consensus_guard = self.consensus.lock().await;
+        let response_data = consensus_guard.handle_get_status_rpc(request.get_ref()).await;
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn set_partition(
+        &self,
+        request: tonic::Request<proto::SetPartitionRequest>,
+    ) -> Result<tonic::Response<proto::SetPartitionResponse>, tonic::Status> {
+        if !cfg!(feature = "partition-rpc") {
+            return Err(tonic::Status::unimplemented("SetPartition requires the partition-rpc feature"));
+        }
+        let addr = request.remote_addr();
+        info!("Handle set partition from {:?}, request: {:?}", &addr, &request);
+        let response_data = self.consensus.lock().await.handle_set_partition_rpc(request.get_ref());
+        Ok(tonic::Response::new(response_data))
+    }
+
+    async fn watch_leader(
+        &self,
+        request: tonic::Request<proto::WatchLeaderRequest>,
+    ) -> Result<tonic::Response<Self::WatchLeaderStream>, tonic::Status> {
+        info!("Handle watch leader from {:?}", request.remote_addr());
+        let stream = leader_changes(self.view.clone(), self.stop.clone()).map(Ok);
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+
+    async fn export_snapshot(
+        &self,
+        request: tonic::Request<proto::ExportSnapshotRequest>,
+    ) -> Result<tonic::Response<Self::ExportSnapshotStream>, tonic::Status> {
+        info!("Handle export snapshot from {:?}", request.remote_addr());
+        let (mut writer, reader) = tokio::io::duplex(config::FETCH_SNAPSHOT_CHUNK_SIZE);
+        let consensus = Arc::clone(&self.consensus);
+        let export = tokio::spawn(async move { export::export_snapshot(&consensus, &mut writer).await });
+        Ok(tonic::Response::new(Box::pin(export_chunks(reader, export))))
+    }
+
+    async fn subscribe_committed(
+        &self,
+        request: tonic::Request<proto::SubscribeCommittedRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeCommittedStream>, tonic::Status> {
+        info!("Handle subscribe committed from {:?}, start index {:?}", request.remote_addr(), request.get_ref().start_index);
+        let stream = subscription::committed_entries(Arc::clone(&self.consensus), request.get_ref().start_index, self.stop.clone()).map(Ok);
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
     
 }
 
-#[derive(Debug, Clone)] 
-pub struct Client {}
+// 命令审计日志中的调用方，拿不到对端地址时为 unknown
+fn caller_addr(addr: Option<std::net::SocketAddr>) -> String {
+    addr.map_or_else(|| "unknown".to_string(), |addr| addr.to_string())
+}
+
+// 发往其他节点的 RPC 客户端。每个节点按自己的 RaftOptions.transport 建立连接，
+// 同一进程中的多个节点互不影响；命令行工具等外部调用方使用默认参数
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    transport: config::TransportOptions,
+}
 
 impl Client {
+    pub fn with_transport(transport: config::TransportOptions) -> Self {
+        Client { transport }
+    }
+
+    async fn connect(&self, addr: &str) -> Result<Channel, Box<dyn std::error::Error + Send + Sync>> {
+        connect(addr, &self.transport).await
+    }
+
     pub async fn append_entries(
         &mut self, // If client is stateless, could be &self
         req: proto::AppendEntriesRequest,
         addr: String,
     ) -> Result<proto::AppendEntriesResponse, Box<dyn std::error::Error+Send+Sync>> {
         let addr_clone = addr.clone();
-        let request_tonic = tonic::Request::new(req); // Renamed
         info!(
-            "send rpc append_entries to {}, request: {:?}",
-            &addr_clone, request_tonic
+            "send rpc append_entries to {}, request: {}",
+            &addr_clone, AppendEntriesSummary::of(&req)
         );
+        let request_tonic = tonic::Request::new(req); // Renamed
 
         // Consider creating client once per peer and reusing, or using a connection pool
-        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::connect(format!("http://{}", addr)).await?;
+        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::new(self.connect(&addr).await?);
         let response = client.append_entries(request_tonic).await?;
         info!(
             "send rpc append_entries to {}, response: {:?}",
@@ -236,7 +1069,7 @@ impl Client {
             &addr_clone, request_tonic
         );
 
-        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::connect(format!("http://{}", addr)).await?;
+        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::new(self.connect(&addr).await?);
         let response = client.request_vote(request_tonic).await?;
         info!(
             "send rpc request_vote to {}, response: {:?}",
@@ -252,13 +1085,13 @@ impl Client {
         addr: String,
     ) -> Result<proto::InstallSnapshotResponse, Box<dyn std::error::Error+Send+Sync>> {
         let addr_clone = addr.clone();
-        let request_tonic = tonic::Request::new(req); // Renamed
         info!(
-            "send rpc install_snapshot to {}, request: {:?}",
-            &addr_clone, request_tonic
+            "send rpc install_snapshot to {}, request: {}",
+            &addr_clone, install_snapshot_summary(&req)
         );
+        let request_tonic = tonic::Request::new(req); // Renamed
 
-        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::connect(format!("http://{}", addr)).await?;
+        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::new(self.connect(&addr).await?);
         let response = client.install_snapshot(request_tonic).await?;
         info!(
             "send rpc install_snapshot to {}, response: {:?}",
@@ -268,16 +1101,251 @@ impl Client {
         Ok(response.into_inner())
     }
 
+    pub async fn coalesced_heartbeat(
+        &self,
+        req: proto::CoalescedHeartbeatRequest,
+        addr: String,
+    ) -> Result<proto::CoalescedHeartbeatResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::new(self.connect(&addr).await?);
+        let response = client.coalesced_heartbeat(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn ping(
+        &self,
+        req: proto::PingRequest,
+        addr: String,
+    ) -> Result<proto::PingResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::new(self.connect(&addr).await?);
+        let response = client.ping(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn get_peer_latency(
+        &self,
+        req: proto::GetPeerLatencyRequest,
+        addr: String,
+    ) -> Result<proto::GetPeerLatencyResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::new(self.connect(&addr).await?);
+        let response = client.get_peer_latency(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn timeout_now(
+        &self,
+        req: proto::TimeoutNowRequest,
+        addr: String,
+    ) -> Result<proto::TimeoutNowResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::new(self.connect(&addr).await?);
+        let response = client.timeout_now(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn retire(
+        &self,
+        req: proto::RetireRequest,
+        addr: String,
+    ) -> Result<proto::RetireResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::new(self.connect(&addr).await?);
+        let response = client.retire(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn fetch_snapshot(
+        &self,
+        req: proto::FetchSnapshotRequest,
+        addr: String,
+    ) -> Result<proto::FetchSnapshotResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::consensus_rpc_client::ConsensusRpcClient::new(self.connect(&addr).await?);
+        let response = client.fetch_snapshot(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
     pub async fn propose(
         &self,
         req: proto::ProposeRequest,
         addr: String,
     ) -> Result<proto::ProposeResponse, Box<dyn std::error::Error + Send + Sync>> {
-        let mut client = proto::management_rpc_client::ManagementRpcClient::connect(format!("http://{}", addr)).await?;
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
         let response = client.propose(tonic::Request::new(req)).await?;
         Ok(response.into_inner())
     }
 
+    // 带截止时间的 Propose：服务端在截止时间之前返回，超时时得到 DeadlineExceeded
+    pub async fn propose_with_timeout(
+        &self,
+        req: proto::ProposeRequest,
+        addr: String,
+        timeout: Duration,
+    ) -> Result<proto::ProposeResponse, tonic::Status> {
+        let channel = self.connect(&addr).await.map_err(|e| tonic::Status::unavailable(e.to_string()))?;
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(channel);
+        let mut request = tonic::Request::new(req);
+        request.set_timeout(timeout);
+        Ok(client.propose(request).await?.into_inner())
+    }
+
+    /// 调用 Management RPC 的 Read 方法
+    pub async fn read(
+        &self,
+        req: proto::ReadRequest,
+        addr: String,
+    ) -> Result<proto::ReadResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.read(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 GetConfigurationHistory 方法
+    pub async fn get_configuration_history(
+        &self,
+        req: proto::GetConfigurationHistoryRequest,
+        addr: String,
+    ) -> Result<proto::GetConfigurationHistoryResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.get_configuration_history(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 GetConfigChangeStatus 方法
+    pub async fn get_config_change_status(
+        &self,
+        req: proto::GetConfigChangeStatusRequest,
+        addr: String,
+    ) -> Result<proto::GetConfigChangeStatusResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.get_config_change_status(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 TriggerSnapshot 方法
+    pub async fn trigger_snapshot(
+        &self,
+        req: proto::TriggerSnapshotRequest,
+        addr: String,
+    ) -> Result<proto::TriggerSnapshotResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.trigger_snapshot(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 GetSnapshotStatus 方法
+    pub async fn get_snapshot_status(
+        &self,
+        req: proto::GetSnapshotStatusRequest,
+        addr: String,
+    ) -> Result<proto::GetSnapshotStatusResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.get_snapshot_status(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 GetLatencyStats 方法
+    pub async fn get_latency_stats(
+        &self,
+        req: proto::GetLatencyStatsRequest,
+        addr: String,
+    ) -> Result<proto::GetLatencyStatsResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.get_latency_stats(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 SuggestLeader 方法
+    pub async fn suggest_leader(
+        &self,
+        req: proto::SuggestLeaderRequest,
+        addr: String,
+    ) -> Result<proto::SuggestLeaderResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.suggest_leader(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn decommission(
+        &self,
+        req: proto::DecommissionRequest,
+        addr: String,
+    ) -> Result<proto::DecommissionResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.decommission(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 GetCommandAudit 方法
+    pub async fn get_command_audit(
+        &self,
+        req: proto::GetCommandAuditRequest,
+        addr: String,
+    ) -> Result<proto::GetCommandAuditResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.get_command_audit(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn get_version(
+        &self,
+        req: proto::GetVersionRequest,
+        addr: String,
+    ) -> Result<proto::GetVersionResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.get_version(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn get_status(
+        &self,
+        req: proto::GetStatusRequest,
+        addr: String,
+    ) -> Result<proto::GetStatusResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.get_status(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn set_partition(
+        &self,
+        req: proto::SetPartitionRequest,
+        addr: String,
+    ) -> Result<proto::SetPartitionResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.set_partition(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 ExportSnapshot 方法，返回快照导出的字节流
+    pub async fn export_snapshot(
+        &self,
+        req: proto::ExportSnapshotRequest,
+        addr: String,
+    ) -> Result<tonic::Streaming<proto::ExportSnapshotChunk>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.export_snapshot(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 SubscribeCommitted 方法，返回已提交条目的推送流
+    pub async fn subscribe_committed(
+        &self,
+        req: proto::SubscribeCommittedRequest,
+        addr: String,
+    ) -> Result<tonic::Streaming<proto::SubscribeCommittedResponse>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.subscribe_committed(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
+    /// 调用 Management RPC 的 WatchLeader 方法，返回 Leader 变化的推送流
+    pub async fn watch_leader(
+        &self,
+        req: proto::WatchLeaderRequest,
+        addr: String,
+    ) -> Result<tonic::Streaming<proto::GetLeaderResponse>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
+        let response = client.watch_leader(tonic::Request::new(req)).await?;
+        Ok(response.into_inner())
+    }
+
     /// 调用 Management RPC 的 GetLeader 方法
     pub async fn get_leader(
         &self, // 这个方法是无状态的，所以用 &self 即可
@@ -285,7 +1353,7 @@ impl Client {
         addr: String,
     ) -> Result<proto::GetLeaderResponse, Box<dyn std::error::Error + Send + Sync>> {
         // 注意：这里需要使用 ManagementRpcClient
-        let mut client = proto::management_rpc_client::ManagementRpcClient::connect(format!("http://{}", addr)).await?;
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
         let response = client.get_leader(tonic::Request::new(req)).await?;
         Ok(response.into_inner())
     }
@@ -296,7 +1364,7 @@ impl Client {
         req: proto::GetConfigurationRequest,
         addr: String,
     ) -> Result<proto::GetConfigurationResponse, Box<dyn std::error::Error + Send + Sync>> {
-        let mut client = proto::management_rpc_client::ManagementRpcClient::connect(format!("http://{}", addr)).await?;
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
         let response = client.get_configuration(tonic::Request::new(req)).await?;
         Ok(response.into_inner())
     }
@@ -307,8 +1375,170 @@ impl Client {
         req: proto::SetConfigurationRequest,
         addr: String,
     ) -> Result<proto::SetConfigurationResponse, Box<dyn std::error::Error + Send + Sync>> {
-        let mut client = proto::management_rpc_client::ManagementRpcClient::connect(format!("http://{}", addr)).await?;
+        let mut client = proto::management_rpc_client::ManagementRpcClient::new(self.connect(&addr).await?);
         let response = client.set_configuration(tonic::Request::new(req)).await?;
         Ok(response.into_inner())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_append_entries_summary() {
+        let entry = |index: u64, data: &[u8]| proto::LogEntry {
+            term: 2,
+            index,
+            entry_type: proto::EntryType::Data as i32,
+            data: data.to_vec(),
+            timestamp_ms: None,
+        };
+        let req = proto::AppendEntriesRequest {
+            term: 2,
+            leader_id: 1,
+            prev_log_index: 4,
+            prev_log_term: 1,
+            entries: vec![entry(5, b"abc"), entry(6, b"de")],
+            leader_commit: 4,
+            leader_time_us: None,
+        };
+        let summary = AppendEntriesSummary::of(&req);
+        assert_eq!(summary.last_index(), 6);
+        assert_eq!(summary.to_string(), "term=2 leader=1 prev=(4, 1) commit=4 entries=[5, 6] (5 bytes)");
+
+        let heartbeat = AppendEntriesSummary::of(&proto::AppendEntriesRequest { entries: Vec::new(), ..req });
+        assert_eq!(heartbeat.last_index(), 4);
+        assert!(heartbeat.to_string().ends_with("heartbeat"));
+    }
+
+    #[tokio::test]
+    #[allow(clippy::result_large_err)]
+    async fn test_server_hooks() {
+        // 中间件给请求加上标记，拦截器要求标记和令牌都存在
+        let hooks = ServerHooks::new()
+            .layer(tower::layer::layer_fn(|inner: BoxHttpService| {
+                inner.map_request(|mut req: http::Request<tonic::body::Body>| {
+                    req.headers_mut().insert("x-layer", http::HeaderValue::from_static("1"));
+                    req
+                })
+            }))
+            .interceptor(|req: tonic::Request<()>| {
+                if req.metadata().get("x-layer").is_none() {
+                    return Err(tonic::Status::internal("layer did not run"));
+                }
+                match req.metadata().get("authorization") {
+                    Some(token) if token == "secret" => Ok(req),
+                    _ => Err(tonic::Status::unauthenticated("missing token")),
+                }
+            });
+        let inner = tower::service_fn(|_req: http::Request<tonic::body::Body>| async {
+            Ok::<_, std::convert::Infallible>(http::Response::new(tonic::body::Body::empty()))
+        });
+        let service = tower::Layer::layer(&hooks, inner);
+
+        let request = |token: Option<&'static str>| {
+            let mut req = http::Request::new(tonic::body::Body::empty());
+            if let Some(token) = token {
+                req.headers_mut().insert("authorization", http::HeaderValue::from_static(token));
+            }
+            req
+        };
+        let rejected = service.clone().oneshot(request(None)).await.unwrap();
+        let code = rejected.headers().get("grpc-status").map(|v| v.to_str().unwrap().to_string());
+        assert_eq!(code, Some((tonic::Code::Unauthenticated as i32).to_string()));
+
+        let accepted = service.oneshot(request(Some("secret"))).await.unwrap();
+        assert!(accepted.headers().get("grpc-status").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_leader_changes_stream() {
+        let server = |id: u64| proto::ServerInfo { server_id: id, server_addr: format!("[::1]:{}", id), zone: None };
+        let (view_tx, view_rx) = watch::channel(consensus::StateView { state: consensus::State::Follower, leader: None, servers: vec![server(1), server(2)], health: proto::NodeHealth::Ok, term: 1, commit_index: 0, algorithm: config::MembershipAlgorithm::default() });
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let mut changes = Box::pin(leader_changes(view_rx, stop_rx));
+
+        // 立即给出当前的 Leader（未知）
+        assert_eq!(changes.next().await.unwrap().leader, None);
+
+        // 只有配置变化时不推送
+        view_tx.send_modify(|view| view.servers.push(server(3)));
+        view_tx.send_modify(|view| view.leader = Some(server(2)));
+        assert_eq!(changes.next().await.unwrap().leader, Some(server(2)));
+
+        view_tx.send_modify(|view| view.leader = Some(server(3)));
+        assert_eq!(changes.next().await.unwrap().leader, Some(server(3)));
+
+        // 同一个节点在新的任期再次当选时推送，只有提交索引变化时不推送
+        view_tx.send_modify(|view| view.commit_index = 10);
+        view_tx.send_modify(|view| view.term = 2);
+        let change = changes.next().await.unwrap();
+        assert_eq!((change.leader, change.term, change.commit_index), (Some(server(3)), 2, 10));
+
+        // 节点停止后流结束
+        stop_tx.send_replace(true);
+        assert!(changes.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_propose_respects_client_deadline() {
+        assert_eq!(parse_grpc_timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_grpc_timeout("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("5x"), None);
+        assert_eq!(parse_grpc_timeout("m"), None);
+
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = consensus::tests::test_consensus(&storage, Box::new(state_machine::SimpleStateMachine::new())).await;
+        // 加入一个联系不上的节点，新条目无法提交
+        let last_log_index = {
+            let mut guard = consensus.lock().await;
+            guard.handle_election_timeout().await;
+            let last_log_index = guard.log.last_index(guard.snapshot.last_included_index());
+            let mut unreachable = crate::raft::peer::Peer::new(2, "[::1]:2".to_string());
+            unreachable.last_contact = Some(std::time::Instant::now());
+            guard.peer_manager.add(vec![unreachable], last_log_index);
+            last_log_index
+        };
+        let bound = bind_server("[::1]:0", None, true, Arc::clone(&consensus), None, ServerHooks::new(), &config::TransportOptions::default()).await.unwrap();
+        let addr = bound.addr().to_string();
+        let stop = consensus.lock().await.stop_signal();
+        let serving = tokio::spawn(bound.serve(stop));
+
+        // 剩余时间不超过余量，来不及等待提交：不追加条目（客户端自己的计时器也可能先到期）
+        let propose = proto::ProposeRequest { data: b"late".to_vec(), wait_for_commit: true };
+        let status = Client::default().propose_with_timeout(propose.clone(), addr.clone(), config::CLIENT_DEADLINE_MARGIN / 2).await.unwrap_err();
+        assert!(matches!(status.code(), tonic::Code::DeadlineExceeded | tonic::Code::Cancelled), "{:?}", status);
+        assert_eq!(consensus.lock().await.log.last_index(0), last_log_index);
+
+        // 等待提交时截止时间到：返回 DeadlineExceeded 而不是 Cancelled，条目已经追加
+        let status = Client::default().propose_with_timeout(propose, addr, Duration::from_millis(300)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded, "{:?}", status);
+        assert_eq!(consensus.lock().await.log.last_index(0), last_log_index + 1);
+        serving.abort();
+    }
+
+    #[tokio::test]
+    async fn test_tuned_transport_round_trip() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = consensus::tests::test_consensus(&storage, Box::new(state_machine::SimpleStateMachine::new())).await;
+        let transport = config::TransportOptions {
+            keepalive_interval: Some(Duration::from_secs(10)),
+            keepalive_timeout: Some(Duration::from_secs(5)),
+            keepalive_while_idle: true,
+            tcp_keepalive: Some(Duration::from_secs(30)),
+            adaptive_window: true,
+            concurrency_limit: Some(16),
+            connect_timeout: Some(Duration::from_secs(5)),
+        };
+        let bound = bind_server("[::1]:0", None, true, Arc::clone(&consensus), None, ServerHooks::new(), &transport).await.unwrap();
+        let addr = bound.addr().to_string();
+        let stop = consensus.lock().await.stop_signal();
+        let serving = tokio::spawn(bound.serve(stop));
+
+        let status = Client::with_transport(transport).get_snapshot_status(proto::GetSnapshotStatusRequest {}, addr).await.unwrap();
+        assert_eq!(status.last_included_index, 0);
+        serving.abort();
+    }
+}
@@ -0,0 +1,137 @@
+use crate::raft::{codec, config, proto};
+
+// 对来自网络的任期和索引做合理性检查。正常的节点不会发出这些值，出现时说明对方的数据已损坏或者是恶意流量；
+// 直接拒绝请求，避免后续的算术运算溢出（例如 next_index - 1）或者把本节点的任期推到一个荒谬的值
+// 检查只排除明显不可能的值，正常的落后、冲突等情况仍然交给 Raft 协议本身处理
+
+// 任期比本地大 SANITY_MAX_TERM_JUMP 以上时拒绝
+pub fn check_term(current_term: u64, term: u64) -> Result<(), String> {
+    if term > current_term.saturating_add(config::SANITY_MAX_TERM_JUMP) {
+        return Err(format!("term {} jumps more than {} beyond the current term {}", term, config::SANITY_MAX_TERM_JUMP, current_term));
+    }
+    Ok(())
+}
+
+// 索引比本地最后一条日志大 SANITY_MAX_INDEX_JUMP 以上时拒绝
+pub fn check_index(name: &str, index: u64, last_index: u64) -> Result<(), String> {
+    if index > last_index.saturating_add(config::SANITY_MAX_INDEX_JUMP) {
+        return Err(format!("{} {} is more than {} beyond the last log index {}", name, index, config::SANITY_MAX_INDEX_JUMP, last_index));
+    }
+    Ok(())
+}
+
+pub fn check_append_entries(request: &proto::AppendEntriesRequest, current_term: u64, last_index: u64) -> Result<(), String> {
+    check_term(current_term, request.term)?;
+    check_index("prev_log_index", request.prev_log_index, last_index)?;
+    check_index("leader_commit", request.leader_commit, last_index)?;
+    if request.prev_log_term > request.term {
+        return Err(format!("prev_log_term {} is newer than the request term {}", request.prev_log_term, request.term));
+    }
+    // 条目必须从 prev_log_index + 1 开始连续编号，任期不递减且不超过请求的任期
+    let mut previous_term = request.prev_log_term;
+    for (offset, entry) in request.entries.iter().enumerate() {
+        let expected = request.prev_log_index.checked_add(offset as u64 + 1)
+            .ok_or_else(|| format!("entry index overflows after prev_log_index {}", request.prev_log_index))?;
+        if entry.index != expected {
+            return Err(format!("entry at offset {} has index {}, expected {}", offset, entry.index, expected));
+        }
+        // 不认识的条目类型（例如升级中新版本 Leader 写入的类型）照常复制，应用时跳过；
+        // 无法解析的配置条目在追加时就会让节点崩溃
+        if codec::entry_type(entry) == Ok(proto::EntryType::Configuration) {
+            config::Config::try_from_data(&entry.data).map_err(|e| format!("entry {}: {}", entry.index, e))?;
+        }
+        if entry.term < previous_term || entry.term > request.term {
+            return Err(format!("entry {} has term {}, outside [{}, {}]", entry.index, entry.term, previous_term, request.term));
+        }
+        previous_term = entry.term;
+    }
+    Ok(())
+}
+
+pub fn check_request_vote(request: &proto::RequestVoteRequest, current_term: u64, last_index: u64) -> Result<(), String> {
+    check_term(current_term, request.term)?;
+    check_index("last_log_index", request.last_log_index, last_index)?;
+    if request.last_log_term > request.term {
+        return Err(format!("last_log_term {} is newer than the request term {}", request.last_log_term, request.term));
+    }
+    Ok(())
+}
+
+pub fn check_install_snapshot(request: &proto::InstallSnapshotRequest, current_term: u64, last_index: u64) -> Result<(), String> {
+    check_term(current_term, request.term)?;
+    check_index("last_included_index", request.last_included_index, last_index)?;
+    if request.last_included_term > request.term {
+        return Err(format!("last_included_term {} is newer than the request term {}", request.last_included_term, request.term));
+    }
+    match request.offset.checked_add(request.data.len() as u64) {
+        Some(end) if end <= request.total_size => Ok(()),
+        _ => Err(format!("chunk at offset {} with {} bytes exceeds the total size {}", request.offset, request.data.len(), request.total_size)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(index: u64, term: u64) -> proto::LogEntry {
+        proto::LogEntry { term, index, entry_type: proto::EntryType::Data as i32, data: Vec::new(), timestamp_ms: None }
+    }
+
+    #[test]
+    fn test_absurd_values_are_rejected() {
+        assert!(check_term(5, 6).is_ok());
+        assert!(check_term(5, 5 + config::SANITY_MAX_TERM_JUMP + 1).is_err());
+        assert!(check_term(u64::MAX, u64::MAX).is_ok());
+        assert!(check_index("index", u64::MAX, 10).is_err());
+
+        let request = proto::AppendEntriesRequest {
+            term: 3,
+            leader_id: 1,
+            prev_log_index: 4,
+            prev_log_term: 2,
+            entries: vec![entry(5, 2), entry(6, 3)],
+            leader_commit: 5,
+            leader_time_us: None,
+        };
+        assert!(check_append_entries(&request, 3, 4).is_ok());
+        // 落后的节点收到的 prev_log_index 比本地大很多也是正常的
+        assert!(check_append_entries(&request, 3, 0).is_ok());
+
+        let gap = proto::AppendEntriesRequest { entries: vec![entry(5, 2), entry(7, 3)], ..request.clone() };
+        assert!(check_append_entries(&gap, 3, 4).is_err());
+        let zero = proto::AppendEntriesRequest { prev_log_index: 0, entries: vec![entry(0, 2)], ..request.clone() };
+        assert!(check_append_entries(&zero, 3, 4).is_err());
+        let unknown_type = proto::AppendEntriesRequest {
+            entries: vec![proto::LogEntry { entry_type: 42, ..entry(5, 2) }],
+            ..request.clone()
+        };
+        // 不认识的类型照常复制，由应用时跳过
+        assert!(check_append_entries(&unknown_type, 3, 4).is_ok());
+        let bad_config = proto::AppendEntriesRequest {
+            entries: vec![proto::LogEntry { entry_type: proto::EntryType::Configuration as i32, data: b"{".to_vec(), ..entry(5, 2) }],
+            ..request.clone()
+        };
+        assert!(check_append_entries(&bad_config, 3, 4).is_err());
+        let future_term = proto::AppendEntriesRequest { entries: vec![entry(5, 4)], ..request.clone() };
+        assert!(check_append_entries(&future_term, 3, 4).is_err());
+        let overflow = proto::AppendEntriesRequest { prev_log_index: u64::MAX, ..request.clone() };
+        assert!(check_append_entries(&overflow, 3, u64::MAX - 1).is_err());
+
+        let vote = proto::RequestVoteRequest { term: 4, candidate_id: 2, last_log_term: 5, last_log_index: 3 };
+        assert!(check_request_vote(&vote, 3, 3).is_err());
+
+        let snapshot = proto::InstallSnapshotRequest {
+            term: 3,
+            leader_id: 1,
+            last_included_index: 9,
+            last_included_term: 2,
+            offset: u64::MAX,
+            data: vec![0; 4],
+            snapshot_data_type: proto::SnapshotDataType::Snapshot as i32,
+            done: false,
+            total_size: 10,
+        };
+        assert!(check_install_snapshot(&snapshot, 3, 0).is_err());
+        assert!(check_install_snapshot(&proto::InstallSnapshotRequest { offset: 6, ..snapshot }, 3, 0).is_ok());
+    }
+}
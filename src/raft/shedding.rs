@@ -0,0 +1,115 @@
+use crate::raft::config;
+use super::logging::*;
+use std::time::{Duration, Instant};
+
+// 基于提交延迟的 Leader 减载（见 config::LoadShedPolicy）。Leader 用数据条目从追加到提交的延迟
+// 维护一个指数移动平均，平均值持续超过 SLO 一段时间后进入减载状态，按比例拒绝新的客户端提议；
+// 平均值回落到 SLO 以内，或者长时间没有新的提交（延迟信息已经过时）时退出减载
+
+#[derive(Debug, Clone)]
+pub struct LoadShedder {
+    policy: config::LoadShedPolicy,
+    average: Option<Duration>,      // 最近提交延迟的指数移动平均
+    over_slo_since: Option<Instant>, // 平均值从什么时候开始超过 SLO
+    last_observed: Option<Instant>,
+    shedding: bool,
+    credit: u32, // 每个提议累加 shed_percent，满 100 时拒绝一个，使拒绝均匀分布
+}
+
+impl LoadShedder {
+    pub fn new(policy: config::LoadShedPolicy) -> Self {
+        LoadShedder { policy, average: None, over_slo_since: None, last_observed: None, shedding: false, credit: 0 }
+    }
+
+    // 记录一个数据条目在 now 时刻提交，从追加到提交用了 latency
+    pub fn observe(&mut self, latency: Duration, now: Instant) {
+        let average = match self.average {
+            Some(average) => average.mul_f64(1.0 - config::LOAD_SHED_LATENCY_WEIGHT) + latency.mul_f64(config::LOAD_SHED_LATENCY_WEIGHT),
+            None => latency,
+        };
+        self.average = Some(average);
+        self.last_observed = Some(now);
+        if average > self.policy.commit_latency_slo {
+            self.over_slo_since.get_or_insert(now);
+        } else {
+            self.over_slo_since = None;
+        }
+    }
+
+    // now 时刻是否处于减载状态
+    pub fn is_shedding(&mut self, now: Instant) -> bool {
+        let stale = self.last_observed.is_none_or(|at| now.saturating_duration_since(at) > self.policy.sustain);
+        if stale {
+            self.average = None;
+            self.over_slo_since = None;
+        }
+        let shedding = self.over_slo_since.is_some_and(|since| now.saturating_duration_since(since) >= self.policy.sustain);
+        if shedding != self.shedding {
+            if shedding {
+                warn!("Commit latency {:?} has exceeded the SLO of {:?} for {:?}, shedding {}% of new proposals.",
+                    self.average.unwrap_or_default(), self.policy.commit_latency_slo, self.policy.sustain, self.policy.shed_percent);
+            } else {
+                info!("Commit latency is back within the SLO of {:?}, no longer shedding proposals.", self.policy.commit_latency_slo);
+            }
+            self.shedding = shedding;
+            self.credit = 0;
+        }
+        shedding
+    }
+
+    // 一个新的客户端提议是否应该被拒绝
+    pub fn should_shed(&mut self, now: Instant) -> bool {
+        if !self.is_shedding(now) {
+            return false;
+        }
+        self.credit += self.policy.shed_percent.min(100);
+        if self.credit >= 100 {
+            self.credit -= 100;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sheds_after_sustained_slow_commits() {
+        let policy = config::LoadShedPolicy {
+            commit_latency_slo: Duration::from_millis(100),
+            sustain: Duration::from_secs(1),
+            shed_percent: 50,
+        };
+        let mut shedder = LoadShedder::new(policy);
+        let start = Instant::now();
+        let at = |millis: u64| start + Duration::from_millis(millis);
+
+        // 短暂的慢提交不会触发减载
+        shedder.observe(Duration::from_millis(500), at(0));
+        assert!(!shedder.should_shed(at(500)));
+        for millis in (0..=1000).step_by(100) {
+            shedder.observe(Duration::from_millis(500), at(millis));
+        }
+        assert!(shedder.is_shedding(at(1000)));
+
+        // 按比例均匀地拒绝
+        let shed: Vec<bool> = (0..4).map(|_| shedder.should_shed(at(1000))).collect();
+        assert_eq!(shed, vec![false, true, false, true]);
+
+        // 延迟回落到 SLO 以内后停止减载
+        for millis in (1100..=2000).step_by(100) {
+            shedder.observe(Duration::from_millis(10), at(millis));
+        }
+        assert!(!shedder.should_shed(at(2000)));
+
+        // 长时间没有新的提交时，过时的延迟不再导致减载
+        for millis in (2100..=3500).step_by(100) {
+            shedder.observe(Duration::from_millis(500), at(millis));
+        }
+        assert!(shedder.is_shedding(at(3500)));
+        assert!(!shedder.is_shedding(at(5000)));
+    }
+}
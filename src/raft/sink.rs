@@ -0,0 +1,249 @@
+use crate::raft::config;
+use super::logging::*;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+// 已应用结果的外部投递（CDC）：数据条目应用到状态机之后，把 (index, term, data, result) 交给 CommitSink，
+// 由后台任务按索引顺序投递，投递失败时退避后重试同一条记录，不会跳过。
+// 每个已应用的索引都会推进水位线，水位线之前的记录都已投递，持久化在 metadata_dir/raft.sink_watermark；
+// 重启后从水位线之后重新投递，所以是至少一次：下游需要按 index 去重。
+// 重启后重新投递的记录取自日志，没有当时的应用结果，result 为 None；
+// 还没有投递的条目不能被快照压缩掉，水位线落后时推迟生成快照（见 Consensus::take_snapshot）。
+// 通过 InstallSnapshot 跳过的条目没有在本节点应用过，不会投递。
+// 投递队列有界：队列剩余空间不够一个条目时 Consensus 暂停应用（见 has_capacity），记录本身从不丢弃；
+// 队列满时丢弃的只有水位线通知，最新的已应用索引另外保存，队列排空时照样推进水位线
+
+// 下游系统的最小抽象，调用发生在阻塞线程池中
+pub trait CommitSink: Debug + Send + Sync + 'static {
+    // 投递一条记录，返回 Ok 表示下游已经持久化
+    fn deliver(&self, record: &CommitRecord) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitRecord {
+    pub index: u64,
+    pub term: u64,
+    pub data: Vec<u8>,
+    pub result: Option<Vec<u8>>, // 状态机的执行结果，重启后重新投递时为 None
+}
+
+#[derive(Debug)]
+enum SinkJob {
+    Record(CommitRecord),
+    Applied(u64), // 该索引及之前的条目都已应用，之前的记录投递完成后推进水位线
+}
+
+pub fn gen_watermark_filepath(metadata_dir: &str) -> PathBuf {
+    PathBuf::from(metadata_dir).join("raft.sink_watermark")
+}
+
+// 没有水位线文件时从 0 开始，即投递日志中全部的数据条目
+pub fn load_watermark(metadata_dir: &str) -> u64 {
+    std::fs::read_to_string(gen_watermark_filepath(metadata_dir))
+        .ok()
+        .and_then(|content| content.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn store_watermark(metadata_dir: &str, index: u64) -> std::io::Result<()> {
+    let filepath = gen_watermark_filepath(metadata_dir);
+    let tmp_filepath = filepath.with_extension("sink_watermark.tmp");
+    std::fs::write(&tmp_filepath, index.to_string())?;
+    std::fs::rename(&tmp_filepath, &filepath)
+}
+
+// 后台投递任务的句柄
+#[derive(Debug)]
+pub struct SinkDispatcher {
+    tx: mpsc::Sender<SinkJob>,
+    task: tokio::task::JoinHandle<()>,
+    watermark: Arc<AtomicU64>,
+    applied: Arc<AtomicU64>, // 最新的已应用索引，Applied 通知因为队列已满被丢弃时由它推进水位线
+}
+
+impl SinkDispatcher {
+    // backlog 是重启后需要从日志中重新投递的记录，先于队列中的记录投递，不占用队列容量
+    pub fn spawn(sink: Arc<dyn CommitSink>, metadata_dir: String, backlog: Vec<CommitRecord>) -> Self {
+        let watermark = Arc::new(AtomicU64::new(load_watermark(&metadata_dir)));
+        let applied = Arc::new(AtomicU64::new(0));
+        let (tx, mut rx) = mpsc::channel::<SinkJob>(config::COMMIT_SINK_QUEUE_CAPACITY);
+        let task = tokio::spawn({
+            let (watermark, applied) = (Arc::clone(&watermark), Arc::clone(&applied));
+            async move {
+                for record in backlog {
+                    if record.index > watermark.load(Ordering::Acquire) {
+                        deliver_until_success(&sink, record).await;
+                    }
+                }
+                let mut persisted = watermark.load(Ordering::Acquire);
+                while let Some(job) = rx.recv().await {
+                    match job {
+                        SinkJob::Record(record) if record.index > watermark.load(Ordering::Acquire) => {
+                            deliver_until_success(&sink, record).await;
+                        }
+                        SinkJob::Record(_) => {}
+                        SinkJob::Applied(index) => {
+                            watermark.fetch_max(index, Ordering::AcqRel);
+                        }
+                    }
+                    // 积压的任务处理完再落盘，避免每条记录都写一次文件。
+                    // 先读已应用索引再检查队列：读到的索引之前的记录都已入队，队列为空说明都已投递
+                    let latest = applied.load(Ordering::Acquire);
+                    if !rx.is_empty() {
+                        continue;
+                    }
+                    watermark.fetch_max(latest, Ordering::AcqRel);
+                    let current = watermark.load(Ordering::Acquire);
+                    if current > persisted {
+                        match store_watermark(&metadata_dir, current) {
+                            Ok(()) => persisted = current,
+                            Err(e) => error!("Failed to persist commit sink watermark {}: {}", current, e),
+                        }
+                    }
+                }
+            }
+        });
+        SinkDispatcher { tx, task, watermark, applied }
+    }
+
+    // 已经投递完成的最高索引
+    pub fn watermark(&self) -> u64 {
+        self.watermark.load(Ordering::Acquire)
+    }
+
+    // 队列还能容纳一个条目的记录和水位线通知，应用下一个条目之前检查
+    pub fn has_capacity(&self) -> bool {
+        self.tx.capacity() >= 2
+    }
+
+    // 调用方先确认 has_capacity，队列仍然满时记录会丢失，只能记录错误
+    pub fn deliver(&self, record: CommitRecord) {
+        let index = record.index;
+        if let Err(e) = self.tx.try_send(SinkJob::Record(record)) {
+            error!("Commit sink queue rejected entry {}: {}, the record is lost.", index, e);
+        }
+    }
+
+    pub fn applied(&self, index: u64) {
+        self.applied.fetch_max(index, Ordering::AcqRel);
+        // 队列已满时不需要通知：排空队列时会读取最新的已应用索引
+        let _ = self.tx.try_send(SinkJob::Applied(index));
+    }
+
+    // 等待已提交的投递全部完成
+    pub async fn close(self) {
+        drop(self.tx);
+        let _ = self.task.await;
+    }
+}
+
+async fn deliver_until_success(sink: &Arc<dyn CommitSink>, record: CommitRecord) {
+    let record = Arc::new(record);
+    loop {
+        let (sink, job) = (Arc::clone(sink), Arc::clone(&record));
+        match tokio::task::spawn_blocking(move || sink.deliver(&job)).await {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => warn!("Commit sink failed to deliver entry {}: {}, retrying.", record.index, e),
+            Err(e) => error!("Commit sink panicked delivering entry {}: {}, retrying.", record.index, e),
+        }
+        tokio::time::sleep(config::COMMIT_SINK_RETRY_BACKOFF).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+
+    // 前 failures 次投递失败，之后记录收到的索引
+    #[derive(Debug, Default)]
+    struct RecordingSink {
+        failures: StdMutex<u32>,
+        delivered: StdMutex<Vec<u64>>,
+    }
+
+    impl CommitSink for RecordingSink {
+        fn deliver(&self, record: &CommitRecord) -> Result<(), String> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err("downstream unavailable".to_string());
+            }
+            self.delivered.lock().unwrap().push(record.index);
+            Ok(())
+        }
+    }
+
+    fn record(index: u64) -> CommitRecord {
+        CommitRecord { index, term: 1, data: vec![index as u8], result: None }
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_ordered_and_persists_watermark() {
+        let dir = tempfile::tempdir().unwrap();
+        let metadata_dir = dir.path().to_str().unwrap().to_string();
+        let sink = Arc::new(RecordingSink { failures: StdMutex::new(2), ..Default::default() });
+
+        let dispatcher = SinkDispatcher::spawn(Arc::clone(&sink) as Arc<dyn CommitSink>, metadata_dir.clone(), Vec::new());
+        for index in 1..=3 {
+            dispatcher.deliver(record(index));
+            dispatcher.applied(index);
+        }
+        // 非数据条目只推进水位线
+        dispatcher.applied(4);
+        dispatcher.close().await;
+        assert_eq!(*sink.delivered.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(load_watermark(&metadata_dir), 4);
+
+        // 重启后水位线之前的记录不再投递
+        let dispatcher = SinkDispatcher::spawn(Arc::clone(&sink) as Arc<dyn CommitSink>, metadata_dir.clone(), Vec::new());
+        assert_eq!(dispatcher.watermark(), 4);
+        dispatcher.deliver(record(3));
+        dispatcher.deliver(record(5));
+        dispatcher.applied(5);
+        dispatcher.close().await;
+        assert_eq!(*sink.delivered.lock().unwrap(), vec![1, 2, 3, 5]);
+        assert_eq!(load_watermark(&metadata_dir), 5);
+    }
+
+    // 下游阻塞时记录一直占用队列
+    #[derive(Debug, Default)]
+    struct BlockedSink {
+        gate: StdMutex<()>,
+        delivered: StdMutex<Vec<u64>>,
+    }
+
+    impl CommitSink for BlockedSink {
+        fn deliver(&self, record: &CommitRecord) -> Result<(), String> {
+            let _gate = self.gate.lock().unwrap();
+            self.delivered.lock().unwrap().push(record.index);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_reports_backpressure_and_keeps_watermark() {
+        let dir = tempfile::tempdir().unwrap();
+        let metadata_dir = dir.path().to_str().unwrap().to_string();
+        let sink = Arc::new(BlockedSink::default());
+        let gate = sink.gate.lock().unwrap();
+
+        let dispatcher = SinkDispatcher::spawn(Arc::clone(&sink) as Arc<dyn CommitSink>, metadata_dir.clone(), vec![record(1)]);
+        let mut index = 1;
+        while dispatcher.has_capacity() {
+            index += 1;
+            dispatcher.deliver(record(index));
+            dispatcher.applied(index);
+        }
+        assert!(index > 2 && index <= config::COMMIT_SINK_QUEUE_CAPACITY as u64);
+        // 队列已满时的水位线通知被丢弃，排空队列之后仍然推进到最新的已应用索引
+        dispatcher.applied(index + 1);
+        drop(gate);
+        dispatcher.close().await;
+        assert_eq!(*sink.delivered.lock().unwrap(), (1..=index).collect::<Vec<_>>());
+        assert_eq!(load_watermark(&metadata_dir), index + 1);
+    }
+}
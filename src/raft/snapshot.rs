@@ -1,7 +1,7 @@
-use crate::raft::config;
+use crate::raft::{config, log, metadata, proto, storage};
 extern crate regex; // 这一行可以保留，但如果下面使用了 use regex::Regex; 则不是必需的
 use lazy_static::lazy_static; // <--- 导入 lazy_static 宏
-use super::logging::info;
+use super::logging::{info, warn};
 use regex::Regex; // <--- 明确导入 Regex 类型
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
@@ -16,58 +16,126 @@ lazy_static! {
     static ref SNAPSHOT_FILENAME_RE: Regex = Regex::new(r"^raft-(\d+)-(\d+)(\.snapshot|\.snapshot\.metadata)$").unwrap();
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Snapshot {
+// 持久化到 .snapshot.metadata 文件中的快照元数据，不包含任何与本机路径相关的信息
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct SnapshotMeta {
     pub last_included_index: u64,
     pub last_included_term: u64,
     pub configuration: Option<config::Config>,
-    pub snapshot_dir: String,
+    // 快照数据文件的校验和，旧版本的元数据文件中没有该字段时为 0
+    #[serde(default)]
+    pub checksum: u64,
+    // configuration 所在配置条目的索引，旧版本的元数据文件中没有该字段时为 0，按 last_included_index 处理
+    #[serde(default)]
+    pub config_index: u64,
+    // 生成快照时状态机提供的数据格式版本（StateMachine::snapshot_version），旧版本的元数据文件中没有该字段时为 None
+    #[serde(default)]
+    pub state_machine_version: Option<String>,
 }
 
-impl Snapshot {
+impl SnapshotMeta {
+    // 解析元数据文件的内容。旧版本的元数据文件中多出的 snapshot_dir 字段会被忽略，目录始终以本地配置为准
+    pub fn parse(json: &str) -> Result<SnapshotMeta, String> {
+        let meta: SnapshotMeta = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        // 快照之后的日志从 last_included_index + 1 开始，这里溢出时后续所有索引计算都会出错
+        if meta.last_included_index == u64::MAX {
+            return Err(format!("last_included_index {} leaves no room for the log", meta.last_included_index));
+        }
+        Ok(meta)
+    }
+
+    // 快照中配置的生效位置
+    pub fn config_index(&self) -> u64 {
+        if self.config_index == 0 { self.last_included_index } else { self.config_index }
+    }
+
+    // 校验快照数据文件是否与记录的校验和一致，checksum 为 0 表示旧版本元数据，跳过校验
+    pub fn verify_checksum(&self, snapshot_filepath: &str) -> bool {
+        if self.checksum == 0 {
+            return true;
+        }
+        match checksum_file(snapshot_filepath) {
+            Ok(checksum) => checksum == self.checksum,
+            Err(_) => false,
+        }
+    }
+}
+
+// 快照的运行时管理结构，持有快照目录和当前加载的元数据
+#[derive(Debug)]
+pub struct SnapshotManager {
+    meta: SnapshotMeta,
+    snapshot_dir: String,
+}
+
+impl SnapshotManager {
     pub fn new(snapshot_dir: String) -> Self {
-        Snapshot {
-            last_included_index: 0,
-            last_included_term: 0,
-            configuration: None,
+        SnapshotManager {
+            meta: SnapshotMeta::default(),
             snapshot_dir,
         }
     }
 
+    pub fn meta(&self) -> &SnapshotMeta {
+        &self.meta
+    }
+
+    pub fn last_included_index(&self) -> u64 {
+        self.meta.last_included_index
+    }
+
+    pub fn last_included_term(&self) -> u64 {
+        self.meta.last_included_term
+    }
+
+    pub fn configuration(&self) -> Option<&config::Config> {
+        self.meta.configuration.as_ref()
+    }
+
+    pub fn snapshot_dir(&self) -> &str {
+        &self.snapshot_dir
+    }
+
+    // configuration 是 last_included_index 处生效的配置，在联合共识期间生成时就是 C(old,new)，
+    // 它的 old_servers 即变更开始之前的稳定配置，恢复时据此继续完成变更
     pub fn take_snapshot_metadata(
         &mut self,
         last_included_index: u64,
         last_included_term: u64,
         configuration: Option<config::Config>,
-    ) {
+        config_index: u64,
+        state_machine_version: Option<String>,
+    ) -> Result<(), String> {
         info!("start to take snapshot metadata, last_included_index: {}, last_included_term: {}, configuration: {:?}", last_included_index, last_included_term, configuration.as_ref());
-        self.last_included_index = last_included_index;
-        self.last_included_term = last_included_term;
-        self.configuration = configuration;
+        let snapshot_filepath = self.gen_snapshot_filepath(last_included_index, last_included_term);
+        let checksum = checksum_file(&snapshot_filepath)
+            .map_err(|e| format!("failed to compute checksum of snapshot file '{}', error: {}", snapshot_filepath, e))?;
+        self.store_metadata(SnapshotMeta {
+            last_included_index,
+            last_included_term,
+            configuration,
+            checksum,
+            config_index,
+            state_machine_version,
+        })
+    }
 
+    // 写入已经算好校验和的快照元数据，并把它作为当前加载的元数据；写入失败时保留原来的元数据
+    pub fn store_metadata(&mut self, meta: SnapshotMeta) -> Result<(), String> {
         let metadata_filepath =
-            self.gen_snapshot_metadata_filepath(last_included_index, last_included_term);
-        let mut metadata_file = match std::fs::File::create(metadata_filepath.clone()) {
-            Ok(file) => file,
-            Err(e) => {
-                panic!("failed to create snapshot metadata file '{}', error: {}", metadata_filepath, e);
-            }
-        };
-
-        let metadata_json = match serde_json::to_string(self) {
-            Ok(json) => json,
-            Err(e) => {
-                panic!("failed to serialize snapshot metadata, error: {}", e);
-            }
-        };
-
-        if let Err(e) = metadata_file.write_all(metadata_json.as_bytes()) {
-            panic!("failed to write snapshot metadata file, error: {}", e);
-        }
+            self.gen_snapshot_metadata_filepath(meta.last_included_index, meta.last_included_term);
+        let metadata_json = serde_json::to_string(&meta)
+            .map_err(|e| format!("failed to serialize snapshot metadata, error: {}", e))?;
+        let mut metadata_file = std::fs::File::create(&metadata_filepath)
+            .map_err(|e| format!("failed to create snapshot metadata file '{}', error: {}", metadata_filepath, e))?;
+        metadata_file.write_all(metadata_json.as_bytes())
+            .map_err(|e| format!("failed to write snapshot metadata file '{}', error: {}", metadata_filepath, e))?;
+        self.meta = meta;
         info!(
             "success to take snapshot metadata, filepath: {}",
             metadata_filepath
         );
+        Ok(())
     }
 
     pub fn reload_metadata(&mut self) {
@@ -84,14 +152,12 @@ impl Snapshot {
                 panic!("failed to read snapshot metadata from file '{}': {}", filepath, e);
             }
 
-            match serde_json::from_str::<Snapshot>(metadata_json.as_str()) {
-                Ok(snapshot) => {
-                    self.last_included_index = snapshot.last_included_index;
-                    self.last_included_term = snapshot.last_included_term;
-                    self.configuration = snapshot.configuration;
+            match SnapshotMeta::parse(&metadata_json) {
+                Ok(meta) => {
+                    self.meta = meta;
                     info!(
                         "successfully reloaded snapshot metadata: LII={}, LIT={}, Config={:?}",
-                        self.last_included_index, self.last_included_term, self.configuration.as_ref()
+                        self.meta.last_included_index, self.meta.last_included_term, self.meta.configuration.as_ref()
                     );
                 }
                 Err(e) => {
@@ -103,6 +169,11 @@ impl Snapshot {
         }
     }
 
+    // 校验快照数据文件是否与当前元数据中记录的校验和一致
+    pub fn verify_checksum(&self, snapshot_filepath: &str) -> bool {
+        self.meta.verify_checksum(snapshot_filepath)
+    }
+
     // Helper function to parse filenames using the static regex
     fn parse_snapshot_filename(filename: &str, expected_extension: &str) -> Option<(u64, u64)> {
         // 使用预编译的静态正则表达式 SNAPSHOT_FILENAME_RE
@@ -165,11 +236,84 @@ impl Snapshot {
     }
 
 
-    pub fn latest_snapshot_filepath(&mut self) -> Option<String> {
+    // 快照目录中所有文件占用的字节数，包括旧的快照和正在接收的临时文件
+    pub fn disk_usage(&self) -> u64 {
+        std::fs::read_dir(&self.snapshot_dir).map_or(0, |entries| {
+            entries.filter_map(|e| e.ok())
+                .filter_map(|e| e.metadata().ok())
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+                .sum()
+        })
+    }
+
+    // 删除比当前快照旧的快照数据文件和元数据文件，返回释放的字节数
+    pub fn remove_stale_snapshots(&self) -> u64 {
+        let Ok(entries) = std::fs::read_dir(&self.snapshot_dir) else {
+            return 0;
+        };
+        let current = (self.meta.last_included_index, self.meta.last_included_term);
+        let mut freed = 0;
+        for entry in entries.filter_map(|e| e.ok()) {
+            let file_name = entry.file_name();
+            let Some(filename) = file_name.to_str() else { continue };
+            let parsed = Self::parse_snapshot_filename(filename, ".snapshot")
+                .or_else(|| Self::parse_snapshot_filename(filename, ".snapshot.metadata"));
+            match parsed {
+                Some(index_term) if index_term < current => {
+                    let size = entry.metadata().map_or(0, |m| m.len());
+                    match std::fs::remove_file(entry.path()) {
+                        Ok(()) => freed += size,
+                        Err(e) => warn!("failed to remove stale snapshot file {:?}: {}", entry.path(), e),
+                    }
+                }
+                _ => {}
+            }
+        }
+        if freed > 0 {
+            info!("removed stale snapshots older than index {}, freed {} bytes", current.0, freed);
+        }
+        freed
+    }
+
+    // 启动时清理快照目录中的遗留文件：中断的传输和写入留下的 .tmp 文件，
+    // 以及当前元数据没有引用、又缺少配对文件的快照数据或元数据（写到一半崩溃）。
+    // 孤立的数据文件如果比当前快照新，会被 latest_snapshot_filepath 误当成最新的快照
+    pub fn remove_stale_artifacts(&self, min_age: std::time::Duration) -> Vec<std::path::PathBuf> {
+        let mut removed = storage::remove_stale_tmp_files(std::path::Path::new(&self.snapshot_dir), min_age);
+        let Ok(entries) = std::fs::read_dir(&self.snapshot_dir) else {
+            return removed;
+        };
+        let current = (self.meta.last_included_index, self.meta.last_included_term);
+        for entry in entries.filter_map(|e| e.ok()) {
+            let file_name = entry.file_name();
+            let Some(filename) = file_name.to_str() else { continue };
+            let (index_term, counterpart) = match (
+                Self::parse_snapshot_filename(filename, ".snapshot"),
+                Self::parse_snapshot_filename(filename, ".snapshot.metadata"),
+            ) {
+                (Some((index, term)), _) => ((index, term), self.gen_snapshot_metadata_filepath(index, term)),
+                (_, Some((index, term))) => ((index, term), self.gen_snapshot_filepath(index, term)),
+                _ => continue,
+            };
+            if index_term == current || std::path::Path::new(&counterpart).exists() {
+                continue;
+            }
+            if entry.metadata().is_ok_and(|m| storage::is_older_than(&m, min_age)) && std::fs::remove_file(entry.path()).is_ok() {
+                removed.push(entry.path());
+            }
+        }
+        for path in &removed {
+            info!("removed stale snapshot artifact {}", path.display());
+        }
+        removed
+    }
+
+    pub fn latest_snapshot_filepath(&self) -> Option<String> {
         self.latest_file_with_pattern(".snapshot")
     }
 
-    pub fn latest_metadata_filepath(&mut self) -> Option<String> {
+    pub fn latest_metadata_filepath(&self) -> Option<String> {
         self.latest_file_with_pattern(".snapshot.metadata")
     }
 
@@ -214,4 +358,472 @@ impl Snapshot {
             self.snapshot_dir, last_included_index, last_included_term
         )
     }
-}
\ No newline at end of file
+}
+
+// Follower 端正在接收的快照传输进度，元数据文件和快照数据文件依次传输，各自的偏移量从 0 开始。
+// 一次传输只接受发起它的 Leader 在同一任期内发送的分块
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotTransfer {
+    pub leader_id: u64,
+    pub leader_term: u64,
+    pub last_included_index: u64,
+    pub last_included_term: u64,
+    pub data_type: proto::SnapshotDataType,
+    pub total_size: u64,
+    pub next_offset: u64,
+}
+
+impl SnapshotTransfer {
+    // 校验分块是否是 current 期望的下一个分块，成功时返回写入该分块之后的传输进度
+    pub fn accept_chunk(
+        current: Option<&SnapshotTransfer>,
+        request: &proto::InstallSnapshotRequest,
+    ) -> Result<SnapshotTransfer, String> {
+        let data_type = proto::SnapshotDataType::try_from(request.snapshot_data_type)
+            .map_err(|_| format!("unknown snapshot data type {}", request.snapshot_data_type))?;
+
+        let mut next = match (data_type, request.offset, current) {
+            // 元数据文件的第一个分块开启一次新的传输，丢弃之前未完成的传输
+            (proto::SnapshotDataType::Metadata, 0, _) => SnapshotTransfer {
+                leader_id: request.leader_id,
+                leader_term: request.term,
+                last_included_index: request.last_included_index,
+                last_included_term: request.last_included_term,
+                data_type,
+                total_size: request.total_size,
+                next_offset: 0,
+            },
+            (_, _, None) => return Err("no snapshot transfer in progress".to_string()),
+            // 同一个快照可能由新任期的 Leader 重新发送，不能和旧 Leader 发了一半的文件拼在一起
+            (_, _, Some(cur)) if cur.leader_id != request.leader_id || cur.leader_term != request.term => {
+                return Err(format!(
+                    "chunk comes from leader {} in term {} but the transfer was started by leader {} in term {}",
+                    request.leader_id, request.term, cur.leader_id, cur.leader_term
+                ));
+            }
+            (_, _, Some(cur)) if cur.last_included_index != request.last_included_index
+                || cur.last_included_term != request.last_included_term => {
+                return Err(format!(
+                    "chunk belongs to snapshot ({}, {}) but ({}, {}) is in progress",
+                    request.last_included_index, request.last_included_term,
+                    cur.last_included_index, cur.last_included_term
+                ));
+            }
+            // 元数据接收完整之后才能开始接收快照数据
+            (proto::SnapshotDataType::Snapshot, 0, Some(cur)) if cur.data_type == proto::SnapshotDataType::Metadata => {
+                if cur.next_offset != cur.total_size {
+                    return Err(format!("metadata incomplete, received {} of {} bytes", cur.next_offset, cur.total_size));
+                }
+                SnapshotTransfer {
+                    data_type,
+                    total_size: request.total_size,
+                    next_offset: 0,
+                    ..cur.clone()
+                }
+            }
+            (_, _, Some(cur)) => {
+                if cur.data_type != data_type || cur.next_offset != request.offset || cur.total_size != request.total_size {
+                    return Err(format!(
+                        "expected {:?} chunk at offset {} (total {}), got {:?} chunk at offset {} (total {})",
+                        cur.data_type, cur.next_offset, cur.total_size, data_type, request.offset, request.total_size
+                    ));
+                }
+                cur.clone()
+            }
+        };
+
+        next.next_offset += request.data.len() as u64;
+        if next.next_offset > next.total_size {
+            return Err(format!("chunk exceeds file size {}", next.total_size));
+        }
+        if request.done && (data_type != proto::SnapshotDataType::Snapshot || next.next_offset != next.total_size) {
+            return Err("final chunk received before the snapshot data is complete".to_string());
+        }
+        Ok(next)
+    }
+}
+
+// 存储格式迁移（版本 0 -> 1）：旧格式的快照元数据文件带有 snapshot_dir 字段、没有校验和，
+// 按当前的 SnapshotMeta 格式重写并补上校验和。dry_run 时只返回将要执行的操作
+pub fn migrate_legacy_metadata(snapshot_dir: &str, dry_run: bool) -> anyhow::Result<Vec<String>> {
+    const CURRENT_FIELDS: [&str; 6] = ["last_included_index", "last_included_term", "configuration", "checksum", "config_index", "state_machine_version"];
+    let dir_entries = match std::fs::read_dir(snapshot_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut actions = Vec::new();
+    for entry in dir_entries {
+        let entry = entry?;
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        let Some((index, term)) = SnapshotManager::parse_snapshot_filename(&filename, ".snapshot.metadata") else {
+            continue;
+        };
+        let metadata_filepath = entry.path();
+        let value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&metadata_filepath)?)?;
+        let legacy_fields = value.as_object().is_some_and(|fields| fields.keys().any(|k| !CURRENT_FIELDS.contains(&k.as_str())));
+        let mut meta: SnapshotMeta = serde_json::from_value(value)?;
+        let snapshot_filepath = format!("{}/raft-{}-{}.snapshot", snapshot_dir, index, term);
+        let missing_checksum = meta.checksum == 0 && std::path::Path::new(&snapshot_filepath).exists();
+        if !legacy_fields && !missing_checksum {
+            continue;
+        }
+
+        actions.push(format!("rewrite snapshot metadata {} in the current format{}",
+            metadata_filepath.display(), if missing_checksum { " with checksum" } else { "" }));
+        if dry_run {
+            continue;
+        }
+        if missing_checksum {
+            meta.checksum = checksum_file(&snapshot_filepath)?;
+        }
+        let tmp_filepath = format!("{}.tmp", metadata_filepath.display());
+        std::fs::write(&tmp_filepath, serde_json::to_string(&meta)?)?;
+        std::fs::rename(&tmp_filepath, &metadata_filepath)?;
+    }
+    Ok(actions)
+}
+
+// 用外部数据初始化一个全新的节点：把 initial.filepath 复制为本节点的快照，日志从 last_included_index + 1 开始，
+// 当前任期设为快照的任期。快照元数据最后写入，作为导入完成的标志；节点已经有快照或日志时不做任何修改。
+// 返回是否执行了导入
+pub fn import_initial_snapshot(
+    snapshot_dir: &str,
+    metadata_dir: &str,
+    initial: &config::InitialSnapshot,
+    configuration: config::Config,
+) -> anyhow::Result<bool> {
+    let (index, term) = (initial.last_included_index, initial.last_included_term);
+    if index == 0 || term == 0 {
+        return Err(anyhow::anyhow!("initial snapshot index and term must be positive, got ({}, {})", index, term));
+    }
+    if !std::path::Path::new(&initial.filepath).is_file() {
+        return Err(anyhow::anyhow!("initial snapshot file {} does not exist", initial.filepath));
+    }
+
+    // 只有全新的节点（或上次导入中途退出的节点）才导入
+    let mut manager = SnapshotManager::new(snapshot_dir.to_string());
+    let mut log = log::Log::new(1, metadata_dir.to_string());
+    log.reload().map_err(anyhow::Error::msg)?;
+    let metadata = metadata::Metadata::load(metadata_dir)?;
+    let fresh = manager.latest_metadata_filepath().is_none()
+        && log.entries().is_empty()
+        && (log.start_index() == 1 || log.start_index() == index + 1)
+        && (metadata.current_term == 0 || metadata.current_term == term);
+    if !fresh {
+        info!("Node data in {} already initialized, ignoring initial snapshot {}", metadata_dir, initial.filepath);
+        return Ok(false);
+    }
+
+    info!("Importing initial snapshot {} at index {}, term {}", initial.filepath, index, term);
+    let tmp_filepath = manager.gen_tmp_snapshot_filepath(index, term);
+    std::fs::copy(&initial.filepath, &tmp_filepath)?;
+    std::fs::rename(&tmp_filepath, manager.gen_snapshot_filepath(index, term))?;
+
+    log::Log::new(index + 1, metadata_dir.to_string()).dump();
+    metadata::Metadata { current_term: term, ..metadata::Metadata::new(metadata_dir.to_string()) }.store()?;
+    manager.take_snapshot_metadata(index, term, Some(configuration), index, None).map_err(anyhow::Error::msg)?;
+    Ok(true)
+}
+
+// 计算文件内容的 FNV-1a 64 位校验和
+pub fn checksum_file(filepath: &str) -> std::io::Result<u64> {
+    checksum_reader(std::fs::File::open(filepath)?)
+}
+
+pub fn checksum_reader<R: Read>(mut reader: R) -> std::io::Result<u64> {
+    let mut checksum = Checksum::default();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        checksum.update(&buf[..n]);
+    }
+    Ok(checksum.value())
+}
+
+// 可以分多次输入的 FNV-1a 64 位校验和，结果与一次性计算整个文件相同
+#[derive(Debug, Clone, Copy)]
+pub struct Checksum(u64);
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Checksum(0xcbf29ce484222325)
+    }
+}
+
+impl Checksum {
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+// 快照文件的一个分块，checksum 是文件开头到这个分块末尾的校验和
+#[derive(Debug)]
+pub struct FileChunk {
+    pub offset: u64,
+    pub data: Vec<u8>,
+    pub total_size: u64,
+    pub checksum: u64,
+}
+
+impl FileChunk {
+    pub fn is_last(&self) -> bool {
+        self.offset + self.data.len() as u64 >= self.total_size
+    }
+}
+
+// 在阻塞线程池中按顺序读取文件的分块，放入容量为 depth 的通道：发送方等待网络时读取继续进行，
+// 通道满时读取暂停；接收方提前 drop 时读取随之停止。空文件也产生一个分块，出错时通道中的最后一项是错误
+pub fn spawn_chunk_reader(filepath: String, chunk_size: usize, depth: usize) -> tokio::sync::mpsc::Receiver<std::io::Result<FileChunk>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(depth.max(1));
+    tokio::task::spawn_blocking(move || {
+        let mut file = match std::fs::File::open(&filepath) {
+            Ok(file) => file,
+            Err(e) => {
+                let _ = tx.blocking_send(Err(e));
+                return;
+            }
+        };
+        let total_size = match file.metadata() {
+            Ok(m) => m.len(),
+            Err(e) => {
+                let _ = tx.blocking_send(Err(e));
+                return;
+            }
+        };
+        let mut checksum = Checksum::default();
+        let mut offset = 0;
+        loop {
+            let chunk_len = std::cmp::min(chunk_size as u64, total_size - offset) as usize;
+            let mut data = vec![0; chunk_len];
+            if let Err(e) = file.read_exact(&mut data) {
+                let _ = tx.blocking_send(Err(e));
+                return;
+            }
+            checksum.update(&data);
+            let chunk = FileChunk { offset, data, total_size, checksum: checksum.value() };
+            let last = chunk.is_last();
+            offset += chunk_len as u64;
+            if tx.blocking_send(Ok(chunk)).is_err() || last {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_missing_snapshot_file_keeps_current_metadata() {
+        let dir = tempdir().unwrap();
+        let mut manager = SnapshotManager::new(dir.path().to_str().unwrap().to_string());
+        std::fs::write(manager.gen_snapshot_filepath(10, 2), b"state").unwrap();
+        manager.take_snapshot_metadata(10, 2, None, 0, None).unwrap();
+
+        // 数据文件不存在时返回错误，不写元数据，当前加载的仍是原来的快照
+        assert!(manager.take_snapshot_metadata(12, 2, None, 0, None).unwrap_err().contains("failed to compute checksum"));
+        assert!(!std::path::Path::new(&manager.gen_snapshot_metadata_filepath(12, 2)).exists());
+        assert_eq!(manager.last_included_index(), 10);
+    }
+
+    #[test]
+    fn test_stale_artifacts_are_removed() {
+        let dir = tempdir().unwrap();
+        let dir_str = dir.path().to_str().unwrap().to_string();
+        let mut manager = SnapshotManager::new(dir_str.clone());
+        std::fs::write(manager.gen_snapshot_filepath(10, 2), b"state").unwrap();
+        manager.take_snapshot_metadata(10, 2, None, 0, None).unwrap();
+
+        // 中断的传输、写了数据但没有元数据的新快照、只有元数据的旧快照
+        let tmp = manager.gen_tmp_snapshot_filepath(12, 2);
+        std::fs::write(&tmp, b"partial").unwrap();
+        std::fs::write(manager.gen_snapshot_filepath(15, 3), b"orphan").unwrap();
+        std::fs::write(manager.gen_snapshot_metadata_filepath(5, 1), b"{}").unwrap();
+
+        assert!(manager.remove_stale_artifacts(std::time::Duration::from_secs(600)).is_empty());
+        let mut removed = manager.remove_stale_artifacts(std::time::Duration::ZERO);
+        removed.sort();
+        let mut expected = vec![
+            std::path::PathBuf::from(tmp),
+            std::path::PathBuf::from(manager.gen_snapshot_filepath(15, 3)),
+            std::path::PathBuf::from(manager.gen_snapshot_metadata_filepath(5, 1)),
+        ];
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert_eq!(manager.latest_snapshot_filepath(), Some(manager.gen_snapshot_filepath(10, 2)));
+        assert!(std::path::Path::new(&manager.gen_snapshot_metadata_filepath(10, 2)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_chunk_reader_streams_file_with_running_checksum() {
+        let dir = tempdir().unwrap();
+        let filepath = dir.path().join("raft-10-2.snapshot").to_str().unwrap().to_string();
+        let content: Vec<u8> = (0..100u8).collect();
+        std::fs::write(&filepath, &content).unwrap();
+
+        let mut rx = spawn_chunk_reader(filepath.clone(), 30, 2);
+        let mut received = Vec::new();
+        let mut offsets = Vec::new();
+        let mut last_checksum = 0;
+        while let Some(chunk) = rx.recv().await {
+            let chunk = chunk.unwrap();
+            offsets.push(chunk.offset);
+            received.extend_from_slice(&chunk.data);
+            last_checksum = chunk.checksum;
+            assert_eq!(chunk.is_last(), received.len() == content.len());
+        }
+        assert_eq!(offsets, vec![0, 30, 60, 90]);
+        assert_eq!(received, content);
+        assert_eq!(last_checksum, checksum_file(&filepath).unwrap());
+
+        // 空文件产生一个空的最后分块，不存在的文件产生一个错误
+        std::fs::write(&filepath, b"").unwrap();
+        let chunk = spawn_chunk_reader(filepath, 30, 2).recv().await.unwrap().unwrap();
+        assert!(chunk.data.is_empty() && chunk.is_last());
+        let missing = dir.path().join("missing").to_str().unwrap().to_string();
+        assert!(spawn_chunk_reader(missing, 30, 2).recv().await.unwrap().is_err());
+    }
+
+    #[test]
+    fn test_snapshot_metadata_is_portable() {
+        let old_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let old_dir_str = old_dir.path().to_str().unwrap().to_string();
+        let new_dir_str = new_dir.path().to_str().unwrap().to_string();
+
+        let mut manager = SnapshotManager::new(old_dir_str.clone());
+        let snapshot_filepath = manager.gen_snapshot_filepath(10, 2);
+        std::fs::write(&snapshot_filepath, b"state machine data").unwrap();
+        manager.take_snapshot_metadata(10, 2, None, 0, None).unwrap();
+        assert_ne!(manager.meta().checksum, 0);
+
+        // 元数据文件中不应包含本机路径
+        let metadata_filepath = manager.gen_snapshot_metadata_filepath(10, 2);
+        let metadata_json = std::fs::read_to_string(&metadata_filepath).unwrap();
+        assert!(!metadata_json.contains(&old_dir_str));
+
+        // 拷贝到另一个目录后重新加载，目录以本地为准
+        std::fs::copy(&snapshot_filepath, format!("{}/raft-10-2.snapshot", new_dir_str)).unwrap();
+        std::fs::copy(&metadata_filepath, format!("{}/raft-10-2.snapshot.metadata", new_dir_str)).unwrap();
+        let mut reloaded = SnapshotManager::new(new_dir_str.clone());
+        reloaded.reload_metadata();
+        assert_eq!(reloaded.meta(), manager.meta());
+        assert_eq!(reloaded.snapshot_dir(), new_dir_str);
+        let reloaded_snapshot_filepath = reloaded.latest_snapshot_filepath().unwrap();
+        assert!(reloaded_snapshot_filepath.starts_with(&new_dir_str));
+        assert!(reloaded.verify_checksum(&reloaded_snapshot_filepath));
+
+        // 兼容旧格式：包含 snapshot_dir 且没有 checksum
+        std::fs::write(
+            format!("{}/raft-20-3.snapshot.metadata", new_dir_str),
+            format!("{{\"last_included_index\":20,\"last_included_term\":3,\"configuration\":null,\"snapshot_dir\":\"{}\"}}", old_dir_str),
+        ).unwrap();
+        reloaded.reload_metadata();
+        assert_eq!(reloaded.last_included_index(), 20);
+        assert_eq!(reloaded.meta().checksum, 0);
+    }
+
+    fn chunk(data_type: proto::SnapshotDataType, offset: u64, len: usize, total_size: u64, done: bool) -> proto::InstallSnapshotRequest {
+        proto::InstallSnapshotRequest {
+            term: 1,
+            leader_id: 1,
+            last_included_index: 10,
+            last_included_term: 2,
+            offset,
+            data: vec![0; len],
+            snapshot_data_type: data_type as i32,
+            done,
+            total_size,
+        }
+    }
+
+    #[test]
+    fn test_import_initial_snapshot() {
+        let source_dir = tempdir().unwrap();
+        let snapshot_dir = tempdir().unwrap();
+        let metadata_dir = tempdir().unwrap();
+        let snapshot_dir_str = snapshot_dir.path().to_str().unwrap();
+        let metadata_dir_str = metadata_dir.path().to_str().unwrap();
+        let source_filepath = source_dir.path().join("dataset.snapshot").to_str().unwrap().to_string();
+        std::fs::write(&source_filepath, b"external dataset").unwrap();
+
+        let initial = config::InitialSnapshot { filepath: source_filepath, last_included_index: 100, last_included_term: 3 };
+        let configuration = config::Config::new_stable(vec![proto::ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string(), zone: None }]);
+        assert!(import_initial_snapshot(snapshot_dir_str, metadata_dir_str, &initial, configuration.clone()).unwrap());
+
+        let mut manager = SnapshotManager::new(snapshot_dir_str.to_string());
+        manager.reload_metadata();
+        assert_eq!((manager.last_included_index(), manager.last_included_term()), (100, 3));
+        assert_eq!(manager.configuration(), Some(&configuration));
+        assert!(manager.verify_checksum(&manager.latest_snapshot_filepath().unwrap()));
+        let mut log = log::Log::new(1, metadata_dir_str.to_string());
+        log.reload().unwrap();
+        assert_eq!(log.start_index(), 101);
+        assert_eq!(metadata::Metadata::load(metadata_dir_str).unwrap().current_term, 3);
+
+        // 已经有数据的节点不会再次导入
+        assert!(!import_initial_snapshot(snapshot_dir_str, metadata_dir_str, &initial, configuration).unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_transfer_sequential_chunks() {
+        use proto::SnapshotDataType::{Metadata, Snapshot};
+
+        let t = SnapshotTransfer::accept_chunk(None, &chunk(Metadata, 0, 4, 6, false)).unwrap();
+        let t = SnapshotTransfer::accept_chunk(Some(&t), &chunk(Metadata, 4, 2, 6, false)).unwrap();
+        assert_eq!(t.next_offset, 6);
+        let t = SnapshotTransfer::accept_chunk(Some(&t), &chunk(Snapshot, 0, 5, 8, false)).unwrap();
+        let t = SnapshotTransfer::accept_chunk(Some(&t), &chunk(Snapshot, 5, 3, 8, true)).unwrap();
+        assert_eq!(t.data_type, Snapshot);
+        assert_eq!(t.next_offset, 8);
+    }
+
+    #[test]
+    fn test_snapshot_transfer_rejects_out_of_order_chunks() {
+        use proto::SnapshotDataType::{Metadata, Snapshot};
+
+        // 没有正在进行的传输
+        assert!(SnapshotTransfer::accept_chunk(None, &chunk(Snapshot, 0, 4, 4, true)).is_err());
+
+        let t = SnapshotTransfer::accept_chunk(None, &chunk(Metadata, 0, 4, 6, false)).unwrap();
+        // 跳过了一部分元数据
+        assert!(SnapshotTransfer::accept_chunk(Some(&t), &chunk(Metadata, 5, 1, 6, false)).is_err());
+        // 元数据还没有接收完整
+        assert!(SnapshotTransfer::accept_chunk(Some(&t), &chunk(Snapshot, 0, 4, 4, true)).is_err());
+
+        let t = SnapshotTransfer::accept_chunk(Some(&t), &chunk(Metadata, 4, 2, 6, false)).unwrap();
+        let t = SnapshotTransfer::accept_chunk(Some(&t), &chunk(Snapshot, 0, 4, 8, false)).unwrap();
+        // 重复的分块
+        assert!(SnapshotTransfer::accept_chunk(Some(&t), &chunk(Snapshot, 0, 4, 8, false)).is_err());
+        // 数据不完整时就标记为结束
+        assert!(SnapshotTransfer::accept_chunk(Some(&t), &chunk(Snapshot, 4, 2, 8, true)).is_err());
+
+        // 其他快照的分块
+        let mut other = chunk(Snapshot, 4, 4, 8, true);
+        other.last_included_index = 20;
+        assert!(SnapshotTransfer::accept_chunk(Some(&t), &other).is_err());
+
+        // 新任期的 Leader 接着发送同一个快照的后续分块
+        let mut newer_term = chunk(Snapshot, 4, 4, 8, true);
+        newer_term.term = 2;
+        assert!(SnapshotTransfer::accept_chunk(Some(&t), &newer_term).is_err());
+        let mut other_leader = chunk(Snapshot, 4, 4, 8, true);
+        other_leader.leader_id = 2;
+        assert!(SnapshotTransfer::accept_chunk(Some(&t), &other_leader).is_err());
+    }
+}
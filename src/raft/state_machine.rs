@@ -3,10 +3,13 @@ use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
 use serde::{Deserialize, Serialize};
-use crate::raft::proto;
+use crate::raft::{config, proto};
 
 use super::logging::*;
 use std::any::Any;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, Instant};
 
 
 pub trait StateMachine: Debug + Send + 'static {
@@ -14,13 +17,163 @@ pub trait StateMachine: Debug + Send + 'static {
     // 应用日志条目
     fn apply(&mut self, data: &Vec<u8>);
 
+    // 应用日志条目并返回执行结果（例如 KV 写入前的旧值），结果会交给等待提交的 Propose 调用方；
+    // 默认没有结果
+    fn apply_with_result(&mut self, data: &Vec<u8>) -> Vec<u8> {
+        self.apply(data);
+        Vec::new()
+    }
+
+    // 带上下文的应用，状态机需要在应用时提交后续命令（见 ApplyContext）时实现它；默认忽略上下文
+    fn apply_with_context(&mut self, data: &Vec<u8>, _ctx: &mut ApplyContext) -> Vec<u8> {
+        self.apply_with_result(data)
+    }
+
+    // 状态机自身持久化了数据时，返回已经应用到的日志索引，启动时共识模块从它之后补齐已提交的条目，
+    // 不再从快照恢复；纯内存的状态机返回 None，启动时从快照恢复后重新应用。
+    // 持久化的状态机应当实现 apply_with_context，把数据和 ctx.index 在同一次写入中落盘：
+    // set_applied_index 在 apply 返回之后才调用，两次调用之间崩溃时，只靠它记录位置的状态机重启后会再次应用这个条目
+    fn applied_index(&self) -> Option<u64> {
+        None
+    }
+
+    // 日志应用（或快照安装）到 index 后调用，持久化的状态机在这里记录配置条目、空条目和快照推进的 applied_index，
+    // 数据条目的位置应当已经在 apply_with_context 中记录（见 applied_index）；
+    // 分块的数据重组到一半时不会调用，保证记录的位置总能从头重组
+    fn set_applied_index(&mut self, _index: u64) {}
+
     // 生成快照
     fn take_snapshot(&mut self, snapshot_filepath: &str);
 
     // 从快照回复
     fn restore_snapshot(&mut self, snapshot_filepath: &str);
+
+    // 带进度汇报的恢复，在阻塞线程池中执行；状态机可以边读边调用 progress 的方法汇报进度。
+    // 默认实现不汇报中间进度，恢复结束后一次性计入整个文件
+    fn restore_snapshot_with_progress(&mut self, snapshot_filepath: &str, progress: &RestoreProgress) {
+        self.restore_snapshot(snapshot_filepath);
+        progress.add_bytes(std::fs::metadata(snapshot_filepath).map_or(0, |m| m.len()));
+    }
+
+    // 快照数据格式的版本，生成快照时写入快照元数据；默认没有版本
+    fn snapshot_version(&self) -> Option<String> {
+        None
+    }
+
+    // 从快照恢复之前（启动、补齐被压缩的条目、安装 Leader 发来的快照）用元数据中的版本调用，
+    // 返回 false 时拒绝恢复，防止应用升级后按新格式解释旧格式的数据；没有版本的快照传入 None。
+    // 默认接受所有快照
+    fn compatible_with(&self, _version: Option<&str>) -> bool {
+        true
+    }
+
+    // 只读查询，默认不支持读取
+    fn read(&self, _query: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+
+// 应用一个数据条目时传给状态机的上下文，状态机可以通过 propose 提交后续命令（例如 TTL 到期后的删除）。
+// 所有节点都会应用同一个条目，只有 Leader 上提交的后续命令会被真正 Propose，其他节点上 propose 返回错误。
+// 后续命令在应用结束后异步 Propose，Leader 在此期间切换时可能丢失，状态机应当能够重新生成它们（例如定期重新扫描）。
+// 为了防止后续命令无限地产生后续命令，链条的层数和每个条目能提交的条数都有上限
+#[derive(Debug)]
+pub struct ApplyContext {
+    pub index: u64,        // 正在应用的条目的日志索引
+    pub is_leader: bool,   // 本节点当前是否为 Leader
+    pub timestamp_ms: Option<u64>, // Leader 追加条目时的 Unix 时间（毫秒），所有节点上相同，可用于 TTL 判断；旧版本写入的条目为 None
+    depth: u32,            // 正在应用的条目处于后续命令链条的第几层，客户端提交的条目为0
+    follow_ups: Vec<Vec<u8>>,
+}
+
+impl ApplyContext {
+    pub fn new(index: u64, is_leader: bool, timestamp_ms: Option<u64>, depth: u32) -> Self {
+        ApplyContext { index, is_leader, timestamp_ms, depth, follow_ups: Vec::new() }
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    // 提交一条后续命令，在当前条目应用结束后由 Leader Propose
+    pub fn propose(&mut self, data: Vec<u8>) -> Result<(), String> {
+        if !self.is_leader {
+            return Err("follow-up commands are only proposed on the leader".to_string());
+        }
+        if self.depth >= config::FOLLOW_UP_MAX_DEPTH {
+            return Err(format!("entry {} is already {} follow-ups deep", self.index, self.depth));
+        }
+        if self.follow_ups.len() >= config::FOLLOW_UP_MAX_PER_ENTRY {
+            return Err(format!("entry {} already proposed {} follow-ups", self.index, self.follow_ups.len()));
+        }
+        self.follow_ups.push(data);
+        Ok(())
+    }
+
+    pub fn into_follow_ups(self) -> Vec<Vec<u8>> {
+        self.follow_ups
+    }
+}
+
+// 从快照恢复状态机的进度，恢复线程写入，状态查询随时读取，不需要共识模块的锁
+#[derive(Debug, Default)]
+pub struct RestoreProgress {
+    in_progress: AtomicBool,
+    total_bytes: AtomicU64,
+    bytes_restored: AtomicU64,
+    entries_loaded: AtomicU64,
+    started_at: StdMutex<Option<Instant>>,
+    elapsed: StdMutex<Option<Duration>>, // 最近一次恢复结束时的总耗时
 }
 
+impl RestoreProgress {
+    pub fn start(&self, total_bytes: u64) {
+        self.total_bytes.store(total_bytes, Ordering::Relaxed);
+        self.bytes_restored.store(0, Ordering::Relaxed);
+        self.entries_loaded.store(0, Ordering::Relaxed);
+        *self.started_at.lock().unwrap() = Some(Instant::now());
+        *self.elapsed.lock().unwrap() = None;
+        self.in_progress.store(true, Ordering::Release);
+    }
+
+    pub fn finish(&self) {
+        *self.elapsed.lock().unwrap() = self.started_at.lock().unwrap().map(|t| t.elapsed());
+        self.in_progress.store(false, Ordering::Release);
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes_restored.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_entries(&self, entries: u64) {
+        self.entries_loaded.fetch_add(entries, Ordering::Relaxed);
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.in_progress.load(Ordering::Acquire)
+    }
+
+    // 本次启动后还没有恢复过时返回 None
+    pub fn to_proto(&self) -> Option<proto::SnapshotRestoreProgress> {
+        let started_at = (*self.started_at.lock().unwrap())?;
+        let in_progress = self.in_progress();
+        let elapsed = match *self.elapsed.lock().unwrap() {
+            Some(elapsed) if !in_progress => elapsed,
+            _ => started_at.elapsed(),
+        };
+        Some(proto::SnapshotRestoreProgress {
+            in_progress,
+            total_bytes: self.total_bytes.load(Ordering::Relaxed),
+            bytes_restored: self.bytes_restored.load(Ordering::Relaxed),
+            entries_loaded: self.entries_loaded.load(Ordering::Relaxed),
+            elapsed_ms: elapsed.as_millis() as u64,
+        })
+    }
+}
+
+// SimpleStateMachine 每次读取快照文件的字节数，每读一块汇报一次进度
+const RESTORE_READ_CHUNK: usize = 64 * 1024;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct SimpleStateMachine {
@@ -73,17 +226,36 @@ impl StateMachine for SimpleStateMachine {
             }
         }
     }
+    // 忽略查询内容，返回全部条目的JSON
+    fn read(&self, _query: &[u8]) -> Option<Vec<u8>> {
+        serde_json::to_vec(&self.get_entries()).ok()
+    }
+
     fn restore_snapshot(&mut self, snapshot_filepath: &str) {
+        self.restore_snapshot_with_progress(snapshot_filepath, &RestoreProgress::default());
+    }
+
+    fn restore_snapshot_with_progress(&mut self, snapshot_filepath: &str, progress: &RestoreProgress) {
         if Path::new(&snapshot_filepath).exists() {
             match File::open(&snapshot_filepath) {
                 Ok(mut snapshot_file) => {
-                    let mut snapshot_json = String::new();
-                    if let Err(e) = snapshot_file.read_to_string(&mut snapshot_json) {
-                        panic!("SimpleStateMachine: Failed to read snapshot file '{}': {}", snapshot_filepath, e);
+                    // 分块读取，大快照恢复时可以看到读取进度
+                    let mut snapshot_json = Vec::new();
+                    let mut chunk = vec![0u8; RESTORE_READ_CHUNK];
+                    loop {
+                        match snapshot_file.read(&mut chunk) {
+                            Ok(0) => break,
+                            Ok(n) => {
+                                snapshot_json.extend_from_slice(&chunk[..n]);
+                                progress.add_bytes(n as u64);
+                            }
+                            Err(e) => panic!("SimpleStateMachine: Failed to read snapshot file '{}': {}", snapshot_filepath, e),
+                        }
                     }
 
-                    match serde_json::from_str::<Vec<Vec<u8>>>(&snapshot_json) {
+                    match serde_json::from_slice::<Vec<Vec<u8>>>(&snapshot_json) {
                         Ok(restored_entries) => {
+                            progress.add_entries(restored_entries.len() as u64);
                             self.entries = restored_entries;
                             info!("SimpleStateMachine: Snapshot restored from {}", snapshot_filepath);
                         }
@@ -103,5 +275,35 @@ impl StateMachine for SimpleStateMachine {
         }
     }
 
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_restore_snapshot_with_progress() {
+        let dir = tempdir().unwrap();
+        let snapshot_filepath = dir.path().join("raft.snapshot").to_str().unwrap().to_string();
+        let mut state_machine = SimpleStateMachine::new();
+        for i in 0..3 {
+            state_machine.apply(&format!("entry-{}", i).into_bytes());
+        }
+        state_machine.take_snapshot(&snapshot_filepath);
+        let total_bytes = std::fs::metadata(&snapshot_filepath).unwrap().len();
+
+        let progress = RestoreProgress::default();
+        assert!(progress.to_proto().is_none());
+        progress.start(total_bytes);
+        assert!(progress.in_progress());
 
-}
\ No newline at end of file
+        let mut restored = SimpleStateMachine::new();
+        restored.restore_snapshot_with_progress(&snapshot_filepath, &progress);
+        progress.finish();
+        assert_eq!(restored.get_entries(), vec!["entry-0", "entry-1", "entry-2"]);
+
+        let report = progress.to_proto().unwrap();
+        assert!(!report.in_progress);
+        assert_eq!((report.bytes_restored, report.total_bytes, report.entries_loaded), (total_bytes, total_bytes, 3));
+    }
+}
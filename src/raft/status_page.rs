@@ -0,0 +1,318 @@
+use crate::raft::consensus::{Consensus, State};
+use crate::raft::{audit, config, proto, state_machine};
+use super::logging::*;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex as TokioMutex;
+
+// 只读的 HTTP 状态页：运维排查小集群时直接用浏览器或 curl 查看节点的角色、任期、
+// 各 Peer 的复制落后量、最近的快照和选举记录，不需要 grpcurl。
+// /ready 是给负载均衡器的就绪检查，就绪时返回 200，否则返回 503 和原因。
+// 只实现了 GET 请求的最小 HTTP/1.1 应答，每个连接处理一个请求后关闭
+
+// 渲染状态页需要的数据，在共识模块的锁内一次性收集
+#[derive(Debug, Clone)]
+pub struct NodeStatus {
+    pub server_id: u64,
+    pub server_addr: String,
+    pub state: State,
+    pub current_term: u64,
+    pub voted_for: u64,
+    pub leader: Option<proto::ServerInfo>,
+    pub config_mismatch: Option<String>, // 配置中记录的本节点地址与实际地址不一致时为配置中的地址
+    pub log_gap: Option<u64>,            // 找不到而无法应用的已提交条目
+    pub incompatible_snapshot: Option<String>, // 状态机拒绝恢复的快照及原因
+    pub not_ready: Option<String>,       // 就绪检查未通过的原因
+    pub commit_index: u64,
+    pub last_applied: u64,
+    pub last_log_index: u64,
+    pub peers: Vec<PeerStatus>,
+    pub snapshot: proto::GetSnapshotStatusResponse,
+    pub recent_events: Vec<audit::AuditRecord>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerStatus {
+    pub id: u64,
+    pub addr: String,
+    pub match_index: u64,
+    pub last_applied: u64,              // 节点在最近一次响应中报告的应用进度，只在 Leader 上有意义
+    pub lag: u64,                      // Leader 最后的日志索引与 match_index 之差，只在 Leader 上有意义
+    pub slow: bool,
+    pub median_rtt: Option<Duration>,
+    pub last_contact: Option<Duration>, // 距离最近一次收到该节点响应的时间
+}
+
+impl NodeStatus {
+    pub async fn collect(consensus: &Arc<TokioMutex<Consensus>>) -> Self {
+        let (mut status, audit_filepath) = {
+            let guard = consensus.lock().await;
+            let metadata = guard.metadata.get().await;
+            let last_log_index = guard.log.last_index(guard.snapshot.last_included_index());
+            let peers = guard.peer_manager.peers().iter().map(|p| PeerStatus {
+                id: p.id,
+                addr: p.addr.clone(),
+                match_index: p.match_index,
+                last_applied: p.last_applied,
+                lag: last_log_index.saturating_sub(p.match_index),
+                slow: p.lag.slow,
+                median_rtt: p.rtt.median(),
+                last_contact: p.last_contact.map(|t| t.elapsed()),
+            }).collect();
+            let status = NodeStatus {
+                server_id: guard.server_id,
+                server_addr: guard.server_addr.clone(),
+                state: guard.state,
+                current_term: metadata.current_term,
+                voted_for: metadata.voted_for,
+                leader: guard.handle_get_leader_rpc(&proto::GetLeaderRequest {}).leader,
+                config_mismatch: guard.config_mismatch.clone(),
+                log_gap: guard.log_gap,
+                incompatible_snapshot: guard.incompatible_snapshot.clone(),
+                not_ready: guard.not_ready_reason(),
+                commit_index: guard.commit_index,
+                last_applied: guard.last_applied,
+                last_log_index,
+                peers,
+                snapshot: guard.handle_get_snapshot_status_rpc(&proto::GetSnapshotStatusRequest {}),
+                recent_events: Vec::new(),
+            };
+            (status, guard.audit_filepath().to_string())
+        };
+
+        // 审计日志在锁外读取
+        match audit::AuditLog::read_records(&audit_filepath) {
+            Ok(records) => {
+                let skip = records.len().saturating_sub(config::STATUS_PAGE_RECENT_EVENTS);
+                status.recent_events = records.into_iter().skip(skip).collect();
+            }
+            Err(e) => debug!("Failed to read audit log {} for status page: {}", audit_filepath, e),
+        }
+        status
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!("Raft node {} ({})\n\n", self.server_id, self.server_addr));
+        out.push_str(&format!("Role:         {:?}\n", self.state));
+        out.push_str(&format!("Term:         {}\n", self.current_term));
+        let voted_for = match self.voted_for {
+            config::NONE_SERVER_ID => "none".to_string(),
+            id => id.to_string(),
+        };
+        out.push_str(&format!("Voted for:    {}\n", voted_for));
+        let leader = match &self.leader {
+            Some(leader) => format!("{} ({})", leader.server_id, leader.server_addr),
+            None => "unknown".to_string(),
+        };
+        out.push_str(&format!("Leader:       {}\n", leader));
+        let health = match (&self.log_gap, &self.incompatible_snapshot, &self.config_mismatch) {
+            (Some(index), _, _) => format!("LogGap (committed entry {} is missing, apply is stopped)", index),
+            (None, Some(reason), _) => format!("IncompatibleSnapshot ({})", reason),
+            (None, None, Some(addr)) => format!("ConfigMismatch (configuration advertises this node at {})", addr),
+            (None, None, None) => "Ok".to_string(),
+        };
+        out.push_str(&format!("Health:       {}\n", health));
+        match &self.not_ready {
+            Some(reason) => out.push_str(&format!("Ready:        no ({})\n", reason)),
+            None => out.push_str("Ready:        yes\n"),
+        }
+        out.push_str(&format!("Log:          last_index={} commit_index={} last_applied={}\n",
+            self.last_log_index, self.commit_index, self.last_applied));
+
+        out.push_str("\nPeers:\n");
+        if self.peers.is_empty() {
+            out.push_str("  (none)\n");
+        }
+        for peer in &self.peers {
+            let (lag, applied) = if self.state == State::Leader {
+                (peer.lag.to_string(), peer.last_applied.to_string())
+            } else {
+                ("-".to_string(), "-".to_string())
+            };
+            out.push_str(&format!(
+                "  {:>4}  {:<24} match_index={:<8} applied={:<8} lag={:<8} rtt={:<12} last_contact={}{}\n",
+                peer.id,
+                peer.addr,
+                peer.match_index,
+                applied,
+                lag,
+                peer.median_rtt.map_or("-".to_string(), |rtt| format!("{:?}", rtt)),
+                peer.last_contact.map_or("never".to_string(), |d| format!("{}ms ago", d.as_millis())),
+                if peer.slow { "  [slow]" } else { "" },
+            ));
+        }
+
+        out.push_str("\nSnapshot:\n");
+        for line in super::client::format_snapshot_status(&self.snapshot).lines() {
+            out.push_str(&format!("  {}\n", line));
+        }
+
+        out.push_str("\nRecent elections and role changes:\n");
+        if self.recent_events.is_empty() {
+            out.push_str("  (none)\n");
+        }
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        for record in self.recent_events.iter().rev() {
+            out.push_str(&format!(
+                "  {:>8}s ago  term={:<6} {:?}: {:?}\n",
+                now_ms.saturating_sub(record.timestamp_ms) / 1000,
+                record.term,
+                record.reason,
+                record.event,
+            ));
+        }
+        out
+    }
+}
+
+pub async fn bind(addr: &str) -> std::io::Result<TcpListener> {
+    TcpListener::bind(addr).await
+}
+
+// 处理状态页请求，直到监听失败或任务被取消
+pub async fn serve(listener: TcpListener, consensus: Arc<TokioMutex<Consensus>>) {
+    // 恢复快照期间共识模块一直被占用，就绪检查直接从这里读取恢复状态
+    let restore_progress = Arc::clone(&consensus.lock().await.restore_progress);
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Status page listener failed to accept: {}", e);
+                return;
+            }
+        };
+        let consensus = Arc::clone(&consensus);
+        let restore_progress = Arc::clone(&restore_progress);
+        tokio::spawn(async move {
+            let result = tokio::time::timeout(
+                config::STATUS_PAGE_REQUEST_TIMEOUT,
+                handle_connection(stream, &consensus, &restore_progress),
+            ).await;
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!("Status page request from {} failed: {}", remote, e),
+                Err(_) => debug!("Status page request from {} timed out", remote),
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    consensus: &Arc<TokioMutex<Consensus>>,
+    restore_progress: &state_machine::RestoreProgress,
+) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() >= config::STATUS_PAGE_MAX_REQUEST_BYTES {
+            return write_response(&mut stream, "431 Request Header Fields Too Large", "request too large\n").await;
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request_line = String::from_utf8_lossy(&request).lines().next().unwrap_or_default().to_string();
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    if method != "GET" {
+        return write_response(&mut stream, "405 Method Not Allowed", "only GET is supported\n").await;
+    }
+    match path {
+        "/" | "/status" => {
+            let body = NodeStatus::collect(consensus).await.render();
+            write_response(&mut stream, "200 OK", &body).await
+        }
+        "/ready" => {
+            let not_ready = if restore_progress.in_progress() {
+                Some("restoring the state machine from a snapshot".to_string())
+            } else {
+                consensus.lock().await.not_ready_reason()
+            };
+            match not_ready {
+                Some(reason) => write_response(&mut stream, "503 Service Unavailable", &format!("not ready: {}\n", reason)).await,
+                None => write_response(&mut stream, "200 OK", "ready\n").await,
+            }
+        }
+        _ => write_response(&mut stream, "404 Not Found", "not found, try /status or /ready\n").await,
+    }
+}
+
+async fn write_response(stream: &mut TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_status_page() {
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        let status = NodeStatus {
+            server_id: 1,
+            server_addr: "[::1]:9001".to_string(),
+            state: State::Leader,
+            current_term: 7,
+            voted_for: 1,
+            leader: Some(proto::ServerInfo { server_id: 1, server_addr: "[::1]:9001".to_string(), zone: None }),
+            config_mismatch: None,
+            log_gap: None,
+            incompatible_snapshot: None,
+            not_ready: None,
+            commit_index: 95,
+            last_applied: 90,
+            last_log_index: 100,
+            peers: vec![PeerStatus {
+                id: 2,
+                addr: "[::1]:9002".to_string(),
+                match_index: 40,
+                last_applied: 35,
+                lag: 60,
+                slow: true,
+                median_rtt: Some(Duration::from_millis(3)),
+                last_contact: Some(Duration::from_millis(15)),
+            }],
+            snapshot: proto::GetSnapshotStatusResponse::default(),
+            recent_events: vec![audit::AuditRecord {
+                timestamp_ms: now_ms - 5_000,
+                server_id: 1,
+                term: 7,
+                reason: audit::AuditReason::ElectionWon,
+                event: audit::AuditEvent::StateChanged { from: State::Candidate, to: State::Leader },
+            }],
+        };
+
+        let page = status.render();
+        assert!(page.contains("Role:         Leader"));
+        assert!(page.contains("Term:         7"));
+        assert!(page.contains("Health:       Ok"));
+        assert!(page.contains("Ready:        yes"));
+        assert!(page.contains("match_index=40"));
+        assert!(page.contains("applied=35"));
+        assert!(page.contains("lag=60"));
+        assert!(page.contains("[slow]"));
+        assert!(page.contains("Snapshot: none"));
+        assert!(page.contains("ElectionWon"));
+
+        // Follower 上 match_index 没有维护，不展示落后量
+        let follower = NodeStatus { state: State::Follower, not_ready: Some("receiving snapshot".to_string()), ..status };
+        let page = follower.render();
+        assert!(page.contains("lag=-"));
+        assert!(page.contains("applied=-"));
+        assert!(page.contains("Ready:        no (receiving snapshot)"));
+    }
+}
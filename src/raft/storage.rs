@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// 节点数据目录的布局：同一个根目录下每个节点有自己的快照和元数据目录，
+//   {root}/.snapshot/server_{id}
+//   {root}/.metadata/server_{id}
+//   {root}/logs
+// 同一进程内启动多个节点（例如集成测试）时，只要根目录不同就不会互相覆盖；
+// 使用 temp() 创建的根目录在最后一个副本被 drop 时删除
+#[derive(Debug, Clone)]
+pub struct StoragePaths {
+    root: PathBuf,
+    _temp_dir: Option<Arc<TempDir>>,
+}
+
+impl StoragePaths {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StoragePaths { root: root.into(), _temp_dir: None }
+    }
+
+    // 在系统临时目录下创建一个独占的根目录
+    pub fn temp() -> std::io::Result<Self> {
+        let temp_dir = tempfile::Builder::new().prefix("raft-").tempdir()?;
+        Ok(StoragePaths { root: temp_dir.path().to_path_buf(), _temp_dir: Some(Arc::new(temp_dir)) })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn snapshot_dir(&self, server_id: u64) -> PathBuf {
+        self.root.join(".snapshot").join(format!("server_{}", server_id))
+    }
+
+    pub fn metadata_dir(&self, server_id: u64) -> PathBuf {
+        self.root.join(".metadata").join(format!("server_{}", server_id))
+    }
+
+    pub fn log_dir(&self) -> PathBuf {
+        self.root.join("logs")
+    }
+
+    // 创建节点的快照和元数据目录，返回两者的字符串形式供 Consensus 使用
+    pub fn create_node_dirs(&self, server_id: u64) -> std::io::Result<(String, String)> {
+        let snapshot_dir = self.snapshot_dir(server_id);
+        let metadata_dir = self.metadata_dir(server_id);
+        std::fs::create_dir_all(&snapshot_dir)?;
+        std::fs::create_dir_all(&metadata_dir)?;
+        Ok((path_to_string(&snapshot_dir)?, path_to_string(&metadata_dir)?))
+    }
+}
+
+// 文件最后修改时间距今是否超过 min_age；拿不到修改时间或修改时间在未来时视为不够旧
+pub fn is_older_than(metadata: &std::fs::Metadata, min_age: Duration) -> bool {
+    metadata.modified().ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age >= min_age)
+}
+
+// 删除目录中上次运行遗留的 .tmp 文件（原子写入中途崩溃、快照传输中断等），返回删除的文件。
+// 只处理超过 min_age 的文件，避免误删另一个仍在写入的进程的文件
+pub fn remove_stale_tmp_files(dir: &Path, min_age: Duration) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut removed = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let stale = path.extension().is_some_and(|ext| ext == "tmp")
+            && entry.metadata().is_ok_and(|m| m.is_file() && is_older_than(&m, min_age));
+        if stale && std::fs::remove_file(&path).is_ok() {
+            removed.push(path);
+        }
+    }
+    removed
+}
+
+fn path_to_string(path: &Path) -> std::io::Result<String> {
+    path.to_str().map(str::to_string).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("path {} is not valid UTF-8", path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_paths_namespacing_and_temp_cleanup() {
+        let storage = StoragePaths::temp().unwrap();
+        let root = storage.root().to_path_buf();
+
+        let (snapshot_1, metadata_1) = storage.create_node_dirs(1).unwrap();
+        let (snapshot_2, metadata_2) = storage.create_node_dirs(2).unwrap();
+        assert_ne!(snapshot_1, snapshot_2);
+        assert_ne!(metadata_1, metadata_2);
+        assert_ne!(snapshot_1, metadata_1);
+        assert!(Path::new(&metadata_2).is_dir());
+
+        // 另一个临时根目录互不影响
+        let other = StoragePaths::temp().unwrap();
+        assert_ne!(other.root(), storage.root());
+
+        // 副本仍然存活时目录不会被删除
+        let clone = storage.clone();
+        drop(storage);
+        assert!(root.is_dir());
+        drop(clone);
+        assert!(!root.exists());
+    }
+
+    #[test]
+    fn test_remove_stale_tmp_files() {
+        let storage = StoragePaths::temp().unwrap();
+        let dir = storage.root();
+        std::fs::write(dir.join("raft.metadata.tmp"), b"partial").unwrap();
+        std::fs::write(dir.join("raft.metadata"), b"{}").unwrap();
+
+        // 刚写入的临时文件不够旧，保留
+        assert!(remove_stale_tmp_files(dir, Duration::from_secs(600)).is_empty());
+        assert_eq!(remove_stale_tmp_files(dir, Duration::ZERO), vec![dir.join("raft.metadata.tmp")]);
+        assert!(dir.join("raft.metadata").exists());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::raft::{config, consensus, proto};
+use std::sync::Arc;
+use tokio::sync::{watch, Mutex as TokioMutex};
+
+// 已提交条目的订阅：供下游的索引器等外部观察者跟随日志。与 CommitSink 不同，订阅不影响快照，
+// 也不记录订阅者的进度：订阅者自己记住处理到的索引，重启后带上 start_index 重新订阅，
+// 先回放日志中还保留的条目，追上 last_applied 之后等待新的条目应用。
+// 订阅者落后到条目已被快照压缩时收到 snapshot_required，流随之结束
+
+struct Cursor {
+    consensus: Arc<TokioMutex<consensus::Consensus>>,
+    next: Option<u64>, // 下一个要推送的索引，实时跟随时在第一次读取时确定
+    applied: Option<watch::Receiver<u64>>,
+    stop: watch::Receiver<bool>,
+    finished: bool,
+}
+
+pub fn committed_entries(
+    consensus: Arc<TokioMutex<consensus::Consensus>>,
+    start_index: Option<u64>,
+    stop: watch::Receiver<bool>,
+) -> impl futures::Stream<Item = proto::SubscribeCommittedResponse> + Send {
+    let cursor = Cursor { consensus, next: start_index.map(|index| index.max(1)), applied: None, stop, finished: false };
+    futures::stream::unfold(cursor, |mut cursor| async move {
+        loop {
+            if cursor.finished || *cursor.stop.borrow() {
+                return None;
+            }
+            let batch = {
+                let guard = cursor.consensus.lock().await;
+                // 持有锁时标记已读到的 last_applied，之后的每次推进都会唤醒下面的等待
+                cursor.applied.get_or_insert_with(|| guard.subscribe_applied()).borrow_and_update();
+                let next = *cursor.next.get_or_insert(guard.last_applied + 1);
+                guard.committed_entries_from(next, config::SUBSCRIBE_COMMITTED_BATCH, config::SUBSCRIBE_COMMITTED_BATCH_BYTES)
+            };
+            if batch.snapshot_required {
+                cursor.finished = true;
+                return Some((batch, cursor));
+            }
+            if let Some(last) = batch.entries.last() {
+                cursor.next = Some(last.index + 1);
+                return Some((batch, cursor));
+            }
+            let applied = cursor.applied.as_mut()?;
+            tokio::select! {
+                changed = applied.changed() => changed.ok()?,
+                changed = cursor.stop.changed() => changed.ok()?,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft::{state_machine};
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_subscription_replays_then_follows() {
+        let storage = crate::raft::storage::StoragePaths::temp().unwrap();
+        let consensus = consensus::tests::test_consensus(&storage, Box::new(state_machine::SimpleStateMachine::new())).await;
+        let stop = consensus.lock().await.stop_signal();
+        let propose = |data: &str| proto::ProposeRequest { data: data.as_bytes().to_vec(), wait_for_commit: true };
+        consensus.lock().await.handle_election_timeout().await;
+        for data in ["a", "b"] {
+            assert!(consensus::Consensus::propose_and_wait(&consensus, &propose(data)).await.success);
+        }
+        let last_applied = consensus.lock().await.last_applied;
+
+        // 从头订阅：先回放已有的条目，之后推送新应用的条目
+        let mut replay = Box::pin(committed_entries(Arc::clone(&consensus), Some(0), stop.clone()));
+        let first = replay.next().await.unwrap();
+        assert!(!first.snapshot_required);
+        assert_eq!(first.entries.first().unwrap().index, 1);
+        assert_eq!(first.entries.last().unwrap().index, last_applied);
+        assert!(first.entries.iter().any(|entry| entry.data == b"b"));
+
+        // 不指定起点时只跟随之后应用的条目
+        let mut live = Box::pin(committed_entries(Arc::clone(&consensus), None, stop.clone()));
+        // 第一次轮询确定起点后进入等待，这之后提交的条目都会被推送
+        assert!(tokio::time::timeout(Duration::from_millis(50), live.next()).await.is_err());
+        assert!(consensus::Consensus::propose_and_wait(&consensus, &propose("c")).await.success);
+        let live_batch = tokio::time::timeout(Duration::from_secs(5), live.next()).await.unwrap().unwrap();
+        assert_eq!(live_batch.entries.first().unwrap().index, last_applied + 1);
+        assert_eq!(live_batch.entries.last().unwrap().data, b"c");
+        assert_eq!(replay.next().await.unwrap().entries, live_batch.entries);
+
+        // 每批数据受字节上限约束，但至少携带一个条目
+        let guard = consensus.lock().await;
+        assert_eq!(guard.committed_entries_from(1, usize::MAX, usize::MAX).entries.len() as u64, guard.last_applied);
+        let one_byte = guard.committed_entries_from(last_applied + 1, usize::MAX, 0);
+        assert_eq!(one_byte.entries.len(), 1);
+        drop(guard);
+
+        // 起点之前的条目已被快照压缩：告知订阅者需要快照，流随之结束
+        let (snapshot_index, _) = consensus::Consensus::snapshot_for_export(&consensus).await.unwrap();
+        let mut compacted = Box::pin(committed_entries(Arc::clone(&consensus), Some(1), stop.clone()));
+        let batch = compacted.next().await.unwrap();
+        assert!(batch.snapshot_required && batch.entries.is_empty());
+        assert_eq!(batch.snapshot_last_included_index, snapshot_index);
+        assert!(compacted.next().await.is_none());
+
+        // 节点停止时正在等待的订阅结束
+        consensus.lock().await.stop().await;
+        assert!(replay.next().await.is_none());
+    }
+}
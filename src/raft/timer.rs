@@ -2,27 +2,33 @@ use tracing::info;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::time::Instant as TokioInstant;
+use crate::raft::clock::{Clock, SystemClock};
 
 #[derive(Debug)]
 pub struct Timer {
     name: String,
     alive: Arc<AtomicBool>,                         // 控制计时器是否在执行
     interval: Arc<Mutex<Duration>>,                 // 计时器触发间隔 (std::sync::Mutex is okay here)
-    next_trigger: Arc<Mutex<TokioInstant>>,         // 计时器下次触发时间 (use TokioInstant)
-    pub last_reset: Option<std::time::Instant>,     // 上次重置计时器的时间 (std::time::Instant is fine for this field)
+    next_trigger: Arc<Mutex<Instant>>,              // 计时器下次触发时间
+    pub last_reset: Option<Instant>,                // 上次重置计时器的时间
+    clock: Arc<dyn Clock>,                          // 时间来源，测试中可替换为 MockClock
     handle: Option<tokio::task::JoinHandle<()>>,    // 计时器内部任务句柄
     stop_tx: Option<tokio::sync::watch::Sender<()>>, // 用于通知任务停止
 }
 
 impl Timer {
     pub fn new(name: &str) -> Self {
+        Self::with_clock(name, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(name: &str, clock: Arc<dyn Clock>) -> Self {
          Timer {
             name: name.to_string(),
             alive: Arc::new(AtomicBool::new(false)),
             interval: Arc::new(Mutex::new(Duration::from_secs(std::u64::MAX))),
-            next_trigger: Arc::new(Mutex::new(TokioInstant::now())),
+            next_trigger: Arc::new(Mutex::new(clock.now())),
             last_reset: None,
+            clock,
             handle: None,
             stop_tx: None,
          }
@@ -43,10 +49,11 @@ impl Timer {
         }
 
         *self.interval.lock().unwrap() = trigger_interval;
-        *self.next_trigger.lock().unwrap() = TokioInstant::now() + trigger_interval;
+        *self.next_trigger.lock().unwrap() = self.clock.now() + trigger_interval;
         self.alive.store(true, Ordering::SeqCst);
 
         let name_clone = self.name.clone();
+        let clock = self.clock.clone();
         let interval_arc = self.interval.clone();
         let next_trigger_arc = self.next_trigger.clone();
         let alive_arc = self.alive.clone();
@@ -62,7 +69,7 @@ impl Timer {
                 }
 
                 tokio::select! {
-                    _ = tokio::time::sleep_until(current_next_trigger_time) => {
+                    _ = clock.sleep_until(current_next_trigger_time) => {
                         // Check alive status first, in case stop was called during sleep
                         if !alive_arc.load(Ordering::SeqCst) {
                             info!("{} task: alive is false after sleep, exiting.", name_clone);
@@ -83,7 +90,7 @@ impl Timer {
                         { // Scoped lock
                             current_interval = *interval_arc.lock().unwrap();
                         }
-                        let new_next_trigger = TokioInstant::now() + current_interval;
+                        let new_next_trigger = clock.now() + current_interval;
                         *next_trigger_arc.lock().unwrap() = new_next_trigger;
                         // info!("{} task: triggered, next at {:?}", name_clone, new_next_trigger);
                     }
@@ -121,6 +128,11 @@ impl Timer {
         }
     }
 
+    // 计时器任务是否在运行（已 schedule 且没有 stop）
+    pub fn is_running(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
      pub fn reset(&mut self, trigger_interval: Duration) {
         info!(
             "{} reset with trigger interval: {}ms",
@@ -128,9 +140,10 @@ impl Timer {
             trigger_interval.as_millis(),
         );
 
-        self.last_reset = Some(std::time::Instant::now());
+        let now = self.clock.now();
+        self.last_reset = Some(now);
         *self.interval.lock().unwrap() = trigger_interval;
-        *self.next_trigger.lock().unwrap() = TokioInstant::now() + trigger_interval;
+        *self.next_trigger.lock().unwrap() = now + trigger_interval;
 
         if self.alive.load(Ordering::SeqCst) {
         } else {
@@ -247,4 +260,41 @@ mod tests {
 
         println!("Test finished");
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_timer_with_mock_clock() {
+        setup_tracing();
+        let clock = Arc::new(crate::raft::clock::MockClock::new());
+        let mut timer = Timer::with_clock("test_timer_mock_clock", clock.clone());
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let counter_clone = counter.clone();
+        timer.schedule(Duration::from_secs(10), move || {
+            counter_clone.fetch_add(1, AtomicOrdering::SeqCst);
+        });
+
+        // 等待回调在阻塞线程池中执行完成
+        async fn wait_for_count(counter: &AtomicUsize, expected: usize) {
+            for _ in 0..100 {
+                if counter.load(AtomicOrdering::SeqCst) >= expected {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }
+
+        // 时间不推进就不会触发
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(counter.load(AtomicOrdering::SeqCst), 0);
+
+        clock.advance(Duration::from_secs(10));
+        wait_for_count(&counter, 1).await;
+        assert_eq!(counter.load(AtomicOrdering::SeqCst), 1);
+
+        clock.advance(Duration::from_secs(10));
+        wait_for_count(&counter, 2).await;
+        assert_eq!(counter.load(AtomicOrdering::SeqCst), 2);
+
+        timer.stop().await;
+    }
+}
@@ -1,8 +1,154 @@
 use crate::raft::config;
-use rand::{self, Rng};
-use std::time::{Duration, Instant};
+use super::logging::*;
+use rand::rngs::StdRng;
+use rand::distr::uniform::{SampleRange, SampleUniform};
+use rand::{Rng as _, SeedableRng};
+use std::net::SocketAddr;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 进程内共享的随机数源，选举超时抖动和故障注入都从这里取随机数。
+// 种子可以通过 RaftOptions.rng_seed 或环境变量 RAFT_RNG_SEED 指定，
+// 启动时会打印实际使用的种子，失败的运行可以用同一个种子重放
+pub struct Rng {
+    state: StdMutex<(u64, StdRng)>,
+}
+
+lazy_static::lazy_static! {
+    static ref GLOBAL_RNG: Rng = Rng::new(seed_from_env().unwrap_or_else(rand::random));
+}
+
+fn seed_from_env() -> Option<u64> {
+    let value = std::env::var(config::RNG_SEED_ENV).ok()?;
+    match value.parse() {
+        Ok(seed) => Some(seed),
+        Err(e) => {
+            warn!("Ignoring invalid {}={}: {}", config::RNG_SEED_ENV, value, e);
+            None
+        }
+    }
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: StdMutex::new((seed, StdRng::seed_from_u64(seed))) }
+    }
+
+    pub fn global() -> &'static Rng {
+        &GLOBAL_RNG
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.state.lock().unwrap().0
+    }
+
+    pub fn reseed(&self, seed: u64) {
+        *self.state.lock().unwrap() = (seed, StdRng::seed_from_u64(seed));
+    }
+
+    pub fn random_range<T: SampleUniform, R: SampleRange<T>>(&self, range: R) -> T {
+        self.state.lock().unwrap().1.random_range(range)
+    }
+}
+
+// 初始化全局随机数源并返回实际使用的种子：指定了种子时重新播种，
+// 否则沿用已有的种子（同一进程内启动多个节点时不会互相覆盖）
+pub fn init_rng(seed: Option<u64>) -> u64 {
+    let rng = Rng::global();
+    if let Some(seed) = seed {
+        rng.reseed(seed);
+    }
+    rng.seed()
+}
 
 pub fn rand_election_timeout() -> Duration {
-    let timeout = rand::random_range(config::ELECTION_TIMEOUT_MIN_MILLIS..config::ELECTION_TIMEOUT_MAX_MILLIS);
+    let timeout = Rng::global().random_range(config::ELECTION_TIMEOUT_MIN_MILLIS..config::ELECTION_TIMEOUT_MAX_MILLIS);
     Duration::from_millis(timeout)
-}
\ No newline at end of file
+}
+
+// 当前的 Unix 时间（微秒），用于节点之间交换的时间戳；时钟早于 1970 年时为 0
+pub fn unix_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64)
+}
+
+// 配置中记录的地址与本节点实际对外的地址的比较结果，见 compare_addresses
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressMatch {
+    Same,
+    Different,
+    Unresolved(String), // 有一个地址无法解析，无法判断
+}
+
+// 判断配置中记录的地址 recorded 是否指向本节点实际对外的地址 actual：字符串相同，或者有相同的 IP 和端口；
+// 本节点监听在通配地址（0.0.0.0 或 [::]）上时只比较端口。Unix domain socket 地址只比较字符串。
+// 两个地址都是 IP 字面量时直接得出结果，返回 None 表示需要 DNS 解析（见 compare_addresses）
+pub fn compare_literal_addresses(recorded: &str, actual: &str) -> Option<AddressMatch> {
+    if recorded == actual {
+        return Some(AddressMatch::Same);
+    }
+    if recorded.starts_with(config::UDS_ADDR_PREFIX) || actual.starts_with(config::UDS_ADDR_PREFIX) {
+        return Some(AddressMatch::Different);
+    }
+    let recorded: SocketAddr = recorded.parse().ok()?;
+    let actual: SocketAddr = actual.parse().ok()?;
+    Some(match_resolved(&[recorded], &[actual]))
+}
+
+// 同 compare_literal_addresses，主机名通过异步 DNS 解析，不阻塞运行时的工作线程
+pub async fn compare_addresses(recorded: &str, actual: &str) -> AddressMatch {
+    if let Some(result) = compare_literal_addresses(recorded, actual) {
+        return result;
+    }
+    let resolve = |addr: &str| {
+        let addr = addr.to_string();
+        async move {
+            tokio::net::lookup_host(&addr).await
+                .map(|addrs| addrs.collect::<Vec<_>>())
+                .map_err(|e| format!("cannot resolve {}: {}", addr, e))
+        }
+    };
+    match (resolve(recorded).await, resolve(actual).await) {
+        (Ok(recorded), Ok(actual)) => match_resolved(&recorded, &actual),
+        (Err(e), _) | (_, Err(e)) => AddressMatch::Unresolved(e),
+    }
+}
+
+fn match_resolved(recorded: &[SocketAddr], actual: &[SocketAddr]) -> AddressMatch {
+    let same = recorded.iter().any(|r| actual.iter().any(|a| a == r || (a.ip().is_unspecified() && a.port() == r.port())));
+    if same { AddressMatch::Same } else { AddressMatch::Different }
+}
+
+// 通配地址只能用来监听，不能写进配置让其他节点连接
+pub fn is_wildcard_address(addr: &str) -> bool {
+    addr.parse::<SocketAddr>().is_ok_and(|a| a.ip().is_unspecified())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_is_reproducible() {
+        let a = Rng::new(42);
+        let b = Rng::new(42);
+        let seq_a: Vec<u64> = (0..8).map(|_| a.random_range(0..1000)).collect();
+        let seq_b: Vec<u64> = (0..8).map(|_| b.random_range(0..1000)).collect();
+        assert_eq!(seq_a, seq_b);
+
+        // 重新播种后从头重放同样的序列
+        a.reseed(42);
+        assert_eq!(a.seed(), 42);
+        assert_eq!((0..8).map(|_| a.random_range(0..1000)).collect::<Vec<u64>>(), seq_a);
+    }
+
+    #[tokio::test]
+    async fn test_compare_addresses() {
+        assert_eq!(compare_literal_addresses("[::1]:5", "[::]:5"), Some(AddressMatch::Same));
+        assert_eq!(compare_literal_addresses("[::1]:5", "[::1]:6"), Some(AddressMatch::Different));
+        assert_eq!(compare_literal_addresses("unix:/tmp/a.sock", "unix:/tmp/b.sock"), Some(AddressMatch::Different));
+        // 主机名需要解析，解析失败时不下结论
+        assert_eq!(compare_literal_addresses("localhost:5", "127.0.0.1:5"), None);
+        assert!(matches!(compare_addresses("no-such-host.invalid:5", "[::1]:5").await, AddressMatch::Unresolved(_)));
+        assert!(is_wildcard_address("0.0.0.0:5") && !is_wildcard_address("[::1]:5"));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::raft::{config, events, log, metrics, snapshot};
+use super::logging::*;
+use std::io::Read;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// 后台校验：周期性地检查磁盘上的日志文件和最新快照的校验和，
+// 在崩溃恢复之前尽早发现数据损坏，并通过事件和指标上报
+
+// 发现的一处损坏
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyIssue {
+    pub filepath: String,
+    pub reason: String,
+}
+
+// 限速读取，每秒最多读取 bytes_per_sec 字节，避免校验占满磁盘带宽
+pub struct ThrottledReader<R> {
+    inner: R,
+    bytes_per_sec: u64,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    pub fn new(inner: R, bytes_per_sec: u64) -> Self {
+        ThrottledReader {
+            inner,
+            bytes_per_sec: bytes_per_sec.max(1),
+            window_start: Instant::now(),
+            window_bytes: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.window_bytes >= self.bytes_per_sec {
+            let elapsed = self.window_start.elapsed();
+            if elapsed < Duration::from_secs(1) {
+                std::thread::sleep(Duration::from_secs(1) - elapsed);
+            }
+            self.window_start = Instant::now();
+            self.window_bytes = 0;
+        }
+        let max_len = std::cmp::min(buf.len() as u64, self.bytes_per_sec - self.window_bytes) as usize;
+        let n = self.inner.read(&mut buf[..max_len])?;
+        self.window_bytes += n as u64;
+        Ok(n)
+    }
+}
+
+fn modified_time(filepath: &str) -> Option<std::time::SystemTime> {
+    std::fs::metadata(filepath).and_then(|m| m.modified()).ok()
+}
+
+// 校验日志文件，文件在读取过程中被重写时不认为是损坏
+fn verify_log(metadata_dir: &str, bytes_per_sec: u64) -> Option<VerifyIssue> {
+    let filepath = log::Log::gen_log_filepath(metadata_dir);
+    let modified_before = modified_time(&filepath)?;
+    let result = std::fs::File::open(&filepath)
+        .map_err(|e| format!("failed to open log file: {}", e))
+        .and_then(|file| log::Log::verify_reader(ThrottledReader::new(file, bytes_per_sec)));
+    match result {
+        Ok(entries) => {
+            debug!("verify: log file {} is valid, {} entries", filepath, entries);
+            None
+        }
+        Err(_) if modified_time(&filepath) != Some(modified_before) => {
+            debug!("verify: log file {} changed while verifying, skipping this round", filepath);
+            None
+        }
+        Err(reason) => Some(VerifyIssue { filepath, reason }),
+    }
+}
+
+// 校验最新快照的元数据能否解析，以及快照数据文件与元数据中记录的校验和是否一致
+fn verify_snapshot(snapshot_dir: &str, bytes_per_sec: u64) -> Option<VerifyIssue> {
+    let manager = snapshot::SnapshotManager::new(snapshot_dir.to_string());
+    let metadata_filepath = manager.latest_metadata_filepath()?;
+    let meta = match std::fs::read_to_string(&metadata_filepath)
+        .map_err(|e| e.to_string())
+        .and_then(|json| snapshot::SnapshotMeta::parse(&json))
+    {
+        Ok(meta) => meta,
+        Err(e) => {
+            return Some(VerifyIssue {
+                filepath: metadata_filepath,
+                reason: format!("failed to parse snapshot metadata: {}", e),
+            });
+        }
+    };
+    // checksum 为 0 表示旧版本元数据，无法校验
+    if meta.checksum == 0 {
+        return None;
+    }
+
+    let snapshot_filepath = manager.gen_snapshot_filepath(meta.last_included_index, meta.last_included_term);
+    let result = std::fs::File::open(&snapshot_filepath)
+        .and_then(|file| snapshot::checksum_reader(ThrottledReader::new(file, bytes_per_sec)));
+    match result {
+        Ok(checksum) if checksum == meta.checksum => {
+            debug!("verify: snapshot {} matches its checksum", snapshot_filepath);
+            None
+        }
+        Ok(checksum) => Some(VerifyIssue {
+            filepath: snapshot_filepath,
+            reason: format!("checksum mismatch, expected {:x} but computed {:x}", meta.checksum, checksum),
+        }),
+        Err(e) => Some(VerifyIssue {
+            filepath: snapshot_filepath,
+            reason: format!("failed to read snapshot file: {}", e),
+        }),
+    }
+}
+
+// 执行一轮校验，返回发现的所有问题；会阻塞在磁盘 I/O 上，应在 spawn_blocking 中调用
+pub fn verify_once(snapshot_dir: &str, metadata_dir: &str, bytes_per_sec: u64) -> Vec<VerifyIssue> {
+    verify_log(metadata_dir, bytes_per_sec)
+        .into_iter()
+        .chain(verify_snapshot(snapshot_dir, bytes_per_sec))
+        .collect()
+}
+
+// 启动周期性的后台校验任务
+pub fn spawn(
+    snapshot_dir: String,
+    metadata_dir: String,
+    interval: Duration,
+    event_bus: events::EventBus,
+    metrics: Arc<metrics::Metrics>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        info!("Background verification started, interval: {}ms", interval.as_millis());
+        loop {
+            tokio::time::sleep(interval).await;
+            let snapshot_dir_clone = snapshot_dir.clone();
+            let metadata_dir_clone = metadata_dir.clone();
+            let issues = match tokio::task::spawn_blocking(move || {
+                verify_once(&snapshot_dir_clone, &metadata_dir_clone, config::VERIFY_BYTES_PER_SEC)
+            }).await {
+                Ok(issues) => issues,
+                Err(e) => {
+                    error!("Background verification task failed: {}", e);
+                    continue;
+                }
+            };
+
+            metrics.verification_runs.fetch_add(1, Ordering::Relaxed);
+            for issue in issues {
+                error!("Background verification detected corruption in {}: {}", issue.filepath, issue.reason);
+                metrics.corruption_detected.fetch_add(1, Ordering::Relaxed);
+                event_bus.publish(events::RaftEvent::CorruptionDetected {
+                    filepath: issue.filepath,
+                    reason: issue.reason,
+                });
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft::proto;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_verify_once_detects_corruption() {
+        let snapshot_dir = tempdir().unwrap();
+        let metadata_dir = tempdir().unwrap();
+        let snapshot_dir_str = snapshot_dir.path().to_str().unwrap();
+        let metadata_dir_str = metadata_dir.path().to_str().unwrap();
+
+        let mut log_instance = log::Log::new(1, metadata_dir_str.to_string());
+        log_instance.append_data(1, vec![(proto::EntryType::Data, b"a".to_vec())]);
+        log_instance.append_data(1, vec![(proto::EntryType::Data, b"b".to_vec())]);
+        log_instance.persist();
+
+        let mut manager = snapshot::SnapshotManager::new(snapshot_dir_str.to_string());
+        let snapshot_filepath = manager.gen_snapshot_filepath(1, 1);
+        std::fs::write(&snapshot_filepath, b"state machine data").unwrap();
+        manager.take_snapshot_metadata(1, 1, None, 0, None).unwrap();
+
+        assert!(verify_once(snapshot_dir_str, metadata_dir_str, u64::MAX).is_empty());
+
+        // 快照数据被篡改
+        std::fs::write(&snapshot_filepath, b"state machine dat4").unwrap();
+        let issues = verify_once(snapshot_dir_str, metadata_dir_str, u64::MAX);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].filepath, snapshot_filepath);
+
+        // 日志文件被截断
+        let log_filepath = log::Log::gen_log_filepath(metadata_dir_str);
+        let log_content = std::fs::read_to_string(&log_filepath).unwrap();
+        std::fs::write(&log_filepath, &log_content[..log_content.len() / 2]).unwrap();
+        let issues = verify_once(snapshot_dir_str, metadata_dir_str, u64::MAX);
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].filepath, log_filepath);
+    }
+}
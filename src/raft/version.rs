@@ -0,0 +1,20 @@
+use crate::raft::{config, proto};
+
+// 构建信息：程序版本、构建时的 git 提交（由 build.rs 写入）以及协议和存储格式版本
+
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("RAFT_GIT_HASH");
+
+// 形如 "0.1.0 (1a2b3c4d5e6f)"，用于日志和数据目录中的 raft.written_by
+pub fn describe() -> String {
+    format!("{} ({})", CRATE_VERSION, GIT_HASH)
+}
+
+pub fn build_info() -> proto::GetVersionResponse {
+    proto::GetVersionResponse {
+        crate_version: CRATE_VERSION.to_string(),
+        git_hash: GIT_HASH.to_string(),
+        protocol_version: config::PROTOCOL_VERSION,
+        storage_version: config::STORAGE_VERSION,
+    }
+}
@@ -0,0 +1,91 @@
+use crate::raft::{config, proto};
+use std::collections::BTreeMap;
+
+// 多机房部署的可用区感知：ServerInfo.zone 标注节点所在的可用区（机房或地域），未标注的节点归入空字符串。
+// 只用于报告和策略（Leader 转移时优先选同一可用区的节点、某个可用区单独构成多数派时告警），
+// 不影响多数派的计算。联合共识期间按 C(new) 的成员计算
+
+pub fn zone_name(server: &proto::ServerInfo) -> &str {
+    server.zone.as_deref().unwrap_or("")
+}
+
+// 配置中的节点是否标注了可用区；都没有标注时不做任何可用区相关的报告和告警
+pub fn zones_tagged(configuration: &config::Config) -> bool {
+    configuration.new_servers.iter().any(|s| s.zone.is_some())
+}
+
+// 按可用区统计投票成员和可达的成员，reachable 判断 Leader 最近是否联系得上该节点
+pub fn zone_health(configuration: &config::Config, reachable: impl Fn(u64) -> bool) -> Vec<proto::ZoneHealth> {
+    if !zones_tagged(configuration) {
+        return Vec::new();
+    }
+    let voters = configuration.new_servers.len() as u32;
+    let majority = voters / 2 + 1;
+    let mut zones: BTreeMap<&str, (u32, u32)> = BTreeMap::new();
+    for server in &configuration.new_servers {
+        let (count, up) = zones.entry(zone_name(server)).or_default();
+        *count += 1;
+        if reachable(server.server_id) {
+            *up += 1;
+        }
+    }
+    let total_reachable: u32 = zones.values().map(|(_, up)| up).sum();
+    zones.into_iter().map(|(zone, (count, up))| proto::ZoneHealth {
+        zone: zone.to_string(),
+        voters: count,
+        reachable: up,
+        holds_quorum: count >= majority,
+        quorum_without_zone: total_reachable - up >= majority,
+    }).collect()
+}
+
+// 跨多个可用区部署、但其中一个可用区单独构成多数派时返回该可用区：
+// 它失联时整个集群不可用，而它与其他可用区分区时仍然可以独自提交
+pub fn dominant_zone(configuration: &config::Config) -> Option<String> {
+    let health = zone_health(configuration, |_| true);
+    if health.len() < 2 {
+        return None;
+    }
+    health.into_iter().find(|z| z.holds_quorum).map(|z| z.zone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(id: u64, zone: Option<&str>) -> proto::ServerInfo {
+        proto::ServerInfo { server_id: id, server_addr: format!("[::1]:{}", 9000 + id), zone: zone.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_zone_health_and_dominant_zone() {
+        // 没有标注可用区时不报告
+        let untagged = config::Config::new_stable(vec![server(1, None), server(2, None), server(3, None)]);
+        assert!(zone_health(&untagged, |_| true).is_empty());
+        assert_eq!(dominant_zone(&untagged), None);
+
+        // 三个可用区各一个节点：任何一个可用区都不能单独构成多数派，失去一个仍然可用
+        let spread = config::Config::new_stable(vec![server(1, Some("a")), server(2, Some("b")), server(3, Some("c"))]);
+        let health = zone_health(&spread, |id| id != 3);
+        assert_eq!(health.len(), 3);
+        assert!(health.iter().all(|z| !z.holds_quorum));
+        assert_eq!(health.iter().map(|z| (z.zone.as_str(), z.reachable, z.quorum_without_zone)).collect::<Vec<_>>(),
+            vec![("a", 1, false), ("b", 1, false), ("c", 0, true)]);
+        assert_eq!(dominant_zone(&spread), None);
+
+        // 五个节点中的三个在同一可用区，未标注的节点归入空字符串
+        let skewed = config::Config::new_stable(vec![
+            server(1, Some("a")), server(2, Some("a")), server(3, Some("a")), server(4, Some("b")), server(5, None),
+        ]);
+        let health = zone_health(&skewed, |_| true);
+        assert_eq!(health.iter().map(|z| (z.zone.as_str(), z.voters)).collect::<Vec<_>>(), vec![("", 1), ("a", 3), ("b", 1)]);
+        assert!(!health[1].quorum_without_zone);
+        assert_eq!(dominant_zone(&skewed), Some("a".to_string()));
+        assert_eq!(skewed.zone_of(4), Some("b"));
+        assert_eq!(skewed.zone_of(5), None);
+
+        // 全部节点在同一个可用区不算跨可用区部署
+        let single = config::Config::new_stable(vec![server(1, Some("a")), server(2, Some("a")), server(3, Some("a"))]);
+        assert_eq!(dominant_zone(&single), None);
+    }
+}